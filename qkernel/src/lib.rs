@@ -201,6 +201,7 @@ pub fn SingletonInit() {
         perflog::THREAD_COUNTS.Init(QMutex::new(perflog::ThreadPerfCounters::default()));
 
         fs::file::InitSingleton();
+        fs::dirent_cache::InitSingleton();
         fs::filesystems::InitSingleton();
         interrupt::InitSingleton();
         kernel::futex::InitSingleton();
@@ -209,6 +210,7 @@ pub fn SingletonInit() {
         kernel::timer::InitSingleton();
         loader::vdso::InitSingleton();
         socket::socket::InitSingleton();
+        socket::hostinet::firewall::InitSingleton();
         syscalls::sys_rlimit::InitSingleton();
         task::InitSingleton();
 
@@ -545,11 +547,20 @@ fn StartRootContainer(_para: *const u8) -> ! {
     info!("StartRootContainer ....");
     let task = Task::Current();
     let mut process = Process::default();
-    Kernel::HostSpace::LoadProcessKernel(&mut process as * mut _ as u64) as usize;
+    let ret = Kernel::HostSpace::LoadProcessKernel(&mut process as * mut _ as u64);
 
     let (_tid, entry, userStackAddr, kernelStackAddr) = {
-        let mut processArgs = LOADER.Lock(task).unwrap().Init(process);
-        match LOADER.LoadRootProcess(&mut processArgs) {
+        let processArgs = if ret < 0 {
+            Err(Error::SysError(-ret as i32))
+        } else {
+            LOADER.Lock(task).unwrap().Init(process)
+        };
+        let ret = match processArgs {
+            Err(e) => Err(e),
+            Ok(mut processArgs) => LOADER.LoadRootProcess(&mut processArgs),
+        };
+
+        match ret {
             Err(e) => {
                 error!("load root process failure with error {:?}, shutting down...", e);
                 SHARESPACE.StoreShutdown();
@@ -49,6 +49,9 @@ pub mod syscalls;
 pub mod sys_memfd;
 pub mod sys_sched;
 pub mod sys_inotify;
+pub mod sys_fanotify;
+pub mod sys_file_handle;
+pub mod sys_quota;
 pub mod sys_xattr;
 pub mod sys_sem;
 pub mod sys_shm;
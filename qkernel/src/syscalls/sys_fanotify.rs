@@ -0,0 +1,192 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::super::qlib::common::*;
+use super::super::qlib::linux_def::*;
+use super::super::qlib::kernel::fs::fanotify::*;
+use super::super::qlib::kernel::fs::anon::*;
+use super::super::qlib::kernel::fs::dirent::*;
+use super::super::qlib::kernel::fs::flags::*;
+use super::super::qlib::kernel::fs::file::*;
+use super::super::kernel::fd_table::*;
+use super::super::syscalls::syscalls::*;
+use super::super::task::*;
+use super::sys_file::*;
+
+// Events which can be requested with fanotify_mark(2).
+const ALL_MARK_EVENTS: u64 = Fanotify::FAN_ALL_EVENTS
+    | Fanotify::FAN_ALL_PERM_EVENTS
+    | Fanotify::FAN_EVENT_ON_CHILD
+    | Fanotify::FAN_ONDIR;
+
+// FanotifyInit implements the fanotify_init() syscall.
+pub fn SysFanotifyInit(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
+    let flags = args.arg0 as u32;
+    let eventFlags = args.arg1 as u32;
+
+    if !task.Creds().HasCapability(Capability::CAP_SYS_ADMIN) {
+        return Err(Error::SysError(SysErr::EPERM))
+    }
+
+    if flags & !Fanotify::FAN_ALL_INIT_FLAGS != 0 {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    let class = flags & Fanotify::FAN_ALL_CLASS_BITS;
+    if class == Fanotify::FAN_ALL_CLASS_BITS {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    // The permission events need an fd to be answered.
+    if flags & Fanotify::FAN_REPORT_FID != 0 && class != Fanotify::FAN_CLASS_NOTIF {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    if eventFlags & Flags::O_ACCMODE as u32 == Flags::O_ACCMODE as u32 {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    let inode = NewAnonInode(task);
+    let dirent = Dirent::New(&inode, "anon_inode:[fanotify]");
+
+    let fileFlags = FileFlags {
+        Read: true,
+        Write: true,
+        NonBlocking: flags & Fanotify::FAN_NONBLOCK != 0,
+        ..Default::default()
+    };
+
+    let group = FanotifyGroup::New(flags, eventFlags | Flags::O_LARGEFILE as u32);
+    let file = File::New(&dirent, &fileFlags, group.into());
+    let fd = task.NewFDFrom(
+        0,
+        &file,
+        &FDFlags {
+            CloseOnExec: flags & Fanotify::FAN_CLOEXEC != 0,
+        },
+    )?;
+
+    return Ok(fd as i64)
+}
+
+// FdToFanotify resolves an fd to a fanotify group.
+pub fn FdToFanotify(task: &Task, fd: i32) -> Result<(FanotifyGroup, File)> {
+    let file = task.GetFile(fd)?;
+    let group = match file.FileOp.as_any().downcast_ref::<FanotifyGroup>() {
+        Some(g) => g.clone(),
+        None => return Err(Error::SysError(SysErr::EINVAL)),
+    };
+
+    return Ok((group, file))
+}
+
+// FanotifyMark implements the fanotify_mark() syscall.
+pub fn SysFanotifyMark(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
+    let fd = args.arg0 as i32;
+    let flags = args.arg1 as u32;
+    let mask = args.arg2 as u64;
+    let dirfd = args.arg3 as i32;
+    let addr = args.arg4 as u64;
+
+    if flags & !Fanotify::FAN_ALL_MARK_FLAGS != 0 {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    let markType = flags & Fanotify::FAN_MARK_TYPE_MASK;
+    if markType == Fanotify::FAN_MARK_MOUNT | Fanotify::FAN_MARK_FILESYSTEM {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    let action = flags & (Fanotify::FAN_MARK_ADD | Fanotify::FAN_MARK_REMOVE | Fanotify::FAN_MARK_FLUSH);
+    if action != Fanotify::FAN_MARK_ADD
+        && action != Fanotify::FAN_MARK_REMOVE
+        && action != Fanotify::FAN_MARK_FLUSH {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    let (group, _file) = FdToFanotify(task, fd)?;
+
+    if action == Fanotify::FAN_MARK_FLUSH {
+        if flags & !(Fanotify::FAN_MARK_FLUSH | Fanotify::FAN_MARK_TYPE_MASK) != 0 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        group.FlushMarks(markType);
+        return Ok(0)
+    }
+
+    if mask & !ALL_MARK_EVENTS != 0 {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    if action == Fanotify::FAN_MARK_ADD && mask == 0 {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    if mask & Fanotify::FAN_ALL_PERM_EVENTS != 0 && group.Class() == Fanotify::FAN_CLASS_NOTIF {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    // There is no file to open for these events, they can only be reported
+    // with a file handle.
+    if mask & Fanotify::FAN_INODE_EVENTS != 0 {
+        if !group.ReportFid() {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        if markType == Fanotify::FAN_MARK_MOUNT {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+    }
+
+    let resolve = flags & Fanotify::FAN_MARK_DONT_FOLLOW == 0;
+    let onlyDir = flags & Fanotify::FAN_MARK_ONLYDIR != 0;
+
+    let mark = |d: &Dirent| -> Result<()> {
+        if onlyDir && !d.Inode().StableAttr().IsDir() {
+            return Err(Error::SysError(SysErr::ENOTDIR))
+        }
+
+        if action == Fanotify::FAN_MARK_ADD {
+            return group.AddMark(d, markType, mask, flags);
+        }
+
+        return group.RemoveMark(d, markType, mask, flags);
+    };
+
+    // "If pathname is NULL, the file system object to be marked is determined
+    // by the file descriptor dirfd." -- fanotify_mark(2)
+    if addr == 0 {
+        let target = if dirfd == ATType::AT_FDCWD {
+            task.Workdir()
+        } else {
+            task.GetFile(dirfd)?.Dirent.clone()
+        };
+
+        mark(&target)?;
+        return Ok(0)
+    }
+
+    let (path, _) = copyInPath(task, addr, false)?;
+    fileOpOn(
+        task,
+        dirfd,
+        &path,
+        resolve,
+        &mut |_root: &Dirent, d: &Dirent, _remainingTraversals: u32| -> Result<()> {
+            return mark(d);
+        })?;
+
+    return Ok(0)
+}
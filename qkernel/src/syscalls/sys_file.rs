@@ -18,6 +18,7 @@ use alloc::string::ToString;
 use super::super::fs::dirent::*;
 use super::super::fs::file::*;
 use super::super::fs::inotify::*;
+use super::super::fs::fanotify::*;
 use super::super::fs::flags::*;
 use super::super::fs::inode::*;
 use super::super::fs::lock::*;
@@ -309,6 +310,8 @@ pub fn openAt(task: &Task, dirFd: i32, addr: u64, flags: u32) -> Result<i32> {
                 }
             };
 
+            FANOTIFY.Permission(task, d, Fanotify::FAN_OPEN_PERM)?;
+
             let newFd = task.NewFDFrom(
                 0,
                 &file,
@@ -560,6 +563,8 @@ pub fn createAt(task: &Task, dirFd: i32, addr: u64, flags: u32, mode: FileMode)
             e => return Err(e)
         };
 
+        FANOTIFY.Permission(task, &newFile.Dirent, Fanotify::FAN_OPEN_PERM)?;
+
         let newFd = task.NewFDFrom(
             0,
             &newFile,
@@ -1985,7 +1990,7 @@ pub fn SysFtruncate(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     inode.Truncate(task, &dirent, len)?;
 
     // File length modified, generate notification.
-    file.Dirent.InotifyEvent(InotifyEvent::IN_MODIFY, 0, EventType::InodeEvent);
+    file.InotifyEvent(InotifyEvent::IN_MODIFY, 0, EventType::InodeEvent);
 
     return Ok(0);
 }
@@ -2417,7 +2422,7 @@ pub fn SysFallocate(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     let dirent = file.Dirent.clone();
     inode.Allocate(task, &dirent, offset, len)?;
 
-    file.Dirent.InotifyEvent(InotifyEvent::IN_MODIFY, 0, EventType::InodeEvent);
+    file.InotifyEvent(InotifyEvent::IN_MODIFY, 0, EventType::InodeEvent);
 
     Ok(0)
 }
@@ -0,0 +1,168 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec::Vec;
+
+use super::super::qlib::common::*;
+use super::super::qlib::linux_def::*;
+use super::super::qlib::qmsg::qcall::MAX_HANDLE_SZ;
+use super::super::qlib::kernel::fs::dirent::*;
+use super::super::qlib::kernel::fs::fanotify::*;
+use super::super::qlib::kernel::fs::file_handle::*;
+use super::super::qlib::kernel::fs::flags::*;
+use super::super::qlib::kernel::fs::inotify::*;
+use super::super::kernel::fd_table::*;
+use super::super::syscalls::syscalls::*;
+use super::super::task::*;
+use super::sys_file::*;
+
+// NameToHandleAt implements the name_to_handle_at() syscall.
+pub fn SysNameToHandleAt(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
+    let dirfd = args.arg0 as i32;
+    let addr = args.arg1 as u64;
+    let handleAddr = args.arg2 as u64;
+    let mountIdAddr = args.arg3 as u64;
+    let flags = args.arg4 as i32;
+
+    if flags & !(ATType::AT_SYMLINK_FOLLOW | ATType::AT_EMPTY_PATH) != 0 {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    let handleBytes = task.CopyInObj::<u32>(handleAddr)?;
+    if handleBytes as usize > MAX_HANDLE_SZ {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    let resolve = flags & ATType::AT_SYMLINK_FOLLOW != 0;
+    let (path, _) = copyInPath(task, addr, flags & ATType::AT_EMPTY_PATH != 0)?;
+
+    let d = if path.len() == 0 {
+        if dirfd == ATType::AT_FDCWD {
+            task.Workdir()
+        } else {
+            task.GetFile(dirfd)?.Dirent.clone()
+        }
+    } else {
+        let mut target = None;
+        fileOpOn(
+            task,
+            dirfd,
+            &path,
+            resolve,
+            &mut |_root: &Dirent, d: &Dirent, _remainingTraversals: u32| -> Result<()> {
+                target = Some(d.clone());
+                return Ok(())
+            })?;
+        target.unwrap()
+    };
+
+    let handle = FileHandle::NewWithHost(&d);
+    let size = handle.Sizeof() as u32;
+    if size > handleBytes {
+        // "EOVERFLOW: The handle_bytes value passed into the call was too small.
+        // When this error occurs, handle->handle_bytes is updated to indicate
+        // the required size for the handle." -- name_to_handle_at(2)
+        task.CopyOutObj(&size, handleAddr)?;
+        return Err(Error::SysError(SysErr::EOVERFLOW))
+    }
+
+    let mountId = match task.mountNS.FindMount(&d) {
+        None => 0,
+        Some(mount) => mount.lock().Id as i32,
+    };
+
+    let buf = handle.Encode();
+    task.CopyOutObj(&size, handleAddr)?;
+    task.CopyOutObj(&handle.handleType, handleAddr + 4)?;
+    task.CopyOutSlice(&buf, handleAddr + FILE_HANDLE_HEADER_SIZE as u64, buf.len())?;
+    task.CopyOutObj(&mountId, mountIdAddr)?;
+
+    let msrc = d.Inode().lock().MountSource.clone();
+    msrc.lock().AddInode(&d);
+    return Ok(0)
+}
+
+// OpenByHandleAt implements the open_by_handle_at() syscall.
+pub fn SysOpenByHandleAt(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
+    let mountfd = args.arg0 as i32;
+    let handleAddr = args.arg1 as u64;
+    let flags = args.arg2 as u32;
+
+    if !task.Creds().HasCapability(Capability::CAP_DAC_READ_SEARCH) {
+        return Err(Error::SysError(SysErr::EPERM))
+    }
+
+    let handleBytes = task.CopyInObj::<u32>(handleAddr)?;
+    if handleBytes == 0 || handleBytes as usize > MAX_HANDLE_SZ {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    let handleType = task.CopyInObj::<i32>(handleAddr + 4)?;
+    let buf: Vec<u8> = task.CopyInVec(handleAddr + FILE_HANDLE_HEADER_SIZE as u64, handleBytes as usize)?;
+    let handle = FileHandle::Decode(handleType, &buf)?;
+
+    let mountDirent = if mountfd == ATType::AT_FDCWD {
+        task.Workdir()
+    } else {
+        task.GetFile(mountfd)?.Dirent.clone()
+    };
+
+    let mountRoot = match task.mountNS.FindMount(&mountDirent) {
+        None => task.Root(),
+        Some(mount) => mount.lock().Root(),
+    };
+
+    let d = handle.Resolve(task, &mountRoot)?;
+
+    let fileFlags = FileFlags::FromFlags(flags);
+    let inode = d.Inode();
+    if !fileFlags.Path {
+        inode.CheckPermission(task, &PermMask::FromFlags(flags))?;
+    }
+
+    if inode.StableAttr().IsSymlink() && !fileFlags.Path {
+        return Err(Error::SysError(SysErr::ELOOP))
+    }
+
+    if inode.StableAttr().IsDir() {
+        if fileFlags.Write {
+            return Err(Error::SysError(SysErr::EISDIR))
+        }
+    } else if fileFlags.Directory {
+        return Err(Error::SysError(SysErr::ENOTDIR))
+    }
+
+    let file = match inode.GetFile(task, &d, &fileFlags) {
+        Ok(f) => f,
+        Err(Error::ErrInterrupted) => {
+            return Err(Error::SysError(SysErr::ERESTARTSYS))
+        }
+        Err(e) => {
+            return Err(e)
+        }
+    };
+
+    FANOTIFY.Permission(task, &d, Fanotify::FAN_OPEN_PERM)?;
+
+    let fd = task.NewFDFrom(
+        0,
+        &file,
+        &FDFlags {
+            CloseOnExec: flags & Flags::O_CLOEXEC as u32 != 0,
+        },
+    )?;
+
+    d.InotifyEvent(InotifyEvent::IN_OPEN, 0, EventType::InodeEvent);
+    return Ok(fd as i64)
+}
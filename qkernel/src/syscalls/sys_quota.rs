@@ -0,0 +1,145 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::super::qlib::auth::id::*;
+use super::super::qlib::common::*;
+use super::super::qlib::linux_def::*;
+use super::super::qlib::kernel::fs::dirent::*;
+use super::super::qlib::kernel::fs::host::quota::*;
+use super::super::syscalls::syscalls::*;
+use super::super::task::*;
+use super::sys_file::*;
+
+// The disk quotas of the sandbox are set by the container runtime and apply
+// to all the users, there is no block device to pass as special: the quotas
+// of the mount of any file can be queried with the path of the file.
+
+// Quotactl implements the quotactl() syscall.
+pub fn SysQuotactl(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
+    let cmd = args.arg0 as u32;
+    let special = args.arg1 as u64;
+    let id = args.arg2 as u32;
+    let addr = args.arg3 as u64;
+
+    if Quota::Cmd(cmd) == Quota::Q_SYNC && special == 0 {
+        return Ok(0)
+    }
+
+    if special == 0 {
+        return Err(Error::SysError(SysErr::EFAULT))
+    }
+
+    let (path, _) = copyInPath(task, special, false)?;
+    let mut target = None;
+    fileOpOn(
+        task,
+        ATType::AT_FDCWD,
+        &path,
+        true,
+        &mut |_root: &Dirent, d: &Dirent, _remainingTraversals: u32| -> Result<()> {
+            target = Some(d.clone());
+            return Ok(())
+        })?;
+
+    return quotactl(task, &target.unwrap(), cmd, id, addr);
+}
+
+// QuotactlFd implements the quotactl_fd() syscall.
+pub fn SysQuotactlFd(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
+    let fd = args.arg0 as i32;
+    let cmd = args.arg1 as u32;
+    let id = args.arg2 as u32;
+    let addr = args.arg3 as u64;
+
+    let file = task.GetFile(fd)?;
+    return quotactl(task, &file.Dirent, cmd, id, addr);
+}
+
+fn quotactl(task: &Task, d: &Dirent, cmd: u32, id: u32, addr: u64) -> Result<i64> {
+    let typ = Quota::Type(cmd);
+    if typ >= Quota::MAXQUOTAS {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    let quota = match HostQuota::Of(&d.Inode()) {
+        None => return Err(Error::SysError(SysErr::ENOTBLK)),
+        Some(q) => q,
+    };
+
+    match Quota::Cmd(cmd) {
+        Quota::Q_SYNC => return Ok(0),
+        Quota::Q_QUOTAON | Quota::Q_QUOTAOFF | Quota::Q_SETINFO | Quota::Q_SETQUOTA => {
+            return Err(Error::SysError(SysErr::EPERM))
+        }
+        _ => (),
+    }
+
+    let usage = match quota.Usage() {
+        // "ESRCH: Quotas have not been turned on for this filesystem."
+        // -- quotactl(2)
+        None => return Err(Error::SysError(SysErr::ESRCH)),
+        Some(u) => u,
+    };
+
+    match Quota::Cmd(cmd) {
+        Quota::Q_GETFMT => {
+            task.CopyOutObj(&Quota::QFMT_VFS_V1, addr)?;
+        }
+        Quota::Q_GETINFO => {
+            let info = IfDqinfo {
+                dqi_valid: Quota::IIF_BGRACE | Quota::IIF_IGRACE | Quota::IIF_FLAGS,
+                ..Default::default()
+            };
+            task.CopyOutObj(&info, addr)?;
+        }
+        Quota::Q_GETQUOTA => {
+            checkQuotaId(task, typ, id)?;
+
+            let dqblk = IfDqblk {
+                dqb_bhardlimit: usage.byteLimit / Quota::QIF_DQBLKSIZE,
+                dqb_curspace: usage.bytes,
+                dqb_ihardlimit: usage.inodeLimit,
+                dqb_curinodes: usage.inodes,
+                dqb_valid: Quota::QIF_LIMITS | Quota::QIF_USAGE,
+                ..Default::default()
+            };
+            task.CopyOutObj(&dqblk, addr)?;
+        }
+        _ => return Err(Error::SysError(SysErr::EINVAL)),
+    }
+
+    return Ok(0)
+}
+
+// checkQuotaId checks that the caller may query the quota of another user or
+// group, as linux does.
+fn checkQuotaId(task: &Task, typ: u32, id: u32) -> Result<()> {
+    let creds = task.Creds();
+    if creds.HasCapability(Capability::CAP_SYS_ADMIN) {
+        return Ok(())
+    }
+
+    let userns = creds.lock().UserNamespace.clone();
+    let allowed = match typ {
+        Quota::USRQUOTA => creds.lock().EffectiveKUID == userns.MapToKUID(UID(id)),
+        Quota::GRPQUOTA => creds.InGroup(userns.MapToKGID(GID(id))),
+        _ => false,
+    };
+
+    if !allowed {
+        return Err(Error::SysError(SysErr::EPERM))
+    }
+
+    return Ok(())
+}
@@ -279,12 +279,14 @@ fn RepReadv(task: &Task, f: &File, dsts: &mut [IoVec]) -> Result<i64> {
 
     if count > 0 {
         // Queue notification if we read anything.
-        f.Dirent.InotifyEvent(InotifyEvent::IN_ACCESS, 0, EventType::InodeEvent);
+        f.InotifyEvent(InotifyEvent::IN_ACCESS, 0, EventType::InodeEvent);
     }
     return Ok(count);
 }
 
 fn readv(task: &Task, f: &File, dsts: &mut [IoVec]) -> Result<i64> {
+    f.FanotifyPermission(task, Fanotify::FAN_ACCESS_PERM)?;
+
     let mut iovs = task.AdjustIOVecPermission(dsts, true, true)?;
     let dsts = &mut iovs;
 
@@ -302,7 +304,7 @@ fn readv(task: &Task, f: &File, dsts: &mut [IoVec]) -> Result<i64> {
         }
         Ok(n) => {
             // Queue notification if we read anything.
-            f.Dirent.InotifyEvent(InotifyEvent::IN_ACCESS, 0, EventType::InodeEvent);
+            f.InotifyEvent(InotifyEvent::IN_ACCESS, 0, EventType::InodeEvent);
             return Ok(n)
         },
     };
@@ -340,7 +342,7 @@ fn readv(task: &Task, f: &File, dsts: &mut [IoVec]) -> Result<i64> {
                 Err(e) => {
                     if count > 0 {
                         // Queue notification if we read anything.
-                        f.Dirent.InotifyEvent(InotifyEvent::IN_ACCESS, 0, EventType::InodeEvent);
+                        f.InotifyEvent(InotifyEvent::IN_ACCESS, 0, EventType::InodeEvent);
                         return Ok(count);
                     }
                     return Err(e);
@@ -353,7 +355,7 @@ fn readv(task: &Task, f: &File, dsts: &mut [IoVec]) -> Result<i64> {
                     count += n;
                     if count == len as i64 || f.Flags().NonBlocking {
                         // Queue notification if we read anything.
-                        f.Dirent.InotifyEvent(InotifyEvent::IN_ACCESS, 0, EventType::InodeEvent);
+                        f.InotifyEvent(InotifyEvent::IN_ACCESS, 0, EventType::InodeEvent);
                         return Ok(count);
                     }
 
@@ -379,6 +381,8 @@ fn readv(task: &Task, f: &File, dsts: &mut [IoVec]) -> Result<i64> {
 }
 
 fn preadv(task: &Task, f: &File, dsts: &mut [IoVec], offset: i64) -> Result<i64> {
+    f.FanotifyPermission(task, Fanotify::FAN_ACCESS_PERM)?;
+
     let mut iovs = task.AdjustIOVecPermission(dsts, true, true)?;
     let dsts = &mut iovs;
 
@@ -391,7 +395,7 @@ fn preadv(task: &Task, f: &File, dsts: &mut [IoVec], offset: i64) -> Result<i64>
         Ok(n) => {
             if n > 0 {
                 // Queue notification if we read anything.
-                f.Dirent.InotifyEvent(InotifyEvent::IN_ACCESS, 0, EventType::InodeEvent)
+                f.InotifyEvent(InotifyEvent::IN_ACCESS, 0, EventType::InodeEvent)
             }
             return Ok(n)
         },
@@ -411,7 +415,7 @@ fn preadv(task: &Task, f: &File, dsts: &mut [IoVec], offset: i64) -> Result<i64>
             Ok(n) => {
                 if n > 0 {
                     // Queue notification if we read anything.
-                    f.Dirent.InotifyEvent(InotifyEvent::IN_ACCESS, 0, EventType::InodeEvent)
+                    f.InotifyEvent(InotifyEvent::IN_ACCESS, 0, EventType::InodeEvent)
                 }
                 return Ok(n);
             }
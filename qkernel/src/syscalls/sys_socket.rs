@@ -15,6 +15,8 @@
 use alloc::vec::Vec;
 
 use super::super::qlib::common::*;
+use super::super::qlib::linux::bpf::*;
+use super::super::qlib::linux::socket::SO_GET_FILTER;
 use super::super::qlib::linux_def::*;
 use super::super::socket::socket::*;
 use super::super::task::*;
@@ -318,6 +320,19 @@ pub fn SysGetSockOpt(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
         0
     };
 
+    // the optlen of SO_GET_FILTER counts the instructions of the program
+    if level == LibcConst::SOL_SOCKET as i32 && name == SO_GET_FILTER {
+        let count = (optlen as usize).min(BPF_MAXINSNS);
+        let mut prog: Vec<u8> = vec![0; count * core::mem::size_of::<SockFilter>()];
+        let res = sock.GetSockOpt(task, level, name, &mut prog)? as usize;
+        if count > 0 {
+            let len = (res * core::mem::size_of::<SockFilter>()).min(prog.len());
+            task.CopyOutSlice(&prog[..len], optValAddr, len)?;
+        }
+        task.CopyOutObj(&(res as i32), optLenAddr)?;
+        return Ok(0);
+    }
+
     let mut optVal: [u8; MAX_OPT_LEN as usize] = [0; MAX_OPT_LEN as usize];
     let res = sock.GetSockOpt(task, level, name, &mut optVal[..optlen as usize])?;
 
@@ -625,7 +640,12 @@ pub fn SysRecvMMsg(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
 
     let sock = file.FileOp.clone();
 
-    if flags & !(MsgType::BASE_RECV_FLAGS | MsgType::MSG_CMSG_CLOEXEC | MsgType::MSG_ERRQUEUE) != 0
+    if flags
+        & !(MsgType::BASE_RECV_FLAGS
+            | MsgType::MSG_CMSG_CLOEXEC
+            | MsgType::MSG_ERRQUEUE
+            | MsgType::MSG_WAITFORONE)
+        != 0
     {
         return Err(Error::SysError(SysErr::EINVAL));
     }
@@ -658,20 +678,31 @@ pub fn SysRecvMMsg(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
         }
     }
 
+    let waitForOne = flags & MsgType::MSG_WAITFORONE != 0;
+    flags &= !MsgType::MSG_WAITFORONE;
+
     let mut count = 0;
-    let mut res = 0;
     //let msgs = task.GetSliceMut::<MMsgHdr>(msgPtr, vlen as usize)?;
     let mut msgs = task.CopyInVec::<MMsgHdr>(msgPtr, vlen as usize)?;
 
-    //info!("SysRecvMMsg 1 vlen is {}", vlen);
-    for i in 0..vlen as usize {
-        res = match recvSingleMsg(
-            task,
-            &sock,
-            &(msgs[i].msgHdr) as *const MsgHdr as u64,
-            flags,
-            deadline,
-        ) {
+    // the socket receives as many messages as it can with one host call, the
+    // message it doesn't batch is received alone
+    while count < vlen as usize {
+        let res = match sock.RecvMMsg(task, &mut msgs[count..], flags, deadline) {
+            Some(res) => res,
+            None => {
+                let msgAddr = &(msgs[count].msgHdr) as *const MsgHdr as u64;
+                match recvSingleMsg(task, &sock, msgAddr, flags, deadline) {
+                    Err(e) => Err(e),
+                    Ok(n) => {
+                        msgs[count].msgLen = n as u32;
+                        Ok(1)
+                    }
+                }
+            }
+        };
+
+        match res {
             Err(e) => {
                 if count > 0 {
                     break;
@@ -679,24 +710,17 @@ pub fn SysRecvMMsg(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
 
                 return Err(e);
             }
-            Ok(n) => n,
-        };
-
-        if res < 0 {
-            break;
+            Ok(n) => count += n as usize,
         }
 
-        msgs[i].msgLen = res as u32;
-        count += 1;
-    }
-
-    if count == 0 {
-        return Err(Error::SysError(-res as i32));
+        if waitForOne {
+            flags |= MsgType::MSG_DONTWAIT;
+        }
     }
 
     task.CopyOutSlice(&msgs, msgPtr, vlen as usize)?;
 
-    return Ok(count);
+    return Ok(count as i64);
 }
 
 pub const BASE_RECV_FLAGS: i32 = MsgType::MSG_OOB
@@ -874,33 +898,39 @@ pub fn SysSendMMsg(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     }
 
     let mut count = 0;
-    let mut res = 0;
     //let msgs = task.GetSliceMut::<MMsgHdr>(msgPtr, vlen as usize)?;
     let mut msgs = task.CopyInVec::<MMsgHdr>(msgPtr, vlen as usize)?;
-    for i in 0..vlen as usize {
-        res = sendSingleMsg(
-            task,
-            &sock,
-            &(msgs[i].msgHdr) as *const MsgHdr as u64,
-            flags,
-            deadline,
-        )?;
 
-        if res < 0 {
-            break;
-        }
+    while count < vlen as usize {
+        let res = match sock.SendMMsg(task, &mut msgs[count..], flags, deadline) {
+            Some(res) => res,
+            None => {
+                let msgAddr = &(msgs[count].msgHdr) as *const MsgHdr as u64;
+                match sendSingleMsg(task, &sock, msgAddr, flags, deadline) {
+                    Err(e) => Err(e),
+                    Ok(n) => {
+                        msgs[count].msgLen = n as u32;
+                        Ok(1)
+                    }
+                }
+            }
+        };
 
-        msgs[i].msgLen = res as u32;
-        count += 1;
-    }
+        match res {
+            Err(e) => {
+                if count > 0 {
+                    break;
+                }
 
-    if count == 0 {
-        return Err(Error::SysError(-res as i32));
+                return Err(e);
+            }
+            Ok(n) => count += n as usize,
+        }
     }
 
     task.CopyOutSlice(&msgs, msgPtr, vlen as usize)?;
 
-    return Ok(count);
+    return Ok(count as i64);
 }
 
 pub fn SysSendTo(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
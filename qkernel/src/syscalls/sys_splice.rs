@@ -365,8 +365,8 @@ pub fn DoSplice(
                     // On Linux, inotify behavior is not very consistent with splice(2). We try
                     // our best to emulate Linux for very basic calls to splice, where for some
                     // reason, events are generated for output files, but not input files.
-                    srcFile.Dirent.InotifyEvent(InotifyEvent::IN_ACCESS, 0, EventType::InodeEvent);
-                    dstFile.Dirent.InotifyEvent(InotifyEvent::IN_MODIFY, 0, EventType::InodeEvent);
+                    srcFile.InotifyEvent(InotifyEvent::IN_ACCESS, 0, EventType::InodeEvent);
+                    dstFile.InotifyEvent(InotifyEvent::IN_MODIFY, 0, EventType::InodeEvent);
                 }
                 return Ok(n)
             },
@@ -586,8 +586,8 @@ pub fn SysTee(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
         // On Linux, inotify behavior is not very consistent with splice(2). We try
         // our best to emulate Linux for very basic calls to splice, where for some
         // reason, events are generated for output files, but not input files.
-        src.Dirent.InotifyEvent(InotifyEvent::IN_ACCESS, 0, EventType::InodeEvent);
-        dst.Dirent.InotifyEvent(InotifyEvent::IN_MODIFY, 0, EventType::InodeEvent);
+        src.InotifyEvent(InotifyEvent::IN_ACCESS, 0, EventType::InodeEvent);
+        dst.InotifyEvent(InotifyEvent::IN_MODIFY, 0, EventType::InodeEvent);
     }
     return Ok(count)
 }
@@ -47,6 +47,9 @@ use super::super::syscalls::sys_write::*;
 use super::super::syscalls::sys_memfd::*;
 use super::super::syscalls::sys_sched::*;
 use super::super::syscalls::sys_inotify::*;
+use super::super::syscalls::sys_fanotify::*;
+use super::super::syscalls::sys_file_handle::*;
+use super::super::syscalls::sys_quota::*;
 use super::super::syscalls::sys_xattr::*;
 use super::super::syscalls::sys_sem::*;
 use super::super::syscalls::sys_shm::*;
@@ -314,7 +317,7 @@ pub const SYS_CALL_TABLE: &'static [SyscallFn] = &[
     SysCapErr,           // 176 sys_delete_module,
     SysNoSys,            // 177 sys_get_kernel_syms, Not supported in Linux > 2.6
     SysNoSys,            // 178 sys_query_module,    Not supported in Linux > 2.6
-    SysCapErr,           // 179 sys_quotactl,
+    SysQuotactl,         // 179 sys_quotactl,
     SysNoSys,            // 180 sys_nfsservctl,      Removed after Linux 3.1
     SysNoSys,            // 181 sys_getpmsg,         Not implemented in Linux.
     SysNoSys,            // 182 sys_putpmsg,         Not implemented in Linux.
@@ -435,11 +438,11 @@ pub const SYS_CALL_TABLE: &'static [SyscallFn] = &[
     SysRtTgsigqueueinfo, // 297 sys_rt_tgsigqueueinfo,
     SysNoDev,            // 298 sys_perf_event_open,     No support for perf counters
     SysRecvMMsg,         // 299 sys_recvmmsg,
-    SysNoSys,            //	300 sys_fanotify_init,       Needs CONFIG_FANOTIFY
-    SysNoSys,            //	309 sys_fanotify_mark,       Needs CONFIG_FANOTIFY
+    SysFanotifyInit,     //	300 sys_fanotify_init,
+    SysFanotifyMark,     //	301 sys_fanotify_mark,
     SysPrlimit64,        //	308 sys_prlimit64,
-    SysOpNotSupport,     //	307 sys_name_to_handle_at,
-    SysOpNotSupport,     //	306 sys_open_by_handle_at,
+    SysNameToHandleAt,   //	303 sys_name_to_handle_at,
+    SysOpenByHandleAt,   //	304 sys_open_by_handle_at,
     SysCapErr,           //	305 sys_clock_adjtime,       CAP_SYS_TIME
     SysSyncFs,           //	304 sys_syncfs,
     SysSendMMsg,         //	303 sys_sendmmsg,
@@ -586,7 +589,7 @@ pub const SYS_CALL_TABLE: &'static [SyscallFn] = &[
     NotImplementSyscall, //	440 sys_process_madvise
     SysPwait2,           //	441 sys_epoll_pwait2
     NotImplementSyscall, //	442 sys_mouLoad(nt_setattr
-    NotImplementSyscall, //	443 sys_quotactl_fd
+    SysQuotactlFd,       //	443 sys_quotactl_fd
     NotImplementSyscall, //	444 sys_landlock_create_ruleset
     NotImplementSyscall, //	445 sys_landlock_add_rule
     NotImplementSyscall, //	446 sys_landlock_restrict_self
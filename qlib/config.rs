@@ -35,11 +35,21 @@ pub struct Config {
     pub ReserveCpuCount: usize,
     pub ShimMode: bool,
     pub EnableInotify: bool,
+    pub HostInotify: bool,
     pub ReaddirCache: bool,
     pub HiberODirect: bool,
     pub DisableCgroup: bool,
     pub CopyDataWithPf: bool,
     pub TlbShootdownWait: bool,
+    pub DiskQuotaBytes: u64,
+    pub DiskQuotaInodes: u64,
+    pub DirentCacheSize: u64,
+    pub ReaddirCacheSize: u64,
+    pub NegativeDirentCacheSize: u64,
+    pub NegativeDirentTTL: u64,
+    pub FsCacheShrinkThreshold: u64,
+    pub EnableNetstack: bool,
+    pub UDPMultishotRecv: bool,
 }
 
 impl Config {
@@ -78,11 +88,21 @@ impl Default for Config {
             ReserveCpuCount: 2,
             ShimMode: false,
             EnableInotify: false,
+            HostInotify: false,
             ReaddirCache: true,
             HiberODirect: true,
             DisableCgroup: true,
             CopyDataWithPf: false,
             TlbShootdownWait: false,
+            DiskQuotaBytes: 0,
+            DiskQuotaInodes: 0,
+            DirentCacheSize: 16384,
+            ReaddirCacheSize: 1024,
+            NegativeDirentCacheSize: 4096,
+            NegativeDirentTTL: 1000, // ms
+            FsCacheShrinkThreshold: 80, // percent of the kernel heap
+            EnableNetstack: false,
+            UDPMultishotRecv: false,
         };
     }
 }
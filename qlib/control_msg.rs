@@ -103,6 +103,13 @@ pub struct StartArgs {
     pub process: Process,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CheckpointArgs {
+    pub cid: String,
+    // the image directory, it is written by the sandbox process
+    pub imagePath: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Payload {
     RootContainerStart(RootProcessStart),
@@ -112,6 +119,8 @@ pub enum Payload {
     WaitContainer(Cid),
     WaitPid(WaitPid),
     Ps(Cid),
+    NetStats(Cid),
+    Checkpoint(CheckpointArgs),
     Signal(SignalArgs),
     ContainerDestroy(Cid),
     CreateSubContainer(CreateArgs),
@@ -143,6 +152,8 @@ pub enum UCallResp {
     PauseResp,
     UnpauseResp,
     PsResp(Vec<ProcessInfo>),
+    NetStatsResp(Vec<NetStats>),
+    CheckpointResp,
     WaitContainerResp(u32),
     WaitPidResp(u32),
     SignalResp,
@@ -168,6 +179,23 @@ pub struct ProcessInfo {
     pub Cmd: String,
 }
 
+// NetStats are the traffic counters of the sockets of a container on an
+// interface of the sandbox.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct NetStats {
+    pub Interface: String,
+    pub RxBytes: u64,
+    pub RxPackets: u64,
+    pub RxErrors: u64,
+    pub RxDropped: u64,
+    pub TxBytes: u64,
+    pub TxPackets: u64,
+    pub TxErrors: u64,
+    pub TxDropped: u64,
+    // the connections made and accepted
+    pub Connections: u64,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WaitAllResp {
     pub cid: String,
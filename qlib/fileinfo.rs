@@ -38,7 +38,11 @@ pub enum SockInfo {
 #[derive(Clone, Default)]
 pub struct SocketInfo {
     pub ipAddr: u32,
+    // the ipv6 address bound on an AF_INET6 socket, zero for an ipv4 one
+    pub ipv6Addr: [u8; 16],
     pub port: u16,
+    // error of the RDMA connect rejected by rdma_srv
+    pub error: i32,
 }
 
 impl fmt::Debug for SockInfo {
@@ -150,6 +154,19 @@ impl FdTbl {
         }
         self.map[fd] = Some(fdInfo)
     }
+
+    // Fds returns the host fds in the table and the events waited on them
+    pub fn Fds(&self) -> Vec<(i32, EventMask)> {
+        let mut fds = Vec::new();
+        for (fd, info) in self.map.iter().enumerate() {
+            if let Some(info) = info {
+                let mask = info.lock().waitInfo.lock().mask;
+                fds.push((fd as i32, mask));
+            }
+        }
+
+        return fds;
+    }
 }
 
 #[derive(Default)]
@@ -257,6 +257,34 @@ impl HostSpace {
         return HostSpace::Call(&mut msg, false) as i64;
     }
 
+    pub fn IORecvMMsg(fd: i32, msgvec: u64, vlen: u32, flags: i32) -> i64 {
+        let mut msg = Msg::IORecvMMsg(IORecvMMsg {
+            fd,
+            msgvec,
+            vlen,
+            flags,
+        });
+
+        return HostSpace::Call(&mut msg, false) as i64;
+    }
+
+    pub fn IOSendMMsg(fd: i32, msgvec: u64, vlen: u32, flags: i32) -> i64 {
+        let mut msg = Msg::IOSendMMsg(IOSendMMsg {
+            fd,
+            msgvec,
+            vlen,
+            flags,
+        });
+
+        return HostSpace::Call(&mut msg, false) as i64;
+    }
+
+    pub fn HostUnixConnect(idx: usize, stype: i32) -> i64 {
+        let mut msg = Msg::HostUnixConnect(HostUnixConnect { idx, stype });
+
+        return HostSpace::Call(&mut msg, false) as i64;
+    }
+
     pub fn GetTimeOfDay(tv: u64, tz: u64) -> i64 {
         let mut msg = Msg::GetTimeOfDay(GetTimeOfDay { tv, tz });
 
@@ -371,6 +399,51 @@ impl HostSpace {
         return HostSpace::HCall(&mut msg, false) as i64;
     }
 
+    pub fn HostInotifyInit() -> i64 {
+        let mut msg = Msg::HostInotifyInit(HostInotifyInit {});
+
+        return HostSpace::HCall(&mut msg, false) as i64;
+    }
+
+    pub fn HostInotifyAddWatch(inotifyfd: i32, fd: i32, mask: u32) -> i64 {
+        let mut msg = Msg::HostInotifyAddWatch(HostInotifyAddWatch {
+            inotifyfd,
+            fd,
+            mask,
+        });
+
+        return HostSpace::HCall(&mut msg, false) as i64;
+    }
+
+    pub fn HostInotifyRmWatch(inotifyfd: i32, wd: i32) -> i64 {
+        let mut msg = Msg::HostInotifyRmWatch(HostInotifyRmWatch { inotifyfd, wd });
+
+        return HostSpace::HCall(&mut msg, false) as i64;
+    }
+
+    pub fn HostNameToHandleAt(fd: i32) -> i64 {
+        let mut msg = Msg::HostNameToHandleAt(HostNameToHandleAt { fd });
+
+        return HostSpace::HCall(&mut msg, false) as i64;
+    }
+
+    pub fn HostOpenByHandleAt(mountfd: i32, id: u64, readonly: bool, addr: u64) -> i64 {
+        let mut msg = Msg::HostOpenByHandleAt(HostOpenByHandleAt {
+            mountfd,
+            id,
+            readonly,
+            addr,
+        });
+
+        return HostSpace::HCall(&mut msg, false) as i64;
+    }
+
+    pub fn NetstackLinkInit(addr: u64) -> i64 {
+        let mut msg = Msg::NetstackLinkInit(NetstackLinkInit { addr });
+
+        return HostSpace::HCall(&mut msg, false) as i64;
+    }
+
     pub fn SwapInPage(addr: u64) -> i64 {
         let mut msg = Msg::SwapInPage(SwapInPage {
             addr
@@ -391,6 +464,12 @@ impl HostSpace {
         return HostSpace::HCall(&mut msg, false) as i64;
     }
 
+    pub fn Checkpoint(dir: u64) -> i64 {
+        let mut msg = Msg::Checkpoint(Checkpoint { dir });
+
+        return HostSpace::HCall(&mut msg, false) as i64;
+    }
+
     pub fn SysSync() -> i64 {
         let mut msg = Msg::SysSync(SysSync {});
 
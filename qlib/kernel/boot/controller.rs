@@ -26,10 +26,14 @@ use super::super::super::super::kernel_def::{
 };
 use super::super::super::common::*;
 use super::super::super::control_msg::*;
+use super::super::super::qmsg::qcall::CHECKPOINT_RESTORED;
 use super::super::super::vcpu_mgr::*;
 use super::super::task::*;
+use super::super::socket::hostinet::traffic::NET_TRAFFIC;
 use super::super::taskMgr;
+use super::super::util::cstring::*;
 use super::super::Kernel;
+use super::super::GlobalIOMgr;
 use super::super::SetWaitContainerfd;
 use super::super::WaitContainerfd;
 use super::super::IOURING;
@@ -169,6 +173,47 @@ pub fn ControlMsgHandler(fd: *const u8) {
             let ps = Processes(&kernel, &cid);
             WriteControlMsgResp(fd, &UCallResp::PsResp(ps), true);
         }
+        Payload::NetStats(cid) => {
+            let stats = NET_TRAFFIC.Stats(&cid);
+            WriteControlMsgResp(fd, &UCallResp::NetStatsResp(stats), true);
+        }
+        Payload::Checkpoint(args) => {
+            let sandboxID = LOADER.Lock(task).unwrap().sandboxID.clone();
+            if args.cid != sandboxID {
+                // the image holds the memory of the whole sandbox, a sub
+                // container can't be checkpointed alone
+                let err = format!(
+                    "checkpoint of sub container {} isn't supported, use the root container id {}",
+                    &args.cid, &sandboxID
+                );
+                WriteControlMsgResp(fd, &UCallResp::UCallRespErr(err), true);
+            } else {
+                // the sandbox is left paused, the caller resumes or destroys it
+                if !SHARESPACE.hibernatePause.load(atomic::Ordering::Relaxed) {
+                    GetKernel().Pause();
+                }
+                GetKernel().ClearFsCache();
+                let path = CString::New(&args.imagePath);
+                let ret = HostSpace::Checkpoint(path.Ptr());
+                if ret as u64 == CHECKPOINT_RESTORED {
+                    // the sandbox is restored from the image in a new process:
+                    // the control connections are gone and the uring ops
+                    // waiting at the checkpoint are lost, this task takes
+                    // over the controller and the host epoll is polled again
+                    SetWaitContainerfd(-1);
+                    GlobalIOMgr().InitPollHostEpoll(SHARESPACE.HostHostEpollfd());
+                    ControllerProcessHandler().expect("restored ControllerProcessHandler fail");
+                } else if ret < 0 {
+                    let err = format!(
+                        "checkpoint to {} fails with errno {}",
+                        &args.imagePath, -ret
+                    );
+                    WriteControlMsgResp(fd, &UCallResp::UCallRespErr(err), true);
+                } else {
+                    WriteControlMsgResp(fd, &UCallResp::CheckpointResp, true);
+                }
+            }
+        }
         Payload::Signal(signalArgs) => {
             HandleSignal(&signalArgs);
             WriteControlMsgResp(fd, &UCallResp::SignalResp, true);
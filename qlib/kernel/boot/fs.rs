@@ -22,10 +22,12 @@ use alloc::vec::Vec;
 use super::super::super::auth::*;
 use super::super::super::common::*;
 use super::super::super::linux_def::{FileMode, FilePermissions, SysErr};
+use super::super::super::loader::MountQuota;
 use super::super::super::path::*;
 use super::super::fs::dirent::*;
 use super::super::fs::filesystems::*;
 use super::super::fs::host::fs::*;
+use super::super::fs::host::quota::*;
 use super::super::fs::host::util::*;
 use super::super::fs::inode::*;
 use super::super::fs::mount::*;
@@ -175,6 +177,22 @@ pub fn SetupContainerFS(
     return Ok(mns);
 }
 
+// AttachDiskQuotas sets the disk quotas of the container mounts on the host
+// directories the mounts are bound to.
+pub fn AttachDiskQuotas(task: &Task, mns: &MountNs, quotas: &Vec<MountQuota>) -> Result<()> {
+    let root = mns.Root();
+    for q in quotas {
+        let mut remainingTraversals = MAX_TRAVERSALS;
+        let d = mns.FindDirent(task, &root, None, &q.Path, &mut remainingTraversals, true)?;
+        match HostQuota::SetMountQuota(&d.Inode(), &DiskQuota::New(q.Bytes, q.Inodes)) {
+            Ok(()) => info!("disk quota {:?} attached", q),
+            Err(_) => info!("ignoring disk quota of {}: not a host directory", q.Path),
+        }
+    }
+
+    return Ok(());
+}
+
 fn CompileMounts(spec: &oci::Spec) -> Vec<oci::Mount> {
     let mut _procMounted = false;
     let mut _sysMounted = false;
@@ -37,6 +37,10 @@ use super::super::kernel::ipc_namespace::*;
 use super::super::kernel::kernel::*;
 use super::super::kernel::uts_namespace::*;
 use super::super::kernel::waiter::qlock::*;
+use super::super::fs::host::socket::SetHostUnixSockets;
+use super::super::socket::hostinet::traffic::NET_TRAFFIC;
+use super::super::socket::hostinet::firewall::FIREWALL;
+use super::super::socket::netlink::SetInterfaces;
 use super::super::task::*;
 use super::super::threadmgr::thread::*;
 use super::super::threadmgr::thread_group::*;
@@ -329,6 +333,11 @@ impl Loader {
         );
         let rootMounts = InitRootFs(Task::Current(), &processSpec.Root)
             .expect("in loader::StartSubContainer, InitRootfs fail");
+        AttachDiskQuotas(Task::Current(), &rootMounts, &processSpec.DiskQuotas)?;
+        NET_TRAFFIC
+            .Get(&processSpec.ID)
+            .shaper
+            .SetRate(processSpec.EgressBandwidth);
         kernel
             .mounts
             .write()
@@ -425,7 +434,7 @@ pub struct LoaderInternal {
 
 impl LoaderInternal {
     //init the root process
-    pub fn Init(&mut self, process: Process) -> CreateProcessArgs {
+    pub fn Init(&mut self, process: Process) -> Result<CreateProcessArgs> {
         let console = process.Terminal;
         let sandboxID = process.ID.to_string();
 
@@ -462,13 +471,21 @@ impl LoaderInternal {
 
         let rootMounts =
             InitRootFs(Task::Current(), &process.Root).expect("in loader::New, InitRootfs fail");
+        AttachDiskQuotas(Task::Current(), &rootMounts, &process.DiskQuotas)?;
+        SetInterfaces(&process.Interfaces);
+        FIREWALL.SetPolicies(&process.Firewall);
+        SetHostUnixSockets(&process.HostUnixSockets);
+        NET_TRAFFIC
+            .Get(&process.ID)
+            .shaper
+            .SetRate(process.EgressBandwidth);
         kernel.mounts.write().insert(sandboxID.clone(), rootMounts);
 
         let processArgs = NewProcess(process, &creds, &kernel);
         self.kernel = kernel;
         self.console = console;
         self.sandboxID = sandboxID;
-        return processArgs;
+        return Ok(processArgs);
     }
 
     pub fn ThreadGroupFromID(&self, key: &ExecID) -> Option<(ThreadGroup, Option<TTYFileOps>)> {
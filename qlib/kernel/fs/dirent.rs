@@ -34,11 +34,14 @@ use super::super::task::*;
 use super::super::SHARESPACE;
 use super::super::uid::*;
 use super::dentry::*;
+use super::dirent_cache::*;
 use super::file::*;
 use super::flags::*;
 use super::inode::*;
 use super::mount::*;
 use super::inotify::*;
+use super::host::hostinotify::*;
+use super::fanotify::*;
 
 lazy_static! {
     pub static ref NEGATIVE_DIRENT: Dirent = Dirent::default();
@@ -131,6 +134,11 @@ impl DirentWeak {
 
         return Some(Dirent(d));
     }
+
+    // Points returns whether the weak reference points to d.
+    pub fn Points(&self, d: &Dirent) -> bool {
+        return self.0.as_ptr() == Arc::as_ptr(&d.0);
+    }
 }
 
 #[derive(Clone, Default)]
@@ -178,7 +186,7 @@ impl Drop for Dirent {
                 None => (),
                 Some(parent) => {
                     let name = self.main.lock().Name.clone();
-                    parent.RemoveChild(&name);
+                    parent.RemoveStaleChild(&name, self);
                 }
             }
 
@@ -201,6 +209,7 @@ impl Drop for Dirent {
                 // about the watch pins since if there were any active pins, this inode
                 // wouldn't be in the destructor.
                 watches.TargetDestroyed();
+                HOST_INOTIFY.RemoveWatch(self);
             }
         }
     }
@@ -432,9 +441,12 @@ impl Dirent {
         let remove = match child {
             Some(dirent) => {
                 if dirent.IsNegative() {
+                    NEGATIVE_DIRENT_HITS.Incr();
                     return Err(Error::SysError(SysErr::ENOENT))
                 }
 
+                DIRENT_CACHE_HITS.Incr();
+
                 let mounted = dirent.main.lock().mounted;
                 /*let subInode = cd.0.lock().Inode.clone();
                 let mountSource = subInode.lock().MountSource.clone();
@@ -458,8 +470,19 @@ impl Dirent {
             self.children.lock().remove(name);
         }
 
+        // the names missing in the host directories are cached for a while
+        // as a lookup of them needs a round trip to the host.
+        let hostBacked = IsHostBacked(&inode);
+        if hostBacked && FS_CACHE.IsNegative(self, name) {
+            return Err(Error::SysError(SysErr::ENOENT))
+        }
+
+        DIRENT_CACHE_MISSES.Incr();
         let c = match inode.Lookup(task, name) {
             Err(Error::SysError(SysErr::ENOENT)) => {
+                if hostBacked {
+                    FS_CACHE.AddNegative(self, name);
+                }
 
                 // why the negative doesn't work? todo: fix this
                 //let negative = Arc::downgrade(&(NEGATIVE_DIRENT.0));
@@ -491,6 +514,39 @@ impl Dirent {
         self.children.lock().remove(name);
     }
 
+    // RemoveStaleChild removes the cache entry of name only when it still refers
+    // to child. The entry might have been replaced by a new dirent after the
+    // child got invalidated.
+    fn RemoveStaleChild(&self, name: &String, child: &Dirent) {
+        let mut children = self.children.lock();
+        let stale = match children.get(name) {
+            None => return,
+            Some(d) => d.0.as_ptr() == Arc::as_ptr(&child.0) || d.0.strong_count() == 0,
+        };
+
+        if stale {
+            children.remove(name);
+        }
+    }
+
+    // InvalidateChild drops the cached child dirent of name so that the next walk
+    // looks it up again from the backing filesystem. It is used when the backing
+    // directory got changed outside of the sandbox. Mount points are kept.
+    pub fn InvalidateChild(&self, name: &str) {
+        let _cl = self.cacheMu.lock();
+        match self.GetCacheChild(name) {
+            Some(child) => {
+                if child.main.lock().mounted {
+                    return;
+                }
+            }
+            None => (),
+        }
+
+        self.children.lock().remove(name);
+        FS_CACHE.RemoveNegative(self, name);
+    }
+
     pub fn IsRoot(&self) -> bool {
         return self.main.lock().IsRoot()
     }
@@ -556,6 +612,7 @@ impl Dirent {
             return Err(Error::SysError(SysErr::EEXIST));
         }
 
+        FS_CACHE.RemoveNegative(self, name);
         let mut inode = self.Inode();
         let file = inode.Create(task, self, name, flags, perms)?;
 
@@ -570,6 +627,7 @@ impl Dirent {
                                    EventType::InodeEvent,
                                    false);
         }
+        FANOTIFY.Notify(self, InotifyEvent::IN_CREATE);
 
         return Ok(file);
     }
@@ -588,6 +646,7 @@ impl Dirent {
             return Err(Error::SysError(SysErr::EEXIST));
         }
 
+        FS_CACHE.RemoveNegative(self, name);
         self.children.lock().remove(name);
         return create();
     }
@@ -609,6 +668,7 @@ impl Dirent {
                                        EventType::PathEvent,
                                        false);
             }
+            FANOTIFY.Notify(self, InotifyEvent::IN_CREATE);
             self.children.lock().remove(oldname);
             self.children.lock().remove(newname);
             return Ok(())
@@ -648,6 +708,8 @@ impl Dirent {
                                        EventType::InodeEvent,
                                        false);
             }
+            FANOTIFY.Notify(target, InotifyEvent::IN_ATTRIB);
+            FANOTIFY.Notify(self, InotifyEvent::IN_CREATE);
             return Ok(())
         });
     }
@@ -669,6 +731,9 @@ impl Dirent {
                                        EventType::PathEvent,
                                        false);
             }
+            if ret.is_ok() {
+                FANOTIFY.Notify(self, InotifyEvent::IN_ISDIR | InotifyEvent::IN_CREATE);
+            }
 
             self.children.lock().remove(name);
             return ret;
@@ -708,6 +773,7 @@ impl Dirent {
                                    EventType::InodeEvent,
                                    false);
         }
+        FANOTIFY.Notify(self, InotifyEvent::IN_CREATE);
 
         return Ok(childDir);
     }
@@ -730,6 +796,7 @@ impl Dirent {
                                        EventType::InodeEvent,
                                        false);
             }
+            FANOTIFY.Notify(self, InotifyEvent::IN_CREATE);
             return Ok(())
         });
     }
@@ -846,6 +913,14 @@ impl Dirent {
             InotifyRemoveChild(task, Some(child.Watches()), Some(self.Watches()), name)
         }
 
+        FANOTIFY.Notify(&child, InotifyEvent::IN_ATTRIB);
+        FANOTIFY.Notify(self, InotifyEvent::IN_DELETE);
+        // The inode is gone with its last link.
+        match childInode.UnstableAttr(task) {
+            Ok(attr) if attr.Links == 0 => FANOTIFY.Notify(&child, InotifyEvent::IN_DELETE_SELF),
+            _ => (),
+        }
+
         // trigger inode destroy
         drop(child);
         drop(childInode);
@@ -896,6 +971,9 @@ impl Dirent {
                                    true);
         }
 
+        FANOTIFY.Notify(&child, InotifyEvent::IN_DELETE_SELF);
+        FANOTIFY.Notify(self, InotifyEvent::IN_ISDIR | InotifyEvent::IN_DELETE);
+
         return Ok(());
     }
 
@@ -1003,6 +1081,7 @@ impl Dirent {
                 exist = true;
             }
             Err(Error::SysError(SysErr::ENOENT)) => {
+                FS_CACHE.RemoveNegative(newParent, newName);
                 exist = false;
             }
             Err(e) => return Err(e),
@@ -1030,6 +1109,7 @@ impl Dirent {
                           newName,
                           isDir);
         }
+        FANOTIFY.NotifyRename(&renamed, oldParent, newParent);
 
         renamed.DropExtendedReference();
         renamed.Watches().Destroy();
@@ -1103,6 +1183,7 @@ impl Dirent {
                 exist = true;
             }
             Err(Error::SysError(SysErr::ENOENT)) => {
+                FS_CACHE.RemoveNegative(parent, newName);
                 exist = false;
             }
             Err(e) => return Err(e),
@@ -1131,6 +1212,7 @@ impl Dirent {
                           isDir);
 
         }
+        FANOTIFY.NotifyRename(&renamed, parent, parent);
 
         renamed.DropExtendedReference();
         renamed.flush();
@@ -1234,6 +1316,8 @@ impl Dirent {
                                    et,
                                    self.IsDeleted());
         }
+
+        FANOTIFY.Notify(self, event);
     }
 
     pub fn ExtendReference(&self) {
@@ -1241,12 +1325,14 @@ impl Dirent {
         let keep = msrc.lock().Keep(self);
         if keep {
             msrc.lock().ExtendReference(self);
+            FS_CACHE.ExtendReference(self, &msrc);
         }
     }
 
     pub fn DropExtendedReference(&self) {
         let msrc = self.Inode().lock().MountSource.clone();
         msrc.lock().DropExtendReference(self);
+        FS_CACHE.DropExtendReference(self);
     }
 
     pub fn Froze(&self) {
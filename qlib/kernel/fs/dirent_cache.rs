@@ -0,0 +1,520 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::qlib::mutex::*;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::sync::Weak;
+use alloc::vec::Vec;
+use core::ops::Deref;
+use lazy_static::lazy_static;
+
+use crate::GLOBAL_ALLOCATOR;
+use super::super::super::lrc_cache::*;
+use super::super::super::metric::*;
+use super::super::super::singleton::*;
+use super::super::kernel::timer::*;
+use super::super::SHARESPACE;
+use super::dirent::*;
+use super::host::diriops::*;
+use super::inode::*;
+use super::mount::*;
+
+pub static DIRENT_CACHE_HITS: Singleton<Arc<U64Metric>> = Singleton::<Arc<U64Metric>>::New();
+pub static DIRENT_CACHE_MISSES: Singleton<Arc<U64Metric>> = Singleton::<Arc<U64Metric>>::New();
+pub static NEGATIVE_DIRENT_HITS: Singleton<Arc<U64Metric>> = Singleton::<Arc<U64Metric>>::New();
+pub static DIRENT_CACHE_EVICTIONS: Singleton<Arc<U64Metric>> = Singleton::<Arc<U64Metric>>::New();
+pub static FS_CACHE_SHRINKS: Singleton<Arc<U64Metric>> = Singleton::<Arc<U64Metric>>::New();
+
+pub unsafe fn InitSingleton() {
+    DIRENT_CACHE_HITS.Init(NewU64Metric(
+        "/fs/dirent_cache_hits",
+        false,
+        "Number of lookups served by the dirent cache.",
+    ));
+    DIRENT_CACHE_MISSES.Init(NewU64Metric(
+        "/fs/dirent_cache_misses",
+        false,
+        "Number of lookups passed to the filesystem.",
+    ));
+    NEGATIVE_DIRENT_HITS.Init(NewU64Metric(
+        "/fs/negative_dirent_hits",
+        false,
+        "Number of lookups served by the negative dirent cache.",
+    ));
+    DIRENT_CACHE_EVICTIONS.Init(NewU64Metric(
+        "/fs/dirent_cache_evictions",
+        false,
+        "Number of dirents evicted from the dirent cache.",
+    ));
+    FS_CACHE_SHRINKS.Init(NewU64Metric(
+        "/fs/cache_shrinks",
+        false,
+        "Number of times the filesystem caches were shrunk on memory pressure.",
+    ));
+}
+
+lazy_static! {
+    pub static ref FS_CACHE: FsCache = FsCache::New();
+}
+
+// The memory pressure is checked every SHRINK_CHECK_INTERVAL insertions in
+// the caches.
+const SHRINK_CHECK_INTERVAL: u64 = 256;
+
+// On memory pressure, the caches are shrunk to SHRINK_RATIO percent of their
+// size.
+const SHRINK_RATIO: u64 = 50;
+
+// NegativeDirent is a name known not to exist in a directory.
+#[derive(Clone)]
+pub struct NegativeDirent {
+    pub parent: u64,
+    pub name: String,
+    // expire is the monotonic time after which the name must be looked up
+    // again, the host directory might have been changed outside the sandbox.
+    pub expire: i64,
+}
+
+// NegativeCache is the LRU of the negative dirents, keyed by parent dirent id
+// and name.
+pub struct NegativeCache {
+    pub lru: LruCache<NegativeDirent>,
+    pub keys: BTreeMap<(u64, String), u64>,
+    pub nextKey: u64,
+}
+
+impl NegativeCache {
+    pub fn New(size: u64) -> Self {
+        return Self {
+            lru: LruCache::New(size),
+            keys: BTreeMap::new(),
+            nextKey: 0,
+        };
+    }
+
+    // Lookup returns whether name is known not to exist in parent at now, the
+    // expired entry is dropped.
+    pub fn Lookup(&mut self, parent: u64, name: &str, now: i64) -> bool {
+        let k = (parent, name.to_string());
+        let key = match self.keys.get(&k) {
+            None => return false,
+            Some(key) => *key,
+        };
+
+        let expire = match self.lru.Get(key) {
+            None => return false,
+            Some(n) => n.expire,
+        };
+
+        if expire <= now {
+            self.lru.Remove(key);
+            self.keys.remove(&k);
+            return false;
+        }
+
+        return true;
+    }
+
+    // Add records that name doesn't exist in parent until expire, evicting
+    // the least recently added entry when the cache is full.
+    pub fn Add(&mut self, parent: u64, name: &str, expire: i64) {
+        self.Remove(parent, name);
+
+        let key = self.nextKey;
+        self.nextKey += 1;
+        let entry = NegativeDirent {
+            parent: parent,
+            name: name.to_string(),
+            expire: expire,
+        };
+
+        if let Some((_, old)) = self.lru.Push(key, entry) {
+            self.keys.remove(&(old.parent, old.name));
+        }
+        self.keys.insert((parent, name.to_string()), key);
+    }
+
+    pub fn Remove(&mut self, parent: u64, name: &str) {
+        if let Some(key) = self.keys.remove(&(parent, name.to_string())) {
+            self.lru.Remove(key);
+        }
+    }
+
+    pub fn Clear(&mut self) {
+        self.lru.Clear();
+        self.keys.clear();
+    }
+}
+
+pub struct FsCacheIntern {
+    // dirents bounds the count of dirents kept alive by all the mount sources,
+    // dirent id -> mount source caching it.
+    pub dirents: LruCache<Weak<QMutex<MountSource>>>,
+
+    // readdirs bounds the count of host directories caching their entries.
+    pub readdirs: LruCache<HostDirOpWeak>,
+
+    // negative is None when the negative dirent cache is disabled.
+    pub negative: Option<NegativeCache>,
+
+    pub insertions: u64,
+}
+
+// Evicted holds what the caches evicted, it is released after the cache lock
+// is dropped as the eviction takes the mount source and directory locks.
+#[derive(Default)]
+pub struct Evicted {
+    pub dirents: Vec<(u64, Weak<QMutex<MountSource>>)>,
+    pub readdirs: Vec<HostDirOpWeak>,
+}
+
+impl Evicted {
+    pub fn Release(self) {
+        for (id, msrc) in self.dirents {
+            if let Some(msrc) = msrc.upgrade() {
+                if msrc.lock().EvictReference(id) {
+                    DIRENT_CACHE_EVICTIONS.Incr();
+                }
+            }
+        }
+
+        for dir in self.readdirs {
+            if let Some(dir) = dir.Upgrade() {
+                dir.lock().readdirCache = None;
+            }
+        }
+    }
+}
+
+impl FsCacheIntern {
+    // New returns the caches of the sizes, the negative dirent cache is
+    // disabled with the size 0.
+    pub fn New(direntSize: u64, readdirSize: u64, negativeSize: u64) -> Self {
+        let negative = if negativeSize == 0 {
+            None
+        } else {
+            Some(NegativeCache::New(negativeSize))
+        };
+
+        return Self {
+            dirents: LruCache::New(core::cmp::max(direntSize, 1)),
+            readdirs: LruCache::New(core::cmp::max(readdirSize, 1)),
+            negative: negative,
+            insertions: 0,
+        };
+    }
+
+    // Shrink evicts the least recently used half of the dirents and the
+    // directory entries and drops the negative dirents.
+    fn Shrink(&mut self, evicted: &mut Evicted) {
+        let count = self.dirents.Size() * (100 - SHRINK_RATIO) / 100;
+        for _ in 0..count {
+            match self.dirents.PopBack() {
+                None => break,
+                Some(e) => evicted.dirents.push(e),
+            }
+        }
+
+        let count = self.readdirs.Size() * (100 - SHRINK_RATIO) / 100;
+        for _ in 0..count {
+            match self.readdirs.PopBack() {
+                None => break,
+                Some((_, dir)) => evicted.readdirs.push(dir),
+            }
+        }
+
+        if let Some(negative) = &mut self.negative {
+            negative.Clear();
+        }
+    }
+
+    // CheckDue counts an insertion and returns whether the memory pressure is
+    // to be checked.
+    fn CheckDue(&mut self) -> bool {
+        self.insertions += 1;
+        return self.insertions % SHRINK_CHECK_INTERVAL == 0;
+    }
+
+    fn Inserted(&mut self, evicted: &mut Evicted) {
+        if self.CheckDue() && MemoryPressure() {
+            FS_CACHE_SHRINKS.Incr();
+            self.Shrink(evicted);
+        }
+    }
+}
+
+pub struct FsCache(QMutex<FsCacheIntern>);
+
+impl Deref for FsCache {
+    type Target = QMutex<FsCacheIntern>;
+
+    fn deref(&self) -> &QMutex<FsCacheIntern> {
+        &self.0
+    }
+}
+
+impl FsCache {
+    pub fn New() -> Self {
+        let config = SHARESPACE.config.read();
+        let intern = FsCacheIntern::New(
+            config.DirentCacheSize,
+            config.ReaddirCacheSize,
+            config.NegativeDirentCacheSize,
+        );
+
+        return Self(QMutex::new(intern));
+    }
+
+    // ExtendReference records that msrc keeps the dirent alive and evicts the
+    // least recently used dirent of all the mounts when the cache is full.
+    pub fn ExtendReference(&self, dirent: &Dirent, msrc: &Arc<QMutex<MountSource>>) {
+        let mut evicted = Evicted::default();
+        {
+            let mut c = self.lock();
+            if let Some(e) = c.dirents.Push(dirent.ID(), Arc::downgrade(msrc)) {
+                evicted.dirents.push(e);
+            }
+            c.Inserted(&mut evicted);
+        }
+
+        evicted.Release();
+    }
+
+    pub fn DropExtendReference(&self, dirent: &Dirent) {
+        self.lock().dirents.Remove(dirent.ID());
+    }
+
+    // CacheReaddir records that the host directory caches its entries and
+    // evicts the entries of the least recently read directory when the cache
+    // is full.
+    pub fn CacheReaddir(&self, dir: &HostDirOp) {
+        let key = Arc::as_ptr(&dir.0) as u64;
+        let weak = dir.Downgrade();
+        let mut evicted = Evicted::default();
+        {
+            let mut c = self.lock();
+            // the address of a dropped directory might have been reused
+            if let Some(old) = c.readdirs.Get(key) {
+                if !old.0.ptr_eq(&weak.0) {
+                    c.readdirs.Remove(key);
+                }
+            }
+
+            if let Some((_, dir)) = c.readdirs.Push(key, weak) {
+                evicted.readdirs.push(dir);
+            }
+            c.Inserted(&mut evicted);
+        }
+
+        evicted.Release();
+    }
+
+    // IsNegative returns whether name is known not to exist in parent.
+    pub fn IsNegative(&self, parent: &Dirent, name: &str) -> bool {
+        let mut c = self.lock();
+        let negative = match &mut c.negative {
+            None => return false,
+            Some(n) => n,
+        };
+
+        if !negative.Lookup(parent.ID(), name, MonotonicNow()) {
+            return false;
+        }
+
+        NEGATIVE_DIRENT_HITS.Incr();
+        return true;
+    }
+
+    // AddNegative records that name doesn't exist in parent.
+    pub fn AddNegative(&self, parent: &Dirent, name: &str) {
+        let ttl = SHARESPACE.config.read().NegativeDirentTTL as i64 * 1_000_000;
+        let mut evicted = Evicted::default();
+        {
+            let mut c = self.lock();
+            match &mut c.negative {
+                None => return,
+                Some(n) => n.Add(parent.ID(), name, MonotonicNow() + ttl),
+            }
+            c.Inserted(&mut evicted);
+        }
+
+        evicted.Release();
+    }
+
+    // RemoveNegative forgets that name doesn't exist in parent, it is called
+    // before name gets created.
+    pub fn RemoveNegative(&self, parent: &Dirent, name: &str) {
+        if let Some(negative) = &mut self.lock().negative {
+            negative.Remove(parent.ID(), name);
+        }
+    }
+}
+
+// MemoryPressure returns whether the kernel heap is used above the configured
+// threshold.
+pub fn MemoryPressure() -> bool {
+    let threshold = SHARESPACE.config.read().FsCacheShrinkThreshold as usize;
+    if threshold == 0 {
+        return false;
+    }
+
+    let (used, total) = GLOBAL_ALLOCATOR.Allocator().HeapUsage();
+    return AboveThreshold(used, total, threshold);
+}
+
+// AboveThreshold returns whether used is at least threshold percent of total.
+pub fn AboveThreshold(used: usize, total: usize, threshold: usize) -> bool {
+    return used * 100 >= total * threshold;
+}
+
+// IsHostBacked returns whether the directory is backed by a host directory,
+// whose entries might be changed outside the sandbox.
+pub fn IsHostBacked(inode: &Inode) -> bool {
+    let upper = match &inode.lock().Overlay {
+        None => None,
+        Some(overlay) => overlay.read().upper.clone(),
+    };
+
+    let inode = match upper {
+        None => inode.clone(),
+        Some(upper) => upper,
+    };
+
+    let iops = inode.lock().InodeOp.clone();
+    return iops.HostDirOp().is_some();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn Ids(evicted: &Evicted) -> Vec<u64> {
+        return evicted.dirents.iter().map(|(id, _)| *id).collect();
+    }
+
+    #[test]
+    fn test_DirentEvictionOrder() {
+        let mut c = FsCacheIntern::New(3, 1, 0);
+        for id in 1..4 {
+            assert!(c.dirents.Push(id, Weak::new()).is_none());
+        }
+
+        // a dirent referenced again becomes the most recently used one
+        assert!(c.dirents.Push(1, Weak::new()).is_none());
+        assert_eq!(c.dirents.Push(4, Weak::new()).map(|(id, _)| id), Some(2));
+        assert_eq!(c.dirents.Push(5, Weak::new()).map(|(id, _)| id), Some(3));
+
+        // a dropped reference is not evicted
+        assert!(c.dirents.Remove(1));
+        assert!(c.dirents.Push(6, Weak::new()).is_none());
+        assert_eq!(c.dirents.PopBack().map(|(id, _)| id), Some(4));
+        assert_eq!(c.dirents.PopBack().map(|(id, _)| id), Some(5));
+        assert_eq!(c.dirents.PopBack().map(|(id, _)| id), Some(6));
+        assert!(c.dirents.PopBack().is_none());
+    }
+
+    #[test]
+    fn test_Shrink() {
+        let mut c = FsCacheIntern::New(8, 4, 4);
+        for id in 1..7 {
+            c.dirents.Push(id, Weak::new());
+        }
+        for key in 1..5 {
+            c.readdirs.Push(key, HostDirOpWeak(Weak::new()));
+        }
+        let negative = c.negative.as_mut().unwrap();
+        negative.Add(1, "a", 100);
+        negative.Add(1, "b", 100);
+
+        // the least recently used half is evicted, the negative dirents are
+        // all dropped
+        let mut evicted = Evicted::default();
+        c.Shrink(&mut evicted);
+        assert_eq!(Ids(&evicted), vec![1, 2, 3]);
+        assert_eq!(evicted.readdirs.len(), 2);
+        assert_eq!(c.dirents.Size(), 3);
+        assert_eq!(c.readdirs.Size(), 2);
+        assert!(c.readdirs.ContainsKey(4));
+        assert!(!c.readdirs.ContainsKey(1));
+
+        let negative = c.negative.as_mut().unwrap();
+        assert_eq!(negative.lru.Size(), 0);
+        assert!(negative.keys.is_empty());
+        assert!(!negative.Lookup(1, "a", 0));
+    }
+
+    #[test]
+    fn test_ShrinkThreshold() {
+        let mut c = FsCacheIntern::New(1, 1, 0);
+        for i in 1..SHRINK_CHECK_INTERVAL * 2 + 1 {
+            let due = i % SHRINK_CHECK_INTERVAL == 0;
+            assert_eq!(c.CheckDue(), due, "insertion {}", i);
+        }
+
+        assert!(!AboveThreshold(79, 100, 80));
+        assert!(AboveThreshold(80, 100, 80));
+        assert!(AboveThreshold(100, 100, 80));
+        assert!(!AboveThreshold(1 << 30, 4 << 30, 30));
+        assert!(AboveThreshold(3 << 30, 4 << 30, 75));
+    }
+
+    #[test]
+    fn test_NegativeExpire() {
+        let mut n = NegativeCache::New(4);
+        n.Add(1, "a", 100);
+        assert!(n.Lookup(1, "a", 99));
+        assert!(!n.Lookup(1, "b", 99));
+        assert!(!n.Lookup(2, "a", 99));
+
+        // the entry expires at its deadline and is dropped
+        assert!(!n.Lookup(1, "a", 100));
+        assert!(n.keys.is_empty());
+        assert_eq!(n.lru.Size(), 0);
+        assert!(!n.Lookup(1, "a", 0));
+
+        // adding the name again renews the deadline
+        n.Add(1, "a", 100);
+        n.Add(1, "a", 200);
+        assert_eq!(n.lru.Size(), 1);
+        assert!(n.Lookup(1, "a", 150));
+
+        n.Remove(1, "a");
+        assert!(!n.Lookup(1, "a", 150));
+        assert_eq!(n.lru.Size(), 0);
+    }
+
+    #[test]
+    fn test_NegativeEviction() {
+        let mut n = NegativeCache::New(2);
+        n.Add(1, "a", 100);
+        n.Add(1, "b", 100);
+        n.Add(2, "a", 100);
+
+        // the oldest entry is evicted with its key
+        assert!(!n.Lookup(1, "a", 0));
+        assert!(n.Lookup(1, "b", 0));
+        assert!(n.Lookup(2, "a", 0));
+        assert_eq!(n.keys.len(), 2);
+
+        // a renewed entry is the most recent one
+        n.Add(1, "b", 100);
+        n.Add(3, "c", 100);
+        assert!(n.Lookup(1, "b", 0));
+        assert!(!n.Lookup(2, "a", 0));
+        assert_eq!(n.keys.len(), 2);
+    }
+}
@@ -0,0 +1,1298 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::sync::Arc;
+use alloc::sync::Weak;
+use alloc::vec::Vec;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::vec_deque::VecDeque;
+use core::ops::Deref;
+use core::any::Any;
+use lazy_static::lazy_static;
+
+use crate::qlib::mutex::*;
+use crate::qlib::kernel::kernel::waiter::*;
+use crate::qlib::kernel::fs::dentry::*;
+use crate::qlib::kernel::fs::attr::UnstableAttr;
+use crate::qlib::kernel::memmgr::vma::MMappable;
+use super::super::task::*;
+use super::super::super::common::*;
+use super::super::super::linux_def::*;
+use super::super::uid::*;
+use super::super::kernel::waiter::Queue;
+use super::super::kernel::fd_table::*;
+use super::super::fs::dirent::*;
+use super::super::fs::flags::*;
+use super::file::*;
+use super::file_handle::*;
+
+// FANOTIFY_EVENT_METADATA_SIZE is the size of linux's struct fanotify_event_metadata.
+pub const FANOTIFY_EVENT_METADATA_SIZE: usize = 24;
+
+// FANOTIFY_RESPONSE_SIZE is the size of linux's struct fanotify_response.
+pub const FANOTIFY_RESPONSE_SIZE: usize = 8;
+
+lazy_static! {
+    pub static ref FANOTIFY: FanotifyGroups = FanotifyGroups::default();
+}
+
+// FanotifyEventMetadata represents a struct fanotify_event_metadata from linux.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FanotifyEventMetadata {
+    pub event_len: u32,
+    pub vers: u8,
+    pub reserved: u8,
+    pub metadata_len: u16,
+    pub mask: u64,
+    pub fd: i32,
+    pub pid: i32,
+}
+
+// FanotifyEventInfoFid represents a struct fanotify_event_info_fid from linux,
+// it is followed by the f_handle bytes of the file handle.
+#[repr(C, packed)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FanotifyEventInfoFid {
+    pub info_type: u8,
+    pub pad: u8,
+    pub len: u16,
+    pub fsid: [i32; 2],
+    pub handle_bytes: u32,
+    pub handle_type: i32,
+}
+
+// FanotifyResponse represents a struct fanotify_response from linux.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FanotifyResponse {
+    pub fd: i32,
+    pub response: u32,
+}
+
+impl FanotifyResponse {
+    // Decision returns FAN_ALLOW or FAN_DENY, FAN_AUDIT is accepted and ignored.
+    pub fn Decision(&self) -> Result<u32> {
+        let decision = self.response & !Fanotify::FAN_AUDIT;
+        if decision != Fanotify::FAN_ALLOW && decision != Fanotify::FAN_DENY {
+            return Err(Error::SysError(SysErr::EINVAL));
+        }
+
+        return Ok(decision);
+    }
+}
+
+// FanMarkKey identifies the object a mark is attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FanMarkKey {
+    // (device id, inode id)
+    Inode(u64, u64),
+    // the mount source of the mount
+    Mount(u64),
+    // device id
+    Filesystem(u64),
+}
+
+impl FanMarkKey {
+    pub fn InodeKey(d: &Dirent) -> Self {
+        let attr = d.Inode().StableAttr();
+        return Self::Inode(attr.DeviceId, attr.InodeId);
+    }
+
+    // The mount is identified by its mount source rather than the mount root
+    // dirent, finding the latter needs the rename lock which might already be
+    // held by the caller.
+    pub fn MountKey(d: &Dirent) -> Self {
+        let msrc = d.Inode().lock().MountSource.clone();
+        return Self::Mount(Arc::as_ptr(&msrc) as *const u8 as u64);
+    }
+
+    pub fn FilesystemKey(d: &Dirent) -> Self {
+        return Self::Filesystem(d.Inode().StableAttr().DeviceId);
+    }
+
+    pub fn New(d: &Dirent, markType: u32) -> Self {
+        if markType & Fanotify::FAN_MARK_MOUNT != 0 {
+            return Self::MountKey(d);
+        }
+
+        if markType & Fanotify::FAN_MARK_FILESYSTEM != 0 {
+            return Self::FilesystemKey(d);
+        }
+
+        return Self::InodeKey(d);
+    }
+
+    // FromIds returns the key of the given mark type for the object identified
+    // by dev, ino and its mount source msrc.
+    pub fn FromIds(markType: u32, dev: u64, ino: u64, msrc: u64) -> Self {
+        if markType & Fanotify::FAN_MARK_MOUNT != 0 {
+            return Self::Mount(msrc);
+        }
+
+        if markType & Fanotify::FAN_MARK_FILESYSTEM != 0 {
+            return Self::Filesystem(dev);
+        }
+
+        return Self::Inode(dev, ino);
+    }
+
+    pub fn MarkType(&self) -> u32 {
+        match self {
+            Self::Inode(..) => Fanotify::FAN_MARK_INODE,
+            Self::Mount(..) => Fanotify::FAN_MARK_MOUNT,
+            Self::Filesystem(..) => Fanotify::FAN_MARK_FILESYSTEM,
+        }
+    }
+}
+
+// FanMarkMasks are the event masks of a mark.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FanMarkMasks {
+    pub mask: u64,
+    pub ignoredMask: u64,
+
+    // ignoredSurvModify is false if the ignored mask is to be cleared on the
+    // next FAN_MODIFY event of the object.
+    pub ignoredSurvModify: bool,
+}
+
+impl FanMarkMasks {
+    // Add merges mask into the mark for FAN_MARK_ADD.
+    pub fn Add(&mut self, mask: u64, flags: u32) {
+        if flags & Fanotify::FAN_MARK_IGNORED_MASK != 0 {
+            self.ignoredMask |= mask;
+            if flags & Fanotify::FAN_MARK_IGNORED_SURV_MODIFY != 0 {
+                self.ignoredSurvModify = true;
+            }
+        } else {
+            self.mask |= mask;
+        }
+    }
+
+    // Remove clears mask from the mark for FAN_MARK_REMOVE. It returns whether
+    // the mark is empty and is to be dropped.
+    pub fn Remove(&mut self, mask: u64, flags: u32) -> bool {
+        if flags & Fanotify::FAN_MARK_IGNORED_MASK != 0 {
+            self.ignoredMask &= !mask;
+        } else {
+            self.mask &= !mask;
+        }
+
+        return self.mask == 0 && self.ignoredMask == 0;
+    }
+}
+
+// MatchMasks returns the events of events the marks of the object objs and
+// the mark of its parent are interested in. A FAN_MODIFY event clears the
+// ignored masks of the object marks without FAN_MARK_IGNORED_SURV_MODIFY.
+pub fn MatchMasks(
+    objs: &mut [&mut FanMarkMasks],
+    parent: Option<&FanMarkMasks>,
+    events: u64,
+) -> u64 {
+    let mut mask = 0;
+    let mut ignored = 0;
+    for obj in objs.iter_mut() {
+        if events & Fanotify::FAN_MODIFY != 0 && !obj.ignoredSurvModify {
+            obj.ignoredMask = 0;
+        }
+
+        mask |= obj.mask;
+        ignored |= obj.ignoredMask;
+    }
+
+    if let Some(p) = parent {
+        if p.mask & Fanotify::FAN_EVENT_ON_CHILD != 0 {
+            mask |= p.mask;
+            ignored |= p.ignoredMask;
+        }
+    }
+
+    let ondir = events & Fanotify::FAN_ONDIR;
+    if ondir != 0 && mask & Fanotify::FAN_ONDIR == 0 {
+        return 0;
+    }
+
+    let matched =
+        events & mask & !ignored & (Fanotify::FAN_ALL_EVENTS | Fanotify::FAN_ALL_PERM_EVENTS);
+    if matched == 0 {
+        return 0;
+    }
+
+    return matched | ondir;
+}
+
+// FanMark is a mark added by fanotify_mark(2) on an inode, a mount or a
+// filesystem.
+pub struct FanMark {
+    pub masks: FanMarkMasks,
+
+    // The dirent the mark was added through. It pins the marked inode, or the
+    // mount, in memory.
+    pub target: Dirent,
+}
+
+// FanPermRequest is the decision the task which triggered a permission event
+// is waiting for.
+#[derive(Default)]
+pub struct FanPermRequest {
+    // 0 while the listener hasn't answered, FAN_ALLOW or FAN_DENY after that.
+    pub response: QMutex<u32>,
+    pub queue: Queue,
+}
+
+impl FanPermRequest {
+    pub fn Respond(&self, response: u32) {
+        *self.response.lock() = response;
+        self.queue.Notify(READABLE_EVENT);
+    }
+
+    // Decision returns the result of the access, None while the listener
+    // hasn't answered.
+    pub fn Decision(&self) -> Option<Result<()>> {
+        let response = *self.response.lock();
+        if response == Fanotify::FAN_DENY {
+            return Some(Err(Error::SysError(SysErr::EPERM)));
+        } else if response != 0 {
+            return Some(Ok(()));
+        }
+
+        return None;
+    }
+
+    // Wait blocks the task until the listener has answered.
+    pub fn Wait(&self, task: &Task) -> Result<()> {
+        let general = task.blocker.generalEntry.clone();
+        self.queue.EventRegister(task, &general, READABLE_EVENT);
+        defer!(self.queue.EventUnregister(task, &general));
+
+        loop {
+            if let Some(res) = self.Decision() {
+                return res;
+            }
+
+            match task.blocker.BlockWithMonoTimer(true, None) {
+                Err(Error::ErrInterrupted) => {
+                    return Err(Error::SysError(SysErr::ERESTARTSYS))
+                },
+                Err(e) => {
+                    return Err(e);
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+// FanEvent is an event queued on a fanotify group.
+pub struct FanEvent {
+    pub mask: u64,
+
+    // The object of the event, used to open the fd reported to groups without
+    // FAN_REPORT_FID and to keep the FID resolvable once it is read by groups
+    // with FAN_REPORT_FID. None for overflow events.
+    pub target: Option<Dirent>,
+
+    // The file handle of the object, reported as FID.
+    pub handle: FileHandle,
+
+    pub pid: i32,
+    pub tid: i32,
+
+    // Set for permission events.
+    pub perm: Option<Arc<FanPermRequest>>,
+}
+
+impl FanEvent {
+    pub fn Sizeof(&self, reportFid: bool) -> usize {
+        if reportFid && self.mask & Fanotify::FAN_Q_OVERFLOW == 0 {
+            return FANOTIFY_EVENT_METADATA_SIZE
+                + core::mem::size_of::<FanotifyEventInfoFid>()
+                + self.handle.Sizeof();
+        }
+
+        return FANOTIFY_EVENT_METADATA_SIZE;
+    }
+
+    // Mergeable returns whether ev can be merged into the event, i.e. both are
+    // non permission events about the same object from the same thread.
+    pub fn Mergeable(&self, ev: &FanEvent) -> bool {
+        return self.perm.is_none()
+            && ev.perm.is_none()
+            && self.handle.dev == ev.handle.dev
+            && self.handle.ino == ev.handle.ino
+            && self.pid == ev.pid
+            && self.tid == ev.tid
+            && self.mask & Fanotify::FAN_Q_OVERFLOW == 0;
+    }
+
+    // Encode writes the event metadata, followed by the FID record when
+    // reportFid is set, in buf and returns the length written.
+    pub fn Encode(&self, buf: &mut [u8], fd: i32, reportFid: bool, reportTid: bool) -> usize {
+        let len = self.Sizeof(reportFid);
+        let metadata = FanotifyEventMetadata {
+            event_len: len as u32,
+            vers: Fanotify::FANOTIFY_METADATA_VERSION,
+            reserved: 0,
+            metadata_len: FANOTIFY_EVENT_METADATA_SIZE as u16,
+            mask: self.mask,
+            fd: fd,
+            pid: if reportTid { self.tid } else { self.pid },
+        };
+
+        unsafe {
+            core::ptr::write_unaligned(
+                &mut buf[0] as *mut _ as *mut FanotifyEventMetadata,
+                metadata,
+            );
+        }
+
+        if len > FANOTIFY_EVENT_METADATA_SIZE {
+            let dev = self.handle.dev;
+            let handle = self.handle.Encode();
+            let info = FanotifyEventInfoFid {
+                info_type: Fanotify::FAN_EVENT_INFO_TYPE_FID,
+                pad: 0,
+                len: (len - FANOTIFY_EVENT_METADATA_SIZE) as u16,
+                fsid: [dev as i32, (dev >> 32) as i32],
+                handle_bytes: handle.len() as u32,
+                handle_type: self.handle.handleType,
+            };
+
+            let infoSize = core::mem::size_of::<FanotifyEventInfoFid>();
+            unsafe {
+                core::ptr::write_unaligned(
+                    &mut buf[FANOTIFY_EVENT_METADATA_SIZE] as *mut _ as *mut FanotifyEventInfoFid,
+                    info,
+                );
+            }
+
+            let start = FANOTIFY_EVENT_METADATA_SIZE + infoSize;
+            buf[start..start + handle.len()].copy_from_slice(&handle);
+        }
+
+        return len;
+    }
+}
+
+#[derive(Default)]
+pub struct FanEventQueue {
+    pub events: VecDeque<FanEvent>,
+
+    // overflow is set when a FAN_Q_OVERFLOW event is queued, no more events are
+    // queued until it is read.
+    pub overflow: bool,
+}
+
+pub struct FanotifyGroupIntern {
+    // Unique identifier for this group.
+    pub id: u64,
+
+    // flags passed to fanotify_init(2).
+    pub flags: u32,
+
+    // event_f_flags passed to fanotify_init(2), used to open the event fds.
+    pub eventFlags: u32,
+
+    pub queue: Queue,
+
+    pub events: QMutex<FanEventQueue>,
+
+    pub marks: QMutex<BTreeMap<FanMarkKey, FanMark>>,
+
+    // Permission events which have been read, keyed by the fd reported with
+    // the event, waiting for a response.
+    pub pending: QMutex<BTreeMap<i32, Arc<FanPermRequest>>>,
+}
+
+#[derive(Clone)]
+pub struct FanotifyGroup(Arc<FanotifyGroupIntern>);
+
+impl Deref for FanotifyGroup {
+    type Target = Arc<FanotifyGroupIntern>;
+
+    fn deref(&self) -> &Arc<FanotifyGroupIntern> {
+        &self.0
+    }
+}
+
+impl Drop for FanotifyGroup {
+    fn drop(&mut self) {
+        if Arc::strong_count(&self.0) == 1 {
+            self.Release();
+        }
+    }
+}
+
+impl FanotifyGroup {
+    pub fn New(flags: u32, eventFlags: u32) -> Self {
+        let intern = FanotifyGroupIntern {
+            id: NewUID(),
+            flags: flags,
+            eventFlags: eventFlags,
+            queue: Queue::default(),
+            events: QMutex::new(FanEventQueue::default()),
+            marks: QMutex::new(BTreeMap::new()),
+            pending: QMutex::new(BTreeMap::new()),
+        };
+
+        let group = Self(Arc::new(intern));
+        FANOTIFY.Add(&group);
+        return group;
+    }
+
+    pub fn Downgrade(&self) -> Weak<FanotifyGroupIntern> {
+        return Arc::downgrade(&self.0);
+    }
+
+    pub fn ReportFid(&self) -> bool {
+        return self.flags & Fanotify::FAN_REPORT_FID != 0;
+    }
+
+    pub fn Class(&self) -> u32 {
+        return self.flags & Fanotify::FAN_ALL_CLASS_BITS;
+    }
+
+    // Release drops the marks of the group and allows all the permission events
+    // still waiting for a decision, as linux does when the group is closed.
+    pub fn Release(&self) {
+        FANOTIFY.Remove(self.id);
+        self.marks.lock().clear();
+
+        let pending: Vec<Arc<FanPermRequest>> = {
+            let mut pending = self.pending.lock();
+            let reqs = pending.values().cloned().collect();
+            pending.clear();
+            reqs
+        };
+
+        for req in pending {
+            req.Respond(Fanotify::FAN_ALLOW);
+        }
+
+        let events: Vec<FanEvent> = self.events.lock().events.drain(..).collect();
+        for event in events {
+            if let Some(req) = &event.perm {
+                req.Respond(Fanotify::FAN_ALLOW);
+            }
+        }
+    }
+
+    pub fn AddMark(&self, target: &Dirent, markType: u32, mask: u64, flags: u32) -> Result<()> {
+        let key = FanMarkKey::New(target, markType);
+        let mut marks = self.marks.lock();
+        if !marks.contains_key(&key) {
+            if self.flags & Fanotify::FAN_UNLIMITED_MARKS == 0
+                && marks.len() >= Fanotify::FANOTIFY_DEFAULT_MAX_MARKS
+            {
+                return Err(Error::SysError(SysErr::ENOSPC));
+            }
+
+            marks.insert(key, FanMark {
+                masks: FanMarkMasks::default(),
+                target: target.clone(),
+            });
+        }
+
+        marks.get_mut(&key).unwrap().masks.Add(mask, flags);
+        return Ok(());
+    }
+
+    pub fn RemoveMark(&self, target: &Dirent, markType: u32, mask: u64, flags: u32) -> Result<()> {
+        let key = FanMarkKey::New(target, markType);
+        let mut marks = self.marks.lock();
+        let empty = match marks.get_mut(&key) {
+            None => return Err(Error::SysError(SysErr::ENOENT)),
+            Some(mark) => mark.masks.Remove(mask, flags),
+        };
+
+        if empty {
+            marks.remove(&key);
+        }
+
+        return Ok(());
+    }
+
+    // FlushMarks removes all the marks of the given type.
+    pub fn FlushMarks(&self, markType: u32) {
+        self.marks.lock().retain(|key, _| key.MarkType() != markType);
+    }
+
+    // Match returns the events of events the group is interested in. parent
+    // is the parent of the object for the events which happen on a child of a
+    // directory.
+    pub fn Match(&self, keys: &[FanMarkKey], parent: Option<FanMarkKey>, events: u64) -> u64 {
+        let mut marks = self.marks.lock();
+        if marks.len() == 0 {
+            return 0;
+        }
+
+        let parent = match parent {
+            None => None,
+            Some(key) => marks.get(&key).map(|mark| mark.masks),
+        };
+
+        let mut objs: Vec<&mut FanMarkMasks> = marks
+            .iter_mut()
+            .filter(|(key, _)| keys.contains(*key))
+            .map(|(_, mark)| &mut mark.masks)
+            .collect();
+
+        return MatchMasks(&mut objs, parent.as_ref(), events);
+    }
+
+    // QueueEvent queues ev and wakes up the readers. Non permission events are
+    // merged with the last queued event if they are about the same object.
+    pub fn QueueEvent(&self, ev: FanEvent) {
+        {
+            let mut q = self.events.lock();
+            if q.overflow {
+                if let Some(req) = &ev.perm {
+                    req.Respond(Fanotify::FAN_ALLOW);
+                }
+                return;
+            }
+
+            if let Some(last) = q.events.back_mut() {
+                if last.Mergeable(&ev) {
+                    last.mask |= ev.mask;
+                    return;
+                }
+            }
+
+            if self.flags & Fanotify::FAN_UNLIMITED_QUEUE == 0
+                && q.events.len() >= Fanotify::FANOTIFY_DEFAULT_MAX_EVENTS
+            {
+                // Nobody will answer a permission event which doesn't make it
+                // to the queue, let it go.
+                if let Some(req) = &ev.perm {
+                    req.Respond(Fanotify::FAN_ALLOW);
+                }
+
+                q.overflow = true;
+                q.events.push_back(FanEvent {
+                    mask: Fanotify::FAN_Q_OVERFLOW,
+                    target: None,
+                    handle: FileHandle::default(),
+                    pid: 0,
+                    tid: 0,
+                    perm: None,
+                });
+            } else {
+                q.events.push_back(ev);
+            }
+        }
+
+        self.queue.Notify(READABLE_EVENT)
+    }
+
+    // Handle queues an event on the group if any of its marks are interested in
+    // events on d. It returns the permission request to wait for when events
+    // is a permission event.
+    pub fn Handle(
+        &self,
+        d: &Dirent,
+        keys: &[FanMarkKey],
+        parent: Option<FanMarkKey>,
+        events: u64,
+        pid: i32,
+        tid: i32,
+    ) -> Option<Arc<FanPermRequest>> {
+        let mask = self.Match(keys, parent, events);
+        if mask == 0 {
+            return None;
+        }
+
+        let perm = if mask & Fanotify::FAN_ALL_PERM_EVENTS != 0 {
+            Some(Arc::new(FanPermRequest::default()))
+        } else {
+            None
+        };
+
+        self.QueueEvent(FanEvent {
+            mask: mask,
+            target: Some(d.clone()),
+            handle: FileHandle::New(d),
+            pid: pid,
+            tid: tid,
+            perm: perm.clone(),
+        });
+
+        return perm;
+    }
+
+    // DropInodeMarks removes the inode marks on the inode which has been deleted.
+    pub fn DropInodeMarks(&self, key: &FanMarkKey) {
+        self.marks.lock().remove(key);
+    }
+
+    // PopEvent dequeues the first event if it fits in size bytes.
+    fn PopEvent(&self, size: usize) -> Option<FanEvent> {
+        let mut q = self.events.lock();
+        let fits = match q.events.front() {
+            None => false,
+            Some(ev) => ev.Sizeof(self.ReportFid()) <= size,
+        };
+
+        if !fits {
+            return None;
+        }
+
+        let ev = q.events.pop_front();
+        if let Some(ev) = &ev {
+            if ev.mask & Fanotify::FAN_Q_OVERFLOW != 0 {
+                q.overflow = false;
+            }
+        }
+
+        return ev;
+    }
+
+    // EventFd opens the object of ev for the listener and installs it in the
+    // listener's fd table.
+    fn EventFd(&self, task: &Task, ev: &FanEvent) -> Result<i32> {
+        if self.ReportFid() {
+            return Ok(Fanotify::FAN_NOFD);
+        }
+
+        let d = match &ev.target {
+            None => return Ok(Fanotify::FAN_NOFD),
+            Some(d) => d.clone(),
+        };
+
+        // The accesses of the listener through the event fd don't generate
+        // events, otherwise it would deadlock on its own permission events.
+        let mut flags = FileFlags::FromFlags(self.eventFlags);
+        flags.NoNotify = true;
+        let file = d.Inode().GetFile(task, &d, &flags)?;
+        let fd = task.NewFDFrom(
+            0,
+            &file,
+            &FDFlags {
+                CloseOnExec: self.eventFlags & Flags::O_CLOEXEC as u32 != 0,
+            },
+        )?;
+
+        return Ok(fd);
+    }
+}
+
+impl FileOperations for FanotifyGroup {
+    fn as_any(&self) -> &Any {
+        return self;
+    }
+
+    fn FopsType(&self) -> FileOpsType {
+        return FileOpsType::FanotifyFileOperations;
+    }
+
+    fn Seekable(&self) -> bool {
+        return false;
+    }
+
+    fn Seek(&self, _task: &Task, _f: &File, _whence: i32, _current: i64, _offset: i64) -> Result<i64> {
+        return Err(Error::SysError(SysErr::ESPIPE))
+    }
+
+    fn ReadDir(
+        &self,
+        _task: &Task,
+        _f: &File,
+        _offset: i64,
+        _serializer: &mut DentrySerializer,
+    ) -> Result<i64> {
+        return Err(Error::SysError(SysErr::ENOTDIR))
+    }
+
+    fn ReadAt(
+        &self,
+        task: &Task,
+        _f: &File,
+        dsts: &mut [IoVec],
+        _offset: i64,
+        _blocking: bool,
+    ) -> Result<i64> {
+        let dsts = task.AdjustIOVecPermission(dsts, true, true)?;
+        let size = IoVec::NumBytes(&dsts);
+
+        if size < FANOTIFY_EVENT_METADATA_SIZE {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let size = if size >= MemoryDef::HUGE_PAGE_SIZE as usize {
+            MemoryDef::HUGE_PAGE_SIZE as usize
+        } else {
+            size
+        };
+        let mut buf = DataBuff::New(size);
+
+        let reportFid = self.ReportFid();
+        let reportTid = self.flags & Fanotify::FAN_REPORT_TID != 0;
+        let mut writelen = 0;
+        loop {
+            let event = match self.PopEvent(size - writelen) {
+                None => break,
+                Some(e) => e,
+            };
+
+            let fd = match self.EventFd(task, &event) {
+                Ok(fd) => fd,
+                Err(e) => {
+                    // The event is lost, don't keep its originator waiting.
+                    if let Some(req) = &event.perm {
+                        req.Respond(Fanotify::FAN_ALLOW);
+                    }
+
+                    if writelen > 0 {
+                        break;
+                    }
+                    return Err(e);
+                }
+            };
+
+            if let Some(req) = &event.perm {
+                self.pending.lock().insert(fd, req.clone());
+            }
+
+            let len = event.Encode(&mut buf.buf[writelen..], fd, reportFid, reportTid);
+            if len > FANOTIFY_EVENT_METADATA_SIZE {
+                // Keep the handle resolvable by open_by_handle_at(2) once the
+                // listener got it.
+                if let Some(d) = &event.target {
+                    let msrc = d.Inode().lock().MountSource.clone();
+                    msrc.lock().AddInode(d);
+                }
+            }
+
+            writelen += len;
+        }
+
+        if writelen == 0 {
+            if self.events.lock().events.len() == 0 {
+                return Err(Error::SysError(SysErr::EAGAIN))
+            }
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        task.CopyDataOutToIovs(&buf.buf[0..writelen], &dsts, false)?;
+        return Ok(writelen as i64);
+    }
+
+    fn WriteAt(
+        &self,
+        task: &Task,
+        _f: &File,
+        srcs: &[IoVec],
+        _offset: i64,
+        _blocking: bool,
+    ) -> Result<i64> {
+        if self.Class() == Fanotify::FAN_CLASS_NOTIF {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let size = IoVec::NumBytes(srcs);
+        if size < FANOTIFY_RESPONSE_SIZE {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let mut buf = DataBuff::New(FANOTIFY_RESPONSE_SIZE);
+        task.CopyDataInFromIovs(&mut buf.buf, srcs, true)?;
+        let response = unsafe { core::ptr::read_unaligned(&buf.buf[0] as *const _ as *const FanotifyResponse) };
+
+        let decision = response.Decision()?;
+
+        let req = match self.pending.lock().remove(&response.fd) {
+            None => return Err(Error::SysError(SysErr::ENOENT)),
+            Some(req) => req,
+        };
+
+        req.Respond(decision);
+        return Ok(FANOTIFY_RESPONSE_SIZE as i64);
+    }
+
+    fn Append(&self, task: &Task, f: &File, srcs: &[IoVec]) -> Result<(i64, i64)> {
+        let n = self.WriteAt(task, f, srcs, 0, false)?;
+        return Ok((n, 0));
+    }
+
+    fn Fsync(
+        &self,
+        _task: &Task,
+        _f: &File,
+        _start: i64,
+        _end: i64,
+        _syncType: SyncType,
+    ) -> Result<()> {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    fn Flush(&self, _task: &Task, _f: &File) -> Result<()> {
+        return Ok(());
+    }
+
+    fn UnstableAttr(&self, task: &Task, f: &File) -> Result<UnstableAttr> {
+        return f.Dirent.Inode().UnstableAttr(task)
+    }
+
+    fn Ioctl(&self, task: &Task, _f: &File, _fd: i32, request: u64, val: u64) -> Result<()> {
+        match request {
+            IoCtlCmd::FIONREAD => {
+                let reportFid = self.ReportFid();
+                let mut size: u32 = 0;
+                for event in self.events.lock().events.iter() {
+                    size += event.Sizeof(reportFid) as u32;
+                }
+
+                task.CopyOutObj(&size, val)?;
+                return Ok(())
+            }
+            _ => {
+                return Err(Error::SysError(SysErr::ENOTTY))
+            }
+        }
+    }
+
+    fn IterateDir(
+        &self,
+        _task: &Task,
+        _d: &Dirent,
+        _dirCtx: &mut DirCtx,
+        _offset: i32,
+    ) -> (i32, Result<i64>) {
+        return (0, Ok(0));
+    }
+
+    fn Mappable(&self) -> Result<MMappable> {
+        return Err(Error::SysError(SysErr::ENODEV))
+    }
+}
+
+impl Waitable for FanotifyGroup {
+    fn Readiness(&self, _task: &Task, mask: EventMask) -> EventMask {
+        let ready = if self.events.lock().events.len() > 0 {
+            READABLE_EVENT
+        } else {
+            0
+        };
+
+        return mask & ready;
+    }
+
+    fn EventRegister(&self, task: &Task, e: &WaitEntry, mask: EventMask) {
+        let queue = self.queue.clone();
+        queue.EventRegister(task, e, mask);
+    }
+
+    fn EventUnregister(&self, task: &Task, e: &WaitEntry) {
+        let queue = self.queue.clone();
+        queue.EventUnregister(task, e);
+    }
+}
+
+impl SockOperations for FanotifyGroup {}
+impl SpliceOperations for FanotifyGroup {}
+
+// FanotifyGroups is the set of live fanotify groups of the sandbox. fanotify
+// marks are global: a mount or filesystem mark sees the events of every task.
+#[derive(Default)]
+pub struct FanotifyGroups(QRwLock<BTreeMap<u64, Weak<FanotifyGroupIntern>>>);
+
+impl Deref for FanotifyGroups {
+    type Target = QRwLock<BTreeMap<u64, Weak<FanotifyGroupIntern>>>;
+
+    fn deref(&self) -> &QRwLock<BTreeMap<u64, Weak<FanotifyGroupIntern>>> {
+        &self.0
+    }
+}
+
+impl FanotifyGroups {
+    pub fn Add(&self, group: &FanotifyGroup) {
+        self.write().insert(group.id, group.Downgrade());
+    }
+
+    pub fn Remove(&self, id: u64) {
+        self.write().remove(&id);
+    }
+
+    fn Groups(&self) -> Vec<FanotifyGroup> {
+        let groups = self.read();
+        let mut ret = Vec::with_capacity(groups.len());
+        for (_, g) in groups.iter() {
+            if let Some(g) = g.upgrade() {
+                ret.push(FanotifyGroup(g));
+            }
+        }
+
+        return ret;
+    }
+
+    // Generate queues the events on the groups interested in them and returns
+    // the permission requests to wait for.
+    fn Generate(&self, task: &Task, d: &Dirent, events: u64) -> Vec<Arc<FanPermRequest>> {
+        let mut reqs = Vec::new();
+        if self.read().len() == 0 {
+            return reqs;
+        }
+
+        let (pid, tid) = match &task.thread {
+            None => (0, 0),
+            Some(t) => (t.ThreadGroup().ID(), t.ThreadID()),
+        };
+
+        // For the directory entry events, FAN_ONDIR is set by the caller when
+        // the entry is a directory.
+        let mut events = events;
+        if events & Fanotify::FAN_DIRENT_EVENTS == 0 && d.Inode().StableAttr().IsDir() {
+            events |= Fanotify::FAN_ONDIR;
+        }
+
+        let keys = [
+            FanMarkKey::InodeKey(d),
+            FanMarkKey::MountKey(d),
+            FanMarkKey::FilesystemKey(d),
+        ];
+
+        // Directory entry events are reported on the directory itself, the
+        // other events are also reported to the parent's marks with
+        // FAN_EVENT_ON_CHILD.
+        let parent = if events & Fanotify::FAN_DIRENT_EVENTS != 0 {
+            None
+        } else {
+            match d.Parent() {
+                None => None,
+                Some(p) => Some(FanMarkKey::InodeKey(&p)),
+            }
+        };
+
+        for g in self.Groups() {
+            if let Some(req) = g.Handle(d, &keys, parent, events, pid, tid) {
+                reqs.push(req);
+            }
+
+            if events & Fanotify::FAN_DELETE_SELF != 0 {
+                g.DropInodeMarks(&keys[0]);
+            }
+        }
+
+        return reqs;
+    }
+
+    // Notify generates the fanotify events for the inotify events which happened
+    // on d. The inotify and fanotify event bits are the same. For the directory
+    // entry events, i.e. IN_CREATE, IN_DELETE and IN_MOVED_*, d is the directory.
+    pub fn Notify(&self, d: &Dirent, events: u32) {
+        let events = events as u64 & (Fanotify::FAN_ALL_EVENTS | Fanotify::FAN_ONDIR);
+        if events & Fanotify::FAN_ALL_EVENTS == 0 {
+            return;
+        }
+
+        let task = Task::Current();
+        self.Generate(task, d, events);
+    }
+
+    // NotifyRename generates the fanotify events of the rename of renamed from
+    // oldParent to newParent.
+    pub fn NotifyRename(&self, renamed: &Dirent, oldParent: &Dirent, newParent: &Dirent) {
+        let mut dirEv = 0;
+        if renamed.Inode().StableAttr().IsDir() {
+            dirEv |= InotifyEvent::IN_ISDIR;
+        }
+
+        self.Notify(oldParent, dirEv | InotifyEvent::IN_MOVED_FROM);
+        self.Notify(newParent, dirEv | InotifyEvent::IN_MOVED_TO);
+        self.Notify(renamed, InotifyEvent::IN_MOVE_SELF);
+    }
+
+    // Permission generates the permission event on d and waits for the decision
+    // of all the groups interested in it.
+    pub fn Permission(&self, task: &Task, d: &Dirent, event: u64) -> Result<()> {
+        let reqs = self.Generate(task, d, event);
+        for req in reqs {
+            req.Wait(task)?;
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn Event(mask: u64, dev: u64, ino: u64) -> FanEvent {
+        return FanEvent {
+            mask: mask,
+            target: None,
+            handle: FileHandle {
+                handleType: FILEID_QUARK,
+                dev: dev,
+                ino: ino,
+                gen: 7,
+                ..Default::default()
+            },
+            pid: 10,
+            tid: 11,
+            perm: None,
+        };
+    }
+
+    #[test]
+    fn test_MarkKeyFromIds() {
+        let inode = FanMarkKey::FromIds(Fanotify::FAN_MARK_INODE, 1, 2, 3);
+        assert_eq!(inode, FanMarkKey::Inode(1, 2));
+        assert_eq!(inode.MarkType(), Fanotify::FAN_MARK_INODE);
+
+        let mount = FanMarkKey::FromIds(Fanotify::FAN_MARK_MOUNT, 1, 2, 3);
+        assert_eq!(mount, FanMarkKey::Mount(3));
+        assert_eq!(mount.MarkType(), Fanotify::FAN_MARK_MOUNT);
+
+        let fs = FanMarkKey::FromIds(Fanotify::FAN_MARK_FILESYSTEM, 1, 2, 3);
+        assert_eq!(fs, FanMarkKey::Filesystem(1));
+        assert_eq!(fs.MarkType(), Fanotify::FAN_MARK_FILESYSTEM);
+    }
+
+    #[test]
+    fn test_MarkMasksAddRemove() {
+        let mut m = FanMarkMasks::default();
+        m.Add(Fanotify::FAN_OPEN, 0);
+        m.Add(Fanotify::FAN_CLOSE_WRITE, 0);
+        m.Add(Fanotify::FAN_ACCESS, Fanotify::FAN_MARK_IGNORED_MASK);
+        assert_eq!(m.mask, Fanotify::FAN_OPEN | Fanotify::FAN_CLOSE_WRITE);
+        assert_eq!(m.ignoredMask, Fanotify::FAN_ACCESS);
+        assert!(!m.ignoredSurvModify);
+
+        m.Add(
+            Fanotify::FAN_ATTRIB,
+            Fanotify::FAN_MARK_IGNORED_MASK | Fanotify::FAN_MARK_IGNORED_SURV_MODIFY,
+        );
+        assert_eq!(m.ignoredMask, Fanotify::FAN_ACCESS | Fanotify::FAN_ATTRIB);
+        assert!(m.ignoredSurvModify);
+
+        assert!(!m.Remove(Fanotify::FAN_OPEN | Fanotify::FAN_CLOSE_WRITE, 0));
+        assert_eq!(m.mask, 0);
+        assert!(m.Remove(
+            Fanotify::FAN_ACCESS | Fanotify::FAN_ATTRIB,
+            Fanotify::FAN_MARK_IGNORED_MASK
+        ));
+    }
+
+    #[test]
+    fn test_MatchMasksIgnored() {
+        let mut m = FanMarkMasks::default();
+        m.Add(
+            Fanotify::FAN_OPEN | Fanotify::FAN_MODIFY | Fanotify::FAN_CLOSE_WRITE,
+            0,
+        );
+        m.Add(Fanotify::FAN_OPEN, Fanotify::FAN_MARK_IGNORED_MASK);
+
+        assert_eq!(MatchMasks(&mut [&mut m], None, Fanotify::FAN_OPEN), 0);
+        assert_eq!(MatchMasks(&mut [&mut m], None, Fanotify::FAN_ACCESS), 0);
+        assert_eq!(
+            MatchMasks(&mut [&mut m], None, Fanotify::FAN_CLOSE_WRITE),
+            Fanotify::FAN_CLOSE_WRITE
+        );
+
+        // FAN_MODIFY clears the ignored mask.
+        assert_eq!(
+            MatchMasks(&mut [&mut m], None, Fanotify::FAN_MODIFY),
+            Fanotify::FAN_MODIFY
+        );
+        assert_eq!(m.ignoredMask, 0);
+        assert_eq!(
+            MatchMasks(&mut [&mut m], None, Fanotify::FAN_OPEN),
+            Fanotify::FAN_OPEN
+        );
+
+        // unless the ignored mask survives modify.
+        m.Add(
+            Fanotify::FAN_OPEN,
+            Fanotify::FAN_MARK_IGNORED_MASK | Fanotify::FAN_MARK_IGNORED_SURV_MODIFY,
+        );
+        MatchMasks(&mut [&mut m], None, Fanotify::FAN_MODIFY);
+        assert_eq!(m.ignoredMask, Fanotify::FAN_OPEN);
+        assert_eq!(MatchMasks(&mut [&mut m], None, Fanotify::FAN_OPEN), 0);
+    }
+
+    #[test]
+    fn test_MatchMasksMerge() {
+        // The masks of the inode, mount and filesystem marks are merged, an
+        // ignored mask of any of them wins.
+        let mut inode = FanMarkMasks::default();
+        inode.Add(Fanotify::FAN_OPEN, 0);
+        let mut mount = FanMarkMasks::default();
+        mount.Add(Fanotify::FAN_CLOSE_WRITE | Fanotify::FAN_ACCESS, 0);
+        let mut fs = FanMarkMasks::default();
+        fs.Add(Fanotify::FAN_ACCESS, Fanotify::FAN_MARK_IGNORED_MASK);
+
+        let events = Fanotify::FAN_OPEN | Fanotify::FAN_CLOSE_WRITE | Fanotify::FAN_ACCESS;
+        assert_eq!(
+            MatchMasks(&mut [&mut inode, &mut mount, &mut fs], None, events),
+            Fanotify::FAN_OPEN | Fanotify::FAN_CLOSE_WRITE
+        );
+    }
+
+    #[test]
+    fn test_MatchMasksParentOndir() {
+        let mut child = FanMarkMasks::default();
+        let mut parent = FanMarkMasks::default();
+        parent.Add(Fanotify::FAN_OPEN, 0);
+
+        // The parent mark only sees the events of its children with
+        // FAN_EVENT_ON_CHILD.
+        assert_eq!(
+            MatchMasks(&mut [&mut child], Some(&parent), Fanotify::FAN_OPEN),
+            0
+        );
+        parent.Add(Fanotify::FAN_EVENT_ON_CHILD, 0);
+        assert_eq!(
+            MatchMasks(&mut [&mut child], Some(&parent), Fanotify::FAN_OPEN),
+            Fanotify::FAN_OPEN
+        );
+
+        // The events on directories need FAN_ONDIR and report it.
+        child.Add(Fanotify::FAN_CLOSE_NOWRITE, 0);
+        let events = Fanotify::FAN_CLOSE_NOWRITE | Fanotify::FAN_ONDIR;
+        assert_eq!(MatchMasks(&mut [&mut child], None, events), 0);
+        child.Add(Fanotify::FAN_ONDIR, 0);
+        assert_eq!(MatchMasks(&mut [&mut child], None, events), events);
+    }
+
+    #[test]
+    fn test_PermRequestDecision() {
+        let req = FanPermRequest::default();
+        assert!(req.Decision().is_none());
+
+        req.Respond(Fanotify::FAN_ALLOW);
+        assert_eq!(req.Decision(), Some(Ok(())));
+
+        let req = FanPermRequest::default();
+        req.Respond(Fanotify::FAN_DENY);
+        assert_eq!(req.Decision(), Some(Err(Error::SysError(SysErr::EPERM))));
+    }
+
+    #[test]
+    fn test_ResponseDecision() {
+        let response = |r: u32| FanotifyResponse { fd: 3, response: r }.Decision();
+
+        assert_eq!(response(Fanotify::FAN_ALLOW), Ok(Fanotify::FAN_ALLOW));
+        assert_eq!(response(Fanotify::FAN_DENY), Ok(Fanotify::FAN_DENY));
+        assert_eq!(
+            response(Fanotify::FAN_DENY | Fanotify::FAN_AUDIT),
+            Ok(Fanotify::FAN_DENY)
+        );
+        assert_eq!(response(0), Err(Error::SysError(SysErr::EINVAL)));
+        assert_eq!(
+            response(Fanotify::FAN_ALLOW | Fanotify::FAN_DENY),
+            Err(Error::SysError(SysErr::EINVAL))
+        );
+        assert_eq!(
+            response(Fanotify::FAN_AUDIT),
+            Err(Error::SysError(SysErr::EINVAL))
+        );
+    }
+
+    #[test]
+    fn test_EncodeMetadata() {
+        let ev = Event(Fanotify::FAN_OPEN, 0x801, 42);
+        let mut buf = [0u8; 64];
+        assert_eq!(
+            ev.Encode(&mut buf, 5, false, false),
+            FANOTIFY_EVENT_METADATA_SIZE
+        );
+
+        let metadata = unsafe {
+            core::ptr::read_unaligned(&buf[0] as *const _ as *const FanotifyEventMetadata)
+        };
+        assert_eq!(metadata.event_len, FANOTIFY_EVENT_METADATA_SIZE as u32);
+        assert_eq!(metadata.vers, Fanotify::FANOTIFY_METADATA_VERSION);
+        assert_eq!(metadata.metadata_len, FANOTIFY_EVENT_METADATA_SIZE as u16);
+        assert_eq!(metadata.mask, Fanotify::FAN_OPEN);
+        assert_eq!(metadata.fd, 5);
+        assert_eq!(metadata.pid, 10);
+
+        ev.Encode(&mut buf, 5, false, true);
+        let metadata = unsafe {
+            core::ptr::read_unaligned(&buf[0] as *const _ as *const FanotifyEventMetadata)
+        };
+        assert_eq!(metadata.pid, 11);
+    }
+
+    #[test]
+    fn test_EncodeFid() {
+        let ev = Event(
+            Fanotify::FAN_CREATE | Fanotify::FAN_ONDIR,
+            0x1_0000_0801,
+            42,
+        );
+        let infoSize = core::mem::size_of::<FanotifyEventInfoFid>();
+        let total = FANOTIFY_EVENT_METADATA_SIZE + infoSize + FILE_HANDLE_BASE_SIZE;
+        assert_eq!(infoSize, 20);
+        assert_eq!(ev.Sizeof(true), total);
+
+        let mut buf = [0u8; 128];
+        assert_eq!(ev.Encode(&mut buf, Fanotify::FAN_NOFD, true, false), total);
+
+        let metadata = unsafe {
+            core::ptr::read_unaligned(&buf[0] as *const _ as *const FanotifyEventMetadata)
+        };
+        assert_eq!(metadata.event_len, total as u32);
+        assert_eq!(metadata.metadata_len, FANOTIFY_EVENT_METADATA_SIZE as u16);
+        assert_eq!(metadata.fd, Fanotify::FAN_NOFD);
+
+        let info = unsafe {
+            core::ptr::read_unaligned(
+                &buf[FANOTIFY_EVENT_METADATA_SIZE] as *const _ as *const FanotifyEventInfoFid,
+            )
+        };
+        let (len, fsid, handleBytes, handleType) =
+            (info.len, info.fsid, info.handle_bytes, info.handle_type);
+        assert_eq!(info.info_type, Fanotify::FAN_EVENT_INFO_TYPE_FID);
+        assert_eq!(len as usize, infoSize + FILE_HANDLE_BASE_SIZE);
+        assert_eq!(fsid, [0x801, 1]);
+        assert_eq!(handleBytes as usize, FILE_HANDLE_BASE_SIZE);
+        assert_eq!(handleType, FILEID_QUARK);
+
+        let start = FANOTIFY_EVENT_METADATA_SIZE + infoSize;
+        let handle = FileHandle::Decode(handleType, &buf[start..total]).unwrap();
+        assert_eq!(handle.dev, 0x1_0000_0801);
+        assert_eq!(handle.ino, 42);
+        assert_eq!(handle.gen, 7);
+
+        // The overflow event has no FID record.
+        let overflow = Event(Fanotify::FAN_Q_OVERFLOW, 0, 0);
+        assert_eq!(
+            overflow.Encode(&mut buf, Fanotify::FAN_NOFD, true, false),
+            FANOTIFY_EVENT_METADATA_SIZE
+        );
+    }
+
+    #[test]
+    fn test_Mergeable() {
+        let last = Event(Fanotify::FAN_OPEN, 1, 2);
+        assert!(last.Mergeable(&Event(Fanotify::FAN_CLOSE_WRITE, 1, 2)));
+        assert!(!last.Mergeable(&Event(Fanotify::FAN_CLOSE_WRITE, 1, 3)));
+
+        let mut other = Event(Fanotify::FAN_CLOSE_WRITE, 1, 2);
+        other.tid = 12;
+        assert!(!last.Mergeable(&other));
+
+        let mut perm = Event(Fanotify::FAN_ACCESS_PERM, 1, 2);
+        perm.perm = Some(Arc::new(FanPermRequest::default()));
+        assert!(!last.Mergeable(&perm));
+        assert!(!perm.Mergeable(&Event(Fanotify::FAN_ACCESS, 1, 2)));
+
+        assert!(!Event(Fanotify::FAN_Q_OVERFLOW, 1, 2).Mergeable(&last));
+    }
+}
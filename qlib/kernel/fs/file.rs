@@ -46,7 +46,10 @@ use super::super::tcpip::tcpip::*;
 use crate::qlib::kernel::Kernel::HostSpace;
 
 use crate::qlib::kernel::fs::file_overlay::OverlayFileOperations;
+use crate::qlib::kernel::fs::inotify::EventType;
 use crate::qlib::kernel::fs::inotify::Inotify;
+use crate::qlib::kernel::fs::fanotify::FanotifyGroup;
+use crate::qlib::kernel::fs::fanotify::FANOTIFY;
 use crate::qlib::kernel::fs::timerfd::TimerOperations;
 use crate::qlib::kernel::fs::dev::full::FullFileOperations;
 use crate::qlib::kernel::fs::dev::null::NullFileOperations;
@@ -78,6 +81,8 @@ use crate::qlib::kernel::socket::unix::unix::UnixSocketOperations;
 use crate::qlib::kernel::socket::hostinet::uring_socket::UringSocketOperations;
 use crate::qlib::kernel::socket::hostinet::socket::SocketOperations;
 use crate::qlib::kernel::socket::hostinet::hostsocket::HostSocketOperations;
+use crate::qlib::kernel::socket::netlink::socket::NetlinkSocketOperations;
+use crate::qlib::kernel::socket::netstack::socket::NetstackSocketOperations;
 use crate::qlib::kernel::socket::hostinet::asyncsocket::AsyncSocketOperations;
 
 
@@ -210,6 +215,30 @@ pub trait SockOperations: Sync + Send {
         return Err(Error::SysError(SysErr::ENOTSOCK));
     }
 
+    // RecvMMsg receives a batch of messages with one host call, it returns
+    // the number of messages. None means that the socket doesn't batch the
+    // next message, which is then received with RecvMsg.
+    fn RecvMMsg(
+        &self,
+        _task: &Task,
+        _msgs: &mut [MMsgHdr],
+        _flags: i32,
+        _deadline: Option<Time>,
+    ) -> Option<Result<i64>> {
+        return None;
+    }
+
+    // SendMMsg is the batched SendMsg, see RecvMMsg.
+    fn SendMMsg(
+        &self,
+        _task: &Task,
+        _msgs: &mut [MMsgHdr],
+        _flags: i32,
+        _deadline: Option<Time>,
+    ) -> Option<Result<i64>> {
+        return None;
+    }
+
     fn SetRecvTimeout(&self, _nanoseconds: i64) {
         return;
     }
@@ -234,6 +263,12 @@ pub trait SockOperations: Sync + Send {
         return 0
     }
 
+    // QueueSize returns the bytes in the transmit and the receive queue of the
+    // socket, as represented by Linux in procfs.
+    fn QueueSize(&self) -> (usize, usize) {
+        return (0, 0)
+    }
+
     fn Type(&self) -> (i32, i32, i32) {
         return (-1, -1, -1)
     }
@@ -302,6 +337,7 @@ pub enum FileOpsType {
     DynamicDirFileOperations,
     SignalOperation,
     InotifyFileOperations,
+    FanotifyFileOperations,
     ProxyFileOperations
 }
 
@@ -310,6 +346,7 @@ pub enum FileOpsType {
 pub enum FileOps {
     OverlayFileOperations(OverlayFileOperations),
     Inotify(Inotify),
+    FanotifyGroup(FanotifyGroup),
     //MockFileOperations(MockFileOperations),
     TimerOperations(TimerOperations),
     FullFileOperations(FullFileOperations),
@@ -341,6 +378,8 @@ pub enum FileOps {
     Writer(Writer),
     AsyncSocketOperations(AsyncSocketOperations),
     HostSocketOperations(HostSocketOperations),
+    NetstackSocketOperations(NetstackSocketOperations),
+    NetlinkSocketOperations(NetlinkSocketOperations),
     SocketOperations(SocketOperations),
     UringSocketOperations(UringSocketOperations),
     UnixSocketOperations(UnixSocketOperations),
@@ -787,6 +826,26 @@ impl File {
         return self.flags.lock().0;
     }
 
+    // InotifyEvent generates the inotify/fanotify events of an access through
+    // the file, unless it is a fanotify event fd.
+    pub fn InotifyEvent(&self, event: u32, cookie: u32, et: EventType) {
+        if self.Flags().NoNotify {
+            return;
+        }
+
+        self.Dirent.InotifyEvent(event, cookie, et);
+    }
+
+    // FanotifyPermission waits for the fanotify listeners' decision on the
+    // access through the file, unless it is a fanotify event fd.
+    pub fn FanotifyPermission(&self, task: &Task, event: u64) -> Result<()> {
+        if self.Flags().NoNotify {
+            return Ok(());
+        }
+
+        return FANOTIFY.Permission(task, &self.Dirent, event);
+    }
+
     pub fn SetFlags(&self, task: &Task, newFlags: SettableFileFlags) {
         let mut f = self.flags.lock();
         f.0.Direct = newFlags.Direct;
@@ -0,0 +1,258 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+use super::super::super::common::*;
+use super::super::super::linux_def::*;
+use super::super::super::qmsg::qcall::*;
+use super::super::task::*;
+use super::super::Kernel::HostSpace;
+use super::dirent::*;
+use super::host::hostinotify::*;
+use super::inode::*;
+
+// FILEID_QUARK is the type of the file handles of the sandbox inodes. The
+// handle is the device id (u64), the inode id (u64) and the generation (u32)
+// of the inode.
+pub const FILEID_QUARK: i32 = 0x51;
+
+// FILEID_QUARK_HOST is the type of the file handles of the host-backed inodes.
+// The FILEID_QUARK handle is followed by the id (u64) of the host handle kept
+// by qvisor, so that the file can be opened again after its dirent has been
+// evicted from the dentry cache. The host handle itself never reaches the guest.
+pub const FILEID_QUARK_HOST: i32 = 0x52;
+
+pub const FILE_HANDLE_BASE_SIZE: usize = 20;
+pub const FILE_HANDLE_HOST_SIZE: usize = FILE_HANDLE_BASE_SIZE + 8;
+
+// FILE_HANDLE_HEADER_SIZE is the size of the handle_bytes and handle_type
+// fields of linux's struct file_handle.
+pub const FILE_HANDLE_HEADER_SIZE: usize = 8;
+
+#[derive(Debug, Default, Clone)]
+pub struct FileHandle {
+    pub handleType: i32,
+    pub dev: u64,
+    pub ino: u64,
+    pub gen: u32,
+
+    // id of the host handle for FILEID_QUARK_HOST
+    pub hostId: u64,
+}
+
+impl FileHandle {
+    // New returns the FILEID_QUARK handle of the inode of d. The generation is
+    // taken from the unique id of the inode so that a handle doesn't resolve to
+    // another inode reusing the inode number.
+    pub fn New(d: &Dirent) -> Self {
+        let inode = d.Inode();
+        let attr = inode.StableAttr();
+        return Self {
+            handleType: FILEID_QUARK,
+            dev: attr.DeviceId,
+            ino: attr.InodeId,
+            gen: inode.ID() as u32,
+            ..Default::default()
+        };
+    }
+
+    // NewWithHost returns the handle of the inode of d, with the host handle
+    // if the inode is host-backed and the host filesystem supports handles.
+    pub fn NewWithHost(d: &Dirent) -> Self {
+        let mut handle = Self::New(d);
+        let hostfd = match HostInotify::HostFd(d) {
+            None => return handle,
+            Some(fd) => fd,
+        };
+
+        // Fall back to the inode table if the host filesystem doesn't support
+        // handles.
+        let id = HostSpace::HostNameToHandleAt(hostfd);
+        if id < 0 {
+            return handle;
+        }
+
+        handle.handleType = FILEID_QUARK_HOST;
+        handle.hostId = id as u64;
+        return handle;
+    }
+
+    pub fn Sizeof(&self) -> usize {
+        if self.handleType == FILEID_QUARK_HOST {
+            return FILE_HANDLE_HOST_SIZE;
+        }
+
+        return FILE_HANDLE_BASE_SIZE;
+    }
+
+    // Encode returns the f_handle bytes of the handle.
+    pub fn Encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.Sizeof());
+        buf.extend_from_slice(&self.dev.to_ne_bytes());
+        buf.extend_from_slice(&self.ino.to_ne_bytes());
+        buf.extend_from_slice(&self.gen.to_ne_bytes());
+        if self.handleType == FILEID_QUARK_HOST {
+            buf.extend_from_slice(&self.hostId.to_ne_bytes());
+        }
+
+        return buf;
+    }
+
+    pub fn Decode(handleType: i32, buf: &[u8]) -> Result<Self> {
+        let size = match handleType {
+            FILEID_QUARK => FILE_HANDLE_BASE_SIZE,
+            FILEID_QUARK_HOST => FILE_HANDLE_HOST_SIZE,
+            _ => return Err(Error::SysError(SysErr::EINVAL)),
+        };
+
+        if buf.len() != size {
+            return Err(Error::SysError(SysErr::EINVAL));
+        }
+
+        let mut handle = Self {
+            handleType: handleType,
+            dev: u64::from_ne_bytes(buf[0..8].try_into().unwrap()),
+            ino: u64::from_ne_bytes(buf[8..16].try_into().unwrap()),
+            gen: u32::from_ne_bytes(buf[16..20].try_into().unwrap()),
+            ..Default::default()
+        };
+
+        if handleType == FILEID_QUARK_HOST {
+            handle.hostId = u64::from_ne_bytes(buf[20..28].try_into().unwrap());
+        }
+
+        return Ok(handle);
+    }
+
+    pub fn Matches(&self, d: &Dirent) -> bool {
+        let inode = d.Inode();
+        let attr = inode.StableAttr();
+        if attr.DeviceId != self.dev || attr.InodeId != self.ino {
+            return false;
+        }
+
+        // A host-backed inode gets a new unique id when it is looked up again,
+        // Resolve goes to the host handle then.
+        return inode.ID() as u32 == self.gen;
+    }
+
+    // Resolve returns the dirent of the handle in the mount of mountRoot: from
+    // the inode table of the mount if the dirent is still alive, from the host
+    // handle otherwise.
+    pub fn Resolve(&self, task: &Task, mountRoot: &Dirent) -> Result<Dirent> {
+        let msrc = mountRoot.Inode().lock().MountSource.clone();
+        let d = msrc.lock().LookupInode(self.ino);
+        if let Some(d) = d {
+            if self.Matches(&d) {
+                return Ok(d);
+            }
+        }
+
+        if self.handleType != FILEID_QUARK_HOST {
+            return Err(Error::SysError(SysErr::ESTALE));
+        }
+
+        let mountfd = match HostInotify::HostFd(mountRoot) {
+            None => return Err(Error::SysError(SysErr::ESTALE)),
+            Some(fd) => fd,
+        };
+
+        let readonly = msrc.lock().Flags.ReadOnly;
+        let fstat = LibcStat::default();
+        let mut tryopen = TryOpenStruct {
+            fstat: &fstat,
+            writeable: false,
+        };
+
+        let fd = HostSpace::HostOpenByHandleAt(
+            mountfd,
+            self.hostId,
+            readonly,
+            &mut tryopen as *mut TryOpenStruct as u64,
+        );
+        if fd < 0 {
+            return Err(Error::SysError(-fd as i32));
+        }
+
+        // qvisor has checked the file against the handle, check it against
+        // the guest handle as well.
+        if fstat.st_dev != self.dev || fstat.st_ino != self.ino {
+            HostSpace::Close(fd as i32);
+            return Err(Error::SysError(SysErr::ESTALE));
+        }
+
+        // The dirent is disconnected: the name and parent of the file are
+        // unknown, as for the dentries linux creates for open_by_handle_at(2).
+        let inode = Inode::NewHostInode(task, &msrc, fd as i32, &fstat, tryopen.writeable, false)?;
+        let d = Dirent::New(&inode, "");
+        msrc.lock().AddInode(&d);
+        return Ok(d);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_EncodeDecode() {
+        let handle = FileHandle {
+            handleType: FILEID_QUARK,
+            dev: 0x801,
+            ino: 42,
+            gen: 7,
+            ..Default::default()
+        };
+
+        let buf = handle.Encode();
+        assert_eq!(buf.len(), FILE_HANDLE_BASE_SIZE);
+        let decoded = FileHandle::Decode(FILEID_QUARK, &buf).unwrap();
+        assert_eq!(decoded.dev, 0x801);
+        assert_eq!(decoded.ino, 42);
+        assert_eq!(decoded.gen, 7);
+    }
+
+    #[test]
+    fn test_EncodeDecodeHost() {
+        let handle = FileHandle {
+            handleType: FILEID_QUARK_HOST,
+            dev: 0x801,
+            ino: 42,
+            gen: 7,
+            hostId: 3,
+        };
+
+        let buf = handle.Encode();
+        assert_eq!(buf.len(), handle.Sizeof());
+        let decoded = FileHandle::Decode(FILEID_QUARK_HOST, &buf).unwrap();
+        assert_eq!(decoded.hostId, 3);
+        assert_eq!(decoded.ino, 42);
+    }
+
+    #[test]
+    fn test_DecodeRejects() {
+        let buf = [0u8; FILE_HANDLE_HOST_SIZE];
+        // unknown type
+        assert!(FileHandle::Decode(1, &buf).is_err());
+        // size doesn't match the type
+        assert!(FileHandle::Decode(FILEID_QUARK, &buf).is_err());
+        assert!(FileHandle::Decode(FILEID_QUARK_HOST, &buf[..FILE_HANDLE_BASE_SIZE]).is_err());
+
+        // raw host handles are not accepted
+        let buf = [0u8; FILE_HANDLE_HOST_SIZE + 12];
+        assert!(FileHandle::Decode(FILEID_QUARK_HOST, &buf).is_err());
+    }
+}
@@ -32,6 +32,11 @@ pub struct FileFlags {
     pub Truncate: bool,
     pub NoFollow: bool,
     pub Path: bool,
+
+    // NoNotify is set on the files opened for the fanotify listeners, the
+    // accesses through them don't generate inotify/fanotify events, as
+    // FMODE_NONOTIFY in linux.
+    pub NoNotify: bool,
 }
 
 impl FileFlags {
@@ -29,14 +29,11 @@ use super::super::super::host::hostinodeop::*;
 use super::*;
 
 use crate::qlib::kernel::fs::procfs::meminfo::MeminfoFileNode;
-use crate::qlib::kernel::fs::procfs::net::NetTCPReadonlyFileNode;
 use crate::qlib::kernel::fs::procfs::task::auxvec::AUXVecReadonlyFileNode;
 use crate::qlib::kernel::fs::procfs::task::comm::CommReadonlyFileNode;
 use crate::qlib::kernel::fs::procfs::task::exec_args::ExecArgReadonlyFileNode;
 use crate::qlib::kernel::fs::procfs::uptime::UptimeFileNode;
 use crate::qlib::kernel::fs::procfs::task::uid_pid_map::IdMapReadonlyFileNode;
-use crate::qlib::kernel::fs::procfs::net::NetUnixReadonlyFileNode;
-use crate::qlib::kernel::fs::procfs::net::NetUDPReadonlyFileNode;
 
 pub fn NewSnapshotReadonlyFileOperations(
     data: Vec<u8>,
@@ -81,9 +78,6 @@ impl ReadonlyFileNodeTrait for SnapshotReadonlyFileNode {
 pub enum ReadonlyFileNode {
     SnapshotReadonlyFileNode(SnapshotReadonlyFileNode),
     MeminfoFileNode(MeminfoFileNode),
-    NetTCPReadonlyFileNode(NetTCPReadonlyFileNode),
-    NetUDPReadonlyFileNode(NetUDPReadonlyFileNode),
-    NetUnixReadonlyFileNode(NetUnixReadonlyFileNode),
     AUXVecReadonlyFileNode(AUXVecReadonlyFileNode),
     CommReadonlyFileNode(CommReadonlyFileNode),
     ExecArgReadonlyFileNode(ExecArgReadonlyFileNode),
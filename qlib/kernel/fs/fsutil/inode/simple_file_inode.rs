@@ -38,9 +38,7 @@ use crate::qlib::kernel::fs::procfs::task::stat::TaskStatData;
 use crate::qlib::kernel::fs::procfs::filesystems::FileSystemData;
 use crate::qlib::kernel::fs::procfs::loadavg::LoadAvgData;
 use crate::qlib::kernel::fs::procfs::meminfo::MeminfoInode;
-use crate::qlib::kernel::fs::procfs::net::NetTCP;
-use crate::qlib::kernel::fs::procfs::net::NetUDP;
-use crate::qlib::kernel::fs::procfs::net::NetUnix;
+use crate::qlib::kernel::fs::procfs::net::NetData;
 use crate::qlib::kernel::fs::procfs::uptime::UptimeInode;
 use crate::qlib::kernel::fs::procfs::task::auxvec::AUXVecSimpleFileTrait;
 use crate::qlib::kernel::fs::procfs::task::comm::CommSimpleFileTrait;
@@ -75,9 +73,7 @@ pub enum SimpleFileImpl {
     FileSystemData(FileSystemData),
     LoadAvgData(LoadAvgData),
     MeminfoInode(MeminfoInode),
-    NetTCP(NetTCP),
-    NetUDP(NetUDP),
-    NetUnix(NetUnix),
+    NetData(NetData),
     TaskStatData(TaskStatData),
     UptimeInode(UptimeInode),
     AUXVecSimpleFileTrait(AUXVecSimpleFileTrait),
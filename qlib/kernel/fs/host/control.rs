@@ -14,84 +14,202 @@
 
 use alloc::vec::Vec;
 
+use super::super::super::super::common::*;
+use super::super::super::super::linux::socket::SOL_SOCKET;
 use super::super::super::super::linux_def::*;
-use super::super::file::*;
-use super::super::flags::*;
-use super::super::super::task::*;
 use super::super::super::socket::control::*;
+use super::super::super::task::*;
 use super::super::super::Kernel::HostSpace;
+use super::super::file::*;
+use super::super::flags::*;
+use super::util::*;
+
+// FilesToHostFds returns the host fds of the files sent to a host unix
+// socket. Only the files backed by a host fd can leave the sandbox, and the
+// host fd is shared by the opens of the inode: it is only sent when it
+// doesn't give the peer more than the guest file.
+pub fn FilesToHostFds(files: &[File]) -> Result<Vec<i32>> {
+    let mut fds = Vec::with_capacity(files.len());
+    for f in files {
+        let inode = f.Dirent.Inode();
+        let iops = inode.lock().InodeOp.clone();
+        let fd = match iops.HostInodeOp() {
+            None => return Err(Error::SysError(SysErr::EINVAL)),
+            Some(hostiops) => hostiops.HostFd(),
+        };
 
-pub struct HostSCMRights (Vec<i32>);
+        let hostFlags = HostSpace::Fcntl(fd, Cmd::F_GETFL, 0);
+        if hostFlags < 0 {
+            return Err(Error::SysError(-hostFlags as i32));
+        }
 
-impl Drop for HostSCMRights {
-    fn drop(&mut self) {
-        for fd in &self.0 {
-            HostSpace::AsyncClose(*fd)
+        if !HostFlagsWithin(hostFlags as i32, &f.Flags()) {
+            return Err(Error::SysError(SysErr::EPERM));
         }
+
+        fds.push(fd);
     }
-}
 
-impl SCMRights for HostSCMRights {
-    fn Files(&mut self, task: &Task, max: usize) -> (SCMRights, bool) {
-        let mut n = max;
-        let mut trunc = false;
-
-        let l = self.0.len();
-        if n > l {
-            n = l;
-        } else if n < l {
-            n = l;
-            trunc = true;
-        }
+    return Ok(fds);
+}
 
-        let rf = SCMRights(FdsToFiles(task, &self.0[..n]));
-        self.0 = self.0.split_off(n);
+// HostFlagsWithin returns whether the host fd flags grant no access over the
+// guest file flags.
+fn HostFlagsWithin(hostFlags: i32, flags: &FileFlags) -> bool {
+    let (read, write) = match hostFlags & Flags::O_ACCMODE {
+        Flags::O_RDONLY => (true, false),
+        Flags::O_WRONLY => (false, true),
+        _ => (true, true),
+    };
+
+    if (read && !flags.Read) || (write && !flags.Write) {
+        return false;
+    }
 
-        return (rf, trunc)
+    // a guest append only file must not be overwritten
+    if write && flags.Append && hostFlags & Flags::O_APPEND == 0 {
+        return false;
     }
+
+    return true;
 }
 
-impl HostSCMRights {
-    pub fn New(fds: Vec<i32>) -> Self {
-        return Self(fds);
+// BuildRights returns the SCM_RIGHTS control message carrying fds.
+pub fn BuildRights(fds: &[i32]) -> Vec<u8> {
+    let dataLen = fds.len() * SIZE_OF_CONTROL_MESSAGE_RIGHT;
+    let mut buf = vec![0u8; CMsgSpace(dataLen)];
+    let header = ControlMessageHeader {
+        Length: (SIZE_OF_CONTROL_MESSAGE_HEADER + dataLen) as u64,
+        Level: SOL_SOCKET,
+        Type: SCM_RIGHTS,
+    };
+
+    unsafe {
+        *(&mut buf[0] as *mut _ as *mut ControlMessageHeader) = header;
     }
 
-    // Clone implements transport.RightsControlMessage.Clone.
-    pub fn Clone(&self) -> Option<SCMRights> {
-        // Host rights never need to be cloned.
-        return None;
+    let start = CMsgAlign(SIZE_OF_CONTROL_MESSAGE_HEADER);
+    for (i, fd) in fds.iter().enumerate() {
+        let off = start + i * SIZE_OF_CONTROL_MESSAGE_RIGHT;
+        buf[off..off + SIZE_OF_CONTROL_MESSAGE_RIGHT].copy_from_slice(&fd.to_ne_bytes());
     }
+
+    return buf;
 }
 
-// If an error is encountered, only files created before the error will be
-// returned. This is what Linux does.
-fn FdsToFiles(task: &Task, fds: &[i32]) -> Vec<File> {
-    let mut files = Vec::new();
+// ParseRights returns the fds of the SCM_RIGHTS messages received from a host
+// unix socket.
+pub fn ParseRights(buf: &[u8]) -> Vec<i32> {
+    let mut fds = Vec::new();
+    let mut buf = buf;
+    while buf.len() >= SIZE_OF_CONTROL_MESSAGE_HEADER {
+        let header = unsafe { *(&buf[0] as *const _ as *const ControlMessageHeader) };
+        let len = header.Length as usize;
+        if len < SIZE_OF_CONTROL_MESSAGE_HEADER || len > buf.len() {
+            break;
+        }
 
-    for fd in fds {
-        let mut fileFlags : i32 = 0;
-        let ret = HostSpace::Fcntl(*fd, Cmd::F_GETFL, &mut fileFlags as * mut _ as u64);
-        if ret < 0 {
-            info!("Error retrieving host FD flags: {}", -ret);
+        if header.Level == SOL_SOCKET && header.Type == SCM_RIGHTS {
+            let start = CMsgAlign(SIZE_OF_CONTROL_MESSAGE_HEADER);
+            let mut off = start;
+            while off + SIZE_OF_CONTROL_MESSAGE_RIGHT <= len {
+                let mut b = [0u8; 4];
+                b.copy_from_slice(&buf[off..off + SIZE_OF_CONTROL_MESSAGE_RIGHT]);
+                fds.push(i32::from_ne_bytes(b));
+                off += SIZE_OF_CONTROL_MESSAGE_RIGHT;
+            }
+        }
+
+        let next = CMsgAlign(len);
+        if next >= buf.len() {
             break;
         }
+        buf = &buf[next..];
+    }
+
+    return fds;
+}
+
+// HostFdsToFiles creates the files of the fds received from a host unix
+// socket. The fds after the first one which can't be imported are closed,
+// this is what Linux does when it fails to install the fds.
+pub fn HostFdsToFiles(task: &Task, fds: &[i32]) -> Vec<File> {
+    let mut files = Vec::new();
 
-        let file = match File::NewFileFromFd(task, *fd, &task.FileOwner(), false, false) {
+    for i in 0..fds.len() {
+        match HostFdToFile(task, fds[i]) {
+            Ok(file) => files.push(file),
             Err(e) => {
                 info!("Error creating file from host FD: {:?}", e);
+                for fd in &fds[i..] {
+                    HostSpace::Close(*fd);
+                }
                 break;
             }
-            Ok(f) => f,
-        };
+        }
+    }
 
-        // Set known flags.
-        file.SetFlags(task, SettableFileFlags {
-            NonBlocking: fileFlags & Flags::O_NONBLOCK != Flags::O_NONBLOCK,
-            ..Default::default()
-        });
+    return files;
+}
 
-        files.push(file);
+fn HostFdToFile(task: &Task, fd: i32) -> Result<File> {
+    let mut fstat = LibcStat::default();
+    let ret = Fstat(fd, &mut fstat) as i32;
+    if ret < 0 {
+        return Err(Error::SysError(-ret as i32));
     }
 
-    return files;
-}
\ No newline at end of file
+    // the host sockets are not imported as sandbox files
+    if fstat.st_mode as u16 & ModeType::S_IFMT == ModeType::S_IFSOCK {
+        return Err(Error::SysError(SysErr::EOPNOTSUPP));
+    }
+
+    let mut fileFlags: i32 = 0;
+    let ret = HostSpace::Fcntl(fd, Cmd::F_GETFL, &mut fileFlags as *mut _ as u64);
+    if ret < 0 {
+        return Err(Error::SysError(-ret as i32));
+    }
+
+    let file = File::NewFileFromFd(task, fd, &task.FileOwner(), false, false)?;
+
+    // Set known flags.
+    file.SetFlags(
+        task,
+        SettableFileFlags {
+            NonBlocking: fileFlags & Flags::O_NONBLOCK != 0,
+            ..Default::default()
+        },
+    );
+
+    return Ok(file);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_HostFlagsWithin() {
+        let ro = FileFlags {
+            Read: true,
+            ..Default::default()
+        };
+        let rw = FileFlags {
+            Read: true,
+            Write: true,
+            ..Default::default()
+        };
+        let append = FileFlags {
+            Write: true,
+            Append: true,
+            ..Default::default()
+        };
+
+        assert!(HostFlagsWithin(Flags::O_RDONLY, &ro));
+        assert!(!HostFlagsWithin(Flags::O_RDWR, &ro));
+        assert!(HostFlagsWithin(Flags::O_RDWR, &rw));
+        assert!(HostFlagsWithin(Flags::O_RDONLY, &rw));
+        assert!(!HostFlagsWithin(Flags::O_WRONLY, &append));
+        assert!(HostFlagsWithin(Flags::O_WRONLY | Flags::O_APPEND, &append));
+    }
+}
@@ -37,10 +37,12 @@ use super::super::super::SHARESPACE;
 use super::super::attr::*;
 use super::super::dirent::*;
 use super::super::dentry::*;
+use super::super::dirent_cache::*;
 use super::super::file::*;
 use super::super::flags::*;
 use super::super::inode::*;
 use super::hostdirfops::*;
+use super::quota::*;
 use super::util::*;
 use super::*;
 
@@ -55,6 +57,10 @@ pub struct HostDirOpIntern {
 
     pub overrides: BTreeMap<String, Inode>,
     pub readdirCache: Option<DentMap>,
+
+    // quota is the set of disk quotas the files created in the directory
+    // are charged to.
+    pub quota: HostQuota,
 }
 
 impl Default for HostDirOpIntern {
@@ -66,6 +72,7 @@ impl Default for HostDirOpIntern {
             errorcode: 0,
             overrides: BTreeMap::new(),
             readdirCache: None,
+            quota: HostQuota::default(),
         };
     }
 }
@@ -94,6 +101,7 @@ impl HostDirOpIntern {
             errorcode: 0,
             readdirCache: None,
             overrides: BTreeMap::new(),
+            quota: HostQuota::default(),
         };
     }
 
@@ -277,6 +285,25 @@ impl HostDirOp {
         return HostDirOpWeak(Arc::downgrade(&self.0));
     }
 
+    pub fn IterateDir(
+        &self,
+        task: &Task,
+        dirCtx: &mut DirCtx,
+        offset: i32,
+    ) -> (i32, Result<i64>) {
+        let (offset, res, cached) = {
+            let mut intern = self.lock();
+            let (offset, res) = intern.IterateDir(task, dirCtx, offset);
+            (offset, res, intern.readdirCache.is_some())
+        };
+
+        if cached {
+            FS_CACHE.CacheReaddir(self);
+        }
+
+        return (offset, res);
+    }
+
     pub fn HostFd(&self) -> i32 {
         return self.lock().HostFd;
     }
@@ -326,6 +353,36 @@ impl HostDirOp {
         let ret = self.lock().HostFd;
         return ret;
     }
+
+    pub fn Quota(&self) -> HostQuota {
+        return self.lock().quota.clone();
+    }
+
+    // Unlink removes the name from the directory and releases the quota
+    // charged for the file if it was its last link.
+    fn Unlink(&self, name: &str, flags: i32) -> Result<()> {
+        let quota = self.Quota();
+        let mut fstat = LibcStat::default();
+        let release = quota.Enabled()
+            && Fstatat(self.HostFd(), name, &mut fstat, ATType::AT_SYMLINK_NOFOLLOW) == 0
+            && (fstat.st_nlink == 1 || fstat.InodeType() == InodeType::Directory);
+
+        let ret = UnLinkAt(self.HostFd(), name, flags);
+
+        if ret < 0 {
+            return Err(Error::SysError(-ret as i32));
+        }
+
+        if release {
+            if fstat.InodeType() == InodeType::RegularFile {
+                quota.ReleaseBytes(fstat.st_size);
+            }
+            quota.ReleaseInode();
+        }
+
+        self.lock().readdirCache = None;
+        return Ok(());
+    }
 }
 
 impl InodeOperations for HostDirOp {
@@ -365,6 +422,7 @@ impl InodeOperations for HostDirOp {
 
         let ms = parent.lock().MountSource.clone();
         let inode = Inode::NewHostInode(task, &ms, fd, &fstat, writeable, false)?;
+        self.Quota().Child(&fstat.StableAttr()).Attach(&inode);
 
         let ret = Ok(Dirent::New(&inode, name));
         return ret;
@@ -388,20 +446,30 @@ impl InodeOperations for HostDirOp {
         newFlags.Read = true;
         newFlags.Write = true;
 
-        let (fd, fstat) = createAt(
+        let quota = self.Quota();
+        quota.ChargeInode()?;
+
+        let (fd, fstat) = match createAt(
             self.HostFd(),
             name,
             newFlags.ToLinux() | LibcConst::O_CREAT as i32,
             perm.LinuxMode(),
             owner.UID.0,
             owner.GID.0,
-        )?;
+        ) {
+            Err(e) => {
+                quota.ReleaseInode();
+                return Err(e);
+            }
+            Ok(r) => r,
+        };
 
         self.lock().readdirCache = None;
 
         let mountSource = dir.lock().MountSource.clone();
 
         let inode = Inode::NewHostInode(task, &mountSource, fd, &fstat, true, false)?;
+        quota.Attach(&inode);
         let dirent = Dirent::New(&inode, name);
 
         let file = inode.GetFile(task, &dirent, flags)?;
@@ -417,6 +485,9 @@ impl InodeOperations for HostDirOp {
     ) -> Result<()> {
         let owner = task.FileOwner();
 
+        let quota = self.Quota();
+        quota.ChargeInode()?;
+
         let ret = Mkdirat(
             self.HostFd(),
             name,
@@ -425,6 +496,7 @@ impl InodeOperations for HostDirOp {
             owner.GID.0,
         );
         if ret < 0 {
+            quota.ReleaseInode();
             return Err(Error::SysError(-ret as i32));
         }
 
@@ -440,9 +512,13 @@ impl InodeOperations for HostDirOp {
         oldname: &str,
         newname: &str,
     ) -> Result<()> {
+        let quota = self.Quota();
+        quota.ChargeInode()?;
+
         let ret = SymLinkAt(oldname, self.HostFd(), newname);
 
         if ret < 0 {
+            quota.ReleaseInode();
             return Err(Error::SysError(-ret as i32));
         }
 
@@ -485,6 +561,9 @@ impl InodeOperations for HostDirOp {
     ) -> Result<()> {
         let owner = task.FileOwner();
 
+        let quota = self.Quota();
+        quota.ChargeInode()?;
+
         let ret = Mkfifoat(
             self.HostFd(),
             name,
@@ -493,6 +572,7 @@ impl InodeOperations for HostDirOp {
             owner.GID.0,
         );
         if ret < 0 {
+            quota.ReleaseInode();
             return Err(Error::SysError(-ret as i32));
         }
 
@@ -509,27 +589,13 @@ impl InodeOperations for HostDirOp {
 
         let flags = 0; //ATType::AT_REMOVEDIR
 
-        let ret = UnLinkAt(self.HostFd(), name, flags);
-
-        if ret < 0 {
-            return Err(Error::SysError(-ret as i32));
-        }
-
-        self.lock().readdirCache = None;
-        return Ok(());
+        return self.Unlink(name, flags);
     }
 
     fn RemoveDirectory(&self, _task: &Task, _dir: &mut Inode, name: &str) -> Result<()> {
         let flags = ATType::AT_REMOVEDIR;
 
-        let ret = UnLinkAt(self.HostFd(), name, flags);
-
-        if ret < 0 {
-            return Err(Error::SysError(-ret as i32));
-        }
-
-        self.lock().readdirCache = None;
-        return Ok(());
+        return self.Unlink(name, flags);
     }
 
     fn Rename(
@@ -667,7 +733,10 @@ impl InodeOperations for HostDirOp {
     }
 
     fn StatFS(&self, _task: &Task) -> Result<FsInfo> {
-        return StatFS(self.HostFd())
+        let mut info = StatFS(self.HostFd())?;
+        let intern = self.lock();
+        intern.quota.StatFS(&mut info, intern.sattr.BlockSize);
+        return Ok(info)
     }
 
     fn Mappable(&self) -> Result<MMappable> {
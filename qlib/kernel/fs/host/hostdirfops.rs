@@ -132,7 +132,7 @@ impl FileOperations for HostDirFops {
         dirCtx: &mut DirCtx,
         offset: i32,
     ) -> (i32, Result<i64>) {
-        return self.DirOp.lock().IterateDir(task, dirCtx, offset);
+        return self.DirOp.IterateDir(task, dirCtx, offset);
     }
 
     fn Mappable(&self) -> Result<MMappable> {
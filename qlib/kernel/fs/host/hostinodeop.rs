@@ -49,6 +49,7 @@ use super::super::flags::*;
 use super::super::inode::*;
 use super::fs::*;
 use super::hostfileop::*;
+use super::quota::*;
 use super::util::*;
 use super::*;
 
@@ -187,6 +188,9 @@ pub struct HostInodeOpIntern {
     pub hasMappable: bool,
 
     pub isMemfd: bool,
+
+    // quota is the set of disk quotas the file is charged to.
+    pub quota: HostQuota,
 }
 
 impl Default for HostInodeOpIntern {
@@ -204,6 +208,7 @@ impl Default for HostInodeOpIntern {
             bufWriteLock: QAsyncLock::default(),
             hasMappable: false,
             isMemfd: false,
+            quota: HostQuota::default(),
         };
     }
 }
@@ -257,7 +262,8 @@ impl HostInodeOpIntern {
             size: fstat.st_size,
             bufWriteLock: QAsyncLock::default(),
             hasMappable: false,
-            isMemfd: isMemfd
+            isMemfd: isMemfd,
+            quota: HostQuota::default(),
         };
 
         if ret.CanMap() {
@@ -566,11 +572,27 @@ impl HostInodeOp {
         return self.lock().HostFd;
     }
 
-    pub fn UpdateMaxLen(&self, size: i64) {
+    // UpdateMaxLen extends the size of the file to size and returns the count
+    // of bytes the file has grown.
+    pub fn UpdateMaxLen(&self, size: i64) -> i64 {
         let mut h = self.lock();
         if h.size < size {
+            let growth = size - h.size;
             h.size = size;
+            return growth;
         }
+
+        return 0;
+    }
+
+    // WriteQuota returns the disk quotas the growth of the file is charged to.
+    pub fn WriteQuota(&self) -> Option<HostQuota> {
+        let h = self.lock();
+        if h.InodeType() != InodeType::RegularFile || h.isMemfd || !h.quota.Enabled() {
+            return None;
+        }
+
+        return Some(h.quota.clone());
     }
 
     pub fn StableAttr(&self) -> StableAttr {
@@ -730,8 +752,6 @@ impl HostInodeOp {
         offset: i64,
         _blocking: bool,
     ) -> Result<i64> {
-        let hostIops = self.clone();
-
         let size = IoVec::NumBytes(srcs);
         if size == 0 {
             return Ok(0)
@@ -743,6 +763,50 @@ impl HostInodeOp {
             size
         };
 
+        let quota = match self.WriteQuota() {
+            None => {
+                let ret = self.HostWriteAt(task, srcs, offset, size)?;
+                if self.InodeType() == InodeType::RegularFile {
+                    self.UpdateMaxLen(offset + ret);
+                }
+                return Ok(ret);
+            }
+            Some(q) => q,
+        };
+
+        // Reserve the growth of the file and shorten the write to the space
+        // left by the quotas.
+        let growth = offset + size as i64 - self.lock().size;
+        let reserved = quota.ReserveBytes(growth);
+        let size = if growth > reserved {
+            let size = size as i64 - (growth - reserved);
+            if size <= 0 {
+                quota.ReleaseBytes(reserved);
+                return Err(Error::SysError(SysErr::EDQUOT));
+            }
+            size as usize
+        } else {
+            size
+        };
+
+        let ret = self.HostWriteAt(task, srcs, offset, size);
+        let charged = match ret {
+            Err(_) => 0,
+            Ok(count) => self.UpdateMaxLen(offset + count),
+        };
+
+        if charged > reserved {
+            quota.ReserveBytes(charged - reserved);
+        } else {
+            quota.ReleaseBytes(reserved - charged);
+        }
+
+        return ret;
+    }
+
+    fn HostWriteAt(&self, task: &Task, srcs: &[IoVec], offset: i64, size: usize) -> Result<i64> {
+        let hostIops = self.clone();
+
         let mut buf = DataBuff::New(size);
         let len = task.CopyDataInFromIovs(&mut buf.buf, srcs, true)?;
         let iovs = buf.Iovs(len);
@@ -779,10 +843,6 @@ impl HostInodeOp {
                         return Err(Error::SysError(-ret as i32));
                     }
                 } else if ret >= 0 {
-                    if inodeType != InodeType::CharacterDevice {
-                        hostIops.UpdateMaxLen(offset + ret);
-                    }
-
                     return Ok(ret as i64);
                 }
 
@@ -791,13 +851,7 @@ impl HostInodeOp {
                 // todo: handle tmp file elegant
             }
 
-            match IOWriteAt(hostIops.HostFd(), &iovs, offset as u64) {
-                Err(e) => return Err(e),
-                Ok(ret) => {
-                    hostIops.UpdateMaxLen(offset + ret);
-                    return Ok(ret);
-                }
-            }
+            return IOWriteAt(hostIops.HostFd(), &iovs, offset as u64);
         }
     }
 
@@ -820,13 +874,29 @@ impl HostInodeOp {
             let iovsAddr = &iovs[0] as *const _ as u64;
             let iovcnt = 1;
 
+            let quota = hostIops.WriteQuota();
+            let reserved = len as i64;
+            if let Some(q) = &quota {
+                q.ChargeBytes(reserved)?;
+            }
+
             let (count, len) = HostSpace::IOAppend(hostIops.HostFd(), iovsAddr, iovcnt);
-            if count < 0 {
-                return Err(Error::SysError(-count as i32));
+            let charged = if count < 0 || inodeType != InodeType::RegularFile {
+                0
+            } else {
+                hostIops.UpdateMaxLen(len)
+            };
+
+            if let Some(q) = &quota {
+                if charged > reserved {
+                    q.ReserveBytes(charged - reserved);
+                } else {
+                    q.ReleaseBytes(reserved - charged);
+                }
             }
 
-            if inodeType == InodeType::RegularFile {
-                hostIops.UpdateMaxLen(len);
+            if count < 0 {
+                return Err(Error::SysError(-count as i32));
             }
 
             return Ok((count, len));
@@ -1247,27 +1317,58 @@ impl InodeOperations for HostInodeOp {
             }
         }
 
+        let quota = self.WriteQuota();
+        if let Some(q) = &quota {
+            q.ChargeBytes(size - oldSize)?;
+        }
+
         let ret = Ftruncate(self.HostFd(), size);
 
         if ret < 0 {
+            if let Some(q) = &quota {
+                q.ReleaseBytes(size - oldSize);
+            }
             return Err(Error::SysError(-ret as i32));
         }
 
+        if let Some(q) = &quota {
+            q.ReleaseBytes(oldSize - size);
+        }
+
         self.lock().size = size;
 
         return Ok(());
     }
 
     fn Allocate(&self, task: &Task, _dir: &mut Inode, offset: i64, length: i64) -> Result<()> {
+        let quota = self.WriteQuota();
+        let oldSize = self.lock().size;
+        let reserved = core::cmp::max(offset + length - oldSize, 0);
+        if let Some(q) = &quota {
+            q.ChargeBytes(reserved)?;
+        }
+
         let ret = Fallocate(self.HostFd(), 0, offset, length);
 
         if ret < 0 {
+            if let Some(q) = &quota {
+                q.ReleaseBytes(reserved);
+            }
             return Err(Error::SysError(-ret as i32));
         }
 
         let uattr = self.UnstableAttr(task)?;
         self.lock().size = uattr.Size;
 
+        if let Some(q) = &quota {
+            let charged = core::cmp::max(uattr.Size - oldSize, 0);
+            if charged > reserved {
+                q.ReserveBytes(charged - reserved);
+            } else {
+                q.ReleaseBytes(reserved - charged);
+            }
+        }
+
         return Ok(());
     }
 
@@ -1300,7 +1401,10 @@ impl InodeOperations for HostInodeOp {
     }
 
     fn StatFS(&self, _task: &Task) -> Result<FsInfo> {
-        return StatFS(self.HostFd())
+        let mut info = StatFS(self.HostFd())?;
+        let intern = self.lock();
+        intern.quota.StatFS(&mut info, intern.sattr.BlockSize);
+        return Ok(info)
     }
 
     fn Mappable(&self) -> Result<MMappable> {
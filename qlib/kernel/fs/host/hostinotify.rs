@@ -0,0 +1,491 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::qlib::mutex::*;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::btree_map::Entry;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::ops::Deref;
+use core::ptr;
+use lazy_static::lazy_static;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::fd::*;
+use super::super::super::guestfdnotifier::*;
+use super::super::super::kernel::waiter::*;
+use super::super::super::task::*;
+use super::super::super::taskMgr;
+use super::super::super::Kernel::HostSpace;
+use super::super::super::SHARESPACE;
+use super::super::dirent::*;
+use super::super::inotify::*;
+
+// HOST_WATCH_MASK is the set of events requested from the host inotify. Access
+// and open events are left out: the guest generates them itself and the host
+// would only report the accesses done by qvisor on behalf of the guest.
+pub const HOST_WATCH_MASK: u32 = InotifyEvent::IN_MODIFY
+    | InotifyEvent::IN_ATTRIB
+    | InotifyEvent::IN_CLOSE_WRITE
+    | InotifyEvent::IN_MOVED_FROM
+    | InotifyEvent::IN_MOVED_TO
+    | InotifyEvent::IN_CREATE
+    | InotifyEvent::IN_DELETE
+    | InotifyEvent::IN_DELETE_SELF
+    | InotifyEvent::IN_MOVE_SELF;
+
+// Events which change the entries of the watched directory.
+pub const HOST_DIR_CHANGE_MASK: u32 = InotifyEvent::IN_MOVED_FROM
+    | InotifyEvent::IN_MOVED_TO
+    | InotifyEvent::IN_CREATE
+    | InotifyEvent::IN_DELETE;
+
+pub const HOST_INOTIFY_BUF_SIZE: usize = 4096;
+
+// MAX_HOST_ECHOES is the number of guest events remembered per watch set.
+pub const MAX_HOST_ECHOES: usize = 16;
+
+lazy_static! {
+    pub static ref HOST_INOTIFY: HostInotify = HostInotify::default();
+}
+
+// HostInotifyEvent is the fixed part of the host struct inotify_event.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct HostInotifyEvent {
+    pub wd: i32,
+    pub mask: u32,
+    pub cookie: u32,
+    pub len: u32,
+}
+
+pub struct HostInotifyIntern {
+    // the host inotify fd, -1 before the first host-backed watch is added
+    pub fd: i32,
+
+    // queue is notified by the host fd notifier when fd becomes readable
+    pub queue: Queue,
+
+    // host watch descriptor -> (dirent id, dirent) of the guest watch targets.
+    // Several dirents might share one host wd when they point to the same host inode.
+    pub watches: BTreeMap<i32, Vec<(u64, DirentWeak)>>,
+
+    // dirent id -> host watch descriptor
+    pub wds: BTreeMap<u64, i32>,
+}
+
+impl Default for HostInotifyIntern {
+    fn default() -> Self {
+        return Self {
+            fd: -1,
+            queue: Queue::default(),
+            watches: BTreeMap::new(),
+            wds: BTreeMap::new(),
+        };
+    }
+}
+
+// HostInotify forwards the changes made to host-backed files from outside of
+// the sandbox, e.g. kubelet updating a ConfigMap volume, to the guest inotify
+// watches on the corresponding dirents.
+#[derive(Default)]
+pub struct HostInotify(QMutex<HostInotifyIntern>);
+
+impl Deref for HostInotify {
+    type Target = QMutex<HostInotifyIntern>;
+
+    fn deref(&self) -> &QMutex<HostInotifyIntern> {
+        &self.0
+    }
+}
+
+// HostEchoes keeps the pending events the guest generated on a host-backed
+// target. Each one is expected to come back once from the host inotify. The host
+// coalesces identical consecutive events, so a match consumes all the pending
+// events of the same name and kind.
+#[derive(Default)]
+pub struct HostEchoes {
+    pub pending: VecDeque<(String, u32)>,
+}
+
+impl HostEchoes {
+    pub fn Record(&mut self, name: &str, events: u32) {
+        let events = events & HOST_WATCH_MASK;
+        if events == 0 {
+            return;
+        }
+
+        if self.pending.len() >= MAX_HOST_ECHOES {
+            self.pending.pop_front();
+        }
+
+        self.pending.push_back((name.to_string(), events));
+    }
+
+    // Consume returns whether the host event is the echo of a guest event.
+    pub fn Consume(&mut self, name: &str, events: u32) -> bool {
+        let events = events & HOST_WATCH_MASK;
+        let count = self.pending.len();
+        self.pending
+            .retain(|(n, e)| !(n == name && *e & events != 0));
+        return self.pending.len() != count;
+    }
+}
+
+// ParseEvents splits the buffer read from the host inotify fd into the events
+// and their names. A truncated trailing record is dropped.
+pub fn ParseEvents(buf: &[u8]) -> Vec<(HostInotifyEvent, String)> {
+    let mut ret = Vec::new();
+    let mut pos = 0;
+    while pos + INOTIFY_EVENT_BASE_SIZE <= buf.len() {
+        let event =
+            unsafe { core::ptr::read_unaligned(&buf[pos] as *const _ as *const HostInotifyEvent) };
+        let nameStart = pos + INOTIFY_EVENT_BASE_SIZE;
+        let nameEnd = nameStart + event.len as usize;
+        if nameEnd > buf.len() {
+            error!("HostInotify get truncated event {:?}", event);
+            break;
+        }
+
+        let name = &buf[nameStart..nameEnd];
+        let nameLen = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        ret.push((event, String::from_utf8_lossy(&name[..nameLen]).to_string()));
+        pos = nameEnd;
+    }
+
+    return ret;
+}
+
+impl HostInotifyIntern {
+    fn Fd(&mut self) -> Result<i32> {
+        if self.fd >= 0 {
+            return Ok(self.fd);
+        }
+
+        let fd = HostSpace::HostInotifyInit();
+        if fd < 0 {
+            return Err(Error::SysError(-fd as i32));
+        }
+
+        self.fd = fd as i32;
+        SetWaitInfo(self.fd, self.queue.clone());
+        taskMgr::CreateTask(HostInotifyProcess as u64, ptr::null(), true);
+        return Ok(self.fd);
+    }
+}
+
+// HostInotifyProcess is the kernel task draining the host inotify fd.
+pub fn HostInotifyProcess(_para: *const u8) {
+    HOST_INOTIFY.Process(Task::Current());
+}
+
+impl HostInotify {
+    // Process reads the host inotify fd whenever the host fd notifier reports it
+    // readable, the same way as the other host fds are waited on.
+    fn Process(&self, task: &Task) {
+        let (fd, queue) = {
+            let intern = self.lock();
+            (intern.fd, intern.queue.clone())
+        };
+
+        let general = task.blocker.generalEntry.clone();
+        queue.EventRegister(task, &general, READABLE_EVENT);
+        match UpdateFD(fd) {
+            Err(e) => {
+                error!("HostInotify wait fd {} fail with error {:?}", fd, e);
+                queue.EventUnregister(task, &general);
+                return;
+            }
+            Ok(()) => (),
+        }
+
+        let buf = DataBuff::New(HOST_INOTIFY_BUF_SIZE);
+        loop {
+            match IORead(fd, &buf.Iovs(buf.Len())) {
+                Err(Error::SysError(SysErr::EAGAIN)) => {
+                    task.blocker.BlockGeneralOnly();
+                }
+                Err(e) => {
+                    error!("HostInotify read fd {} fail with error {:?}", fd, e);
+                    break;
+                }
+                Ok(cnt) => {
+                    self.ProcessEvents(&buf.buf[0..cnt as usize]);
+                }
+            }
+        }
+
+        queue.EventUnregister(task, &general);
+    }
+
+    // HostFd returns the host fd backing the dirent's inode, if there is one.
+    pub fn HostFd(d: &Dirent) -> Option<i32> {
+        let inode = d.Inode();
+        if inode.lock().Overlay.is_some() {
+            return None;
+        }
+
+        let iops = inode.lock().InodeOp.clone();
+        if let Some(dirop) = iops.HostDirOp() {
+            return Some(dirop.HostFd());
+        }
+
+        if let Some(iop) = iops.HostInodeOp() {
+            return Some(iop.HostFd());
+        }
+
+        return None;
+    }
+
+    // AddWatch starts to watch the host file backing d. It is called when the
+    // guest adds an inotify watch on d.
+    pub fn AddWatch(&self, d: &Dirent) {
+        if !SHARESPACE.config.read().HostInotify {
+            return;
+        }
+
+        let hostfd = match Self::HostFd(d) {
+            None => return,
+            Some(fd) => fd,
+        };
+
+        let mut intern = self.lock();
+        let fd = match intern.Fd() {
+            Err(e) => {
+                error!("HostInotify init fail with error {:?}", e);
+                return;
+            }
+            Ok(fd) => fd,
+        };
+
+        let wd = match intern.wds.entry(d.ID()) {
+            Entry::Occupied(_) => return,
+            Entry::Vacant(e) => {
+                let wd = HostSpace::HostInotifyAddWatch(fd, hostfd, HOST_WATCH_MASK);
+                if wd < 0 {
+                    // e.g. memfd or a host fd which doesn't support inotify
+                    info!(
+                        "HostInotify add watch for {} fail with error {}",
+                        d.MyFullName(),
+                        wd
+                    );
+                    return;
+                }
+
+                *e.insert(wd as i32)
+            }
+        };
+
+        intern
+            .watches
+            .entry(wd)
+            .or_insert(Vec::new())
+            .push((d.ID(), d.Downgrade()));
+    }
+
+    // RemoveWatch stops watching the host file backing d when the guest has no
+    // inotify watch left on d.
+    pub fn RemoveWatch(&self, d: &Dirent) {
+        let fd;
+        let wd;
+        {
+            let mut intern = self.lock();
+            let id = d.ID();
+            wd = match intern.wds.remove(&id) {
+                None => return,
+                Some(wd) => wd,
+            };
+
+            let empty = match intern.watches.get_mut(&wd) {
+                None => return,
+                Some(dirents) => {
+                    dirents.retain(|(did, _)| *did != id);
+                    dirents.len() == 0
+                }
+            };
+
+            if !empty {
+                return;
+            }
+
+            intern.watches.remove(&wd);
+            fd = intern.fd;
+        }
+
+        HostSpace::HostInotifyRmWatch(fd, wd);
+    }
+
+    fn Dirents(&self, wd: i32) -> Vec<Dirent> {
+        let intern = self.lock();
+        let mut ret = Vec::new();
+        match intern.watches.get(&wd) {
+            None => (),
+            Some(dirents) => {
+                for (_, d) in dirents {
+                    if let Some(d) = d.Upgrade() {
+                        ret.push(d);
+                    }
+                }
+            }
+        }
+
+        return ret;
+    }
+
+    fn AllDirents(&self) -> Vec<Dirent> {
+        let intern = self.lock();
+        let mut ret = Vec::new();
+        for (_, dirents) in &intern.watches {
+            for (_, d) in dirents {
+                if let Some(d) = d.Upgrade() {
+                    ret.push(d);
+                }
+            }
+        }
+
+        return ret;
+    }
+
+    // ProcessEvents handles the struct inotify_event records read from the host
+    // inotify fd.
+    pub fn ProcessEvents(&self, buf: &[u8]) {
+        for (event, name) in ParseEvents(buf) {
+            self.ProcessEvent(event.wd, event.mask, event.cookie, &name);
+        }
+    }
+
+    pub fn ProcessEvent(&self, wd: i32, mask: u32, cookie: u32, name: &str) {
+        if mask & InotifyEvent::IN_Q_OVERFLOW != 0 {
+            // Host events got lost, drop all the cached listings of the watched
+            // directories as we can't tell which of them changed.
+            for d in self.AllDirents() {
+                Self::Invalidate(&d, "", HOST_DIR_CHANGE_MASK);
+            }
+            return;
+        }
+
+        if mask & InotifyEvent::IN_IGNORED != 0 {
+            // The host watch is gone, i.e. the file got deleted or its
+            // filesystem got unmounted.
+            let mut intern = self.lock();
+            if let Some(dirents) = intern.watches.remove(&wd) {
+                for (id, _) in dirents {
+                    intern.wds.remove(&id);
+                }
+            }
+            return;
+        }
+
+        for d in self.Dirents(wd) {
+            Self::Invalidate(&d, name, mask);
+
+            let watches = d.Watches();
+            watches.NotifyFromHost(name, mask, cookie);
+            if mask & InotifyEvent::IN_DELETE_SELF != 0 {
+                watches.MarkUnlinked();
+                watches.TargetDestroyed();
+            }
+        }
+    }
+
+    // Invalidate drops the guest caches which are stale after a host side change:
+    // the cached listing of the directory and the dirent of the changed child.
+    fn Invalidate(d: &Dirent, name: &str, mask: u32) {
+        if mask & HOST_DIR_CHANGE_MASK == 0 {
+            return;
+        }
+
+        let iops = d.Inode().lock().InodeOp.clone();
+        if let Some(dirop) = iops.HostDirOp() {
+            dirop.lock().readdirCache = None;
+        }
+
+        if name.len() > 0 {
+            d.InvalidateChild(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn Event(buf: &mut Vec<u8>, wd: i32, mask: u32, name: &str, len: u32) {
+        buf.extend_from_slice(&wd.to_ne_bytes());
+        buf.extend_from_slice(&mask.to_ne_bytes());
+        buf.extend_from_slice(&0u32.to_ne_bytes());
+        buf.extend_from_slice(&len.to_ne_bytes());
+        let mut name = name.as_bytes().to_vec();
+        name.resize(len as usize, 0);
+        buf.extend_from_slice(&name);
+    }
+
+    #[test]
+    fn test_ParseEvents() {
+        let mut buf = Vec::new();
+        Event(&mut buf, 1, InotifyEvent::IN_CREATE, "a.txt", 16);
+        Event(&mut buf, 2, InotifyEvent::IN_DELETE_SELF, "", 0);
+        let events = ParseEvents(&buf);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].0.wd, 1);
+        assert_eq!(events[0].0.mask, InotifyEvent::IN_CREATE);
+        assert_eq!(events[0].1, "a.txt");
+        assert_eq!(events[1].0.wd, 2);
+        assert_eq!(events[1].1, "");
+    }
+
+    #[test]
+    fn test_ParseEventsTruncated() {
+        let mut buf = Vec::new();
+        Event(&mut buf, 1, InotifyEvent::IN_MODIFY, "a", 16);
+        Event(&mut buf, 1, InotifyEvent::IN_MODIFY, "b", 16);
+        buf.truncate(buf.len() - 4);
+        let events = ParseEvents(&buf);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].1, "a");
+    }
+
+    #[test]
+    fn test_HostEchoesConsume() {
+        let mut echoes = HostEchoes::default();
+        echoes.Record("a", InotifyEvent::IN_MODIFY);
+        echoes.Record("a", InotifyEvent::IN_MODIFY);
+        echoes.Record("b", InotifyEvent::IN_CREATE);
+
+        // the host coalesces the two modifications into one event
+        assert!(echoes.Consume("a", InotifyEvent::IN_MODIFY));
+        assert!(!echoes.Consume("a", InotifyEvent::IN_MODIFY));
+
+        // an event of another kind is not an echo
+        assert!(!echoes.Consume("b", InotifyEvent::IN_DELETE));
+        assert!(echoes.Consume("b", InotifyEvent::IN_CREATE));
+        assert!(echoes.pending.is_empty());
+    }
+
+    #[test]
+    fn test_HostEchoesGuestOnlyEvents() {
+        let mut echoes = HostEchoes::default();
+        echoes.Record("a", InotifyEvent::IN_ACCESS | InotifyEvent::IN_OPEN);
+        assert!(echoes.pending.is_empty());
+
+        for i in 0..MAX_HOST_ECHOES + 4 {
+            echoes.Record(&format!("{}", i), InotifyEvent::IN_MODIFY);
+        }
+        assert_eq!(echoes.pending.len(), MAX_HOST_ECHOES);
+        assert!(!echoes.Consume("0", InotifyEvent::IN_MODIFY));
+    }
+}
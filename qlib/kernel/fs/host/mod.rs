@@ -12,17 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod control;
 pub mod dirent;
 pub mod fs;
 pub mod hostfileop;
 pub mod hostinodeop;
 pub mod ioctl;
+pub mod socket;
 pub mod socket_iovec;
 pub mod tty;
 pub mod util;
 pub mod diriops;
 pub mod hostdirfops;
 pub mod fifoiops;
+pub mod hostinotify;
+pub mod quota;
 
 use alloc::collections::btree_map::BTreeMap;
 use alloc::string::String;
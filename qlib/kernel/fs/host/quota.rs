@@ -0,0 +1,381 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::sync::Arc;
+use core::ops::Deref;
+use lazy_static::lazy_static;
+
+use crate::qlib::mutex::*;
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::SHARESPACE;
+use super::super::attr::*;
+use super::super::inode::*;
+use super::super::mount::*;
+
+lazy_static! {
+    // SANDBOX_QUOTA is the quota of all the host files written by the sandbox.
+    pub static ref SANDBOX_QUOTA: Option<DiskQuota> = {
+        let config = SHARESPACE.config.read();
+        if config.DiskQuotaBytes == 0 && config.DiskQuotaInodes == 0 {
+            None
+        } else {
+            Some(DiskQuota::New(config.DiskQuotaBytes, config.DiskQuotaInodes))
+        }
+    };
+
+    // MOUNT_QUOTAS maps the (device id, inode id) of the root directories of
+    // the mounts with a quota to the quota, so that the quota is found again
+    // when the directory is looked up after its dirent has been evicted.
+    pub static ref MOUNT_QUOTAS: QRwLock<BTreeMap<(u64, u64), DiskQuota>> = QRwLock::new(BTreeMap::new());
+}
+
+// QuotaUsage is the usage of a disk quota. The usage only accounts for the
+// files created and the bytes written by the sandbox, the files present in the
+// host directory before the sandbox started are not charged.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct QuotaUsage {
+    // byteLimit and inodeLimit are the hard limits of the quota, 0 means no limit.
+    pub byteLimit: u64,
+    pub inodeLimit: u64,
+
+    pub bytes: u64,
+    pub inodes: u64,
+}
+
+impl QuotaUsage {
+    pub fn FreeBytes(&self) -> u64 {
+        if self.byteLimit == 0 {
+            return core::u64::MAX;
+        }
+
+        return self.byteLimit.saturating_sub(self.bytes);
+    }
+
+    pub fn FreeInodes(&self) -> u64 {
+        if self.inodeLimit == 0 {
+            return core::u64::MAX;
+        }
+
+        return self.inodeLimit.saturating_sub(self.inodes);
+    }
+}
+
+#[derive(Clone)]
+pub struct DiskQuota(Arc<QMutex<QuotaUsage>>);
+
+impl Deref for DiskQuota {
+    type Target = Arc<QMutex<QuotaUsage>>;
+
+    fn deref(&self) -> &Arc<QMutex<QuotaUsage>> {
+        &self.0
+    }
+}
+
+impl DiskQuota {
+    pub fn New(byteLimit: u64, inodeLimit: u64) -> Self {
+        let usage = QuotaUsage {
+            byteLimit: byteLimit,
+            inodeLimit: inodeLimit,
+            ..Default::default()
+        };
+
+        return Self(Arc::new(QMutex::new(usage)));
+    }
+
+    pub fn Usage(&self) -> QuotaUsage {
+        return *self.lock();
+    }
+
+    // ReserveBytes charges up to len bytes to the quota and returns the
+    // count of bytes charged.
+    pub fn ReserveBytes(&self, len: u64) -> u64 {
+        let mut usage = self.lock();
+        let len = core::cmp::min(len, usage.FreeBytes());
+        usage.bytes += len;
+        return len;
+    }
+
+    pub fn ReleaseBytes(&self, len: u64) {
+        let mut usage = self.lock();
+        usage.bytes = usage.bytes.saturating_sub(len);
+    }
+
+    pub fn ChargeInode(&self) -> Result<()> {
+        let mut usage = self.lock();
+        if usage.FreeInodes() == 0 {
+            return Err(Error::SysError(SysErr::EDQUOT));
+        }
+
+        usage.inodes += 1;
+        return Ok(());
+    }
+
+    pub fn ReleaseInode(&self) {
+        let mut usage = self.lock();
+        usage.inodes = usage.inodes.saturating_sub(1);
+    }
+}
+
+// HostQuota is the set of quotas a host file is charged to: the quota of the
+// mount the file is in, if any, and the sandbox quota.
+#[derive(Clone, Default)]
+pub struct HostQuota {
+    pub mount: Option<DiskQuota>,
+}
+
+impl HostQuota {
+    pub fn New(mount: &DiskQuota) -> Self {
+        return Self {
+            mount: Some(mount.clone()),
+        };
+    }
+
+    // HostInode returns the host inode of an overlay inode: the container
+    // root is an overlay over the host root filesystem.
+    pub fn HostInode(inode: &Inode) -> Inode {
+        let upper = match &inode.lock().Overlay {
+            None => None,
+            Some(overlay) => overlay.read().upper.clone(),
+        };
+
+        match upper {
+            None => return inode.clone(),
+            Some(upper) => return upper,
+        }
+    }
+
+    // Of returns the quotas of a host-backed inode.
+    pub fn Of(inode: &Inode) -> Option<Self> {
+        let inode = Self::HostInode(inode);
+        let iops = inode.lock().InodeOp.clone();
+        if let Some(iops) = iops.HostInodeOp() {
+            return Some(iops.lock().quota.clone());
+        }
+
+        if let Some(iops) = iops.HostDirOp() {
+            return Some(iops.lock().quota.clone());
+        }
+
+        return None;
+    }
+
+    // SetMountQuota sets the quota of the mount whose root directory is the
+    // host-backed inode.
+    pub fn SetMountQuota(inode: &Inode, quota: &DiskQuota) -> Result<()> {
+        let inode = Self::HostInode(inode);
+        if Self::Of(&inode).is_none() {
+            return Err(Error::SysError(SysErr::EINVAL));
+        }
+
+        let attr = inode.StableAttr();
+        MOUNT_QUOTAS
+            .write()
+            .insert((attr.DeviceId, attr.InodeId), quota.clone());
+        Self::New(quota).Attach(&inode);
+        return Ok(());
+    }
+
+    // Child returns the quotas of a file looked up in a directory with these
+    // quotas.
+    pub fn Child(&self, attr: &StableAttr) -> Self {
+        if attr.IsDir() {
+            if let Some(q) = MOUNT_QUOTAS.read().get(&(attr.DeviceId, attr.InodeId)) {
+                return Self::New(q);
+            }
+        }
+
+        return self.clone();
+    }
+
+    // Attach sets the quotas of a host-backed inode, the inodes looked up
+    // or created in a directory inherit the quotas of the directory.
+    pub fn Attach(&self, inode: &Inode) {
+        let iops = inode.lock().InodeOp.clone();
+        if let Some(iops) = iops.HostInodeOp() {
+            iops.lock().quota = self.clone();
+        } else if let Some(iops) = iops.HostDirOp() {
+            iops.lock().quota = self.clone();
+        }
+    }
+
+    pub fn Enabled(&self) -> bool {
+        return self.mount.is_some() || SANDBOX_QUOTA.is_some();
+    }
+
+    // ReserveBytes charges up to len bytes to the quotas and returns the
+    // count of bytes charged to all of them.
+    pub fn ReserveBytes(&self, len: i64) -> i64 {
+        if len <= 0 {
+            return 0;
+        }
+
+        let mut len = len as u64;
+        if let Some(q) = &self.mount {
+            len = q.ReserveBytes(len);
+        }
+
+        if let Some(q) = SANDBOX_QUOTA.as_ref() {
+            let granted = q.ReserveBytes(len);
+            if let Some(m) = &self.mount {
+                m.ReleaseBytes(len - granted);
+            }
+            len = granted;
+        }
+
+        return len as i64;
+    }
+
+    // ChargeBytes charges len bytes to the quotas, or nothing if one of them
+    // doesn't have len bytes left.
+    pub fn ChargeBytes(&self, len: i64) -> Result<()> {
+        let granted = self.ReserveBytes(len);
+        if granted < len {
+            self.ReleaseBytes(granted);
+            return Err(Error::SysError(SysErr::EDQUOT));
+        }
+
+        return Ok(());
+    }
+
+    pub fn ReleaseBytes(&self, len: i64) {
+        if len <= 0 {
+            return;
+        }
+
+        if let Some(q) = &self.mount {
+            q.ReleaseBytes(len as u64);
+        }
+
+        if let Some(q) = SANDBOX_QUOTA.as_ref() {
+            q.ReleaseBytes(len as u64);
+        }
+    }
+
+    pub fn ChargeInode(&self) -> Result<()> {
+        if let Some(q) = &self.mount {
+            q.ChargeInode()?;
+        }
+
+        if let Some(q) = SANDBOX_QUOTA.as_ref() {
+            match q.ChargeInode() {
+                Ok(()) => (),
+                Err(e) => {
+                    if let Some(m) = &self.mount {
+                        m.ReleaseInode();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    pub fn ReleaseInode(&self) {
+        if let Some(q) = &self.mount {
+            q.ReleaseInode();
+        }
+
+        if let Some(q) = SANDBOX_QUOTA.as_ref() {
+            q.ReleaseInode();
+        }
+    }
+
+    // Usage returns the usage of the quota reported to the sandbox: the mount
+    // quota if there is one, the sandbox quota otherwise.
+    pub fn Usage(&self) -> Option<QuotaUsage> {
+        if let Some(q) = &self.mount {
+            return Some(q.Usage());
+        }
+
+        return SANDBOX_QUOTA.as_ref().map(|q| q.Usage());
+    }
+
+    // StatFS limits the host filesystem info to the space and the inodes the
+    // quotas leave to the sandbox.
+    pub fn StatFS(&self, info: &mut FsInfo, blockSize: i64) {
+        if blockSize <= 0 {
+            return;
+        }
+
+        let mut quotas = [None, None];
+        quotas[0] = self.mount.as_ref().map(|q| q.Usage());
+        quotas[1] = SANDBOX_QUOTA.as_ref().map(|q| q.Usage());
+
+        for usage in quotas.iter() {
+            let usage = match usage {
+                None => continue,
+                Some(u) => u,
+            };
+
+            if usage.byteLimit != 0 {
+                let total = usage.byteLimit / blockSize as u64;
+                let free = usage.FreeBytes() / blockSize as u64;
+                info.TotalBlocks = core::cmp::min(info.TotalBlocks, total);
+                info.FreeBlocks = core::cmp::min(info.FreeBlocks, free);
+            }
+
+            if usage.inodeLimit != 0 {
+                info.TotalFiles = core::cmp::min(info.TotalFiles, usage.inodeLimit);
+                info.FreeFiles = core::cmp::min(info.FreeFiles, usage.FreeInodes());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ReserveBytes() {
+        let q = DiskQuota::New(100, 0);
+        assert_eq!(q.ReserveBytes(60), 60);
+        // only the space left is granted
+        assert_eq!(q.ReserveBytes(60), 40);
+        assert_eq!(q.ReserveBytes(1), 0);
+        assert_eq!(q.Usage().FreeBytes(), 0);
+
+        q.ReleaseBytes(30);
+        assert_eq!(q.Usage().bytes, 70);
+        q.ReleaseBytes(1000);
+        assert_eq!(q.Usage().bytes, 0);
+    }
+
+    #[test]
+    fn test_Unlimited() {
+        let q = DiskQuota::New(0, 0);
+        assert_eq!(q.ReserveBytes(1 << 40), 1 << 40);
+        assert_eq!(q.Usage().FreeBytes(), core::u64::MAX);
+        assert!(q.ChargeInode().is_ok());
+        assert_eq!(q.Usage().FreeInodes(), core::u64::MAX);
+    }
+
+    #[test]
+    fn test_ChargeInode() {
+        let q = DiskQuota::New(0, 2);
+        assert!(q.ChargeInode().is_ok());
+        assert!(q.ChargeInode().is_ok());
+        match q.ChargeInode() {
+            Err(Error::SysError(SysErr::EDQUOT)) => (),
+            _ => panic!("inode limit not enforced"),
+        }
+
+        q.ReleaseInode();
+        assert!(q.ChargeInode().is_ok());
+        assert_eq!(q.Usage().inodes, 2);
+    }
+}
@@ -0,0 +1,272 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::qlib::mutex::*;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::cmp;
+use core::ops::Deref;
+use lazy_static::lazy_static;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::loader::HostUnixSocket;
+use super::super::super::guestfdnotifier::*;
+use super::super::super::kernel::waiter::*;
+use super::super::super::socket::control::*;
+use super::super::super::socket::unix::transport::unix::*;
+use super::super::super::task::*;
+use super::super::super::tcpip::tcpip::*;
+use super::super::super::Kernel::HostSpace;
+use super::control::*;
+
+lazy_static! {
+    // the guest paths of the host unix sockets the sandbox may connect to,
+    // the host side keeps the host paths in the same order
+    pub static ref HOST_UNIX_SOCKETS: QRwLock<Vec<String>> = QRwLock::new(Vec::new());
+}
+
+pub fn SetHostUnixSockets(socks: &[HostUnixSocket]) {
+    *HOST_UNIX_SOCKETS.write() = socks.iter().map(|s| s.GuestPath.clone()).collect();
+}
+
+// HostUnixSocketIdx returns the allowlist index of a unix socket path.
+pub fn HostUnixSocketIdx(path: &[u8]) -> Option<usize> {
+    return HOST_UNIX_SOCKETS
+        .read()
+        .iter()
+        .position(|p| p.as_bytes() == path);
+}
+
+pub struct HostConnectedEndpointInternal {
+    pub fd: i32,
+    pub stype: i32,
+    // the guest path of the host socket
+    pub path: String,
+    pub queue: Queue,
+}
+
+impl Drop for HostConnectedEndpointInternal {
+    fn drop(&mut self) {
+        HostSpace::Close(self.fd);
+    }
+}
+
+// HostConnectedEndpoint is the peer of a sandbox unix socket connected to an
+// allowlisted host unix socket. It moves the data with the host socket and
+// translates the control messages:
+// . SCM_RIGHTS: the sandbox can only send the files backed by a host fd, the
+// received host fds are imported as sandbox files.
+// . SCM_CREDENTIALS: the sandbox credentials are not sent, the host kernel
+// reports the credentials of the sandbox process to the peer. The credentials
+// of the host peer can't be mapped in the sandbox and are reported as nobody.
+#[derive(Clone)]
+pub struct HostConnectedEndpoint(Arc<HostConnectedEndpointInternal>);
+
+impl Deref for HostConnectedEndpoint {
+    type Target = Arc<HostConnectedEndpointInternal>;
+
+    fn deref(&self) -> &Arc<HostConnectedEndpointInternal> {
+        &self.0
+    }
+}
+
+impl HostConnectedEndpoint {
+    pub fn Connect(idx: usize, path: &str, stype: i32, queue: &Queue) -> Result<Self> {
+        let fd = HostSpace::HostUnixConnect(idx, stype) as i32;
+        if fd < 0 {
+            return Err(Error::SysError(-fd));
+        }
+
+        SetWaitInfo(fd, queue.clone());
+
+        let internal = HostConnectedEndpointInternal {
+            fd: fd,
+            stype: stype,
+            path: path.to_string(),
+            queue: queue.clone(),
+        };
+
+        return Ok(Self(Arc::new(internal)));
+    }
+
+    fn MsgHdr(data: &[IoVec], control: &mut [u8]) -> MsgHdr {
+        let mut msgHdr = MsgHdr::default();
+        if data.len() > 0 {
+            msgHdr.iov = &data[0] as *const _ as u64;
+            msgHdr.iovLen = data.len();
+        }
+
+        if control.len() > 0 {
+            msgHdr.msgControl = &mut control[0] as *mut _ as u64;
+            msgHdr.msgControlLen = control.len();
+        }
+
+        return msgHdr;
+    }
+}
+
+impl Passcred for HostConnectedEndpoint {
+    fn Passcred(&self) -> bool {
+        return false;
+    }
+}
+
+impl PartialEndPoint for HostConnectedEndpoint {
+    fn GetLocalAddress(&self) -> Result<SockAddrUnix> {
+        return Ok(SockAddrUnix::New(&self.path));
+    }
+
+    fn Type(&self) -> i32 {
+        return self.stype;
+    }
+}
+
+impl Receiver for HostConnectedEndpoint {
+    fn as_any(&self) -> &Any {
+        return self;
+    }
+
+    fn Recv(
+        &self,
+        data: &mut [IoVec],
+        _creds: bool,
+        numRights: u64,
+        peek: bool,
+    ) -> Result<(usize, usize, SCMControlMessages, bool, SockAddrUnix, bool)> {
+        let rights = cmp::min(numRights as usize, SCM_MAX_FD);
+        let mut control = if rights > 0 {
+            vec![0u8; CMsgSpace(rights * SIZE_OF_CONTROL_MESSAGE_RIGHT)]
+        } else {
+            Vec::new()
+        };
+
+        let mut msgHdr = Self::MsgHdr(data, &mut control);
+        let mut flags = MsgType::MSG_DONTWAIT | MsgType::MSG_CMSG_CLOEXEC;
+        if peek {
+            flags |= MsgType::MSG_PEEK;
+        }
+
+        if self.stype != SockType::SOCK_STREAM {
+            // get the real length of the truncated datagrams
+            flags |= MsgType::MSG_TRUNC;
+        }
+
+        let ret = HostSpace::IORecvMsg(self.fd, &mut msgHdr as *mut _ as u64, flags, false);
+        if ret < 0 {
+            return Err(Error::SysError(-ret as i32));
+        }
+
+        let total = IoVec::NumBytes(data);
+        if ret == 0 && self.stype == SockType::SOCK_STREAM && total > 0 {
+            return Err(Error::ErrClosedForReceive);
+        }
+
+        let mut ctrls = SCMControlMessages::default();
+        let fds = ParseRights(&control[..cmp::min(msgHdr.msgControlLen, control.len())]);
+        if fds.len() > 0 {
+            let files = HostFdsToFiles(Task::Current(), &fds);
+            ctrls.Rights = Some(SCMRights(files));
+        }
+
+        let cmTruncated = msgHdr.msgFlags & MsgType::MSG_CTRUNC != 0;
+        let msgLen = ret as usize;
+        let copied = cmp::min(msgLen, total);
+        return Ok((copied, msgLen, ctrls, cmTruncated, SockAddrUnix::default(), false));
+    }
+
+    fn RecvNotify(&self) {}
+
+    fn CloseRecv(&self) {
+        HostSpace::Shutdown(self.fd, LibcConst::SHUT_RD as i32);
+    }
+
+    fn CloseNotify(&self) {
+        self.queue.Notify(READABLE_EVENT | WRITEABLE_EVENT);
+    }
+
+    fn Readable(&self) -> bool {
+        return NonBlockingPoll(self.fd, READABLE_EVENT) != 0;
+    }
+
+    fn RecvQueuedSize(&self) -> i64 {
+        return -1;
+    }
+
+    fn RecvMaxQueueSize(&self) -> i64 {
+        return -1;
+    }
+}
+
+impl ConnectedEndpoint for HostConnectedEndpoint {
+    fn Send(
+        &self,
+        data: &[IoVec],
+        controlMessages: &SCMControlMessages,
+        _from: &SockAddrUnix,
+    ) -> Result<(usize, bool)> {
+        if self.stype == SockType::SOCK_STREAM && IoVec::NumBytes(data) == 0 {
+            return Ok((0, false));
+        }
+
+        let mut control = match &controlMessages.Rights {
+            Some(rights) if rights.0.len() > 0 => BuildRights(&FilesToHostFds(&rights.0)?),
+            _ => Vec::new(),
+        };
+
+        let mut msgHdr = Self::MsgHdr(data, &mut control);
+        let flags = MsgType::MSG_DONTWAIT | MsgType::MSG_NOSIGNAL;
+        let ret = HostSpace::IOSendMsg(self.fd, &mut msgHdr as *mut _ as u64, flags, false);
+        if ret < 0 {
+            return Err(Error::SysError(-ret as i32));
+        }
+
+        return Ok((ret as usize, false));
+    }
+
+    fn SendNotify(&self) {}
+
+    fn CloseSend(&self) {
+        HostSpace::Shutdown(self.fd, LibcConst::SHUT_WR as i32);
+    }
+
+    fn CloseNotify(&self) {
+        self.queue.Notify(READABLE_EVENT | WRITEABLE_EVENT);
+    }
+
+    fn Writable(&self) -> bool {
+        return NonBlockingPoll(self.fd, WRITEABLE_EVENT) != 0;
+    }
+
+    fn EventUpdate(&self) {
+        UpdateFD(self.fd).unwrap();
+    }
+
+    fn SendQueuedSize(&self) -> i64 {
+        return -1;
+    }
+
+    fn SendMaxQueueSize(&self) -> i64 {
+        return -1;
+    }
+
+    fn CloseUnread(&self) {}
+
+    fn SetSendBufferSize(&self, v: i64) -> i64 {
+        return v;
+    }
+}
@@ -198,6 +198,13 @@ impl Iops {
         }
     } 
 
+    pub fn HostDirOp(&self) -> Option<HostDirOp> {
+        match self {
+            Self::HostDirOp(inner) => Some(inner.clone()),
+            _ => None,
+        }
+    }
+
     pub fn TTYDevice(&self) -> Option<TTYDevice> {
         match self {
             Self::TTYDevice(inner) => Some(inner.clone()),
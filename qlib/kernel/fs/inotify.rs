@@ -32,7 +32,9 @@ use super::super::super::linux_def::*;
 use super::super::uid::*;
 use super::super::kernel::waiter::Queue;
 use super::super::fs::dirent::*;
+use super::super::SHARESPACE;
 use super::file::*;
+use super::host::hostinotify::*;
 
 // inotifyEventBaseSize is the base size of linux's struct inotify_event. This
 // must be a power 2 for rounding below.
@@ -164,6 +166,11 @@ pub struct WatchesIntern {
     // knowing if the target inode is going down due to a deletion or
     // revalidation.
     pub unlinked: bool,
+
+    // echoes keeps the events generated by guest operations on a host-backed
+    // target. The host inotify reports these changes again, the echo is dropped
+    // when it matches one of them.
+    pub echoes: HostEchoes,
 }
 
 #[derive(Default, Clone)]
@@ -239,6 +246,28 @@ impl Watches {
             return;
         }
 
+        if SHARESPACE.config.read().HostInotify {
+            self.write().echoes.Record(name, events);
+        }
+
+        self.notify(name, events, cookie, et, unlinked);
+    }
+
+    // NotifyFromHost queues an event reported by the host inotify on the file
+    // backing the target. Events just generated by the guest itself are skipped.
+    pub fn NotifyFromHost(&self, name: &str, events: u32, cookie: u32) {
+        if self.read().ws.len() == 0 {
+            return;
+        }
+
+        if self.write().echoes.Consume(name, events) {
+            return;
+        }
+
+        self.notify(name, events, cookie, EventType::InodeEvent, false);
+    }
+
+    fn notify(&self, name: &str, events: u32, cookie: u32, et: EventType, unlinked: bool) {
         let mut hasExpired = false;
         let mut watchArr = Vec::new();
         {
@@ -389,7 +418,12 @@ impl Inotify {
             let inode = w.lock().target.clone();
             match inode {
                 None => (),
-                Some(i) => i.Watches().Remove(w.Id())
+                Some(i) => {
+                    i.Watches().Remove(w.Id());
+                    if i.Watches().read().ws.len() == 0 {
+                        HOST_INOTIFY.RemoveWatch(&i);
+                    }
+                }
             }
         }
     }
@@ -429,6 +463,7 @@ impl Inotify {
         ws.watches.insert(wd, watch.clone());
 
         target.Watches().Add(&watch);
+        HOST_INOTIFY.AddWatch(target);
         return watch
     }
 
@@ -499,6 +534,9 @@ impl Inotify {
                 watchId = watch.Id();
                 // Remove the watch from the watch target.
                 target.Watches().Remove(watchId);
+                if target.Watches().read().ws.len() == 0 {
+                    HOST_INOTIFY.RemoveWatch(&target);
+                }
             }
         }
 
@@ -13,7 +13,9 @@
 // limitations under the License.
 
 pub mod dirent;
+pub mod dirent_cache;
 pub mod file;
+pub mod file_handle;
 pub mod inode;
 //pub mod inodeOperations;
 pub mod anon;
@@ -38,6 +40,7 @@ pub mod timerfd;
 pub mod tmpfs;
 pub mod tty;
 pub mod inotify;
+pub mod fanotify;
 
 pub fn Init() {
     self::tty::Init();
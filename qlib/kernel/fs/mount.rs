@@ -572,6 +572,10 @@ impl MountOptions {
 
 const DEFAULT_DIRENT_CACHE_SIZE: u64 = 1024;
 
+// The dead entries of the inode table are dropped every INODE_TABLE_PRUNE_INTERVAL
+// insertions.
+const INODE_TABLE_PRUNE_INTERVAL: usize = 1024;
+
 //#[derive(Clone)]
 pub struct MountSource {
     pub FileSystemType: String,
@@ -579,6 +583,10 @@ pub struct MountSource {
     pub MountSourceOperations: Arc<QMutex<MountSourceOperations>>,
     pub fscache: LruCache<Dirent>,
     frozen: Vec<Dirent>,
+
+    // inode id -> dirent, for the inodes of the mount whose file handles have
+    // been handed out by name_to_handle_at(2).
+    inodeTable: BTreeMap<u64, DirentWeak>,
 }
 
 impl Default for MountSource {
@@ -589,6 +597,7 @@ impl Default for MountSource {
             MountSourceOperations: Arc::new(QMutex::new(SimpleMountSourceOperations::default())),
             fscache: LruCache::New(DEFAULT_DIRENT_CACHE_SIZE),
             frozen: Vec::new(),
+            inodeTable: BTreeMap::new(),
         };
     }
 }
@@ -628,6 +637,7 @@ impl MountSource {
             MountSourceOperations: mops.clone(),
             fscache: LruCache::New(DEFAULT_DIRENT_CACHE_SIZE),
             frozen: Vec::new(),
+            inodeTable: BTreeMap::new(),
         };
     }
 
@@ -643,6 +653,7 @@ impl MountSource {
             MountSourceOperations: mops.clone(),
             fscache: LruCache::New(DEFAULT_DIRENT_CACHE_SIZE),
             frozen: Vec::new(),
+            inodeTable: BTreeMap::new(),
         };
     }
 
@@ -658,6 +669,7 @@ impl MountSource {
             MountSourceOperations: mops.clone(),
             fscache: LruCache::New(DEFAULT_DIRENT_CACHE_SIZE),
             frozen: Vec::new(),
+            inodeTable: BTreeMap::new(),
         };
     }
 
@@ -684,6 +696,7 @@ impl MountSource {
             MountSourceOperations: mops,
             fscache: LruCache::New(DEFAULT_DIRENT_CACHE_SIZE),
             frozen: Vec::new(),
+            inodeTable: BTreeMap::new(),
         };
     }
 
@@ -702,6 +715,7 @@ impl MountSource {
             MountSourceOperations: mops,
             fscache: LruCache::New(DEFAULT_DIRENT_CACHE_SIZE),
             frozen: Vec::new(),
+            inodeTable: BTreeMap::new(),
         };
     }
 
@@ -720,6 +734,7 @@ impl MountSource {
             MountSourceOperations: mops,
             fscache: LruCache::New(DEFAULT_DIRENT_CACHE_SIZE),
             frozen: Vec::new(),
+            inodeTable: BTreeMap::new(),
         };
     }
 
@@ -738,6 +753,7 @@ impl MountSource {
             MountSourceOperations: mops,
             fscache: LruCache::New(DEFAULT_DIRENT_CACHE_SIZE),
             frozen: Vec::new(),
+            inodeTable: BTreeMap::new(),
         };
     }
 
@@ -754,6 +770,7 @@ impl MountSource {
             MountSourceOperations: mops,
             fscache: LruCache::New(DEFAULT_DIRENT_CACHE_SIZE),
             frozen: Vec::new(),
+            inodeTable: BTreeMap::new(),
         };
     }
 
@@ -776,9 +793,44 @@ impl MountSource {
         self.fscache.Remove(id);
     }
 
+    // EvictReference drops the reference of the dirent evicted from the
+    // global dirent cache.
+    pub fn EvictReference(&mut self, id: u64) -> bool {
+        return self.fscache.Remove(id);
+    }
+
     pub fn Froze(&mut self, dirent: &Dirent) {
         self.frozen.push(dirent.clone());
     }
+
+    // AddInode records dirent in the inode table so that a file handle of its
+    // inode can be resolved back while the dirent is alive.
+    pub fn AddInode(&mut self, dirent: &Dirent) {
+        let ino = dirent.Inode().StableAttr().InodeId;
+        if let Some(d) = self.inodeTable.get(&ino) {
+            if d.Points(dirent) {
+                return;
+            }
+        }
+
+        if self.inodeTable.insert(ino, dirent.Downgrade()).is_none()
+            && self.inodeTable.len() % INODE_TABLE_PRUNE_INTERVAL == 0 {
+            self.inodeTable.retain(|_, d| d.Upgrade().is_some());
+        }
+    }
+
+    pub fn LookupInode(&mut self, ino: u64) -> Option<Dirent> {
+        let d = match self.inodeTable.get(&ino) {
+            None => return None,
+            Some(d) => d.Upgrade(),
+        };
+
+        if d.is_none() {
+            self.inodeTable.remove(&ino);
+        }
+
+        return d;
+    }
 }
 
 pub trait DirentOperations {
@@ -18,9 +18,12 @@ use alloc::string::String;
 use alloc::vec::Vec;
 use alloc::string::ToString;
 use alloc::sync::Arc;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
 
 use super::super::super::super::auth::*;
 use super::super::super::super::common::*;
+use super::super::super::super::control_msg::NetStats;
 use super::super::super::super::linux_def::*;
 use super::super::super::super::linux::time::*;
 use super::super::super::super::kernel::kernel::kernel::GetKernel;
@@ -28,6 +31,10 @@ use super::super::super::super::kernel::socket::control::*;
 use super::super::super::super::kernel::socket::unix::unix::*;
 use super::super::super::tcpip::tcpip::*;
 use super::super::super::task::*;
+use super::super::super::socket::hostinet::socket::SIZEOF_SOCKADDR;
+use super::super::super::socket::hostinet::traffic::*;
+use super::super::super::socket::netlink::INTERFACES;
+use super::super::super::socket::netstack::tcp::TcpState;
 use super::super::super::socket::unix::transport::unix::*;
 use super::super::attr::*;
 use super::super::fsutil::inode::simple_file_inode::*;
@@ -35,6 +42,7 @@ use super::super::fsutil::file::readonly_file::*;
 use super::super::dirent::*;
 use super::super::file::*;
 use super::super::flags::*;
+use super::super::host::SuperOperations;
 use super::super::inode::*;
 use super::super::mount::*;
 use super::super::ramfs::dir::*;
@@ -77,9 +85,6 @@ pub fn NewNetDir(
     let netlink = "sk       Eth Pid    Groups   Rmem     Wmem     Dump     Locks     Drops     Inode\n";
     contents.insert("netlink".to_string(), NewStaticProcInode(task, msrc, &Arc::new(netlink.as_bytes().to_vec())));
 
-    let netstat = "TcpExt: SyncookiesSent SyncookiesRecv SyncookiesFailed EmbryonicRsts PruneCalled RcvPruned OfoPruned OutOfWindowIcmps LockDroppedIcmps ArpFilter TW TWRecycled TWKilled PAWSPassive PAWSActive PAWSEstab DelayedACKs DelayedACKLocked DelayedACKLost ListenOverflows ListenDrops TCPPrequeued TCPDirectCopyFromBacklog TCPDirectCopyFromPrequeue TCPPrequeueDropped TCPHPHits TCPHPHitsToUser TCPPureAcks TCPHPAcks TCPRenoRecovery TCPSackRecovery TCPSACKReneging TCPFACKReorder TCPSACKReorder TCPRenoReorder TCPTSReorder TCPFullUndo TCPPartialUndo TCPDSACKUndo TCPLossUndo TCPLostRetransmit TCPRenoFailures TCPSackFailures TCPLossFailures TCPFastRetrans TCPForwardRetrans TCPSlowStartRetrans TCPTimeouts TCPLossProbes TCPLossProbeRecovery TCPRenoRecoveryFail TCPSackRecoveryFail TCPSchedulerFailed TCPRcvCollapsed TCPDSACKOldSent TCPDSACKOfoSent TCPDSACKRecv TCPDSACKOfoRecv TCPAbortOnData TCPAbortOnClose TCPAbortOnMemory TCPAbortOnTimeout TCPAbortOnLinger TCPAbortFailed TCPMemoryPressures TCPSACKDiscard TCPDSACKIgnoredOld TCPDSACKIgnoredNoUndo TCPSpuriousRTOs TCPMD5NotFound TCPMD5Unexpected TCPMD5Failure TCPSackShifted TCPSackMerged TCPSackShiftFallback TCPBacklogDrop TCPMinTTLDrop TCPDeferAcceptDrop IPReversePathFilter TCPTimeWaitOverflow TCPReqQFullDoCookies TCPReqQFullDrop TCPRetransFail TCPRcvCoalesce TCPOFOQueue TCPOFODrop TCPOFOMerge TCPChallengeACK TCPSYNChallenge TCPFastOpenActive TCPFastOpenActiveFail TCPFastOpenPassive TCPFastOpenPassiveFail TCPFastOpenListenOverflow TCPFastOpenCookieReqd TCPSpuriousRtxHostQueues BusyPollRxPackets TCPAutoCorking TCPFromZeroWindowAdv TCPToZeroWindowAdv TCPWantZeroWindowAdv TCPSynRetrans TCPOrigDataSent TCPHystartTrainDetect TCPHystartTrainCwnd TCPHystartDelayDetect TCPHystartDelayCwnd TCPACKSkippedSynRecv TCPACKSkippedPAWS TCPACKSkippedSeq TCPACKSkippedFinWait2 TCPACKSkippedTimeWait TCPACKSkippedChallenge TCPWinProbe TCPKeepAlive TCPMTUPFail TCPMTUPSuccess\n\n";
-    contents.insert("netstat".to_string(), NewStaticProcInode(task, msrc, &Arc::new(netstat.as_bytes().to_vec())));
-
     let packet = "sk       RefCnt Type Proto  Iface R Rmem   User   Inode\n";
     contents.insert("packet".to_string(), NewStaticProcInode(task, msrc, &Arc::new(packet.as_bytes().to_vec())));
 
@@ -95,9 +100,15 @@ pub fn NewNetDir(
     let ptype = "Type Device      Function\n";
     contents.insert("ptype".to_string(), NewStaticProcInode(task, msrc, &Arc::new(ptype.as_bytes().to_vec())));
 
-    contents.insert("tcp".to_string(), NewNetTCP(task, msrc));
-    contents.insert("udp".to_string(), NewNetUDP(task, msrc));
-    contents.insert("unix".to_string(), NewNetUnix(task, msrc));
+    contents.insert("tcp".to_string(), NewNetFile(task, msrc, NetFile::TCP));
+    contents.insert("tcp6".to_string(), NewNetFile(task, msrc, NetFile::TCP6));
+    contents.insert("udp".to_string(), NewNetFile(task, msrc, NetFile::UDP));
+    contents.insert("udp6".to_string(), NewNetFile(task, msrc, NetFile::UDP6));
+    contents.insert("unix".to_string(), NewNetFile(task, msrc, NetFile::Unix));
+    contents.insert("dev".to_string(), NewNetFile(task, msrc, NetFile::Dev));
+    contents.insert("snmp".to_string(), NewNetFile(task, msrc, NetFile::Snmp));
+    contents.insert("netstat".to_string(), NewNetFile(task, msrc, NetFile::Netstat));
+    contents.insert("sockstat".to_string(), NewNetFile(task, msrc, NetFile::Sockstat));
 
     let taskDir = DirNode {
         dir: Dir::New(
@@ -123,593 +134,812 @@ pub fn NetworkToHost16(n: u16) -> u16 {
     return (low << 8) | high;
 }
 
-pub fn WriteInetAddr(addr: &SockAddr) -> String {
-    match addr {
-        SockAddr::Inet(addr) => {
-            let ipAddr = unsafe {
-                *(&addr.Addr[0] as * const _ as * const u32)
-            };
-            return format!("{:08X}:{:04X} ", ipAddr, NetworkToHost16(addr.Port))
-        }
-        SockAddr::Inet6(addr) => {
-            let ipAddr0 = unsafe {
-                *(&addr.Addr[0] as * const _ as * const u32)
-            };
-            let ipAddr1 = unsafe {
-                *(&addr.Addr[4] as * const _ as * const u32)
-            };
-            let ipAddr2 = unsafe {
-                *(&addr.Addr[8] as * const _ as * const u32)
-            };
-            let ipAddr3 = unsafe {
-                *(&addr.Addr[12] as * const _ as * const u32)
-            };
-            return format!("{:08X}{:08X}{:08X}{:08X}:{:04X} ",
-                           ipAddr0, ipAddr1, ipAddr2, ipAddr3, NetworkToHost16(addr.Port))
+// WriteInetAddr formats an address as /proc/net/{tcp,udp}[6] do, the words
+// of the ip address in the host byte order and the port. The sockets without
+// an address of the family report the any address.
+pub fn WriteInetAddr(family: i32, addr: &Option<SockAddr>) -> String {
+    let (ip, port) = match addr {
+        Some(SockAddr::Inet(addr)) => (addr.Addr.to_vec(), addr.Port),
+        Some(SockAddr::Inet6(addr)) => (addr.Addr.to_vec(), addr.Port),
+        _ => {
+            let len = if family == AFType::AF_INET6 { 16 } else { 4 };
+            (vec![0; len], 0)
         }
-        _ => panic!("WriteInetAddr doesn't support address {:x?}", addr)
+    };
+
+    let mut buf = String::new();
+    for w in ip.chunks(4) {
+        buf += &format!("{:08X}", u32::from_ne_bytes([w[0], w[1], w[2], w[3]]));
     }
+
+    buf += &format!(":{:04X} ", NetworkToHost16(port));
+    return buf;
 }
 
-pub struct NetTCP {}
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NetFile {
+    TCP,
+    TCP6,
+    UDP,
+    UDP6,
+    Unix,
+    Dev,
+    Snmp,
+    Netstat,
+    Sockstat,
+}
+
+// NetData generates a file of /proc/net from the sockets of the sandbox and
+// their traffic counters.
+pub struct NetData {
+    pub file: NetFile,
+}
+
+impl NetData {
+    pub fn GenSnapshot(&self, task: &Task) -> Vec<u8> {
+        let data = match self.file {
+            NetFile::TCP => NetTCP(task, AFType::AF_INET),
+            NetFile::TCP6 => NetTCP(task, AFType::AF_INET6),
+            NetFile::UDP => NetUDP(task, AFType::AF_INET),
+            NetFile::UDP6 => NetUDP(task, AFType::AF_INET6),
+            NetFile::Unix => NetUnix(),
+            NetFile::Dev => NetDev(task),
+            NetFile::Snmp => NetSnmp(&NET_SNMP, CurrEstab()),
+            NetFile::Netstat => {
+                let (rx, tx) = NET_TRAFFIC.Octets();
+                NetNetstat(rx, tx)
+            }
+            NetFile::Sockstat => NetSockstat(),
+        };
+
+        return data.as_bytes().to_vec();
+    }
+}
 
-impl SimpleFileTrait for NetTCP {
+impl SimpleFileTrait for NetData {
     fn GetFile(
         &self,
-        _task: &Task,
+        task: &Task,
         _dir: &Inode,
         dirent: &Dirent,
         flags: FileFlags,
     ) -> Result<File> {
-        let fops = ReadonlyFileOperations {
-            node: NetTCPReadonlyFileNode {}.into(),
-        };
-
+        let fops = NewSnapshotReadonlyFileOperations(self.GenSnapshot(task));
         let file = File::New(dirent, &flags, fops.into());
         return Ok(file);
     }
 }
 
-pub fn NewNetTCP(task: &Task, msrc: &Arc<QMutex<MountSource>>) -> Inode {
+pub fn NewNetFile(task: &Task, msrc: &Arc<QMutex<MountSource>>, file: NetFile) -> Inode {
     let node = SimpleFileInode::New(
         task,
         &ROOT_OWNER,
-        &FilePermissions {
-            User: PermMask {
-                read: true,
-                write: false,
-                execute: false,
-            },
-            Group: PermMask {
-                read: true,
-                write: false,
-                execute: false,
-            },
-            Other: PermMask {
-                read: true,
-                write: false,
-                execute: false,
-            },
-            ..Default::default()
-        },
-        FSMagic::ANON_INODE_FS_MAGIC,
+        &FilePermissions::FromMode(FileMode(0o444)),
+        FSMagic::PROC_SUPER_MAGIC,
         false,
-        NetTCP {}.into(),
+        NetData { file: file }.into(),
     );
 
     return NewProcInode(node.into(), msrc, InodeType::SpecialFile, None);
 }
 
-#[derive(Clone)]
-pub struct NetTCPReadonlyFileNode {}
-
-impl ReadonlyFileNodeTrait for NetTCPReadonlyFileNode {
-    fn ReadAt(
-        &self,
-        task: &Task,
-        _f: &File,
-        dsts: &mut [IoVec],
-        offset: i64,
-        _blocking: bool,
-    ) -> Result<i64> {
-        if offset < 0 {
-            return Err(Error::SysError(SysErr::EINVAL));
+// InetSockets returns the sockets of the sandbox of family and stype.
+fn InetSockets(family: i32, stype: i32) -> Vec<File> {
+    let mut socks = Vec::new();
+    for (_, file) in GetKernel().sockets.ListSockets() {
+        if file.FileOp.FopsType() != FileOpsType::SocketOperations {
+            continue;
         }
 
-        let header = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode                                                     \n";
-        let bytes = GetData(task, AFType::AF_INET, header)?;
-        if offset as usize > bytes.len() {
-            return Ok(0);
+        let (fa, st, _protocol) = file.FileOp.Type();
+        if fa == family && st & SocketType::SOCK_TYPE_MASK == stype {
+            socks.push(file);
         }
-
-        let n = task.CopyDataOutToIovs(&bytes[offset as usize..], dsts, true)?;
-
-        return Ok(n as i64);
     }
+
+    return socks;
 }
 
-pub fn GetData(task: &Task, fa: i32, header: &str) -> Result<Vec<u8>> {
-    let kernel = GetKernel();
-    let sockets = kernel.sockets.ListSockets();
+fn SockName(task: &Task, file: &File, family: i32, peer: bool) -> Option<SockAddr> {
+    let mut buf = [0; SIZEOF_SOCKADDR];
+    let len = if peer {
+        file.FileOp.GetPeerName(task, &mut buf).ok()?
+    } else {
+        file.FileOp.GetSockName(task, &mut buf).ok()?
+    };
 
-    let mut buf = header.to_string();
-    for (id, file) in sockets {
-        let fopsType = file.FileOp.FopsType();
-        //&& fopsType != FileOpsType::UnixSocketOperations
-        if fopsType != FileOpsType::SocketOperations {
-            continue
-        }
+    let len = core::cmp::min(len as usize, buf.len());
+    return GetAddr(family as i16, &buf[..len]).ok();
+}
 
-        let sockops = file.FileOp.clone();
-        /*let sockops = fileops
-            .as_any()
-            .downcast_ref::<SocketOperations>()
-            .expect("SocketOperations convert fail")
-            .clone();
+// SocketUID returns the owner of the socket in the user namespace of task.
+// The inode of a host socket is owned by the owner of the host fd, the
+// creator of the socket is the mounter of its mount source.
+fn SocketUID(task: &Task, file: &File) -> u32 {
+    let inode = file.Dirent.Inode();
+    let mounter = {
+        let msrc = inode.lock().MountSource.clone();
+        let mops = msrc.lock().MountSourceOperations.clone();
+        let mops = mops.lock();
+        mops.as_any()
+            .downcast_ref::<SuperOperations>()
+            .map(|s| s.mounter.UID)
+    };
 
-        let family = sockops.family;
-        let stype = sockops.stype;*/
+    let uid = match mounter {
+        Some(uid) => uid,
+        None => match inode.UnstableAttr(task) {
+            Err(e) => {
+                error!("Failed to retrieve unstable attr for socket file: {:?}", e);
+                return 0;
+            }
+            Ok(uattr) => uattr.Owner.UID,
+        },
+    };
 
-        let (family, stype, _protocol) = sockops.Type();
+    let userns = task.Creds().lock().UserNamespace.clone();
+    return uid.In(&userns).OrOverflow().0;
+}
 
-        if fa != family || stype != SockType::SOCK_STREAM {
-            continue;
-        }
+// SocketEntry is a socket of /proc/net/{tcp,udp}[6].
+pub struct SocketEntry {
+    pub local: Option<SockAddr>,
+    pub remote: Option<SockAddr>,
+    pub state: u32,
+    pub txQueue: usize,
+    pub rxQueue: usize,
+    pub uid: u32,
+    // the inode of the /proc/[pid]/fd link
+    pub inode: u64,
+    pub refs: usize,
+}
 
-        // Linux's documentation for the fields below can be found at
-        // https://www.kernel.org/doc/Documentation/networking/proc_net_tcp.txt.
-        // For Linux's implementation, see net/ipv4/tcp_ipv4.c:get_tcp4_sock().
-        // Note that the header doesn't contain labels for all the fields.
-
-        // Field: sl; entry number.
-        buf += &format!("{:>4}: ", id);
-
-        let mut sockBuf = [0; 256];
-
-        // Field: local_adddress.
-        let _ = sockops.GetSockName(task, &mut sockBuf)?;
-        let addr = GetAddr(AFType::AF_INET as _, &sockBuf)?;
-        buf += &WriteInetAddr(&addr);
-
-        // Field: rem_address.
-        let addr = {
-            match sockops.GetPeerName(task, &mut sockBuf) {
-                Err(_) => {
-                    let inetAddr = SockAddrInet {
-                        Family: family as _,
-                        ..Default::default()
-                    };
-                    SockAddr::Inet(inetAddr)
-                }
-                Ok(_) => {
-                    GetAddr(AFType::AF_INET as _, &sockBuf)?
-                }
-            }
+impl SocketEntry {
+    // New gets the entry of a socket, the ones without a tcp state are
+    // closed.
+    fn New(task: &Task, file: &File, family: i32) -> Self {
+        let (tx, rx) = file.FileOp.QueueSize();
+        return Self {
+            local: SockName(task, file, family, false),
+            remote: SockName(task, file, family, true),
+            state: match file.FileOp.State() {
+                0 => TcpState::Closed.LinuxState(),
+                s => s,
+            },
+            txQueue: tx,
+            rxQueue: rx,
+            uid: SocketUID(task, file),
+            inode: file.Dirent.Inode().StableAttr().InodeId,
+            // Don't count the ref we obtain while deferencing the weakref to
+            // this socket.
+            refs: file.ReadRefs() - 1,
         };
+    }
+}
 
-        buf += &WriteInetAddr(&addr);
+// TCPHeader is the first line of /proc/net/tcp and /proc/net/tcp6.
+pub fn TCPHeader(family: i32) -> String {
+    if family == AFType::AF_INET {
+        return format!("{:<149}\n", "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode");
+    }
 
-        // Field: state; socket state.
-        buf += &format!("{:02X} ", sockops.State());
+    return "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n".to_string();
+}
 
-        // Field: tx_queue, rx_queue; number of packets in the transmit and
-        // receive queue. Unimplemented.
-        buf += &format!("{:08X}:{:08X} ", 0, 0);
+// TCPLine formats the socket sl of /proc/net/tcp and /proc/net/tcp6. Linux's
+// documentation for the fields can be found at
+// https://www.kernel.org/doc/Documentation/networking/proc_net_tcp.txt.
+pub fn TCPLine(family: i32, sl: usize, e: &SocketEntry) -> String {
+    // Field: sl; entry number.
+    let mut buf = format!("{:>4}: ", sl);
 
-        // Field: tr, tm->when; timer active state and number of jiffies
-        // until timer expires. Unimplemented.
-        buf += &format!("{:02X}:{:08X} ", 0, 0);
+    // Field: local_adddress, rem_address.
+    buf += &WriteInetAddr(family, &e.local);
+    buf += &WriteInetAddr(family, &e.remote);
 
-        // Field: retrnsmt; number of unrecovered RTO timeouts.
-        // Unimplemented.
-        buf += &format!("{:08X} ", 0);
+    // Field: state; socket state.
+    buf += &format!("{:02X} ", e.state);
 
-        // Field: uid.
-        match file.Dirent.Inode().UnstableAttr(task) {
-            Err(e) => {
-                error!("Failed to retrieve unstable attr for socket file: {:?}", e);
-                buf += &format!("{:<5} ", 0);
-            }
-            Ok(uattr) => {
-                let creds = task.Creds();
-                let usernamespace = creds.lock().UserNamespace.clone();
-                buf += &format!("{:<5} ", uattr.Owner.UID.In(&usernamespace).OrOverflow().0);
-            }
-        }
+    // Field: tx_queue, rx_queue; bytes in the transmit and receive queue.
+    buf += &format!("{:08X}:{:08X} ", e.txQueue, e.rxQueue);
 
-        // Field: timeout; number of unanswered 0-window probes.
-        // Unimplemented.
-        buf += &format!("{:>8} ", 0);
+    // Field: tr, tm->when; timer active state and number of jiffies
+    // until timer expires. Unimplemented.
+    buf += &format!("{:02X}:{:08X} ", 0, 0);
 
-        // Field: inode.
-        let inodeId = file.Dirent.Inode().StableAttr().InodeId;
-        buf += &format!("{:>8} ", inodeId);
+    // Field: retrnsmt; number of unrecovered RTO timeouts.
+    // Unimplemented.
+    buf += &format!("{:08X} ", 0);
 
-        // Field: ref; reference count on the socket inode. Don't count the ref
-        // we obtain while deferencing the weakref to this socket.
-        buf += &format!("{ } ", file.ReadRefs() - 1);
+    // Field: uid.
+    buf += &format!("{:>5} ", e.uid);
 
-        // Field: Socket struct address. Redacted due to the same reason as
-        // the 'Num' field in /proc/net/unix, see netUnix.ReadSeqFileData.
-        buf += &format!("{:>16} ", 0);
+    // Field: timeout; number of unanswered 0-window probes.
+    // Unimplemented.
+    buf += &format!("{:>8} ", 0);
 
-        // Field: retransmit timeout. Unimplemented.
-        buf += &format!("{} ", 0);
+    // Field: inode.
+    buf += &format!("{} ", e.inode);
 
-        // Field: predicted tick of soft clock (delayed ACK control data).
-        // Unimplemented.
-        buf += &format!("{} ", 0);
+    // Field: ref; reference count on the socket inode.
+    buf += &format!("{} ", e.refs);
 
-        // Field: (ack.quick<<1)|ack.pingpong, Unimplemented.
-        buf += &format!("{} ", 0);
+    // Field: Socket struct address. Redacted due to the same reason as
+    // the 'Num' field in /proc/net/unix.
+    buf += &format!("{:016X} ", 0);
 
-        // Field: sending congestion window, Unimplemented.
-        buf += &format!("{} ", 0);
+    // Field: retransmit timeout, predicted tick of soft clock,
+    // (ack.quick<<1)|ack.pingpong and sending congestion window.
+    // Unimplemented.
+    buf += &format!("{} {} {} {} ", 0, 0, 0, 0);
 
-        // Field: Slow start size threshold, -1 if threshold >= 0xFFFF.
-        // Unimplemented, report as large threshold.
-        buf += &format!("{} ", -1);
+    // Field: Slow start size threshold, -1 if threshold >= 0xFFFF.
+    // Unimplemented, report as large threshold.
+    buf += &format!("{}\n", -1);
+    return buf;
+}
 
-        buf += &format!("\n");
+// NetTCP generates /proc/net/tcp and /proc/net/tcp6.
+fn NetTCP(task: &Task, family: i32) -> String {
+    let mut buf = TCPHeader(family);
+    for (sl, file) in InetSockets(family, SockType::SOCK_STREAM).iter().enumerate() {
+        buf += &TCPLine(family, sl, &SocketEntry::New(task, file, family));
     }
 
-    return Ok(buf.as_bytes().to_vec());
+    return buf;
 }
 
-pub struct NetUDP {}
-
-impl SimpleFileTrait for NetUDP {
-    fn GetFile(
-        &self,
-        _task: &Task,
-        _dir: &Inode,
-        dirent: &Dirent,
-        flags: FileFlags,
-    ) -> Result<File> {
-        let fops = ReadonlyFileOperations {
-            node: NetUDPReadonlyFileNode {}.into(),
-        };
-
-        let file = File::New(dirent, &flags, fops.into());
-        return Ok(file);
+// UDPHeader is the first line of /proc/net/udp and /proc/net/udp6.
+pub fn UDPHeader(family: i32) -> String {
+    if family == AFType::AF_INET {
+        return format!("{:<127}\n", "   sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops");
     }
-}
-
-pub fn NewNetUDP(task: &Task, msrc: &Arc<QMutex<MountSource>>) -> Inode {
-    let node = SimpleFileInode::New(
-        task,
-        &ROOT_OWNER,
-        &FilePermissions {
-            User: PermMask {
-                read: true,
-                write: false,
-                execute: false,
-            },
-            Group: PermMask {
-                read: true,
-                write: false,
-                execute: false,
-            },
-            Other: PermMask {
-                read: true,
-                write: false,
-                execute: false,
-            },
-            ..Default::default()
-        },
-        FSMagic::ANON_INODE_FS_MAGIC,
-        false,
-        NetUDP {}.into(),
-    );
 
-    return NewProcInode(node.into(), msrc, InodeType::SpecialFile, None);
+    return "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops\n".to_string();
 }
 
-#[derive(Clone)]
-pub struct NetUDPReadonlyFileNode {}
-
-impl NetUDPReadonlyFileNode {
-    pub fn GetData(&self, task: &Task, header: &str) -> Result<Vec<u8>> {
-        let kernel = GetKernel();
-        let sockets = kernel.sockets.ListSockets();
-
-        let mut buf = header.to_string();
-        for (id, file) in sockets {
-            let fopsType = file.FileOp.FopsType();
-            //&& fopsType != FileOpsType::UnixSocketOperations
-            if fopsType != FileOpsType::SocketOperations {
-                continue
-            }
-
-            let sockops = file.FileOp.clone();
-            /*let sockops = fileops
-                .as_any()
-                .downcast_ref::<SocketOperations>()
-                .expect("SocketOperations convert fail")
-                .clone();
-
-            let family = sockops.family;
-            let stype = sockops.stype;*/
-
-            let (family, stype, _protocol) = sockops.Type();
+// UDPLine formats the socket sl of /proc/net/udp and /proc/net/udp6, the
+// fields are the ones of /proc/net/tcp but the last which is the number of
+// dropped datagrams. A udp socket is established when it is connected.
+pub fn UDPLine(family: i32, sl: usize, e: &SocketEntry) -> String {
+    let mut buf = format!("{:>5}: ", sl);
 
-            if family != AFType::AF_INET || stype != SockType::SOCK_STREAM {
-                continue;
-            }
-
-            // Linux's documentation for the fields below can be found at
-            // https://www.kernel.org/doc/Documentation/networking/proc_net_tcp.txt.
-            // For Linux's implementation, see net/ipv4/tcp_ipv4.c:get_tcp4_sock().
-            // Note that the header doesn't contain labels for all the fields.
-
-            // Field: sl; entry number.
-            buf += &format!("{:<5}: ", id);
+    buf += &WriteInetAddr(family, &e.local);
+    buf += &WriteInetAddr(family, &e.remote);
 
-            let mut sockBuf = [0; 256];
-
-            // Field: local_adddress.
-            let _ = sockops.GetSockName(task, &mut sockBuf)?;
-            let addr = GetAddr(AFType::AF_INET as _, &sockBuf)?;
-            buf += &WriteInetAddr(&addr);
-
-            // Field: rem_address.
-            let _ = sockops.GetPeerName(task, &mut sockBuf)?;
-            let addr = GetAddr(AFType::AF_INET as _, &sockBuf)?;
-            buf += &WriteInetAddr(&addr);
+    let state = if e.remote.is_some() {
+        TcpState::Established.LinuxState()
+    } else {
+        TcpState::Closed.LinuxState()
+    };
+    buf += &format!("{:02X} ", state);
+
+    buf += &format!("{:08X}:{:08X} ", e.txQueue, e.rxQueue);
+    buf += &format!("{:02X}:{:08X} ", 0, 0);
+    buf += &format!("{:08X} ", 0);
+    buf += &format!("{:>5} ", e.uid);
+    buf += &format!("{:>8} ", 0);
+    buf += &format!("{} ", e.inode);
+    buf += &format!("{} ", e.refs);
+    buf += &format!("{:016X} ", 0);
+
+    // Field: drops; number of dropped packets. Unimplemented.
+    buf += &format!("{}\n", 0);
+    return buf;
+}
 
-            // Field: state; socket state.
-            buf += &format!("{:02X} ", sockops.State());
+// NetUDP generates /proc/net/udp and /proc/net/udp6.
+fn NetUDP(task: &Task, family: i32) -> String {
+    let mut buf = UDPHeader(family);
+    for (sl, file) in InetSockets(family, SockType::SOCK_DGRAM).iter().enumerate() {
+        buf += &UDPLine(family, sl, &SocketEntry::New(task, file, family));
+    }
 
-            // Field: tx_queue, rx_queue; number of packets in the transmit and
-            // receive queue. Unimplemented.
-            buf += &format!("{:08X}:{:08X} ", 0, 0);
+    return buf;
+}
 
-            // Field: tr, tm->when; timer active state and number of jiffies
-            // until timer expires. Unimplemented.
-            buf += &format!("{:02X}:{:08X} ", 0, 0);
+fn NetUnix() -> String {
+    let mut buf = "Num       RefCount Protocol Flags    Type St Inode Path\n".to_string();
+    for (_, file) in GetKernel().sockets.ListSockets() {
+        if file.FileOp.FopsType() != FileOpsType::UnixSocketOperations {
+            continue;
+        }
 
-            // Field: retrnsmt; number of unrecovered RTO timeouts.
-            // Unimplemented.
-            buf += &format!("{:08X} ", 0);
+        let sockops = file
+            .FileOp
+            .as_any()
+            .downcast_ref::<UnixSocketOperations>()
+            .expect("SocketOperations convert fail")
+            .clone();
 
-            // Field: uid.
-            match file.Dirent.Inode().UnstableAttr(task) {
-                Err(e) => {
-                    error!("Failed to retrieve unstable attr for socket file: {:?}", e);
-                    buf += &format!("{:<5} ", 0);
-                }
-                Ok(uattr) => {
-                    let creds = task.Creds();
-                    let usernamespace = creds.lock().UserNamespace.clone();
-                    buf += &format!("{:<5} ", uattr.Owner.UID.In(&usernamespace).OrOverflow().0);
+        let addr = match sockops.ep.GetLocalAddress() {
+            Err(e) => {
+                error!("NetUnix Failed to retrieve socket name from {:?}", e);
+                SockAddrUnix {
+                    Family: AFType::AF_UNIX as _,
+                    Path: "<unknown>".to_string(),
                 }
             }
+            Ok(addr) => addr,
+        };
 
-            // Field: timeout; number of unanswered 0-window probes.
-            // Unimplemented.
-            buf += &format!("{:>8} ", 0);
-
-            // Field: inode.
-            let inodeId = file.Dirent.Inode().StableAttr().InodeId;
-            buf += &format!("{:>8} ", inodeId);
-
-            // Field: ref; reference count on the socket inode. Don't count the ref
-            // we obtain while deferencing the weakref to this socket.
-            buf += &format!("{ } ", file.ReadRefs() - 1);
-
-            // Field: Socket struct address. Redacted due to the same reason as
-            // the 'Num' field in /proc/net/unix, see netUnix.ReadSeqFileData.
-            buf += &format!("{:>16} ", 0);
+        let mut sockFlags = 0;
+        match &sockops.ep {
+            BoundEndpoint::Connected(sock) => {
+                if sock.Listening() {
+                    // For unix domain sockets, linux reports a single flag
+                    // value if the socket is listening, of __SO_ACCEPTCON.
+                    sockFlags = SO_ACCEPTCON;
+                }
+            }
+            _ => (),
+        }
 
-            // Field: drops; number of dropped packets. Unimplemented.
-            buf += &format!("{} ", 0);
+        let inodeId = file.Dirent.Inode().StableAttr().InodeId;
 
-            buf += &format!("\n");
+        // In the socket entry below, the value for the 'Num' field requires
+        // some consideration. Linux prints the address to the struct
+        // unix_sock representing a socket in the kernel, but may redact the
+        // value for unprivileged users depending on the kptr_restrict
+        // sysctl.
+        //
+        // One use for this field is to allow a privileged user to
+        // introspect into the kernel memory to determine information about
+        // a socket not available through procfs, such as the socket's peer.
+        //
+        // Returning a pointer to our internal structures would be
+        // pointless, as it wouldn't match the memory layout for struct
+        // unix_sock, making introspection difficult. For now, we always
+        // redact this pointer.
+        buf += &format!(
+            "{:016X}: {:08X} {:08X} {:08X} {:04X} {:02X} {:>5}",
+            0,
+            file.ReadRefs() - 1,
+            0,
+            sockFlags,
+            sockops.ep.Type(),
+            sockops.State(),
+            inodeId
+        );
+
+        if addr.Path.len() != 0 {
+            if addr.Path.as_bytes()[0] == 0 {
+                buf += &format!(" @{}", &addr.Path[1..]);
+            } else {
+                buf += &format!(" {}", addr.Path);
+            }
         }
 
-        return Ok(buf.as_bytes().to_vec());
+        buf += "\n";
     }
+
+    return buf;
 }
 
-impl ReadonlyFileNodeTrait for NetUDPReadonlyFileNode {
-    fn ReadAt(
-        &self,
-        task: &Task,
-        _f: &File,
-        dsts: &mut [IoVec],
-        offset: i64,
-        _blocking: bool,
-    ) -> Result<i64> {
-        if offset < 0 {
-            return Err(Error::SysError(SysErr::EINVAL));
-        }
+// NetTable formats a table of /proc/net/snmp and /proc/net/netstat, a line
+// of the field names and a line of the values, both after the prefix.
+pub fn NetTable(prefix: &str, fields: &[(&str, i64)]) -> String {
+    let mut names = format!("{}:", prefix);
+    let mut values = format!("{}:", prefix);
+    for (name, value) in fields {
+        names += &format!(" {}", name);
+        values += &format!(" {}", value);
+    }
 
-        let header = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops             \n";
-        let bytes = self.GetData(task, header)?;
-        if offset as usize > bytes.len() {
-            return Ok(0);
-        }
+    return names + "\n" + &values + "\n";
+}
+
+// NetSnmp generates /proc/net/snmp from the counters of the sockets of the
+// sandbox. The ip packets are the tcp segments and the udp datagrams, the
+// sandbox neither forwards nor sends icmp.
+pub fn NetSnmp(snmp: &SnmpCounters, currEstab: usize) -> String {
+    let load = |c: &AtomicU64| c.load(Ordering::Relaxed) as i64;
+    let (tcp, udp) = (&snmp.tcp, &snmp.udp);
+    let inPackets = load(&tcp.inPackets) + load(&udp.inPackets);
+    let outPackets = load(&tcp.outPackets) + load(&udp.outPackets);
+
+    let mut buf = NetTable(
+        "Ip",
+        &[
+            ("Forwarding", 2),
+            ("DefaultTTL", 64),
+            ("InReceives", inPackets),
+            ("InHdrErrors", 0),
+            ("InAddrErrors", 0),
+            ("ForwDatagrams", 0),
+            ("InUnknownProtos", 0),
+            ("InDiscards", 0),
+            ("InDelivers", inPackets),
+            ("OutRequests", outPackets),
+            ("OutDiscards", 0),
+            ("OutNoRoutes", 0),
+            ("ReasmTimeout", 0),
+            ("ReasmReqds", 0),
+            ("ReasmOKs", 0),
+            ("ReasmFails", 0),
+            ("FragOKs", 0),
+            ("FragFails", 0),
+            ("FragCreates", 0),
+        ],
+    );
 
-        let n = task.CopyDataOutToIovs(&bytes[offset as usize..], dsts, true)?;
+    let icmp: Vec<(&str, i64)> = ICMP_FIELDS.iter().map(|f| (*f, 0)).collect();
+    buf += &NetTable("Icmp", &icmp);
+
+    buf += &NetTable(
+        "Tcp",
+        &[
+            ("RtoAlgorithm", 1),
+            ("RtoMin", 200),
+            ("RtoMax", 120000),
+            ("MaxConn", -1),
+            ("ActiveOpens", load(&tcp.activeOpens)),
+            ("PassiveOpens", load(&tcp.passiveOpens)),
+            ("AttemptFails", load(&tcp.attemptFails)),
+            ("EstabResets", 0),
+            ("CurrEstab", currEstab as i64),
+            ("InSegs", load(&tcp.inPackets)),
+            ("OutSegs", load(&tcp.outPackets)),
+            ("RetransSegs", 0),
+            ("InErrs", load(&tcp.inErrors)),
+            ("OutRsts", 0),
+            ("InCsumErrors", 0),
+        ],
+    );
 
-        return Ok(n as i64);
-    }
+    let udpFields = |inDatagrams, inErrors, outDatagrams, sndbufErrors| {
+        [
+            ("InDatagrams", inDatagrams),
+            ("NoPorts", 0),
+            ("InErrors", inErrors),
+            ("OutDatagrams", outDatagrams),
+            ("RcvbufErrors", 0),
+            ("SndbufErrors", sndbufErrors),
+            ("InCsumErrors", 0),
+            ("IgnoredMulti", 0),
+        ]
+    };
+    buf += &NetTable(
+        "Udp",
+        &udpFields(
+            load(&udp.inPackets),
+            load(&udp.inErrors),
+            load(&udp.outPackets),
+            load(&udp.outErrors),
+        ),
+    );
+    buf += &NetTable("UdpLite", &udpFields(0, 0, 0, 0));
+    return buf;
 }
 
-pub struct NetUnix {}
-
-impl SimpleFileTrait for NetUnix {
-    fn GetFile(
-        &self,
-        _task: &Task,
-        _dir: &Inode,
-        dirent: &Dirent,
-        flags: FileFlags,
-    ) -> Result<File> {
-        let fops = ReadonlyFileOperations {
-            node: NetUnixReadonlyFileNode {}.into(),
-        };
+const ICMP_FIELDS: [&str; 27] = [
+    "InMsgs",
+    "InErrors",
+    "InCsumErrors",
+    "InDestUnreachs",
+    "InTimeExcds",
+    "InParmProbs",
+    "InSrcQuenchs",
+    "InRedirects",
+    "InEchos",
+    "InEchoReps",
+    "InTimestamps",
+    "InTimestampReps",
+    "InAddrMasks",
+    "InAddrMaskReps",
+    "OutMsgs",
+    "OutErrors",
+    "OutDestUnreachs",
+    "OutTimeExcds",
+    "OutParmProbs",
+    "OutSrcQuenchs",
+    "OutRedirects",
+    "OutEchos",
+    "OutEchoReps",
+    "OutTimestamps",
+    "OutTimestampReps",
+    "OutAddrMasks",
+    "OutAddrMaskReps",
+];
+
+const TCPEXT_FIELDS: [&str; 20] = [
+    "SyncookiesSent",
+    "SyncookiesRecv",
+    "SyncookiesFailed",
+    "EmbryonicRsts",
+    "PruneCalled",
+    "RcvPruned",
+    "OfoPruned",
+    "OutOfWindowIcmps",
+    "LockDroppedIcmps",
+    "ArpFilter",
+    "TW",
+    "TWRecycled",
+    "TWKilled",
+    "PAWSActive",
+    "PAWSEstab",
+    "DelayedACKs",
+    "DelayedACKLocked",
+    "DelayedACKLost",
+    "ListenOverflows",
+    "ListenDrops",
+];
+
+// NetNetstat generates /proc/net/netstat. The sandbox doesn't keep the tcp
+// extension counters, the ip octets are the bytes the sockets of the sandbox
+// received and sent.
+pub fn NetNetstat(inOctets: u64, outOctets: u64) -> String {
+    let tcpExt: Vec<(&str, i64)> = TCPEXT_FIELDS.iter().map(|f| (*f, 0)).collect();
+    let mut buf = NetTable("TcpExt", &tcpExt);
+    buf += &NetTable(
+        "IpExt",
+        &[
+            ("InNoRoutes", 0),
+            ("InTruncatedPkts", 0),
+            ("InMcastPkts", 0),
+            ("OutMcastPkts", 0),
+            ("InBcastPkts", 0),
+            ("OutBcastPkts", 0),
+            ("InOctets", inOctets as i64),
+            ("OutOctets", outOctets as i64),
+            ("InMcastOctets", 0),
+            ("OutMcastOctets", 0),
+            ("InBcastOctets", 0),
+            ("OutBcastOctets", 0),
+            ("InCsumErrors", 0),
+            ("InNoECTPkts", 0),
+            ("InECT1Pkts", 0),
+            ("InECT0Pkts", 0),
+            ("InCEPkts", 0),
+        ],
+    );
+    return buf;
+}
 
-        let file = File::New(dirent, &flags, fops.into());
-        return Ok(file);
+// CurrEstab counts the established tcp sockets of the sandbox.
+fn CurrEstab() -> usize {
+    let established = TcpState::Established.LinuxState();
+    let mut count = 0;
+    for family in [AFType::AF_INET, AFType::AF_INET6].iter() {
+        count += InetSockets(*family, SockType::SOCK_STREAM)
+            .iter()
+            .filter(|f| f.FileOp.State() == established)
+            .count();
     }
+
+    return count;
 }
 
-pub fn NewNetUnix(task: &Task, msrc: &Arc<QMutex<MountSource>>) -> Inode {
-    let node = SimpleFileInode::New(
-        task,
-        &ROOT_OWNER,
-        &FilePermissions {
-            User: PermMask {
-                read: true,
-                write: false,
-                execute: false,
-            },
-            Group: PermMask {
-                read: true,
-                write: false,
-                execute: false,
-            },
-            Other: PermMask {
-                read: true,
-                write: false,
-                execute: false,
-            },
-            ..Default::default()
-        },
-        FSMagic::ANON_INODE_FS_MAGIC,
-        false,
-        NetUnix {}.into(),
-    );
+// NetDev generates /proc/net/dev for the interfaces of the sandbox with the
+// traffic counters of the container of the task. The traffic in the guest
+// memory is on the loopback interface, the traffic through the host is on the
+// first other interface.
+fn NetDev(task: &Task) -> String {
+    let mut buf = "Inter-|   Receive                                                |  Transmit\n".to_string();
+    buf += " face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed\n";
+
+    let traffic = NET_TRAFFIC.ForTask(task);
+    let stats = traffic.Stats();
+    let intfs = INTERFACES.read();
+    for intf in &intfs.interfaces {
+        let s = stats
+            .iter()
+            .find(|st| st.Interface == intf.name)
+            .cloned()
+            .unwrap_or_default();
+
+        buf += &format!(
+            "{:>6}: {:>7} {:>7} {:>4} {:>4} {:>4} {:>5} {:>10} {:>9} {:>8} {:>7} {:>4} {:>4} {:>4} {:>5} {:>7} {:>10}\n",
+            intf.name,
+            s.RxBytes,
+            s.RxPackets,
+            s.RxErrors,
+            s.RxDropped,
+            0,
+            0,
+            0,
+            0,
+            s.TxBytes,
+            s.TxPackets,
+            s.TxErrors,
+            s.TxDropped,
+            0,
+            0,
+            0,
+            0
+        );
+    }
 
-    return NewProcInode(node.into(), msrc, InodeType::SpecialFile, None);
+    return buf;
 }
 
-#[derive(Clone)]
-pub struct NetUnixReadonlyFileNode {}
-
-impl NetUnixReadonlyFileNode {
-    pub fn GetData(&self, _task: &Task, header: &str) -> Result<Vec<u8>> {
-        let kernel = GetKernel();
-        let sockets = kernel.sockets.ListSockets();
-
-        let mut buf = header.to_string();
-        for (id, file) in sockets {
-            let fopsType = file.FileOp.FopsType();
-            if fopsType != FileOpsType::UnixSocketOperations {
-                continue
-            }
+fn NetSockstat() -> String {
+    let sockets = GetKernel().sockets.ListSockets();
+    let count = |family: i32, stype: i32| {
+        sockets
+            .iter()
+            .filter(|(_, f)| {
+                let (fa, st, _protocol) = f.FileOp.Type();
+                fa == family && st & SocketType::SOCK_TYPE_MASK == stype
+            })
+            .count()
+    };
 
-            let fops = file.FileOp.clone();
-            let (family, _stype, _protocol) = fops.Type();
+    let tcp = count(AFType::AF_INET, SockType::SOCK_STREAM);
+    let tcp6 = count(AFType::AF_INET6, SockType::SOCK_STREAM);
 
-            if family != AFType::AF_UNIX {
-                continue;
-            }
+    let mut buf = format!("sockets: used {}\n", sockets.len());
+    buf += &format!("TCP: inuse {} orphan 0 tw 0 alloc {} mem 0\n", tcp, tcp + tcp6);
+    buf += &format!("UDP: inuse {} mem 0\n", count(AFType::AF_INET, SockType::SOCK_DGRAM));
+    buf += "UDPLITE: inuse 0\n";
+    buf += &format!("RAW: inuse {}\n", count(AFType::AF_INET, SockType::SOCK_RAW));
+    buf += "FRAG: inuse 0 memory 0\n";
+    return buf;
+}
 
-            let sockops = fops
-                .as_any()
-                .downcast_ref::<UnixSocketOperations>()
-                .expect("SocketOperations convert fail")
-                .clone();
-
-            // Linux's documentation for the fields below can be found at
-            // https://www.kernel.org/doc/Documentation/networking/proc_net_tcp.txt.
-            // For Linux's implementation, see net/ipv4/tcp_ipv4.c:get_tcp4_sock().
-            // Note that the header doesn't contain labels for all the fields.
-
-            // Field: sl; entry number.
-            buf += &format!("{:<5}: ", id);
-
-            // Field: local_adddress.
-            let addr = match sockops.ep.GetLocalAddress() {
-                Err(e) => {
-                    error!("NetUnixReadonlyFileNode Failed to retrieve socket name from {:?}", e);
-                    SockAddrUnix {
-                        Family: AFType::AF_UNIX as _,
-                        Path: "<unknown>".to_string(),
-                    }
-                }
-                Ok(addr) => addr
-            };
-
-            let mut sockFlags = 0;
-            match &sockops.ep {
-                BoundEndpoint::Connected(sock) => {
-                    if sock.Listening() {
-                        // For unix domain sockets, linux reports a single flag
-                        // value if the socket is listening, of __SO_ACCEPTCON.
-                        sockFlags = SO_ACCEPTCON;
-                    }
-                }
-                _ => ()
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn Inet(addr: [u8; 4], port: u16) -> Option<SockAddr> {
+        return Some(SockAddr::Inet(SockAddrInet {
+            Family: AFType::AF_INET as u16,
+            Port: port.to_be(),
+            Addr: addr,
+            Zero: [0; 8],
+        }));
+    }
 
-            let inodeId = file.Dirent.Inode().StableAttr().InodeId;
-
-            // In the socket entry below, the value for the 'Num' field requires
-            // some consideration. Linux prints the address to the struct
-            // unix_sock representing a socket in the kernel, but may redact the
-            // value for unprivileged users depending on the kptr_restrict
-            // sysctl.
-            //
-            // One use for this field is to allow a privileged user to
-            // introspect into the kernel memory to determine information about
-            // a socket not available through procfs, such as the socket's peer.
-            //
-            // On gvisor, returning a pointer to our internal structures would
-            // be pointless, as it wouldn't match the memory layout for struct
-            // unix_sock, making introspection difficult. We could populate a
-            // struct unix_sock with the appropriate data, but even that
-            // requires consideration for which kernel version to emulate, as
-            // the definition of this struct changes over time.
-            //
-            // For now, we always redact this pointer.
-            buf += &format!("{:010}: {:08X} {:08X} {:08X} {:04X} {:02X} {:>5}",
-                            0,
-                            file.ReadRefs() - 1,
-                            0,
-                            sockFlags,
-                            sockops.ep.Type(),
-                            sockops.State(),
-                            inodeId);
-
-            if addr.Path.len() != 0 {
-                if addr.Path.as_bytes()[0] == 0 {
-                    buf += &format!(" @{}", &addr.Path[1..]);
-                } else {
-                    buf += &format!(" {}", addr.Path);
-                }
-            }
+    fn Entry(remote: Option<SockAddr>) -> SocketEntry {
+        return SocketEntry {
+            local: Inet([10, 0, 0, 5], 80),
+            remote: remote,
+            state: TcpState::Established.LinuxState(),
+            txQueue: 0x10,
+            rxQueue: 0x20,
+            uid: 1000,
+            inode: 12345,
+            refs: 1,
+        };
+    }
 
-            buf += &format!("\n");
+    // Tables returns the prefix, the names and the values of the tables.
+    fn Tables(buf: &str) -> Vec<(String, Vec<String>, Vec<String>)> {
+        let lines: Vec<&str> = buf.lines().collect();
+        assert_eq!(lines.len() % 2, 0);
+        let mut tables = Vec::new();
+        for pair in lines.chunks(2) {
+            let names: Vec<&str> = pair[0].split(' ').collect();
+            let values: Vec<&str> = pair[1].split(' ').collect();
+            assert_eq!(names[0], values[0]);
+            assert_eq!(names.len(), values.len());
+            tables.push((
+                names[0].trim_end_matches(':').to_string(),
+                names[1..].iter().map(|n| n.to_string()).collect(),
+                values[1..].iter().map(|v| v.to_string()).collect(),
+            ));
         }
 
-        return Ok(buf.as_bytes().to_vec());
+        return tables;
     }
-}
 
-impl ReadonlyFileNodeTrait for NetUnixReadonlyFileNode {
-    fn ReadAt(
-        &self,
-        task: &Task,
-        _f: &File,
-        dsts: &mut [IoVec],
-        offset: i64,
-        _blocking: bool,
-    ) -> Result<i64> {
-        if offset < 0 {
-            return Err(Error::SysError(SysErr::EINVAL));
-        }
+    fn Value(tables: &[(String, Vec<String>, Vec<String>)], prefix: &str, name: &str) -> i64 {
+        let (_, names, values) = tables.iter().find(|t| t.0 == prefix).unwrap();
+        let idx = names.iter().position(|n| n == name).unwrap();
+        return values[idx].parse().unwrap();
+    }
 
-        let header = "Num       RefCount Protocol Flags    Type St Inode Path\n";
-        let bytes = self.GetData(task, header)?;
-        if offset as usize > bytes.len() {
-            return Ok(0);
-        }
+    #[test]
+    fn test_NetTable() {
+        let buf = NetTable(
+            "Udp",
+            &[("InDatagrams", 3), ("NoPorts", 0), ("MaxConn", -1)],
+        );
+        assert_eq!(buf, "Udp: InDatagrams NoPorts MaxConn\nUdp: 3 0 -1\n");
+    }
 
-        let n = task.CopyDataOutToIovs(&bytes[offset as usize..], dsts, true)?;
+    #[test]
+    fn test_NetSnmp() {
+        let snmp = SnmpCounters::default();
+        snmp.Proto(AFType::AF_INET, SockType::SOCK_STREAM)
+            .Open(false);
+        snmp.Proto(AFType::AF_INET6, SockType::SOCK_STREAM)
+            .Open(true);
+        snmp.tcp.AttemptFail(&Error::SysError(SysErr::ECONNREFUSED));
+        snmp.tcp.InPackets(4);
+        snmp.tcp.Tx(&Ok(100));
+        snmp.udp.Rx(&Ok(10));
+        snmp.udp.Rx(&Err(Error::SysError(SysErr::ENOMEM)));
+        snmp.udp.OutPackets(2);
+
+        // the interrupted calls and the other sockets don't count
+        snmp.udp.Rx(&Err(Error::SysError(SysErr::EAGAIN)));
+        snmp.Proto(AFType::AF_PACKET, SockType::SOCK_DGRAM)
+            .InPackets(7);
+        snmp.Proto(AFType::AF_INET, SockType::SOCK_RAW).InPackets(7);
+
+        let tables = Tables(&NetSnmp(&snmp, 2));
+        let prefixes: Vec<&str> = tables.iter().map(|t| t.0.as_str()).collect();
+        assert_eq!(prefixes, vec!["Ip", "Icmp", "Tcp", "Udp", "UdpLite"]);
+        assert_eq!(tables[1].1.len(), 27);
+
+        assert_eq!(Value(&tables, "Ip", "Forwarding"), 2);
+        assert_eq!(Value(&tables, "Ip", "InReceives"), 5);
+        assert_eq!(Value(&tables, "Ip", "InDelivers"), 5);
+        assert_eq!(Value(&tables, "Ip", "OutRequests"), 3);
+        assert_eq!(Value(&tables, "Tcp", "MaxConn"), -1);
+        assert_eq!(Value(&tables, "Tcp", "ActiveOpens"), 1);
+        assert_eq!(Value(&tables, "Tcp", "PassiveOpens"), 1);
+        assert_eq!(Value(&tables, "Tcp", "AttemptFails"), 1);
+        assert_eq!(Value(&tables, "Tcp", "CurrEstab"), 2);
+        assert_eq!(Value(&tables, "Tcp", "InSegs"), 4);
+        assert_eq!(Value(&tables, "Tcp", "OutSegs"), 1);
+        assert_eq!(Value(&tables, "Udp", "InDatagrams"), 1);
+        assert_eq!(Value(&tables, "Udp", "InErrors"), 1);
+        assert_eq!(Value(&tables, "Udp", "OutDatagrams"), 2);
+        assert_eq!(Value(&tables, "UdpLite", "InDatagrams"), 0);
+    }
+
+    #[test]
+    fn test_NetNetstat() {
+        let tables = Tables(&NetNetstat(1500, 300));
+        let prefixes: Vec<&str> = tables.iter().map(|t| t.0.as_str()).collect();
+        assert_eq!(prefixes, vec!["TcpExt", "IpExt"]);
+        assert_eq!(Value(&tables, "TcpExt", "ListenDrops"), 0);
+        assert_eq!(Value(&tables, "IpExt", "InOctets"), 1500);
+        assert_eq!(Value(&tables, "IpExt", "OutOctets"), 300);
+    }
+
+    #[test]
+    fn test_TCPLine() {
+        let header = TCPHeader(AFType::AF_INET);
+        assert_eq!(header.len(), 150);
+        assert!(header.starts_with("  sl  local_address rem_address   st"));
+
+        let line = TCPLine(AFType::AF_INET, 3, &Entry(Inet([10, 0, 0, 6], 4000)));
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        assert_eq!(fields[0], "3:");
+        assert_eq!(fields[1], "0500000A:0050");
+        assert_eq!(fields[2], "0600000A:0FA0");
+        assert_eq!(fields[3], "01");
+        assert_eq!(fields[4], "00000010:00000020");
+        assert_eq!(fields[7], "1000");
+        // the inode is the one of the fd link, and then the refs
+        assert_eq!(fields[9], "12345");
+        assert_eq!(fields[10], "1");
+        assert_eq!(*fields.last().unwrap(), "-1");
+        assert_eq!(fields.len(), 17);
+
+        // a socket without an address of the family has the any address
+        let mut entry = Entry(None);
+        entry.local = None;
+        let line = TCPLine(AFType::AF_INET6, 0, &entry);
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        assert_eq!(fields[1], format!("{}:0000", "0".repeat(32)));
+        assert_eq!(fields[9], "12345");
+    }
 
-        return Ok(n as i64);
+    #[test]
+    fn test_UDPLine() {
+        let header = UDPHeader(AFType::AF_INET);
+        assert_eq!(header.len(), 128);
+        assert!(header.trim_end().ends_with("inode ref pointer drops"));
+
+        // an unconnected socket is closed
+        let line = UDPLine(AFType::AF_INET, 0, &Entry(None));
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        assert_eq!(fields[0], "0:");
+        assert_eq!(fields[2], "00000000:0000");
+        assert_eq!(fields[3], "07");
+        assert_eq!(fields[9], "12345");
+        assert_eq!(fields[10], "1");
+        assert_eq!(*fields.last().unwrap(), "0");
+        assert_eq!(fields.len(), 13);
+
+        let line = UDPLine(AFType::AF_INET, 1, &Entry(Inet([10, 0, 0, 6], 53)));
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        assert_eq!(fields[2], "0600000A:0035");
+        assert_eq!(fields[3], "01");
+        assert_eq!(fields[9], "12345");
     }
 }
@@ -25,12 +25,15 @@ use super::super::super::linux_def::*;
 use super::super::super::kernel::fs::dirent::*;
 use super::super::socket::unix::transport::unix::*;
 use crate::qlib::kernel::kernel::fd_table::GapMgr;
+use crate::qlib::kernel::socket::hostinet::loopbacksocket::*;
 use crate::qlib::kernel::socket::hostinet::uring_socket::UringSocketOperationsWeak;
+use crate::qlib::kernel::kernel::waiter::Queue;
 use crate::qlib::socket_buf::AcceptQueue;
 
 lazy_static! {
     pub static ref ABSTRACT_SOCKET: AbstractSocketNamespace = AbstractSocketNamespace::default();
     pub static ref TCP_SOCKET: TCPSocketNamespace = TCPSocketNamespace::New();
+    pub static ref UDP_SOCKET: UDPSocketNamespace = UDPSocketNamespace::default();
     pub static ref UNIX_SOCKET_PINS: UnixSocketPins = UnixSocketPins::default();
 }
 
@@ -166,27 +169,49 @@ impl TCPSocketNamespace {
         return Self(Arc::new(QMutex::new(TCPSocketNamespaceInner::New())))
     }
 
-    pub fn Add(&self, port: u16, q: AcceptQueue) -> Result<()> {
-        return self.lock().Add(port, q)
+    pub fn Add(&self, port: u16, addr: Vec<u8>, family: i32, q: AcceptQueue) -> Result<()> {
+        return self.lock().Add(port, addr, family, q)
     }
 
-    pub fn Remove(&self, port: u16) -> Result<()> {
-        return self.lock().Remove(port);
+    pub fn Remove(&self, port: u16, q: &AcceptQueue) -> Result<()> {
+        return self.lock().Remove(port, q);
     }
 
-    pub fn Get(&self, port: u16) -> Option<AcceptQueue> {
-        match self.lock().descTbl.get(&port) {
-            None => return None,
-            Some(q) => {
-                return Some(q.clone())
-            }
-        }
+    // Get returns the family and the accept queue of the listener for the
+    // local address addr:port.
+    pub fn Get(&self, addr: &[u8], port: u16) -> Option<(i32, AcceptQueue)> {
+        return self.lock().Get(addr, port);
     }
 }
 
+#[derive(Clone)]
+pub struct TCPListener {
+    // the bound address, ipv4 mapped addresses are stored as ipv4
+    pub addr: Vec<u8>,
+    pub family: i32,
+    pub queue: AcceptQueue,
+}
+
+fn IsWildcard(bound: &[u8]) -> bool {
+    return bound.iter().all(|b| *b == 0);
+}
+
+// BoundMatches returns whether a socket bound to the address bound receives
+// the packets to addr.
+fn BoundMatches(bound: &[u8], addr: &[u8]) -> bool {
+    if !IsWildcard(bound) {
+        return bound[..] == addr[..];
+    }
+
+    // a [::] socket also receives ipv4 packets
+    return bound.len() == addr.len() || bound.len() == 16;
+}
+
 pub struct TCPSocketNamespaceInner {
     pub gaps: GapMgr,
-    pub descTbl: HashMap<u16, AcceptQueue>,
+    pub descTbl: HashMap<u16, Vec<TCPListener>>,
+    // the next listener of a SO_REUSEPORT group
+    pub next: usize,
 }
 
 impl TCPSocketNamespaceInner {
@@ -194,20 +219,115 @@ impl TCPSocketNamespaceInner {
         return Self {
             gaps: GapMgr::New(0, i16::MAX as u64),
             descTbl: HashMap::new(),
+            next: 0,
         };
     }
 
-    pub fn Add(&mut self, port: u16, q: AcceptQueue) -> Result<()> {
-        match self.descTbl.insert(port, q) {
+    pub fn Add(&mut self, port: u16, addr: Vec<u8>, family: i32, q: AcceptQueue) -> Result<()> {
+        let listeners = self.descTbl.entry(port).or_insert_with(Vec::new);
+        // listen is called again to change the backlog
+        if listeners.iter().any(|l| Arc::ptr_eq(l.queue.deref(), q.deref())) {
+            return Ok(());
+        }
+
+        listeners.push(TCPListener {
+            addr: addr,
+            family: family,
+            queue: q,
+        });
+        return Ok(());
+    }
+
+    pub fn Remove(&mut self, port: u16, q: &AcceptQueue) -> Result<()> {
+        let empty = match self.descTbl.get_mut(&port) {
             None => return Ok(()),
-            _ => return Ok(()), // double listen in nginx panic!("TCPSocketNamespaceInner::NewSocketAt for port {}", port)
+            Some(listeners) => {
+                listeners.retain(|l| !Arc::ptr_eq(l.queue.deref(), q.deref()));
+                listeners.len() == 0
+            }
+        };
+
+        if empty {
+            self.descTbl.remove(&port);
         }
+
+        return Ok(());
+    }
+
+    // Get prefers the listeners bound to addr over the wildcard ones as Linux
+    // does, the connections are spread over a SO_REUSEPORT group round robin.
+    pub fn Get(&mut self, addr: &[u8], port: u16) -> Option<(i32, AcceptQueue)> {
+        let listeners = self.descTbl.get(&port)?;
+        let mut group: Vec<&TCPListener> = listeners
+            .iter()
+            .filter(|l| !IsWildcard(&l.addr) && BoundMatches(&l.addr, addr))
+            .collect();
+        if group.len() == 0 {
+            group = listeners
+                .iter()
+                .filter(|l| BoundMatches(&l.addr, addr))
+                .collect();
+        }
+
+        if group.len() == 0 {
+            return None;
+        }
+
+        self.next = self.next.wrapping_add(1);
+        let l = group[self.next % group.len()];
+        return Some((l.family, l.queue.clone()));
+    }
+}
+
+#[derive(Clone)]
+pub struct UDPBinding {
+    // the bound address, ipv4 mapped addresses are stored as ipv4
+    pub addr: Vec<u8>,
+    pub family: i32,
+    pub dgrams: DatagramQueueWeak,
+    // the wait queue of the socket
+    pub queue: Queue,
+}
+
+// UDPSocketNamespace holds the bound udp sockets of the sandbox, the datagrams
+// between them are delivered in the guest.
+#[derive(Default)]
+pub struct UDPSocketNamespace(QMutex<BTreeMap<u16, Vec<UDPBinding>>>);
+
+impl UDPSocketNamespace {
+    pub fn Add(&self, port: u16, binding: UDPBinding) {
+        self.0.lock().entry(port).or_insert_with(Vec::new).push(binding);
     }
 
-    pub fn Remove(&mut self, port: u16) -> Result<()> {
-        match self.descTbl.remove(&port) {
-            None => return Ok(()), //panic!("TCPSocketNamespaceInner::RemoveSocket for port {}", port),
-            _ => return Ok(())
+    pub fn Remove(&self, port: u16, dgrams: &DatagramQueue) {
+        let mut tbl = self.0.lock();
+        let empty = match tbl.get_mut(&port) {
+            None => return,
+            Some(bindings) => {
+                bindings.retain(|b| match b.dgrams.Upgrade() {
+                    None => false,
+                    Some(q) => !Arc::ptr_eq(q.deref(), dgrams.deref()),
+                });
+                bindings.len() == 0
+            }
+        };
+
+        if empty {
+            tbl.remove(&port);
         }
     }
-}
\ No newline at end of file
+
+    // Get returns the family, the datagram queue and the wait queue of the
+    // socket receiving the datagrams to addr:port.
+    pub fn Get(&self, addr: &[u8], port: u16) -> Option<(i32, DatagramQueue, Queue)> {
+        let tbl = self.0.lock();
+        let bindings = tbl.get(&port)?;
+        let binding = bindings
+            .iter()
+            .find(|b| !IsWildcard(&b.addr) && BoundMatches(&b.addr, addr))
+            .or_else(|| bindings.iter().find(|b| BoundMatches(&b.addr, addr)))?;
+
+        let dgrams = binding.dgrams.Upgrade()?;
+        return Some((binding.family, dgrams, binding.queue.clone()));
+    }
+}
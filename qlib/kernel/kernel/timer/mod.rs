@@ -46,7 +46,7 @@ pub struct TimerUpdater {}
 
 impl TimerListenerTrait for TimerUpdater {
     fn Notify(&self, _exp: u64) {
-        TIME_KEEPER.write().Update();
+        TIME_KEEPER.Update();
     }
 
     fn Destroy(&self) {}
@@ -20,6 +20,7 @@ use super::super::super::super::common::*;
 use super::super::super::super::linux::time::*;
 use super::super::super::kernel::time::*;
 //use super::super::super::super::perf_tunning::*;
+use super::super::super::Kernel::HostSpace;
 use super::super::vdso::*;
 use super::calibratedClock::*;
 use super::timer::Clock;
@@ -39,9 +40,13 @@ impl Deref for TimeKeeper {
 
 impl TimeKeeper {
     pub fn Initialization(&self, vdsoParamPageAddr: u64) {
-        {
+        let realtimeOk = {
             let mut internal = self.write();
-            internal.Init(vdsoParamPageAddr);
+            internal.Init(vdsoParamPageAddr)
+        };
+
+        if realtimeOk {
+            self.UpdateHostRealtimeOffset();
         }
 
         let timer = Timer::Period(
@@ -70,7 +75,22 @@ impl TimeKeeper {
     }
 
     pub fn Update(&self) {
-        self.write().Update();
+        let realtimeOk = self.write().Update();
+        if realtimeOk {
+            self.UpdateHostRealtimeOffset();
+        }
+    }
+
+    // UpdateHostRealtimeOffset samples the host realtime clock between two
+    // reads of the sandbox one. The hypercall is made without the lock, so
+    // that the clock readers don't wait for the host.
+    fn UpdateHostRealtimeOffset(&self) {
+        let before = self.read().clocks.GetTime(REALTIME);
+        let host = HostSpace::KernelGetTime(REALTIME);
+        let after = self.read().clocks.GetTime(REALTIME);
+        if let (Ok(before), Ok(host), Ok(after)) = (before, host, after) {
+            self.write().hostRealtimeOffset = before + (after - before) / 2 - host;
+        }
     }
 
     pub fn GetTime(&self, c: ClockID) -> Result<i64> {
@@ -80,6 +100,10 @@ impl TimeKeeper {
     pub fn BootTime(&self) -> Time {
         return self.read().BootTime();
     }
+
+    pub fn HostRealtimeOffset(&self) -> i64 {
+        return self.read().hostRealtimeOffset;
+    }
 }
 
 pub struct TimeKeeperInternal {
@@ -96,6 +120,11 @@ pub struct TimeKeeperInternal {
     // It is set only once, by SetClocks.
     pub monotonicOffset: i64,
 
+    // hostRealtimeOffset is the offset of the realtime clock, which is
+    // calibrated from the tsc, to the host realtime clock. It moves the host
+    // socket timestamps to the sandbox clock.
+    pub hostRealtimeOffset: i64,
+
     // params manages the parameter page.
     pub params: VDSOParamPage,
 
@@ -112,6 +141,7 @@ impl Default for TimeKeeperInternal {
             clocks: clocks,
             bootTime: Time::default(),
             monotonicOffset: 0,
+            hostRealtimeOffset: 0,
             params: VDSOParamPage::default(),
             inited: false,
             timer: None,
@@ -122,7 +152,8 @@ impl Default for TimeKeeperInternal {
 }
 
 impl TimeKeeperInternal {
-    pub fn Init(&mut self, vdsoParamPageAddr: u64) {
+    // Init returns whether the realtime clock is ready, as Update.
+    pub fn Init(&mut self, vdsoParamPageAddr: u64) -> bool {
         self.params.SetParamPageAddr(vdsoParamPageAddr);
 
         // Compute the offset of the monotonic clock from the base Clocks.
@@ -141,14 +172,16 @@ impl TimeKeeperInternal {
         self.monotonicOffset = wantMonotonic - nowMonotonic;
         self.bootTime = Time::FromNs(nowRealtime);
         self.inited = true;
-        self.Update();
+        return self.Update();
     }
 
     pub fn MonotonicFrequency(&self) -> u64 {
         return self.params.vdsoParams.monotonicFrequency;
     }
 
-    pub fn Update(&mut self) {
+    // Update returns whether the realtime clock is ready, the host realtime
+    // offset is then updated by the caller without the lock.
+    pub fn Update(&mut self) -> bool {
         //PerfPrint();
         //super::super::super::perflog::THREAD_COUNTS.lock().Print(true);
         //super::super::super::AllocatorPrint();
@@ -177,6 +210,8 @@ impl TimeKeeperInternal {
             Err(err) => info!("Unable to update VDSO parameter page: {:?}", err),
             _ => (),
         }
+
+        return realtimeOk;
     }
 
     // GetTime returns the current time in nanoseconds.
@@ -24,6 +24,7 @@ use super::super::super::path::*;
 use super::super::super::range::*;
 use super::super::fs::dirent::*;
 use super::super::fs::inotify::*;
+use super::super::fs::fanotify::*;
 use super::super::fs::file::*;
 use super::super::fs::flags::*;
 use super::super::kernel::timer::*;
@@ -150,7 +151,10 @@ pub fn OpenPath(task: &mut Task, filename: &str, maxTraversals: u32) -> Result<(
         },
     )?;
 
+    FANOTIFY.Permission(task, &d, Fanotify::FAN_OPEN_EXEC_PERM)?;
+
     file.Dirent.InotifyEvent(InotifyEvent::IN_OPEN, 0, EventType::InodeEvent);
+    FANOTIFY.Notify(&file.Dirent, Fanotify::FAN_OPEN_EXEC as u32);
 
     return Ok((file, d));
 }
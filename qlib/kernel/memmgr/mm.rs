@@ -1432,7 +1432,12 @@ impl MemoryManager {
                     AccessType(perms.0 & !MmapProt::PROT_WRITE)
                 };
 
-                if precommit && segAr.Len() < 0x200000 {
+                // A shared writable mapping of a file with a disk quota is
+                // faulted in page by page: MapFilePage only maps the pages
+                // below the file size charged to the quota, the file can only
+                // grow through write(2), ftruncate(2) or fallocate(2).
+                let quotaLimited = !vma.private && perms.Write() && iops.WriteQuota().is_some();
+                if precommit && segAr.Len() < 0x200000 && !quotaLimited {
                     self.pagetable.write().pt.MapFile(
                         task,
                         ar.Start(),
@@ -13,12 +13,16 @@
 // limitations under the License.
 
 use crate::qlib::mutex::*;
+use alloc::boxed::Box;
 use alloc::collections::vec_deque::VecDeque;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::marker::Send;
 use core::ops::Deref;
 use enum_dispatch::enum_dispatch;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicU16;
+use core::sync::atomic::AtomicU64;
 use core::sync::atomic::Ordering;
 
 use super::super::super::super::kernel_def::*;
@@ -29,6 +33,10 @@ use super::super::super::socket_buf::*;
 use super::super::super::uring::opcode;
 use super::super::super::uring::opcode::*;
 use super::super::super::uring::squeue;
+use super::super::super::uring::sys::sys::IORING_CQE_BUFFER_SHIFT;
+use super::super::super::uring::sys::sys::IORING_CQE_F_BUFFER;
+use super::super::super::uring::sys::sys::IORING_CQE_F_MORE;
+use super::super::super::uring::sys::sys::IORING_RECV_MULTISHOT;
 use super::super::fs::file::*;
 use super::super::kernel::aio::aio_context::*;
 use super::super::kernel::async_wait::*;
@@ -36,8 +44,12 @@ use super::super::kernel::eventfd::*;
 use super::super::kernel::timer;
 use super::super::kernel::waiter::qlock::*;
 use super::super::kernel::waiter::*;
+use super::super::socket::control::AdjustTimestamps;
+use super::super::socket::hostinet::loopbacksocket::*;
 use super::super::socket::hostinet::socket::*;
+use super::super::socket::hostinet::traffic::NET_SNMP;
 use super::super::socket::hostinet::uring_socket::*;
+use super::super::socket::netstack::NETSTACK;
 use super::super::task::*;
 use super::super::IOURING;
 use super::super::SHARESPACE;
@@ -52,6 +64,44 @@ pub trait AsyncOpsTrait {
     fn Process(&mut self, _result: i32) -> bool {
         panic!("doesn't support AsyncOpsTrait::Process")
     }
+
+    // ProcessCqe also gets the cqe flags, which the multishot ops need.
+    fn ProcessCqe(&mut self, result: i32, _flags: u32) -> bool {
+        return self.Process(result);
+    }
+
+    // Token returns the token of the user_data of an op which is cancelled,
+    // 0 for the others.
+    fn Token(&self) -> u64 {
+        return 0;
+    }
+
+    // RearmLinks returns the ops to submit linked before a multishot op
+    // which is submitted again.
+    fn RearmLinks(&mut self) -> Vec<AsyncOps> {
+        return Vec::new();
+    }
+}
+
+// ASYNC_TOKEN_TAG marks the user_data holding a token over the slot index.
+// The ops which are cancelled get one so that a cancel issued after the op
+// completed can't hit the next op of the slot. The UringCall pointers are
+// below it.
+pub const ASYNC_TOKEN_TAG: u64 = 1 << 63;
+pub const ASYNC_SLOT_MASK: u64 = 0xffff;
+
+static NEXT_ASYNC_TOKEN: AtomicU64 = AtomicU64::new(1);
+
+pub fn NewAsyncToken() -> u64 {
+    return NEXT_ASYNC_TOKEN.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn AsyncUserData(token: u64, id: usize) -> u64 {
+    if token == 0 {
+        return id as u64;
+    }
+
+    return ASYNC_TOKEN_TAG | (token << 16) | id as u64;
 }
 
 #[enum_dispatch]
@@ -80,20 +130,37 @@ pub enum AsyncOps {
     AsyncSend(AsyncSend),
     PollHostEpollWait(PollHostEpollWait),
     AsyncConnect(AsyncConnect),
+    AsyncNetstackRead(AsyncNetstackRead),
+    AsyncNetstackWrite(AsyncNetstackWrite),
+    AsyncRecvMultishot(AsyncRecvMultishot),
+    AsyncProvideBuffers(AsyncProvideBuffers),
+    AsyncRemoveBuffers(AsyncRemoveBuffers),
+    AsyncCancelOp(AsyncCancelOp),
+    AsyncShapeDelay(AsyncShapeDelay),
     None(AsyncNone),
 }
 
 impl AsyncOps {
-    pub fn ProcessResult(&mut self, result: i32, id: usize) -> bool {
-        let ret = self.Process(result);
-
-        if ret {
-            IOURING.AUCallDirect(self, id);
+    pub fn ProcessResult(&mut self, result: i32, flags: u32, id: usize) -> bool {
+        let ret = self.ProcessCqe(result, flags);
+
+        // a multishot op is still armed in the host while IORING_CQE_F_MORE is set
+        if ret && flags & IORING_CQE_F_MORE == 0 {
+            let links = self.RearmLinks();
+            if links.len() == 0 {
+                IOURING.AUCallDirect(self, id);
+            } else {
+                IOURING.AUCallLinkedDirect(links, self, id);
+            }
         }
 
         return ret;
     }
 
+    pub fn UserData(&self, id: usize) -> u64 {
+        return AsyncUserData(self.Token(), id);
+    }
+
     pub fn Type(&self) -> usize {
         match self {
             AsyncOps::AsyncTimeout(_) => return 1,
@@ -119,6 +186,13 @@ impl AsyncOps {
             AsyncOps::AsyncSend(_) => return 21,
             AsyncOps::PollHostEpollWait(_) => return 22,
             AsyncOps::AsyncConnect(_) => return 23,
+            AsyncOps::AsyncNetstackRead(_) => return 24,
+            AsyncOps::AsyncNetstackWrite(_) => return 25,
+            AsyncOps::AsyncRecvMultishot(_) => return 26,
+            AsyncOps::AsyncProvideBuffers(_) => return 27,
+            AsyncOps::AsyncRemoveBuffers(_) => return 28,
+            AsyncOps::AsyncCancelOp(_) => return 29,
+            AsyncOps::AsyncShapeDelay(_) => return 30,
             AsyncOps::None(_) => (),
         };
 
@@ -178,8 +252,9 @@ impl UringAsyncMgr {
     }
 
     pub fn SetOps(&self, id: usize, ops: AsyncOps) -> squeue::Entry {
-        *self.ops[id].lock() = ops;
-        return self.ops[id].lock().SEntry().user_data(id as u64);
+        let mut op = self.ops[id].lock();
+        *op = ops;
+        return op.SEntry().user_data(op.UserData(id));
     }
 }
 
@@ -547,6 +622,8 @@ impl AsyncOpsTrait for AsyncSend {
 
     fn Process(&mut self, result: i32) -> bool {
         if result < 0 {
+            self.ops.traffic.ext.TxError(&Error::SysError(-result));
+            NET_SNMP.tcp.TxError(&Error::SysError(-result));
             self.buf.SetErr(-result);
             self.queue
                 .Notify(EventMaskFromLinux((EVENT_ERR | READABLE_EVENT) as u32));
@@ -566,6 +643,10 @@ impl AsyncOpsTrait for AsyncSend {
             return false;
         }
 
+        self.ops.traffic.ext.TxPackets(result as u64, 1);
+        NET_SNMP.tcp.OutPackets(1);
+        // only the bytes the host took are charged, the rest is sent again
+        let delay = self.ops.traffic.shaper.Charge(result as usize);
         let (trigger, addr, len) = self.buf.ConsumeAndGetAvailableWriteBuf(result as usize);
         if trigger {
             self.queue.Notify(EventMaskFromLinux(WRITEABLE_EVENT as u32));
@@ -582,6 +663,15 @@ impl AsyncOpsTrait for AsyncSend {
         self.addr = addr;
         self.len = len;
 
+        if delay > 0 {
+            let op = AsyncSend::New(self.fd, self.queue.clone(), self.buf.clone(), addr, len, &self.ops);
+            IOURING.AUCall(AsyncOps::AsyncShapeDelay(AsyncShapeDelay::New(
+                delay,
+                AsyncOps::AsyncSend(op),
+            )));
+            return false;
+        }
+
         return true;
     }
 }
@@ -604,6 +694,17 @@ impl AsyncSend {
             ops: ops.clone(),
         };
     }
+
+    // Shaped returns the op to submit, which waits for the egress shaper when
+    // the container has sent over its bandwidth.
+    pub fn Shaped(self) -> AsyncOps {
+        let delay = self.ops.traffic.shaper.Delay();
+        if delay == 0 {
+            return AsyncOps::AsyncSend(self);
+        }
+
+        return AsyncOps::AsyncShapeDelay(AsyncShapeDelay::New(delay, AsyncOps::AsyncSend(self)));
+    }
 }
 
 pub struct AsyncFiletWrite {
@@ -885,6 +986,7 @@ impl AsyncOpsTrait for AsycnSendMsg {
             return
         }*/
 
+        let delay = intern.ops.traffic.shaper.Charge(result as usize);
         if buf.ConsumeWriteBuf(result as usize) {
             intern.ops.Notify(WRITEABLE_EVENT);
         }
@@ -894,7 +996,17 @@ impl AsyncOpsTrait for AsycnSendMsg {
             return false;
         }
 
-        //let sendMsgOp = AsycnSendMsg::New(intern.fd, &intern.ops);
+        if delay > 0 {
+            let sendMsgOp = AsycnSendMsg::New(intern.fd, &intern.ops);
+            sendMsgOp.lock().SetIovs(addr, cnt);
+            IOURING.AUCall(AsyncOps::AsyncShapeDelay(AsyncShapeDelay::New(
+                delay,
+                AsyncOps::AsycnSendMsg(sendMsgOp),
+            )));
+            return false;
+        }
+
+        drop(intern);
         self.lock().SetIovs(addr, cnt);
 
         return true;
@@ -1397,9 +1509,12 @@ impl AsyncOpsTrait for AsyncConnect {
         socket.SetConnErrno(result);
 
         if result == 0 {
+            socket.traffic.ext.Connected();
+            NET_SNMP.tcp.Open(false);
             socket.SetRemoteAddr(self.addr.data[0..self.len as _].to_vec()).expect(&format!("AsyncConnect fail {:?}", &self.addr.data[0..self.len as _]));
             socket.PostConnect();
         } else {
+            NET_SNMP.tcp.AttemptFail(&Error::SysError(-result));
             let socktype = UringSocketType::TCPInit;
             *socket.socketType.lock() = socktype;
         }
@@ -1481,6 +1596,85 @@ impl PollHostEpollWait {
     }
 }
 
+pub struct AsyncNetstackRead {
+    pub fd: i32,
+    pub buf: DataBuff,
+}
+
+impl AsyncOpsTrait for AsyncNetstackRead {
+    fn SEntry(&self) -> squeue::Entry {
+        let op = Read::new(types::Fd(self.fd), self.buf.Ptr() as *mut u8, self.buf.Len() as u32);
+
+        if SHARESPACE.config.read().UringFixedFile {
+            return op.build().flags(squeue::Flags::FIXED_FILE);
+        } else {
+            return op.build();
+        }
+    }
+
+    fn Process(&mut self, result: i32) -> bool {
+        if result == -SysErr::EAGAIN || result == -SysErr::EINTR {
+            return true;
+        }
+
+        if result < 0 {
+            error!("AsyncNetstackRead::Process result {}", result);
+            return false;
+        }
+
+        NETSTACK.DeliverFrame(&self.buf.buf[0..result as usize]);
+        return true;
+    }
+}
+
+impl AsyncNetstackRead {
+    pub fn New(fd: i32, len: usize) -> Self {
+        return Self {
+            fd,
+            buf: DataBuff::New(len),
+        };
+    }
+}
+
+pub struct AsyncNetstackWrite {
+    pub fd: i32,
+    pub buf: DataBuff,
+}
+
+impl AsyncOpsTrait for AsyncNetstackWrite {
+    fn SEntry(&self) -> squeue::Entry {
+        let op = opcode::Write::new(
+            types::Fd(self.fd),
+            self.buf.Ptr() as *const u8,
+            self.buf.Len() as u32,
+        );
+
+        if SHARESPACE.config.read().UringFixedFile {
+            return op.build().flags(squeue::Flags::FIXED_FILE);
+        } else {
+            return op.build();
+        }
+    }
+
+    fn Process(&mut self, result: i32) -> bool {
+        // the frame is lost as on a congested link, tcp retransmits it
+        if result < 0 {
+            debug!("AsyncNetstackWrite::Process result {}", result);
+        }
+
+        return false;
+    }
+}
+
+impl AsyncNetstackWrite {
+    pub fn New(fd: i32, frame: Vec<u8>) -> Self {
+        return Self {
+            fd,
+            buf: DataBuff { buf: frame },
+        };
+    }
+}
+
 #[repr(C)]
 #[repr(packed)]
 #[derive(Debug, Default, Copy, Clone)]
@@ -1535,6 +1729,373 @@ impl AsyncEpollCtl {
 
 }
 
+// The buffers of MULTISHOT_BUF_SIZE provided to the host for a multishot
+// recvmsg are allocated by chunks of MULTISHOT_BUF_CHUNK, a new chunk is
+// added each time the host runs out of buffers up to MULTISHOT_BUF_COUNT. A
+// buffer holds a whole udp datagram.
+pub const MULTISHOT_BUF_COUNT: usize = 16;
+pub const MULTISHOT_BUF_CHUNK: usize = 2;
+pub const MULTISHOT_BUF_SIZE: usize = 64 * 1024 + 512;
+pub const MULTISHOT_NAME_LEN: usize = 128;
+pub const MULTISHOT_CONTROL_LEN: usize = 256;
+
+// the buffer group ids of the multishot recvmsg, 0 is not used
+static NEXT_BUF_GROUP: AtomicU16 = AtomicU16::new(1);
+
+pub fn NewBufGroup() -> u16 {
+    loop {
+        let bgid = NEXT_BUF_GROUP.fetch_add(1, Ordering::Relaxed);
+        if bgid != 0 {
+            return bgid;
+        }
+    }
+}
+
+// RecvMsgOut is struct io_uring_recvmsg_out, it is at the start of the buffer
+// of a multishot recvmsg and followed by the name, the control messages and
+// the payload.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RecvMsgOut {
+    pub nameLen: u32,
+    pub controlLen: u32,
+    pub payloadLen: u32,
+    pub flags: u32,
+}
+
+// AsyncRecvMultishot is a multishot recvmsg on the host fd of a udp socket,
+// it stays armed in the host and queues each datagram to the guest socket
+// without a new submission.
+pub struct AsyncRecvMultishot {
+    pub fd: i32,
+    pub bgid: u16,
+    // the chunks of buffers, the buffer bid is in the chunk
+    // bid / MULTISHOT_BUF_CHUNK
+    pub pools: Vec<DataBuff>,
+    pub msg: MsgHdr,
+    pub dgrams: DatagramQueue,
+    pub queue: Queue,
+    pub armed: Arc<AtomicBool>,
+    // the consumed buffers, they are provided again in batches
+    pub free: Vec<u16>,
+    // the buffers to provide before the recvmsg is armed again
+    pub rearm: Vec<AsyncProvideBuffers>,
+    pub token: u64,
+}
+
+impl AsyncOpsTrait for AsyncRecvMultishot {
+    fn SEntry(&self) -> squeue::Entry {
+        let op = RecvMsg::new(types::Fd(self.fd), &self.msg as *const _ as *const u64)
+            .ioprio(IORING_RECV_MULTISHOT)
+            .buf_group(self.bgid);
+
+        if SHARESPACE.config.read().UringFixedFile {
+            return op
+                .build()
+                .flags(squeue::Flags::FIXED_FILE | squeue::Flags::BUFFER_SELECT);
+        } else {
+            return op.build().flags(squeue::Flags::BUFFER_SELECT);
+        }
+    }
+
+    fn ProcessCqe(&mut self, result: i32, flags: u32) -> bool {
+        if result >= 0 && flags & IORING_CQE_F_BUFFER != 0 {
+            let bid = (flags >> IORING_CQE_BUFFER_SHIFT) as u16;
+            self.Deliver(bid, result as usize);
+            self.free.push(bid);
+            if self.free.len() >= self.Count() / 2 {
+                for provide in self.TakeFree() {
+                    IOURING.AUCall(AsyncOps::AsyncProvideBuffers(provide));
+                }
+            }
+        }
+
+        if flags & IORING_CQE_F_MORE != 0 {
+            return true;
+        }
+
+        // the host stops when it runs out of buffers, arm it again after the
+        // buffers are provided so that it doesn't stop again at once
+        if result >= 0 || result == -SysErr::ENOBUFS {
+            if result == -SysErr::ENOBUFS && self.Count() < MULTISHOT_BUF_COUNT {
+                self.rearm.push(self.Grow());
+            }
+            let free = self.TakeFree();
+            self.rearm.extend(free);
+            return true;
+        }
+
+        // the readers go back to the host socket
+        self.armed.store(false, Ordering::Release);
+        if result != -SysErr::ECANCELED {
+            debug!("AsyncRecvMultishot::Process fd {} result {}", self.fd, result);
+            self.queue.Notify(READABLE_EVENT);
+        }
+
+        // the host holds the provided buffers until they are removed
+        let remove = AsyncRemoveBuffers {
+            bgid: self.bgid,
+            nbufs: self.Count() as u16,
+            pools: core::mem::replace(&mut self.pools, Vec::new()),
+        };
+        IOURING.AUCall(AsyncOps::AsyncRemoveBuffers(remove));
+        return false;
+    }
+
+    fn Token(&self) -> u64 {
+        return self.token;
+    }
+
+    fn RearmLinks(&mut self) -> Vec<AsyncOps> {
+        return self
+            .rearm
+            .drain(..)
+            .map(|provide| AsyncOps::AsyncProvideBuffers(provide))
+            .collect();
+    }
+}
+
+impl AsyncRecvMultishot {
+    pub fn New(fd: i32, dgrams: &DatagramQueue, queue: &Queue, armed: &Arc<AtomicBool>) -> Self {
+        let mut msg = MsgHdr::default();
+        msg.nameLen = MULTISHOT_NAME_LEN as u32;
+        msg.msgControlLen = MULTISHOT_CONTROL_LEN;
+
+        return Self {
+            fd: fd,
+            bgid: NewBufGroup(),
+            pools: vec![DataBuff::New(MULTISHOT_BUF_CHUNK * MULTISHOT_BUF_SIZE)],
+            msg: msg,
+            dgrams: dgrams.clone(),
+            queue: queue.clone(),
+            armed: armed.clone(),
+            free: Vec::new(),
+            rearm: Vec::new(),
+            token: NewAsyncToken(),
+        };
+    }
+
+    // Count returns the number of buffers of the pool.
+    pub fn Count(&self) -> usize {
+        return self.pools.len() * MULTISHOT_BUF_CHUNK;
+    }
+
+    pub fn ProvideAll(&self) -> AsyncProvideBuffers {
+        return AsyncProvideBuffers {
+            addr: self.pools[0].Ptr(),
+            nbufs: MULTISHOT_BUF_CHUNK as u16,
+            bgid: self.bgid,
+            bid: 0,
+        };
+    }
+
+    // Grow adds a chunk of buffers to the pool, it returns the request
+    // providing them.
+    pub fn Grow(&mut self) -> AsyncProvideBuffers {
+        let bid = self.Count() as u16;
+        let chunk = DataBuff::New(MULTISHOT_BUF_CHUNK * MULTISHOT_BUF_SIZE);
+        let provide = AsyncProvideBuffers {
+            addr: chunk.Ptr(),
+            nbufs: MULTISHOT_BUF_CHUNK as u16,
+            bgid: self.bgid,
+            bid: bid,
+        };
+        self.pools.push(chunk);
+        return provide;
+    }
+
+    // BufAddr returns the address of the buffer bid.
+    pub fn BufAddr(&self, bid: u16) -> u64 {
+        let chunk = &self.pools[bid as usize / MULTISHOT_BUF_CHUNK];
+        let offset = bid as usize % MULTISHOT_BUF_CHUNK * MULTISHOT_BUF_SIZE;
+        return chunk.Ptr() + offset as u64;
+    }
+
+    // TakeFree returns the requests providing the consumed buffers again,
+    // one for each run of consecutive buffer ids in a chunk. The host hands
+    // out the buffers in order so there are few runs.
+    pub fn TakeFree(&mut self) -> Vec<AsyncProvideBuffers> {
+        self.free.sort();
+        let mut provides = Vec::new();
+        let mut i = 0;
+        while i < self.free.len() {
+            let start = self.free[i];
+            let mut n = 1;
+            while i + n < self.free.len()
+                && self.free[i + n] == start + n as u16
+                && (start as usize + n) % MULTISHOT_BUF_CHUNK != 0
+            {
+                n += 1;
+            }
+
+            provides.push(AsyncProvideBuffers {
+                addr: self.BufAddr(start),
+                nbufs: n as u16,
+                bgid: self.bgid,
+                bid: start,
+            });
+            i += n;
+        }
+
+        self.free.clear();
+        return provides;
+    }
+
+    // Deliver queues the datagram in the buffer bid which the host filled
+    // with len bytes.
+    pub fn Deliver(&self, bid: u16, len: usize) {
+        let hdrLen = core::mem::size_of::<RecvMsgOut>();
+        if bid as usize >= self.Count() {
+            return;
+        }
+
+        let chunk = &self.pools[bid as usize / MULTISHOT_BUF_CHUNK];
+        let start = bid as usize % MULTISHOT_BUF_CHUNK * MULTISHOT_BUF_SIZE;
+        let len = core::cmp::min(len, MULTISHOT_BUF_SIZE);
+        if len < hdrLen {
+            return;
+        }
+
+        let buf = &chunk.buf[start..start + len];
+        let out = unsafe { *(&buf[0] as *const _ as *const RecvMsgOut) };
+
+        let nameOff = hdrLen;
+        let controlOff = nameOff + MULTISHOT_NAME_LEN;
+        let payloadOff = controlOff + MULTISHOT_CONTROL_LEN;
+        if payloadOff > len {
+            return;
+        }
+
+        let nameLen = core::cmp::min(out.nameLen as usize, MULTISHOT_NAME_LEN);
+        let controlLen = core::cmp::min(out.controlLen as usize, MULTISHOT_CONTROL_LEN);
+        let payloadLen = core::cmp::min(out.payloadLen as usize, len - payloadOff);
+
+        // the truncation of the host is reported to the reader
+        let mut flags = out.flags as i32 & (MsgType::MSG_TRUNC | MsgType::MSG_CTRUNC);
+        if payloadLen < out.payloadLen as usize {
+            flags |= MsgType::MSG_TRUNC;
+        }
+
+        let mut control = buf[controlOff..controlOff + controlLen].to_vec();
+        AdjustTimestamps(&mut control, timer::TIME_KEEPER.HostRealtimeOffset());
+
+        let d = Datagram {
+            data: buf[payloadOff..payloadOff + payloadLen].to_vec(),
+            from: buf[nameOff..nameOff + nameLen].to_vec(),
+            control: control,
+            time: 0,
+            flags: flags,
+        };
+
+        // a datagram over the receive buffer is dropped as udp does
+        if self.dgrams.Enq(d) {
+            self.queue.Notify(READABLE_EVENT);
+        }
+    }
+}
+
+pub struct AsyncProvideBuffers {
+    pub addr: u64,
+    pub nbufs: u16,
+    pub bgid: u16,
+    pub bid: u16,
+}
+
+impl AsyncOpsTrait for AsyncProvideBuffers {
+    fn SEntry(&self) -> squeue::Entry {
+        let op = ProvideBuffers::new(
+            self.addr as *mut u8,
+            MULTISHOT_BUF_SIZE as i32,
+            self.nbufs,
+            self.bgid,
+            self.bid,
+        );
+
+        return op.build();
+    }
+
+    fn Process(&mut self, result: i32) -> bool {
+        if result < 0 {
+            error!("AsyncProvideBuffers::Process bgid {} result {}", self.bgid, result);
+        }
+
+        return false;
+    }
+}
+
+// AsyncRemoveBuffers removes the buffers of a multishot recvmsg from the host,
+// the pool is freed once the host doesn't use it anymore.
+pub struct AsyncRemoveBuffers {
+    pub bgid: u16,
+    pub nbufs: u16,
+    pub pools: Vec<DataBuff>,
+}
+
+impl AsyncOpsTrait for AsyncRemoveBuffers {
+    fn SEntry(&self) -> squeue::Entry {
+        let op = RemoveBuffers::new(self.nbufs, self.bgid);
+        return op.build();
+    }
+
+    fn Process(&mut self, _result: i32) -> bool {
+        return false;
+    }
+}
+
+pub struct AsyncCancelOp {
+    pub userData: u64,
+}
+
+impl AsyncCancelOp {
+    pub fn New(userData: u64) -> Self {
+        return Self { userData: userData };
+    }
+}
+
+impl AsyncOpsTrait for AsyncCancelOp {
+    fn SEntry(&self) -> squeue::Entry {
+        let op = AsyncCancel::new(self.userData);
+        return op.build();
+    }
+
+    fn Process(&mut self, _result: i32) -> bool {
+        return false;
+    }
+}
+
+// AsyncShapeDelay submits a send when the egress shaper has the tokens for it.
+pub struct AsyncShapeDelay {
+    pub ts: types::Timespec,
+    pub op: Option<Box<AsyncOps>>,
+}
+
+impl AsyncShapeDelay {
+    pub fn New(delay: i64, op: AsyncOps) -> Self {
+        return Self {
+            ts: types::Timespec {
+                tv_sec: delay / 1000_000_000,
+                tv_nsec: delay % 1000_000_000,
+            },
+            op: Some(Box::new(op)),
+        };
+    }
+}
+
+impl AsyncOpsTrait for AsyncShapeDelay {
+    fn SEntry(&self) -> squeue::Entry {
+        let op = Timeout::new(&self.ts);
+        return op.build();
+    }
+
+    fn Process(&mut self, _result: i32) -> bool {
+        if let Some(op) = self.op.take() {
+            IOURING.AUCall(*op);
+        }
+
+        return false;
+    }
+}
+
 #[derive(Clone, Debug, Copy)]
 pub struct AsyncNone {}
 
@@ -13,7 +13,9 @@
 // limitations under the License.
 
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::sync::atomic;
+use core::sync::atomic::AtomicBool;
 use core::sync::atomic::AtomicU64;
 use core::sync::atomic::Ordering;
 
@@ -35,6 +37,7 @@ use super::super::super::vcpu_mgr::*;
 use super::super::kernel::async_wait::*;
 use super::super::kernel::waiter::qlock::*;
 use super::super::kernel::waiter::*;
+use super::super::socket::hostinet::loopbacksocket::*;
 use super::super::socket::hostinet::uring_socket::*;
 use super::super::Kernel::HostSpace;
 use super::super::IOURING;
@@ -341,6 +344,40 @@ impl QUring {
         IOURING.AUCall(AsyncOps::PollHostEpollWait(op));
     }
 
+    pub fn NetstackReadInit(&self, fd: i32, len: usize) {
+        let op = AsyncNetstackRead::New(fd, len);
+        IOURING.AUCall(AsyncOps::AsyncNetstackRead(op));
+    }
+
+    pub fn NetstackWrite(&self, fd: i32, frame: Vec<u8>) {
+        let op = AsyncNetstackWrite::New(fd, frame);
+        IOURING.AUCall(AsyncOps::AsyncNetstackWrite(op));
+    }
+
+    // RecvMultishotInit provides the buffers of the multishot recvmsg and then
+    // arms it, it returns the user_data of the recvmsg to cancel it.
+    pub fn RecvMultishotInit(
+        &self,
+        fd: i32,
+        dgrams: &DatagramQueue,
+        queue: &Queue,
+        armed: &Arc<AtomicBool>,
+    ) -> u64 {
+        let op = AsyncRecvMultishot::New(fd, dgrams, queue, armed);
+        let provide = op.ProvideAll();
+        let token = op.token;
+        let slot = IOURING.AUCallLinked(
+            AsyncOps::AsyncProvideBuffers(provide),
+            AsyncOps::AsyncRecvMultishot(op),
+        );
+        return AsyncUserData(token, slot);
+    }
+
+    pub fn AsyncCancel(&self, userData: u64) -> usize {
+        let op = AsyncCancelOp::New(userData);
+        return IOURING.AUCall(AsyncOps::AsyncCancelOp(op));
+    }
+
     pub fn BufSockInit(fd: i32, queue: Queue, buf: SocketBuff, isSocket: bool) -> Result<()> {
         let (addr, len) = buf.GetFreeReadBuf();
         let readop = AsyncFileRead::New(fd, queue, buf, addr, len, isSocket);
@@ -382,7 +419,7 @@ impl QUring {
         if let Some((addr, len)) = writeBuf {
             let writeop = AsyncSend::New(fd, queue, buf, addr, len, ops);
 
-            IOURING.AUCall(AsyncOps::AsyncSend(writeop));
+            IOURING.AUCall(writeop.Shaped());
         }
 
         return Ok(());
@@ -401,7 +438,7 @@ impl QUring {
         if let Some((addr, len)) = writeBuf {
             let writeop = AsyncSend::New(fd, queue, buf, addr, len, ops);
 
-            IOURING.AUCall(AsyncOps::AsyncSend(writeop));
+            IOURING.AUCall(writeop.Shaped());
         }
 
         return Ok(count as i64);
@@ -471,18 +508,18 @@ impl QUring {
         let ret = cqe.result();
 
         // the taskid should be larger than 0x1000 (4K)
-        if data > 0x10000 {
+        if data & ASYNC_TOKEN_TAG == 0 && data > 0x10000 {
             let call = unsafe { &mut *(data as *mut UringCall) };
 
             call.ret = ret;
             //error!("uring process: call is {:x?}", &call);
             ScheduleQ(call.taskId, true);
         } else {
-            let idx = data as usize;
+            let idx = (data & ASYNC_SLOT_MASK) as usize;
             let rerun = {
                 let mut ops = self.asyncMgr.ops[idx].lock();
                 //error!("uring process2: call is {:?}, idx {}", ops.Type(), idx);
-                ops.ProcessResult(ret, idx)
+                ops.ProcessResult(ret, cqe.flags(), idx)
             };
 
             if super::super::Shutdown() {
@@ -513,7 +550,7 @@ impl QUring {
     }
 
     pub fn AUCallDirect(&self, ops: &AsyncOps, id: usize) {
-        let entry = ops.SEntry().user_data(id as u64);
+        let entry = ops.SEntry().user_data(ops.UserData(id));
         self.AUringCall(entry)
     }
 
@@ -539,7 +576,8 @@ impl QUring {
         return index as usize;
     }
 
-    pub fn AUCallLinked(&self, ops1: AsyncOps, ops2: AsyncOps) {
+    // AUCallLinked returns the slot of ops2.
+    pub fn AUCallLinked(&self, ops1: AsyncOps, ops2: AsyncOps) -> usize {
         let index1;
 
         loop {
@@ -575,6 +613,32 @@ impl QUring {
         let entry2 = self.asyncMgr.SetOps(index2, ops2);
 
         self.AUringCallLinked(entry1, entry2);
+        return index2;
+    }
+
+    // AUCallLinkedDirect submits the op of slot id again after the links.
+    pub fn AUCallLinkedDirect(&self, links: Vec<AsyncOps>, ops: &AsyncOps, id: usize) {
+        let mut entries = Vec::with_capacity(links.len() + 1);
+        for link in links {
+            let index;
+            loop {
+                match self.asyncMgr.AllocSlot() {
+                    None => {
+                        self.asyncMgr.Print();
+                        print!("AUCall async slots usage up...");
+                    }
+                    Some(idx) => {
+                        index = idx;
+                        break;
+                    }
+                }
+            }
+
+            entries.push(self.asyncMgr.SetOps(index, link));
+        }
+
+        entries.push(ops.SEntry().user_data(ops.UserData(id)));
+        self.AUringCallChain(entries);
     }
 
     pub fn NextCompleteEntry(&self) -> Option<cqueue::Entry> {
@@ -673,32 +737,43 @@ impl QUring {
     }
 
     pub fn AUringCallLinked(&self, entry1: squeue::Entry, entry2: squeue::Entry) {
+        self.AUringCallChain(vec![entry1, entry2]);
+    }
+
+    // AUringCallChain submits the entries linked, each one starts once the
+    // previous one completes.
+    pub fn AUringCallChain(&self, entries: Vec<squeue::Entry>) {
+        let last = entries.len() - 1;
+        let entries = entries.into_iter().enumerate().map(|(i, entry)| {
+            if i < last {
+                entry.flags(squeue::Flags::IO_LINK)
+            } else {
+                entry
+            }
+        });
+
         if super::super::SHARESPACE.config.read().UringBuf {
             let mut s = self.IOUring().submitq.lock();
-            s.push_back(entry1.flags(squeue::Flags::IO_LINK));
-            s.push_back(entry2);
+            for entry in entries {
+                s.push_back(entry);
+            }
         } else {
             loop {
                 let mut s = self.IOUring().sq.lock();
-                if s.freeSlot() < Self::SUBMISSION_QUEUE_FREE_COUNT + 1 {
+                if s.freeSlot() < Self::SUBMISSION_QUEUE_FREE_COUNT + last {
                     drop(s);
                     super::super::super::ShareSpace::Yield();
-                    error!("AUringCallLinked: submission full... idx");
+                    error!("AUringCallChain: submission full... idx");
                     continue;
                 }
 
-                unsafe {
-                    match s.push(entry1.flags(squeue::Flags::IO_LINK)) {
-                        Ok(_) => (),
-                        Err(_e) => {
-                            panic!("AUringCallLinked push fail 1 ...");
-                        }
-                    }
-
-                    match s.push(entry2) {
-                        Ok(_) => (),
-                        Err(_e) => {
-                            panic!("AUringCallLinked push fail 2 ...");
+                for entry in entries {
+                    unsafe {
+                        match s.push(entry) {
+                            Ok(_) => (),
+                            Err(_e) => {
+                                panic!("AUringCallChain push fail ...");
+                            }
                         }
                     }
                 }
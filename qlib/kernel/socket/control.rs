@@ -268,6 +268,11 @@ pub struct TCPInfo {
 pub const SCM_RIGHTS: i32 = 0x1;
 pub const SCM_CREDENTIALS: i32 = 0x2;
 pub const SCM_TIMESTAMP: i32 = SO_TIMESTAMP;
+pub const SCM_TIMESTAMPNS: i32 = SO_TIMESTAMPNS;
+pub const SCM_TIMESTAMPING: i32 = SO_TIMESTAMPING;
+pub const SCM_TIMESTAMP_NEW: i32 = SO_TIMESTAMP_NEW;
+pub const SCM_TIMESTAMPNS_NEW: i32 = SO_TIMESTAMPNS_NEW;
+pub const SCM_TIMESTAMPING_NEW: i32 = SO_TIMESTAMPING_NEW;
 pub const SCM_TCP_INQ: i32 = 0x24; // /* Notify bytes available to read as a cmsg on read */
                                    // A ControlMessageHeader is the header for a socket control message.
                                    //
@@ -501,6 +506,53 @@ impl ControlMessage for ControlMessageRights {
     }
 }
 
+// A ControlMessageUDPGRO is the UDP_GRO control message, it holds the segment
+// size of a coalesced datagram.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ControlMessageUDPGRO(pub i32);
+
+impl ControlMessage for ControlMessageUDPGRO {
+    fn CMsgLevel(&self) -> i32 {
+        return SOL_UDP;
+    }
+
+    fn Len(&self) -> usize {
+        let headerLen = CMsgAlign(mem::size_of::<ControlMessageHeader>());
+        let bodyLen = mem::size_of_val(&self.0);
+        return headerLen + bodyLen;
+    }
+
+    fn CMsgType(&self) -> i32 {
+        return UDP_GRO;
+    }
+
+    fn EncodeInto<'a>(&self, buf: &'a mut [u8], flags: i32) -> (&'a mut [u8], i32) {
+        let mut flags = flags;
+        let length = 4 + mem::size_of::<ControlMessageHeader>();
+        if AlignDown(buf.len(), 4) < length {
+            flags |= MsgType::MSG_CTRUNC;
+            return (buf, flags);
+        }
+
+        let cmsg = ControlMessageHeader {
+            Length: length as u64,
+            Level: self.CMsgLevel(),
+            Type: self.CMsgType(),
+        };
+
+        let buf = CopyBytes(&cmsg, buf);
+        let buf = CopyBytes(&self.0, buf);
+
+        let aligned = AlignUp(length, ALIGNMENT) - length;
+        if aligned > buf.len() {
+            return (buf, flags);
+        }
+
+        return (&mut buf[aligned..], flags);
+    }
+}
+
 pub fn AlignSlice<'a>(buf: &'a mut [u8], align: usize) -> &'a mut [u8] {
     let aligned = AlignUp(buf.len(), align);
     if aligned > buf.len() {
@@ -514,7 +566,7 @@ pub fn AlignSlice<'a>(buf: &'a mut [u8], align: usize) -> &'a mut [u8] {
 }
 
 #[derive(Debug, Default, Clone)]
-pub struct ControlMessageTimeStamp(Timeval);
+pub struct ControlMessageTimeStamp(pub Timeval);
 
 impl ControlMessage for ControlMessageTimeStamp {
     fn CMsgLevel(&self) -> i32 {
@@ -564,6 +616,137 @@ impl ControlMessage for ControlMessageTimeStamp {
     }
 }
 
+// ControlMessageTimespecs is a SCM_TIMESTAMPNS (one timespec) or a
+// SCM_TIMESTAMPING (three timespecs) message. The _OLD and _NEW variants of
+// the messages both carry 64 bits timespecs on x86_64, only the type differs.
+#[derive(Debug, Default, Clone)]
+pub struct ControlMessageTimespecs {
+    pub Type: i32,
+    pub Ts: Vec<Timespec>,
+}
+
+impl ControlMessageTimespecs {
+    // NewTimestamp returns the SCM_TIMESTAMP(_NEW) message of ns, the timeval
+    // of the message has the layout of a timespec on x86_64.
+    pub fn NewTimestamp(typ: i32, ns: i64) -> Self {
+        let tv = Timeval::FromNs(ns);
+        return Self {
+            Type: typ,
+            Ts: vec![Timespec {
+                tv_sec: tv.Sec,
+                tv_nsec: tv.Usec,
+            }],
+        };
+    }
+
+    // NewTimestampNs returns the SCM_TIMESTAMPNS(_NEW) message of ns.
+    pub fn NewTimestampNs(typ: i32, ns: i64) -> Self {
+        return Self {
+            Type: typ,
+            Ts: vec![Timespec::FromNs(ns)],
+        };
+    }
+
+    // NewTimestamping returns the SCM_TIMESTAMPING(_NEW) message of a software
+    // timestamp, the legacy and the hardware timestamps are zero.
+    pub fn NewTimestamping(typ: i32, ns: i64) -> Self {
+        return Self {
+            Type: typ,
+            Ts: vec![Timespec::FromNs(ns), Timespec::default(), Timespec::default()],
+        };
+    }
+}
+
+impl ControlMessage for ControlMessageTimespecs {
+    fn CMsgLevel(&self) -> i32 {
+        return SOL_SOCKET;
+    }
+
+    fn Len(&self) -> usize {
+        let headerLen = CMsgAlign(mem::size_of::<ControlMessageHeader>());
+        let bodyLen = self.Ts.len() * mem::size_of::<Timespec>();
+        return headerLen + bodyLen;
+    }
+
+    fn CMsgType(&self) -> i32 {
+        return self.Type;
+    }
+
+    fn EncodeInto<'a>(&self, buf: &'a mut [u8], flags: i32) -> (&'a mut [u8], i32) {
+        let space = AlignDown(buf.len(), 4);
+        let mut flags = flags;
+
+        let length = self.Len();
+        if length > space {
+            flags |= MsgType::MSG_CTRUNC;
+            return (buf, flags);
+        }
+
+        let cmsg = ControlMessageHeader {
+            Length: length as _,
+            Level: self.CMsgLevel(),
+            Type: self.CMsgType(),
+        };
+
+        let mut buf = CopyBytes(&cmsg, buf);
+        for ts in &self.Ts {
+            buf = CopyBytes(ts, buf);
+        }
+
+        let aligned = AlignUp(length, ALIGNMENT) - length;
+        if aligned > buf.len() {
+            return (buf, flags);
+        }
+
+        return (&mut buf[aligned..], flags);
+    }
+}
+
+// AdjustTimestamps moves the timestamps of the messages received from a host
+// socket by offset ns, from the host realtime clock to the sandbox one. The
+// hardware timestamps of SCM_TIMESTAMPING are in the NIC clock and are kept.
+pub fn AdjustTimestamps(buf: &mut [u8], offset: i64) {
+    let mut i = 0;
+    while i + SIZE_OF_CONTROL_MESSAGE_HEADER <= buf.len() {
+        let h = unsafe { *(&buf[i] as *const _ as *const ControlMessageHeader) };
+        let len = h.Length as usize;
+        if len < SIZE_OF_CONTROL_MESSAGE_HEADER || len > buf.len() - i {
+            return;
+        }
+
+        let data = i + SIZE_OF_CONTROL_MESSAGE_HEADER;
+        let dataLen = len - SIZE_OF_CONTROL_MESSAGE_HEADER;
+        if h.Level == SOL_SOCKET && dataLen >= 16 {
+            let p = &mut buf[data] as *mut _ as *mut i64;
+            let (sec, frac) = unsafe { (p.read_unaligned(), p.add(1).read_unaligned()) };
+            let ns = match h.Type {
+                SCM_TIMESTAMP | SCM_TIMESTAMP_NEW => Some(sec * 1_000_000_000 + frac * 1000),
+                SCM_TIMESTAMPNS | SCM_TIMESTAMPNS_NEW | SCM_TIMESTAMPING
+                | SCM_TIMESTAMPING_NEW => Some(sec * 1_000_000_000 + frac),
+                _ => None,
+            };
+
+            // the zero timestamps are not set by the host
+            if let Some(ns) = ns {
+                if ns != 0 {
+                    let ns = ns + offset;
+                    let frac = if h.Type == SCM_TIMESTAMP || h.Type == SCM_TIMESTAMP_NEW {
+                        (ns % 1_000_000_000) / 1000
+                    } else {
+                        ns % 1_000_000_000
+                    };
+                    unsafe {
+                        p.write_unaligned(ns / 1_000_000_000);
+                        p.add(1).write_unaligned(frac);
+                    }
+                }
+            }
+        }
+
+        i += AlignUp(len, ALIGNMENT);
+    }
+}
+
 pub type AlignedOfCmsgData = usize;
 
 // Round `len` up to meet the platform's required alignment for
@@ -731,6 +914,39 @@ pub fn Parse(buf: &[u8]) -> Result<ControlMessages> {
     return Ok(ret);
 }
 
+// ParseUDPSegment returns the segment size of the UDP_SEGMENT message of a
+// udp send. It returns None when buf holds other messages, the host handles
+// those.
+pub fn ParseUDPSegment(buf: &[u8]) -> Result<Option<u16>> {
+    let mut segment = None;
+    let mut i = 0;
+    while i < buf.len() {
+        if i + SIZE_OF_CONTROL_MESSAGE_HEADER > buf.len() {
+            return Err(Error::SysError(SysErr::EINVAL));
+        }
+
+        let h = unsafe {
+            &*(buf[i..i + SIZE_OF_CONTROL_MESSAGE_HEADER].as_ptr() as *const ControlMessageHeader)
+        };
+
+        if (h.Length as usize) < SIZE_OF_CONTROL_MESSAGE_HEADER || h.Length as usize > buf.len() - i
+        {
+            return Err(Error::SysError(SysErr::EINVAL));
+        }
+
+        let length = h.Length as usize - SIZE_OF_CONTROL_MESSAGE_HEADER;
+        if h.Level != SOL_UDP || h.Type != UDP_SEGMENT || length < 2 {
+            return Ok(None);
+        }
+
+        i += SIZE_OF_CONTROL_MESSAGE_HEADER;
+        segment = Some(u16::from_ne_bytes([buf[i], buf[i + 1]]));
+        i += AlignUp(length, ALIGNMENT);
+    }
+
+    return Ok(segment);
+}
+
 pub fn MakeCreds(task: &Task, _cred: Option<BoundEndpoint>) -> Option<ScmCredentials> {
     //TODO: this is duplicating the function of scmCredentials::new, refactoring this
     /*let cr = match cred {
@@ -764,3 +980,104 @@ pub fn NewControlMessage(
         Rights: rights,
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn Header(buf: &[u8]) -> ControlMessageHeader {
+        return unsafe { *(&buf[0] as *const _ as *const ControlMessageHeader) };
+    }
+
+    fn Ts(buf: &[u8], off: usize) -> (i64, i64) {
+        let p = &buf[off] as *const _ as *const i64;
+        return unsafe { (p.read_unaligned(), p.add(1).read_unaligned()) };
+    }
+
+    fn Encode(msgs: &[ControlMessageTimespecs], len: usize) -> (Vec<u8>, i32) {
+        let mut buf = vec![0; len];
+        let mut flags = 0;
+        let used = {
+            let mut rest: &mut [u8] = &mut buf;
+            for m in msgs {
+                let (r, f) = m.EncodeInto(rest, flags);
+                rest = r;
+                flags = f;
+            }
+            len - rest.len()
+        };
+        buf.truncate(used);
+        return (buf, flags);
+    }
+
+    #[test]
+    fn test_TimestampEncoding() {
+        let ns = 1500 * 1_000_000_000 + 123_456;
+        let hdr = SIZE_OF_CONTROL_MESSAGE_HEADER;
+
+        let (buf, flags) = Encode(
+            &[ControlMessageTimespecs::NewTimestamp(SCM_TIMESTAMP, ns)],
+            64,
+        );
+        assert_eq!(flags, 0);
+        assert_eq!(buf.len(), hdr + 16);
+        let h = Header(&buf);
+        assert_eq!(h.Length as usize, hdr + 16);
+        assert_eq!(h.Level, SOL_SOCKET);
+        assert_eq!(h.Type, SCM_TIMESTAMP);
+        // the timeval is rounded up to the microsecond
+        assert_eq!(Ts(&buf, hdr), (1500, 124));
+
+        let msg = ControlMessageTimespecs::NewTimestampNs(SCM_TIMESTAMPNS_NEW, ns);
+        let (buf, _) = Encode(&[msg], 64);
+        assert_eq!(Header(&buf).Type, SCM_TIMESTAMPNS_NEW);
+        assert_eq!(Ts(&buf, hdr), (1500, 123_456));
+
+        let msg = ControlMessageTimespecs::NewTimestamping(SCM_TIMESTAMPING, ns);
+        let (buf, _) = Encode(&[msg], 128);
+        assert_eq!(Header(&buf).Length as usize, hdr + 48);
+        assert_eq!(Ts(&buf, hdr), (1500, 123_456));
+        assert_eq!(Ts(&buf, hdr + 16), (0, 0));
+        assert_eq!(Ts(&buf, hdr + 32), (0, 0));
+    }
+
+    #[test]
+    fn test_TimestampEncodingTruncated() {
+        let msgs = [
+            ControlMessageTimespecs::NewTimestampNs(SCM_TIMESTAMPNS, 1),
+            ControlMessageTimespecs::NewTimestamping(SCM_TIMESTAMPING, 1),
+        ];
+        let (buf, flags) = Encode(&msgs, 40);
+        assert_eq!(flags, MsgType::MSG_CTRUNC);
+        assert_eq!(buf.len(), 32);
+        assert_eq!(Header(&buf).Type, SCM_TIMESTAMPNS);
+    }
+
+    #[test]
+    fn test_AdjustTimestamps() {
+        let ns = 1500 * 1_000_000_000 + 999_999_000;
+        let msgs = [
+            ControlMessageTimespecs::NewTimestamp(SCM_TIMESTAMP_NEW, ns),
+            ControlMessageTimespecs::NewTimestampNs(SCM_TIMESTAMPNS, ns),
+            ControlMessageTimespecs::NewTimestamping(SCM_TIMESTAMPING, 0),
+            ControlMessageTimespecs::NewTimestampNs(SCM_TIMESTAMPNS, ns),
+        ];
+        let (mut buf, _) = Encode(&msgs, 256);
+
+        // the last message is cut, it is left alone
+        let len = buf.len() - 8;
+        AdjustTimestamps(&mut buf[..len], 2_000_000_500);
+
+        let hdr = SIZE_OF_CONTROL_MESSAGE_HEADER;
+        assert_eq!(Ts(&buf, hdr), (1502, 999_999));
+        assert_eq!(Ts(&buf, 32 + hdr), (1502, 999_999_500));
+        // the zero timestamps are not set by the host
+        assert_eq!(Ts(&buf, 64 + hdr), (0, 0));
+        assert_eq!(Ts(&buf, 128 + hdr), (1500, 999_999_000));
+
+        // the timestamps move back too
+        let (mut buf, _) = Encode(&msgs[1..2], 64);
+        AdjustTimestamps(&mut buf, -1_000_000_000);
+        assert_eq!(Ts(&buf, hdr), (1499, 999_999_000));
+    }
+}
@@ -1091,6 +1091,10 @@ impl SockOperations for AsyncSocketOperations {
         return info.State as u32;
     }
 
+    fn QueueSize(&self) -> (usize, usize) {
+        return HostQueueSize(self.fd);
+    }
+
     fn Type(&self) -> (i32, i32, i32) {
         return (self.family, self.stype, -1)
     }
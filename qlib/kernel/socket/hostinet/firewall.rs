@@ -0,0 +1,438 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::qlib::mutex::*;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::loader::*;
+use super::super::super::super::metric::*;
+use super::super::super::super::singleton::*;
+use super::super::super::task::*;
+use super::super::super::Kernel::GetSockOptI32;
+use super::super::super::Kernel::HostSpace;
+
+pub static FIREWALL_EGRESS_DENIED: Singleton<Arc<U64Metric>> = Singleton::<Arc<U64Metric>>::New();
+pub static FIREWALL_INGRESS_DENIED: Singleton<Arc<U64Metric>> = Singleton::<Arc<U64Metric>>::New();
+pub static FIREWALL_BIND_DENIED: Singleton<Arc<U64Metric>> = Singleton::<Arc<U64Metric>>::New();
+pub static FIREWALL_LOOPBACK_DENIED: Singleton<Arc<U64Metric>> = Singleton::<Arc<U64Metric>>::New();
+
+pub unsafe fn InitSingleton() {
+    FIREWALL_EGRESS_DENIED.Init(NewU64Metric(
+        "/net/firewall/egress_denied",
+        false,
+        "Number of connect and send calls denied by the sandbox firewall.",
+    ));
+    FIREWALL_INGRESS_DENIED.Init(NewU64Metric(
+        "/net/firewall/ingress_denied",
+        false,
+        "Number of accepted connections dropped by the sandbox firewall.",
+    ));
+    FIREWALL_BIND_DENIED.Init(NewU64Metric(
+        "/net/firewall/bind_denied",
+        false,
+        "Number of bind and listen calls denied by the sandbox firewall.",
+    ));
+    FIREWALL_LOOPBACK_DENIED.Init(NewU64Metric(
+        "/net/firewall/loopback_denied",
+        false,
+        "Number of loopback connections to the host denied by the sandbox firewall.",
+    ));
+}
+
+lazy_static! {
+    pub static ref FIREWALL: Firewall = Firewall::default();
+}
+
+// Endpoint is the ip address and the port of an AF_INET/AF_INET6 socket
+// address, the ipv4 mapped ipv6 addresses are returned as ipv4.
+pub struct Endpoint {
+    pub addr: Vec<u8>,
+    pub port: u16,
+}
+
+impl Endpoint {
+    pub fn Parse(sockaddr: &[u8]) -> Option<Self> {
+        if sockaddr.len() < 2 {
+            return None;
+        }
+
+        let port = if sockaddr.len() >= 4 {
+            u16::from_be_bytes([sockaddr[2], sockaddr[3]])
+        } else {
+            0
+        };
+
+        match u16::from_ne_bytes([sockaddr[0], sockaddr[1]]) as i32 {
+            AFType::AF_INET if sockaddr.len() >= 8 => {
+                return Some(Self {
+                    addr: sockaddr[4..8].to_vec(),
+                    port: port,
+                });
+            }
+            AFType::AF_INET6 if sockaddr.len() >= 24 => {
+                let addr = &sockaddr[8..24];
+                let mapped =
+                    addr[..10].iter().all(|b| *b == 0) && addr[10] == 0xff && addr[11] == 0xff;
+                let addr = if mapped { &addr[12..] } else { addr };
+                return Some(Self {
+                    addr: addr.to_vec(),
+                    port: port,
+                });
+            }
+            _ => return None,
+        }
+    }
+
+    pub fn IsLoopback(&self) -> bool {
+        if self.addr.len() == 4 {
+            return self.addr[0] == 127;
+        }
+
+        return self.addr[..15].iter().all(|b| *b == 0) && self.addr[15] == 1;
+    }
+}
+
+impl FirewallRule {
+    pub fn Matches(&self, ep: &Endpoint) -> bool {
+        if ep.port < self.PortLo || ep.port > self.PortHi {
+            return false;
+        }
+
+        if self.Addr.len() == 0 {
+            return true;
+        }
+
+        if self.Addr.len() != ep.addr.len() {
+            return false;
+        }
+
+        let bits = self.PrefixLen as usize;
+        let bytes = bits / 8;
+        if self.Addr[..bytes] != ep.addr[..bytes] {
+            return false;
+        }
+
+        if bits % 8 != 0 {
+            let mask = !(0xffu8 >> (bits % 8));
+            return self.Addr[bytes] & mask == ep.addr[bytes] & mask;
+        }
+
+        return true;
+    }
+}
+
+impl FirewallPolicy {
+    pub fn Allowed(&self, direction: FirewallDirection, ep: &Endpoint) -> bool {
+        for rule in &self.Rules {
+            if rule.Direction == direction && rule.Matches(ep) {
+                return rule.Allow;
+            }
+        }
+
+        match direction {
+            FirewallDirection::Egress => return self.DefaultEgress,
+            FirewallDirection::Ingress => return self.DefaultIngress,
+            FirewallDirection::Bind => return self.DefaultBind,
+        }
+    }
+}
+
+// Firewall holds the policies hostinet and the netstack consult before the
+// socket calls go out of the sandbox, a call must be allowed by all of them.
+// The sockets of the other families are not filtered.
+#[derive(Default)]
+pub struct Firewall {
+    pub policies: QRwLock<Vec<FirewallPolicy>>,
+    // host fd => port of the listening sockets of the sandbox. The entries
+    // are checked again before use as the fds can be closed and reused.
+    pub listeners: QMutex<BTreeMap<i32, u16>>,
+}
+
+impl Firewall {
+    pub fn SetPolicies(&self, policies: &[FirewallPolicy]) {
+        *self.policies.write() = policies.to_vec();
+    }
+
+    fn Allowed(policies: &[FirewallPolicy], direction: FirewallDirection, ep: &Endpoint) -> bool {
+        return policies.iter().all(|p| p.Allowed(direction, ep));
+    }
+
+    // ServedBySandbox returns whether a sandbox socket listens on port.
+    fn ServedBySandbox(&self, port: u16) -> bool {
+        let mut listeners = self.listeners.lock();
+        let fds: Vec<i32> = listeners
+            .iter()
+            .filter(|(_, p)| **p == port)
+            .map(|(fd, _)| *fd)
+            .collect();
+
+        for fd in fds {
+            let listening = GetSockOptI32(
+                fd,
+                LibcConst::SOL_SOCKET as i32,
+                LibcConst::SO_ACCEPTCONN as i32,
+            )
+            .unwrap_or(0)
+                != 0;
+            if listening && LocalPort(fd) == Some(port) {
+                return true;
+            }
+
+            listeners.remove(&fd);
+        }
+
+        return false;
+    }
+
+    // CheckEgress checks the destination sockaddr. host is whether the
+    // loopback destinations reach the host, they don't for the netstack
+    // sockets whose loopback stays in the sandbox.
+    fn CheckEgress(&self, sockaddr: &[u8], errno: i32, host: bool) -> Result<()> {
+        let policies = self.policies.read();
+        if policies.len() == 0 {
+            return Ok(());
+        }
+
+        let ep = match Endpoint::Parse(sockaddr) {
+            None => return Ok(()),
+            Some(ep) => ep,
+        };
+
+        let noLoopbackToHost = policies.iter().any(|p| p.NoLoopbackToHost);
+        if host && noLoopbackToHost && ep.IsLoopback() && !self.ServedBySandbox(ep.port) {
+            FIREWALL_LOOPBACK_DENIED.Incr();
+            return Err(Error::SysError(errno));
+        }
+
+        if !Self::Allowed(&policies, FirewallDirection::Egress, &ep) {
+            FIREWALL_EGRESS_DENIED.Incr();
+            return Err(Error::SysError(errno));
+        }
+
+        return Ok(());
+    }
+
+    pub fn CheckConnect(&self, sockaddr: &[u8]) -> Result<()> {
+        return self.CheckEgress(sockaddr, SysErr::ECONNREFUSED, true);
+    }
+
+    // CheckLocalConnect checks the connect of a socket whose loopback is
+    // served in the sandbox.
+    pub fn CheckLocalConnect(&self, sockaddr: &[u8]) -> Result<()> {
+        return self.CheckEgress(sockaddr, SysErr::ECONNREFUSED, false);
+    }
+
+    // CheckSendMsg checks the destination of an unconnected send, the
+    // connected sockets were checked on connect.
+    pub fn CheckSendMsg(&self, task: &Task, msgHdr: &MsgHdr) -> Result<()> {
+        if msgHdr.msgName == 0 || msgHdr.nameLen == 0 || self.policies.read().len() == 0 {
+            return Ok(());
+        }
+
+        let name: Vec<u8> = task.CopyInVec(msgHdr.msgName, msgHdr.nameLen as usize)?;
+        return self.CheckEgress(&name, SysErr::EACCES, true);
+    }
+
+    // CheckLocalSendTo checks the destination of an unconnected send of a
+    // socket whose loopback is served in the sandbox.
+    pub fn CheckLocalSendTo(&self, sockaddr: &[u8]) -> Result<()> {
+        return self.CheckEgress(sockaddr, SysErr::EACCES, false);
+    }
+
+    pub fn CheckBind(&self, sockaddr: &[u8]) -> Result<()> {
+        let policies = self.policies.read();
+        if policies.len() == 0 {
+            return Ok(());
+        }
+
+        // the ephemeral ports are checked on listen
+        let ep = match Endpoint::Parse(sockaddr) {
+            Some(ep) if ep.port != 0 => ep,
+            _ => return Ok(()),
+        };
+
+        if !Self::Allowed(&policies, FirewallDirection::Bind, &ep) {
+            FIREWALL_BIND_DENIED.Incr();
+            return Err(Error::SysError(SysErr::EACCES));
+        }
+
+        return Ok(());
+    }
+
+    // CheckListenAddr checks the local address sockaddr of a socket about to
+    // listen, it returns the port to record.
+    fn CheckListenAddr(&self, policies: &[FirewallPolicy], sockaddr: &[u8]) -> Result<Option<u16>> {
+        let ep = match Endpoint::Parse(sockaddr) {
+            None => return Ok(None),
+            Some(ep) => ep,
+        };
+
+        if ep.port != 0 && !Self::Allowed(policies, FirewallDirection::Bind, &ep) {
+            FIREWALL_BIND_DENIED.Incr();
+            return Err(Error::SysError(SysErr::EACCES));
+        }
+
+        return Ok(Some(ep.port));
+    }
+
+    // CheckListen checks the local address of the host socket fd, and then
+    // records it as a listener of the sandbox.
+    pub fn CheckListen(&self, fd: i32) -> Result<()> {
+        let policies = self.policies.read();
+        if policies.len() == 0 {
+            return Ok(());
+        }
+
+        let sockaddr = match LocalAddr(fd) {
+            None => return Ok(()),
+            Some(a) => a,
+        };
+
+        let port = match self.CheckListenAddr(&policies, &sockaddr)? {
+            None => return Ok(()),
+            Some(p) => p,
+        };
+
+        if policies.iter().any(|p| p.NoLoopbackToHost) {
+            self.listeners.lock().insert(fd, port);
+        }
+
+        return Ok(());
+    }
+
+    // CheckLocalListen checks the local address of a socket which doesn't
+    // listen on the host.
+    pub fn CheckLocalListen(&self, sockaddr: &[u8]) -> Result<()> {
+        let policies = self.policies.read();
+        if policies.len() == 0 {
+            return Ok(());
+        }
+
+        self.CheckListenAddr(&policies, sockaddr)?;
+        return Ok(());
+    }
+
+    // CheckAccept checks the peer of an accepted connection. The connection
+    // is dropped with ECONNABORTED as if the peer had reset it.
+    pub fn CheckAccept(&self, peer: &[u8]) -> Result<()> {
+        let policies = self.policies.read();
+        if policies.len() == 0 {
+            return Ok(());
+        }
+
+        let ep = match Endpoint::Parse(peer) {
+            None => return Ok(()),
+            Some(ep) => ep,
+        };
+
+        if !Self::Allowed(&policies, FirewallDirection::Ingress, &ep) {
+            FIREWALL_INGRESS_DENIED.Incr();
+            return Err(Error::SysError(SysErr::ECONNABORTED));
+        }
+
+        return Ok(());
+    }
+}
+
+// LocalAddr returns the local address of the host socket fd.
+pub fn LocalAddr(fd: i32) -> Option<Vec<u8>> {
+    let mut buf = [0u8; SIZEOF_SOCKADDR_STORAGE];
+    let len = buf.len() as u32;
+    let res = HostSpace::GetSockName(fd, &mut buf[0] as *mut _ as u64, &len as *const _ as u64);
+    if res < 0 {
+        return None;
+    }
+
+    return Some(buf[..core::cmp::min(len as usize, buf.len())].to_vec());
+}
+
+fn LocalPort(fd: i32) -> Option<u16> {
+    return Endpoint::Parse(&LocalAddr(fd)?).map(|ep| ep.port);
+}
+
+const SIZEOF_SOCKADDR_STORAGE: usize = 128;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn Ep(addr: &[u8], port: u16) -> Endpoint {
+        return Endpoint {
+            addr: addr.to_vec(),
+            port: port,
+        };
+    }
+
+    fn Rule(allow: bool, addr: &[u8], prefixLen: u8) -> FirewallRule {
+        return FirewallRule {
+            Direction: FirewallDirection::Egress,
+            Allow: allow,
+            Addr: addr.to_vec(),
+            PrefixLen: prefixLen,
+            PortLo: 0,
+            PortHi: u16::MAX,
+        };
+    }
+
+    #[test]
+    fn test_RuleMatches() {
+        let rule = Rule(true, &[10, 128, 0, 0], 9);
+        assert!(rule.Matches(&Ep(&[10, 200, 1, 1], 80)));
+        assert!(!rule.Matches(&Ep(&[10, 1, 1, 1], 80)));
+        assert!(!rule.Matches(&Ep(&[0; 16], 80)));
+        assert!(Rule(true, &[], 0).Matches(&Ep(&[0; 16], 80)));
+    }
+
+    #[test]
+    fn test_PoliciesIntersect() {
+        // the host allows 10.0.0.0/8, the sandbox narrows it to 10.1.0.0/16
+        let mut host = FirewallPolicy::default();
+        host.DefaultEgress = false;
+        host.Rules.push(Rule(true, &[10, 0, 0, 0], 8));
+
+        let mut sandbox = FirewallPolicy::default();
+        sandbox.DefaultEgress = false;
+        sandbox.Rules.push(Rule(true, &[10, 1, 0, 0], 16));
+        let policies = vec![host.clone(), sandbox];
+
+        let dir = FirewallDirection::Egress;
+        assert!(Firewall::Allowed(&policies, dir, &Ep(&[10, 1, 2, 3], 443)));
+        assert!(!Firewall::Allowed(&policies, dir, &Ep(&[10, 2, 2, 3], 443)));
+
+        // a sandbox policy allowing everything doesn't widen the host one
+        let policies = vec![host, FirewallPolicy::default()];
+        assert!(!Firewall::Allowed(&policies, dir, &Ep(&[8, 8, 8, 8], 53)));
+        assert!(Firewall::Allowed(&[], dir, &Ep(&[8, 8, 8, 8], 53)));
+    }
+
+    #[test]
+    fn test_EndpointParse() {
+        let mut sa = [0u8; 28];
+        sa[0..2].copy_from_slice(&(AFType::AF_INET6 as u16).to_ne_bytes());
+        sa[2..4].copy_from_slice(&8080u16.to_be_bytes());
+        sa[18] = 0xff;
+        sa[19] = 0xff;
+        sa[20..24].copy_from_slice(&[127, 0, 0, 1]);
+        let ep = Endpoint::Parse(&sa).unwrap();
+        assert_eq!(ep.addr, vec![127, 0, 0, 1]);
+        assert_eq!(ep.port, 8080);
+        assert!(ep.IsLoopback());
+        assert!(Endpoint::Parse(&sa[..10]).is_none());
+    }
+}
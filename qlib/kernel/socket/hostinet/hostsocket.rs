@@ -13,17 +13,35 @@
 // limitations under the License.
 
 use crate::qlib::mutex::*;
+use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::any::Any;
 use core::ops::Deref;
 use core::ptr;
+use core::slice;
 use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicI32;
 use core::sync::atomic::AtomicI64;
+use core::sync::atomic::AtomicU16;
+use core::sync::atomic::AtomicU64;
 use core::sync::atomic::Ordering;
 
 //use super::super::*;
 use super::super::super::super::common::*;
+use super::super::super::super::linux::socket::SOF_TIMESTAMPING_RX_SOFTWARE;
+use super::super::super::super::linux::socket::SOF_TIMESTAMPING_SOFTWARE;
+use super::super::super::super::linux::socket::SOL_UDP;
+use super::super::super::super::linux::socket::SO_GET_FILTER;
+use super::super::super::super::linux::socket::SO_TIMESTAMP;
+use super::super::super::super::linux::socket::SO_TIMESTAMPING;
+use super::super::super::super::linux::socket::SO_TIMESTAMPING_NEW;
+use super::super::super::super::linux::socket::SO_TIMESTAMPNS;
+use super::super::super::super::linux::socket::SO_TIMESTAMPNS_NEW;
+use super::super::super::super::linux::socket::SO_TIMESTAMP_NEW;
+use super::super::super::super::linux::socket::UDP_GRO;
+use super::super::super::super::linux::socket::UDP_MAX_SEGMENTS;
+use super::super::super::super::linux::socket::UDP_SEGMENT;
 use super::super::super::super::linux::time::Timeval;
 use super::super::super::super::linux_def::*;
 use super::super::super::super::socket_buf::*;
@@ -39,15 +57,23 @@ use super::super::super::kernel::async_wait::*;
 use super::super::super::kernel::fd_table::*;
 use super::super::super::kernel::kernel::GetKernel;
 use super::super::super::kernel::time::*;
+use super::super::super::kernel::timer::RealNow;
+use super::super::super::kernel::timer::TIME_KEEPER;
 use super::super::super::kernel::waiter::*;
 use super::super::super::task::*;
 use super::super::super::tcpip::tcpip::*;
 use super::super::super::Kernel;
 use super::super::super::Kernel::HostSpace;
 use super::super::super::IOURING;
+use super::super::super::SHARESPACE;
 use super::super::control::*;
 use super::super::socket::*;
+use super::super::super::kernel::abstract_socket_namespace::*;
+use super::firewall::*;
+use super::loopbacksocket::*;
+use super::packet::*;
 use super::socket::*;
+use super::traffic::*;
 
 pub fn newHostSocketFile(
     task: &Task,
@@ -68,6 +94,7 @@ pub fn newHostSocketFile(
         hostiops.Queue(),
         hostiops.clone(),
         addr,
+        NET_TRAFFIC.ForTask(task),
     )?;
 
     let file = File::New(
@@ -95,11 +122,46 @@ pub struct HostSocketOperationsIntern {
     pub remoteAddr: QMutex<Option<SockAddr>>,
     pub hostops: HostInodeOp,
     passInq: AtomicBool,
+    // the udp datagrams sent by the other sockets of the sandbox
+    pub dgrams: DatagramQueue,
+    pub loopbackPort: AtomicU16,
+    // the UDP_SEGMENT and UDP_GRO options, which also apply to the datagrams
+    // of the sandbox
+    pub gsoSize: AtomicU16,
+    pub gro: AtomicBool,
+    // whether the multishot recvmsg of the host socket is armed, and its
+    // uring user_data. The user_data is 0 before it is armed.
+    pub multishot: Arc<AtomicBool>,
+    pub multishotData: AtomicU64,
+    // the SO_TIMESTAMP(NS) option and the SO_TIMESTAMPING option (type << 32
+    // | flags), the receive timestamps are also reported for the datagrams of
+    // the sandbox
+    pub rxTstamp: AtomicI32,
+    pub tstamping: AtomicU64,
+    // the traffic counters of the container of the socket
+    pub traffic: Arc<ContainerTraffic>,
 }
 
 #[derive(Clone)]
 pub struct HostSocketOperations(Arc<HostSocketOperationsIntern>);
 
+impl Drop for HostSocketOperations {
+    fn drop(&mut self) {
+        if Arc::strong_count(&self.0) == 1 {
+            let loopbackPort = self.loopbackPort.load(Ordering::Acquire);
+            if loopbackPort > 0 {
+                UDP_SOCKET.Remove(loopbackPort, &self.dgrams);
+            }
+
+            if self.multishot.load(Ordering::Acquire) {
+                IOURING.AsyncCancel(self.multishotData.load(Ordering::Acquire));
+            }
+
+            RemoveSocketFilter(self.fd);
+        }
+    }
+}
+
 impl HostSocketOperations {
     pub fn New(
         family: i32,
@@ -108,6 +170,7 @@ impl HostSocketOperations {
         queue: Queue,
         hostops: HostInodeOp,
         addr: Option<Vec<u8>>,
+        traffic: Arc<ContainerTraffic>,
     ) -> Result<Self> {
         let addr = match addr {
             None => None,
@@ -130,12 +193,692 @@ impl HostSocketOperations {
             remoteAddr: QMutex::new(addr),
             hostops: hostops,
             passInq: AtomicBool::new(false),
+            dgrams: DatagramQueue::default(),
+            loopbackPort: AtomicU16::new(0),
+            gsoSize: AtomicU16::new(0),
+            gro: AtomicBool::new(false),
+            multishot: Arc::new(AtomicBool::new(false)),
+            multishotData: AtomicU64::new(0),
+            rxTstamp: AtomicI32::new(0),
+            tstamping: AtomicU64::new(0),
+            traffic: traffic,
         };
 
         let ret = Self(Arc::new(ret));
         return Ok(ret);
     }
 
+    fn IsInetDgram(&self) -> bool {
+        return (self.family == AFType::AF_INET || self.family == AFType::AF_INET6)
+            && self.stype == SockType::SOCK_DGRAM;
+    }
+
+    // CountConnection counts an established tcp connection, passive is the
+    // one of accept.
+    fn CountConnection(&self, passive: bool) {
+        if self.stype == SockType::SOCK_STREAM {
+            self.traffic.ext.Connected();
+            self.Snmp().Open(passive);
+        }
+    }
+
+    fn Snmp(&self) -> &'static ProtoCounters {
+        return NET_SNMP.Proto(self.family, self.stype);
+    }
+
+    // RegisterLoopback records the bound address of a udp socket so that the
+    // datagrams from the sandbox are delivered through the guest memory.
+    fn RegisterLoopback(&self) {
+        if !self.IsInetDgram() || self.loopbackPort.load(Ordering::Acquire) != 0 {
+            return;
+        }
+
+        let ep = match LocalAddr(self.fd).and_then(|a| Endpoint::Parse(&a)) {
+            Some(ep) if ep.port != 0 => ep,
+            _ => return,
+        };
+
+        self.loopbackPort.store(ep.port, Ordering::Release);
+        UDP_SOCKET.Add(
+            ep.port,
+            UDPBinding {
+                addr: ep.addr,
+                family: self.family,
+                dgrams: self.dgrams.Downgrade(),
+                queue: self.queue.clone(),
+            },
+        );
+    }
+
+    // SendLoopback delivers a datagram to a udp socket of the sandbox. It
+    // returns None when the datagram goes through the host. A datagram with a
+    // segment size is split as the UDP_SEGMENT send of the host would be.
+    fn SendLoopback(&self, dst: &[u8], data: &[u8], segment: u16) -> Option<Result<i64>> {
+        let ep = match SandboxDst(self.family, dst, false) {
+            Err(e) => return Some(Err(e)),
+            Ok(ep) => ep?,
+        };
+
+        let segment = segment as usize;
+        if segment > 0 && data.len() > segment * UDP_MAX_SEGMENTS {
+            return Some(Err(Error::SysError(SysErr::EINVAL)));
+        }
+
+        let (family, dgrams, queue) = UDP_SOCKET.Get(&ep.addr, ep.port)?;
+
+        // the receiver replies to the port of the sender, bind it as the
+        // first send does on Linux
+        if self.loopbackPort.load(Ordering::Acquire) == 0 {
+            let bound = LocalAddr(self.fd).and_then(|a| Endpoint::Parse(&a));
+            if bound.map(|ep| ep.port).unwrap_or(0) == 0 {
+                let any = if self.family == AFType::AF_INET6 { vec![0; 16] } else { vec![0; 4] };
+                let addr = SockAddrBytes(self.family, &any, 0);
+                let res = Kernel::HostSpace::Bind(self.fd, &addr[0] as *const _ as u64, addr.len() as u32, 0);
+                if res < 0 {
+                    return Some(Err(Error::SysError(-res as i32)));
+                }
+            }
+            self.RegisterLoopback();
+        }
+
+        let src = LocalAddr(self.fd).and_then(|a| Endpoint::Parse(&a))?;
+        // a socket bound to the wildcard address sends from the destination
+        let srcAddr = if src.addr.iter().all(|b| *b == 0) {
+            ep.addr.clone()
+        } else {
+            src.addr
+        };
+
+        let from = SockAddrBytes(family, &srcAddr, src.port);
+        let segs: Vec<&[u8]> = if segment > 0 && data.len() > 0 {
+            data.chunks(segment).collect()
+        } else {
+            vec![data]
+        };
+
+        let time = RealNow();
+        let mut queued = false;
+        for seg in segs {
+            let d = Datagram {
+                data: seg.to_vec(),
+                from: from.clone(),
+                control: Vec::new(),
+                time: time,
+                flags: 0,
+            };
+
+            // a datagram over the receive buffer is dropped as udp does
+            queued |= dgrams.Enq(d);
+        }
+
+        if queued {
+            queue.Notify(READABLE_EVENT);
+        }
+
+        return Some(Ok(data.len() as i64));
+    }
+
+    // IsLoopbackDst returns whether a datagram to dst is delivered in the guest.
+    fn IsLoopbackDst(&self, dst: &[u8]) -> bool {
+        return match Endpoint::Parse(dst) {
+            Some(ep) => {
+                LocalDst(&INTERFACES.read(), self.family, dst).is_some()
+                    && UDP_SOCKET.Get(&ep.addr, ep.port).is_some()
+            }
+            None => false,
+        };
+    }
+
+    // ArmMultishot starts the multishot recvmsg of a udp socket, the host then
+    // queues the datagrams in the guest without a submission for each one.
+    fn ArmMultishot(&self) {
+        if !SHARESPACE.config.read().UDPMultishotRecv || !self.IsInetDgram() {
+            return;
+        }
+
+        // 1 marks the arming in progress, the user_data has ASYNC_TOKEN_TAG
+        if self
+            .multishotData
+            .compare_exchange(0, 1, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return;
+        }
+
+        self.multishot.store(true, Ordering::Release);
+        let data = IOURING.RecvMultishotInit(self.fd, &self.dgrams, &self.queue, &self.multishot);
+        self.multishotData.store(data, Ordering::Release);
+    }
+
+    fn MultishotArmed(&self) -> bool {
+        return self.multishot.load(Ordering::Acquire);
+    }
+
+    // HostRecvMsg receives from the host socket, unless the multishot recvmsg
+    // takes the datagrams.
+    fn HostRecvMsg(&self, msgHdr: &mut MsgHdr, flags: i32) -> i32 {
+        if flags & MsgType::MSG_ERRQUEUE == 0 && self.MultishotArmed() {
+            return -SysErr::EWOULDBLOCK;
+        }
+
+        return Kernel::HostSpace::IORecvMsg(
+            self.fd,
+            msgHdr as *mut _ as u64,
+            flags | MsgType::MSG_DONTWAIT,
+            false,
+        ) as i32;
+    }
+
+    fn RecvLoopback(
+        &self,
+        task: &Task,
+        dsts: &mut [IoVec],
+        flags: i32,
+        senderRequested: bool,
+        controlDataLen: usize,
+    ) -> Option<Result<(i64, i32, Option<(SockAddr, usize)>, Vec<u8>)>> {
+        if flags & MsgType::MSG_ERRQUEUE != 0 {
+            return None;
+        }
+
+        let peek = flags & MsgType::MSG_PEEK != 0;
+        let (d, segment) = if self.gro.load(Ordering::Relaxed) && !peek {
+            self.dgrams.DeqGRO(IoVec::NumBytes(dsts))?
+        } else {
+            (self.dgrams.Deq(peek)?, 0)
+        };
+
+        let count = match task.CopyDataOutToIovs(&d.data, dsts, false) {
+            Err(e) => return Some(Err(e)),
+            Ok(n) => n,
+        };
+
+        // the datagrams of the multishot recvmsg come from the host
+        if !peek {
+            self.traffic.Counters(d.time != 0).RxPackets(count as u64, 1);
+            self.Snmp().InPackets(1);
+        }
+
+        let mut msgFlags = d.flags;
+        let mut res = count;
+        if count < d.data.len() {
+            msgFlags |= MsgType::MSG_TRUNC;
+            if flags & MsgType::MSG_TRUNC != 0 {
+                res = d.data.len();
+            }
+        }
+
+        let senderAddr = if senderRequested {
+            match GetAddr(d.from[0] as i16, &d.from) {
+                Err(e) => return Some(Err(e)),
+                Ok(addr) => {
+                    let l = addr.Len();
+                    Some((addr, l))
+                }
+            }
+        } else {
+            None
+        };
+
+        let mut control = d.control;
+        if segment > 0 || d.time != 0 {
+            control = self.LoopbackControl(segment, d.time, controlDataLen, &mut msgFlags);
+        } else if control.len() > controlDataLen {
+            control.truncate(controlDataLen);
+            msgFlags |= MsgType::MSG_CTRUNC;
+        }
+
+        return Some(Ok((res as i64, msgFlags, senderAddr, control)));
+    }
+
+    // LoopbackControl encodes the receive timestamps and the UDP_GRO message of
+    // a datagram of the sandbox.
+    fn LoopbackControl(
+        &self,
+        segment: usize,
+        time: i64,
+        controlDataLen: usize,
+        msgFlags: &mut i32,
+    ) -> Vec<u8> {
+        let mut msgs: Vec<Box<ControlMessage>> = Vec::new();
+        if time != 0 {
+            match self.rxTstamp.load(Ordering::Relaxed) {
+                SO_TIMESTAMP => {
+                    msgs.push(Box::new(ControlMessageTimespecs::NewTimestamp(SCM_TIMESTAMP, time)))
+                }
+                SO_TIMESTAMP_NEW => msgs.push(Box::new(ControlMessageTimespecs::NewTimestamp(
+                    SCM_TIMESTAMP_NEW,
+                    time,
+                ))),
+                SO_TIMESTAMPNS | SO_TIMESTAMPNS_NEW => {
+                    let typ = self.rxTstamp.load(Ordering::Relaxed);
+                    msgs.push(Box::new(ControlMessageTimespecs::NewTimestampNs(typ, time)))
+                }
+                _ => (),
+            }
+
+            let tstamping = self.tstamping.load(Ordering::Relaxed);
+            let flags = tstamping as u32;
+            if flags & SOF_TIMESTAMPING_RX_SOFTWARE != 0 && flags & SOF_TIMESTAMPING_SOFTWARE != 0 {
+                let typ = (tstamping >> 32) as i32;
+                msgs.push(Box::new(ControlMessageTimespecs::NewTimestamping(typ, time)));
+            }
+        }
+
+        if segment > 0 {
+            msgs.push(Box::new(ControlMessageUDPGRO(segment as i32)));
+        }
+
+        let mut buf = vec![0; controlDataLen];
+        let used = {
+            let mut rest: &mut [u8] = &mut buf;
+            for m in &msgs {
+                let (r, f) = m.EncodeInto(rest, *msgFlags);
+                rest = r;
+                *msgFlags = f;
+            }
+            controlDataLen - rest.len()
+        };
+        buf.truncate(used);
+        return buf;
+    }
+
+    // RecordUdpOpt records the udp offloads, which are also applied to the
+    // datagrams of the sandbox.
+    fn RecordUdpOpt(&self, name: i32, opt: &[u8]) {
+        if opt.len() < 4 {
+            return;
+        }
+
+        let val = unsafe { *(&opt[0] as *const _ as u64 as *const i32) };
+        if name == UDP_SEGMENT {
+            self.gsoSize.store(val as u16, Ordering::Relaxed);
+        } else if name == UDP_GRO {
+            self.gro.store(val != 0, Ordering::Relaxed);
+        }
+    }
+
+    // RecordTimestampOpt records the timestamp options, which are also applied
+    // to the datagrams of the sandbox.
+    fn RecordTimestampOpt(&self, name: i32, opt: &[u8]) {
+        if opt.len() < 4 {
+            return;
+        }
+
+        let val = unsafe { *(&opt[0] as *const _ as u64 as *const i32) };
+        match name {
+            SO_TIMESTAMP | SO_TIMESTAMP_NEW | SO_TIMESTAMPNS | SO_TIMESTAMPNS_NEW => {
+                self.rxTstamp
+                    .store(if val != 0 { name } else { 0 }, Ordering::Relaxed);
+            }
+            SO_TIMESTAMPING | SO_TIMESTAMPING_NEW => {
+                let typ = if name == SO_TIMESTAMPING {
+                    SCM_TIMESTAMPING
+                } else {
+                    SCM_TIMESTAMPING_NEW
+                };
+                self.tstamping.store(
+                    ((typ as u64) << 32) | val as u32 as u64,
+                    Ordering::Relaxed,
+                );
+            }
+            _ => (),
+        }
+    }
+
+    // HostRecvMMsg receives the datagrams with one recvmmsg of the host socket.
+    // They are staged in kernel buffers and then copied to the user buffers.
+    // A datagram of the sandbox which arrives while blocked is received from
+    // the guest queue instead.
+    fn HostRecvMMsg(
+        &self,
+        task: &Task,
+        msgs: &mut [MMsgHdr],
+        flags: i32,
+        deadline: Option<Time>,
+    ) -> Option<Result<i64>> {
+        let cnt = msgs.len();
+        let mut dsts = Vec::with_capacity(cnt);
+        let mut bufs = Vec::with_capacity(cnt);
+        let mut iovs = Vec::with_capacity(cnt);
+        let mut names = vec![[0u8; SIZEOF_SOCKADDR]; cnt];
+        let mut controls = Vec::with_capacity(cnt);
+        for m in msgs.iter() {
+            let hdr = &m.msgHdr;
+            let dst = match task.IovsFromAddr(hdr.iov, hdr.iovLen) {
+                Err(e) => return Some(Err(e)),
+                Ok(dst) => dst,
+            };
+
+            let buf = DataBuff::New(IoVec::NumBytes(&dst));
+            iovs.push(IoVec::NewFromAddr(buf.Ptr(), buf.Len()));
+            let controlLen = if hdr.msgControl != 0 { hdr.msgControlLen } else { 0 };
+            controls.push(vec![0u8; controlLen]);
+            dsts.push(dst);
+            bufs.push(buf);
+        }
+
+        let mut hostMsgs = Vec::with_capacity(cnt);
+        for i in 0..cnt {
+            let mut hdr = MsgHdr::default();
+            hdr.iov = &iovs[i] as *const _ as u64;
+            hdr.iovLen = 1;
+            if msgs[i].msgHdr.msgName != 0 && msgs[i].msgHdr.nameLen > 0 {
+                hdr.msgName = &mut names[i][0] as *mut _ as u64;
+                hdr.nameLen = SIZEOF_SOCKADDR as u32;
+            }
+
+            if controls[i].len() > 0 {
+                hdr.msgControl = &mut controls[i][0] as *mut _ as u64;
+                hdr.msgControlLen = controls[i].len();
+            }
+
+            hostMsgs.push(MMsgHdr {
+                msgHdr: hdr,
+                msgLen: 0,
+            });
+        }
+
+        let general = task.blocker.generalEntry.clone();
+        self.EventRegister(task, &general, EVENT_READ);
+        defer!(self.EventUnregister(task, &general));
+
+        let msgvec = &mut hostMsgs[0] as *mut _ as u64;
+        let mut res = Kernel::HostSpace::IORecvMMsg(
+            self.fd,
+            msgvec,
+            cnt as u32,
+            flags | MsgType::MSG_DONTWAIT,
+        ) as i32;
+
+        while res == -SysErr::EWOULDBLOCK
+            && flags & (MsgType::MSG_DONTWAIT | MsgType::MSG_ERRQUEUE) == 0
+        {
+            match task.blocker.BlockWithMonoTimer(true, deadline) {
+                Err(Error::ErrInterrupted) => {
+                    return Some(Err(Error::SysError(SysErr::ERESTARTSYS)));
+                }
+                Err(Error::SysError(SysErr::ETIMEDOUT)) => {
+                    return Some(Err(Error::SysError(SysErr::EAGAIN)));
+                }
+                Err(e) => {
+                    return Some(Err(e));
+                }
+                _ => (),
+            }
+
+            if let Some(res) = self.DeqBatch(task, msgs, flags) {
+                return Some(res);
+            }
+
+            res = Kernel::HostSpace::IORecvMMsg(
+                self.fd,
+                msgvec,
+                cnt as u32,
+                flags | MsgType::MSG_DONTWAIT,
+            ) as i32;
+        }
+
+        if res < 0 {
+            let err = Error::SysError(-res as i32);
+            self.traffic.ext.RxError(&err);
+            self.Snmp().RxError(&err);
+            return Some(Err(err));
+        }
+
+        let bytes: u64 = hostMsgs[..res as usize].iter().map(|m| m.msgLen as u64).sum();
+        if flags & (MsgType::MSG_PEEK | MsgType::MSG_ERRQUEUE) == 0 {
+            self.traffic.ext.RxPackets(bytes, res as u64);
+            self.Snmp().InPackets(res as u64);
+        }
+
+        for i in 0..res as usize {
+            let host = &hostMsgs[i];
+            let n = host.msgLen as usize;
+            let count = core::cmp::min(n, bufs[i].buf.len());
+            if let Err(e) = task.CopyDataOutToIovs(&bufs[i].buf[..count], &dsts[i], false) {
+                return Some(Err(e));
+            }
+
+            let hdr = &mut msgs[i].msgHdr;
+            if hdr.msgName != 0 && hdr.nameLen > 0 {
+                let len = core::cmp::min(host.msgHdr.nameLen, hdr.nameLen) as usize;
+                if let Err(e) = task.CopyOutSlice(&names[i][..len], hdr.msgName, len) {
+                    return Some(Err(e));
+                }
+                hdr.nameLen = host.msgHdr.nameLen;
+            } else {
+                hdr.nameLen = 0;
+            }
+
+            let controlLen = host.msgHdr.msgControlLen;
+            if controlLen > 0 {
+                AdjustTimestamps(&mut controls[i][..controlLen], TIME_KEEPER.HostRealtimeOffset());
+                if let Err(e) = task.CopyOutSlice(&controls[i][..controlLen], hdr.msgControl, controlLen) {
+                    return Some(Err(e));
+                }
+            }
+
+            hdr.msgControlLen = controlLen;
+            hdr.msgFlags = host.msgHdr.msgFlags;
+            msgs[i].msgLen = n as u32;
+        }
+
+        return Some(Ok(res as i64));
+    }
+
+    // DeqBatch fills the batch with the datagrams queued in the guest, None
+    // means that none is queued.
+    fn DeqBatch(&self, task: &Task, msgs: &mut [MMsgHdr], flags: i32) -> Option<Result<i64>> {
+        // a peek gets the same datagram for each message
+        let cnt = if flags & MsgType::MSG_PEEK != 0 {
+            1
+        } else {
+            msgs.len()
+        };
+
+        return RecvBatch(cnt, |i| {
+            let hdr = &mut msgs[i].msgHdr;
+            let mut dsts = match task.IovsFromAddr(hdr.iov, hdr.iovLen) {
+                Err(e) => return Some(Err(e)),
+                Ok(dsts) => dsts,
+            };
+
+            let senderRequested = hdr.msgName != 0 && hdr.nameLen > 0;
+            let controlLen = if hdr.msgControl != 0 {
+                hdr.msgControlLen
+            } else {
+                0
+            };
+            let (n, msgFlags, sender, control) =
+                match self.RecvLoopback(task, &mut dsts, flags, senderRequested, controlLen)? {
+                    Err(e) => return Some(Err(e)),
+                    Ok(res) => res,
+                };
+
+            if let Some((addr, len)) = sender {
+                let mut name = vec![0u8; len];
+                if let Err(e) = addr.Marsh(&mut name, len) {
+                    return Some(Err(e));
+                }
+
+                let len = core::cmp::min(len, hdr.nameLen as usize);
+                if let Err(e) = task.CopyOutSlice(&name[..len], hdr.msgName, len) {
+                    return Some(Err(e));
+                }
+                hdr.nameLen = name.len() as u32;
+            } else {
+                hdr.nameLen = 0;
+            }
+
+            if control.len() > 0 {
+                if let Err(e) = task.CopyOutSlice(&control, hdr.msgControl, control.len()) {
+                    return Some(Err(e));
+                }
+            }
+
+            hdr.msgControlLen = control.len();
+            hdr.msgFlags = msgFlags;
+            msgs[i].msgLen = n as u32;
+            return Some(Ok(()));
+        });
+    }
+
+    // RecvQueued receives a batch of the datagrams queued in the guest. While
+    // the multishot recvmsg takes the host datagrams it waits for them, else
+    // None means that the batch is received from the host socket.
+    fn RecvQueued(
+        &self,
+        task: &Task,
+        msgs: &mut [MMsgHdr],
+        flags: i32,
+        deadline: Option<Time>,
+    ) -> Option<Result<i64>> {
+        if flags & MsgType::MSG_ERRQUEUE != 0 {
+            return None;
+        }
+
+        let general = task.blocker.generalEntry.clone();
+        self.EventRegister(task, &general, EVENT_READ);
+        defer!(self.EventUnregister(task, &general));
+
+        loop {
+            if let Some(res) = self.DeqBatch(task, msgs, flags) {
+                return Some(res);
+            }
+
+            if !self.MultishotArmed() {
+                return None;
+            }
+
+            if flags & MsgType::MSG_DONTWAIT != 0 {
+                return Some(Err(Error::SysError(SysErr::EWOULDBLOCK)));
+            }
+
+            match task.blocker.BlockWithMonoTimer(true, deadline) {
+                Err(Error::ErrInterrupted) => {
+                    return Some(Err(Error::SysError(SysErr::ERESTARTSYS)));
+                }
+                Err(Error::SysError(SysErr::ETIMEDOUT)) => {
+                    return Some(Err(Error::SysError(SysErr::EAGAIN)));
+                }
+                Err(e) => {
+                    return Some(Err(e));
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // HostSendMMsg sends the datagrams with one sendmmsg of the host socket.
+    fn HostSendMMsg(
+        &self,
+        task: &Task,
+        msgs: &mut [MMsgHdr],
+        flags: i32,
+        deadline: Option<Time>,
+    ) -> Result<i64> {
+        let cnt = msgs.len();
+        let mut bufs = Vec::with_capacity(cnt);
+        let mut iovs = Vec::with_capacity(cnt);
+        let mut names = Vec::with_capacity(cnt);
+        let mut controls = Vec::with_capacity(cnt);
+        for m in msgs.iter() {
+            let hdr = &m.msgHdr;
+            let srcs = task.IovsFromAddr(hdr.iov, hdr.iovLen)?;
+            let mut buf = DataBuff::New(IoVec::NumBytes(&srcs));
+            let len = task.CopyDataInFromIovs(&mut buf.buf, &srcs, true)?;
+            iovs.push(IoVec::NewFromAddr(buf.Ptr(), len));
+            bufs.push(buf);
+
+            let name: Vec<u8> = if hdr.msgName != 0 && hdr.nameLen > 0 {
+                task.CopyInVec(hdr.msgName, hdr.nameLen as usize)?
+            } else {
+                Vec::new()
+            };
+            names.push(name);
+
+            let control: Vec<u8> = if hdr.msgControl != 0 && hdr.msgControlLen > 0 {
+                task.CopyInVec(hdr.msgControl, hdr.msgControlLen)?
+            } else {
+                Vec::new()
+            };
+            controls.push(control);
+        }
+
+        let mut hostMsgs = Vec::with_capacity(cnt);
+        for i in 0..cnt {
+            let mut hdr = MsgHdr::default();
+            hdr.iov = &iovs[i] as *const _ as u64;
+            hdr.iovLen = 1;
+            if names[i].len() > 0 {
+                hdr.msgName = &names[i][0] as *const _ as u64;
+                hdr.nameLen = names[i].len() as u32;
+            }
+
+            if controls[i].len() > 0 {
+                hdr.msgControl = &controls[i][0] as *const _ as u64;
+                hdr.msgControlLen = controls[i].len();
+            }
+
+            hostMsgs.push(MMsgHdr {
+                msgHdr: hdr,
+                msgLen: 0,
+            });
+        }
+
+        self.traffic.shaper.Wait(task)?;
+
+        let msgvec = &mut hostMsgs[0] as *mut _ as u64;
+        let mut res = Kernel::HostSpace::IOSendMMsg(
+            self.fd,
+            msgvec,
+            cnt as u32,
+            flags | MsgType::MSG_DONTWAIT,
+        ) as i32;
+
+        while res == -SysErr::EWOULDBLOCK && flags & MsgType::MSG_DONTWAIT == 0 {
+            let general = task.blocker.generalEntry.clone();
+
+            self.EventRegister(task, &general, EVENT_WRITE);
+            defer!(self.EventUnregister(task, &general));
+            match task.blocker.BlockWithMonoTimer(true, deadline) {
+                Err(Error::SysError(SysErr::ETIMEDOUT)) => {
+                    return Err(Error::SysError(SysErr::EAGAIN))
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+                _ => (),
+            }
+
+            res = Kernel::HostSpace::IOSendMMsg(
+                self.fd,
+                msgvec,
+                cnt as u32,
+                flags | MsgType::MSG_DONTWAIT,
+            ) as i32;
+        }
+
+        if res < 0 {
+            let err = Error::SysError(-res as i32);
+            self.traffic.ext.TxError(&err);
+            self.Snmp().TxError(&err);
+            return Err(err);
+        }
+
+        let mut bytes = 0;
+        for i in 0..res as usize {
+            msgs[i].msgLen = hostMsgs[i].msgLen;
+            bytes += hostMsgs[i].msgLen as u64;
+        }
+        self.traffic.ext.TxPackets(bytes, res as u64);
+        self.Snmp().OutPackets(res as u64);
+        self.traffic.shaper.Charge(bytes as usize);
+
+        return Ok(res as i64);
+    }
+
     pub fn IOAccept(&self) -> Result<AcceptItem> {
         let mut ai = AcceptItem::default();
         ai.len = ai.addr.data.len() as _;
@@ -192,7 +935,12 @@ impl Waitable for HostSocketOperations {
 
     fn Readiness(&self, _task: &Task, mask: EventMask) -> EventMask {
         let fd = self.fd;
-        return NonBlockingPoll(fd, mask);
+        let mut ready = NonBlockingPoll(fd, mask);
+        if self.dgrams.HasData() {
+            ready |= mask & READABLE_EVENT;
+        }
+
+        return ready;
     }
 
     fn EventRegister(&self, task: &Task, e: &WaitEntry, mask: EventMask) {
@@ -254,10 +1002,24 @@ impl FileOperations for HostSocketOperations {
         _offset: i64,
         _blocking: bool,
     ) -> Result<i64> {
+        if let Some(d) = self.dgrams.Deq(false) {
+            let n = task.CopyDataOutToIovs(&d.data, dsts, false)?;
+            self.traffic.Counters(d.time != 0).RxPackets(n as u64, 1);
+            self.Snmp().InPackets(1);
+            return Ok(n as i64);
+        }
+
+        if self.MultishotArmed() {
+            return Err(Error::SysError(SysErr::EWOULDBLOCK));
+        }
+
         let size = IoVec::NumBytes(dsts);
         let buf = DataBuff::New(size);
         let iovs = buf.Iovs(size);
-        let ret = IORead(self.fd, &iovs)?;
+        let ret = IORead(self.fd, &iovs);
+        self.traffic.ext.Rx(&ret);
+        self.Snmp().Rx(&ret);
+        let ret = ret?;
 
         // handle partial memcopy
         task.CopyDataOutToIovs(&buf.buf[0..ret as usize], dsts, false)?;
@@ -280,8 +1042,24 @@ impl FileOperations for HostSocketOperations {
         let size = IoVec::NumBytes(srcs);
         let mut buf = DataBuff::New(size);
         let len = task.CopyDataInFromIovs(&mut buf.buf, srcs, true)?;
+        if self.IsInetDgram() {
+            if let Some(dst) = self.GetRemoteAddr() {
+                let segment = self.gsoSize.load(Ordering::Relaxed);
+                if let Some(res) = self.SendLoopback(&dst, &buf.buf[..len], segment) {
+                    self.traffic.lo.Tx(&res);
+                    self.Snmp().Tx(&res);
+                    return res;
+                }
+            }
+        }
+
+        self.traffic.shaper.Wait(task)?;
         let iovs = buf.Iovs(len);
-        return IOWrite(self.fd, &iovs);
+        let ret = IOWrite(self.fd, &iovs);
+        self.traffic.ext.Tx(&ret);
+        self.Snmp().Tx(&ret);
+        self.traffic.shaper.Sent(&ret);
+        return ret;
     }
 
     fn Append(&self, task: &Task, f: &File, srcs: &[IoVec]) -> Result<(i64, i64)> {
@@ -375,6 +1153,8 @@ impl FileOperations for HostSocketOperations {
 
 impl SockOperations for HostSocketOperations {
     fn Connect(&self, task: &Task, sockaddr: &[u8], blocking: bool) -> Result<i64> {
+        FIREWALL.CheckConnect(sockaddr)?;
+
         let mut socketaddr = sockaddr;
 
         if (self.family == AFType::AF_INET || self.family == AFType::AF_INET6)
@@ -395,12 +1175,19 @@ impl SockOperations for HostSocketOperations {
                 self.SetRemoteAddr(socketaddr.to_vec())?;
             }
 
+            // connect binds an unbound udp socket
+            self.RegisterLoopback();
+            self.CountConnection(false);
             return Ok(0);
         }
 
         if res != 0 {
             if -res != SysErr::EINPROGRESS || !blocking {
-                return Err(Error::SysError(-res));
+                let err = Error::SysError(-res);
+                if -res != SysErr::EINPROGRESS && self.stype == SockType::SOCK_STREAM {
+                    self.Snmp().AttemptFail(&err);
+                }
+                return Err(err);
             }
 
             //todo: which one is more efficent?
@@ -436,10 +1223,15 @@ impl SockOperations for HostSocketOperations {
         }
 
         if val != 0 {
-            return Err(Error::SysError(val as i32));
+            let err = Error::SysError(val as i32);
+            if self.stype == SockType::SOCK_STREAM {
+                self.Snmp().AttemptFail(&err);
+            }
+            return Err(err);
         }
 
         self.SetRemoteAddr(socketaddr.to_vec())?;
+        self.CountConnection(false);
         return Ok(0);
     }
 
@@ -517,6 +1309,10 @@ impl SockOperations for HostSocketOperations {
             Some(remoteAddr.to_vec()),
         )?;
 
+        // the connection is closed with the file if the peer is denied
+        FIREWALL.CheckAccept(&acceptItem.addr.data)?;
+        self.CountConnection(true);
+
         let fdFlags = FDFlags {
             CloseOnExec: flags & SocketFlags::SOCK_CLOEXEC != 0,
         };
@@ -526,6 +1322,11 @@ impl SockOperations for HostSocketOperations {
     }
 
     fn Bind(&self, task: &Task, sockaddr: &[u8]) -> Result<i64> {
+        FIREWALL.CheckBind(sockaddr)?;
+        if self.family == AFType::AF_PACKET {
+            CheckLinkAddr(self.fd, sockaddr, SysErr::ENODEV)?;
+        }
+
         let mut socketaddr = sockaddr;
 
         info!(
@@ -548,10 +1349,13 @@ impl SockOperations for HostSocketOperations {
             return Err(Error::SysError(-res as i32));
         }
 
+        self.RegisterLoopback();
         return Ok(res);
     }
 
     fn Listen(&self, _task: &Task, backlog: i32) -> Result<i64> {
+        FIREWALL.CheckListen(self.fd)?;
+
         let len = if backlog <= 0 {
             5
         } else {
@@ -656,6 +1460,10 @@ impl SockOperations for HostSocketOperations {
         return Ok(optlen as i64)
         */
 
+        if (level as u64) == LibcConst::SOL_SOCKET && name == SO_GET_FILTER {
+            return GetSocketFilter(self.fd, opt);
+        }
+
         let mut optLen = opt.len();
         let res = if optLen == 0 {
             Kernel::HostSpace::GetSockOpt(
@@ -683,6 +1491,20 @@ impl SockOperations for HostSocketOperations {
     }
 
     fn SetSockOpt(&self, task: &Task, level: i32, name: i32, opt: &[u8]) -> Result<i64> {
+        if self.family == AFType::AF_PACKET {
+            if let Some(res) = SetPacketSockOpt(task, self.fd, level, name, opt) {
+                return res;
+            }
+        } else if (level as u64) == LibcConst::SOL_SOCKET
+            && (name as u64) == LibcConst::SO_ATTACH_FILTER
+        {
+            return AttachFilter(task, self.fd, false, opt);
+        } else if (level as u64) == LibcConst::SOL_SOCKET
+            && (name as u64) == LibcConst::SO_DETACH_FILTER
+        {
+            DetachFilter(self.fd, false)?;
+        }
+
         if (level as u64) == LibcConst::SOL_SOCKET && (name as u64) == LibcConst::SO_SNDTIMEO {
             if opt.len() >= SocketSize::SIZEOF_TIMEVAL {
                 let timeVal = task.CopyInObj::<Timeval>(&opt[0] as *const _ as u64)?;
@@ -737,6 +1559,12 @@ impl SockOperations for HostSocketOperations {
             return Err(Error::SysError(-res as i32));
         }
 
+        if level == SOL_SOCKET {
+            self.RecordTimestampOpt(name, opt);
+        } else if level == SOL_UDP {
+            self.RecordUdpOpt(name, opt);
+        }
+
         return Ok(res);
     }
 
@@ -816,12 +1644,12 @@ impl SockOperations for HostSocketOperations {
         self.EventRegister(task, &general, EVENT_READ);
         defer!(self.EventUnregister(task, &general));
 
-        let mut res = Kernel::HostSpace::IORecvMsg(
-            self.fd,
-            &mut msgHdr as *mut _ as u64,
-            flags | MsgType::MSG_DONTWAIT,
-            false,
-        ) as i32;
+        self.ArmMultishot();
+        if let Some(ret) = self.RecvLoopback(task, dsts, flags, senderRequested, controlDataLen) {
+            return ret;
+        }
+
+        let mut res = self.HostRecvMsg(&mut msgHdr, flags);
 
         while res == -SysErr::EWOULDBLOCK && flags & (MsgType::MSG_DONTWAIT | MsgType::MSG_ERRQUEUE) == 0 {
 
@@ -838,16 +1666,26 @@ impl SockOperations for HostSocketOperations {
                 _ => (),
             }
 
-            res = Kernel::HostSpace::IORecvMsg(
-                self.fd,
-                &mut msgHdr as *mut _ as u64,
-                flags | MsgType::MSG_DONTWAIT,
-                false,
-            ) as i32;
+            if let Some(ret) = self.RecvLoopback(task, dsts, flags, senderRequested, controlDataLen) {
+                return ret;
+            }
+
+            res = self.HostRecvMsg(&mut msgHdr, flags);
         }
 
+        let counted = flags & (MsgType::MSG_PEEK | MsgType::MSG_ERRQUEUE) == 0;
         if res < 0 {
-            return Err(Error::SysError(-res as i32));
+            let err = Error::SysError(-res as i32);
+            if counted {
+                self.traffic.ext.RxError(&err);
+                self.Snmp().RxError(&err);
+            }
+            return Err(err);
+        }
+
+        if counted {
+            self.traffic.ext.RxPackets(res as u64, 1);
+            self.Snmp().InPackets(1);
         }
 
         let msgFlags = msgHdr.msgFlags & !MsgType::MSG_CTRUNC;
@@ -863,6 +1701,7 @@ impl SockOperations for HostSocketOperations {
         };
 
         controlVec.resize(msgHdr.msgControlLen, 0);
+        AdjustTimestamps(&mut controlVec, TIME_KEEPER.HostRealtimeOffset());
 
         // todo: need to handle partial copy
         let count = if res < buf.buf.len() as i32 {
@@ -882,6 +1721,12 @@ impl SockOperations for HostSocketOperations {
         msgHdr: &mut MsgHdr,
         deadline: Option<Time>,
     ) -> Result<i64> {
+        FIREWALL.CheckSendMsg(task, msgHdr)?;
+        if self.family == AFType::AF_PACKET && msgHdr.msgName != 0 && msgHdr.nameLen > 0 {
+            let name: Vec<u8> = task.CopyInVec(msgHdr.msgName, msgHdr.nameLen as usize)?;
+            CheckLinkAddr(self.fd, &name, SysErr::ENXIO)?;
+        }
+
         if flags
             & !(MsgType::MSG_DONTWAIT
             | MsgType::MSG_EOR
@@ -896,6 +1741,36 @@ impl SockOperations for HostSocketOperations {
         let size = IoVec::NumBytes(srcs);
         let mut buf = DataBuff::New(size);
         let len = task.CopyDataInFromIovs(&mut buf.buf, srcs, true)?;
+
+        // the control messages but UDP_SEGMENT are handled by the host
+        let segment = if !self.IsInetDgram() {
+            None
+        } else if msgHdr.msgControlLen == 0 {
+            Some(self.gsoSize.load(Ordering::Relaxed))
+        } else {
+            let control = unsafe {
+                slice::from_raw_parts(msgHdr.msgControl as *const u8, msgHdr.msgControlLen)
+            };
+            ParseUDPSegment(control)?
+        };
+
+        if let Some(segment) = segment {
+            let dst = if msgHdr.msgName != 0 && msgHdr.nameLen > 0 {
+                Some(task.CopyInVec::<u8>(msgHdr.msgName, msgHdr.nameLen as usize)?)
+            } else {
+                self.GetRemoteAddr()
+            };
+
+            if let Some(dst) = dst {
+                if let Some(res) = self.SendLoopback(&dst, &buf.buf[..len], segment) {
+                    self.traffic.lo.Tx(&res);
+                    self.Snmp().Tx(&res);
+                    return res;
+                }
+            }
+        }
+
+        self.traffic.shaper.Wait(task)?;
         let iovs = buf.Iovs(len);
 
         msgHdr.iov = &iovs[0] as *const _ as u64;
@@ -954,11 +1829,82 @@ impl SockOperations for HostSocketOperations {
             };
         }
 
-        if res < 0 {
-            return Err(Error::SysError(-res as i32));
+        let ret = if res < 0 {
+            Err(Error::SysError(-res as i32))
+        } else {
+            Ok(res as i64)
+        };
+
+        self.traffic.ext.Tx(&ret);
+        self.Snmp().Tx(&ret);
+        self.traffic.shaper.Sent(&ret);
+        return ret;
+    }
+
+    fn RecvMMsg(
+        &self,
+        task: &Task,
+        msgs: &mut [MMsgHdr],
+        flags: i32,
+        deadline: Option<Time>,
+    ) -> Option<Result<i64>> {
+        if !self.IsInetDgram() {
+            return None;
         }
 
-        return Ok(res as i64);
+        self.ArmMultishot();
+        if let Some(res) = self.RecvQueued(task, msgs, flags, deadline) {
+            return Some(res);
+        }
+
+        return self.HostRecvMMsg(task, msgs, flags, deadline);
+    }
+
+    fn SendMMsg(
+        &self,
+        task: &Task,
+        msgs: &mut [MMsgHdr],
+        flags: i32,
+        deadline: Option<Time>,
+    ) -> Option<Result<i64>> {
+        if !self.IsInetDgram() {
+            return None;
+        }
+
+        // the batch stops before the first datagram the firewall denies, and
+        // before the first one to the sandbox, which is sent in the guest
+        let mut cnt = msgs.len();
+        for (i, m) in msgs.iter().enumerate() {
+            if let Err(e) = FIREWALL.CheckSendMsg(task, &m.msgHdr) {
+                if i == 0 {
+                    return Some(Err(e));
+                }
+                cnt = i;
+                break;
+            }
+
+            let hdr = &m.msgHdr;
+            let dst = if hdr.msgName != 0 && hdr.nameLen > 0 {
+                match task.CopyInVec::<u8>(hdr.msgName, hdr.nameLen as usize) {
+                    Err(e) => return Some(Err(e)),
+                    Ok(v) => Some(v),
+                }
+            } else {
+                self.GetRemoteAddr()
+            };
+
+            if let Some(dst) = dst {
+                if self.IsLoopbackDst(&dst) {
+                    if i == 0 {
+                        return None;
+                    }
+                    cnt = i;
+                    break;
+                }
+            }
+        }
+
+        return Some(self.HostSendMMsg(task, &mut msgs[..cnt], flags, deadline));
     }
 
     fn SetRecvTimeout(&self, ns: i64) {
@@ -1003,6 +1949,11 @@ impl SockOperations for HostSocketOperations {
         return info.State as u32;
     }
 
+    fn QueueSize(&self) -> (usize, usize) {
+        let (tx, rx) = HostQueueSize(self.fd);
+        return (tx, rx + self.dgrams.lock().size);
+    }
+
     fn Type(&self) -> (i32, i32, i32) {
         return (self.family, self.stype, -1)
     }
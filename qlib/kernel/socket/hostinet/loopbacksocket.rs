@@ -12,21 +12,130 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::qlib::mutex::*;
+use alloc::collections::vec_deque::VecDeque;
 use alloc::sync::Arc;
 use alloc::sync::Weak;
+use alloc::vec::Vec;
 use core::ops::Deref;
 use core::fmt;
 
 use crate::qlib::common::*;
+use crate::qlib::linux::socket::UDP_MAX_SEGMENTS;
 use crate::qlib::linux_def::*;
 use crate::qlib::socket_buf::*;
 use crate::qlib::bytestream::*;
 use crate::qlib::kernel::kernel::waiter::Queue;
+use crate::qlib::kernel::socket::netlink::InterfaceTable;
+use crate::qlib::kernel::socket::netlink::INTERFACES;
+use crate::qlib::kernel::socket::netstack::tcp::TcpState;
 use crate::qlib::kernel::task::Task;
+use crate::qlib::kernel::tcpip::tcpip::*;
+use super::firewall::Endpoint;
+use super::firewall::Firewall;
+use super::firewall::FIREWALL;
 
-// input: client side queue, server side queue
+// LocalDst returns the endpoint of sockaddr if the traffic of a socket of
+// family to it can stay in the sandbox: the loopback addresses and the
+// addresses of the sandbox interfaces, which are the ones of the pod network
+// namespace from the sandbox load on. An ipv4 socket only reaches the ipv4
+// addresses.
+pub fn LocalDst(table: &InterfaceTable, family: i32, sockaddr: &[u8]) -> Option<Endpoint> {
+    let ep = Endpoint::Parse(sockaddr)?;
+    if ep.addr.len() == 16 && family != AFType::AF_INET6 {
+        return None;
+    }
+
+    if !table.IsLocalAddr(&ep.addr) {
+        return None;
+    }
+
+    return Some(ep);
+}
+
+// SandboxDst is LocalDst with the firewall applied: the traffic kept in the
+// guest memory is subject to the same egress rules as the one going through
+// the host. connect tells a connect from a datagram send.
+pub fn SandboxDst(family: i32, sockaddr: &[u8], connect: bool) -> Result<Option<Endpoint>> {
+    return CheckedLocalDst(&INTERFACES.read(), &FIREWALL, family, sockaddr, connect);
+}
+
+fn CheckedLocalDst(
+    table: &InterfaceTable,
+    firewall: &Firewall,
+    family: i32,
+    sockaddr: &[u8],
+    connect: bool,
+) -> Result<Option<Endpoint>> {
+    let ep = match LocalDst(table, family, sockaddr) {
+        None => return Ok(None),
+        Some(ep) => ep,
+    };
+
+    if connect {
+        firewall.CheckLocalConnect(sockaddr)?;
+    } else {
+        firewall.CheckLocalSendTo(sockaddr)?;
+    }
+
+    return Ok(Some(ep));
+}
+
+// SockAddrBytes returns the sockaddr of addr:port for a socket of family, the
+// ipv4 addresses are mapped for AF_INET6.
+pub fn SockAddrBytes(family: i32, addr: &[u8], port: u16) -> Vec<u8> {
+    if family == AFType::AF_INET6 {
+        let mut sa = SocketAddrInet6 {
+            Family: AFType::AF_INET6 as u16,
+            Port: port.to_be(),
+            ..Default::default()
+        };
+        if addr.len() == 4 {
+            sa.Addr[10] = 0xff;
+            sa.Addr[11] = 0xff;
+            sa.Addr[12..].copy_from_slice(addr);
+        } else {
+            sa.Addr.copy_from_slice(&addr[..16]);
+        }
+
+        return ToBytes(&sa);
+    }
+
+    let mut sa = SockAddrInet {
+        Family: AFType::AF_INET as u16,
+        Port: port.to_be(),
+        ..Default::default()
+    };
+    sa.Addr.copy_from_slice(&addr[..4]);
+    return ToBytes(&sa);
+}
+
+fn ToBytes<T: Copy>(v: &T) -> Vec<u8> {
+    let data = unsafe {
+        core::slice::from_raw_parts(v as *const T as *const u8, core::mem::size_of::<T>())
+    };
+    return data.to_vec();
+}
+
+// CopyAddr copies the sockaddr addr to dst as getsockname does, it returns
+// the full length of addr.
+fn CopyAddr(dst: &mut [u8], addr: &[u8]) -> i64 {
+    let len = core::cmp::min(dst.len(), addr.len());
+    dst[..len].copy_from_slice(&addr[..len]);
+    return addr.len() as i64;
+}
+
+// input: client side queue, server side queue, the families of the client and
+// the listener, the client and the server addresses
 // return: (client side socket, server side socket)
-pub fn LoopbackSocketPair(clientQueue: Queue, serverQueue: Queue) -> (LoopbackSocket, LoopbackSocket) {
+pub fn LoopbackSocketPair(
+    clientQueue: Queue,
+    serverQueue: Queue,
+    clientFamily: i32,
+    serverFamily: i32,
+    client: &Endpoint,
+    server: &Endpoint,
+) -> (LoopbackSocket, LoopbackSocket) {
     let buf1 = ByteStream::Init(MemoryDef::DEFAULT_BUF_PAGE_COUNT);
     let buf2 = ByteStream::Init(MemoryDef::DEFAULT_BUF_PAGE_COUNT);
 
@@ -37,12 +146,16 @@ pub fn LoopbackSocketPair(clientQueue: Queue, serverQueue: Queue) -> (LoopbackSo
         sockBuff: clientSockBuf.clone(),
         peer: serverSockBuf.Downgrade(),
         peerQueue: serverQueue,
+        local: SockAddrBytes(clientFamily, &client.addr, client.port),
+        remote: SockAddrBytes(clientFamily, &server.addr, server.port),
     }));
 
     let serverSock = LoopbackSocket(Arc::new(LoopbackSocketInner {
         sockBuff: serverSockBuf.clone(),
         peer: clientSockBuf.Downgrade(),
         peerQueue: clientQueue,
+        local: SockAddrBytes(serverFamily, &server.addr, server.port),
+        remote: SockAddrBytes(serverFamily, &client.addr, client.port),
     })); 
 
     return (clientSock, serverSock)
@@ -53,6 +166,10 @@ pub struct LoopbackSocketInner {
     pub sockBuff: SocketBuff,
     pub peer: SocketBuffWeak,
     pub peerQueue: Queue,
+    // the sockaddrs reported by getsockname and getpeername, the host
+    // sockets are not connected
+    pub local: Vec<u8>,
+    pub remote: Vec<u8>,
 }
 
 pub struct LoopbackSocketWeak(Weak<LoopbackSocketInner>);
@@ -144,4 +261,345 @@ impl LoopbackSocket {
     pub fn Events(&self) -> EventMask {
         return self.sockBuff.Events();
     }
+
+    pub fn Shutdown(&self, how: u64) -> Result<i64> {
+        if how != LibcConst::SHUT_RD && how != LibcConst::SHUT_WR && how != LibcConst::SHUT_RDWR {
+            return Err(Error::SysError(SysErr::EINVAL));
+        }
+
+        if how == LibcConst::SHUT_RD || how == LibcConst::SHUT_RDWR {
+            self.SetRClosed();
+        }
+
+        if how == LibcConst::SHUT_WR || how == LibcConst::SHUT_RDWR {
+            self.SetWClosed();
+        }
+
+        // the peer reads the end of the stream or fails to write
+        self.peerQueue.Notify(READABLE_EVENT | WRITEABLE_EVENT);
+        return Ok(0);
+    }
+
+    pub fn State(&self) -> u32 {
+        if self.sockBuff.RClosed() && self.sockBuff.WClosed() {
+            return TcpState::Closed.LinuxState();
+        }
+
+        return TcpState::Established.LinuxState();
+    }
+
+    pub fn GetSockName(&self, socketaddr: &mut [u8]) -> i64 {
+        return CopyAddr(socketaddr, &self.local);
+    }
+
+    pub fn GetPeerName(&self, socketaddr: &mut [u8]) -> i64 {
+        return CopyAddr(socketaddr, &self.remote);
+    }
+}
+
+// LOOPBACK_DGRAM_BUF_SIZE bounds the datagrams queued to a socket, it is the
+// default SO_RCVBUF of Linux.
+pub const LOOPBACK_DGRAM_BUF_SIZE: usize = 212992;
+
+// Datagram is a udp datagram sent by a socket of the sandbox to another one,
+// or received by the multishot recvmsg of the host socket.
+pub struct Datagram {
+    pub data: Vec<u8>,
+    // the sockaddr of the sender
+    pub from: Vec<u8>,
+    // the control messages of the host
+    pub control: Vec<u8>,
+    // the realtime when a datagram of the sandbox was sent, the host
+    // datagrams have their timestamps in control
+    pub time: i64,
+    // the MSG_TRUNC and MSG_CTRUNC of the host receive
+    pub flags: i32,
+}
+
+#[derive(Default)]
+pub struct DatagramQueueInner {
+    pub queue: VecDeque<Datagram>,
+    pub size: usize,
+}
+
+#[derive(Clone, Default)]
+pub struct DatagramQueue(Arc<QMutex<DatagramQueueInner>>);
+
+impl Deref for DatagramQueue {
+    type Target = Arc<QMutex<DatagramQueueInner>>;
+
+    fn deref(&self) -> &Arc<QMutex<DatagramQueueInner>> {
+        &self.0
+    }
+}
+
+#[derive(Clone)]
+pub struct DatagramQueueWeak(Weak<QMutex<DatagramQueueInner>>);
+
+impl DatagramQueueWeak {
+    pub fn Upgrade(&self) -> Option<DatagramQueue> {
+        return self.0.upgrade().map(|q| DatagramQueue(q));
+    }
+}
+
+impl DatagramQueue {
+    pub fn Downgrade(&self) -> DatagramQueueWeak {
+        return DatagramQueueWeak(Arc::downgrade(&self.0));
+    }
+
+    // Enq queues d, a datagram over the buffer size is dropped as udp does.
+    pub fn Enq(&self, d: Datagram) -> bool {
+        let mut q = self.lock();
+        if q.size > 0 && q.size + d.data.len() > LOOPBACK_DGRAM_BUF_SIZE {
+            return false;
+        }
+
+        q.size += d.data.len();
+        q.queue.push_back(d);
+        return true;
+    }
+
+    pub fn Deq(&self, peek: bool) -> Option<Datagram> {
+        let mut q = self.lock();
+        if peek {
+            return q.queue.front().map(|d| Datagram {
+                data: d.data.clone(),
+                from: d.from.clone(),
+                control: d.control.clone(),
+                time: d.time,
+                flags: d.flags,
+            });
+        }
+
+        let d = q.queue.pop_front()?;
+        q.size -= d.data.len();
+        return Some(d);
+    }
+
+    // DeqGRO dequeues the datagrams of one sender coalesced as UDP_GRO does,
+    // they have the size of the first one but the last which can be shorter.
+    // It returns the segment size, or 0 when nothing is coalesced.
+    pub fn DeqGRO(&self, max: usize) -> Option<(Datagram, usize)> {
+        let mut q = self.lock();
+        let mut d = q.queue.pop_front()?;
+        q.size -= d.data.len();
+
+        let segment = d.data.len();
+        let mut segs = 1;
+        while segment > 0 && d.control.len() == 0 && d.flags == 0 && segs < UDP_MAX_SEGMENTS {
+            match q.queue.front() {
+                Some(next)
+                    if next.from == d.from
+                        && next.control.len() == 0
+                        && next.flags == 0
+                        && next.data.len() <= segment
+                        && d.data.len() + next.data.len() <= max => (),
+                _ => break,
+            }
+
+            let next = q.queue.pop_front().unwrap();
+            q.size -= next.data.len();
+            d.data.extend_from_slice(&next.data);
+            segs += 1;
+            if next.data.len() < segment {
+                break;
+            }
+        }
+
+        if segs == 1 {
+            return Some((d, 0));
+        }
+
+        return Some((d, segment));
+    }
+
+    pub fn HasData(&self) -> bool {
+        return self.lock().queue.len() > 0;
+    }
+}
+
+// RecvBatch receives up to cnt datagrams of a recvmmsg, recv receives the
+// i-th one and returns None when no datagram is queued. As recvmmsg does, an
+// error after the first datagram ends the batch. None means that nothing is
+// queued.
+pub fn RecvBatch<F>(cnt: usize, mut recv: F) -> Option<Result<i64>>
+where
+    F: FnMut(usize) -> Option<Result<()>>,
+{
+    let mut count = 0;
+    while count < cnt {
+        match recv(count) {
+            None => break,
+            Some(Err(e)) => {
+                if count == 0 {
+                    return Some(Err(e));
+                }
+
+                break;
+            }
+            Some(Ok(())) => count += 1,
+        }
+    }
+
+    if count == 0 {
+        return None;
+    }
+
+    return Some(Ok(count as i64));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qlib::loader::*;
+    use alloc::string::ToString;
+
+    fn Table() -> InterfaceTable {
+        let mut table = InterfaceTable::default();
+        table.Add(&NetInterface {
+            Name: "eth0".to_string(),
+            Addrs: vec![NetAddr {
+                Addr: vec![10, 0, 0, 5],
+                PrefixLen: 24,
+            }],
+            ..Default::default()
+        });
+        return table;
+    }
+
+    fn V6(addr: &[u8]) -> Vec<u8> {
+        return SockAddrBytes(AFType::AF_INET6, addr, 80);
+    }
+
+    fn V4(addr: &[u8]) -> Vec<u8> {
+        return SockAddrBytes(AFType::AF_INET, addr, 80);
+    }
+
+    #[test]
+    fn test_LocalDst() {
+        let table = Table();
+        let inet = AFType::AF_INET;
+        let inet6 = AFType::AF_INET6;
+
+        assert!(LocalDst(&table, inet, &V4(&[127, 0, 0, 1])).is_some());
+        assert!(LocalDst(&table, inet, &V4(&[127, 1, 2, 3])).is_some());
+        assert!(LocalDst(&table, inet, &V4(&[10, 0, 0, 5])).is_some());
+        assert!(LocalDst(&table, inet, &V4(&[10, 0, 0, 6])).is_none());
+        assert!(LocalDst(&table, inet, &V4(&[8, 8, 8, 8])).is_none());
+
+        let mut lo6 = [0u8; 16];
+        lo6[15] = 1;
+        assert!(LocalDst(&table, inet6, &V6(&lo6)).is_some());
+        assert!(LocalDst(&table, inet, &V6(&lo6)).is_none());
+
+        // the ipv4 mapped addresses are the ipv4 ones
+        let ep = LocalDst(&table, inet6, &V6(&[127, 0, 0, 1])).unwrap();
+        assert_eq!(ep.addr, vec![127, 0, 0, 1]);
+        assert_eq!(ep.port, 80);
+        assert!(LocalDst(&table, inet6, &V6(&[10, 0, 0, 5])).is_some());
+        assert!(LocalDst(&table, inet6, &V6(&[8, 8, 8, 8])).is_none());
+
+        assert!(LocalDst(&table, inet, &V4(&[127, 0, 0, 1])[..6]).is_none());
+        assert!(LocalDst(&table, inet, &[]).is_none());
+    }
+
+    #[test]
+    fn test_CheckedLocalDst() {
+        let table = Table();
+        let inet = AFType::AF_INET;
+
+        let mut policy = FirewallPolicy::default();
+        policy.Rules.push(FirewallRule {
+            Direction: FirewallDirection::Egress,
+            Allow: false,
+            Addr: vec![10, 0, 0, 5],
+            PrefixLen: 32,
+            PortLo: 0,
+            PortHi: u16::MAX,
+        });
+        policy.DefaultEgress = true;
+        let firewall = Firewall::default();
+        firewall.SetPolicies(&[policy]);
+
+        // the local destinations are filtered as the host ones
+        match CheckedLocalDst(&table, &firewall, inet, &V4(&[10, 0, 0, 5]), true) {
+            Err(Error::SysError(errno)) => assert_eq!(errno, SysErr::ECONNREFUSED),
+            _ => panic!("the denied local connect is not refused"),
+        }
+        match CheckedLocalDst(&table, &firewall, inet, &V4(&[10, 0, 0, 5]), false) {
+            Err(Error::SysError(errno)) => assert_eq!(errno, SysErr::EACCES),
+            _ => panic!("the denied local send is not refused"),
+        }
+
+        let allowed = CheckedLocalDst(&table, &firewall, inet, &V4(&[127, 0, 0, 1]), true);
+        assert!(allowed.unwrap().is_some());
+
+        // the remote destinations are left to the host path and its checks
+        let remote = CheckedLocalDst(&table, &firewall, inet, &V4(&[8, 8, 8, 8]), true);
+        assert!(remote.unwrap().is_none());
+    }
+
+    fn Dgram(data: &[u8]) -> Datagram {
+        return Datagram {
+            data: data.to_vec(),
+            from: V4(&[10, 0, 0, 6]),
+            control: Vec::new(),
+            time: 0,
+            flags: 0,
+        };
+    }
+
+    #[test]
+    fn test_RecvBatch() {
+        let dgrams = DatagramQueue::default();
+        for data in [&b"a"[..], &b"bb"[..], &b"ccc"[..]].iter() {
+            assert!(dgrams.Enq(Dgram(data)));
+        }
+
+        // a batch takes all the queued datagrams, in order
+        let mut msgs = vec![Vec::new(); 4];
+        let res = RecvBatch(msgs.len(), |i| {
+            let d = dgrams.Deq(false)?;
+            msgs[i] = d.data;
+            return Some(Ok(()));
+        });
+        assert_eq!(res.unwrap().unwrap(), 3);
+        assert_eq!(msgs[0], b"a".to_vec());
+        assert_eq!(msgs[1], b"bb".to_vec());
+        assert_eq!(msgs[2], b"ccc".to_vec());
+        assert!(msgs[3].is_empty());
+        assert!(!dgrams.HasData());
+        assert_eq!(dgrams.lock().size, 0);
+
+        // and a smaller batch leaves the others queued
+        for data in [&b"a"[..], &b"bb"[..], &b"ccc"[..]].iter() {
+            assert!(dgrams.Enq(Dgram(data)));
+        }
+        let res = RecvBatch(2, |_| dgrams.Deq(false).map(|_| Ok(())));
+        assert_eq!(res.unwrap().unwrap(), 2);
+        assert_eq!(dgrams.Deq(false).unwrap().data, b"ccc".to_vec());
+
+        assert!(RecvBatch(4, |_| dgrams.Deq(false).map(|_| Ok(()))).is_none());
+    }
+
+    #[test]
+    fn test_RecvBatchError() {
+        let fault = || Error::SysError(SysErr::EFAULT);
+
+        // the error of the first datagram is returned
+        match RecvBatch(4, |_| Some(Err(fault()))) {
+            Some(Err(Error::SysError(errno))) => assert_eq!(errno, SysErr::EFAULT),
+            _ => panic!("the error of the first datagram is lost"),
+        }
+
+        // and a later one ends the batch
+        let res = RecvBatch(4, |i| {
+            if i < 2 {
+                Some(Ok(()))
+            } else {
+                Some(Err(fault()))
+            }
+        });
+        assert_eq!(res.unwrap().unwrap(), 2);
+    }
 }
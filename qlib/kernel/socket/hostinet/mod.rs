@@ -17,9 +17,14 @@ pub mod socket;
 pub mod socket_buf;
 pub mod hostsocket;
 pub mod asyncsocket;
+pub mod firewall;
 pub mod uring_socket;
 pub mod loopbacksocket;
+pub mod packet;
+pub mod traffic;
 
 pub fn Init() {
+    // the raw sockets are served before the SocketProvider takes them
+    self::packet::Init();
     self::socket::Init();
 }
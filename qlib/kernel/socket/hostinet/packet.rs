@@ -0,0 +1,517 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// packet provides the AF_PACKET sockets and the raw ICMP sockets on top of
+// host sockets. They need CAP_NET_RAW in the sandbox, and the packet sockets
+// are confined to the interfaces of the sandbox with a classic BPF filter
+// on the host socket. The filter is rebuilt when the interfaces change.
+
+use crate::qlib::mutex::*;
+use alloc::boxed::Box;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux::bpf::*;
+use super::super::super::super::linux::netdevice::*;
+use super::super::super::super::linux::socket::SO_ATTACH_BPF;
+use super::super::super::super::linux_def::*;
+use super::super::super::fs::file::*;
+use super::super::super::task::*;
+use super::super::super::tcpip::tcpip::*;
+use super::super::super::Kernel::HostSpace;
+use super::super::netlink::INTERFACES;
+use super::super::socket::*;
+use super::hostsocket::*;
+
+pub const PACKET_TX_RING: i32 = 13;
+pub const PACKET_FANOUT: i32 = 18;
+pub const PACKET_FANOUT_DATA: i32 = 22;
+
+// SocketFilter is the SO_ATTACH_FILTER program of the application on a host
+// socket. It is kept so that the confinement prefix of a packet socket can be
+// rebuilt and hidden from SO_GET_FILTER.
+pub struct SocketFilter {
+    pub confine: bool,
+    pub prog: Vec<SockFilter>,
+}
+
+lazy_static! {
+    // host fd => filter of the host sockets filtered by the sandbox, the
+    // packet sockets are in from their creation.
+    pub static ref SOCKET_FILTERS: QMutex<BTreeMap<i32, SocketFilter>> =
+        QMutex::new(BTreeMap::new());
+}
+
+// PacketMreq is struct packet_mreq, the PACKET_ADD_MEMBERSHIP option.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PacketMreq {
+    pub InterfaceIndex: i32,
+    pub Type: u16,
+    pub AddrLen: u16,
+    pub Addr: [u8; 8],
+}
+
+// SandboxIfIndexes returns the host indexes of the sandbox interfaces, they
+// are looked up by name with the host socket fd.
+fn SandboxIfIndexes(fd: i32) -> Vec<u32> {
+    let names: Vec<_> = INTERFACES
+        .read()
+        .interfaces
+        .iter()
+        .map(|i| i.name.clone())
+        .collect();
+
+    let mut indexes = Vec::new();
+    for name in names {
+        if name.len() >= IFNAMSIZ {
+            continue;
+        }
+
+        let mut ifr = IFReq::default();
+        ifr.SetName(&name);
+        let res = HostSpace::IoCtl(fd, LibcConst::SIOCGIFINDEX, &mut ifr as *mut _ as u64);
+        if res < 0 {
+            continue;
+        }
+
+        let index = i32::from_ne_bytes([ifr.Data[0], ifr.Data[1], ifr.Data[2], ifr.Data[3]]);
+        indexes.push(index as u32);
+    }
+
+    return indexes;
+}
+
+// IfIndexAllowed returns whether a packet socket may use the host interface
+// index, 0 means all the interfaces which the filter confines.
+fn IfIndexAllowed(fd: i32, index: i32) -> bool {
+    return index == 0 || SandboxIfIndexes(fd).contains(&(index as u32));
+}
+
+// CheckLinkAddr checks the interface of the sockaddr_ll of bind or sendto.
+pub fn CheckLinkAddr(fd: i32, sockaddr: &[u8], errno: i32) -> Result<()> {
+    let addr = match GetAddr(AFType::AF_PACKET as i16, sockaddr)? {
+        SockAddr::Link(addr) => addr,
+        _ => return Err(Error::SysError(SysErr::EINVAL)),
+    };
+
+    if !IfIndexAllowed(fd, addr.InterfaceIndex) {
+        return Err(Error::SysError(errno));
+    }
+
+    return Ok(());
+}
+
+// ConfineFilter returns the filter prefix dropping the packets of the
+// interfaces other than the current sandbox ones.
+fn ConfineFilter(fd: i32) -> Vec<SockFilter> {
+    return ConfineProg(&SandboxIfIndexes(fd));
+}
+
+// ConfineProg returns the filter prefix dropping the packets of the
+// interfaces not in indexes, the program continues after it for the others.
+// The jumps are 8 bits, the indexes past the 255th are dropped.
+fn ConfineProg(indexes: &[u32]) -> Vec<SockFilter> {
+    let indexes = &indexes[..indexes.len().min(u8::MAX as usize)];
+    let n = indexes.len();
+    let mut prog = Vec::with_capacity(n + 2);
+    prog.push(SockFilter::Stmt(
+        BPF_LD | BPF_W | BPF_ABS,
+        (SKF_AD_OFF + SKF_AD_IFINDEX) as u32,
+    ));
+    for (i, index) in indexes.iter().enumerate() {
+        // jump over the remaining checks and the drop
+        prog.push(SockFilter::Jump(
+            BPF_JMP | BPF_JEQ | BPF_K,
+            *index,
+            (n - i) as u8,
+            0,
+        ));
+    }
+    prog.push(SockFilter::Stmt(BPF_RET | BPF_K, 0));
+    return prog;
+}
+
+fn SetHostFilter(fd: i32, prog: &[SockFilter]) -> Result<()> {
+    let fprog = SockFprog {
+        Len: prog.len() as u16,
+        Filter: &prog[0] as *const _ as u64,
+        ..Default::default()
+    };
+
+    let res = HostSpace::SetSockOpt(
+        fd,
+        LibcConst::SOL_SOCKET as i32,
+        LibcConst::SO_ATTACH_FILTER as i32,
+        &fprog as *const _ as u64,
+        core::mem::size_of::<SockFprog>() as u32,
+    );
+    if res < 0 {
+        return Err(Error::SysError(-res as i32));
+    }
+
+    return Ok(());
+}
+
+// ApplyFilter sets the host filter of the socket filter, the program of a
+// packet socket runs after the confinement filter.
+fn ApplyFilter(fd: i32, filter: &SocketFilter) -> Result<()> {
+    if !filter.confine {
+        return SetHostFilter(fd, &filter.prog);
+    }
+
+    let mut prog = ConfineFilter(fd);
+    if filter.prog.len() == 0 {
+        prog.push(SockFilter::Stmt(BPF_RET | BPF_K, u32::MAX));
+    } else {
+        prog.extend_from_slice(&filter.prog);
+    }
+
+    if prog.len() > BPF_MAXINSNS {
+        return Err(Error::SysError(SysErr::EINVAL));
+    }
+
+    return SetHostFilter(fd, &prog);
+}
+
+// AttachFilter attaches the SO_ATTACH_FILTER program of the application, it
+// is copied in as the host can't read the application memory.
+pub fn AttachFilter(task: &Task, fd: i32, confine: bool, opt: &[u8]) -> Result<i64> {
+    if opt.len() < core::mem::size_of::<SockFprog>() {
+        return Err(Error::SysError(SysErr::EINVAL));
+    }
+
+    let fprog = unsafe { *(&opt[0] as *const _ as *const SockFprog) };
+    if fprog.Len == 0 || fprog.Len as usize > BPF_MAXINSNS {
+        return Err(Error::SysError(SysErr::EINVAL));
+    }
+
+    let filter = SocketFilter {
+        confine: confine,
+        prog: task.CopyInVec(fprog.Filter, fprog.Len as usize)?,
+    };
+
+    let mut filters = SOCKET_FILTERS.lock();
+    ApplyFilter(fd, &filter)?;
+    filters.insert(fd, filter);
+    return Ok(0);
+}
+
+// DetachFilter removes the program of the application. A packet socket keeps
+// the confinement filter alone, the other sockets are detached by the host.
+pub fn DetachFilter(fd: i32, confine: bool) -> Result<i64> {
+    if !confine {
+        SOCKET_FILTERS.lock().remove(&fd);
+        return Ok(0);
+    }
+
+    ConfinePacketSocket(fd)?;
+    return Ok(0);
+}
+
+// ConfinePacketSocket attaches the confinement filter alone, it accepts the
+// whole packets of the sandbox interfaces.
+pub fn ConfinePacketSocket(fd: i32) -> Result<()> {
+    let filter = SocketFilter {
+        confine: true,
+        prog: Vec::new(),
+    };
+
+    let mut filters = SOCKET_FILTERS.lock();
+    ApplyFilter(fd, &filter)?;
+    filters.insert(fd, filter);
+    return Ok(());
+}
+
+// RefreshPacketFilters rebuilds the confinement filters of the packet
+// sockets, it is called when the sandbox interfaces change so that the
+// sockets see the interfaces added after them.
+pub fn RefreshPacketFilters() {
+    let filters = SOCKET_FILTERS.lock();
+    for (fd, filter) in filters.iter() {
+        if !filter.confine {
+            continue;
+        }
+
+        if let Err(e) = ApplyFilter(*fd, filter) {
+            error!("RefreshPacketFilters: fd {} fail {:?}", fd, e);
+        }
+    }
+}
+
+// RemoveSocketFilter forgets the filter of a host socket being closed.
+pub fn RemoveSocketFilter(fd: i32) {
+    SOCKET_FILTERS.lock().remove(&fd);
+}
+
+// GetSocketFilter is SO_GET_FILTER: opt is sized in instructions as the
+// optlen of Linux, and the count of instructions is returned. The host
+// program isn't read back as it holds the confinement prefix.
+pub fn GetSocketFilter(fd: i32, opt: &mut [u8]) -> Result<i64> {
+    let filters = SOCKET_FILTERS.lock();
+    let prog = match filters.get(&fd) {
+        None => return Ok(0),
+        Some(filter) => &filter.prog,
+    };
+
+    if opt.len() == 0 {
+        return Ok(prog.len() as i64);
+    }
+
+    let size = prog.len() * core::mem::size_of::<SockFilter>();
+    if opt.len() < size {
+        return Err(Error::SysError(SysErr::EINVAL));
+    }
+
+    let bytes = unsafe { core::slice::from_raw_parts(prog.as_ptr() as *const u8, size) };
+    opt[..size].copy_from_slice(bytes);
+    return Ok(prog.len() as i64);
+}
+
+// SetPacketSockOpt handles the options of a packet socket which can break
+// out of the confinement. It returns None for the options passed to the host.
+pub fn SetPacketSockOpt(
+    task: &Task,
+    fd: i32,
+    level: i32,
+    name: i32,
+    opt: &[u8],
+) -> Option<Result<i64>> {
+    match (level as u64, name) {
+        (LibcConst::SOL_SOCKET, n) if n == LibcConst::SO_ATTACH_FILTER as i32 => {
+            return Some(AttachFilter(task, fd, true, opt));
+        }
+        (LibcConst::SOL_SOCKET, n) if n == LibcConst::SO_DETACH_FILTER as i32 => {
+            return Some(DetachFilter(fd, true));
+        }
+        (LibcConst::SOL_SOCKET, SO_ATTACH_BPF) => {
+            return Some(Err(Error::SysError(SysErr::EPERM)));
+        }
+        // the rings are mapped from the host memory
+        (LibcConst::SOL_PACKET, n)
+            if n == LibcConst::PACKET_RX_RING as i32 || n == PACKET_TX_RING =>
+        {
+            return Some(Err(Error::SysError(SysErr::ENOPROTOOPT)));
+        }
+        // the fanout groups are shared with the host sockets of the network
+        // namespace, and their programs select the sockets past the filters
+        (LibcConst::SOL_PACKET, PACKET_FANOUT) | (LibcConst::SOL_PACKET, PACKET_FANOUT_DATA) => {
+            return Some(Err(Error::SysError(SysErr::EPERM)));
+        }
+        (LibcConst::SOL_PACKET, n) if n == LibcConst::PACKET_ADD_MEMBERSHIP as i32 => {
+            if opt.len() < core::mem::size_of::<PacketMreq>() {
+                return Some(Err(Error::SysError(SysErr::EINVAL)));
+            }
+
+            let mreq = unsafe { *(&opt[0] as *const _ as *const PacketMreq) };
+            if !IfIndexAllowed(fd, mreq.InterfaceIndex) {
+                return Some(Err(Error::SysError(SysErr::ENODEV)));
+            }
+            return None;
+        }
+        _ => return None,
+    }
+}
+
+fn CheckNetRaw(task: &Task) -> Result<()> {
+    if !task.Creds().HasCapability(Capability::CAP_NET_RAW) {
+        return Err(Error::SysError(SysErr::EPERM));
+    }
+
+    return Ok(());
+}
+
+pub struct PacketSocketProvider {}
+
+impl Provider for PacketSocketProvider {
+    fn Socket(&self, task: &Task, stype: i32, protocol: i32) -> Result<Option<Arc<File>>> {
+        let nonblocking = stype & SocketFlags::SOCK_NONBLOCK != 0;
+        let stype = stype & SocketType::SOCK_TYPE_MASK;
+        if stype != SockType::SOCK_RAW && stype != SockType::SOCK_DGRAM {
+            return Err(Error::SysError(SysErr::ESOCKTNOSUPPORT));
+        }
+
+        CheckNetRaw(task)?;
+
+        // the host socket is created without a protocol so that it gets no
+        // packet before the filter is attached
+        let res = HostSpace::Socket(AFType::AF_PACKET, stype | SocketFlags::SOCK_CLOEXEC, 0);
+        if res < 0 {
+            return Err(Error::SysError(-res as i32));
+        }
+
+        let fd = res as i32;
+        let file = newHostSocketFile(task, AFType::AF_PACKET, fd, stype, nonblocking, None)?;
+        ConfinePacketSocket(fd)?;
+
+        // protocol is the ethertype in network byte order
+        if protocol != 0 {
+            let addr = SockAddrLink {
+                Family: AFType::AF_PACKET as u16,
+                Protocol: protocol as u16,
+                ..Default::default()
+            };
+            let res = HostSpace::Bind(fd, &addr as *const _ as u64, addr.Len() as u32, 0);
+            if res < 0 {
+                return Err(Error::SysError(-res as i32));
+            }
+        }
+
+        return Ok(Some(Arc::new(file)));
+    }
+
+    fn Pair(
+        &self,
+        _task: &Task,
+        _stype: i32,
+        _protocol: i32,
+    ) -> Result<Option<(Arc<File>, Arc<File>)>> {
+        return Err(Error::SysError(SysErr::EOPNOTSUPP));
+    }
+}
+
+// RawSocketProvider provides the raw ICMP sockets, the other raw protocols
+// aren't supported.
+pub struct RawSocketProvider {
+    pub family: i32,
+}
+
+impl Provider for RawSocketProvider {
+    fn Socket(&self, task: &Task, stype: i32, protocol: i32) -> Result<Option<Arc<File>>> {
+        let nonblocking = stype & SocketFlags::SOCK_NONBLOCK != 0;
+        let stype = stype & SocketType::SOCK_TYPE_MASK;
+        if stype != SockType::SOCK_RAW {
+            return Ok(None);
+        }
+
+        let icmp = if self.family == AFType::AF_INET6 {
+            LibcConst::IPPROTO_ICMPV6
+        } else {
+            LibcConst::IPPROTO_ICMP
+        };
+        if protocol != icmp as i32 {
+            return Err(Error::SysError(SysErr::EPROTONOSUPPORT));
+        }
+
+        CheckNetRaw(task)?;
+
+        let res = HostSpace::Socket(self.family, stype | SocketFlags::SOCK_CLOEXEC, protocol);
+        if res < 0 {
+            return Err(Error::SysError(-res as i32));
+        }
+
+        let file = newHostSocketFile(task, self.family, res as i32, stype, nonblocking, None)?;
+        return Ok(Some(Arc::new(file)));
+    }
+
+    fn Pair(
+        &self,
+        _task: &Task,
+        _stype: i32,
+        _protocol: i32,
+    ) -> Result<Option<(Arc<File>, Arc<File>)>> {
+        return Err(Error::SysError(SysErr::EOPNOTSUPP));
+    }
+}
+
+// Init registers the raw providers before the other host ones.
+pub fn Init() {
+    for family in [AFType::AF_INET, AFType::AF_INET6].iter() {
+        FAMILIAES
+            .write()
+            .RegisterProvider(*family, Box::new(RawSocketProvider { family: *family }))
+    }
+
+    FAMILIAES
+        .write()
+        .RegisterProvider(AFType::AF_PACKET, Box::new(PacketSocketProvider {}));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run runs the subset of the classic BPF of the filters on a packet of
+    // the interface ifindex.
+    fn Run(prog: &[SockFilter], ifindex: u32) -> u32 {
+        let mut a = 0;
+        let mut pc = 0;
+        loop {
+            let ins = prog[pc];
+            match ins.Code {
+                c if c == BPF_LD | BPF_W | BPF_ABS => {
+                    assert_eq!(ins.K, (SKF_AD_OFF + SKF_AD_IFINDEX) as u32);
+                    a = ifindex;
+                }
+                c if c == BPF_JMP | BPF_JEQ | BPF_K => {
+                    let jump = if a == ins.K {
+                        ins.JumpTrue
+                    } else {
+                        ins.JumpFalse
+                    };
+                    pc += jump as usize;
+                }
+                c if c == BPF_RET | BPF_K => return ins.K,
+                c => panic!("unexpected code {:x}", c),
+            }
+            pc += 1;
+        }
+    }
+
+    fn Confined(indexes: &[u32], tail: u32) -> Vec<SockFilter> {
+        let mut prog = ConfineProg(indexes);
+        prog.push(SockFilter::Stmt(BPF_RET | BPF_K, tail));
+        return prog;
+    }
+
+    #[test]
+    fn test_ConfineProg() {
+        let prog = Confined(&[2, 5, 7], 1500);
+        assert_eq!(prog.len(), 3 + 3);
+        for index in [2, 5, 7].iter() {
+            assert_eq!(Run(&prog, *index), 1500);
+        }
+        for index in [0, 1, 3, 6, 8].iter() {
+            assert_eq!(Run(&prog, *index), 0);
+        }
+
+        // each jump lands on the instruction after the drop
+        for (i, ins) in prog[1..4].iter().enumerate() {
+            assert_eq!(1 + i + 1 + ins.JumpTrue as usize, 5);
+            assert_eq!(ins.JumpFalse, 0);
+        }
+    }
+
+    #[test]
+    fn test_ConfineProgEmpty() {
+        let prog = Confined(&[], u32::MAX);
+        assert_eq!(Run(&prog, 0), 0);
+        assert_eq!(Run(&prog, 1), 0);
+    }
+
+    #[test]
+    fn test_ConfineProgMaxJump() {
+        let indexes: Vec<u32> = (1..300).collect();
+        let prog = Confined(&indexes, 1);
+        assert_eq!(prog.len(), 255 + 3);
+        assert_eq!(prog[1].JumpTrue, u8::MAX);
+        assert_eq!(Run(&prog, 1), 1);
+        assert_eq!(Run(&prog, 255), 1);
+        // the indexes past the jump range are dropped
+        assert_eq!(Run(&prog, 256), 0);
+    }
+}
@@ -46,6 +46,7 @@ use super::super::super::kernel::async_wait::*;
 use super::super::super::kernel::fd_table::*;
 use super::super::super::kernel::kernel::GetKernel;
 use super::super::super::kernel::time::*;
+use super::super::super::kernel::timer::TIME_KEEPER;
 use super::super::super::kernel::waiter::*;
 use super::super::super::quring::QUring;
 use crate::qlib::rdmasocket::RDMAServerSock;
@@ -61,8 +62,10 @@ use super::super::super::SHARESPACE;
 use super::super::control::*;
 use super::super::socket::*;
 use super::super::unix::transport::unix::*;
+use super::firewall::FIREWALL;
 use super::hostsocket::*;
 use super::rdma_socket::*;
+use super::traffic::*;
 use super::uring_socket::*;
 use crate::qlib::kernel::socket::hostinet::loopbacksocket::LoopbackSocket;
 
@@ -91,6 +94,7 @@ fn newSocketFile(
         hostiops.clone(),
         socketBuf,
         addr,
+        NET_TRAFFIC.ForTask(task),
     )?;
 
     let file = File::New(
@@ -204,6 +208,8 @@ pub struct SocketOperationsIntern {
     passInq: AtomicBool,
     pub tcpRDMA: bool,
     pub udpRDMA: bool,
+    // the traffic counters of the container of the socket
+    pub traffic: Arc<ContainerTraffic>,
 }
 
 #[derive(Clone)]
@@ -218,6 +224,7 @@ impl SocketOperations {
         hostops: HostInodeOp,
         socketBuf: SocketBufType,
         addr: Option<Vec<u8>>,
+        traffic: Arc<ContainerTraffic>,
     ) -> Result<Self> {
         let addr = match addr {
             None => None,
@@ -261,6 +268,7 @@ impl SocketOperations {
             passInq: AtomicBool::new(false),
             tcpRDMA,
             udpRDMA,
+            traffic,
         };
 
         let ret = Self(Arc::new(ret));
@@ -542,6 +550,9 @@ impl Waitable for SocketOperations {
                     SockInfo::RDMADataSocket(dataSock) => {
                         return dataSock.socketBuf.Events() & mask;
                     }
+                    SockInfo::Socket(info) if info.error != 0 => {
+                        return (EVENT_ERR | WRITEABLE_EVENT) & mask;
+                    }
                     _ => {
                         return 0;
                     }
@@ -626,6 +637,83 @@ impl DummyHostSocket {
     }
 }
 
+// HostQueueSize returns the bytes in the transmit and the receive queue of
+// the host socket.
+pub fn HostQueueSize(hostfd: i32) -> (usize, usize) {
+    let mut tx: i32 = 0;
+    let mut rx: i32 = 0;
+    if HostSpace::IoCtl(hostfd, LibcConst::TIOCOUTQ, &mut tx as *mut _ as u64) < 0 {
+        tx = 0;
+    }
+
+    if HostSpace::IoCtl(hostfd, LibcConst::TIOCINQ, &mut rx as *mut _ as u64) < 0 {
+        rx = 0;
+    }
+
+    return (tx as usize, rx as usize);
+}
+
+// RecvErrQueue reads the error queue of the host socket, which holds the
+// socket errors and the TX timestamps. It never blocks, as in linux. The
+// sender is the offender address of the IP_RECVERR errors.
+pub fn RecvErrQueue(
+    task: &Task,
+    hostfd: i32,
+    dsts: &mut [IoVec],
+    flags: i32,
+    senderRequested: bool,
+    controlDataLen: usize,
+) -> Result<(i64, i32, Option<(SockAddr, usize)>, Vec<u8>)> {
+    let size = IoVec::NumBytes(dsts);
+    let buf = DataBuff::New(size);
+    let iovs = buf.Iovs(size);
+
+    let mut msgHdr = MsgHdr::default();
+    if iovs.len() > 0 {
+        msgHdr.iov = &iovs[0] as *const _ as u64;
+        msgHdr.iovLen = iovs.len();
+    }
+
+    let mut addr: [u8; SIZEOF_SOCKADDR] = [0; SIZEOF_SOCKADDR];
+    if senderRequested {
+        msgHdr.msgName = &mut addr[0] as *mut _ as u64;
+        msgHdr.nameLen = SIZEOF_SOCKADDR as u32;
+    }
+
+    let mut control: Vec<u8> = vec![0; controlDataLen];
+    if controlDataLen > 0 {
+        msgHdr.msgControl = &mut control[0] as *mut _ as u64;
+        msgHdr.msgControlLen = controlDataLen;
+    }
+
+    let flags = flags & (MsgType::MSG_ERRQUEUE | MsgType::MSG_PEEK | MsgType::MSG_TRUNC);
+    let res = HostSpace::IORecvMsg(
+        hostfd,
+        &mut msgHdr as *mut _ as u64,
+        flags | MsgType::MSG_DONTWAIT,
+        false,
+    );
+    if res < 0 {
+        return Err(Error::SysError(-res as i32));
+    }
+
+    control.resize(msgHdr.msgControlLen, 0);
+    AdjustTimestamps(&mut control, TIME_KEEPER.HostRealtimeOffset());
+
+    // the errors without an offender have no address
+    let senderAddr = if senderRequested && msgHdr.nameLen >= 4 {
+        let addr = GetAddr(addr[0] as i16, &addr[0..msgHdr.nameLen as usize])?;
+        let l = addr.Len();
+        Some((addr, l))
+    } else {
+        None
+    };
+
+    let count = core::cmp::min(res as usize, size);
+    task.CopyDataOutToIovs(&buf.buf[0..count], dsts, false)?;
+    return Ok((res as i64, msgHdr.msgFlags, senderAddr, control));
+}
+
 // pass the ioctl to the shadow hostfd
 pub fn HostIoctlIFReq(task: &Task, hostfd: i32, request: u64, addr: u64) -> Result<()> {
     let mut ifr: IFReq = task.CopyInObj(addr)?;
@@ -896,8 +984,46 @@ impl SocketOperations {
     //pub fn ConnectIntern(fd: i32, addr: u64, addrlen: u32) -> i64 {}
 }
 
+// RDMASockAddr returns the address of a TSoR socket, the ipv4 address is
+// IPv4-mapped on an AF_INET6 socket
+fn RDMASockAddr(family: i32, ipAddr: u32, ipv6Addr: [u8; 16], port: u16) -> SockAddr {
+    if family != AFType::AF_INET6 {
+        return SockAddr::Inet(SockAddrInet {
+            Family: AFType::AF_INET as u16,
+            Port: port,
+            Addr: ipAddr.to_be_bytes(),
+            Zero: [0; 8],
+        });
+    }
+
+    let mut addr = ipv6Addr;
+    if !IsIpv6(&ipv6Addr) && ipAddr != 0 {
+        addr[10] = 0xff;
+        addr[11] = 0xff;
+        addr[12..].copy_from_slice(&ipAddr.to_be_bytes());
+    }
+    return SockAddr::Inet6(SocketAddrInet6 {
+        Family: AFType::AF_INET6 as u16,
+        Port: port,
+        Flowinfo: 0,
+        Addr: addr,
+        Scope_id: 0,
+    });
+}
+
+// Ipv4Mapped returns the ipv4 address of an IPv4-mapped ipv6 address
+fn Ipv4Mapped(addr: &[u8; 16]) -> Option<u32> {
+    if addr[..10].iter().all(|b| *b == 0) && addr[10] == 0xff && addr[11] == 0xff {
+        return Some(u32::from_be_bytes([addr[12], addr[13], addr[14], addr[15]]));
+    }
+
+    return None;
+}
+
 impl SockOperations for SocketOperations {
     fn Connect(&self, task: &Task, sockaddr: &[u8], blocking: bool) -> Result<i64> {
+        FIREWALL.CheckConnect(sockaddr)?;
+
         let mut socketaddr = sockaddr;
 
         if (self.family == AFType::AF_INET || self.family == AFType::AF_INET6)
@@ -928,6 +1054,29 @@ impl SockOperations for SocketOperations {
                     *self.socketBuf.lock() = socketBuf.clone();
                     res = -SysErr::EINPROGRESS;
                 }
+                SockAddr::Inet6(ipv6) => {
+                    let port = ipv6.Port.to_le();
+                    //TODO: get local ip and port
+                    let srcPort = 16866u16.to_be();
+                    let rdmaId = GlobalRDMASvcCli()
+                        .nextRDMAId
+                        .fetch_add(1, Ordering::Release);
+                    GlobalRDMASvcCli()
+                        .rdmaIdToSocketMappings
+                        .lock()
+                        .insert(rdmaId, self.fd);
+                    let _ret = match Ipv4Mapped(&ipv6.Addr) {
+                        Some(ipAddr) => {
+                            GlobalRDMASvcCli().connectUsingPodId(rdmaId, ipAddr, port, srcPort)
+                        }
+                        None => {
+                            GlobalRDMASvcCli().connectUsingPodIdV6(rdmaId, ipv6.Addr, port, srcPort)
+                        }
+                    };
+                    let socketBuf = self.SocketBufType().Connect();
+                    *self.socketBuf.lock() = socketBuf.clone();
+                    res = -SysErr::EINPROGRESS;
+                }
                 _ => {
                     panic!("sockAddr: {:?} can't enable RDMA!", sockAddr);
                 }
@@ -988,6 +1137,17 @@ impl SockOperations for SocketOperations {
             }
         }
 
+        if self.tcpRDMA {
+            let fdInfo = GlobalIOMgr().GetByHost(self.fd).unwrap();
+            let sockInfo = fdInfo.lock().sockInfo.lock().clone();
+            match sockInfo {
+                SockInfo::Socket(info) if info.error != 0 => {
+                    return Err(Error::SysError(info.error));
+                }
+                _ => (),
+            }
+        }
+
         let mut val: i32 = 0;
         let len: i32 = 4;
         let res = HostSpace::GetSockOpt(
@@ -1094,6 +1254,9 @@ impl SockOperations for SocketOperations {
             Some(remoteAddr.to_vec()),
         )?;
 
+        // the connection is closed with the file if the peer is denied
+        FIREWALL.CheckAccept(&acceptItem.addr.data)?;
+
         let fdFlags = FDFlags {
             CloseOnExec: flags & SocketFlags::SOCK_CLOEXEC != 0,
         };
@@ -1103,6 +1266,8 @@ impl SockOperations for SocketOperations {
     }
 
     fn Bind(&self, task: &Task, sockaddr: &[u8]) -> Result<i64> {
+        FIREWALL.CheckBind(sockaddr)?;
+
         let mut socketaddr = sockaddr;
 
         info!(
@@ -1135,7 +1300,9 @@ impl SockOperations for SocketOperations {
                     if self.tcpRDMA {
                         *fdInfo.lock().sockInfo.lock() = SockInfo::Socket(SocketInfo {
                             ipAddr: u32::from_be_bytes(ipv4.Addr), //u32::from_be_bytes([192, 168, 6, 8]), //ipAddr: u32::from_be_bytes(ipv4.Addr), // ipAddr: 3232237064,
-                            port,                                  // port: 58433,
+                            ipv6Addr: [0; 16],
+                            port, // port: 58433,
+                            error: 0,
                         }); //192.168.6.8:16868
                     } else if self.udpRDMA {
                         debug!("SocketOperations::Bind, port: {}", port);
@@ -1150,11 +1317,18 @@ impl SockOperations for SocketOperations {
                 SockAddr::Inet6(ipv6) => {
                     let port = ipv6.Port.to_le();
                     let fdInfo = GlobalIOMgr().GetByHost(self.fd).unwrap();
+                    // an IPv4-mapped address binds the ipv4 address, "::" binds
+                    // both families as nodejs does
+                    let (ipAddr, ipv6Addr) = match Ipv4Mapped(&ipv6.Addr) {
+                        Some(ipAddr) => (ipAddr, [0; 16]),
+                        None => (0, ipv6.Addr),
+                    };
                     *fdInfo.lock().sockInfo.lock() = SockInfo::Socket(SocketInfo {
-                        //ipAddr: u32::from_be_bytes(ipv6.Addr), //u32::from_be_bytes([192, 168, 6, 8]), //ipAddr: u32::from_be_bytes(ipv4.Addr), // ipAddr: 3232237064,
-                        ipAddr: u32::from_be_bytes([0, 0, 0, 0]), //TODO: this is a temp workaround for nodejs
-                        port,                                     // port: 58433,
-                    }); //192.168.6.8:16868
+                        ipAddr,
+                        ipv6Addr,
+                        port,
+                        error: 0,
+                    });
                     if self.udpRDMA {
                         GlobalRDMASvcCli()
                             .portToFdInfoMappings
@@ -1172,6 +1346,8 @@ impl SockOperations for SocketOperations {
     }
 
     fn Listen(&self, _task: &Task, backlog: i32) -> Result<i64> {
+        FIREWALL.CheckListen(self.fd)?;
+
         let asyncAccept = SHARESPACE.config.read().AsyncAccept
             && (self.family == AFType::AF_INET || self.family == AFType::AF_INET6)
             && self.stype == SockType::SOCK_STREAM;
@@ -1208,8 +1384,13 @@ impl SockOperations for SocketOperations {
                         .rdmaIdToSocketMappings
                         .lock()
                         .insert(rdmaId, self.fd);
-                    let rdmaSocket =
-                        RDMAServerSock::New(rdmaId, acceptQueue.clone(), info.ipAddr, info.port);
+                    let rdmaSocket = RDMAServerSock::New(
+                        rdmaId,
+                        acceptQueue.clone(),
+                        info.ipAddr,
+                        info.ipv6Addr,
+                        info.port,
+                    );
                     *fdInfo.lock().sockInfo.lock() = SockInfo::RDMAServerSocket(rdmaSocket);
                     debug!("Listen, rdmaId: {}, serverSockFd: {}", rdmaId, self.fd);
                     let _ret = GlobalRDMASvcCli().listenUsingPodId(rdmaId, port, backlog);
@@ -1518,18 +1699,22 @@ impl SockOperations for SocketOperations {
             let fdInfoLock = fdInfo.lock();
             let sockInfo = fdInfoLock.sockInfo.lock().clone();
             let ipAddr;
+            let mut ipv6Addr = [0; 16];
             let port;
             match sockInfo {
                 SockInfo::RDMADataSocket(sock) => {
                     ipAddr = sock.localIpAddr;
+                    ipv6Addr = sock.localIpv6Addr;
                     port = sock.localPort;
                 }
                 SockInfo::RDMAServerSocket(sock) => {
                     ipAddr = sock.ipAddr;
+                    ipv6Addr = sock.ipv6Addr;
                     port = sock.port;
                 }
                 SockInfo::Socket(sock) => {
                     ipAddr = sock.ipAddr;
+                    ipv6Addr = sock.ipv6Addr;
                     port = sock.port;
                 }
                 SockInfo::RDMAUDPSocket(sock) => {
@@ -1540,12 +1725,7 @@ impl SockOperations for SocketOperations {
                     panic!("Incorrect sockInfo")
                 }
             }
-            let sockAddr = SockAddr::Inet(SockAddrInet {
-                Family: AFType::AF_INET as u16,
-                Port: port,
-                Addr: ipAddr.to_be_bytes(),
-                Zero: [0; 8],
-            });
+            let sockAddr = RDMASockAddr(self.family, ipAddr, ipv6Addr, port);
             let len = socketaddr.len() as usize;
             sockAddr.Marsh(socketaddr, len)?;
 
@@ -1572,10 +1752,12 @@ impl SockOperations for SocketOperations {
             let fdInfoLock = fdInfo.lock();
             let sockInfo = fdInfoLock.sockInfo.lock().clone();
             let ipAddr;
+            let ipv6Addr;
             let port;
             match sockInfo {
                 SockInfo::RDMADataSocket(sock) => {
                     ipAddr = sock.peerIpAddr;
+                    ipv6Addr = sock.peerIpv6Addr;
                     port = sock.peerPort;
                 }
                 SockInfo::RDMAServerSocket(_sock) => {
@@ -1589,12 +1771,7 @@ impl SockOperations for SocketOperations {
                 }
             }
             debug!("GetPeerName, ipAddr: {}, port: {}", ipAddr, port);
-            let sockAddr = SockAddr::Inet(SockAddrInet {
-                Family: AFType::AF_INET as u16,
-                Port: port,
-                Addr: ipAddr.to_be_bytes(),
-                Zero: [0; 8],
-            });
+            let sockAddr = RDMASockAddr(self.family, ipAddr, ipv6Addr, port);
             let len = sockAddr.Len();
             sockAddr.Marsh(socketaddr, len)?;
             //TODO: handle unhappy case
@@ -1617,10 +1794,8 @@ impl SockOperations for SocketOperations {
         senderRequested: bool,
         controlDataLen: usize,
     ) -> Result<(i64, i32, Option<(SockAddr, usize)>, Vec<u8>)> {
-        //todo: we don't support MSG_ERRQUEUE
         if flags & MsgType::MSG_ERRQUEUE != 0 {
-            // Pretend we have an empty error queue.
-            return Err(Error::SysError(SysErr::EAGAIN));
+            return RecvErrQueue(task, self.fd, dsts, flags, senderRequested, controlDataLen);
         }
 
         if flags
@@ -1950,6 +2125,8 @@ impl SockOperations for SocketOperations {
         msgHdr: &mut MsgHdr,
         deadline: Option<Time>,
     ) -> Result<i64> {
+        FIREWALL.CheckSendMsg(task, msgHdr)?;
+
         if flags
             & !(MsgType::MSG_DONTWAIT
                 | MsgType::MSG_EOR
@@ -2254,6 +2431,18 @@ impl SockOperations for SocketOperations {
         return info.State as u32;
     }
 
+    fn QueueSize(&self) -> (usize, usize) {
+        let (tx, rx) = HostQueueSize(self.fd);
+        if !self.SocketBufEnabled() {
+            return (tx, rx);
+        }
+
+        let buf = self.SocketBuf();
+        let tx = tx + buf.writeBuf.lock().AvailableDataSize();
+        let rx = rx + buf.readBuf.lock().AvailableDataSize();
+        return (tx, rx);
+    }
+
     fn Type(&self) -> (i32, i32, i32) {
         return (self.family, self.stype, -1);
     }
@@ -2359,9 +2548,74 @@ impl Provider for SocketProvider {
 }
 
 pub fn Init() {
-    for family in [AFType::AF_INET, AFType::AF_INET6, AFType::AF_NETLINK].iter() {
+    // AF_NETLINK is served by the guest, it never reaches the host netlink
+    for family in [AFType::AF_INET, AFType::AF_INET6].iter() {
         FAMILIAES
             .write()
             .RegisterProvider(*family, Box::new(SocketProvider { family: *family }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn Inet6Addr(sockAddr: SockAddr) -> ([u8; 16], u16) {
+        match sockAddr {
+            SockAddr::Inet6(addr) => {
+                assert_eq!(addr.Family, AFType::AF_INET6 as u16);
+                return (addr.Addr, addr.Port);
+            }
+            _ => panic!("not an ipv6 address: {:?}", sockAddr),
+        }
+    }
+
+    #[test]
+    fn test_Ipv4Mapped() {
+        let mut addr = [0u8; 16];
+        addr[10] = 0xff;
+        addr[11] = 0xff;
+        addr[12..].copy_from_slice(&[10, 244, 1, 5]);
+        assert_eq!(Ipv4Mapped(&addr), Some(u32::from_be_bytes([10, 244, 1, 5])));
+
+        // "::" and a global ipv6 address are not mapped
+        assert_eq!(Ipv4Mapped(&[0; 16]), None);
+        let mut global = [0u8; 16];
+        global[0] = 0xfd;
+        global[15] = 5;
+        assert_eq!(Ipv4Mapped(&global), None);
+        // the IPv4-compatible form has no ffff
+        let mut compat = [0u8; 16];
+        compat[12..].copy_from_slice(&[10, 244, 1, 5]);
+        assert_eq!(Ipv4Mapped(&compat), None);
+    }
+
+    #[test]
+    fn test_RDMASockAddr() {
+        let ip = u32::from_be_bytes([10, 244, 1, 5]);
+        let port = 8080u16.to_be();
+        match RDMASockAddr(AFType::AF_INET, ip, [0; 16], port) {
+            SockAddr::Inet(addr) => {
+                assert_eq!(addr.Family, AFType::AF_INET as u16);
+                assert_eq!(addr.Addr, [10, 244, 1, 5]);
+                assert_eq!(addr.Port, port);
+            }
+            sockAddr => panic!("not an ipv4 address: {:?}", sockAddr),
+        }
+
+        // the ipv4 address of an AF_INET6 socket is IPv4-mapped
+        let (addr, p) = Inet6Addr(RDMASockAddr(AFType::AF_INET6, ip, [0; 16], port));
+        assert_eq!(Ipv4Mapped(&addr), Some(ip));
+        assert_eq!(p, port);
+
+        let mut ipv6 = [0u8; 16];
+        ipv6[0] = 0xfd;
+        ipv6[15] = 5;
+        let (addr, _) = Inet6Addr(RDMASockAddr(AFType::AF_INET6, 0, ipv6, port));
+        assert_eq!(addr, ipv6);
+
+        // a socket bound on "::" reports "::"
+        let (addr, _) = Inet6Addr(RDMASockAddr(AFType::AF_INET6, 0, [0; 16], port));
+        assert_eq!(addr, [0; 16]);
+    }
+}
@@ -0,0 +1,397 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::qlib::mutex::*;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+use lazy_static::lazy_static;
+
+use super::super::super::super::common::*;
+use super::super::super::super::control_msg::NetStats;
+use super::super::super::super::linux_def::*;
+use super::super::super::kernel::timer::MonotonicNow;
+use super::super::super::task::*;
+use super::super::netlink::INTERFACES;
+use super::super::unix::transport::unix::SockType;
+
+lazy_static! {
+    pub static ref NET_TRAFFIC: NetTraffic = NetTraffic::default();
+    pub static ref NET_SNMP: SnmpCounters = SnmpCounters::default();
+}
+
+// NetCounters are the traffic counters of the sockets of a container on an
+// interface.
+#[derive(Default)]
+pub struct NetCounters {
+    pub rxBytes: AtomicU64,
+    pub rxPackets: AtomicU64,
+    pub rxErrors: AtomicU64,
+    pub txBytes: AtomicU64,
+    pub txPackets: AtomicU64,
+    pub txErrors: AtomicU64,
+    pub connections: AtomicU64,
+}
+
+// IsTrafficError returns whether a failed send or receive counts as an error,
+// a call which would block or is interrupted doesn't.
+fn IsTrafficError(e: &Error) -> bool {
+    match e {
+        Error::SysError(errno) => {
+            return *errno != SysErr::EAGAIN
+                && *errno != SysErr::EINTR
+                && *errno != SysErr::ERESTARTSYS
+                && *errno != SysErr::ERESTARTNOINTR
+                && *errno != SysErr::ERESTARTNOHAND
+        }
+        Error::ErrInterrupted => return false,
+        _ => return true,
+    }
+}
+
+impl NetCounters {
+    pub fn RxPackets(&self, bytes: u64, packets: u64) {
+        self.rxBytes.fetch_add(bytes, Ordering::Relaxed);
+        self.rxPackets.fetch_add(packets, Ordering::Relaxed);
+    }
+
+    pub fn TxPackets(&self, bytes: u64, packets: u64) {
+        self.txBytes.fetch_add(bytes, Ordering::Relaxed);
+        self.txPackets.fetch_add(packets, Ordering::Relaxed);
+    }
+
+    // Rx counts the result of a receive call as a packet.
+    pub fn Rx(&self, res: &Result<i64>) {
+        match res {
+            Ok(n) if *n > 0 => self.RxPackets(*n as u64, 1),
+            Ok(_) => (),
+            Err(e) => self.RxError(e),
+        }
+    }
+
+    // Tx counts the result of a send call as a packet.
+    pub fn Tx(&self, res: &Result<i64>) {
+        match res {
+            Ok(n) if *n > 0 => self.TxPackets(*n as u64, 1),
+            Ok(_) => (),
+            Err(e) => self.TxError(e),
+        }
+    }
+
+    pub fn RxError(&self, e: &Error) {
+        if IsTrafficError(e) {
+            self.rxErrors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn TxError(&self, e: &Error) {
+        if IsTrafficError(e) {
+            self.txErrors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn Connected(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn Stats(&self, name: &str) -> NetStats {
+        return NetStats {
+            Interface: name.to_string(),
+            RxBytes: self.rxBytes.load(Ordering::Relaxed),
+            RxPackets: self.rxPackets.load(Ordering::Relaxed),
+            RxErrors: self.rxErrors.load(Ordering::Relaxed),
+            RxDropped: 0,
+            TxBytes: self.txBytes.load(Ordering::Relaxed),
+            TxPackets: self.txPackets.load(Ordering::Relaxed),
+            TxErrors: self.txErrors.load(Ordering::Relaxed),
+            TxDropped: 0,
+            Connections: self.connections.load(Ordering::Relaxed),
+        };
+    }
+}
+
+// ContainerTraffic is the traffic of the sockets of a container. The traffic
+// between the sockets of the sandbox, which stays in the guest memory, is on
+// the loopback interface, the traffic through the host sockets is on the
+// first interface of the sandbox. The shaper caps the traffic the container
+// sends through the host sockets.
+#[derive(Default)]
+pub struct ContainerTraffic {
+    pub lo: NetCounters,
+    pub ext: NetCounters,
+    pub shaper: EgressShaper,
+}
+
+impl ContainerTraffic {
+    pub fn Counters(&self, loopback: bool) -> &NetCounters {
+        if loopback {
+            return &self.lo;
+        }
+
+        return &self.ext;
+    }
+
+    // Stats returns the counters with the interface names of the sandbox.
+    pub fn Stats(&self) -> Vec<NetStats> {
+        let mut stats = Vec::new();
+        let intfs = INTERFACES.read();
+        let mut ext = false;
+        for intf in &intfs.interfaces {
+            if intf.IsLoopback() {
+                stats.push(self.lo.Stats(&intf.name));
+            } else if !ext {
+                stats.push(self.ext.Stats(&intf.name));
+                ext = true;
+            }
+        }
+
+        return stats;
+    }
+}
+
+// NetTraffic is the traffic of the containers of the sandbox, keyed by the
+// container id of the task creating the socket.
+#[derive(Default)]
+pub struct NetTraffic {
+    containers: QMutex<BTreeMap<String, Arc<ContainerTraffic>>>,
+}
+
+impl NetTraffic {
+    pub fn Get(&self, cid: &str) -> Arc<ContainerTraffic> {
+        let mut containers = self.containers.lock();
+        if let Some(t) = containers.get(cid) {
+            return t.clone();
+        }
+
+        let t = Arc::new(ContainerTraffic::default());
+        containers.insert(cid.to_string(), t.clone());
+        return t;
+    }
+
+    pub fn ForTask(&self, task: &Task) -> Arc<ContainerTraffic> {
+        return self.Get(&task.Thread().ContainerID());
+    }
+
+    pub fn Stats(&self, cid: &str) -> Vec<NetStats> {
+        return self.Get(cid).Stats();
+    }
+
+    // Octets returns the bytes all the containers received and sent.
+    pub fn Octets(&self) -> (u64, u64) {
+        let (mut rx, mut tx) = (0, 0);
+        for t in self.containers.lock().values() {
+            for c in [&t.lo, &t.ext].iter() {
+                rx += c.rxBytes.load(Ordering::Relaxed);
+                tx += c.txBytes.load(Ordering::Relaxed);
+            }
+        }
+
+        return (rx, tx);
+    }
+}
+
+// ProtoCounters are the snmp counters of a transport protocol of the sandbox,
+// the segments of tcp and the datagrams of udp. The opens are the ones of tcp.
+#[derive(Default)]
+pub struct ProtoCounters {
+    pub inPackets: AtomicU64,
+    pub inErrors: AtomicU64,
+    pub outPackets: AtomicU64,
+    pub outErrors: AtomicU64,
+    pub activeOpens: AtomicU64,
+    pub passiveOpens: AtomicU64,
+    pub attemptFails: AtomicU64,
+}
+
+impl ProtoCounters {
+    pub fn InPackets(&self, packets: u64) {
+        self.inPackets.fetch_add(packets, Ordering::Relaxed);
+    }
+
+    pub fn OutPackets(&self, packets: u64) {
+        self.outPackets.fetch_add(packets, Ordering::Relaxed);
+    }
+
+    pub fn Rx(&self, res: &Result<i64>) {
+        match res {
+            Ok(n) if *n > 0 => self.InPackets(1),
+            Ok(_) => (),
+            Err(e) => self.RxError(e),
+        }
+    }
+
+    pub fn Tx(&self, res: &Result<i64>) {
+        match res {
+            Ok(n) if *n > 0 => self.OutPackets(1),
+            Ok(_) => (),
+            Err(e) => self.TxError(e),
+        }
+    }
+
+    pub fn RxError(&self, e: &Error) {
+        if IsTrafficError(e) {
+            self.inErrors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn TxError(&self, e: &Error) {
+        if IsTrafficError(e) {
+            self.outErrors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Open counts an established connection, passive is the one of accept.
+    pub fn Open(&self, passive: bool) {
+        if passive {
+            self.passiveOpens.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.activeOpens.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn AttemptFail(&self, e: &Error) {
+        if IsTrafficError(e) {
+            self.attemptFails.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// SnmpCounters are the counters of /proc/net/snmp for the sockets of the
+// sandbox, the other sockets count in other.
+#[derive(Default)]
+pub struct SnmpCounters {
+    pub tcp: ProtoCounters,
+    pub udp: ProtoCounters,
+    pub other: ProtoCounters,
+}
+
+impl SnmpCounters {
+    pub fn Proto(&self, family: i32, stype: i32) -> &ProtoCounters {
+        if family != AFType::AF_INET && family != AFType::AF_INET6 {
+            return &self.other;
+        }
+
+        match stype & SocketType::SOCK_TYPE_MASK {
+            SockType::SOCK_STREAM => return &self.tcp,
+            SockType::SOCK_DGRAM => return &self.udp,
+            _ => return &self.other,
+        }
+    }
+}
+
+// the burst of the shaper is 100ms of traffic, but no less than 64KB so that
+// a TSO sized segment always goes out in one piece
+const SHAPER_BURSTS_PER_SEC: u64 = 10;
+const SHAPER_MIN_BURST: u64 = 64 * 1024;
+
+#[derive(Default)]
+struct ShaperState {
+    tokens: i64,
+    last: i64,
+}
+
+// EgressShaper is the token bucket capping the traffic a container sends
+// through the host sockets. The sends take the tokens of the bytes the host
+// accepted, the tokens go negative when a send is larger than the bucket and
+// the next sends then wait for the refill.
+#[derive(Default)]
+pub struct EgressShaper {
+    // bytes per second, 0 means no cap
+    rate: AtomicU64,
+    burst: AtomicU64,
+    state: QMutex<ShaperState>,
+}
+
+impl EgressShaper {
+    pub fn SetRate(&self, rate: u64) {
+        let burst = core::cmp::max(rate / SHAPER_BURSTS_PER_SEC, SHAPER_MIN_BURST);
+        let mut state = self.state.lock();
+        state.tokens = burst as i64;
+        state.last = 0;
+        self.burst.store(burst, Ordering::Relaxed);
+        self.rate.store(rate, Ordering::Release);
+    }
+
+    pub fn Enabled(&self) -> bool {
+        return self.rate.load(Ordering::Acquire) != 0;
+    }
+
+    // Charge takes the tokens of len bytes sent and returns how long (ns) the
+    // next send has to wait for the refill.
+    pub fn Charge(&self, len: usize) -> i64 {
+        let rate = self.rate.load(Ordering::Acquire);
+        if rate == 0 {
+            return 0;
+        }
+
+        let burst = self.burst.load(Ordering::Relaxed) as i64;
+        let now = MonotonicNow();
+        let mut state = self.state.lock();
+        if state.last == 0 {
+            state.last = now;
+        } else {
+            // the clock only moves on by the whole tokens refilled so that the
+            // frequent calls don't drop the fractions
+            let elapsed = (now - state.last) as u128;
+            let refill = elapsed * rate as u128 / 1_000_000_000;
+            if state.tokens as i128 + refill as i128 >= burst as i128 {
+                state.tokens = burst;
+                state.last = now;
+            } else {
+                state.tokens += refill as i64;
+                state.last += (refill * 1_000_000_000 / rate as u128) as i64;
+            }
+        }
+        state.tokens -= len as i64;
+        if state.tokens >= 0 {
+            return 0;
+        }
+
+        return (-state.tokens as u128 * 1_000_000_000 / rate as u128) as i64;
+    }
+
+    // Delay returns how long (ns) a send has to wait for the tokens taken by
+    // the previous sends.
+    pub fn Delay(&self) -> i64 {
+        return self.Charge(0);
+    }
+
+    // Sent charges the bytes of a successful send.
+    pub fn Sent(&self, res: &Result<i64>) {
+        if let Ok(n) = res {
+            if *n > 0 {
+                self.Charge(*n as usize);
+            }
+        }
+    }
+
+    // Wait blocks the task until the previous sends are within the rate.
+    pub fn Wait(&self, task: &Task) -> Result<()> {
+        let delay = self.Delay();
+        if delay == 0 {
+            return Ok(());
+        }
+
+        let (_, res) = task.blocker.BlockWithMonoTimeout(false, Some(delay));
+        match res {
+            Err(Error::SysError(SysErr::ETIMEDOUT)) => return Ok(()),
+            Err(Error::ErrInterrupted) => return Err(Error::SysError(SysErr::ERESTARTSYS)),
+            res => return res,
+        }
+    }
+}
@@ -52,10 +52,14 @@ use super::super::super::SHARESPACE;
 use super::super::control::*;
 use super::super::socket::*;
 use super::super::unix::transport::unix::*;
+use super::socket::HostQueueSize;
+use super::socket::RecvErrQueue;
 use crate::qlib::kernel::socket::hostinet::socket::HostIoctlIFConf;
 use crate::qlib::kernel::socket::hostinet::socket::HostIoctlIFReq;
 use crate::qlib::bytestream::*;
 use crate::qlib::kernel::socket::hostinet::loopbacksocket::*;
+use crate::qlib::kernel::socket::hostinet::firewall::*;
+use crate::qlib::kernel::socket::hostinet::traffic::*;
 use crate::qlib::kernel::kernel::abstract_socket_namespace::*;
 use crate::qlib::kernel::kernel::waiter::Queue;
 
@@ -81,6 +85,7 @@ pub fn newUringSocketFile(
         hostiops.clone(),
         socketType,
         addr,
+        NET_TRAFFIC.ForTask(task),
     )?;
 
     let file = File::New(
@@ -190,6 +195,8 @@ pub struct UringSocketOperationsIntern {
     pub loopbackPort: AtomicU16,
     pub hostops: HostInodeOp,
     passInq: AtomicBool,
+    // the traffic counters of the container of the socket
+    pub traffic: Arc<ContainerTraffic>,
 }
 
 #[derive(Clone)]
@@ -214,7 +221,9 @@ impl Drop for UringSocketOperations {
         if Arc::strong_count(&self.0) == 1 {
             let loopbackPort = self.loopbackPort.load(Ordering::Acquire);
             if loopbackPort > 0 {
-                TCP_SOCKET.Remove(loopbackPort).unwrap();
+                if let UringSocketType::TCPUringlServer(q) = &*self.socketType.lock() {
+                    TCP_SOCKET.Remove(loopbackPort, q).unwrap();
+                }
             }
         }
     }
@@ -240,6 +249,64 @@ impl UringSocketOperations {
         return Ok(socketAddr);
     }
 
+    // RegisterLoopback makes the listener reachable from the sandbox through
+    // the guest memory, it is keyed by the bound address of the host socket.
+    fn RegisterLoopback(&self, q: &AcceptQueue) -> Result<()> {
+        let ep = match LocalAddr(self.fd).and_then(|a| Endpoint::Parse(&a)) {
+            None => return Ok(()),
+            Some(ep) => ep,
+        };
+
+        TCP_SOCKET.Add(ep.port, ep.addr, self.family, q.clone())?;
+        self.loopbackPort.store(ep.port, Ordering::Release);
+        return Ok(());
+    }
+
+    // LoopbackConnect connects to a listener of the sandbox without the host,
+    // the host sockets only provide the addresses.
+    fn LoopbackConnect(&self, peer: &Endpoint, family: i32, q: AcceptQueue) -> Result<i64> {
+        // the client keeps an ephemeral port of the host so that its address
+        // is unique
+        let mut local = LocalAddr(self.fd).and_then(|a| Endpoint::Parse(&a));
+        if local.as_ref().map(|ep| ep.port).unwrap_or(0) == 0 {
+            let addr = SockAddrBytes(self.family, &peer.addr, 0);
+            let res = Kernel::HostSpace::Bind(self.fd, &addr[0] as *const _ as u64, addr.len() as u32, 0);
+            if res < 0 {
+                return Err(Error::SysError(-res as i32));
+            }
+            local = LocalAddr(self.fd).and_then(|a| Endpoint::Parse(&a));
+        }
+
+        let mut local = match local {
+            None => return Err(Error::SysError(SysErr::EADDRNOTAVAIL)),
+            Some(ep) => ep,
+        };
+        if local.addr.iter().all(|b| *b == 0) {
+            local.addr = peer.addr.clone();
+        }
+
+        let res =
+            Kernel::HostSpace::Socket(family, SocketType::SOCK_STREAM | SocketFlags::SOCK_CLOEXEC, 0);
+        if res < 0 {
+            return Err(Error::SysError(-res as i32));
+        }
+
+        let fd = res as i32;
+        let serverQueue = Queue::default();
+        let (clientSock, serverSock) =
+            LoopbackSocketPair(self.queue.clone(), serverQueue.clone(), self.family, family, &local, peer);
+        self.SetRemoteAddr(clientSock.remote.clone())?;
+        *self.socketType.lock() = UringSocketType::Loopback(clientSock);
+
+        let mut addr = TcpSockAddr::default();
+        let len = serverSock.remote.len();
+        addr.data[..len].copy_from_slice(&serverSock.remote);
+        q.EnqSocket(fd, addr, len as u32, serverSock.into(), serverQueue);
+        self.traffic.lo.Connected();
+        NET_SNMP.tcp.Open(false);
+        return Ok(0);
+    }
+
     pub fn SetConnErrno(&self, errno: i32) {
         self.connErrNo.store(errno, Ordering::Release);
     }
@@ -256,6 +323,7 @@ impl UringSocketOperations {
         hostops: HostInodeOp,
         socketBuf: UringSocketType,
         addr: Option<Vec<u8>>,
+        traffic: Arc<ContainerTraffic>,
     ) -> Result<Self> {
         let addr = match addr {
             None => None,
@@ -289,6 +357,7 @@ impl UringSocketOperations {
             loopbackPort: AtomicU16::new(0),
             hostops: hostops,
             passInq: AtomicBool::new(false),
+            traffic: traffic,
         };
 
         let ret = Self(Arc::new(ret));
@@ -434,20 +503,24 @@ impl UringSocketOperations {
         dsts: &mut [IoVec],
         peek: bool,
     ) -> Result<i64> {
-        let ret = match buf {
-            UringSocketType::Uring(buf) => {
-                QUring::RingFileRead(task, self.fd, self.queue.clone(), buf.clone(), dsts, true, peek)?
-            }
-            UringSocketType::Loopback(loopback) => {
-                loopback.Readv(task, dsts, peek)?
-            }
+        let (ret, loopback) = match buf {
+            UringSocketType::Uring(buf) => (
+                QUring::RingFileRead(task, self.fd, self.queue.clone(), buf.clone(), dsts, true, peek),
+                false,
+            ),
+            UringSocketType::Loopback(loopback) => (loopback.Readv(task, dsts, peek), true),
             _ => {
                 //return Err(Error::SysError(SysErr::ECONNREFUSED));
                 return Err(Error::SysError(SysErr::ENOTCONN));
             }
         };
 
-        return Ok(ret);
+        if !peek {
+            self.traffic.Counters(loopback).Rx(&ret);
+            NET_SNMP.tcp.Rx(&ret);
+        }
+
+        return ret;
     }
 
     pub fn WriteToBuf(
@@ -456,12 +529,16 @@ impl UringSocketOperations {
         buf: &UringSocketType,
         srcs: &[IoVec],
     ) -> Result<i64> {
+        // the sends through the host are counted by AsyncSend
         let ret = match buf {
             UringSocketType::Uring(buf) => {
-                QUring::SocketSend(task, self.fd, self.queue.clone(), buf.clone(), srcs, self)?
+                QUring::SocketSend(task, self.fd, self.queue.clone(), buf.clone(), srcs, self)
             }
             UringSocketType::Loopback(loopback) => {
-                loopback.Writev(task, srcs)?
+                let ret = loopback.Writev(task, srcs);
+                self.traffic.lo.Tx(&ret);
+                NET_SNMP.tcp.Tx(&ret);
+                ret
             }
             _ => {
                 //return Err(Error::SysError(SysErr::ECONNREFUSED));
@@ -469,7 +546,7 @@ impl UringSocketOperations {
             }
         };
 
-        return Ok(ret);
+        return ret;
     }
 }
 
@@ -765,42 +842,18 @@ impl FileOperations for UringSocketOperations {
 
 impl SockOperations for UringSocketOperations {
     fn Connect(&self, task: &Task, sockaddr: &[u8], blocking: bool) -> Result<i64> {
+        FIREWALL.CheckConnect(sockaddr)?;
+
         let sockType = self.SocketType();
         match sockType {
             UringSocketType::TCPInit => {
-                let addr = unsafe {
-                    &*(&sockaddr[0] as * const _ as u64 as * const SockAddrInet)
-                };
-
-                if addr.Family == AFType::AF_INET as u16 && addr.Addr == [127, 0, 0, 1] {
-                    match TCP_SOCKET.Get(addr.Port) {
-                        // in case for host network, there are process listen localhost outside of the container
-                        None => (), //return Err(Error::SysError(SysErr::ECONNREFUSED)),
-                        Some(q) => {
-                            let serverQueue = Queue::default();
-                            let (clientSock, serverSock) = LoopbackSocketPair(self.queue.clone(), serverQueue.clone());
-                            *self.socketType.lock() = UringSocketType::Loopback(clientSock);
-                            let addr = SockAddrInet {
-                                Family: AFType::AF_INET as u16,
-                                Port: 0,
-                                Addr: [127, 0, 0, 1],
-                                ..Default::default()
-                            };
-
-                            let res =
-                                Kernel::HostSpace::Socket(AFType::AF_INET, SocketType::SOCK_STREAM | SocketFlags::SOCK_CLOEXEC, 0);
-                            if res < 0 {
-                                return Err(Error::SysError(-res as i32));
-                            }
-
-                            let fd = res as i32;
-                            
-                            q.EnqSocket(fd, TcpSockAddr::NewFromInet(addr), 16, serverSock.into(), serverQueue);
-                            return Ok(0)
-                        }
-                    };
-
-
+                // without a listener of the sandbox, the connection goes to the
+                // host, e.g. to the processes outside of the container with
+                // host network
+                if let Some(ep) = SandboxDst(self.family, sockaddr, true)? {
+                    if let Some((family, q)) = TCP_SOCKET.Get(&ep.addr, ep.port) {
+                        return self.LoopbackConnect(&ep, family, q);
+                    }
                 }
 
                 QUring::AsyncConnect(self.fd, self, sockaddr)?;
@@ -904,6 +957,10 @@ impl SockOperations for UringSocketOperations {
         let remoteAddr = &acceptItem.addr.data[0..len];
         //let sockBuf = self.ConfigUringSocketType();
         let sockBuf = self.SocketType().Accept(acceptItem.sockBuf.clone());
+        let loopback = match &sockBuf {
+            UringSocketType::Loopback(_) => true,
+            _ => false,
+        };
 
         let file = newUringSocketFile(
             task,
@@ -916,6 +973,11 @@ impl SockOperations for UringSocketOperations {
             Some(remoteAddr.to_vec()),
         )?;
 
+        // the connection is closed with the file if the peer is denied
+        FIREWALL.CheckAccept(&acceptItem.addr.data)?;
+        self.traffic.Counters(loopback).Connected();
+        NET_SNMP.tcp.Open(true);
+
         let fdFlags = FDFlags {
             CloseOnExec: flags & SocketFlags::SOCK_CLOEXEC != 0,
         };
@@ -925,6 +987,8 @@ impl SockOperations for UringSocketOperations {
     }
 
     fn Bind(&self, task: &Task, sockaddr: &[u8]) -> Result<i64> {
+        FIREWALL.CheckBind(sockaddr)?;
+
         let mut socketaddr = sockaddr;
 
         let addr = unsafe {
@@ -950,15 +1014,12 @@ impl SockOperations for UringSocketOperations {
             return Err(Error::SysError(-res as i32));
         }
 
-        if addr.Family == AFType::AF_INET as u16 
-            && (addr.Addr == [0, 0, 0, 0] || addr.Addr == [127, 0, 0, 1]) {
-            self.loopbackPort.store(addr.Port, Ordering::Release);
-        }
-
         return Ok(res);
     }
 
     fn Listen(&self, _task: &Task, backlog: i32) -> Result<i64> {
+        FIREWALL.CheckListen(self.fd)?;
+
         let asyncAccept = SHARESPACE.config.read().AsyncAccept
             && (self.family == AFType::AF_INET || self.family == AFType::AF_INET6)
             && self.stype == SockType::SOCK_STREAM;
@@ -969,10 +1030,6 @@ impl SockOperations for UringSocketOperations {
         let acceptQueue = match socketBuf {
             UringSocketType::TCPUringlServer(q) => {
                 q.lock().SetQueueLen(len as usize);
-                let loopbackPort = self.loopbackPort.load(Ordering::Acquire);
-                if loopbackPort > 0 {
-                    TCP_SOCKET.Add(loopbackPort, q.clone())?;
-                }
                 return Ok(0);
             }
             UringSocketType::TCPInit => AcceptQueue::New(len as usize, self.queue.clone()),
@@ -991,10 +1048,8 @@ impl SockOperations for UringSocketOperations {
                 self.enableAsyncAccept.store(true, Ordering::Relaxed);
             }
 
-            let loopbackPort = self.loopbackPort.load(Ordering::Acquire);
-            if loopbackPort > 0 {
-                TCP_SOCKET.Add(loopbackPort, acceptQueue.clone())?;
-            }
+            // listen binds an unbound socket, the address is known now
+            self.RegisterLoopback(&acceptQueue)?;
 
             UringSocketType::TCPUringlServer(acceptQueue)
         };
@@ -1009,6 +1064,12 @@ impl SockOperations for UringSocketOperations {
         match &sockType {
             UringSocketType::TCPInit => (),
             UringSocketType::TCPConnecting => (),
+            UringSocketType::Loopback(ref loopback) => {
+                // the host socket is not connected
+                let res = loopback.Shutdown(how)?;
+                self.queue.Notify(EventMaskFromLinux(EVENT_HUP as u32));
+                return Ok(res);
+            }
             UringSocketType::Uring(ref buf) => {
                 if how == LibcConst::SHUT_WR || how == LibcConst::SHUT_RDWR {
                     if buf.HasWriteData() {
@@ -1023,7 +1084,6 @@ impl SockOperations for UringSocketOperations {
                     }
                 }
             }
-            UringSocketType::Loopback(_) => (),
             UringSocketType::TCPUringlServer(_) => (),
         }
 
@@ -1045,15 +1105,7 @@ impl SockOperations for UringSocketOperations {
                         buf.SetWClosed();
                     }
                 }
-                UringSocketType::Loopback(ref loopback) => {
-                    if how == LibcConst::SHUT_RD || how == LibcConst::SHUT_RDWR {
-                        loopback.SetRClosed();
-                    }
-
-                    if how == LibcConst::SHUT_WR || how == LibcConst::SHUT_RDWR {
-                        loopback.SetWClosed();
-                    }
-                }
+                UringSocketType::Loopback(_) => (),
                 UringSocketType::TCPUringlServer(_) => (),
             }
 
@@ -1282,6 +1334,10 @@ impl SockOperations for UringSocketOperations {
     }
 
     fn GetSockName(&self, _task: &Task, socketaddr: &mut [u8]) -> Result<i64> {
+        if let UringSocketType::Loopback(loopback) = self.SocketType() {
+            return Ok(loopback.GetSockName(socketaddr));
+        }
+
         let len = socketaddr.len() as i32;
 
         let res = Kernel::HostSpace::GetSockName(
@@ -1297,6 +1353,10 @@ impl SockOperations for UringSocketOperations {
     }
 
     fn GetPeerName(&self, _task: &Task, socketaddr: &mut [u8]) -> Result<i64> {
+        if let UringSocketType::Loopback(loopback) = self.SocketType() {
+            return Ok(loopback.GetPeerName(socketaddr));
+        }
+
         let len = socketaddr.len() as i32;
         let res = Kernel::HostSpace::GetPeerName(
             self.fd,
@@ -1321,10 +1381,8 @@ impl SockOperations for UringSocketOperations {
     ) -> Result<(i64, i32, Option<(SockAddr, usize)>, Vec<u8>)> {
         let buf = self.socketType.lock().clone();
 
-        //todo: we don't support MSG_ERRQUEUE
         if flags & MsgType::MSG_ERRQUEUE != 0 {
-            // Pretend we have an empty error queue.
-            return Err(Error::SysError(SysErr::EAGAIN));
+            return RecvErrQueue(task, self.fd, dsts, flags, senderRequested, controlDataLen);
         }
 
         if flags
@@ -1474,9 +1532,11 @@ impl SockOperations for UringSocketOperations {
         task: &Task,
         srcs: &[IoVec],
         flags: i32,
-        _msgHdr: &mut MsgHdr,
+        msgHdr: &mut MsgHdr,
         deadline: Option<Time>,
     ) -> Result<i64> {
+        FIREWALL.CheckSendMsg(task, msgHdr)?;
+
         let buf = self.SocketType();
 
         if buf.WClosed() {
@@ -1576,6 +1636,10 @@ impl SockOperations for UringSocketOperations {
     }
 
     fn State(&self) -> u32 {
+        if let UringSocketType::Loopback(loopback) = self.SocketType() {
+            return loopback.State();
+        }
+
         let mut info = TCPInfo::default();
         let mut len = SocketSize::SIZEOF_TCPINFO;
 
@@ -1602,6 +1666,21 @@ impl SockOperations for UringSocketOperations {
         return info.State as u32;
     }
 
+    fn QueueSize(&self) -> (usize, usize) {
+        match self.SocketType() {
+            UringSocketType::Loopback(loopback) => {
+                return (0, loopback.sockBuff.readBuf.lock().AvailableDataSize());
+            }
+            UringSocketType::Uring(buf) => {
+                let (tx, rx) = HostQueueSize(self.fd);
+                let tx = tx + buf.writeBuf.lock().AvailableDataSize();
+                let rx = rx + buf.readBuf.lock().AvailableDataSize();
+                return (tx, rx);
+            }
+            _ => return HostQueueSize(self.fd),
+        }
+    }
+
     fn Type(&self) -> (i32, i32, i32) {
         return (self.family, self.stype, -1)
     }
@@ -16,11 +16,16 @@ pub mod buffer;
 pub mod control;
 pub mod epsocket;
 pub mod hostinet;
+pub mod netlink;
+pub mod netstack;
 pub mod socket;
 pub mod unix;
 pub mod socketopts;
 
 pub fn Init() {
+    // the netstack providers are registered first to take over the host ones
+    self::netstack::Init();
     self::hostinet::Init();
+    self::netlink::Init();
     self::unix::Init();
 }
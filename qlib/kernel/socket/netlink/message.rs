@@ -0,0 +1,294 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::slice;
+
+use super::super::super::super::linux::netlink::*;
+
+fn Bytes<T: Copy>(v: &T) -> &[u8] {
+    return unsafe { slice::from_raw_parts(v as *const T as *const u8, size_of::<T>()) };
+}
+
+// Read reads a T from the start of buf, it returns None if buf is too short.
+pub fn Read<T: Copy>(buf: &[u8]) -> Option<T> {
+    if buf.len() < size_of::<T>() {
+        return None;
+    }
+
+    return Some(unsafe { core::ptr::read_unaligned(buf.as_ptr() as *const T) });
+}
+
+// Message is a netlink message being built.
+pub struct Message {
+    pub buf: Vec<u8>,
+}
+
+impl Message {
+    pub fn New(typ: u16, flags: u16, seq: u32, portId: u32) -> Self {
+        let hdr = NetlinkMessageHeader {
+            Length: 0,
+            Type: typ,
+            Flags: flags,
+            Seq: seq,
+            PortID: portId,
+        };
+
+        let mut m = Self { buf: Vec::new() };
+        m.Put(&hdr);
+        return m;
+    }
+
+    // Put appends v to the message, padded to the netlink alignment.
+    pub fn Put<T: Copy>(&mut self, v: &T) {
+        self.PutBytes(Bytes(v));
+    }
+
+    pub fn PutBytes(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+        let len = NetlinkMessageAlign(self.buf.len());
+        self.buf.resize(len, 0);
+    }
+
+    pub fn PutAttr(&mut self, typ: u16, data: &[u8]) {
+        let attr = RtAttr {
+            Len: (RT_ATTR_SIZE + data.len()) as u16,
+            Type: typ,
+        };
+
+        self.buf.extend_from_slice(Bytes(&attr));
+        self.PutBytes(data);
+    }
+
+    pub fn PutAttrU32(&mut self, typ: u16, v: u32) {
+        self.PutAttr(typ, &v.to_ne_bytes());
+    }
+
+    pub fn PutAttrU8(&mut self, typ: u16, v: u8) {
+        self.PutAttr(typ, &[v]);
+    }
+
+    // PutAttrString appends a NUL terminated string attribute.
+    pub fn PutAttrString(&mut self, typ: u16, s: &str) {
+        let mut data = s.as_bytes().to_vec();
+        data.push(0);
+        self.PutAttr(typ, &data);
+    }
+
+    pub fn Finalize(mut self) -> Vec<u8> {
+        let len = (self.buf.len() as u32).to_ne_bytes();
+        self.buf[..4].copy_from_slice(&len);
+        return self.buf;
+    }
+}
+
+// MessageSet collects the replies to a request.
+pub struct MessageSet {
+    pub portId: u32,
+    pub seq: u32,
+    // the replies of a dump are multipart messages terminated with NLMSG_DONE
+    pub multi: bool,
+    pub messages: Vec<Vec<u8>>,
+}
+
+impl MessageSet {
+    pub fn New(portId: u32, seq: u32, multi: bool) -> Self {
+        return Self {
+            portId: portId,
+            seq: seq,
+            multi: multi,
+            messages: Vec::new(),
+        };
+    }
+
+    pub fn NewMessage(&self, typ: u16) -> Message {
+        let flags = if self.multi { NLM_F_MULTI } else { 0 };
+        return Message::New(typ, flags, self.seq, self.portId);
+    }
+
+    pub fn Add(&mut self, m: Message) {
+        self.messages.push(m.Finalize());
+    }
+
+    pub fn AddDone(&mut self) {
+        let mut m = self.NewMessage(NLMSG_DONE);
+        m.Put(&0i32);
+        self.Add(m);
+    }
+
+    // AddError adds the NLMSG_ERROR reply to hdr, errno 0 is an ack.
+    pub fn AddError(&mut self, hdr: &NetlinkMessageHeader, errno: i32) {
+        let mut m = Message::New(NLMSG_ERROR, 0, self.seq, self.portId);
+        m.Put(&NetlinkErrorMessage {
+            Error: -errno,
+            Header: *hdr,
+        });
+        self.Add(m);
+    }
+}
+
+// ParseMessages splits buf into the netlink messages and their payloads, a
+// truncated trailing message is ignored as Linux does.
+pub fn ParseMessages(mut buf: &[u8]) -> Vec<(NetlinkMessageHeader, &[u8])> {
+    let mut msgs = Vec::new();
+    while let Some(hdr) = Read::<NetlinkMessageHeader>(buf) {
+        let len = hdr.Length as usize;
+        if len < NETLINK_MESSAGE_HEADER_SIZE || len > buf.len() {
+            break;
+        }
+
+        msgs.push((hdr, &buf[NETLINK_MESSAGE_HEADER_SIZE..len]));
+        buf = &buf[core::cmp::min(NetlinkMessageAlign(len), buf.len())..];
+    }
+
+    return msgs;
+}
+
+// ParseAttrs returns the route attributes in buf.
+pub fn ParseAttrs(mut buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attrs = Vec::new();
+    while let Some(attr) = Read::<RtAttr>(buf) {
+        let len = attr.Len as usize;
+        if len < RT_ATTR_SIZE || len > buf.len() {
+            break;
+        }
+
+        attrs.push((attr.Type, &buf[RT_ATTR_SIZE..len]));
+        buf = &buf[core::cmp::min(RtAttrAlign(len), buf.len())..];
+    }
+
+    return attrs;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AF_INET: u8 = 2;
+
+    #[test]
+    fn test_MessageAlign() {
+        let mut m = Message::New(RTM_NEWLINK, NLM_F_MULTI, 7, 100);
+        assert_eq!(m.buf.len(), NETLINK_MESSAGE_HEADER_SIZE);
+
+        // "lo\0" is 7 bytes with the attribute header, padded to 8
+        m.PutAttrString(IFLA_IFNAME, "lo");
+        assert_eq!(m.buf.len(), NETLINK_MESSAGE_HEADER_SIZE + 8);
+        m.PutAttrU8(IFLA_OPERSTATE, IF_OPER_UP);
+        assert_eq!(m.buf.len(), NETLINK_MESSAGE_HEADER_SIZE + 16);
+        m.PutAttrU32(IFLA_MTU, 1500);
+        assert_eq!(m.buf.len(), NETLINK_MESSAGE_HEADER_SIZE + 24);
+
+        let buf = m.Finalize();
+        let hdr = Read::<NetlinkMessageHeader>(&buf).unwrap();
+        assert_eq!(hdr.Length as usize, buf.len());
+        assert_eq!(hdr.Type, RTM_NEWLINK);
+        assert_eq!(hdr.Flags, NLM_F_MULTI);
+        assert_eq!(hdr.Seq, 7);
+        assert_eq!(hdr.PortID, 100);
+
+        let attrs = ParseAttrs(&buf[NETLINK_MESSAGE_HEADER_SIZE..]);
+        assert_eq!(attrs.len(), 3);
+        assert_eq!(attrs[0], (IFLA_IFNAME, &b"lo\0"[..]));
+        assert_eq!(attrs[1], (IFLA_OPERSTATE, &[IF_OPER_UP][..]));
+        assert_eq!(attrs[2], (IFLA_MTU, &1500u32.to_ne_bytes()[..]));
+
+        // the attribute length doesn't count the padding
+        let attr = Read::<RtAttr>(&buf[NETLINK_MESSAGE_HEADER_SIZE..]).unwrap();
+        assert_eq!(attr.Len, 7);
+    }
+
+    #[test]
+    fn test_ParseMessages() {
+        let mut m = Message::New(RTM_GETLINK, NLM_F_REQUEST, 1, 0);
+        m.Put(&[1u8; 5]);
+        let mut buf = m.Finalize();
+        assert_eq!(buf.len(), 24);
+        // the length of the message doesn't count its padding either
+        buf.truncate(21);
+        buf[..4].copy_from_slice(&21u32.to_ne_bytes());
+        buf.resize(24, 0);
+
+        let mut m = Message::New(RTM_GETADDR, NLM_F_REQUEST | NLM_F_DUMP, 2, 0);
+        m.Put(&(AF_INET));
+        buf.extend_from_slice(&m.Finalize());
+
+        let msgs = ParseMessages(&buf);
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].0.Type, RTM_GETLINK);
+        assert_eq!(msgs[0].1, &[1u8; 5][..]);
+        assert_eq!(msgs[1].0.Type, RTM_GETADDR);
+        assert_eq!(msgs[1].0.Seq, 2);
+        assert_eq!(msgs[1].1, &[AF_INET, 0, 0, 0][..]);
+
+        // a truncated trailing message is dropped
+        assert_eq!(ParseMessages(&buf[..buf.len() - 1]).len(), 1);
+        assert_eq!(ParseMessages(&buf[..10]).len(), 0);
+
+        // so is a message shorter than its header
+        let mut bad = buf.clone();
+        bad[..4].copy_from_slice(&8u32.to_ne_bytes());
+        assert_eq!(ParseMessages(&bad).len(), 0);
+    }
+
+    #[test]
+    fn test_ParseAttrsTruncated() {
+        let mut m = Message::New(RTM_NEWADDR, 0, 0, 0);
+        m.PutAttr(IFA_ADDRESS, &[10, 0, 0, 1]);
+        m.PutAttrString(IFA_LABEL, "eth0");
+        let buf = m.Finalize();
+        let attrs = &buf[NETLINK_MESSAGE_HEADER_SIZE..];
+        assert_eq!(ParseAttrs(attrs).len(), 2);
+        assert_eq!(ParseAttrs(&attrs[..attrs.len() - 4]).len(), 1);
+        assert_eq!(ParseAttrs(&attrs[..3]).len(), 0);
+    }
+
+    #[test]
+    fn test_MessageSet() {
+        let mut ms = MessageSet::New(100, 9, true);
+        let m = ms.NewMessage(RTM_NEWADDR);
+        ms.Add(m);
+        ms.AddDone();
+        assert_eq!(ms.messages.len(), 2);
+        for msg in &ms.messages {
+            let hdr = Read::<NetlinkMessageHeader>(msg).unwrap();
+            assert_eq!(hdr.Flags, NLM_F_MULTI);
+            assert_eq!(hdr.Seq, 9);
+            assert_eq!(hdr.PortID, 100);
+        }
+
+        let done = Read::<NetlinkMessageHeader>(&ms.messages[1]).unwrap();
+        assert_eq!(done.Type, NLMSG_DONE);
+        assert_eq!(done.Length as usize, NETLINK_MESSAGE_HEADER_SIZE + 4);
+
+        let req = NetlinkMessageHeader {
+            Length: 32,
+            Type: RTM_NEWROUTE,
+            Flags: NLM_F_REQUEST | NLM_F_ACK,
+            Seq: 9,
+            PortID: 0,
+        };
+        let mut ms = MessageSet::New(100, 9, false);
+        ms.AddError(&req, 1);
+        let hdr = Read::<NetlinkMessageHeader>(&ms.messages[0]).unwrap();
+        assert_eq!(hdr.Type, NLMSG_ERROR);
+        assert_eq!(hdr.Flags, 0);
+        let err =
+            Read::<NetlinkErrorMessage>(&ms.messages[0][NETLINK_MESSAGE_HEADER_SIZE..]).unwrap();
+        assert_eq!(err.Error, -1);
+        assert_eq!(err.Header.Type, RTM_NEWROUTE);
+        assert_eq!(err.Header.Length, 32);
+    }
+}
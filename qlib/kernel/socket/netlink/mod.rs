@@ -0,0 +1,240 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// netlink implements the netlink sockets in the guest. NETLINK_ROUTE answers
+// from the sandbox interface table, the requests are never forwarded to the
+// host.
+
+pub mod message;
+pub mod route;
+pub mod socket;
+
+use crate::qlib::mutex::*;
+use alloc::collections::btree_set::BTreeSet;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+
+use super::super::super::linux::netlink::*;
+use super::super::super::loader::NetAddr;
+use super::super::super::loader::NetInterface;
+use super::super::super::qmsg::qcall::NetstackNicInfo;
+use super::hostinet::packet::RefreshPacketFilters;
+
+pub const LOOPBACK_INDEX: i32 = 1;
+pub const LOOPBACK_MTU: u32 = 65536;
+
+lazy_static! {
+    pub static ref INTERFACES: QRwLock<InterfaceTable> = QRwLock::new(InterfaceTable::default());
+    pub static ref PORTS: QMutex<PortTable> = QMutex::new(PortTable::default());
+}
+
+pub fn Init() {
+    self::socket::Init();
+}
+
+#[derive(Clone, Debug)]
+pub struct Interface {
+    pub index: i32,
+    pub name: String,
+    // ARPHRD_*
+    pub deviceType: u16,
+    pub flags: u32,
+    pub mac: [u8; 6],
+    pub mtu: u32,
+    pub addrs: Vec<NetAddr>,
+    pub gateways: Vec<Vec<u8>>,
+}
+
+impl Interface {
+    pub fn IsLoopback(&self) -> bool {
+        return self.flags & IFF_LOOPBACK != 0;
+    }
+}
+
+// InterfaceTable is the sandbox level interface table. It holds the loopback
+// device, then the interfaces of the pod network namespace as qvisor saw them
+// at load, or the ones of the sandbox spec annotation. Without any, the
+// interface taken over by the netstack is added when it starts.
+pub struct InterfaceTable {
+    pub interfaces: Vec<Interface>,
+    pub netstackIndex: Option<i32>,
+}
+
+impl Default for InterfaceTable {
+    fn default() -> Self {
+        let lo = Interface {
+            index: LOOPBACK_INDEX,
+            name: "lo".to_string(),
+            deviceType: ARPHRD_LOOPBACK,
+            flags: IFF_UP | IFF_LOOPBACK | IFF_RUNNING | IFF_LOWER_UP,
+            mac: [0; 6],
+            mtu: LOOPBACK_MTU,
+            addrs: vec![
+                NetAddr {
+                    Addr: vec![127, 0, 0, 1],
+                    PrefixLen: 8,
+                },
+                NetAddr {
+                    Addr: vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+                    PrefixLen: 128,
+                },
+            ],
+            gateways: Vec::new(),
+        };
+
+        return Self {
+            interfaces: vec![lo],
+            netstackIndex: None,
+        };
+    }
+}
+
+impl InterfaceTable {
+    pub fn Add(&mut self, intf: &NetInterface) -> i32 {
+        let index = self.interfaces.last().map(|i| i.index).unwrap_or(0) + 1;
+        self.interfaces.push(Interface {
+            index: index,
+            name: intf.Name.to_string(),
+            deviceType: ARPHRD_ETHER,
+            flags: IFF_UP | IFF_BROADCAST | IFF_RUNNING | IFF_MULTICAST | IFF_LOWER_UP,
+            mac: intf.Mac,
+            mtu: intf.Mtu,
+            addrs: intf.Addrs.clone(),
+            gateways: intf.Gateways.clone(),
+        });
+
+        return index;
+    }
+
+    pub fn Get(&self, index: i32) -> Option<&Interface> {
+        return self.interfaces.iter().find(|i| i.index == index);
+    }
+
+    pub fn GetByName(&self, name: &str) -> Option<&Interface> {
+        return self.interfaces.iter().find(|i| i.name == name);
+    }
+
+    // IsLocalAddr returns whether addr is an address of the sandbox, the
+    // whole 127.0.0.0/8 is local as on Linux.
+    pub fn IsLocalAddr(&self, addr: &[u8]) -> bool {
+        if addr.len() == 4 && addr[0] == 127 {
+            return true;
+        }
+
+        return self
+            .interfaces
+            .iter()
+            .any(|i| i.addrs.iter().any(|a| a.Addr[..] == addr[..]));
+    }
+}
+
+// SetInterfaces sets the interfaces of the sandbox, it is called once when
+// the root container is loaded.
+pub fn SetInterfaces(intfs: &[NetInterface]) {
+    {
+        let mut table = INTERFACES.write();
+        for intf in intfs {
+            table.Add(intf);
+        }
+    }
+
+    RefreshPacketFilters();
+}
+
+// AddNetstackInterface records the interface taken over by the netstack. It
+// is matched with the spec interfaces by mac address.
+pub fn AddNetstackInterface(nic: &NetstackNicInfo) {
+    let mut table = INTERFACES.write();
+    if let Some(intf) = table.interfaces.iter().find(|i| i.mac == nic.mac) {
+        table.netstackIndex = Some(intf.index);
+        return;
+    }
+
+    if table.interfaces.len() > 1 {
+        return;
+    }
+
+    let mut intf = NetInterface {
+        Name: "eth0".to_string(),
+        Mac: nic.mac,
+        Mtu: nic.mtu,
+        ..Default::default()
+    };
+
+    if nic.addr != [0; 4] {
+        intf.Addrs.push(NetAddr {
+            Addr: nic.addr.to_vec(),
+            PrefixLen: nic.prefixLen as u8,
+        });
+    }
+
+    if nic.addr6 != [0; 16] {
+        intf.Addrs.push(NetAddr {
+            Addr: nic.addr6.to_vec(),
+            PrefixLen: nic.prefixLen6 as u8,
+        });
+    }
+
+    if nic.gateway != [0; 4] {
+        intf.Gateways.push(nic.gateway.to_vec());
+    }
+
+    if nic.gateway6 != [0; 16] {
+        intf.Gateways.push(nic.gateway6.to_vec());
+    }
+
+    let index = table.Add(&intf);
+    table.netstackIndex = Some(index);
+    core::mem::drop(table);
+
+    RefreshPacketFilters();
+}
+
+// PortTable tracks the port ids of the netlink sockets.
+pub struct PortTable {
+    pub used: BTreeSet<u32>,
+    // the autobind ports go down from -4096 as Linux does
+    pub rover: u32,
+}
+
+impl Default for PortTable {
+    fn default() -> Self {
+        return Self {
+            used: BTreeSet::new(),
+            rover: -4096i32 as u32,
+        };
+    }
+}
+
+impl PortTable {
+    pub fn Bind(&mut self, port: u32) -> bool {
+        return self.used.insert(port);
+    }
+
+    pub fn AutoBind(&mut self) -> u32 {
+        loop {
+            let port = self.rover;
+            self.rover = self.rover.wrapping_sub(1);
+            if port != 0 && self.used.insert(port) {
+                return port;
+            }
+        }
+    }
+
+    pub fn Release(&mut self, port: u32) {
+        self.used.remove(&port);
+    }
+}
@@ -0,0 +1,695 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec::Vec;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux::netlink::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::task::*;
+use super::super::netstack::NETSTACK;
+use super::message::*;
+use super::*;
+
+const DEFAULT_TXQLEN: u32 = 1000;
+
+fn Family(addr: &[u8]) -> u8 {
+    if addr.len() == 16 {
+        return AFType::AF_INET6 as u8;
+    }
+
+    return AFType::AF_INET as u8;
+}
+
+// Masked returns the network prefix of addr.
+fn Masked(addr: &[u8], prefixLen: u8) -> Vec<u8> {
+    let mut ret = addr.to_vec();
+    for i in 0..ret.len() {
+        let bits = (prefixLen as usize).saturating_sub(i * 8);
+        if bits < 8 {
+            ret[i] &= !(0xffu8 >> bits);
+        }
+    }
+
+    return ret;
+}
+
+fn Scope(addr: &[u8]) -> u8 {
+    if addr.len() == 4 && addr[0] == 127 {
+        return RT_SCOPE_HOST;
+    }
+
+    if addr.len() == 16 {
+        if addr[..15].iter().all(|b| *b == 0) && addr[15] == 1 {
+            return RT_SCOPE_HOST;
+        }
+
+        if addr[0] == 0xfe && addr[1] & 0xc0 == 0x80 {
+            return RT_SCOPE_LINK;
+        }
+    }
+
+    return RT_SCOPE_UNIVERSE;
+}
+
+// FamilyFilter returns the address family a dump request is restricted to,
+// it is the first byte of the request payload.
+fn FamilyFilter(data: &[u8]) -> u8 {
+    return data.first().cloned().unwrap_or(AFType::AF_UNSPEC as u8);
+}
+
+fn Matches(filter: u8, addr: &[u8]) -> bool {
+    return filter == AFType::AF_UNSPEC as u8 || filter == Family(addr);
+}
+
+fn AddLink(intf: &Interface, ms: &mut MessageSet) {
+    let mut m = ms.NewMessage(RTM_NEWLINK);
+    m.Put(&InterfaceInfoMessage {
+        Family: AFType::AF_UNSPEC as u8,
+        Type: intf.deviceType,
+        Index: intf.index,
+        Flags: intf.flags,
+        ..Default::default()
+    });
+
+    m.PutAttrString(IFLA_IFNAME, &intf.name);
+    m.PutAttrU32(IFLA_MTU, intf.mtu);
+    if intf.IsLoopback() {
+        m.PutAttrU32(IFLA_TXQLEN, 0);
+        m.PutAttrU8(IFLA_OPERSTATE, IF_OPER_UNKNOWN);
+        m.PutAttr(IFLA_ADDRESS, &[0; 6]);
+        m.PutAttr(IFLA_BROADCAST, &[0; 6]);
+    } else {
+        m.PutAttrU32(IFLA_TXQLEN, DEFAULT_TXQLEN);
+        m.PutAttrU8(IFLA_OPERSTATE, IF_OPER_UP);
+        m.PutAttr(IFLA_ADDRESS, &intf.mac);
+        m.PutAttr(IFLA_BROADCAST, &[0xff; 6]);
+    }
+
+    ms.Add(m);
+}
+
+fn GetLink(
+    table: &InterfaceTable,
+    hdr: &NetlinkMessageHeader,
+    data: &[u8],
+    ms: &mut MessageSet,
+) -> Result<()> {
+    if hdr.Flags & NLM_F_DUMP == NLM_F_DUMP {
+        for intf in &table.interfaces {
+            AddLink(intf, ms);
+        }
+        return Ok(());
+    }
+
+    let ifinfo = match Read::<InterfaceInfoMessage>(data) {
+        None => return Err(Error::SysError(SysErr::EINVAL)),
+        Some(i) => i,
+    };
+
+    let attrs = &data[core::cmp::min(
+        NetlinkMessageAlign(core::mem::size_of::<InterfaceInfoMessage>()),
+        data.len(),
+    )..];
+    let intf = if ifinfo.Index > 0 {
+        table.Get(ifinfo.Index)
+    } else {
+        let mut ret = None;
+        for (typ, val) in ParseAttrs(attrs) {
+            if typ == IFLA_IFNAME {
+                let len = val.iter().position(|b| *b == 0).unwrap_or(val.len());
+                let name = core::str::from_utf8(&val[..len]).unwrap_or("");
+                ret = table.GetByName(name);
+            }
+        }
+        ret
+    };
+
+    match intf {
+        None => return Err(Error::SysError(SysErr::ENODEV)),
+        Some(intf) => AddLink(intf, ms),
+    }
+
+    return Ok(());
+}
+
+fn DumpAddrs(table: &InterfaceTable, data: &[u8], ms: &mut MessageSet) -> Result<()> {
+    let filter = FamilyFilter(data);
+    for intf in &table.interfaces {
+        for addr in &intf.addrs {
+            if !Matches(filter, &addr.Addr) {
+                continue;
+            }
+
+            let v4 = addr.Addr.len() == 4;
+            let mut m = ms.NewMessage(RTM_NEWADDR);
+            m.Put(&InterfaceAddrMessage {
+                Family: Family(&addr.Addr),
+                PrefixLen: addr.PrefixLen,
+                Flags: IFA_F_PERMANENT,
+                Scope: Scope(&addr.Addr),
+                Index: intf.index as u32,
+            });
+
+            m.PutAttr(IFA_ADDRESS, &addr.Addr);
+            if v4 {
+                m.PutAttr(IFA_LOCAL, &addr.Addr);
+                if !intf.IsLoopback() && addr.PrefixLen < 31 {
+                    let mut brd = Masked(&addr.Addr, addr.PrefixLen);
+                    let host = u32::MAX.checked_shr(addr.PrefixLen as u32).unwrap_or(0);
+                    for (i, b) in host.to_be_bytes().iter().enumerate() {
+                        brd[i] |= *b;
+                    }
+                    m.PutAttr(IFA_BROADCAST, &brd);
+                }
+                m.PutAttrString(IFA_LABEL, &intf.name);
+            }
+
+            ms.Add(m);
+        }
+    }
+
+    return Ok(());
+}
+
+fn AddRoute(
+    ms: &mut MessageSet,
+    msg: RouteMessage,
+    dst: Option<&[u8]>,
+    gateway: Option<&[u8]>,
+    prefsrc: Option<&[u8]>,
+    index: i32,
+) {
+    let table = msg.Table;
+    let mut m = ms.NewMessage(RTM_NEWROUTE);
+    m.Put(&msg);
+    m.PutAttrU32(RTA_TABLE, table as u32);
+    if let Some(dst) = dst {
+        m.PutAttr(RTA_DST, dst);
+    }
+    if let Some(src) = prefsrc {
+        m.PutAttr(RTA_PREFSRC, src);
+    }
+    if let Some(gw) = gateway {
+        m.PutAttr(RTA_GATEWAY, gw);
+    }
+    m.PutAttrU32(RTA_OIF, index as u32);
+    ms.Add(m);
+}
+
+fn DumpRoutes(table: &InterfaceTable, data: &[u8], ms: &mut MessageSet) -> Result<()> {
+    let filter = FamilyFilter(data);
+    for intf in &table.interfaces {
+        for addr in &intf.addrs {
+            if !Matches(filter, &addr.Addr) {
+                continue;
+            }
+
+            let family = Family(&addr.Addr);
+            let v4 = addr.Addr.len() == 4;
+            let prefsrc = if v4 { Some(&addr.Addr[..]) } else { None };
+
+            // the prefix route in the main table
+            if !intf.IsLoopback() {
+                let dst = Masked(&addr.Addr, addr.PrefixLen);
+                let msg = RouteMessage {
+                    Family: family,
+                    DstLen: addr.PrefixLen,
+                    Table: RT_TABLE_MAIN,
+                    Protocol: RTPROT_KERNEL,
+                    Scope: RT_SCOPE_LINK,
+                    Type: RTN_UNICAST,
+                    ..Default::default()
+                };
+                AddRoute(ms, msg, Some(&dst), None, prefsrc, intf.index);
+            }
+
+            // the address route in the local table
+            let msg = RouteMessage {
+                Family: family,
+                DstLen: addr.Addr.len() as u8 * 8,
+                Table: RT_TABLE_LOCAL,
+                Protocol: RTPROT_KERNEL,
+                Scope: RT_SCOPE_HOST,
+                Type: RTN_LOCAL,
+                ..Default::default()
+            };
+            AddRoute(ms, msg, Some(&addr.Addr), None, prefsrc, intf.index);
+        }
+
+        for gw in &intf.gateways {
+            if !Matches(filter, gw) {
+                continue;
+            }
+
+            let msg = RouteMessage {
+                Family: Family(gw),
+                Table: RT_TABLE_MAIN,
+                Protocol: RTPROT_BOOT,
+                Scope: RT_SCOPE_UNIVERSE,
+                Type: RTN_UNICAST,
+                ..Default::default()
+            };
+            AddRoute(ms, msg, None, Some(gw), None, intf.index);
+        }
+    }
+
+    return Ok(());
+}
+
+// DumpNeighbors reports the neighbors learned by the netstack, there are none
+// without it. index is the interface taken over by the netstack.
+fn DumpNeighbors(index: Option<i32>, data: &[u8], ms: &mut MessageSet) -> Result<()> {
+    let filter = FamilyFilter(data);
+    let index = match index {
+        None => return Ok(()),
+        Some(i) => i,
+    };
+
+    let neighbors: Vec<(Vec<u8>, [u8; 6])> = match NETSTACK.stack.lock().as_ref() {
+        None => return Ok(()),
+        Some(stack) => stack
+            .neighbors
+            .iter()
+            .map(|(addr, mac)| (addr.Bytes().to_vec(), *mac))
+            .collect(),
+    };
+
+    for (addr, mac) in neighbors {
+        if !Matches(filter, &addr) {
+            continue;
+        }
+
+        let mut m = ms.NewMessage(RTM_NEWNEIGH);
+        m.Put(&NeighborMessage {
+            Family: Family(&addr),
+            Index: index,
+            State: NUD_REACHABLE,
+            Type: RTN_UNICAST,
+            ..Default::default()
+        });
+        m.PutAttr(NDA_DST, &addr);
+        m.PutAttr(NDA_LLADDR, &mac);
+        ms.Add(m);
+    }
+
+    return Ok(());
+}
+
+// ProcessMessage handles a NETLINK_ROUTE request. The interface table is read
+// only, the requests changing it fail.
+pub fn ProcessMessage(
+    task: &Task,
+    hdr: &NetlinkMessageHeader,
+    data: &[u8],
+    ms: &mut MessageSet,
+) -> Result<()> {
+    // The netstack adds its interface to the table with its lock held, the
+    // neighbors are read without holding the table.
+    if hdr.Type == RTM_GETNEIGH && hdr.Flags & NLM_F_DUMP == NLM_F_DUMP {
+        let index = INTERFACES.read().netstackIndex;
+        return DumpNeighbors(index, data, ms);
+    }
+
+    let netAdmin = task.Creds().HasCapability(Capability::CAP_NET_ADMIN);
+    return Process(&INTERFACES.read(), netAdmin, hdr, data, ms);
+}
+
+// Process handles a request with the interface table, netAdmin is whether the
+// sender has CAP_NET_ADMIN.
+pub fn Process(
+    table: &InterfaceTable,
+    netAdmin: bool,
+    hdr: &NetlinkMessageHeader,
+    data: &[u8],
+    ms: &mut MessageSet,
+) -> Result<()> {
+    let dump = hdr.Flags & NLM_F_DUMP == NLM_F_DUMP;
+    match hdr.Type {
+        RTM_GETLINK => return GetLink(table, hdr, data, ms),
+        RTM_GETADDR if dump => return DumpAddrs(table, data, ms),
+        RTM_GETROUTE if dump => return DumpRoutes(table, data, ms),
+        RTM_NEWLINK | RTM_DELLINK | RTM_SETLINK | RTM_NEWADDR | RTM_DELADDR | RTM_NEWROUTE
+        | RTM_DELROUTE | RTM_NEWNEIGH | RTM_DELNEIGH => {
+            if !netAdmin {
+                return Err(Error::SysError(SysErr::EPERM));
+            }
+            return Err(Error::SysError(SysErr::EOPNOTSUPP));
+        }
+        _ => return Err(Error::SysError(SysErr::EOPNOTSUPP)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::super::super::loader::NetAddr;
+    use super::super::super::super::super::loader::NetInterface;
+    use super::*;
+
+    const FD00_5: [u8; 16] = [0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 5];
+    const FD00_1: [u8; 16] = [0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+    const FD00: [u8; 16] = [0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    const MAC: [u8; 6] = [2, 0, 0, 0, 0, 1];
+
+    // Table returns the loopback device and eth0 with 10.1.2.3/24 and
+    // fd00::5/64, the default gateways are 10.1.2.1 and fd00::1.
+    fn Table() -> InterfaceTable {
+        let mut table = InterfaceTable::default();
+        table.Add(&NetInterface {
+            Name: "eth0".to_string(),
+            Mac: MAC,
+            Mtu: 1450,
+            Addrs: vec![
+                NetAddr {
+                    Addr: vec![10, 1, 2, 3],
+                    PrefixLen: 24,
+                },
+                NetAddr {
+                    Addr: FD00_5.to_vec(),
+                    PrefixLen: 64,
+                },
+            ],
+            Gateways: vec![vec![10, 1, 2, 1], FD00_1.to_vec()],
+        });
+        return table;
+    }
+
+    // Request processes a request with the payload and returns the headers and
+    // the payloads of the replies.
+    fn Request(
+        netAdmin: bool,
+        typ: u16,
+        flags: u16,
+        payload: &[u8],
+    ) -> Result<Vec<(NetlinkMessageHeader, Vec<u8>)>> {
+        let hdr = NetlinkMessageHeader {
+            Length: (NETLINK_MESSAGE_HEADER_SIZE + payload.len()) as u32,
+            Type: typ,
+            Flags: NLM_F_REQUEST | flags,
+            Seq: 1,
+            PortID: 0,
+        };
+
+        let mut ms = MessageSet::New(100, 1, flags & NLM_F_DUMP == NLM_F_DUMP);
+        Process(&Table(), netAdmin, &hdr, payload, &mut ms)?;
+        return Ok(ms
+            .messages
+            .iter()
+            .map(|m| {
+                let msgs = ParseMessages(m);
+                assert_eq!(msgs.len(), 1);
+                (msgs[0].0, msgs[0].1.to_vec())
+            })
+            .collect());
+    }
+
+    fn Dump(typ: u16, family: i32) -> Vec<(NetlinkMessageHeader, Vec<u8>)> {
+        return Request(false, typ, NLM_F_DUMP, &[family as u8]).unwrap();
+    }
+
+    // Attr returns the attribute typ of a reply whose fixed header is a T.
+    fn Attr<T>(data: &[u8], typ: u16) -> Option<Vec<u8>> {
+        let attrs = &data[NetlinkMessageAlign(core::mem::size_of::<T>())..];
+        return ParseAttrs(attrs)
+            .into_iter()
+            .find(|(t, _)| *t == typ)
+            .map(|(_, v)| v.to_vec());
+    }
+
+    #[test]
+    fn test_GetLinkDump() {
+        let msgs = Dump(RTM_GETLINK, AFType::AF_UNSPEC);
+        assert_eq!(msgs.len(), 2);
+
+        let (hdr, data) = &msgs[0];
+        assert_eq!(hdr.Type, RTM_NEWLINK);
+        assert_eq!(hdr.Flags, NLM_F_MULTI);
+        let info = Read::<InterfaceInfoMessage>(data).unwrap();
+        assert_eq!(info.Index, LOOPBACK_INDEX);
+        assert_eq!(info.Type, ARPHRD_LOOPBACK);
+        assert!(info.Flags & IFF_LOOPBACK != 0);
+        assert_eq!(
+            Attr::<InterfaceInfoMessage>(data, IFLA_IFNAME).unwrap(),
+            b"lo\0"
+        );
+        assert_eq!(
+            Attr::<InterfaceInfoMessage>(data, IFLA_MTU).unwrap(),
+            LOOPBACK_MTU.to_ne_bytes()
+        );
+
+        let data = &msgs[1].1;
+        let info = Read::<InterfaceInfoMessage>(data).unwrap();
+        assert_eq!(info.Index, 2);
+        assert_eq!(info.Type, ARPHRD_ETHER);
+        assert_eq!(
+            Attr::<InterfaceInfoMessage>(data, IFLA_IFNAME).unwrap(),
+            b"eth0\0"
+        );
+        assert_eq!(
+            Attr::<InterfaceInfoMessage>(data, IFLA_ADDRESS).unwrap(),
+            MAC
+        );
+        assert_eq!(
+            Attr::<InterfaceInfoMessage>(data, IFLA_BROADCAST).unwrap(),
+            [0xff; 6]
+        );
+        assert_eq!(
+            Attr::<InterfaceInfoMessage>(data, IFLA_MTU).unwrap(),
+            1450u32.to_ne_bytes()
+        );
+        assert_eq!(
+            Attr::<InterfaceInfoMessage>(data, IFLA_OPERSTATE).unwrap(),
+            [IF_OPER_UP]
+        );
+    }
+
+    #[test]
+    fn test_GetLink() {
+        let mut byIndex = Message::New(0, 0, 0, 0);
+        byIndex.Put(&InterfaceInfoMessage {
+            Index: 2,
+            ..Default::default()
+        });
+        let payload = byIndex.Finalize()[NETLINK_MESSAGE_HEADER_SIZE..].to_vec();
+        let msgs = Request(false, RTM_GETLINK, 0, &payload).unwrap();
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].0.Flags, 0);
+        assert_eq!(Read::<InterfaceInfoMessage>(&msgs[0].1).unwrap().Index, 2);
+
+        let mut byName = Message::New(0, 0, 0, 0);
+        byName.Put(&InterfaceInfoMessage::default());
+        byName.PutAttrString(IFLA_IFNAME, "lo");
+        let payload = byName.Finalize()[NETLINK_MESSAGE_HEADER_SIZE..].to_vec();
+        let msgs = Request(false, RTM_GETLINK, 0, &payload).unwrap();
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(
+            Read::<InterfaceInfoMessage>(&msgs[0].1).unwrap().Index,
+            LOOPBACK_INDEX
+        );
+
+        let mut unknown = Message::New(0, 0, 0, 0);
+        unknown.Put(&InterfaceInfoMessage {
+            Index: 9,
+            ..Default::default()
+        });
+        let payload = unknown.Finalize()[NETLINK_MESSAGE_HEADER_SIZE..].to_vec();
+        assert_eq!(
+            Request(false, RTM_GETLINK, 0, &payload).err(),
+            Some(Error::SysError(SysErr::ENODEV))
+        );
+        assert_eq!(
+            Request(false, RTM_GETLINK, 0, &[0; 4]).err(),
+            Some(Error::SysError(SysErr::EINVAL))
+        );
+    }
+
+    #[test]
+    fn test_GetAddrDump() {
+        assert_eq!(Dump(RTM_GETADDR, AFType::AF_UNSPEC).len(), 4);
+
+        let msgs = Dump(RTM_GETADDR, AFType::AF_INET);
+        assert_eq!(msgs.len(), 2);
+
+        let data = &msgs[0].1;
+        let ifa = Read::<InterfaceAddrMessage>(data).unwrap();
+        assert_eq!(ifa.Family, AFType::AF_INET as u8);
+        assert_eq!(ifa.PrefixLen, 8);
+        assert_eq!(ifa.Scope, RT_SCOPE_HOST);
+        assert_eq!(ifa.Index, LOOPBACK_INDEX as u32);
+        assert_eq!(
+            Attr::<InterfaceAddrMessage>(data, IFA_LOCAL).unwrap(),
+            [127, 0, 0, 1]
+        );
+        assert_eq!(Attr::<InterfaceAddrMessage>(data, IFA_BROADCAST), None);
+        assert_eq!(
+            Attr::<InterfaceAddrMessage>(data, IFA_LABEL).unwrap(),
+            b"lo\0"
+        );
+
+        let data = &msgs[1].1;
+        let ifa = Read::<InterfaceAddrMessage>(data).unwrap();
+        assert_eq!(ifa.PrefixLen, 24);
+        assert_eq!(ifa.Scope, RT_SCOPE_UNIVERSE);
+        assert_eq!(ifa.Flags, IFA_F_PERMANENT);
+        assert_eq!(ifa.Index, 2);
+        assert_eq!(
+            Attr::<InterfaceAddrMessage>(data, IFA_ADDRESS).unwrap(),
+            [10, 1, 2, 3]
+        );
+        assert_eq!(
+            Attr::<InterfaceAddrMessage>(data, IFA_BROADCAST).unwrap(),
+            [10, 1, 2, 255]
+        );
+        assert_eq!(
+            Attr::<InterfaceAddrMessage>(data, IFA_LABEL).unwrap(),
+            b"eth0\0"
+        );
+
+        let msgs = Dump(RTM_GETADDR, AFType::AF_INET6);
+        assert_eq!(msgs.len(), 2);
+        let ifa = Read::<InterfaceAddrMessage>(&msgs[0].1).unwrap();
+        assert_eq!(ifa.PrefixLen, 128);
+        assert_eq!(ifa.Scope, RT_SCOPE_HOST);
+        let data = &msgs[1].1;
+        let ifa = Read::<InterfaceAddrMessage>(data).unwrap();
+        assert_eq!(ifa.Family, AFType::AF_INET6 as u8);
+        assert_eq!(ifa.PrefixLen, 64);
+        assert_eq!(ifa.Scope, RT_SCOPE_UNIVERSE);
+        assert_eq!(
+            Attr::<InterfaceAddrMessage>(data, IFA_ADDRESS).unwrap(),
+            FD00_5
+        );
+        assert_eq!(Attr::<InterfaceAddrMessage>(data, IFA_LOCAL), None);
+    }
+
+    // Route returns the route message, the destination, the gateway and the
+    // output interface of a route reply.
+    fn Route(data: &[u8]) -> (RouteMessage, Option<Vec<u8>>, Option<Vec<u8>>, u32) {
+        let msg = Read::<RouteMessage>(data).unwrap();
+        let oif = Attr::<RouteMessage>(data, RTA_OIF).unwrap();
+        assert_eq!(
+            Attr::<RouteMessage>(data, RTA_TABLE).unwrap(),
+            (msg.Table as u32).to_ne_bytes()
+        );
+        return (
+            msg,
+            Attr::<RouteMessage>(data, RTA_DST),
+            Attr::<RouteMessage>(data, RTA_GATEWAY),
+            u32::from_ne_bytes([oif[0], oif[1], oif[2], oif[3]]),
+        );
+    }
+
+    #[test]
+    fn test_GetRouteDump() {
+        let msgs = Dump(RTM_GETROUTE, AFType::AF_INET);
+        assert_eq!(msgs.len(), 4);
+        for (hdr, _) in &msgs {
+            assert_eq!(hdr.Type, RTM_NEWROUTE);
+        }
+
+        // the loopback address route
+        let (msg, dst, gw, oif) = Route(&msgs[0].1);
+        assert_eq!(
+            (msg.Table, msg.Type, msg.Scope),
+            (RT_TABLE_LOCAL, RTN_LOCAL, RT_SCOPE_HOST)
+        );
+        assert_eq!(
+            (msg.DstLen, dst, gw, oif),
+            (32, Some(vec![127, 0, 0, 1]), None, 1)
+        );
+
+        // the eth0 prefix route
+        let (msg, dst, gw, oif) = Route(&msgs[1].1);
+        assert_eq!(
+            (msg.Table, msg.Type, msg.Scope),
+            (RT_TABLE_MAIN, RTN_UNICAST, RT_SCOPE_LINK)
+        );
+        assert_eq!(
+            (msg.DstLen, dst, gw, oif),
+            (24, Some(vec![10, 1, 2, 0]), None, 2)
+        );
+        assert_eq!(
+            Attr::<RouteMessage>(&msgs[1].1, RTA_PREFSRC).unwrap(),
+            [10, 1, 2, 3]
+        );
+
+        // the eth0 address route
+        let (msg, dst, _, _) = Route(&msgs[2].1);
+        assert_eq!(
+            (msg.Table, msg.DstLen, dst),
+            (RT_TABLE_LOCAL, 32, Some(vec![10, 1, 2, 3]))
+        );
+
+        // the default route
+        let (msg, dst, gw, oif) = Route(&msgs[3].1);
+        assert_eq!((msg.Table, msg.Protocol), (RT_TABLE_MAIN, RTPROT_BOOT));
+        assert_eq!(
+            (msg.DstLen, dst, gw, oif),
+            (0, None, Some(vec![10, 1, 2, 1]), 2)
+        );
+
+        let msgs = Dump(RTM_GETROUTE, AFType::AF_INET6);
+        assert_eq!(msgs.len(), 4);
+        let (msg, dst, _, _) = Route(&msgs[1].1);
+        assert_eq!(
+            (msg.Family, msg.DstLen, dst),
+            (AFType::AF_INET6 as u8, 64, Some(FD00.to_vec()))
+        );
+        assert_eq!(Attr::<RouteMessage>(&msgs[1].1, RTA_PREFSRC), None);
+        let (_, dst, gw, _) = Route(&msgs[3].1);
+        assert_eq!((dst, gw), (None, Some(FD00_1.to_vec())));
+
+        assert_eq!(Dump(RTM_GETROUTE, AFType::AF_UNSPEC).len(), 8);
+    }
+
+    #[test]
+    fn test_RejectChanges() {
+        let types = [
+            RTM_NEWLINK,
+            RTM_DELLINK,
+            RTM_SETLINK,
+            RTM_NEWADDR,
+            RTM_DELADDR,
+            RTM_NEWROUTE,
+            RTM_DELROUTE,
+            RTM_NEWNEIGH,
+            RTM_DELNEIGH,
+        ];
+        for typ in types.iter() {
+            let payload = [0u8; 16];
+            assert_eq!(
+                Request(false, *typ, NLM_F_ACK, &payload).err(),
+                Some(Error::SysError(SysErr::EPERM))
+            );
+            assert_eq!(
+                Request(true, *typ, NLM_F_ACK, &payload).err(),
+                Some(Error::SysError(SysErr::EOPNOTSUPP))
+            );
+        }
+
+        // only the dumps of the addresses and routes are supported
+        assert_eq!(
+            Request(false, RTM_GETADDR, 0, &[0; 8]).err(),
+            Some(Error::SysError(SysErr::EOPNOTSUPP))
+        );
+        assert_eq!(
+            Request(false, RTM_GETROUTE, 0, &[0; 12]).err(),
+            Some(Error::SysError(SysErr::EOPNOTSUPP))
+        );
+        assert_eq!(
+            Request(false, 100, NLM_F_DUMP, &[0]).err(),
+            Some(Error::SysError(SysErr::EOPNOTSUPP))
+        );
+    }
+}
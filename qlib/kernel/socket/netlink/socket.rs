@@ -0,0 +1,826 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::qlib::mutex::*;
+use alloc::boxed::Box;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::ops::Deref;
+use core::sync::atomic::AtomicI64;
+use core::sync::atomic::Ordering;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux::netlink::*;
+use super::super::super::super::linux::socket::SOL_NETLINK;
+use super::super::super::super::linux::time::Timeval;
+use super::super::super::super::linux_def::*;
+use super::super::super::fs::attr::*;
+use super::super::super::fs::dentry::*;
+use super::super::super::fs::dirent::*;
+use super::super::super::fs::file::*;
+use super::super::super::fs::flags::*;
+use super::super::super::kernel::kernel::GetKernel;
+use super::super::super::kernel::time::*;
+use super::super::super::kernel::waiter::*;
+use super::super::super::task::*;
+use super::super::super::tcpip::tcpip::*;
+use super::super::epsocket::epsocket::*;
+use super::super::socket::*;
+use super::super::unix::transport::unix::SockType;
+use super::super::unix::unix::NewUnixSocketDummyDirent;
+use super::message::*;
+use super::*;
+
+pub const NETLINK_DEFAULT_BUF_SIZE: usize = 208 * 1024;
+pub const NETLINK_MIN_BUF_SIZE: usize = 4096;
+pub const NETLINK_MAX_BUF_SIZE: usize = 4 << 20;
+
+pub fn NewNetlinkSocketFile(
+    task: &Task,
+    stype: i32,
+    protocol: i32,
+    nonblock: bool,
+) -> Result<File> {
+    let dirent = NewUnixSocketDummyDirent(task, SOCKET_DEVICE.clone())?;
+    let ops = NetlinkSocketOperations(Arc::new(NetlinkSocketOperationsIntern {
+        stype: stype,
+        protocol: protocol,
+        queue: Queue::default(),
+        ep: QMutex::new(NetlinkEndpoint::default()),
+        send: AtomicI64::new(0),
+        recv: AtomicI64::new(0),
+    }));
+
+    let file = File::New(
+        &dirent,
+        &FileFlags {
+            NonBlocking: nonblock,
+            Read: true,
+            Write: true,
+            ..Default::default()
+        },
+        ops.into(),
+    );
+
+    GetKernel().sockets.AddSocket(&file);
+    return Ok(file);
+}
+
+pub struct NetlinkEndpoint {
+    // 0 until the socket is bound
+    pub portId: u32,
+    pub groups: u32,
+    pub rcvQueue: VecDeque<Vec<u8>>,
+    pub rcvBufSize: usize,
+    pub sndBufSize: usize,
+    pub passCred: bool,
+}
+
+impl Default for NetlinkEndpoint {
+    fn default() -> Self {
+        return Self {
+            portId: 0,
+            groups: 0,
+            rcvQueue: VecDeque::new(),
+            rcvBufSize: NETLINK_DEFAULT_BUF_SIZE,
+            sndBufSize: NETLINK_DEFAULT_BUF_SIZE,
+            passCred: false,
+        };
+    }
+}
+
+pub struct NetlinkSocketOperationsIntern {
+    pub stype: i32,
+    pub protocol: i32,
+    pub queue: Queue,
+    pub ep: QMutex<NetlinkEndpoint>,
+    pub send: AtomicI64,
+    pub recv: AtomicI64,
+}
+
+impl Drop for NetlinkSocketOperationsIntern {
+    fn drop(&mut self) {
+        let port = self.ep.lock().portId;
+        if port != 0 {
+            PORTS.lock().Release(port);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct NetlinkSocketOperations(Arc<NetlinkSocketOperationsIntern>);
+
+impl Deref for NetlinkSocketOperations {
+    type Target = Arc<NetlinkSocketOperationsIntern>;
+
+    fn deref(&self) -> &Arc<NetlinkSocketOperationsIntern> {
+        &self.0
+    }
+}
+
+impl NetlinkSocketOperations {
+    // PortId returns the port id of the socket, binding it first if needed.
+    fn PortId(&self) -> u32 {
+        let mut ep = self.ep.lock();
+        if ep.portId == 0 {
+            ep.portId = PORTS.lock().AutoBind();
+        }
+
+        return ep.portId;
+    }
+
+    fn ParseAddr(sockaddr: &[u8]) -> Result<SockAddrNetlink> {
+        match GetAddr(AFType::AF_NETLINK as i16, sockaddr)? {
+            SockAddr::Netlink(addr) => return Ok(addr),
+            _ => return Err(Error::SysError(SysErr::EINVAL)),
+        }
+    }
+
+    // Process handles the requests in buf and queues the replies.
+    fn Process(&self, task: &Task, buf: &[u8]) -> Result<()> {
+        let portId = self.PortId();
+
+        // the uevent sockets never get anything from the guest kernel
+        if self.protocol != NETLINK_ROUTE {
+            return Ok(());
+        }
+
+        let replies = Replies(portId, buf, &mut |hdr, data, ms| {
+            super::route::ProcessMessage(task, hdr, data, ms)
+        })?;
+
+        if replies.len() == 0 {
+            return Ok(());
+        }
+
+        self.ep.lock().rcvQueue.extend(replies);
+        self.queue.Notify(READABLE_EVENT);
+        return Ok(());
+    }
+
+    fn Recv(&self, peek: bool) -> Result<Vec<u8>> {
+        let mut ep = self.ep.lock();
+        let msg = if peek {
+            ep.rcvQueue.front().cloned()
+        } else {
+            ep.rcvQueue.pop_front()
+        };
+
+        match msg {
+            None => return Err(Error::SysError(SysErr::EWOULDBLOCK)),
+            Some(m) => return Ok(m),
+        }
+    }
+
+    fn RecvBlocking(&self, task: &Task, peek: bool, deadline: Option<Time>) -> Result<Vec<u8>> {
+        let general = task.blocker.generalEntry.clone();
+        self.EventRegister(task, &general, EVENT_READ);
+        defer!(self.EventUnregister(task, &general));
+
+        loop {
+            match self.Recv(peek) {
+                Err(Error::SysError(SysErr::EWOULDBLOCK)) => (),
+                ret => return ret,
+            }
+
+            match task.blocker.BlockWithMonoTimer(true, deadline) {
+                Err(Error::ErrInterrupted) => {
+                    return Err(Error::SysError(SysErr::ERESTARTSYS));
+                }
+                Err(Error::SysError(SysErr::ETIMEDOUT)) => {
+                    return Err(Error::SysError(SysErr::EAGAIN));
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn LocalAddr(&self) -> SockAddrNetlink {
+        let ep = self.ep.lock();
+        return SockAddrNetlink {
+            Family: AFType::AF_NETLINK as u16,
+            Padding: 0,
+            PortID: ep.portId,
+            Groups: ep.groups,
+        };
+    }
+}
+
+// KERNEL_ADDR is the address of the replies, they come from the kernel.
+const KERNEL_ADDR: SockAddrNetlink = SockAddrNetlink {
+    Family: AFType::AF_NETLINK as u16,
+    Padding: 0,
+    PortID: 0,
+    Groups: 0,
+};
+
+impl Waitable for NetlinkSocketOperations {
+    fn Readiness(&self, _task: &Task, mask: EventMask) -> EventMask {
+        let mut ready = WRITEABLE_EVENT;
+        if self.ep.lock().rcvQueue.len() > 0 {
+            ready |= READABLE_EVENT;
+        }
+
+        return ready & mask;
+    }
+
+    fn EventRegister(&self, task: &Task, e: &WaitEntry, mask: EventMask) {
+        self.queue.EventRegister(task, e, mask);
+    }
+
+    fn EventUnregister(&self, task: &Task, e: &WaitEntry) {
+        self.queue.EventUnregister(task, e);
+    }
+}
+
+impl SpliceOperations for NetlinkSocketOperations {}
+
+impl FileOperations for NetlinkSocketOperations {
+    fn as_any(&self) -> &Any {
+        return self;
+    }
+
+    fn FopsType(&self) -> FileOpsType {
+        return FileOpsType::SocketOperations;
+    }
+
+    fn Seekable(&self) -> bool {
+        return false;
+    }
+
+    fn Seek(
+        &self,
+        _task: &Task,
+        _f: &File,
+        _whence: i32,
+        _current: i64,
+        _offset: i64,
+    ) -> Result<i64> {
+        return Err(Error::SysError(SysErr::ESPIPE));
+    }
+
+    fn ReadDir(
+        &self,
+        _task: &Task,
+        _f: &File,
+        _offset: i64,
+        _serializer: &mut DentrySerializer,
+    ) -> Result<i64> {
+        return Err(Error::SysError(SysErr::ENOTDIR));
+    }
+
+    fn ReadAt(
+        &self,
+        task: &Task,
+        _f: &File,
+        dsts: &mut [IoVec],
+        _offset: i64,
+        _blocking: bool,
+    ) -> Result<i64> {
+        let data = self.Recv(false)?;
+        let len = core::cmp::min(IoVec::NumBytes(dsts), data.len());
+        task.CopyDataOutToIovs(&data[..len], dsts, false)?;
+        return Ok(len as i64);
+    }
+
+    fn WriteAt(
+        &self,
+        task: &Task,
+        _f: &File,
+        srcs: &[IoVec],
+        _offset: i64,
+        _blocking: bool,
+    ) -> Result<i64> {
+        let size = IoVec::NumBytes(srcs);
+        let mut buf = DataBuff::New(size);
+        let len = task.CopyDataInFromIovs(&mut buf.buf, srcs, true)?;
+        self.Process(task, &buf.buf[..len])?;
+        return Ok(len as i64);
+    }
+
+    fn Append(&self, task: &Task, f: &File, srcs: &[IoVec]) -> Result<(i64, i64)> {
+        let n = self.WriteAt(task, f, srcs, 0, false)?;
+        return Ok((n, 0));
+    }
+
+    fn Fsync(
+        &self,
+        _task: &Task,
+        _f: &File,
+        _start: i64,
+        _end: i64,
+        _syncType: SyncType,
+    ) -> Result<()> {
+        return Err(Error::SysError(SysErr::EINVAL));
+    }
+
+    fn Flush(&self, _task: &Task, _f: &File) -> Result<()> {
+        return Ok(());
+    }
+
+    fn UnstableAttr(&self, task: &Task, f: &File) -> Result<UnstableAttr> {
+        let inode = f.Dirent.Inode();
+        return inode.UnstableAttr(task);
+    }
+
+    fn Ioctl(&self, task: &Task, _f: &File, _fd: i32, request: u64, val: u64) -> Result<()> {
+        match request {
+            LibcConst::TIOCINQ => {
+                let v = self
+                    .ep
+                    .lock()
+                    .rcvQueue
+                    .front()
+                    .map(|m| m.len())
+                    .unwrap_or(0) as i32;
+                task.CopyOutObj(&v, val)?;
+                return Ok(());
+            }
+            _ => return Err(Error::SysError(SysErr::ENOTTY)),
+        }
+    }
+
+    fn IterateDir(
+        &self,
+        _task: &Task,
+        _d: &Dirent,
+        _dirCtx: &mut DirCtx,
+        _offset: i32,
+    ) -> (i32, Result<i64>) {
+        return (0, Err(Error::SysError(SysErr::ENOTDIR)));
+    }
+
+    fn Mappable(&self) -> Result<MMappable> {
+        return Err(Error::SysError(SysErr::ENODEV));
+    }
+}
+
+impl SockOperations for NetlinkSocketOperations {
+    fn Connect(&self, _task: &Task, sockaddr: &[u8], _blocking: bool) -> Result<i64> {
+        if sockaddr.len() >= 2 && u16::from_ne_bytes([sockaddr[0], sockaddr[1]]) == 0 {
+            return Ok(0);
+        }
+
+        // only the kernel can be connected to
+        let addr = Self::ParseAddr(sockaddr)?;
+        if addr.PortID != 0 {
+            return Err(Error::SysError(SysErr::ECONNREFUSED));
+        }
+
+        self.PortId();
+        return Ok(0);
+    }
+
+    fn Accept(
+        &self,
+        _task: &Task,
+        _addr: &mut [u8],
+        _addrlen: &mut u32,
+        _flags: i32,
+        _blocking: bool,
+    ) -> Result<i64> {
+        return Err(Error::SysError(SysErr::EOPNOTSUPP));
+    }
+
+    fn Bind(&self, _task: &Task, sockaddr: &[u8]) -> Result<i64> {
+        let addr = Self::ParseAddr(sockaddr)?;
+        let mut ep = self.ep.lock();
+        if addr.PortID != 0 && ep.portId != addr.PortID {
+            if ep.portId != 0 {
+                return Err(Error::SysError(SysErr::EINVAL));
+            }
+
+            if !PORTS.lock().Bind(addr.PortID) {
+                return Err(Error::SysError(SysErr::EADDRINUSE));
+            }
+            ep.portId = addr.PortID;
+        }
+
+        if ep.portId == 0 {
+            ep.portId = PORTS.lock().AutoBind();
+        }
+
+        // the groups are accepted, the interface table doesn't change so that
+        // there are no events
+        ep.groups = addr.Groups;
+        return Ok(0);
+    }
+
+    fn Listen(&self, _task: &Task, _backlog: i32) -> Result<i64> {
+        return Err(Error::SysError(SysErr::EOPNOTSUPP));
+    }
+
+    fn Shutdown(&self, _task: &Task, _how: i32) -> Result<i64> {
+        return Err(Error::SysError(SysErr::EOPNOTSUPP));
+    }
+
+    fn GetSockOpt(&self, _task: &Task, level: i32, name: i32, opt: &mut [u8]) -> Result<i64> {
+        let val = match (level as u64, name as u64) {
+            (LibcConst::SOL_SOCKET, LibcConst::SO_TYPE) => SockOptResult::I32(self.stype),
+            (LibcConst::SOL_SOCKET, LibcConst::SO_DOMAIN) => SockOptResult::I32(AFType::AF_NETLINK),
+            (LibcConst::SOL_SOCKET, LibcConst::SO_PROTOCOL) => SockOptResult::I32(self.protocol),
+            (LibcConst::SOL_SOCKET, LibcConst::SO_ERROR) => SockOptResult::I32(0),
+            (LibcConst::SOL_SOCKET, LibcConst::SO_RCVBUF) => {
+                SockOptResult::I32(self.ep.lock().rcvBufSize as i32)
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_SNDBUF) => {
+                SockOptResult::I32(self.ep.lock().sndBufSize as i32)
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_PASSCRED) => {
+                SockOptResult::I32(self.ep.lock().passCred as i32)
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_RCVTIMEO) => {
+                SockOptResult::Timeval(Timeval::FromNs(self.RecvTimeout()))
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_SNDTIMEO) => {
+                SockOptResult::Timeval(Timeval::FromNs(self.SendTimeout()))
+            }
+            _ if level == SOL_NETLINK => match name {
+                NETLINK_PKTINFO
+                | NETLINK_BROADCAST_ERROR
+                | NETLINK_NO_ENOBUFS
+                | NETLINK_LISTEN_ALL_NSID
+                | NETLINK_CAP_ACK
+                | NETLINK_EXT_ACK
+                | NETLINK_GET_STRICT_CHK => SockOptResult::I32(0),
+                _ => return Err(Error::SysError(SysErr::ENOPROTOOPT)),
+            },
+            _ => return Err(Error::SysError(SysErr::ENOPROTOOPT)),
+        };
+
+        let size = val.Marsh(opt)?;
+        return Ok(size as i64);
+    }
+
+    fn SetSockOpt(&self, task: &Task, level: i32, name: i32, opt: &[u8]) -> Result<i64> {
+        if level as u64 == LibcConst::SOL_SOCKET
+            && (name as u64 == LibcConst::SO_SNDTIMEO || name as u64 == LibcConst::SO_RCVTIMEO)
+        {
+            if opt.len() < SocketSize::SIZEOF_TIMEVAL {
+                return Err(Error::SysError(SysErr::EINVAL));
+            }
+
+            let timeVal = task.CopyInObj::<Timeval>(&opt[0] as *const _ as u64)?;
+            if name as u64 == LibcConst::SO_SNDTIMEO {
+                self.SetSendTimeout(timeVal.ToDuration() as i64);
+            } else {
+                self.SetRecvTimeout(timeVal.ToDuration() as i64);
+            }
+            return Ok(0);
+        }
+
+        if opt.len() < 4 {
+            return Err(Error::SysError(SysErr::EINVAL));
+        }
+
+        let v = i32::from_ne_bytes([opt[0], opt[1], opt[2], opt[3]]);
+        match (level as u64, name as u64) {
+            (LibcConst::SOL_SOCKET, LibcConst::SO_RCVBUF)
+            | (LibcConst::SOL_SOCKET, LibcConst::SO_RCVBUFFORCE) => {
+                let size = (v.max(0) as usize * 2)
+                    .max(NETLINK_MIN_BUF_SIZE)
+                    .min(NETLINK_MAX_BUF_SIZE);
+                self.ep.lock().rcvBufSize = size;
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_SNDBUF)
+            | (LibcConst::SOL_SOCKET, LibcConst::SO_SNDBUFFORCE) => {
+                let size = (v.max(0) as usize * 2)
+                    .max(NETLINK_MIN_BUF_SIZE)
+                    .min(NETLINK_MAX_BUF_SIZE);
+                self.ep.lock().sndBufSize = size;
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_PASSCRED) => {
+                self.ep.lock().passCred = v != 0;
+            }
+            _ if level == SOL_NETLINK => match name {
+                // there are no multicast events, the memberships are accepted
+                // without effect
+                NETLINK_ADD_MEMBERSHIP
+                | NETLINK_DROP_MEMBERSHIP
+                | NETLINK_PKTINFO
+                | NETLINK_BROADCAST_ERROR
+                | NETLINK_NO_ENOBUFS
+                | NETLINK_LISTEN_ALL_NSID
+                | NETLINK_CAP_ACK
+                | NETLINK_EXT_ACK
+                | NETLINK_GET_STRICT_CHK => (),
+                _ => return Err(Error::SysError(SysErr::ENOPROTOOPT)),
+            },
+            _ => return Err(Error::SysError(SysErr::ENOPROTOOPT)),
+        }
+
+        return Ok(0);
+    }
+
+    fn GetSockName(&self, _task: &Task, socketaddr: &mut [u8]) -> Result<i64> {
+        let data = SockAddr::Netlink(self.LocalAddr()).ToVec()?;
+        let n = core::cmp::min(socketaddr.len(), data.len());
+        socketaddr[..n].copy_from_slice(&data[..n]);
+        return Ok(data.len() as i64);
+    }
+
+    fn GetPeerName(&self, _task: &Task, socketaddr: &mut [u8]) -> Result<i64> {
+        let data = SockAddr::Netlink(KERNEL_ADDR).ToVec()?;
+        let n = core::cmp::min(socketaddr.len(), data.len());
+        socketaddr[..n].copy_from_slice(&data[..n]);
+        return Ok(data.len() as i64);
+    }
+
+    fn RecvMsg(
+        &self,
+        task: &Task,
+        dsts: &mut [IoVec],
+        flags: i32,
+        deadline: Option<Time>,
+        senderRequested: bool,
+        _controlDataLen: usize,
+    ) -> Result<(i64, i32, Option<(SockAddr, usize)>, Vec<u8>)> {
+        if flags
+            & !(MsgType::MSG_DONTWAIT
+                | MsgType::MSG_PEEK
+                | MsgType::MSG_TRUNC
+                | MsgType::MSG_ERRQUEUE
+                | MsgType::MSG_CTRUNC
+                | MsgType::MSG_WAITALL)
+            != 0
+        {
+            return Err(Error::SysError(SysErr::EINVAL));
+        }
+
+        if flags & MsgType::MSG_ERRQUEUE != 0 {
+            return Err(Error::SysError(SysErr::EAGAIN));
+        }
+
+        let peek = flags & MsgType::MSG_PEEK != 0;
+        let data = if flags & MsgType::MSG_DONTWAIT != 0 {
+            match self.Recv(peek) {
+                Err(Error::SysError(SysErr::EWOULDBLOCK)) => {
+                    return Err(Error::SysError(SysErr::EAGAIN))
+                }
+                ret => ret?,
+            }
+        } else {
+            self.RecvBlocking(task, peek, deadline)?
+        };
+
+        let len = core::cmp::min(IoVec::NumBytes(dsts), data.len());
+        task.CopyDataOutToIovs(&data[..len], dsts, false)?;
+
+        let mut msgFlags = 0;
+        if len < data.len() {
+            msgFlags |= MsgType::MSG_TRUNC;
+        }
+
+        let ret = if flags & MsgType::MSG_TRUNC != 0 {
+            data.len()
+        } else {
+            len
+        };
+
+        let sender = if senderRequested {
+            Some((SockAddr::Netlink(KERNEL_ADDR), KERNEL_ADDR.Len()))
+        } else {
+            None
+        };
+
+        return Ok((ret as i64, msgFlags, sender, Vec::new()));
+    }
+
+    fn SendMsg(
+        &self,
+        task: &Task,
+        srcs: &[IoVec],
+        _flags: i32,
+        msgHdr: &mut MsgHdr,
+        _deadline: Option<Time>,
+    ) -> Result<i64> {
+        if msgHdr.msgName != 0 && msgHdr.nameLen > 0 {
+            let name = task.CopyInVec(msgHdr.msgName, msgHdr.nameLen as usize)?;
+            let addr = Self::ParseAddr(&name)?;
+            // there are no other netlink sockets to talk to than the kernel
+            if addr.PortID != 0 {
+                return Err(Error::SysError(SysErr::ECONNREFUSED));
+            }
+        }
+
+        let size = IoVec::NumBytes(srcs);
+        if size > self.ep.lock().sndBufSize {
+            return Err(Error::SysError(SysErr::EMSGSIZE));
+        }
+
+        let mut buf = DataBuff::New(size);
+        let len = task.CopyDataInFromIovs(&mut buf.buf, srcs, true)?;
+        self.Process(task, &buf.buf[..len])?;
+        return Ok(len as i64);
+    }
+
+    fn SetRecvTimeout(&self, ns: i64) {
+        self.recv.store(ns, Ordering::Relaxed)
+    }
+
+    fn SetSendTimeout(&self, ns: i64) {
+        self.send.store(ns, Ordering::Relaxed)
+    }
+
+    fn RecvTimeout(&self) -> i64 {
+        return self.recv.load(Ordering::Relaxed);
+    }
+
+    fn SendTimeout(&self) -> i64 {
+        return self.send.load(Ordering::Relaxed);
+    }
+
+    fn State(&self) -> u32 {
+        return 0;
+    }
+
+    fn Type(&self) -> (i32, i32, i32) {
+        return (AFType::AF_NETLINK, self.stype, self.protocol);
+    }
+}
+
+pub struct NetlinkProvider {}
+
+impl Provider for NetlinkProvider {
+    fn Socket(&self, task: &Task, stype: i32, protocol: i32) -> Result<Option<Arc<File>>> {
+        let nonblocking = stype & SocketFlags::SOCK_NONBLOCK != 0;
+        let stype = stype & SocketType::SOCK_TYPE_MASK;
+
+        if stype != SockType::SOCK_RAW && stype != SockType::SOCK_DGRAM {
+            return Err(Error::SysError(SysErr::ESOCKTNOSUPPORT));
+        }
+
+        // the other protocols would need the host netlink
+        if protocol != NETLINK_ROUTE && protocol != NETLINK_KOBJECT_UEVENT {
+            return Err(Error::SysError(SysErr::EPROTONOSUPPORT));
+        }
+
+        let file = NewNetlinkSocketFile(task, stype, protocol, nonblocking)?;
+        return Ok(Some(Arc::new(file)));
+    }
+
+    fn Pair(
+        &self,
+        _task: &Task,
+        _stype: i32,
+        _protocol: i32,
+    ) -> Result<Option<(Arc<File>, Arc<File>)>> {
+        return Err(Error::SysError(SysErr::EOPNOTSUPP));
+    }
+}
+
+// Replies returns the replies to the requests in buf, process handles a
+// request. The errors of the requests are reported with NLMSG_ERROR, as the
+// acks, the dumps are terminated with NLMSG_DONE.
+pub fn Replies(
+    portId: u32,
+    buf: &[u8],
+    process: &mut dyn FnMut(&NetlinkMessageHeader, &[u8], &mut MessageSet) -> Result<()>,
+) -> Result<Vec<Vec<u8>>> {
+    let mut replies = Vec::new();
+    for (hdr, data) in ParseMessages(buf) {
+        if hdr.Flags & NLM_F_REQUEST == 0 || hdr.Type < NLMSG_MIN_TYPE {
+            continue;
+        }
+
+        let dump = hdr.Flags & NLM_F_DUMP == NLM_F_DUMP;
+        let mut ms = MessageSet::New(portId, hdr.Seq, dump);
+        match process(&hdr, data, &mut ms) {
+            Err(Error::SysError(e)) => {
+                ms.messages.clear();
+                ms.AddError(&hdr, e);
+            }
+            Err(e) => return Err(e),
+            Ok(()) => {
+                if dump {
+                    ms.AddDone();
+                } else if hdr.Flags & NLM_F_ACK != 0 {
+                    ms.AddError(&hdr, 0);
+                }
+            }
+        }
+
+        replies.append(&mut ms.messages);
+    }
+
+    return Ok(replies);
+}
+
+pub fn Init() {
+    FAMILIAES
+        .write()
+        .RegisterProvider(AFType::AF_NETLINK, Box::new(NetlinkProvider {}));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn Request(typ: u16, flags: u16, seq: u32, payload: &[u8]) -> Vec<u8> {
+        let mut m = Message::New(typ, flags, seq, 0);
+        m.PutBytes(payload);
+        return m.Finalize();
+    }
+
+    // Send returns the headers of the replies to the requests in buf, the
+    // requests are served from the loopback only interface table.
+    fn Send(buf: &[u8]) -> Vec<(NetlinkMessageHeader, Vec<u8>)> {
+        let table = InterfaceTable::default();
+        let replies = Replies(100, buf, &mut |hdr, data, ms| {
+            super::super::route::Process(&table, false, hdr, data, ms)
+        })
+        .unwrap();
+
+        return replies
+            .iter()
+            .map(|m| {
+                let msgs = ParseMessages(m);
+                assert_eq!(msgs.len(), 1);
+                (msgs[0].0, msgs[0].1.to_vec())
+            })
+            .collect();
+    }
+
+    #[test]
+    fn test_RepliesDump() {
+        let replies = Send(&Request(
+            RTM_GETADDR,
+            NLM_F_REQUEST | NLM_F_DUMP,
+            7,
+            &[AFType::AF_UNSPEC as u8],
+        ));
+        assert_eq!(replies.len(), 3);
+        for (hdr, _) in &replies {
+            assert_eq!(hdr.Flags, NLM_F_MULTI);
+            assert_eq!(hdr.Seq, 7);
+            assert_eq!(hdr.PortID, 100);
+        }
+        assert_eq!(replies[0].0.Type, RTM_NEWADDR);
+        assert_eq!(replies[1].0.Type, RTM_NEWADDR);
+        assert_eq!(replies[2].0.Type, NLMSG_DONE);
+    }
+
+    #[test]
+    fn test_RepliesError() {
+        let req = Request(RTM_NEWROUTE, NLM_F_REQUEST | NLM_F_ACK, 3, &[0; 12]);
+        let replies = Send(&req);
+        assert_eq!(replies.len(), 1);
+
+        let (hdr, data) = &replies[0];
+        assert_eq!(hdr.Type, NLMSG_ERROR);
+        assert_eq!(hdr.Seq, 3);
+        let err = Read::<NetlinkErrorMessage>(data).unwrap();
+        assert_eq!(err.Error, -SysErr::EPERM);
+        assert_eq!(err.Header.Type, RTM_NEWROUTE);
+        assert_eq!(err.Header.Length as usize, req.len());
+
+        // a failed dump has no NLMSG_DONE
+        let replies = Send(&Request(RTM_GETNEIGH, NLM_F_REQUEST, 4, &[0; 12]));
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].0.Type, NLMSG_ERROR);
+    }
+
+    #[test]
+    fn test_RepliesAck() {
+        let mut info = Message::New(0, 0, 0, 0);
+        info.Put(&InterfaceInfoMessage {
+            Index: 1,
+            ..Default::default()
+        });
+        let payload = info.Finalize()[NETLINK_MESSAGE_HEADER_SIZE..].to_vec();
+
+        // the requests of a batch are answered in order, the messages which are
+        // not requests are ignored
+        let mut buf = Request(RTM_GETLINK, NLM_F_REQUEST | NLM_F_ACK, 1, &payload);
+        buf.extend_from_slice(&Request(RTM_GETLINK, 0, 2, &payload));
+        buf.extend_from_slice(&Request(NLMSG_NOOP, NLM_F_REQUEST, 3, &[]));
+        buf.extend_from_slice(&Request(RTM_GETLINK, NLM_F_REQUEST, 4, &payload));
+
+        let replies = Send(&buf);
+        let types: Vec<(u16, u32)> = replies.iter().map(|(h, _)| (h.Type, h.Seq)).collect();
+        assert_eq!(
+            types,
+            vec![(RTM_NEWLINK, 1), (NLMSG_ERROR, 1), (RTM_NEWLINK, 4)]
+        );
+
+        let ack = Read::<NetlinkErrorMessage>(&replies[1].1).unwrap();
+        assert_eq!(ack.Error, 0);
+        assert_eq!(ack.Header.Seq, 1);
+    }
+}
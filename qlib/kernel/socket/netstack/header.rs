@@ -0,0 +1,728 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec::Vec;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::tcpip::tcpip::*;
+
+pub const ETHERNET_HEADER_SIZE: usize = 14;
+pub const ETH_P_IP: u16 = 0x0800;
+pub const ETH_P_ARP: u16 = 0x0806;
+pub const ETH_P_IPV6: u16 = 0x86dd;
+
+pub const IPV4_HEADER_SIZE: usize = 20;
+pub const IPV6_HEADER_SIZE: usize = 40;
+pub const ARP_SIZE: usize = 28;
+pub const UDP_HEADER_SIZE: usize = 8;
+pub const TCP_HEADER_SIZE: usize = 20;
+
+pub const IPPROTO_ICMP: u8 = 1;
+pub const IPPROTO_TCP: u8 = 6;
+pub const IPPROTO_UDP: u8 = 17;
+pub const IPPROTO_ICMPV6: u8 = 58;
+
+pub const DEFAULT_TTL: u8 = 64;
+
+pub const BROADCAST_MAC: [u8; 6] = [0xff; 6];
+
+pub fn GetU16(b: &[u8], off: usize) -> u16 {
+    return u16::from_be_bytes([b[off], b[off + 1]]);
+}
+
+pub fn GetU32(b: &[u8], off: usize) -> u32 {
+    return u32::from_be_bytes([b[off], b[off + 1], b[off + 2], b[off + 3]]);
+}
+
+pub fn PutU16(b: &mut [u8], off: usize, v: u16) {
+    b[off..off + 2].copy_from_slice(&v.to_be_bytes());
+}
+
+pub fn PutU32(b: &mut [u8], off: usize, v: u32) {
+    b[off..off + 4].copy_from_slice(&v.to_be_bytes());
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IpAddr {
+    V4([u8; 4]),
+    V6([u8; 16]),
+}
+
+pub const IPV4_ANY: IpAddr = IpAddr::V4([0; 4]);
+pub const IPV6_ANY: IpAddr = IpAddr::V6([0; 16]);
+pub const IPV4_LOOPBACK: IpAddr = IpAddr::V4([127, 0, 0, 1]);
+pub const IPV6_LOOPBACK: IpAddr = IpAddr::V6([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+impl IpAddr {
+    pub fn Any(v6: bool) -> Self {
+        if v6 {
+            return IPV6_ANY;
+        }
+
+        return IPV4_ANY;
+    }
+
+    pub fn IsV6(&self) -> bool {
+        match self {
+            Self::V4(_) => false,
+            Self::V6(_) => true,
+        }
+    }
+
+    pub fn Bytes(&self) -> &[u8] {
+        match self {
+            Self::V4(a) => &a[..],
+            Self::V6(a) => &a[..],
+        }
+    }
+
+    pub fn IsUnspecified(&self) -> bool {
+        return self.Bytes().iter().all(|b| *b == 0);
+    }
+
+    pub fn IsLoopback(&self) -> bool {
+        match self {
+            Self::V4(a) => a[0] == 127,
+            Self::V6(_) => *self == IPV6_LOOPBACK,
+        }
+    }
+
+    pub fn IsMulticast(&self) -> bool {
+        match self {
+            Self::V4(a) => a[0] & 0xf0 == 0xe0,
+            Self::V6(a) => a[0] == 0xff,
+        }
+    }
+
+    pub fn IsBroadcast(&self) -> bool {
+        match self {
+            Self::V4(a) => *a == [0xff; 4],
+            Self::V6(_) => false,
+        }
+    }
+
+    // InSubnet returns whether the address is in the subnet of prefix/prefixLen.
+    pub fn InSubnet(&self, prefix: &IpAddr, prefixLen: u32) -> bool {
+        if self.IsV6() != prefix.IsV6() {
+            return false;
+        }
+
+        let a = self.Bytes();
+        let b = prefix.Bytes();
+        let mut bits = prefixLen as usize;
+        for i in 0..a.len() {
+            if bits == 0 {
+                return true;
+            }
+
+            let mask = if bits >= 8 { 0xff } else { 0xffu8 << (8 - bits) };
+            if a[i] & mask != b[i] & mask {
+                return false;
+            }
+            bits -= core::cmp::min(bits, 8);
+        }
+
+        return true;
+    }
+
+    // Unmapped converts the v4-mapped ipv6 address ::ffff:a.b.c.d to a.b.c.d.
+    pub fn Unmapped(&self) -> Self {
+        if let Self::V6(a) = self {
+            if a[..10].iter().all(|b| *b == 0) && a[10] == 0xff && a[11] == 0xff {
+                return Self::V4([a[12], a[13], a[14], a[15]]);
+            }
+        }
+
+        return *self;
+    }
+
+    pub fn Mapped(&self) -> Self {
+        match self {
+            Self::V4(a) => {
+                let mut v = [0; 16];
+                v[10] = 0xff;
+                v[11] = 0xff;
+                v[12..].copy_from_slice(&a[..]);
+                return Self::V6(v);
+            }
+            Self::V6(_) => *self,
+        }
+    }
+}
+
+// FullAddr is an ip address with a port in host byte order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FullAddr {
+    pub addr: IpAddr,
+    pub port: u16,
+}
+
+impl FullAddr {
+    pub fn New(addr: IpAddr, port: u16) -> Self {
+        return Self { addr, port };
+    }
+
+    // Parse parses the sockaddr of a socket of family. The v4-mapped addresses
+    // of ipv6 sockets are converted to ipv4 addresses.
+    pub fn Parse(family: i32, sockaddr: &[u8]) -> Result<Self> {
+        if sockaddr.len() < 2 {
+            return Err(Error::SysError(SysErr::EINVAL));
+        }
+
+        let sfamily = u16::from_ne_bytes([sockaddr[0], sockaddr[1]]) as i32;
+        if sfamily != family {
+            return Err(Error::SysError(SysErr::EAFNOSUPPORT));
+        }
+
+        match GetAddr(sfamily as i16, sockaddr)? {
+            SockAddr::Inet(a) => return Ok(Self::New(IpAddr::V4(a.Addr), ntohs(a.Port))),
+            SockAddr::Inet6(a) => {
+                return Ok(Self::New(IpAddr::V6(a.Addr).Unmapped(), ntohs(a.Port)))
+            }
+            _ => return Err(Error::SysError(SysErr::EAFNOSUPPORT)),
+        }
+    }
+
+    pub fn ToSockAddr(&self, family: i32) -> SockAddr {
+        if family == AFType::AF_INET6 {
+            let addr = match self.addr.Mapped() {
+                IpAddr::V6(a) => a,
+                IpAddr::V4(_) => unreachable!(),
+            };
+            return SockAddr::Inet6(SocketAddrInet6 {
+                Family: AFType::AF_INET6 as u16,
+                Port: htons(self.port),
+                Addr: addr,
+                ..Default::default()
+            });
+        }
+
+        let addr = match self.addr {
+            IpAddr::V4(a) => a,
+            IpAddr::V6(_) => [0; 4],
+        };
+        return SockAddr::Inet(SockAddrInet {
+            Family: AFType::AF_INET as u16,
+            Port: htons(self.port),
+            Addr: addr,
+            ..Default::default()
+        });
+    }
+}
+
+// Checksum adds the 16 bits words of data to the partial checksum sum.
+pub fn Checksum(data: &[u8], mut sum: u32) -> u32 {
+    let mut i = 0;
+    while i + 1 < data.len() {
+        sum += GetU16(data, i) as u32;
+        i += 2;
+    }
+
+    if i < data.len() {
+        sum += (data[i] as u32) << 8;
+    }
+
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    return sum;
+}
+
+pub fn FinishChecksum(sum: u32) -> u16 {
+    return !(Checksum(&[], sum) as u16);
+}
+
+// PseudoHeaderChecksum returns the partial checksum of the tcp/udp pseudo
+// header.
+pub fn PseudoHeaderChecksum(src: &IpAddr, dst: &IpAddr, protocol: u8, len: usize) -> u32 {
+    let mut sum = Checksum(src.Bytes(), 0);
+    sum = Checksum(dst.Bytes(), sum);
+    sum += protocol as u32;
+    sum += len as u32 & 0xffff;
+    sum += (len as u32) >> 16;
+    return Checksum(&[], sum);
+}
+
+// TransportChecksum returns the checksum of a tcp/udp/icmpv6 packet whose
+// checksum field is zero or must verify to zero.
+pub fn TransportChecksum(src: &IpAddr, dst: &IpAddr, protocol: u8, packet: &[u8]) -> u16 {
+    let sum = PseudoHeaderChecksum(src, dst, protocol, packet.len());
+    return FinishChecksum(Checksum(packet, sum));
+}
+
+pub fn EthernetHeader(dst: &[u8; 6], src: &[u8; 6], ethType: u16, payloadLen: usize) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(ETHERNET_HEADER_SIZE + payloadLen);
+    frame.extend_from_slice(dst);
+    frame.extend_from_slice(src);
+    frame.extend_from_slice(&ethType.to_be_bytes());
+    return frame;
+}
+
+// IpHeader appends the ip header of a packet from src to dst to buf, src and
+// dst must be of the same family.
+pub fn IpHeader(
+    buf: &mut Vec<u8>,
+    src: &IpAddr,
+    dst: &IpAddr,
+    protocol: u8,
+    payloadLen: usize,
+    ident: u16,
+) -> Result<()> {
+    match (src, dst) {
+        (IpAddr::V4(s), IpAddr::V4(d)) => {
+            let mut h = [0u8; IPV4_HEADER_SIZE];
+            h[0] = 0x45;
+            PutU16(&mut h, 2, (IPV4_HEADER_SIZE + payloadLen) as u16);
+            PutU16(&mut h, 4, ident);
+            // don't fragment
+            PutU16(&mut h, 6, 0x4000);
+            h[8] = DEFAULT_TTL;
+            h[9] = protocol;
+            h[12..16].copy_from_slice(s);
+            h[16..20].copy_from_slice(d);
+            let csum = FinishChecksum(Checksum(&h, 0));
+            PutU16(&mut h, 10, csum);
+            buf.extend_from_slice(&h);
+        }
+        (IpAddr::V6(s), IpAddr::V6(d)) => {
+            let mut h = [0u8; IPV6_HEADER_SIZE];
+            h[0] = 0x60;
+            PutU16(&mut h, 4, payloadLen as u16);
+            h[6] = protocol;
+            // ndp messages must have a hop limit of 255
+            h[7] = if protocol == IPPROTO_ICMPV6 { 255 } else { DEFAULT_TTL };
+            h[8..24].copy_from_slice(s);
+            h[24..40].copy_from_slice(d);
+            buf.extend_from_slice(&h);
+        }
+        _ => return Err(Error::SysError(SysErr::EAFNOSUPPORT)),
+    }
+
+    return Ok(());
+}
+
+pub fn IpHeaderSize(addr: &IpAddr) -> usize {
+    if addr.IsV6() {
+        return IPV6_HEADER_SIZE;
+    }
+
+    return IPV4_HEADER_SIZE;
+}
+
+// IpPacket is a parsed ip packet, payload is the transport packet.
+pub struct IpPacket<'a> {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub protocol: u8,
+    pub payload: &'a [u8],
+}
+
+// ParseIpv4 validates the ipv4 header of packet. Fragments are not
+// reassembled, the tcp mss is clamped to the link mtu so they are rare.
+pub fn ParseIpv4(packet: &[u8]) -> Option<IpPacket> {
+    if packet.len() < IPV4_HEADER_SIZE || packet[0] >> 4 != 4 {
+        return None;
+    }
+
+    let ihl = (packet[0] & 0xf) as usize * 4;
+    let total = GetU16(packet, 2) as usize;
+    if ihl < IPV4_HEADER_SIZE || total < ihl || total > packet.len() {
+        return None;
+    }
+
+    if FinishChecksum(Checksum(&packet[..ihl], 0)) != 0 {
+        return None;
+    }
+
+    // more fragments or a fragment offset
+    if GetU16(packet, 6) & 0x3fff != 0 {
+        return None;
+    }
+
+    let mut src = [0; 4];
+    let mut dst = [0; 4];
+    src.copy_from_slice(&packet[12..16]);
+    dst.copy_from_slice(&packet[16..20]);
+    return Some(IpPacket {
+        src: IpAddr::V4(src),
+        dst: IpAddr::V4(dst),
+        protocol: packet[9],
+        payload: &packet[ihl..total],
+    });
+}
+
+// ParseIpv6 validates the ipv6 header of packet, the packets with extension
+// headers are dropped.
+pub fn ParseIpv6(packet: &[u8]) -> Option<IpPacket> {
+    if packet.len() < IPV6_HEADER_SIZE || packet[0] >> 4 != 6 {
+        return None;
+    }
+
+    let len = GetU16(packet, 4) as usize;
+    if IPV6_HEADER_SIZE + len > packet.len() {
+        return None;
+    }
+
+    let mut src = [0; 16];
+    let mut dst = [0; 16];
+    src.copy_from_slice(&packet[8..24]);
+    dst.copy_from_slice(&packet[24..40]);
+    return Some(IpPacket {
+        src: IpAddr::V6(src),
+        dst: IpAddr::V6(dst),
+        protocol: packet[6],
+        payload: &packet[IPV6_HEADER_SIZE..IPV6_HEADER_SIZE + len],
+    });
+}
+
+pub const TCP_FLAG_FIN: u8 = 0x01;
+pub const TCP_FLAG_SYN: u8 = 0x02;
+pub const TCP_FLAG_RST: u8 = 0x04;
+pub const TCP_FLAG_PSH: u8 = 0x08;
+pub const TCP_FLAG_ACK: u8 = 0x10;
+
+pub const TCP_OPT_EOL: u8 = 0;
+pub const TCP_OPT_NOP: u8 = 1;
+pub const TCP_OPT_MSS: u8 = 2;
+pub const TCP_OPT_WS: u8 = 3;
+pub const TCP_OPT_SACK_PERMITTED: u8 = 4;
+pub const TCP_OPT_SACK: u8 = 5;
+
+// MAX_SACK_BLOCKS is the count of sack blocks sent in a segment, the option
+// space left by the other options is 40 - 4 (nop, nop, kind, len).
+pub const MAX_SACK_BLOCKS: usize = 4;
+
+#[derive(Clone, Debug, Default)]
+pub struct TcpOptions {
+    pub mss: Option<u16>,
+    pub ws: Option<u8>,
+    pub sackPermitted: bool,
+    pub sack: Vec<(u32, u32)>,
+}
+
+impl TcpOptions {
+    pub fn Parse(opts: &[u8]) -> Self {
+        let mut ret = Self::default();
+        let mut i = 0;
+        while i < opts.len() {
+            let kind = opts[i];
+            if kind == TCP_OPT_EOL {
+                break;
+            }
+
+            if kind == TCP_OPT_NOP {
+                i += 1;
+                continue;
+            }
+
+            if i + 1 >= opts.len() {
+                break;
+            }
+
+            let len = opts[i + 1] as usize;
+            if len < 2 || i + len > opts.len() {
+                break;
+            }
+
+            let data = &opts[i + 2..i + len];
+            match kind {
+                TCP_OPT_MSS if data.len() == 2 => ret.mss = Some(GetU16(data, 0)),
+                TCP_OPT_WS if data.len() == 1 => ret.ws = Some(core::cmp::min(data[0], 14)),
+                TCP_OPT_SACK_PERMITTED => ret.sackPermitted = true,
+                TCP_OPT_SACK => {
+                    for b in data.chunks_exact(8) {
+                        ret.sack.push((GetU32(b, 0), GetU32(b, 4)));
+                    }
+                }
+                _ => (),
+            }
+            i += len;
+        }
+
+        return ret;
+    }
+
+    pub fn Marsh(&self) -> Vec<u8> {
+        let mut v = Vec::new();
+        if let Some(mss) = self.mss {
+            v.push(TCP_OPT_MSS);
+            v.push(4);
+            v.extend_from_slice(&mss.to_be_bytes());
+        }
+
+        if let Some(ws) = self.ws {
+            v.push(TCP_OPT_NOP);
+            v.push(TCP_OPT_WS);
+            v.push(3);
+            v.push(ws);
+        }
+
+        if self.sackPermitted {
+            v.push(TCP_OPT_NOP);
+            v.push(TCP_OPT_NOP);
+            v.push(TCP_OPT_SACK_PERMITTED);
+            v.push(2);
+        }
+
+        if self.sack.len() > 0 {
+            v.push(TCP_OPT_NOP);
+            v.push(TCP_OPT_NOP);
+            v.push(TCP_OPT_SACK);
+            v.push((2 + self.sack.len() * 8) as u8);
+            for (start, end) in &self.sack {
+                v.extend_from_slice(&start.to_be_bytes());
+                v.extend_from_slice(&end.to_be_bytes());
+            }
+        }
+
+        while v.len() % 4 != 0 {
+            v.push(TCP_OPT_EOL);
+        }
+
+        return v;
+    }
+}
+
+pub struct TcpSegment<'a> {
+    pub srcPort: u16,
+    pub dstPort: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    pub options: TcpOptions,
+    pub payload: &'a [u8],
+}
+
+impl<'a> TcpSegment<'a> {
+    pub fn Parse(src: &IpAddr, dst: &IpAddr, packet: &'a [u8]) -> Option<Self> {
+        if packet.len() < TCP_HEADER_SIZE {
+            return None;
+        }
+
+        let off = (packet[12] >> 4) as usize * 4;
+        if off < TCP_HEADER_SIZE || off > packet.len() {
+            return None;
+        }
+
+        if TransportChecksum(src, dst, IPPROTO_TCP, packet) != 0 {
+            return None;
+        }
+
+        return Some(Self {
+            srcPort: GetU16(packet, 0),
+            dstPort: GetU16(packet, 2),
+            seq: GetU32(packet, 4),
+            ack: GetU32(packet, 8),
+            flags: packet[13],
+            window: GetU16(packet, 14),
+            options: TcpOptions::Parse(&packet[TCP_HEADER_SIZE..off]),
+            payload: &packet[off..],
+        });
+    }
+
+    // Len returns the sequence space consumed by the segment.
+    pub fn Len(&self) -> u32 {
+        let mut len = self.payload.len() as u32;
+        if self.flags & TCP_FLAG_SYN != 0 {
+            len += 1;
+        }
+
+        if self.flags & TCP_FLAG_FIN != 0 {
+            len += 1;
+        }
+
+        return len;
+    }
+
+    pub fn Marsh(&self, src: &IpAddr, dst: &IpAddr) -> Vec<u8> {
+        let opts = self.options.Marsh();
+        let hlen = TCP_HEADER_SIZE + opts.len();
+        let mut v = Vec::with_capacity(hlen + self.payload.len());
+        v.resize(TCP_HEADER_SIZE, 0);
+        PutU16(&mut v, 0, self.srcPort);
+        PutU16(&mut v, 2, self.dstPort);
+        PutU32(&mut v, 4, self.seq);
+        PutU32(&mut v, 8, self.ack);
+        v[12] = ((hlen / 4) as u8) << 4;
+        v[13] = self.flags;
+        PutU16(&mut v, 14, self.window);
+        v.extend_from_slice(&opts);
+        v.extend_from_slice(self.payload);
+        let csum = TransportChecksum(src, dst, IPPROTO_TCP, &v);
+        PutU16(&mut v, 16, csum);
+        return v;
+    }
+}
+
+pub fn UdpPacket(src: &FullAddr, dst: &FullAddr, payload: &[u8]) -> Vec<u8> {
+    let len = UDP_HEADER_SIZE + payload.len();
+    let mut v = Vec::with_capacity(len);
+    v.resize(UDP_HEADER_SIZE, 0);
+    PutU16(&mut v, 0, src.port);
+    PutU16(&mut v, 2, dst.port);
+    PutU16(&mut v, 4, len as u16);
+    v.extend_from_slice(payload);
+    let mut csum = TransportChecksum(&src.addr, &dst.addr, IPPROTO_UDP, &v);
+    if csum == 0 {
+        csum = 0xffff;
+    }
+    PutU16(&mut v, 6, csum);
+    return v;
+}
+
+// SeqLT etc. compare the tcp sequence numbers with wraparound.
+pub fn SeqLT(a: u32, b: u32) -> bool {
+    return (a.wrapping_sub(b) as i32) < 0;
+}
+
+pub fn SeqLEQ(a: u32, b: u32) -> bool {
+    return (a.wrapping_sub(b) as i32) <= 0;
+}
+
+pub fn SeqGT(a: u32, b: u32) -> bool {
+    return SeqLT(b, a);
+}
+
+pub fn SeqGEQ(a: u32, b: u32) -> bool {
+    return SeqLEQ(b, a);
+}
+
+// InWindow returns whether seq is in [start, start + size).
+pub fn InWindow(seq: u32, start: u32, size: u32) -> bool {
+    return seq.wrapping_sub(start) < size;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A4: IpAddr = IpAddr::V4([10, 0, 0, 1]);
+    const B4: IpAddr = IpAddr::V4([10, 0, 0, 2]);
+
+    #[test]
+    fn test_IpHeaderFamilyMismatch() {
+        let mut buf = Vec::new();
+        assert!(IpHeader(&mut buf, &A4, &IPV6_LOOPBACK, IPPROTO_TCP, 0, 0).is_err());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_ParseIpv4() {
+        let mut packet = Vec::new();
+        IpHeader(&mut packet, &A4, &B4, IPPROTO_UDP, 3, 7).unwrap();
+        packet.extend_from_slice(&[1, 2, 3]);
+        // trailing link padding is not part of the payload
+        packet.push(0);
+
+        let p = ParseIpv4(&packet).unwrap();
+        assert_eq!(p.src, A4);
+        assert_eq!(p.dst, B4);
+        assert_eq!(p.protocol, IPPROTO_UDP);
+        assert_eq!(p.payload, &[1, 2, 3]);
+
+        // bad checksum
+        let mut bad = packet.clone();
+        bad[8] -= 1;
+        assert!(ParseIpv4(&bad).is_none());
+
+        // fragment
+        let mut frag = packet.clone();
+        PutU16(&mut frag, 6, 0x2000);
+        PutU16(&mut frag, 10, 0);
+        let csum = FinishChecksum(Checksum(&frag[..IPV4_HEADER_SIZE], 0));
+        PutU16(&mut frag, 10, csum);
+        assert!(ParseIpv4(&frag).is_none());
+
+        // truncated
+        assert!(ParseIpv4(&packet[..IPV4_HEADER_SIZE + 1]).is_none());
+        assert!(ParseIpv4(&packet[..10]).is_none());
+    }
+
+    #[test]
+    fn test_ParseIpv6() {
+        let mut packet = Vec::new();
+        IpHeader(&mut packet, &IPV6_LOOPBACK, &IPV6_LOOPBACK, IPPROTO_ICMPV6, 2, 0).unwrap();
+        assert_eq!(packet[7], 255);
+        packet.extend_from_slice(&[1, 2]);
+
+        let p = ParseIpv6(&packet).unwrap();
+        assert_eq!(p.src, IPV6_LOOPBACK);
+        assert_eq!(p.protocol, IPPROTO_ICMPV6);
+        assert_eq!(p.payload, &[1, 2]);
+
+        assert!(ParseIpv6(&packet[..IPV6_HEADER_SIZE + 1]).is_none());
+        assert!(ParseIpv4(&packet).is_none());
+    }
+
+    #[test]
+    fn test_TcpSegment() {
+        let seg = TcpSegment {
+            srcPort: 1000,
+            dstPort: 80,
+            seq: 1,
+            ack: 2,
+            flags: TCP_FLAG_SYN | TCP_FLAG_ACK,
+            window: 1024,
+            options: TcpOptions {
+                mss: Some(1460),
+                ws: Some(7),
+                sackPermitted: true,
+                sack: vec![(10, 20)],
+            },
+            payload: &[9, 9],
+        };
+
+        let packet = seg.Marsh(&A4, &B4);
+        let p = TcpSegment::Parse(&A4, &B4, &packet).unwrap();
+        assert_eq!((p.srcPort, p.dstPort, p.seq, p.ack), (1000, 80, 1, 2));
+        assert_eq!(p.flags, TCP_FLAG_SYN | TCP_FLAG_ACK);
+        assert_eq!(p.window, 1024);
+        assert_eq!(p.options.mss, Some(1460));
+        assert_eq!(p.options.ws, Some(7));
+        assert!(p.options.sackPermitted);
+        assert_eq!(p.options.sack, vec![(10, 20)]);
+        assert_eq!(p.payload, &[9, 9]);
+        assert_eq!(p.Len(), 3);
+
+        // the pseudo header is part of the checksum
+        assert!(TcpSegment::Parse(&A4, &A4, &packet).is_none());
+        assert!(TcpSegment::Parse(&A4, &B4, &packet[..TCP_HEADER_SIZE - 1]).is_none());
+    }
+
+    #[test]
+    fn test_TcpOptionsMalformed() {
+        // a length running past the options stops the parsing
+        let opts = TcpOptions::Parse(&[TCP_OPT_MSS, 4, 5, 0xb4, TCP_OPT_WS, 9, 1]);
+        assert_eq!(opts.mss, Some(0x5b4));
+        assert_eq!(opts.ws, None);
+
+        // the window scale is capped
+        let opts = TcpOptions::Parse(&[TCP_OPT_WS, 3, 20]);
+        assert_eq!(opts.ws, Some(14));
+    }
+
+    #[test]
+    fn test_UdpPacket() {
+        let src = FullAddr::New(A4, 53);
+        let dst = FullAddr::New(B4, 5353);
+        let packet = UdpPacket(&src, &dst, &[1, 2, 3]);
+        assert_eq!(GetU16(&packet, 0), 53);
+        assert_eq!(GetU16(&packet, 2), 5353);
+        assert_eq!(GetU16(&packet, 4) as usize, UDP_HEADER_SIZE + 3);
+        assert_eq!(TransportChecksum(&A4, &B4, IPPROTO_UDP, &packet), 0);
+    }
+}
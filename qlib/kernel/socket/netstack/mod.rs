@@ -0,0 +1,196 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// netstack is an in-guest TCP/IP stack. It takes over the network interface
+// of the sandbox and exchanges ethernet frames with it through uring, so that
+// the host kernel only sees L2 frames instead of the socket syscalls.
+
+pub mod header;
+pub mod socket;
+pub mod stack;
+pub mod tcp;
+pub mod udp;
+
+use crate::qlib::mutex::*;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+
+use super::super::super::common::*;
+use super::super::super::linux::time::*;
+use super::super::super::linux_def::*;
+use super::super::super::qmsg::qcall::NetstackNicInfo;
+use super::super::kernel::timer::timer::*;
+use super::super::kernel::timer::*;
+use super::super::kernel::waiter::*;
+use super::super::kernel_util::*;
+use super::super::Kernel::HostSpace;
+use super::super::IOURING;
+use super::super::SHARESPACE;
+use self::header::*;
+use self::stack::*;
+
+// The tcp timers run on a NETSTACK_TICK period.
+pub const NETSTACK_TICK: Duration = 10 * MILLISECOND;
+
+lazy_static! {
+    pub static ref NETSTACK: Netstack = Netstack::default();
+}
+
+pub fn Init() {
+    if !SHARESPACE.config.read().EnableNetstack {
+        return;
+    }
+
+    self::socket::Init();
+}
+
+// Output collects what the stack produces while it is locked, the frames are
+// written and the waiters are notified after the lock is released.
+pub struct Output {
+    pub now: i64,
+    pub packets: VecDeque<(IpAddr, IpAddr, u8, Vec<u8>)>,
+    pub frames: Vec<Vec<u8>>,
+    pub notify: Vec<(Queue, EventMask)>,
+}
+
+impl Output {
+    pub fn New() -> Self {
+        return Self {
+            now: MonotonicNow(),
+            packets: VecDeque::new(),
+            frames: Vec::new(),
+            notify: Vec::new(),
+        };
+    }
+
+    // Send queues the transport packet to be routed.
+    pub fn Send(&mut self, src: IpAddr, dst: IpAddr, protocol: u8, payload: Vec<u8>) {
+        self.packets.push_back((src, dst, protocol, payload));
+    }
+
+    // SendRstFor answers seg, received from remote for local, with a reset.
+    pub fn SendRstFor(&mut self, local: &FullAddr, remote: &FullAddr, seg: &TcpSegment) {
+        if seg.flags & TCP_FLAG_RST != 0 {
+            return;
+        }
+
+        let (seq, ack, flags) = if seg.flags & TCP_FLAG_ACK != 0 {
+            (seg.ack, 0, TCP_FLAG_RST)
+        } else {
+            (0, seg.seq.wrapping_add(seg.Len()), TCP_FLAG_RST | TCP_FLAG_ACK)
+        };
+
+        let rst = TcpSegment {
+            srcPort: local.port,
+            dstPort: remote.port,
+            seq: seq,
+            ack: ack,
+            flags: flags,
+            window: 0,
+            options: TcpOptions::default(),
+            payload: &[],
+        };
+
+        let packet = rst.Marsh(&local.addr, &remote.addr);
+        self.Send(local.addr, remote.addr, IPPROTO_TCP, packet);
+    }
+
+    fn Flush(self, fd: i32) {
+        for frame in self.frames {
+            IOURING.NetstackWrite(fd, frame);
+        }
+
+        for (queue, mask) in self.notify {
+            queue.Notify(mask);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Netstack {
+    pub stack: QMutex<Option<Stack>>,
+    pub ticker: QMutex<Option<Timer>>,
+}
+
+impl Netstack {
+    // Start takes over the sandbox network interface, it is called when the
+    // first socket is created.
+    pub fn Start(&self) -> Result<()> {
+        let fd;
+        let mtu;
+        {
+            let mut stack = self.stack.lock();
+            if stack.is_some() {
+                return Ok(());
+            }
+
+            let mut nic = NetstackNicInfo::default();
+            let ret = HostSpace::NetstackLinkInit(&mut nic as *mut _ as u64);
+            if ret < 0 {
+                error!("netstack: fail to open the link: {}", ret);
+                return Err(Error::SysError(-ret as i32));
+            }
+
+            fd = ret as i32;
+            mtu = nic.mtu as usize;
+            let secret = RandU64().unwrap_or(MonotonicNow() as u64);
+            *stack = Some(Stack::New(fd, nic, secret));
+            super::netlink::AddNetstackInterface(&nic);
+        }
+
+        IOURING.NetstackReadInit(fd, mtu + ETHERNET_HEADER_SIZE);
+        let timer = Timer::Period(
+            &MONOTONIC_CLOCK,
+            TimerListener::NetstackTicker(NetstackTicker {}),
+            NETSTACK_TICK,
+        );
+        *self.ticker.lock() = Some(timer);
+        return Ok(());
+    }
+
+    // Run runs f with the stack locked, then routes the packets f sent.
+    pub fn Run<T>(&self, f: impl FnOnce(&mut Stack, &mut Output) -> T) -> T {
+        let mut out = Output::New();
+        let (ret, fd) = {
+            let mut stack = self.stack.lock();
+            let stack = stack.as_mut().expect("netstack is not started");
+            let ret = f(stack, &mut out);
+            stack.Drain(&mut out);
+            (ret, stack.fd)
+        };
+
+        out.Flush(fd);
+        return ret;
+    }
+
+    pub fn DeliverFrame(&self, frame: &[u8]) {
+        self.Run(|stack, out| stack.DeliverFrame(frame, out));
+    }
+}
+
+#[derive(Clone)]
+pub struct NetstackTicker {}
+
+impl NetstackTicker {
+    pub fn Notify(&self, _exp: u64) {
+        if NETSTACK.stack.lock().is_none() {
+            return;
+        }
+
+        NETSTACK.Run(|stack, out| stack.Tick(out));
+    }
+
+    pub fn Destroy(&self) {}
+}
@@ -0,0 +1,1066 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::ops::Deref;
+use core::sync::atomic::AtomicI64;
+use core::sync::atomic::Ordering;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux::time::Timeval;
+use super::super::super::super::linux_def::*;
+use super::super::super::fs::attr::*;
+use super::super::super::fs::dentry::*;
+use super::super::super::fs::dirent::*;
+use super::super::super::fs::file::*;
+use super::super::super::fs::flags::*;
+use super::super::super::kernel::fd_table::*;
+use super::super::super::kernel::kernel::GetKernel;
+use super::super::super::kernel::time::*;
+use super::super::super::kernel::waiter::*;
+use super::super::super::task::*;
+use super::super::super::tcpip::tcpip::*;
+use super::super::epsocket::epsocket::*;
+use super::super::hostinet::firewall::FIREWALL;
+use super::super::socket::*;
+use super::super::unix::transport::unix::SockType;
+use super::super::unix::unix::NewUnixSocketDummyDirent;
+use super::header::*;
+use super::tcp::*;
+use super::udp::*;
+use super::*;
+
+const SOMAXCONN: i32 = 4096;
+
+pub fn NewNetstackSocketFile(
+    task: &Task,
+    family: i32,
+    stype: i32,
+    id: u64,
+    queue: Queue,
+    nonblock: bool,
+) -> Result<File> {
+    let dirent = NewUnixSocketDummyDirent(task, SOCKET_DEVICE.clone())?;
+    let ops = NetstackSocketOperations(Arc::new(NetstackSocketOperationsIntern {
+        family: family,
+        stype: stype,
+        id: id,
+        queue: queue,
+        send: AtomicI64::new(0),
+        recv: AtomicI64::new(0),
+    }));
+
+    let file = File::New(
+        &dirent,
+        &FileFlags {
+            NonBlocking: nonblock,
+            Read: true,
+            Write: true,
+            ..Default::default()
+        },
+        ops.into(),
+    );
+
+    GetKernel().sockets.AddSocket(&file);
+    return Ok(file);
+}
+
+pub struct NetstackSocketOperationsIntern {
+    pub family: i32,
+    pub stype: i32,
+    pub id: u64,
+    pub queue: Queue,
+    pub send: AtomicI64,
+    pub recv: AtomicI64,
+}
+
+impl Drop for NetstackSocketOperationsIntern {
+    fn drop(&mut self) {
+        let id = self.id;
+        NETSTACK.Run(|stack, out| stack.Close(id, out));
+    }
+}
+
+#[derive(Clone)]
+pub struct NetstackSocketOperations(Arc<NetstackSocketOperationsIntern>);
+
+impl Deref for NetstackSocketOperations {
+    type Target = Arc<NetstackSocketOperationsIntern>;
+
+    fn deref(&self) -> &Arc<NetstackSocketOperationsIntern> {
+        &self.0
+    }
+}
+
+impl NetstackSocketOperations {
+    pub fn IsTcp(&self) -> bool {
+        return self.stype == SockType::SOCK_STREAM;
+    }
+
+    pub fn Protocol(&self) -> i32 {
+        if self.IsTcp() {
+            return IPPROTO_TCP as i32;
+        }
+
+        return IPPROTO_UDP as i32;
+    }
+
+    // Tcp runs f on the endpoint of the socket. The stack only releases the
+    // endpoint of a closed socket, if it is gone anyway f sees a closed
+    // endpoint instead of taking the sandbox down.
+    fn Tcp<T>(&self, f: impl FnOnce(&mut TcpEndpoint, &mut Output) -> T) -> T {
+        let id = self.id;
+        let v6 = self.family == AFType::AF_INET6;
+        return NETSTACK.Run(|stack, out| match stack.tcpEps.get_mut(&id) {
+            Some(ep) => f(ep, out),
+            None => {
+                error!("netstack: tcp endpoint {} is gone", id);
+                f(&mut TcpEndpoint::New(id, self.queue.clone(), v6), out)
+            }
+        });
+    }
+
+    fn Udp<T>(&self, f: impl FnOnce(&mut UdpEndpoint, &mut Output) -> T) -> T {
+        let id = self.id;
+        let v6 = self.family == AFType::AF_INET6;
+        return NETSTACK.Run(|stack, out| match stack.udpEps.get_mut(&id) {
+            Some(ep) => f(ep, out),
+            None => {
+                error!("netstack: udp endpoint {} is gone", id);
+                f(&mut UdpEndpoint::New(id, self.queue.clone(), v6), out)
+            }
+        });
+    }
+
+    fn Local(&self) -> Option<FullAddr> {
+        if self.IsTcp() {
+            return self.Tcp(|ep, _| ep.local);
+        }
+
+        return self.Udp(|ep, _| ep.local);
+    }
+
+    fn Remote(&self) -> Option<FullAddr> {
+        if self.IsTcp() {
+            return self.Tcp(|ep, _| {
+                if ep.state.Connected() || ep.state == TcpState::SynSent {
+                    ep.remote
+                } else {
+                    None
+                }
+            });
+        }
+
+        return self.Udp(|ep, _| ep.remote);
+    }
+
+    fn TakeError(&self) -> Option<i32> {
+        if self.IsTcp() {
+            return self.Tcp(|ep, _| ep.error.take());
+        }
+
+        return self.Udp(|ep, _| ep.error.take());
+    }
+
+    // Block retries f until it doesn't return EWOULDBLOCK, waiting for the
+    // events of mask in between.
+    fn Block<T>(
+        &self,
+        task: &Task,
+        mask: EventMask,
+        deadline: Option<Time>,
+        mut f: impl FnMut() -> Result<T>,
+    ) -> Result<T> {
+        let general = task.blocker.generalEntry.clone();
+        self.EventRegister(task, &general, mask);
+        defer!(self.EventUnregister(task, &general));
+
+        loop {
+            match f() {
+                Err(Error::SysError(SysErr::EWOULDBLOCK)) => (),
+                ret => return ret,
+            }
+
+            match task.blocker.BlockWithMonoTimer(true, deadline) {
+                Err(Error::ErrInterrupted) => {
+                    return Err(Error::SysError(SysErr::ERESTARTSYS));
+                }
+                Err(Error::SysError(SysErr::ETIMEDOUT)) => {
+                    return Err(Error::SysError(SysErr::EAGAIN));
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn TcpRecv(&self, len: usize, peek: bool) -> Result<Vec<u8>> {
+        return self.Tcp(|ep, out| {
+            if ep.rcvBuf.len() > 0 {
+                return Ok(ep.Read(len, peek, out));
+            }
+
+            if let Some(e) = ep.error.take() {
+                return Err(Error::SysError(e));
+            }
+
+            if ep.rcvClosed || ep.shutRead {
+                return Ok(Vec::new());
+            }
+
+            match ep.state {
+                TcpState::SynSent | TcpState::SynRcvd => Err(Error::SysError(SysErr::EWOULDBLOCK)),
+                TcpState::Listen => Err(Error::SysError(SysErr::ENOTCONN)),
+                TcpState::Closed if !ep.connected => Err(Error::SysError(SysErr::ENOTCONN)),
+                TcpState::Closed => Ok(Vec::new()),
+                _ => Err(Error::SysError(SysErr::EWOULDBLOCK)),
+            }
+        });
+    }
+
+    fn TcpSend(&self, data: &[u8]) -> Result<usize> {
+        return self.Tcp(|ep, out| {
+            if let Some(e) = ep.error.take() {
+                return Err(Error::SysError(e));
+            }
+
+            if ep.finQueued {
+                return Err(Error::SysError(SysErr::EPIPE));
+            }
+
+            match ep.state {
+                TcpState::Established | TcpState::CloseWait => (),
+                TcpState::SynSent | TcpState::SynRcvd => {
+                    return Err(Error::SysError(SysErr::EWOULDBLOCK))
+                }
+                TcpState::Listen => return Err(Error::SysError(SysErr::ENOTCONN)),
+                TcpState::Closed if !ep.connected => {
+                    return Err(Error::SysError(SysErr::ENOTCONN))
+                }
+                _ => return Err(Error::SysError(SysErr::EPIPE)),
+            }
+
+            let n = ep.Write(data, out);
+            if n == 0 {
+                return Err(Error::SysError(SysErr::EWOULDBLOCK));
+            }
+
+            return Ok(n);
+        });
+    }
+
+    fn UdpRecv(&self, peek: bool) -> Result<(FullAddr, Vec<u8>)> {
+        return self.Udp(|ep, _| {
+            if let Some(d) = ep.Recv(peek) {
+                return Ok(d);
+            }
+
+            if let Some(e) = ep.error.take() {
+                return Err(Error::SysError(e));
+            }
+
+            if ep.shutRead {
+                return Ok((FullAddr::New(IpAddr::Any(ep.v6), 0), Vec::new()));
+            }
+
+            return Err(Error::SysError(SysErr::EWOULDBLOCK));
+        });
+    }
+
+    fn UdpSend(&self, dst: Option<FullAddr>, data: &[u8]) -> Result<usize> {
+        let id = self.id;
+        return NETSTACK.Run(|stack, out| stack.UdpSend(id, dst, data, out));
+    }
+
+    fn ParseAddr(&self, sockaddr: &[u8]) -> Result<FullAddr> {
+        return FullAddr::Parse(self.family, sockaddr);
+    }
+}
+
+impl Waitable for NetstackSocketOperations {
+    fn Readiness(&self, _task: &Task, mask: EventMask) -> EventMask {
+        if self.IsTcp() {
+            return self.Tcp(|ep, _| ep.Readiness(mask));
+        }
+
+        return self.Udp(|ep, _| ep.Readiness(mask));
+    }
+
+    fn EventRegister(&self, task: &Task, e: &WaitEntry, mask: EventMask) {
+        self.queue.EventRegister(task, e, mask);
+    }
+
+    fn EventUnregister(&self, task: &Task, e: &WaitEntry) {
+        self.queue.EventUnregister(task, e);
+    }
+}
+
+impl SpliceOperations for NetstackSocketOperations {}
+
+impl FileOperations for NetstackSocketOperations {
+    fn as_any(&self) -> &Any {
+        return self;
+    }
+
+    fn FopsType(&self) -> FileOpsType {
+        return FileOpsType::SocketOperations;
+    }
+
+    fn Seekable(&self) -> bool {
+        return false;
+    }
+
+    fn Seek(
+        &self,
+        _task: &Task,
+        _f: &File,
+        _whence: i32,
+        _current: i64,
+        _offset: i64,
+    ) -> Result<i64> {
+        return Err(Error::SysError(SysErr::ESPIPE));
+    }
+
+    fn ReadDir(
+        &self,
+        _task: &Task,
+        _f: &File,
+        _offset: i64,
+        _serializer: &mut DentrySerializer,
+    ) -> Result<i64> {
+        return Err(Error::SysError(SysErr::ENOTDIR));
+    }
+
+    fn ReadAt(
+        &self,
+        task: &Task,
+        _f: &File,
+        dsts: &mut [IoVec],
+        _offset: i64,
+        _blocking: bool,
+    ) -> Result<i64> {
+        let size = IoVec::NumBytes(dsts);
+        let data = if self.IsTcp() {
+            self.TcpRecv(size, false)?
+        } else {
+            self.UdpRecv(false)?.1
+        };
+
+        let len = core::cmp::min(size, data.len());
+        task.CopyDataOutToIovs(&data[..len], dsts, false)?;
+        return Ok(len as i64);
+    }
+
+    fn WriteAt(
+        &self,
+        task: &Task,
+        _f: &File,
+        srcs: &[IoVec],
+        _offset: i64,
+        _blocking: bool,
+    ) -> Result<i64> {
+        let size = IoVec::NumBytes(srcs);
+        let mut buf = DataBuff::New(size);
+        let len = task.CopyDataInFromIovs(&mut buf.buf, srcs, true)?;
+        let data = &buf.buf[..len];
+        let n = if self.IsTcp() {
+            if len == 0 {
+                return Ok(0);
+            }
+            self.TcpSend(data)?
+        } else {
+            self.UdpSend(None, data)?
+        };
+
+        return Ok(n as i64);
+    }
+
+    fn Append(&self, task: &Task, f: &File, srcs: &[IoVec]) -> Result<(i64, i64)> {
+        let n = self.WriteAt(task, f, srcs, 0, false)?;
+        return Ok((n, 0));
+    }
+
+    fn Fsync(
+        &self,
+        _task: &Task,
+        _f: &File,
+        _start: i64,
+        _end: i64,
+        _syncType: SyncType,
+    ) -> Result<()> {
+        return Err(Error::SysError(SysErr::EINVAL));
+    }
+
+    fn Flush(&self, _task: &Task, _f: &File) -> Result<()> {
+        return Ok(());
+    }
+
+    fn UnstableAttr(&self, task: &Task, f: &File) -> Result<UnstableAttr> {
+        let inode = f.Dirent.Inode();
+        return inode.UnstableAttr(task);
+    }
+
+    fn Ioctl(&self, task: &Task, _f: &File, _fd: i32, request: u64, val: u64) -> Result<()> {
+        let v: i32 = match request {
+            LibcConst::TIOCINQ => {
+                if self.IsTcp() {
+                    self.Tcp(|ep, _| ep.rcvBuf.len())
+                } else {
+                    self.Udp(|ep, _| ep.rcvQueue.front().map(|(_, d)| d.len()).unwrap_or(0))
+                }
+            }
+            LibcConst::TIOCOUTQ => {
+                if self.IsTcp() {
+                    self.Tcp(|ep, _| ep.sndBuf.len())
+                } else {
+                    0
+                }
+            }
+            _ => return Err(Error::SysError(SysErr::ENOTTY)),
+        } as i32;
+
+        task.CopyOutObj(&v, val)?;
+        return Ok(());
+    }
+
+    fn IterateDir(
+        &self,
+        _task: &Task,
+        _d: &Dirent,
+        _dirCtx: &mut DirCtx,
+        _offset: i32,
+    ) -> (i32, Result<i64>) {
+        return (0, Err(Error::SysError(SysErr::ENOTDIR)));
+    }
+
+    fn Mappable(&self) -> Result<MMappable> {
+        return Err(Error::SysError(SysErr::ENODEV));
+    }
+}
+
+impl SockOperations for NetstackSocketOperations {
+    fn Connect(&self, task: &Task, sockaddr: &[u8], blocking: bool) -> Result<i64> {
+        let id = self.id;
+        if !self.IsTcp() {
+            if sockaddr.len() >= 2 && u16::from_ne_bytes([sockaddr[0], sockaddr[1]]) == 0 {
+                NETSTACK.Run(|stack, _| stack.UdpConnect(id, None))?;
+                return Ok(0);
+            }
+
+            FIREWALL.CheckLocalConnect(sockaddr)?;
+
+            let remote = self.ParseAddr(sockaddr)?;
+            NETSTACK.Run(|stack, _| stack.UdpConnect(id, Some(remote)))?;
+            return Ok(0);
+        }
+
+        FIREWALL.CheckLocalConnect(sockaddr)?;
+        let remote = self.ParseAddr(sockaddr)?;
+        NETSTACK.Run(|stack, out| stack.TcpConnect(id, remote, out))?;
+        if !blocking {
+            return Err(Error::SysError(SysErr::EINPROGRESS));
+        }
+
+        self.Block(task, EVENT_OUT | EVENT_HUP | EVENT_ERR, None, || {
+            return self.Tcp(|ep, _| match ep.state {
+                TcpState::SynSent | TcpState::SynRcvd => Err(Error::SysError(SysErr::EWOULDBLOCK)),
+                TcpState::Closed => Err(Error::SysError(
+                    ep.error.take().unwrap_or(SysErr::ECONNREFUSED),
+                )),
+                _ => Ok(0),
+            });
+        })
+    }
+
+    fn Accept(
+        &self,
+        task: &Task,
+        addr: &mut [u8],
+        addrlen: &mut u32,
+        flags: i32,
+        blocking: bool,
+    ) -> Result<i64> {
+        if !self.IsTcp() {
+            return Err(Error::SysError(SysErr::EOPNOTSUPP));
+        }
+
+        let id = self.id;
+        let accept = || {
+            return NETSTACK.Run(|stack, _| {
+                match stack.tcpEps.get(&id) {
+                    Some(ep) if ep.state == TcpState::Listen => (),
+                    _ => return Err(Error::SysError(SysErr::EINVAL)),
+                }
+
+                loop {
+                    let child = match stack.Accept(id) {
+                        None => return Err(Error::SysError(SysErr::EWOULDBLOCK)),
+                        Some(c) => c,
+                    };
+
+                    // a queued connection may have been released since
+                    if let Some(ep) = stack.tcpEps.get(&child) {
+                        return Ok((child, ep.queue.clone(), ep.Remote()));
+                    }
+                }
+            });
+        };
+
+        let (child, queue, remote) = if blocking {
+            self.Block(task, EVENT_IN, None, accept)?
+        } else {
+            match accept() {
+                Err(Error::SysError(SysErr::EWOULDBLOCK)) => {
+                    return Err(Error::SysError(SysErr::EAGAIN))
+                }
+                ret => ret?,
+            }
+        };
+
+        let file = NewNetstackSocketFile(
+            task,
+            self.family,
+            self.stype,
+            child,
+            queue,
+            flags & SocketFlags::SOCK_NONBLOCK != 0,
+        )?;
+
+        let sockaddr = remote.ToSockAddr(self.family);
+        let data = sockaddr.ToVec()?;
+        // the connection is closed with the file if the peer is denied
+        FIREWALL.CheckAccept(&data)?;
+
+        if addr.len() > 0 {
+            let len = sockaddr.Len();
+            let n = core::cmp::min(addr.len(), len);
+            addr[..n].copy_from_slice(&data[..n]);
+            *addrlen = len as u32;
+        }
+
+        let fdFlags = FDFlags {
+            CloseOnExec: flags & SocketFlags::SOCK_CLOEXEC != 0,
+        };
+
+        let fd = task.NewFDFrom(0, &Arc::new(file), &fdFlags)?;
+        return Ok(fd as i64);
+    }
+
+    fn Bind(&self, _task: &Task, sockaddr: &[u8]) -> Result<i64> {
+        FIREWALL.CheckBind(sockaddr)?;
+        let addr = self.ParseAddr(sockaddr)?;
+        let id = self.id;
+        if self.IsTcp() {
+            NETSTACK.Run(|stack, _| stack.TcpBind(id, addr))?;
+        } else {
+            NETSTACK.Run(|stack, _| stack.UdpBind(id, addr))?;
+        }
+
+        return Ok(0);
+    }
+
+    fn Listen(&self, _task: &Task, backlog: i32) -> Result<i64> {
+        if !self.IsTcp() {
+            return Err(Error::SysError(SysErr::EOPNOTSUPP));
+        }
+
+        let id = self.id;
+        // an unbound socket gets its ephemeral port before the check
+        if self.Local().is_none() {
+            let any = FullAddr::New(IpAddr::Any(self.family == AFType::AF_INET6), 0);
+            NETSTACK.Run(|stack, _| stack.TcpBind(id, any))?;
+        }
+
+        if let Some(local) = self.Local() {
+            FIREWALL.CheckLocalListen(&local.ToSockAddr(self.family).ToVec()?)?;
+        }
+
+        let backlog = core::cmp::min(core::cmp::max(backlog, 0), SOMAXCONN) as usize;
+        NETSTACK.Run(|stack, _| stack.Listen(id, backlog))?;
+        return Ok(0);
+    }
+
+    fn Shutdown(&self, _task: &Task, how: i32) -> Result<i64> {
+        let (read, write) = match how as u64 {
+            LibcConst::SHUT_RD => (true, false),
+            LibcConst::SHUT_WR => (false, true),
+            LibcConst::SHUT_RDWR => (true, true),
+            _ => return Err(Error::SysError(SysErr::EINVAL)),
+        };
+
+        let id = self.id;
+        if self.IsTcp() {
+            NETSTACK.Run(|stack, out| stack.TcpShutdown(id, read, write, out))?;
+            return Ok(0);
+        }
+
+        self.Udp(|ep, out| {
+            if ep.remote.is_none() {
+                return Err(Error::SysError(SysErr::ENOTCONN));
+            }
+
+            ep.shutRead |= read;
+            ep.shutWrite |= write;
+            out.notify.push((ep.queue.clone(), READABLE_EVENT | WRITEABLE_EVENT));
+            return Ok(());
+        })?;
+
+        return Ok(0);
+    }
+
+    fn GetSockOpt(&self, _task: &Task, level: i32, name: i32, opt: &mut [u8]) -> Result<i64> {
+        let tcp = self.IsTcp();
+        let val = match (level as u64, name as u64) {
+            (LibcConst::SOL_SOCKET, LibcConst::SO_TYPE) => SockOptResult::I32(self.stype),
+            (LibcConst::SOL_SOCKET, LibcConst::SO_DOMAIN) => SockOptResult::I32(self.family),
+            (LibcConst::SOL_SOCKET, LibcConst::SO_PROTOCOL) => SockOptResult::I32(self.Protocol()),
+            (LibcConst::SOL_SOCKET, LibcConst::SO_ERROR) => {
+                SockOptResult::I32(self.TakeError().unwrap_or(0))
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_REUSEADDR) => {
+                let v = if tcp {
+                    self.Tcp(|ep, _| ep.reuseAddr)
+                } else {
+                    self.Udp(|ep, _| ep.reuseAddr)
+                };
+                SockOptResult::I32(v as i32)
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_KEEPALIVE) if tcp => {
+                SockOptResult::I32(self.Tcp(|ep, _| ep.keepAlive) as i32)
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_BROADCAST) if !tcp => {
+                SockOptResult::I32(self.Udp(|ep, _| ep.broadcast) as i32)
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_RCVBUF) => {
+                let v = if tcp {
+                    self.Tcp(|ep, _| ep.rcvBufSize)
+                } else {
+                    self.Udp(|ep, _| ep.rcvBufSize)
+                };
+                SockOptResult::I32(v as i32)
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_SNDBUF) => {
+                let v = if tcp {
+                    self.Tcp(|ep, _| ep.sndBufSize)
+                } else {
+                    self.Udp(|ep, _| ep.sndBufSize)
+                };
+                SockOptResult::I32(v as i32)
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_ACCEPTCONN) => {
+                let v = tcp && self.Tcp(|ep, _| ep.state == TcpState::Listen);
+                SockOptResult::I32(v as i32)
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_RCVTIMEO) => {
+                SockOptResult::Timeval(Timeval::FromNs(self.RecvTimeout()))
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_SNDTIMEO) => {
+                SockOptResult::Timeval(Timeval::FromNs(self.SendTimeout()))
+            }
+            (LibcConst::SOL_TCP, LibcConst::TCP_NODELAY) if tcp => {
+                SockOptResult::I32(self.Tcp(|ep, _| ep.nodelay) as i32)
+            }
+            (LibcConst::SOL_TCP, LibcConst::TCP_MAXSEG) if tcp => {
+                SockOptResult::I32(self.Tcp(|ep, _| ep.mss) as i32)
+            }
+            (LibcConst::SOL_IPV6, LibcConst::IPV6_V6ONLY) if self.family == AFType::AF_INET6 => {
+                let v = if tcp {
+                    self.Tcp(|ep, _| ep.v6only)
+                } else {
+                    self.Udp(|ep, _| ep.v6only)
+                };
+                SockOptResult::I32(v as i32)
+            }
+            _ => return Err(Error::SysError(SysErr::ENOPROTOOPT)),
+        };
+
+        let size = val.Marsh(opt)?;
+        return Ok(size as i64);
+    }
+
+    fn SetSockOpt(&self, task: &Task, level: i32, name: i32, opt: &[u8]) -> Result<i64> {
+        if level as u64 == LibcConst::SOL_SOCKET
+            && (name as u64 == LibcConst::SO_SNDTIMEO || name as u64 == LibcConst::SO_RCVTIMEO)
+        {
+            if opt.len() < SocketSize::SIZEOF_TIMEVAL {
+                return Err(Error::SysError(SysErr::EINVAL));
+            }
+
+            let timeVal = task.CopyInObj::<Timeval>(&opt[0] as *const _ as u64)?;
+            if name as u64 == LibcConst::SO_SNDTIMEO {
+                self.SetSendTimeout(timeVal.ToDuration() as i64);
+            } else {
+                self.SetRecvTimeout(timeVal.ToDuration() as i64);
+            }
+            return Ok(0);
+        }
+
+        if opt.len() < 4 {
+            return Err(Error::SysError(SysErr::EINVAL));
+        }
+
+        let v = i32::from_ne_bytes([opt[0], opt[1], opt[2], opt[3]]);
+        let tcp = self.IsTcp();
+        match (level as u64, name as u64) {
+            (LibcConst::SOL_SOCKET, LibcConst::SO_REUSEADDR) => {
+                if tcp {
+                    self.Tcp(|ep, _| ep.reuseAddr = v != 0);
+                } else {
+                    self.Udp(|ep, _| ep.reuseAddr = v != 0);
+                }
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_KEEPALIVE) if tcp => {
+                self.Tcp(|ep, _| ep.keepAlive = v != 0);
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_BROADCAST) if !tcp => {
+                self.Udp(|ep, _| ep.broadcast = v != 0);
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_RCVBUF) => {
+                // Linux doubles the value for the bookkeeping overhead
+                let size = (v.max(0) as usize * 2).max(TCP_MIN_BUF_SIZE).min(TCP_MAX_BUF_SIZE);
+                if tcp {
+                    self.Tcp(|ep, _| ep.rcvBufSize = size);
+                } else {
+                    self.Udp(|ep, _| ep.rcvBufSize = size);
+                }
+            }
+            (LibcConst::SOL_SOCKET, LibcConst::SO_SNDBUF) => {
+                let size = (v.max(0) as usize * 2).max(TCP_MIN_BUF_SIZE).min(TCP_MAX_BUF_SIZE);
+                if tcp {
+                    self.Tcp(|ep, _| ep.sndBufSize = size);
+                } else {
+                    self.Udp(|ep, _| ep.sndBufSize = size);
+                }
+            }
+            (LibcConst::SOL_TCP, LibcConst::TCP_NODELAY) if tcp => {
+                self.Tcp(|ep, out| {
+                    ep.nodelay = v != 0;
+                    ep.Output(out);
+                });
+            }
+            (LibcConst::SOL_IPV6, LibcConst::IPV6_V6ONLY) if self.family == AFType::AF_INET6 => {
+                let bound = self.Local().is_some();
+                if bound {
+                    return Err(Error::SysError(SysErr::EINVAL));
+                }
+
+                if tcp {
+                    self.Tcp(|ep, _| ep.v6only = v != 0);
+                } else {
+                    self.Udp(|ep, _| ep.v6only = v != 0);
+                }
+            }
+            // accepted without effect
+            (LibcConst::SOL_SOCKET, LibcConst::SO_REUSEPORT)
+            | (LibcConst::SOL_SOCKET, LibcConst::SO_LINGER)
+            | (LibcConst::SOL_TCP, LibcConst::TCP_KEEPIDLE)
+            | (LibcConst::SOL_TCP, LibcConst::TCP_KEEPINTVL)
+            | (LibcConst::SOL_TCP, LibcConst::TCP_KEEPCNT)
+            | (LibcConst::SOL_TCP, LibcConst::TCP_QUICKACK)
+            | (LibcConst::SOL_IP, LibcConst::IP_TOS)
+            | (LibcConst::SOL_IPV6, LibcConst::IPV6_TCLASS) => (),
+            _ => return Err(Error::SysError(SysErr::ENOPROTOOPT)),
+        }
+
+        return Ok(0);
+    }
+
+    fn GetSockName(&self, _task: &Task, socketaddr: &mut [u8]) -> Result<i64> {
+        let v6 = self.family == AFType::AF_INET6;
+        let local = self
+            .Local()
+            .unwrap_or(FullAddr::New(IpAddr::Any(v6), 0));
+        let data = local.ToSockAddr(self.family).ToVec()?;
+        let n = core::cmp::min(socketaddr.len(), data.len());
+        socketaddr[..n].copy_from_slice(&data[..n]);
+        return Ok(data.len() as i64);
+    }
+
+    fn GetPeerName(&self, _task: &Task, socketaddr: &mut [u8]) -> Result<i64> {
+        let remote = match self.Remote() {
+            None => return Err(Error::SysError(SysErr::ENOTCONN)),
+            Some(r) => r,
+        };
+
+        let data = remote.ToSockAddr(self.family).ToVec()?;
+        let n = core::cmp::min(socketaddr.len(), data.len());
+        socketaddr[..n].copy_from_slice(&data[..n]);
+        return Ok(data.len() as i64);
+    }
+
+    fn RecvMsg(
+        &self,
+        task: &Task,
+        dsts: &mut [IoVec],
+        flags: i32,
+        deadline: Option<Time>,
+        senderRequested: bool,
+        _controlDataLen: usize,
+    ) -> Result<(i64, i32, Option<(SockAddr, usize)>, Vec<u8>)> {
+        if flags
+            & !(MsgType::MSG_DONTWAIT
+                | MsgType::MSG_PEEK
+                | MsgType::MSG_TRUNC
+                | MsgType::MSG_ERRQUEUE
+                | MsgType::MSG_CTRUNC
+                | MsgType::MSG_WAITALL)
+            != 0
+        {
+            return Err(Error::SysError(SysErr::EINVAL));
+        }
+
+        // there is no error queue
+        if flags & MsgType::MSG_ERRQUEUE != 0 {
+            return Err(Error::SysError(SysErr::EAGAIN));
+        }
+
+        let size = IoVec::NumBytes(dsts);
+        let peek = flags & MsgType::MSG_PEEK != 0;
+        let dontwait = flags & MsgType::MSG_DONTWAIT != 0;
+
+        if self.IsTcp() {
+            let waitAll = flags & MsgType::MSG_WAITALL != 0 && !peek && !dontwait;
+            let mut data = Vec::new();
+            loop {
+                let want = size - data.len();
+                let ret = if dontwait {
+                    self.TcpRecv(want, peek)
+                } else {
+                    self.Block(task, EVENT_READ, deadline, || self.TcpRecv(want, peek))
+                };
+
+                let buf = match ret {
+                    Err(e) => {
+                        if data.len() > 0 {
+                            break;
+                        }
+                        return Err(e);
+                    }
+                    Ok(b) => b,
+                };
+
+                let eof = buf.len() == 0;
+                data.extend_from_slice(&buf);
+                if !waitAll || eof || data.len() >= size {
+                    break;
+                }
+            }
+
+            task.CopyDataOutToIovs(&data, dsts, false)?;
+            return Ok((data.len() as i64, 0, None, Vec::new()));
+        }
+
+        let (from, data) = if dontwait {
+            match self.UdpRecv(peek) {
+                Err(Error::SysError(SysErr::EWOULDBLOCK)) => {
+                    return Err(Error::SysError(SysErr::EAGAIN))
+                }
+                ret => ret?,
+            }
+        } else {
+            self.Block(task, EVENT_READ, deadline, || self.UdpRecv(peek))?
+        };
+
+        let len = core::cmp::min(size, data.len());
+        task.CopyDataOutToIovs(&data[..len], dsts, false)?;
+
+        let mut msgFlags = 0;
+        if len < data.len() {
+            msgFlags |= MsgType::MSG_TRUNC;
+        }
+
+        let ret = if flags & MsgType::MSG_TRUNC != 0 {
+            data.len()
+        } else {
+            len
+        };
+
+        let sender = if senderRequested {
+            let addr = from.ToSockAddr(self.family);
+            let l = addr.Len();
+            Some((addr, l))
+        } else {
+            None
+        };
+
+        return Ok((ret as i64, msgFlags, sender, Vec::new()));
+    }
+
+    fn SendMsg(
+        &self,
+        task: &Task,
+        srcs: &[IoVec],
+        flags: i32,
+        msgHdr: &mut MsgHdr,
+        deadline: Option<Time>,
+    ) -> Result<i64> {
+        if flags
+            & !(MsgType::MSG_DONTWAIT
+                | MsgType::MSG_EOR
+                | MsgType::MSG_FASTOPEN
+                | MsgType::MSG_MORE
+                | MsgType::MSG_NOSIGNAL)
+            != 0
+        {
+            return Err(Error::SysError(SysErr::EINVAL));
+        }
+
+        let size = IoVec::NumBytes(srcs);
+        let mut buf = DataBuff::New(size);
+        let len = task.CopyDataInFromIovs(&mut buf.buf, srcs, true)?;
+        let data = &buf.buf[..len];
+        let dontwait = flags & MsgType::MSG_DONTWAIT != 0;
+
+        if !self.IsTcp() {
+            let dst = if msgHdr.msgName != 0 && msgHdr.nameLen > 0 {
+                let name = task.CopyInVec(msgHdr.msgName, msgHdr.nameLen as usize)?;
+                FIREWALL.CheckLocalSendTo(&name)?;
+                Some(self.ParseAddr(&name)?)
+            } else {
+                None
+            };
+
+            return Ok(self.UdpSend(dst, data)? as i64);
+        }
+
+        // the address of a connected stream socket is ignored
+        let mut sent = 0;
+        while sent < len {
+            let ret = if dontwait {
+                self.TcpSend(&data[sent..])
+            } else {
+                self.Block(task, EVENT_WRITE, deadline, || self.TcpSend(&data[sent..]))
+            };
+
+            match ret {
+                Ok(n) => sent += n,
+                Err(e) => {
+                    if sent > 0 {
+                        break;
+                    }
+                    if dontwait && e == Error::SysError(SysErr::EWOULDBLOCK) {
+                        return Err(Error::SysError(SysErr::EAGAIN));
+                    }
+                    return Err(e);
+                }
+            }
+
+            if dontwait {
+                break;
+            }
+        }
+
+        return Ok(sent as i64);
+    }
+
+    fn SetRecvTimeout(&self, ns: i64) {
+        self.recv.store(ns, Ordering::Relaxed)
+    }
+
+    fn SetSendTimeout(&self, ns: i64) {
+        self.send.store(ns, Ordering::Relaxed)
+    }
+
+    fn RecvTimeout(&self) -> i64 {
+        return self.recv.load(Ordering::Relaxed);
+    }
+
+    fn SendTimeout(&self) -> i64 {
+        return self.send.load(Ordering::Relaxed);
+    }
+
+    fn State(&self) -> u32 {
+        if self.IsTcp() {
+            return self.Tcp(|ep, _| ep.state.LinuxState());
+        }
+
+        // udp sockets report TCP_ESTABLISHED once connected, TCP_CLOSE otherwise
+        if self.Remote().is_some() {
+            return TcpState::Established.LinuxState();
+        }
+        return TcpState::Closed.LinuxState();
+    }
+
+    fn Type(&self) -> (i32, i32, i32) {
+        return (self.family, self.stype, self.Protocol());
+    }
+}
+
+pub struct NetstackProvider {
+    pub family: i32,
+}
+
+impl Provider for NetstackProvider {
+    fn Socket(&self, task: &Task, stype: i32, protocol: i32) -> Result<Option<Arc<File>>> {
+        let nonblocking = stype & SocketFlags::SOCK_NONBLOCK != 0;
+        let stype = stype & SocketType::SOCK_TYPE_MASK;
+
+        match stype {
+            SockType::SOCK_STREAM => {
+                if protocol != 0 && protocol != IPPROTO_TCP as i32 {
+                    return Err(Error::SysError(SysErr::EPROTONOSUPPORT));
+                }
+            }
+            SockType::SOCK_DGRAM => {
+                if protocol != 0 && protocol != IPPROTO_UDP as i32 {
+                    return Err(Error::SysError(SysErr::EPROTONOSUPPORT));
+                }
+            }
+            // the host stack doesn't own the interface any more
+            _ => return Err(Error::SysError(SysErr::ESOCKTNOSUPPORT)),
+        }
+
+        // fall back to the host sockets when the link can't be taken over
+        if let Err(e) = NETSTACK.Start() {
+            error!("netstack: fail to start, use the host network: {:?}", e);
+            return Ok(None);
+        }
+
+        let v6 = self.family == AFType::AF_INET6;
+        let queue = Queue::default();
+        let q = queue.clone();
+        let id = NETSTACK.Run(|stack, _| {
+            if stype == SockType::SOCK_STREAM {
+                stack.NewTcpEndpoint(q, v6)
+            } else {
+                stack.NewUdpEndpoint(q, v6)
+            }
+        });
+
+        let file = NewNetstackSocketFile(task, self.family, stype, id, queue, nonblocking)?;
+        return Ok(Some(Arc::new(file)));
+    }
+
+    fn Pair(
+        &self,
+        _task: &Task,
+        _stype: i32,
+        _protocol: i32,
+    ) -> Result<Option<(Arc<File>, Arc<File>)>> {
+        return Err(Error::SysError(SysErr::EOPNOTSUPP));
+    }
+}
+
+pub fn Init() {
+    for family in [AFType::AF_INET, AFType::AF_INET6].iter() {
+        FAMILIAES
+            .write()
+            .RegisterProvider(*family, Box::new(NetstackProvider { family: *family }))
+    }
+}
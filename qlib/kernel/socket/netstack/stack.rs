@@ -0,0 +1,1411 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::vec::Vec;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::qmsg::qcall::NetstackNicInfo;
+use super::super::super::kernel::waiter::*;
+use super::header::*;
+use super::tcp::*;
+use super::udp::*;
+use super::*;
+
+pub const EPHEMERAL_PORT_START: u16 = 32768;
+pub const EPHEMERAL_PORT_END: u16 = 60999;
+
+// The loopback traffic isn't framed, only the ip total length limits it.
+pub const LOOPBACK_MTU: usize = 65535;
+
+// The packets waiting for the neighbor resolution are dropped after
+// NEIGHBOR_MAX_PROBES solicitations.
+pub const NEIGHBOR_MAX_PROBES: u32 = 3;
+pub const NEIGHBOR_PROBE_INTERVAL: i64 = 1_000_000_000;
+pub const NEIGHBOR_MAX_PENDING: usize = 16;
+
+const ARP_REQUEST: u16 = 1;
+const ARP_REPLY: u16 = 2;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+const ICMPV6_NEIGHBOR_SOLICIT: u8 = 135;
+const ICMPV6_NEIGHBOR_ADVERT: u8 = 136;
+const NDP_OPT_SOURCE_LINK_ADDR: u8 = 1;
+const NDP_OPT_TARGET_LINK_ADDR: u8 = 2;
+const NDP_FLAG_SOLICITED: u8 = 0x40;
+const NDP_FLAG_OVERRIDE: u8 = 0x20;
+
+pub struct PendingPackets {
+    pub packets: Vec<(u16, Vec<u8>)>,
+    pub probes: u32,
+    pub deadline: i64,
+}
+
+pub struct Stack {
+    pub fd: i32,
+    pub nic: NetstackNicInfo,
+    pub addr4: Option<IpAddr>,
+    pub gateway4: Option<IpAddr>,
+    pub addr6: Option<IpAddr>,
+    pub gateway6: Option<IpAddr>,
+    pub linkLocal6: IpAddr,
+
+    pub neighbors: BTreeMap<IpAddr, [u8; 6]>,
+    pub pending: BTreeMap<IpAddr, PendingPackets>,
+    pub ident: u16,
+
+    pub tcpEps: BTreeMap<u64, TcpEndpoint>,
+    // (local, remote) => endpoint id
+    pub tcpConns: BTreeMap<(FullAddr, FullAddr), u64>,
+    pub tcpListeners: BTreeMap<FullAddr, u64>,
+    pub udpEps: BTreeMap<u64, UdpEndpoint>,
+
+    pub nextId: u64,
+    pub nextPort: u16,
+    pub isnSecret: u64,
+}
+
+fn Mix(mut x: u64) -> u64 {
+    // splitmix64 finalizer
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    return x ^ (x >> 31);
+}
+
+// Covers returns whether the bound address a, of a socket with v6only, covers
+// the address b.
+fn Covers(a: &IpAddr, v6only: bool, b: &IpAddr) -> bool {
+    if !a.IsUnspecified() {
+        return a == b;
+    }
+
+    if a.IsV6() {
+        return !v6only || b.IsV6();
+    }
+
+    return !b.IsV6();
+}
+
+fn Overlaps(a: &IpAddr, aV6only: bool, b: &IpAddr, bV6only: bool) -> bool {
+    return Covers(a, aV6only, b) || Covers(b, bV6only, a);
+}
+
+fn MulticastMac(addr: &IpAddr) -> [u8; 6] {
+    match addr {
+        IpAddr::V4(a) => [0x01, 0x00, 0x5e, a[1] & 0x7f, a[2], a[3]],
+        IpAddr::V6(a) => [0x33, 0x33, a[12], a[13], a[14], a[15]],
+    }
+}
+
+fn SolicitedNode(addr: &IpAddr) -> IpAddr {
+    let b = addr.Bytes();
+    let mut v = [0u8; 16];
+    v[0] = 0xff;
+    v[1] = 0x02;
+    v[11] = 0x01;
+    v[12] = 0xff;
+    v[13..16].copy_from_slice(&b[13..16]);
+    return IpAddr::V6(v);
+}
+
+fn IsLinkLocal6(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V6(a) => a[0] == 0xfe && a[1] & 0xc0 == 0x80,
+        IpAddr::V4(_) => false,
+    }
+}
+
+impl Stack {
+    pub fn New(fd: i32, nic: NetstackNicInfo, isnSecret: u64) -> Self {
+        let addr4 = if nic.prefixLen > 0 {
+            Some(IpAddr::V4(nic.addr))
+        } else {
+            None
+        };
+        let gateway4 = if nic.gateway != [0; 4] {
+            Some(IpAddr::V4(nic.gateway))
+        } else {
+            None
+        };
+        let addr6 = if nic.prefixLen6 > 0 {
+            Some(IpAddr::V6(nic.addr6))
+        } else {
+            None
+        };
+        let gateway6 = if nic.gateway6 != [0; 16] {
+            Some(IpAddr::V6(nic.gateway6))
+        } else {
+            None
+        };
+
+        // EUI-64 link local address
+        let mac = nic.mac;
+        let linkLocal6 = IpAddr::V6([
+            0xfe, 0x80, 0, 0, 0, 0, 0, 0,
+            mac[0] ^ 0x02, mac[1], mac[2], 0xff, 0xfe, mac[3], mac[4], mac[5],
+        ]);
+
+        return Self {
+            fd: fd,
+            nic: nic,
+            addr4: addr4,
+            gateway4: gateway4,
+            addr6: addr6,
+            gateway6: gateway6,
+            linkLocal6: linkLocal6,
+            neighbors: BTreeMap::new(),
+            pending: BTreeMap::new(),
+            ident: 0,
+            tcpEps: BTreeMap::new(),
+            tcpConns: BTreeMap::new(),
+            tcpListeners: BTreeMap::new(),
+            udpEps: BTreeMap::new(),
+            nextId: 1,
+            nextPort: EPHEMERAL_PORT_START + (isnSecret % 1024) as u16,
+            isnSecret: isnSecret,
+        };
+    }
+
+    pub fn NewTcpEndpoint(&mut self, queue: Queue, v6: bool) -> u64 {
+        let id = self.NextId();
+        self.tcpEps.insert(id, TcpEndpoint::New(id, queue, v6));
+        return id;
+    }
+
+    pub fn NewUdpEndpoint(&mut self, queue: Queue, v6: bool) -> u64 {
+        let id = self.NextId();
+        self.udpEps.insert(id, UdpEndpoint::New(id, queue, v6));
+        return id;
+    }
+
+    // TcpEp returns the tcp endpoint id, an endpoint released by the stack
+    // (e.g. reset while its socket is being closed) is EBADF.
+    fn TcpEp(&self, id: u64) -> Result<&TcpEndpoint> {
+        match self.tcpEps.get(&id) {
+            None => return Err(Error::SysError(SysErr::EBADF)),
+            Some(ep) => return Ok(ep),
+        }
+    }
+
+    fn TcpEpMut(&mut self, id: u64) -> Result<&mut TcpEndpoint> {
+        match self.tcpEps.get_mut(&id) {
+            None => return Err(Error::SysError(SysErr::EBADF)),
+            Some(ep) => return Ok(ep),
+        }
+    }
+
+    fn UdpEp(&self, id: u64) -> Result<&UdpEndpoint> {
+        match self.udpEps.get(&id) {
+            None => return Err(Error::SysError(SysErr::EBADF)),
+            Some(ep) => return Ok(ep),
+        }
+    }
+
+    fn UdpEpMut(&mut self, id: u64) -> Result<&mut UdpEndpoint> {
+        match self.udpEps.get_mut(&id) {
+            None => return Err(Error::SysError(SysErr::EBADF)),
+            Some(ep) => return Ok(ep),
+        }
+    }
+
+    fn NextId(&mut self) -> u64 {
+        let id = self.nextId;
+        self.nextId += 1;
+        return id;
+    }
+
+    // IsLocalAddr returns whether addr is an address of the sandbox, the
+    // packets to them are delivered without going through the link.
+    pub fn IsLocalAddr(&self, addr: &IpAddr) -> bool {
+        if addr.IsLoopback() || *addr == self.linkLocal6 {
+            return true;
+        }
+
+        return Some(*addr) == self.addr4 || Some(*addr) == self.addr6;
+    }
+
+    // SourceAddr returns the address the packets to dst are sent from.
+    pub fn SourceAddr(&self, dst: &IpAddr) -> Result<IpAddr> {
+        if dst.IsLoopback() {
+            if dst.IsV6() {
+                return Ok(IPV6_LOOPBACK);
+            }
+            return Ok(IPV4_LOOPBACK);
+        }
+
+        if self.IsLocalAddr(dst) {
+            return Ok(*dst);
+        }
+
+        if dst.IsV6() {
+            if IsLinkLocal6(dst) || (dst.IsMulticast() && self.addr6.is_none()) {
+                return Ok(self.linkLocal6);
+            }
+            return self.addr6.ok_or(Error::SysError(SysErr::ENETUNREACH));
+        }
+
+        return self.addr4.ok_or(Error::SysError(SysErr::ENETUNREACH));
+    }
+
+    pub fn Mtu(&self, dst: &IpAddr) -> usize {
+        if self.IsLocalAddr(dst) {
+            return LOOPBACK_MTU;
+        }
+
+        return self.nic.mtu as usize;
+    }
+
+    pub fn Mss(&self, dst: &IpAddr) -> u32 {
+        let mss = self.Mtu(dst) - IpHeaderSize(dst) - TCP_HEADER_SIZE;
+        // the segments carry the sack blocks on top of mss bytes of data and
+        // they are not fragmented, leave room for the options
+        return (mss - 40) as u32;
+    }
+
+    // Isn returns the initial sequence number of the connection as in RFC 6528.
+    pub fn Isn(&self, local: &FullAddr, remote: &FullAddr, now: i64) -> u32 {
+        let mut h = self.isnSecret;
+        for b in local.addr.Bytes().iter().chain(remote.addr.Bytes().iter()) {
+            h = Mix(h ^ *b as u64);
+        }
+        h = Mix(h ^ ((local.port as u64) << 16 | remote.port as u64));
+        return (h as u32).wrapping_add((now / 4000) as u32);
+    }
+
+    // PortInUse returns whether binding addr conflicts with the other
+    // endpoints of the protocol.
+    fn PortInUse(&self, tcp: bool, id: u64, addr: &FullAddr, reuse: bool, v6only: bool) -> bool {
+        if tcp {
+            for (eid, ep) in &self.tcpEps {
+                if *eid == id || ep.parent.is_some() {
+                    continue;
+                }
+
+                let local = match &ep.local {
+                    Some(l) if l.port == addr.port => l,
+                    _ => continue,
+                };
+
+                if reuse && ep.reuseAddr && ep.state != TcpState::Listen {
+                    continue;
+                }
+
+                if Overlaps(&local.addr, ep.v6only, &addr.addr, v6only) {
+                    return true;
+                }
+            }
+
+            // the accepted connections hold the port of their listener
+            return false;
+        }
+
+        for (eid, ep) in &self.udpEps {
+            if *eid == id {
+                continue;
+            }
+
+            let local = match &ep.local {
+                Some(l) if l.port == addr.port => l,
+                _ => continue,
+            };
+
+            if reuse && ep.reuseAddr {
+                continue;
+            }
+
+            if Overlaps(&local.addr, ep.v6only, &addr.addr, v6only) {
+                return true;
+            }
+        }
+
+        return false;
+    }
+
+    fn PickPort(&mut self, tcp: bool, id: u64, addr: &IpAddr, v6only: bool) -> Result<u16> {
+        let count = (EPHEMERAL_PORT_END - EPHEMERAL_PORT_START + 1) as u32;
+        for _ in 0..count {
+            let port = self.nextPort;
+            self.nextPort = if port >= EPHEMERAL_PORT_END {
+                EPHEMERAL_PORT_START
+            } else {
+                port + 1
+            };
+
+            if !self.PortInUse(tcp, id, &FullAddr::New(*addr, port), false, v6only) {
+                return Ok(port);
+            }
+        }
+
+        return Err(Error::SysError(SysErr::EADDRINUSE));
+    }
+
+    fn CheckBindAddr(&self, v6: bool, v6only: bool, addr: &IpAddr) -> Result<()> {
+        if addr.IsV6() && !v6 {
+            return Err(Error::SysError(SysErr::EAFNOSUPPORT));
+        }
+
+        if v6 && v6only && !addr.IsV6() {
+            return Err(Error::SysError(SysErr::EADDRNOTAVAIL));
+        }
+
+        if !addr.IsUnspecified() && !addr.IsMulticast() && !addr.IsBroadcast() && !self.IsLocalAddr(addr) {
+            return Err(Error::SysError(SysErr::EADDRNOTAVAIL));
+        }
+
+        return Ok(());
+    }
+
+    pub fn TcpBind(&mut self, id: u64, addr: FullAddr) -> Result<()> {
+        let (v6, v6only, reuse) = {
+            let ep = self.TcpEp(id)?;
+            if ep.local.is_some() {
+                return Err(Error::SysError(SysErr::EINVAL));
+            }
+            (ep.v6, ep.v6only, ep.reuseAddr)
+        };
+
+        self.CheckBindAddr(v6, v6only, &addr.addr)?;
+        let port = if addr.port == 0 {
+            self.PickPort(true, id, &addr.addr, v6only)?
+        } else {
+            if self.PortInUse(true, id, &addr, reuse, v6only) {
+                return Err(Error::SysError(SysErr::EADDRINUSE));
+            }
+            addr.port
+        };
+
+        self.TcpEpMut(id)?.local = Some(FullAddr::New(addr.addr, port));
+        return Ok(());
+    }
+
+    pub fn UdpBind(&mut self, id: u64, addr: FullAddr) -> Result<()> {
+        let (v6, v6only, reuse) = {
+            let ep = self.UdpEp(id)?;
+            if ep.local.is_some() {
+                return Err(Error::SysError(SysErr::EINVAL));
+            }
+            (ep.v6, ep.v6only, ep.reuseAddr)
+        };
+
+        self.CheckBindAddr(v6, v6only, &addr.addr)?;
+        let port = if addr.port == 0 {
+            self.PickPort(false, id, &addr.addr, v6only)?
+        } else {
+            if self.PortInUse(false, id, &addr, reuse, v6only) {
+                return Err(Error::SysError(SysErr::EADDRINUSE));
+            }
+            addr.port
+        };
+
+        self.UdpEpMut(id)?.local = Some(FullAddr::New(addr.addr, port));
+        return Ok(());
+    }
+
+    pub fn Listen(&mut self, id: u64, backlog: usize) -> Result<()> {
+        let (state, local, v6) = {
+            let ep = self.TcpEp(id)?;
+            (ep.state, ep.local, ep.v6)
+        };
+
+        match state {
+            TcpState::Listen => {
+                self.TcpEpMut(id)?.backlog = backlog;
+                return Ok(());
+            }
+            TcpState::Closed => (),
+            _ => return Err(Error::SysError(SysErr::EINVAL)),
+        }
+
+        let local = match local {
+            Some(l) => l,
+            None => {
+                self.TcpBind(id, FullAddr::New(IpAddr::Any(v6), 0))?;
+                self.TcpEp(id)?.Local()
+            }
+        };
+
+        if self.tcpListeners.contains_key(&local) {
+            return Err(Error::SysError(SysErr::EADDRINUSE));
+        }
+
+        let ep = self.TcpEpMut(id)?;
+        ep.state = TcpState::Listen;
+        ep.backlog = backlog;
+        self.tcpListeners.insert(local, id);
+        return Ok(());
+    }
+
+    pub fn TcpConnect(&mut self, id: u64, remote: FullAddr, out: &mut Output) -> Result<()> {
+        let (state, local, v6, v6only, connected) = {
+            let ep = self.TcpEp(id)?;
+            (ep.state, ep.local, ep.v6, ep.v6only, ep.connected)
+        };
+
+        match state {
+            TcpState::Closed => {
+                if connected {
+                    return Err(Error::SysError(SysErr::EISCONN));
+                }
+            }
+            TcpState::SynSent | TcpState::SynRcvd => {
+                return Err(Error::SysError(SysErr::EALREADY))
+            }
+            TcpState::Listen => return Err(Error::SysError(SysErr::EINVAL)),
+            _ => return Err(Error::SysError(SysErr::EISCONN)),
+        }
+
+        // connecting to the unspecified address connects to the loopback
+        let mut remote = remote;
+        if remote.addr.IsUnspecified() {
+            remote.addr = if remote.addr.IsV6() {
+                IPV6_LOOPBACK
+            } else {
+                IPV4_LOOPBACK
+            };
+        }
+
+        if remote.addr.IsMulticast() || remote.addr.IsBroadcast() {
+            return Err(Error::SysError(SysErr::ENETUNREACH));
+        }
+
+        if v6 && v6only && !remote.addr.IsV6() {
+            return Err(Error::SysError(SysErr::ENETUNREACH));
+        }
+
+        let src = match local {
+            Some(l) if !l.addr.IsUnspecified() => {
+                if l.addr.IsV6() != remote.addr.IsV6() {
+                    return Err(Error::SysError(SysErr::EAFNOSUPPORT));
+                }
+                l.addr
+            }
+            _ => self.SourceAddr(&remote.addr)?,
+        };
+
+        let port = match local {
+            Some(l) => l.port,
+            None => self.PickPort(true, id, &src, v6only)?,
+        };
+
+        let local = FullAddr::New(src, port);
+        if self.tcpConns.contains_key(&(local, remote)) {
+            return Err(Error::SysError(SysErr::EADDRNOTAVAIL));
+        }
+
+        let iss = self.Isn(&local, &remote, out.now);
+        let mss = self.Mss(&remote.addr);
+        self.tcpConns.insert((local, remote), id);
+        let ep = self.TcpEpMut(id)?;
+        ep.local = Some(local);
+        ep.remote = Some(remote);
+        ep.error = None;
+        ep.Connect(iss, mss, out);
+        return Ok(());
+    }
+
+    // Accept returns the id of a connection established on the listener.
+    pub fn Accept(&mut self, id: u64) -> Option<u64> {
+        let child = self.tcpEps.get_mut(&id)?.acceptQueue.pop_front()?;
+        if let Some(ep) = self.tcpEps.get_mut(&child) {
+            ep.parent = None;
+        }
+
+        return Some(child);
+    }
+
+    pub fn UdpConnect(&mut self, id: u64, remote: Option<FullAddr>) -> Result<()> {
+        let remote = match remote {
+            None => {
+                // AF_UNSPEC dissolves the association
+                self.UdpEpMut(id)?.remote = None;
+                return Ok(());
+            }
+            Some(r) => r,
+        };
+
+        let (local, v6, v6only) = {
+            let ep = self.UdpEp(id)?;
+            (ep.local, ep.v6, ep.v6only)
+        };
+
+        if v6 && v6only && !remote.addr.IsV6() {
+            return Err(Error::SysError(SysErr::ENETUNREACH));
+        }
+
+        let src = match local {
+            Some(l) if !l.addr.IsUnspecified() => l.addr,
+            _ => self.SourceAddr(&remote.addr)?,
+        };
+
+        let port = match local {
+            Some(l) => l.port,
+            None => self.PickPort(false, id, &src, v6only)?,
+        };
+
+        let ep = self.UdpEpMut(id)?;
+        ep.local = Some(FullAddr::New(src, port));
+        ep.remote = Some(remote);
+        return Ok(());
+    }
+
+    pub fn UdpSend(&mut self, id: u64, dst: Option<FullAddr>, data: &[u8], out: &mut Output) -> Result<usize> {
+        let (local, remote, v6, v6only, broadcast, shutWrite) = {
+            let ep = self.UdpEp(id)?;
+            (ep.local, ep.remote, ep.v6, ep.v6only, ep.broadcast, ep.shutWrite)
+        };
+
+        if shutWrite {
+            return Err(Error::SysError(SysErr::EPIPE));
+        }
+
+        let dst = match dst.or(remote) {
+            None => return Err(Error::SysError(SysErr::EDESTADDRREQ)),
+            Some(d) => d,
+        };
+
+        if dst.port == 0 {
+            return Err(Error::SysError(SysErr::EINVAL));
+        }
+
+        if v6 && v6only && !dst.addr.IsV6() {
+            return Err(Error::SysError(SysErr::ENETUNREACH));
+        }
+
+        let subnetBroadcast = match (self.addr4, &dst.addr) {
+            (Some(IpAddr::V4(a)), IpAddr::V4(d)) => {
+                let host = 32 - self.nic.prefixLen;
+                host > 1 && dst.addr.InSubnet(&IpAddr::V4(a), self.nic.prefixLen)
+                    && (u32::from_be_bytes(*d) & ((1u32 << host) - 1)) == (1u32 << host) - 1
+            }
+            _ => false,
+        };
+        if (dst.addr.IsBroadcast() || subnetBroadcast) && !broadcast {
+            return Err(Error::SysError(SysErr::EACCES));
+        }
+
+        if data.len() > UDP_MAX_PAYLOAD {
+            return Err(Error::SysError(SysErr::EMSGSIZE));
+        }
+
+        let src = match local {
+            Some(l) if !l.addr.IsUnspecified() && l.addr.IsV6() == dst.addr.IsV6() => l.addr,
+            _ => self.SourceAddr(&dst.addr)?,
+        };
+
+        let port = match local {
+            Some(l) => l.port,
+            None => {
+                let port = self.PickPort(false, id, &IpAddr::Any(v6), v6only)?;
+                self.UdpEpMut(id)?.local = Some(FullAddr::New(IpAddr::Any(v6), port));
+                port
+            }
+        };
+
+        // the datagrams are not fragmented
+        if IpHeaderSize(&dst.addr) + UDP_HEADER_SIZE + data.len() > self.Mtu(&dst.addr) {
+            return Err(Error::SysError(SysErr::EMSGSIZE));
+        }
+
+        let src = FullAddr::New(src, port);
+        let packet = UdpPacket(&src, &dst, data);
+        out.Send(src.addr, dst.addr, IPPROTO_UDP, packet);
+        return Ok(data.len());
+    }
+
+    pub fn TcpShutdown(&mut self, id: u64, read: bool, write: bool, out: &mut Output) -> Result<()> {
+        let transition = {
+            let ep = self.TcpEpMut(id)?;
+            match ep.state {
+                TcpState::Listen => return Ok(()),
+                TcpState::Closed | TcpState::SynSent if !ep.connected => {
+                    return Err(Error::SysError(SysErr::ENOTCONN))
+                }
+                _ => (),
+            }
+
+            if read {
+                ep.shutRead = true;
+                out.notify.push((ep.queue.clone(), READABLE_EVENT));
+            }
+
+            if write && !ep.finQueued {
+                ep.Shutdown(out)
+            } else {
+                Transition::None
+            }
+        };
+
+        self.OnTransition(id, transition, out);
+        return Ok(());
+    }
+
+    // Close releases the endpoint of a closed socket, a connection being
+    // shutdown stays until it reaches Closed.
+    pub fn Close(&mut self, id: u64, out: &mut Output) {
+        if self.udpEps.remove(&id).is_some() {
+            return;
+        }
+
+        let (state, unread) = match self.tcpEps.get(&id) {
+            None => return,
+            Some(ep) => (ep.state, ep.rcvBuf.len() > 0),
+        };
+
+        if state == TcpState::Listen {
+            let children: Vec<u64> = self
+                .tcpEps
+                .iter()
+                .filter(|(_, ep)| ep.parent == Some(id))
+                .map(|(cid, _)| *cid)
+                .collect();
+            for cid in children {
+                if let Some(mut child) = self.tcpEps.remove(&cid) {
+                    child.Abort(out);
+                    self.tcpConns.remove(&(child.Local(), child.Remote()));
+                }
+            }
+
+            if let Some(ep) = self.tcpEps.remove(&id) {
+                self.tcpListeners.remove(&ep.Local());
+            }
+            return;
+        }
+
+        let transition = {
+            let ep = match self.tcpEps.get_mut(&id) {
+                None => return,
+                Some(ep) => ep,
+            };
+            ep.orphan = true;
+            if state == TcpState::Closed {
+                Transition::Closed
+            } else if unread {
+                ep.Abort(out)
+            } else {
+                ep.Shutdown(out)
+            }
+        };
+
+        self.OnTransition(id, transition, out);
+    }
+
+    fn OnTransition(&mut self, id: u64, transition: Transition, out: &mut Output) {
+        match transition {
+            Transition::None => (),
+            Transition::Established => {
+                let parent = match self.tcpEps.get(&id).and_then(|ep| ep.parent) {
+                    None => return,
+                    Some(p) => p,
+                };
+
+                match self.tcpEps.get_mut(&parent) {
+                    Some(l) if l.state == TcpState::Listen => {
+                        l.synQueue -= 1;
+                        l.acceptQueue.push_back(id);
+                        out.notify.push((l.queue.clone(), READABLE_EVENT));
+                    }
+                    _ => {
+                        if let Some(ep) = self.tcpEps.get_mut(&id) {
+                            let t = ep.Abort(out);
+                            self.OnTransition(id, t, out);
+                        }
+                    }
+                }
+            }
+            Transition::Closed => {
+                let (local, remote, parent, connected, orphan) = match self.tcpEps.get(&id) {
+                    None => return,
+                    Some(ep) => (ep.local, ep.remote, ep.parent, ep.connected, ep.orphan),
+                };
+
+                if let (Some(l), Some(r)) = (local, remote) {
+                    if self.tcpConns.get(&(l, r)) == Some(&id) {
+                        self.tcpConns.remove(&(l, r));
+                    }
+                }
+
+                // a connection reset before the handshake completes leaves the
+                // syn queue, an established one is still returned by accept
+                let mut release = orphan;
+                if let Some(p) = parent {
+                    if !connected {
+                        if let Some(l) = self.tcpEps.get_mut(&p) {
+                            l.synQueue -= 1;
+                        }
+                        release = true;
+                    }
+                }
+
+                if release {
+                    self.tcpEps.remove(&id);
+                }
+            }
+        }
+    }
+
+    // Tick runs the tcp timers and the neighbor probes.
+    pub fn Tick(&mut self, out: &mut Output) {
+        let ids: Vec<u64> = self.tcpEps.keys().copied().collect();
+        for id in ids {
+            let remote = match self.tcpEps.get(&id) {
+                Some(ep) if ep.state != TcpState::Closed && ep.state != TcpState::Listen => {
+                    ep.Remote()
+                }
+                _ => continue,
+            };
+
+            let mss = self.Mss(&remote.addr);
+            let transition = match self.tcpEps.get_mut(&id) {
+                None => continue,
+                Some(ep) => ep.OnTick(mss, out),
+            };
+
+            self.OnTransition(id, transition, out);
+        }
+
+        let now = out.now;
+        let expired: Vec<IpAddr> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| p.deadline <= now)
+            .map(|(a, _)| *a)
+            .collect();
+        for addr in expired {
+            let retry = {
+                let p = self.pending.get_mut(&addr).unwrap();
+                p.probes += 1;
+                p.deadline = now + NEIGHBOR_PROBE_INTERVAL;
+                p.probes < NEIGHBOR_MAX_PROBES
+            };
+
+            if retry {
+                self.Solicit(&addr, out);
+            } else {
+                self.pending.remove(&addr);
+            }
+        }
+    }
+
+    // Drain routes the packets sent while the stack is locked, the packets
+    // to the local addresses are delivered directly.
+    pub fn Drain(&mut self, out: &mut Output) {
+        while let Some((src, dst, protocol, payload)) = out.packets.pop_front() {
+            if self.IsLocalAddr(&dst) {
+                self.DeliverIp(src, dst, protocol, &payload, out);
+                continue;
+            }
+
+            self.SendIp(&src, &dst, protocol, &payload, out);
+        }
+    }
+
+    fn NextHop(&self, dst: &IpAddr) -> Option<IpAddr> {
+        if dst.IsV6() {
+            if IsLinkLocal6(dst) {
+                return Some(*dst);
+            }
+
+            if let Some(a) = &self.addr6 {
+                if dst.InSubnet(a, self.nic.prefixLen6) {
+                    return Some(*dst);
+                }
+            }
+
+            return self.gateway6;
+        }
+
+        if let Some(a) = &self.addr4 {
+            if dst.InSubnet(a, self.nic.prefixLen) {
+                return Some(*dst);
+            }
+        }
+
+        return self.gateway4;
+    }
+
+    fn SendIp(&mut self, src: &IpAddr, dst: &IpAddr, protocol: u8, payload: &[u8], out: &mut Output) {
+        let len = IpHeaderSize(dst) + payload.len();
+        if len > self.nic.mtu as usize {
+            return;
+        }
+
+        let mut packet = Vec::with_capacity(len);
+        let ident = self.ident;
+        self.ident = self.ident.wrapping_add(1);
+        if IpHeader(&mut packet, src, dst, protocol, payload.len(), ident).is_err() {
+            error!(
+                "netstack: drop packet {:?} -> {:?}, address family mismatch",
+                src, dst
+            );
+            return;
+        }
+        packet.extend_from_slice(payload);
+
+        let ethType = if dst.IsV6() { ETH_P_IPV6 } else { ETH_P_IP };
+        if dst.IsMulticast() {
+            self.SendFrame(&MulticastMac(dst), ethType, &packet, out);
+            return;
+        }
+
+        if dst.IsBroadcast() {
+            self.SendFrame(&BROADCAST_MAC, ethType, &packet, out);
+            return;
+        }
+
+        let hop = match self.NextHop(dst) {
+            None => return,
+            Some(h) => h,
+        };
+
+        if let Some(mac) = self.neighbors.get(&hop) {
+            let mac = *mac;
+            self.SendFrame(&mac, ethType, &packet, out);
+            return;
+        }
+
+        let solicit = match self.pending.get_mut(&hop) {
+            Some(p) => {
+                if p.packets.len() < NEIGHBOR_MAX_PENDING {
+                    p.packets.push((ethType, packet));
+                }
+                false
+            }
+            None => {
+                self.pending.insert(
+                    hop,
+                    PendingPackets {
+                        packets: vec![(ethType, packet)],
+                        probes: 0,
+                        deadline: out.now + NEIGHBOR_PROBE_INTERVAL,
+                    },
+                );
+                true
+            }
+        };
+
+        if solicit {
+            self.Solicit(&hop, out);
+        }
+    }
+
+    fn SendFrame(&self, dst: &[u8; 6], ethType: u16, packet: &[u8], out: &mut Output) {
+        let mut frame = EthernetHeader(dst, &self.nic.mac, ethType, packet.len());
+        frame.extend_from_slice(packet);
+        out.frames.push(frame);
+    }
+
+    // Solicit sends an arp request or a neighbor solicitation for addr.
+    fn Solicit(&mut self, addr: &IpAddr, out: &mut Output) {
+        match addr {
+            IpAddr::V4(target) => {
+                let spa = match self.addr4 {
+                    Some(IpAddr::V4(a)) => a,
+                    _ => return,
+                };
+
+                let arp = self.ArpPacket(ARP_REQUEST, &[0; 6], &spa, target);
+                self.SendFrame(&BROADCAST_MAC, ETH_P_ARP, &arp, out);
+            }
+            IpAddr::V6(target) => {
+                let src = if IsLinkLocal6(addr) {
+                    self.linkLocal6
+                } else {
+                    self.addr6.unwrap_or(self.linkLocal6)
+                };
+
+                let mut ns = vec![0u8; 8];
+                ns[0] = ICMPV6_NEIGHBOR_SOLICIT;
+                ns.extend_from_slice(target);
+                ns.extend_from_slice(&[NDP_OPT_SOURCE_LINK_ADDR, 1]);
+                ns.extend_from_slice(&self.nic.mac);
+                let dst = SolicitedNode(addr);
+                let csum = TransportChecksum(&src, &dst, IPPROTO_ICMPV6, &ns);
+                PutU16(&mut ns, 2, csum);
+                out.Send(src, dst, IPPROTO_ICMPV6, ns);
+            }
+        }
+    }
+
+    fn ArpPacket(&self, op: u16, tha: &[u8; 6], spa: &[u8; 4], tpa: &[u8; 4]) -> Vec<u8> {
+        let mut arp = vec![0u8; ARP_SIZE];
+        PutU16(&mut arp, 0, 1);
+        PutU16(&mut arp, 2, ETH_P_IP);
+        arp[4] = 6;
+        arp[5] = 4;
+        PutU16(&mut arp, 6, op);
+        arp[8..14].copy_from_slice(&self.nic.mac);
+        arp[14..18].copy_from_slice(spa);
+        arp[18..24].copy_from_slice(tha);
+        arp[24..28].copy_from_slice(tpa);
+        return arp;
+    }
+
+    // Learn records the link address of addr and sends the packets waiting
+    // for it.
+    fn Learn(&mut self, addr: IpAddr, mac: [u8; 6], out: &mut Output) {
+        self.neighbors.insert(addr, mac);
+        if let Some(p) = self.pending.remove(&addr) {
+            for (ethType, packet) in p.packets {
+                self.SendFrame(&mac, ethType, &packet, out);
+            }
+        }
+    }
+
+    pub fn DeliverFrame(&mut self, frame: &[u8], out: &mut Output) {
+        if frame.len() < ETHERNET_HEADER_SIZE {
+            return;
+        }
+
+        let dst = &frame[0..6];
+        if dst != &self.nic.mac[..] && dst[0] & 1 == 0 {
+            return;
+        }
+
+        let payload = &frame[ETHERNET_HEADER_SIZE..];
+        match GetU16(frame, 12) {
+            ETH_P_ARP => self.HandleArp(payload, out),
+            ETH_P_IP => {
+                if let Some(p) = ParseIpv4(payload) {
+                    if self.IsLocalAddr(&p.dst) || p.dst.IsBroadcast() || p.dst.IsMulticast() {
+                        self.DeliverIp(p.src, p.dst, p.protocol, p.payload, out);
+                    }
+                }
+            }
+            ETH_P_IPV6 => {
+                if let Some(p) = ParseIpv6(payload) {
+                    if self.IsLocalAddr(&p.dst) || p.dst.IsMulticast() {
+                        self.DeliverIp(p.src, p.dst, p.protocol, p.payload, out);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn HandleArp(&mut self, arp: &[u8], out: &mut Output) {
+        if arp.len() < ARP_SIZE || GetU16(arp, 0) != 1 || GetU16(arp, 2) != ETH_P_IP {
+            return;
+        }
+
+        let mut sha = [0u8; 6];
+        let mut spa = [0u8; 4];
+        let mut tpa = [0u8; 4];
+        sha.copy_from_slice(&arp[8..14]);
+        spa.copy_from_slice(&arp[14..18]);
+        tpa.copy_from_slice(&arp[24..28]);
+
+        if spa != [0; 4] {
+            self.Learn(IpAddr::V4(spa), sha, out);
+        }
+
+        if GetU16(arp, 6) == ARP_REQUEST && Some(IpAddr::V4(tpa)) == self.addr4 {
+            let reply = self.ArpPacket(ARP_REPLY, &sha, &tpa, &spa);
+            self.SendFrame(&sha, ETH_P_ARP, &reply, out);
+        }
+    }
+
+    fn DeliverIp(&mut self, src: IpAddr, dst: IpAddr, protocol: u8, payload: &[u8], out: &mut Output) {
+        match protocol {
+            IPPROTO_TCP => self.HandleTcp(src, dst, payload, out),
+            IPPROTO_UDP => self.HandleUdp(src, dst, payload, out),
+            IPPROTO_ICMP => self.HandleIcmp(src, dst, payload, out),
+            IPPROTO_ICMPV6 => self.HandleIcmpv6(src, dst, payload, out),
+            _ => (),
+        }
+    }
+
+    fn HandleIcmp(&mut self, src: IpAddr, dst: IpAddr, payload: &[u8], out: &mut Output) {
+        if payload.len() < 8 || FinishChecksum(Checksum(payload, 0)) != 0 {
+            return;
+        }
+
+        if payload[0] != ICMP_ECHO_REQUEST || dst.IsBroadcast() || dst.IsMulticast() {
+            return;
+        }
+
+        let mut reply = payload.to_vec();
+        reply[0] = ICMP_ECHO_REPLY;
+        PutU16(&mut reply, 2, 0);
+        let csum = FinishChecksum(Checksum(&reply, 0));
+        PutU16(&mut reply, 2, csum);
+        out.Send(dst, src, IPPROTO_ICMP, reply);
+    }
+
+    fn HandleIcmpv6(&mut self, src: IpAddr, dst: IpAddr, payload: &[u8], out: &mut Output) {
+        if payload.len() < 8 || TransportChecksum(&src, &dst, IPPROTO_ICMPV6, payload) != 0 {
+            return;
+        }
+
+        match payload[0] {
+            ICMPV6_ECHO_REQUEST => {
+                if dst.IsMulticast() {
+                    return;
+                }
+
+                let mut reply = payload.to_vec();
+                reply[0] = ICMPV6_ECHO_REPLY;
+                PutU16(&mut reply, 2, 0);
+                let csum = TransportChecksum(&dst, &src, IPPROTO_ICMPV6, &reply);
+                PutU16(&mut reply, 2, csum);
+                out.Send(dst, src, IPPROTO_ICMPV6, reply);
+            }
+            ICMPV6_NEIGHBOR_SOLICIT | ICMPV6_NEIGHBOR_ADVERT => {
+                if payload.len() < 24 {
+                    return;
+                }
+
+                let mut t = [0u8; 16];
+                t.copy_from_slice(&payload[8..24]);
+                let target = IpAddr::V6(t);
+                let wantOpt = if payload[0] == ICMPV6_NEIGHBOR_SOLICIT {
+                    NDP_OPT_SOURCE_LINK_ADDR
+                } else {
+                    NDP_OPT_TARGET_LINK_ADDR
+                };
+
+                let mut mac = None;
+                let mut opts = &payload[24..];
+                while opts.len() >= 8 && opts[1] > 0 {
+                    let len = opts[1] as usize * 8;
+                    if len > opts.len() {
+                        break;
+                    }
+                    if opts[0] == wantOpt {
+                        let mut m = [0u8; 6];
+                        m.copy_from_slice(&opts[2..8]);
+                        mac = Some(m);
+                    }
+                    opts = &opts[len..];
+                }
+
+                if payload[0] == ICMPV6_NEIGHBOR_ADVERT {
+                    if let Some(m) = mac {
+                        self.Learn(target, m, out);
+                    }
+                    return;
+                }
+
+                if target != self.linkLocal6 && Some(target) != self.addr6 {
+                    return;
+                }
+
+                let solicited = !src.IsUnspecified();
+                if let Some(m) = mac {
+                    if solicited {
+                        self.Learn(src, m, out);
+                    }
+                }
+
+                let mut na = vec![0u8; 8];
+                na[0] = ICMPV6_NEIGHBOR_ADVERT;
+                na[4] = NDP_FLAG_OVERRIDE;
+                if solicited {
+                    na[4] |= NDP_FLAG_SOLICITED;
+                }
+                na.extend_from_slice(&t);
+                na.extend_from_slice(&[NDP_OPT_TARGET_LINK_ADDR, 1]);
+                na.extend_from_slice(&self.nic.mac);
+                let to = if solicited {
+                    src
+                } else {
+                    // all nodes
+                    IpAddr::V6([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1])
+                };
+                let csum = TransportChecksum(&target, &to, IPPROTO_ICMPV6, &na);
+                PutU16(&mut na, 2, csum);
+                out.Send(target, to, IPPROTO_ICMPV6, na);
+            }
+            _ => (),
+        }
+    }
+
+    // FindListener returns the listener for a connection to local: the one
+    // bound to the address, otherwise the one bound to the wildcard address.
+    fn FindListener(&self, local: &FullAddr) -> Option<u64> {
+        if let Some(id) = self.tcpListeners.get(local) {
+            return Some(*id);
+        }
+
+        let any = FullAddr::New(IpAddr::Any(local.addr.IsV6()), local.port);
+        if let Some(id) = self.tcpListeners.get(&any) {
+            return Some(*id);
+        }
+
+        if !local.addr.IsV6() {
+            let any6 = FullAddr::New(IPV6_ANY, local.port);
+            if let Some(id) = self.tcpListeners.get(&any6) {
+                if self.tcpEps.get(id).map(|ep| !ep.v6only).unwrap_or(false) {
+                    return Some(*id);
+                }
+            }
+        }
+
+        return None;
+    }
+
+    fn HandleTcp(&mut self, src: IpAddr, dst: IpAddr, payload: &[u8], out: &mut Output) {
+        let seg = match TcpSegment::Parse(&src, &dst, payload) {
+            None => return,
+            Some(s) => s,
+        };
+
+        if dst.IsMulticast() || dst.IsBroadcast() {
+            return;
+        }
+
+        let local = FullAddr::New(dst, seg.dstPort);
+        let remote = FullAddr::New(src, seg.srcPort);
+        let mss = self.Mss(&src);
+
+        if let Some(id) = self.tcpConns.get(&(local, remote)).copied() {
+            let transition = match self.tcpEps.get_mut(&id) {
+                Some(ep) => ep.HandleSegment(&seg, mss, out),
+                None => {
+                    // a stale connection entry, the endpoint is gone
+                    self.tcpConns.remove(&(local, remote));
+                    out.SendRstFor(&local, &remote, &seg);
+                    return;
+                }
+            };
+            self.OnTransition(id, transition, out);
+            return;
+        }
+
+        let lid = match self.FindListener(&local) {
+            None => {
+                out.SendRstFor(&local, &remote, &seg);
+                return;
+            }
+            Some(id) => id,
+        };
+
+        if seg.flags & TCP_FLAG_RST != 0 {
+            return;
+        }
+
+        if seg.flags & TCP_FLAG_ACK != 0 || seg.flags & TCP_FLAG_SYN == 0 {
+            out.SendRstFor(&local, &remote, &seg);
+            return;
+        }
+
+        let (v6, queued) = {
+            let l = match self.tcpEps.get(&lid) {
+                None => return,
+                Some(l) => l,
+            };
+            let limit = core::cmp::max(l.backlog, 1);
+            if l.synQueue + l.acceptQueue.len() >= limit {
+                // the peer retransmits the SYN
+                return;
+            }
+            (l.v6, l.synQueue)
+        };
+
+        let id = self.NextId();
+        let mut child = TcpEndpoint::New(id, Queue::default(), v6);
+        if let Some(l) = self.tcpEps.get(&lid) {
+            child.nodelay = l.nodelay;
+            child.keepAlive = l.keepAlive;
+            child.rcvBufSize = l.rcvBufSize;
+            child.sndBufSize = l.sndBufSize;
+        }
+        child.local = Some(local);
+        child.remote = Some(remote);
+        child.parent = Some(lid);
+
+        let iss = self.Isn(&local, &remote, out.now);
+        child.Accepted(iss, mss, &seg, out);
+        if let Some(l) = self.tcpEps.get_mut(&lid) {
+            l.synQueue = queued + 1;
+        }
+        self.tcpEps.insert(id, child);
+        self.tcpConns.insert((local, remote), id);
+    }
+
+    fn HandleUdp(&mut self, src: IpAddr, dst: IpAddr, payload: &[u8], out: &mut Output) {
+        if payload.len() < UDP_HEADER_SIZE {
+            return;
+        }
+
+        let len = GetU16(payload, 4) as usize;
+        if len < UDP_HEADER_SIZE || len > payload.len() {
+            return;
+        }
+
+        let packet = &payload[..len];
+        let csum = GetU16(packet, 6);
+        if csum != 0 || src.IsV6() {
+            if TransportChecksum(&src, &dst, IPPROTO_UDP, packet) != 0 {
+                return;
+            }
+        }
+
+        let from = FullAddr::New(src, GetU16(packet, 0));
+        let to = FullAddr::New(dst, GetU16(packet, 2));
+        let data = &packet[UDP_HEADER_SIZE..];
+
+        if dst.IsMulticast() || dst.IsBroadcast() {
+            for ep in self.udpEps.values_mut() {
+                let local = match ep.local {
+                    Some(l) if l.port == to.port && l.addr.IsUnspecified() => l,
+                    _ => continue,
+                };
+
+                if !Covers(&local.addr, ep.v6only, &dst) {
+                    continue;
+                }
+
+                if ep.remote.is_some() && ep.remote != Some(from) {
+                    continue;
+                }
+
+                ep.Deliver(from, data, out);
+            }
+            return;
+        }
+
+        // the most specific endpoint receives the datagram
+        let mut best: Option<(u32, u64)> = None;
+        for (id, ep) in &self.udpEps {
+            if !ep.Matches(&from, &to) {
+                continue;
+            }
+
+            let local = ep.local.unwrap();
+            let mut score = 0;
+            if ep.remote.is_some() {
+                score += 2;
+            }
+            if !local.addr.IsUnspecified() {
+                score += 1;
+            }
+
+            if best.map(|(s, _)| score > s).unwrap_or(true) {
+                best = Some((score, *id));
+            }
+        }
+
+        if let Some((_, id)) = best {
+            if let Some(ep) = self.udpEps.get_mut(&id) {
+                ep.Deliver(from, data, out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::vec_deque::VecDeque;
+
+    fn TestStack() -> Stack {
+        let mut nic = NetstackNicInfo::default();
+        nic.mac = [2, 0, 0, 0, 0, 1];
+        nic.mtu = 1500;
+        nic.addr = [10, 0, 0, 2];
+        nic.prefixLen = 24;
+        return Stack::New(-1, nic, 1);
+    }
+
+    fn TestOutput() -> Output {
+        return Output {
+            now: 0,
+            packets: VecDeque::new(),
+            frames: Vec::new(),
+            notify: Vec::new(),
+        };
+    }
+
+    fn State(stack: &Stack, id: u64) -> TcpState {
+        return stack.tcpEps.get(&id).unwrap().state;
+    }
+
+    #[test]
+    fn test_TcpHandshakeAndClose() {
+        let mut stack = TestStack();
+        let mut out = TestOutput();
+        let server = FullAddr::New(IPV4_LOOPBACK, 80);
+
+        let l = stack.NewTcpEndpoint(Queue::default(), false);
+        stack.TcpBind(l, server).unwrap();
+        stack.Listen(l, 8).unwrap();
+        assert!(State(&stack, l) == TcpState::Listen);
+
+        let c = stack.NewTcpEndpoint(Queue::default(), false);
+        stack.TcpConnect(c, server, &mut out).unwrap();
+        assert!(State(&stack, c) == TcpState::SynSent);
+
+        // the loopback segments are delivered by Drain
+        stack.Drain(&mut out);
+        assert!(State(&stack, c) == TcpState::Established);
+        let s = stack.Accept(l).unwrap();
+        assert!(State(&stack, s) == TcpState::Established);
+        assert!(stack.Accept(l).is_none());
+
+        // active close by the client
+        stack.TcpShutdown(c, false, true, &mut out).unwrap();
+        stack.Drain(&mut out);
+        assert!(State(&stack, c) == TcpState::FinWait2);
+        assert!(State(&stack, s) == TcpState::CloseWait);
+
+        stack.TcpShutdown(s, false, true, &mut out).unwrap();
+        stack.Drain(&mut out);
+        assert!(State(&stack, c) == TcpState::TimeWait);
+        assert!(State(&stack, s) == TcpState::Closed);
+
+        // the endpoints of the closed sockets are released
+        stack.Close(s, &mut out);
+        assert!(stack.tcpEps.get(&s).is_none());
+        stack.Close(l, &mut out);
+        assert!(stack.tcpEps.get(&l).is_none());
+        assert!(stack.tcpListeners.is_empty());
+    }
+
+    #[test]
+    fn test_TcpConnectRefused() {
+        let mut stack = TestStack();
+        let mut out = TestOutput();
+
+        let c = stack.NewTcpEndpoint(Queue::default(), false);
+        stack
+            .TcpConnect(c, FullAddr::New(IPV4_LOOPBACK, 81), &mut out)
+            .unwrap();
+        stack.Drain(&mut out);
+        assert!(State(&stack, c) == TcpState::Closed);
+        assert!(stack.tcpEps.get(&c).unwrap().error.is_some());
+    }
+
+    #[test]
+    fn test_ReleasedEndpoint() {
+        let mut stack = TestStack();
+        let mut out = TestOutput();
+
+        let c = stack.NewTcpEndpoint(Queue::default(), false);
+        stack.Close(c, &mut out);
+        assert!(stack.tcpEps.get(&c).is_none());
+
+        // the calls on a released endpoint fail instead of panicking
+        let addr = FullAddr::New(IPV4_LOOPBACK, 80);
+        assert!(stack.TcpBind(c, addr).is_err());
+        assert!(stack.Listen(c, 1).is_err());
+        assert!(stack.TcpConnect(c, addr, &mut out).is_err());
+        assert!(stack.TcpShutdown(c, true, true, &mut out).is_err());
+        assert!(stack.Accept(c).is_none());
+        stack.Close(c, &mut out);
+    }
+}
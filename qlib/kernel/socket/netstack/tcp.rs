@@ -0,0 +1,1218 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::collections::vec_deque::VecDeque;
+use alloc::vec::Vec;
+
+use super::super::super::super::linux_def::*;
+use super::super::super::kernel::waiter::*;
+use super::header::*;
+use super::*;
+
+pub const TCP_DEFAULT_BUF_SIZE: usize = 256 * 1024;
+pub const TCP_MIN_BUF_SIZE: usize = 4096;
+pub const TCP_MAX_BUF_SIZE: usize = 4 * 1024 * 1024;
+
+pub const TCP_DEFAULT_MSS_V4: u32 = 536;
+pub const TCP_DEFAULT_MSS_V6: u32 = 1220;
+
+// RFC 6928 initial window.
+pub const TCP_INITIAL_CWND: u32 = 10;
+pub const TCP_DUP_ACK_THRESHOLD: u32 = 3;
+
+pub const TCP_INITIAL_RTO: i64 = 1_000_000_000;
+pub const TCP_MIN_RTO: i64 = 200_000_000;
+pub const TCP_MAX_RTO: i64 = 60_000_000_000;
+pub const TCP_MAX_RETRIES: u32 = 15;
+pub const TCP_MAX_SYN_RETRIES: u32 = 6;
+
+// 2 * MSL, also bounds the time an orphaned connection stays in FIN_WAIT_2.
+pub const TCP_TIME_WAIT: i64 = 60_000_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynRcvd,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+impl TcpState {
+    // LinuxState returns the state as in include/net/tcp_states.h.
+    pub fn LinuxState(&self) -> u32 {
+        match self {
+            Self::Established => 1,
+            Self::SynSent => 2,
+            Self::SynRcvd => 3,
+            Self::FinWait1 => 4,
+            Self::FinWait2 => 5,
+            Self::TimeWait => 6,
+            Self::Closed => 7,
+            Self::CloseWait => 8,
+            Self::LastAck => 9,
+            Self::Listen => 10,
+            Self::Closing => 11,
+        }
+    }
+
+    pub fn Connected(&self) -> bool {
+        match self {
+            Self::Established | Self::FinWait1 | Self::FinWait2 | Self::CloseWait => true,
+            Self::Closing | Self::LastAck | Self::TimeWait => true,
+            _ => false,
+        }
+    }
+}
+
+// Transition is a state change of the endpoint the stack has to act on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transition {
+    None,
+    Established,
+    Closed,
+}
+
+pub struct TcpEndpoint {
+    pub id: u64,
+    pub queue: Queue,
+    pub v6: bool,
+    pub state: TcpState,
+    pub local: Option<FullAddr>,
+    pub remote: Option<FullAddr>,
+
+    // orphan is set when the socket is closed while the connection is still
+    // being shutdown, the endpoint is released when it reaches Closed.
+    pub orphan: bool,
+    pub error: Option<i32>,
+    // connected is set once the endpoint has been connected, a closed
+    // endpoint returns EOF instead of ENOTCONN.
+    pub connected: bool,
+
+    pub nodelay: bool,
+    pub reuseAddr: bool,
+    pub keepAlive: bool,
+    pub v6only: bool,
+
+    // listener
+    pub backlog: usize,
+    pub acceptQueue: VecDeque<u64>,
+    pub synQueue: usize,
+    pub parent: Option<u64>,
+
+    // send sequence space
+    pub iss: u32,
+    pub sndUna: u32,
+    pub sndNxt: u32,
+    pub sndMax: u32,
+    pub sndWnd: u32,
+    pub sndWl1: u32,
+    pub sndWl2: u32,
+    pub sndWndShift: u8,
+    // sndBuf holds the data from sndBufSeq, unacknowledged and unsent.
+    pub sndBuf: VecDeque<u8>,
+    pub sndBufSeq: u32,
+    pub sndBufSize: usize,
+    pub finQueued: bool,
+    pub mss: u32,
+
+    // receive sequence space
+    pub irs: u32,
+    pub rcvNxt: u32,
+    pub rcvBuf: VecDeque<u8>,
+    pub rcvBufSize: usize,
+    pub rcvWndShift: u8,
+    pub rcvAdvertised: u32,
+    pub rcvClosed: bool,
+    pub shutRead: bool,
+    pub ooo: Vec<(u32, Vec<u8>)>,
+    pub oooFin: Option<u32>,
+
+    pub wsEnabled: bool,
+    pub sackPermitted: bool,
+    // scoreboard holds the ranges sacked by the peer above sndUna.
+    pub scoreboard: Vec<(u32, u32)>,
+
+    // NewReno congestion control
+    pub cwnd: u32,
+    pub ssthresh: u32,
+    pub dupAcks: u32,
+    pub inRecovery: bool,
+    pub recover: u32,
+    pub highRxt: u32,
+
+    // RFC 6298 retransmission timer
+    pub srtt: i64,
+    pub rttvar: i64,
+    pub rto: i64,
+    pub rttSample: Option<(u32, i64)>,
+    pub rtoDeadline: Option<i64>,
+    pub retries: u32,
+    pub timeWaitDeadline: i64,
+}
+
+impl TcpEndpoint {
+    pub fn New(id: u64, queue: Queue, v6: bool) -> Self {
+        return Self {
+            id: id,
+            queue: queue,
+            v6: v6,
+            state: TcpState::Closed,
+            local: None,
+            remote: None,
+            orphan: false,
+            error: None,
+            connected: false,
+            nodelay: false,
+            reuseAddr: false,
+            keepAlive: false,
+            v6only: false,
+            backlog: 0,
+            acceptQueue: VecDeque::new(),
+            synQueue: 0,
+            parent: None,
+            iss: 0,
+            sndUna: 0,
+            sndNxt: 0,
+            sndMax: 0,
+            sndWnd: 0,
+            sndWl1: 0,
+            sndWl2: 0,
+            sndWndShift: 0,
+            sndBuf: VecDeque::new(),
+            sndBufSeq: 0,
+            sndBufSize: TCP_DEFAULT_BUF_SIZE,
+            finQueued: false,
+            mss: TCP_DEFAULT_MSS_V4,
+            irs: 0,
+            rcvNxt: 0,
+            rcvBuf: VecDeque::new(),
+            rcvBufSize: TCP_DEFAULT_BUF_SIZE,
+            rcvWndShift: 0,
+            rcvAdvertised: 0,
+            rcvClosed: false,
+            shutRead: false,
+            ooo: Vec::new(),
+            oooFin: None,
+            wsEnabled: false,
+            sackPermitted: false,
+            scoreboard: Vec::new(),
+            cwnd: 0,
+            ssthresh: u32::MAX,
+            dupAcks: 0,
+            inRecovery: false,
+            recover: 0,
+            highRxt: 0,
+            srtt: 0,
+            rttvar: 0,
+            rto: TCP_INITIAL_RTO,
+            rttSample: None,
+            rtoDeadline: None,
+            retries: 0,
+            timeWaitDeadline: 0,
+        };
+    }
+
+    pub fn Local(&self) -> FullAddr {
+        return self.local.unwrap();
+    }
+
+    pub fn Remote(&self) -> FullAddr {
+        return self.remote.unwrap();
+    }
+
+    fn Notify(&self, out: &mut Output, mask: EventMask) {
+        out.notify.push((self.queue.clone(), mask));
+    }
+
+    // WndShift returns the window scale to advertise for the receive buffer.
+    pub fn WndShift(&self) -> u8 {
+        let mut shift = 0;
+        while shift < 14 && (self.rcvBufSize >> shift) > 0xffff {
+            shift += 1;
+        }
+
+        return shift;
+    }
+
+    pub fn SndDataEnd(&self) -> u32 {
+        return self.sndBufSeq.wrapping_add(self.sndBuf.len() as u32);
+    }
+
+    pub fn FinSent(&self) -> bool {
+        return self.finQueued && SeqGT(self.sndMax, self.SndDataEnd());
+    }
+
+    pub fn FinAcked(&self) -> bool {
+        return self.finQueued && SeqGT(self.sndUna, self.SndDataEnd());
+    }
+
+    pub fn InFlight(&self) -> u32 {
+        return self.sndNxt.wrapping_sub(self.sndUna);
+    }
+
+    pub fn RcvWnd(&self) -> u32 {
+        let oooBytes: usize = self.ooo.iter().map(|(_, d)| d.len()).sum();
+        let used = self.rcvBuf.len() + oooBytes;
+        if used >= self.rcvBufSize {
+            return 0;
+        }
+
+        return (self.rcvBufSize - used) as u32;
+    }
+
+    pub fn SndSpace(&self) -> usize {
+        if self.sndBuf.len() >= self.sndBufSize {
+            return 0;
+        }
+
+        return self.sndBufSize - self.sndBuf.len();
+    }
+
+    pub fn Readiness(&self, mask: EventMask) -> EventMask {
+        let mut ready = 0;
+        if self.state == TcpState::Listen {
+            if self.acceptQueue.len() > 0 {
+                ready |= READABLE_EVENT;
+            }
+            return ready & mask;
+        }
+
+        if self.rcvBuf.len() > 0 || self.rcvClosed || self.shutRead {
+            ready |= READABLE_EVENT;
+        }
+
+        match self.state {
+            TcpState::Established | TcpState::CloseWait => {
+                if !self.finQueued && self.SndSpace() > 0 {
+                    ready |= WRITEABLE_EVENT;
+                }
+            }
+            TcpState::Closed => {
+                ready |= EVENT_HUP;
+                if self.connected {
+                    ready |= READABLE_EVENT | WRITEABLE_EVENT;
+                }
+            }
+            _ => (),
+        }
+
+        if self.rcvClosed && self.finQueued {
+            ready |= EVENT_HUP;
+        }
+
+        if self.error.is_some() {
+            ready |= EVENT_ERR;
+        }
+
+        return ready & (mask | EVENT_HUP | EVENT_ERR);
+    }
+
+    // SynOptions returns the options of the SYN or SYN-ACK, peer is the SYN
+    // received from the peer for a SYN-ACK.
+    fn SynOptions(&self, ourMss: u32, peer: Option<&TcpOptions>) -> TcpOptions {
+        let mut opts = TcpOptions {
+            mss: Some(ourMss as u16),
+            ..Default::default()
+        };
+
+        match peer {
+            None => {
+                opts.ws = Some(self.rcvWndShift);
+                opts.sackPermitted = true;
+            }
+            Some(p) => {
+                if p.ws.is_some() {
+                    opts.ws = Some(self.rcvWndShift);
+                }
+                opts.sackPermitted = p.sackPermitted;
+            }
+        }
+
+        return opts;
+    }
+
+    // SackBlocks returns the ranges received out of order, they are sent in
+    // the sack option of the acks.
+    fn SackBlocks(&self) -> Vec<(u32, u32)> {
+        let mut ranges: Vec<(u32, u32)> = self
+            .ooo
+            .iter()
+            .map(|(seq, d)| (*seq, seq.wrapping_add(d.len() as u32)))
+            .collect();
+        ranges.sort_by(|a, b| {
+            let x = a.0.wrapping_sub(self.rcvNxt);
+            let y = b.0.wrapping_sub(self.rcvNxt);
+            x.cmp(&y)
+        });
+
+        let mut blocks: Vec<(u32, u32)> = Vec::new();
+        for (start, end) in ranges {
+            if let Some(last) = blocks.last_mut() {
+                if SeqLEQ(start, last.1) {
+                    if SeqGT(end, last.1) {
+                        last.1 = end;
+                    }
+                    continue;
+                }
+            }
+            blocks.push((start, end));
+        }
+
+        blocks.truncate(MAX_SACK_BLOCKS - 1);
+        return blocks;
+    }
+
+    pub fn SendSegment(&mut self, seq: u32, flags: u8, data: &[u8], options: TcpOptions, out: &mut Output) {
+        let local = self.Local();
+        let remote = self.Remote();
+
+        let mut options = options;
+        if flags & TCP_FLAG_SYN == 0 && self.sackPermitted && self.ooo.len() > 0 {
+            options.sack = self.SackBlocks();
+        }
+
+        let wnd = if flags & TCP_FLAG_SYN != 0 {
+            core::cmp::min(self.RcvWnd(), 0xffff)
+        } else {
+            core::cmp::min(self.RcvWnd() >> self.rcvWndShift, 0xffff)
+        };
+        self.rcvAdvertised = wnd << self.rcvWndShift;
+
+        let ack = if flags & TCP_FLAG_ACK != 0 { self.rcvNxt } else { 0 };
+        let seg = TcpSegment {
+            srcPort: local.port,
+            dstPort: remote.port,
+            seq: seq,
+            ack: ack,
+            flags: flags,
+            window: wnd as u16,
+            options: options,
+            payload: data,
+        };
+
+        let packet = seg.Marsh(&local.addr, &remote.addr);
+        out.Send(local.addr, remote.addr, IPPROTO_TCP, packet);
+    }
+
+    pub fn SendAck(&mut self, out: &mut Output) {
+        let seq = self.sndNxt;
+        self.SendSegment(seq, TCP_FLAG_ACK, &[], TcpOptions::default(), out);
+    }
+
+    pub fn SendRst(&mut self, out: &mut Output) {
+        let seq = self.sndNxt;
+        self.SendSegment(seq, TCP_FLAG_RST | TCP_FLAG_ACK, &[], TcpOptions::default(), out);
+    }
+
+    pub fn SendSyn(&mut self, ourMss: u32, peer: Option<&TcpOptions>, out: &mut Output) {
+        let opts = self.SynOptions(ourMss, peer);
+        let flags = if self.state == TcpState::SynRcvd {
+            TCP_FLAG_SYN | TCP_FLAG_ACK
+        } else {
+            TCP_FLAG_SYN
+        };
+        let iss = self.iss;
+        self.SendSegment(iss, flags, &[], opts, out);
+        if self.rtoDeadline.is_none() {
+            self.rtoDeadline = Some(out.now + self.rto);
+        }
+    }
+
+    // Connect starts the active open, the local and remote addresses are set.
+    pub fn Connect(&mut self, iss: u32, ourMss: u32, out: &mut Output) {
+        self.iss = iss;
+        self.sndUna = iss;
+        self.sndNxt = iss.wrapping_add(1);
+        self.sndMax = self.sndNxt;
+        self.sndBufSeq = self.sndNxt;
+        self.rcvWndShift = self.WndShift();
+        self.mss = ourMss;
+        self.state = TcpState::SynSent;
+        self.SendSyn(ourMss, None, out);
+    }
+
+    // Accepted initializes the endpoint created by a listener for the SYN seg.
+    pub fn Accepted(&mut self, iss: u32, ourMss: u32, seg: &TcpSegment, out: &mut Output) {
+        self.iss = iss;
+        self.sndUna = iss;
+        self.sndNxt = iss.wrapping_add(1);
+        self.sndMax = self.sndNxt;
+        self.sndBufSeq = self.sndNxt;
+        self.irs = seg.seq;
+        self.rcvNxt = seg.seq.wrapping_add(1);
+        self.Negotiate(ourMss, &seg.options);
+        self.sndWnd = seg.window as u32;
+        self.sndWl1 = seg.seq;
+        self.sndWl2 = 0;
+        self.state = TcpState::SynRcvd;
+        self.SendSyn(ourMss, Some(&seg.options), out);
+    }
+
+    fn Negotiate(&mut self, ourMss: u32, opts: &TcpOptions) {
+        let peerMss = match opts.mss {
+            Some(m) if m > 0 => m as u32,
+            _ => {
+                if self.Local().addr.IsV6() {
+                    TCP_DEFAULT_MSS_V6
+                } else {
+                    TCP_DEFAULT_MSS_V4
+                }
+            }
+        };
+        self.mss = core::cmp::min(ourMss, peerMss);
+
+        match opts.ws {
+            Some(ws) => {
+                self.wsEnabled = true;
+                self.sndWndShift = ws;
+                self.rcvWndShift = self.WndShift();
+            }
+            None => {
+                self.wsEnabled = false;
+                self.sndWndShift = 0;
+                self.rcvWndShift = 0;
+            }
+        }
+
+        self.sackPermitted = opts.sackPermitted;
+        self.cwnd = TCP_INITIAL_CWND * self.mss;
+        self.ssthresh = u32::MAX;
+    }
+
+    fn SetError(&mut self, err: i32, out: &mut Output) {
+        self.error = Some(err);
+        self.Notify(out, READABLE_EVENT | WRITEABLE_EVENT | EVENT_ERR | EVENT_HUP);
+    }
+
+    pub fn Close(&mut self, out: &mut Output) -> Transition {
+        self.state = TcpState::Closed;
+        self.rtoDeadline = None;
+        self.Notify(out, READABLE_EVENT | WRITEABLE_EVENT | EVENT_HUP);
+        return Transition::Closed;
+    }
+
+    // Abort resets the connection, e.g. it is closed with unread data.
+    pub fn Abort(&mut self, out: &mut Output) -> Transition {
+        match self.state {
+            TcpState::SynRcvd
+            | TcpState::Established
+            | TcpState::FinWait1
+            | TcpState::FinWait2
+            | TcpState::CloseWait => self.SendRst(out),
+            _ => (),
+        }
+
+        return self.Close(out);
+    }
+
+    // Shutdown queues the FIN after the data in the send buffer.
+    pub fn Shutdown(&mut self, out: &mut Output) -> Transition {
+        match self.state {
+            TcpState::SynSent | TcpState::Closed => return self.Close(out),
+            TcpState::SynRcvd | TcpState::Established => self.state = TcpState::FinWait1,
+            TcpState::CloseWait => self.state = TcpState::LastAck,
+            _ => return Transition::None,
+        }
+
+        self.finQueued = true;
+        self.Output(out);
+        return Transition::None;
+    }
+
+    fn ArmRto(&mut self, now: i64) {
+        if self.rtoDeadline.is_none() {
+            self.rtoDeadline = Some(now + self.rto);
+        }
+    }
+
+    // SendData sends len bytes of the send buffer from seq, with the FIN if
+    // they are the last bytes and the FIN is queued.
+    fn SendData(&mut self, seq: u32, len: u32, out: &mut Output) -> u32 {
+        let offset = seq.wrapping_sub(self.sndBufSeq) as usize;
+        let data: Vec<u8> = self
+            .sndBuf
+            .range(offset..offset + len as usize)
+            .copied()
+            .collect();
+        let end = seq.wrapping_add(len);
+        let mut flags = TCP_FLAG_ACK;
+        let mut consumed = len;
+        if end == self.SndDataEnd() {
+            flags |= TCP_FLAG_PSH;
+            if self.finQueued {
+                flags |= TCP_FLAG_FIN;
+                consumed += 1;
+            }
+        }
+
+        self.SendSegment(seq, flags, &data, TcpOptions::default(), out);
+        return consumed;
+    }
+
+    // Output sends the data and the FIN allowed by the congestion and the
+    // send windows.
+    pub fn Output(&mut self, out: &mut Output) {
+        match self.state {
+            TcpState::Established
+            | TcpState::CloseWait
+            | TcpState::FinWait1
+            | TcpState::Closing
+            | TcpState::LastAck => (),
+            _ => return,
+        }
+
+        loop {
+            let dataEnd = self.SndDataEnd();
+            let avail = if SeqLT(self.sndNxt, dataEnd) {
+                dataEnd.wrapping_sub(self.sndNxt)
+            } else {
+                0
+            };
+
+            if avail == 0 {
+                if self.finQueued && self.sndNxt == dataEnd {
+                    let seq = self.sndNxt;
+                    self.SendSegment(seq, TCP_FLAG_FIN | TCP_FLAG_ACK, &[], TcpOptions::default(), out);
+                    self.Advance(1, out.now);
+                }
+                return;
+            }
+
+            let wnd = core::cmp::min(self.cwnd, self.sndWnd);
+            let inflight = self.InFlight();
+            if inflight >= wnd {
+                if self.sndWnd == 0 {
+                    // the zero window is probed on the retransmission timer
+                    self.ArmRto(out.now);
+                }
+                return;
+            }
+
+            let len = core::cmp::min(core::cmp::min(avail, self.mss), wnd - inflight);
+            // Nagle's algorithm, the small segments wait for the unacked data.
+            if len < self.mss && inflight > 0 && !self.nodelay {
+                return;
+            }
+
+            let seq = self.sndNxt;
+            let consumed = self.SendData(seq, len, out);
+            self.Advance(consumed, out.now);
+        }
+    }
+
+    fn Advance(&mut self, consumed: u32, now: i64) {
+        let seq = self.sndNxt;
+        self.sndNxt = self.sndNxt.wrapping_add(consumed);
+        if SeqGT(self.sndNxt, self.sndMax) {
+            // only the new data is timed, Karn's algorithm
+            if self.rttSample.is_none() && seq == self.sndMax {
+                self.rttSample = Some((seq, now));
+            }
+            self.sndMax = self.sndNxt;
+        }
+        self.ArmRto(now);
+    }
+
+    fn Sacked(&self, seq: u32) -> Option<u32> {
+        for (start, end) in &self.scoreboard {
+            if SeqGEQ(seq, *start) && SeqLT(seq, *end) {
+                return Some(*end);
+            }
+        }
+
+        return None;
+    }
+
+    // RetransmitHole retransmits the first segment not sacked by the peer
+    // from highRxt.
+    fn RetransmitHole(&mut self, out: &mut Output) {
+        let mut seq = if SeqGT(self.highRxt, self.sndUna) {
+            self.highRxt
+        } else {
+            self.sndUna
+        };
+
+        while let Some(end) = self.Sacked(seq) {
+            seq = end;
+        }
+
+        if SeqGEQ(seq, self.sndMax) {
+            return;
+        }
+
+        let dataEnd = self.SndDataEnd();
+        if seq == dataEnd {
+            if self.finQueued {
+                self.SendSegment(seq, TCP_FLAG_FIN | TCP_FLAG_ACK, &[], TcpOptions::default(), out);
+                self.highRxt = seq.wrapping_add(1);
+            }
+            return;
+        }
+
+        let mut len = core::cmp::min(dataEnd.wrapping_sub(seq), self.mss);
+        for (start, _) in &self.scoreboard {
+            if SeqGT(*start, seq) && SeqLT(*start, seq.wrapping_add(len)) {
+                len = start.wrapping_sub(seq);
+            }
+        }
+
+        let consumed = self.SendData(seq, len, out);
+        self.highRxt = seq.wrapping_add(consumed);
+    }
+
+    fn UpdateScoreboard(&mut self, blocks: &[(u32, u32)]) {
+        for (start, end) in blocks {
+            if SeqLEQ(*end, self.sndUna) || SeqGT(*end, self.sndMax) || SeqGEQ(*start, *end) {
+                continue;
+            }
+            self.scoreboard.push((*start, *end));
+        }
+
+        let una = self.sndUna;
+        self.scoreboard.retain(|(_, end)| SeqGT(*end, una));
+        self.scoreboard.sort_by(|a, b| a.0.wrapping_sub(una).cmp(&b.0.wrapping_sub(una)));
+        let mut merged: Vec<(u32, u32)> = Vec::new();
+        for (start, end) in self.scoreboard.drain(..) {
+            if let Some(last) = merged.last_mut() {
+                if SeqLEQ(start, last.1) {
+                    if SeqGT(end, last.1) {
+                        last.1 = end;
+                    }
+                    continue;
+                }
+            }
+            merged.push((start, end));
+        }
+        self.scoreboard = merged;
+    }
+
+    fn UpdateRtt(&mut self, ack: u32, now: i64) {
+        let (seq, start) = match self.rttSample {
+            None => return,
+            Some(s) => s,
+        };
+
+        if SeqLEQ(ack, seq) {
+            return;
+        }
+
+        self.rttSample = None;
+        let r = core::cmp::max(now - start, 1);
+        if self.srtt == 0 {
+            self.srtt = r;
+            self.rttvar = r / 2;
+        } else {
+            let delta = if self.srtt > r { self.srtt - r } else { r - self.srtt };
+            self.rttvar = (3 * self.rttvar + delta) / 4;
+            self.srtt = (7 * self.srtt + r) / 8;
+        }
+
+        let rto = self.srtt + core::cmp::max(4 * self.rttvar, 1_000_000);
+        self.rto = core::cmp::min(core::cmp::max(rto, TCP_MIN_RTO), TCP_MAX_RTO);
+    }
+
+    fn EnterRecovery(&mut self, out: &mut Output) {
+        let flight = self.InFlight();
+        self.ssthresh = core::cmp::max(flight / 2, 2 * self.mss);
+        self.cwnd = self.ssthresh + TCP_DUP_ACK_THRESHOLD * self.mss;
+        self.recover = self.sndMax;
+        self.inRecovery = true;
+        self.highRxt = self.sndUna;
+        self.rttSample = None;
+        self.RetransmitHole(out);
+    }
+
+    // ProcessAck handles the acknowledgment field of seg, it returns false
+    // when seg must be dropped.
+    fn ProcessAck(&mut self, seg: &TcpSegment, out: &mut Output) -> bool {
+        let ack = seg.ack;
+        let wnd = (seg.window as u32) << self.sndWndShift;
+
+        if SeqGT(ack, self.sndMax) {
+            // acks something not yet sent
+            self.SendAck(out);
+            return false;
+        }
+
+        if self.sackPermitted && seg.options.sack.len() > 0 {
+            self.UpdateScoreboard(&seg.options.sack);
+        }
+
+        if SeqGT(ack, self.sndUna) {
+            let acked = ack.wrapping_sub(self.sndUna);
+            self.UpdateRtt(ack, out.now);
+
+            let dataAcked = if SeqGT(ack, self.sndBufSeq) {
+                core::cmp::min(ack.wrapping_sub(self.sndBufSeq) as usize, self.sndBuf.len())
+            } else {
+                0
+            };
+            self.sndBuf.drain(..dataAcked);
+            self.sndBufSeq = self.sndBufSeq.wrapping_add(dataAcked as u32);
+            self.sndUna = ack;
+            if SeqLT(self.sndNxt, ack) {
+                self.sndNxt = ack;
+            }
+            self.UpdateScoreboard(&[]);
+
+            if self.inRecovery {
+                if SeqGEQ(ack, self.recover) {
+                    self.inRecovery = false;
+                    self.cwnd = self.ssthresh;
+                } else {
+                    // NewReno partial ack: the next hole is lost as well
+                    self.cwnd = self.cwnd.saturating_sub(acked) + self.mss;
+                    self.RetransmitHole(out);
+                }
+            } else if self.cwnd < self.ssthresh {
+                self.cwnd += core::cmp::min(acked, self.mss);
+            } else {
+                self.cwnd += core::cmp::max(self.mss * self.mss / self.cwnd, 1);
+            }
+
+            self.dupAcks = 0;
+            self.retries = 0;
+            self.rtoDeadline = None;
+            if self.sndUna != self.sndMax {
+                self.rtoDeadline = Some(out.now + self.rto);
+            }
+
+            if dataAcked > 0 {
+                self.Notify(out, WRITEABLE_EVENT);
+            }
+        } else if ack == self.sndUna
+            && seg.payload.len() == 0
+            && seg.flags & (TCP_FLAG_SYN | TCP_FLAG_FIN) == 0
+            && wnd == self.sndWnd
+            && self.sndUna != self.sndMax
+        {
+            self.dupAcks += 1;
+            if self.dupAcks == TCP_DUP_ACK_THRESHOLD && !self.inRecovery {
+                self.EnterRecovery(out);
+            } else if self.inRecovery {
+                self.cwnd += self.mss;
+                if self.sackPermitted {
+                    self.RetransmitHole(out);
+                }
+            }
+        }
+
+        // window update
+        if SeqLT(self.sndWl1, seg.seq) || (self.sndWl1 == seg.seq && SeqLEQ(self.sndWl2, ack)) {
+            let opened = wnd > self.sndWnd;
+            self.sndWnd = wnd;
+            self.sndWl1 = seg.seq;
+            self.sndWl2 = ack;
+            if opened && self.sndUna == self.sndMax {
+                self.rtoDeadline = None;
+            }
+        }
+
+        return true;
+    }
+
+    fn Acceptable(&self, seg: &TcpSegment) -> bool {
+        let wnd = core::cmp::max(self.RcvWnd(), self.rcvAdvertised);
+        let len = seg.Len();
+        if len == 0 {
+            if wnd == 0 {
+                return seg.seq == self.rcvNxt;
+            }
+            return InWindow(seg.seq, self.rcvNxt, wnd);
+        }
+
+        if wnd == 0 {
+            return false;
+        }
+
+        return InWindow(seg.seq, self.rcvNxt, wnd)
+            || InWindow(seg.seq.wrapping_add(len - 1), self.rcvNxt, wnd);
+    }
+
+    // ProcessData queues the in-window payload of seg and returns whether an
+    // ack must be sent.
+    fn ProcessData(&mut self, seg: &TcpSegment, out: &mut Output) -> bool {
+        let fin = seg.flags & TCP_FLAG_FIN != 0;
+        if seg.payload.len() == 0 && !fin {
+            return false;
+        }
+
+        if self.rcvClosed {
+            return true;
+        }
+
+        let mut seq = seg.seq;
+        let mut data = seg.payload;
+        if SeqLT(seq, self.rcvNxt) {
+            let skip = self.rcvNxt.wrapping_sub(seq) as usize;
+            if skip > data.len() {
+                return true;
+            }
+            data = &data[skip..];
+            seq = self.rcvNxt;
+        }
+
+        let wnd = self.RcvWnd() as usize;
+        let finSeq = seq.wrapping_add(data.len() as u32);
+        if data.len() > wnd {
+            data = &data[..wnd];
+        }
+        let fin = fin && data.len() as u32 == finSeq.wrapping_sub(seq);
+
+        if seq != self.rcvNxt {
+            let dup = self
+                .ooo
+                .iter()
+                .any(|(s, d)| *s == seq && d.len() >= data.len());
+            if data.len() > 0 && !dup {
+                self.ooo.push((seq, data.to_vec()));
+            }
+            if fin {
+                self.oooFin = Some(finSeq);
+            }
+            return true;
+        }
+
+        if !self.shutRead {
+            self.rcvBuf.extend(data.iter());
+        }
+        self.rcvNxt = self.rcvNxt.wrapping_add(data.len() as u32);
+        if fin {
+            self.oooFin = Some(finSeq);
+        }
+
+        // pull the out of order segments which became contiguous
+        loop {
+            let mut progress = false;
+            let mut i = 0;
+            while i < self.ooo.len() {
+                let (s, ref d) = self.ooo[i];
+                let end = s.wrapping_add(d.len() as u32);
+                if SeqLEQ(end, self.rcvNxt) {
+                    self.ooo.swap_remove(i);
+                    continue;
+                }
+
+                if SeqLEQ(s, self.rcvNxt) {
+                    let skip = self.rcvNxt.wrapping_sub(s) as usize;
+                    let (_, d) = self.ooo.swap_remove(i);
+                    if !self.shutRead {
+                        self.rcvBuf.extend(d[skip..].iter());
+                    }
+                    self.rcvNxt = end;
+                    progress = true;
+                    continue;
+                }
+                i += 1;
+            }
+
+            if !progress {
+                break;
+            }
+        }
+
+        if self.oooFin == Some(self.rcvNxt) {
+            self.oooFin = None;
+            self.rcvNxt = self.rcvNxt.wrapping_add(1);
+            self.rcvClosed = true;
+            self.ooo.clear();
+            self.ReceivedFin(out);
+        }
+
+        self.Notify(out, READABLE_EVENT);
+        return true;
+    }
+
+    fn ReceivedFin(&mut self, out: &mut Output) {
+        match self.state {
+            TcpState::Established | TcpState::SynRcvd => self.state = TcpState::CloseWait,
+            TcpState::FinWait1 => {
+                if self.FinAcked() {
+                    self.EnterTimeWait(out.now);
+                } else {
+                    self.state = TcpState::Closing;
+                }
+            }
+            TcpState::FinWait2 => self.EnterTimeWait(out.now),
+            _ => (),
+        }
+
+        self.Notify(out, READABLE_EVENT | EVENT_HUP);
+    }
+
+    fn EnterTimeWait(&mut self, now: i64) {
+        self.state = TcpState::TimeWait;
+        self.rtoDeadline = None;
+        self.timeWaitDeadline = now + TCP_TIME_WAIT;
+    }
+
+    // HandleSynSent processes seg received in SynSent.
+    fn HandleSynSent(&mut self, seg: &TcpSegment, ourMss: u32, out: &mut Output) -> Transition {
+        let hasAck = seg.flags & TCP_FLAG_ACK != 0;
+        if hasAck && (SeqLEQ(seg.ack, self.iss) || SeqGT(seg.ack, self.sndMax)) {
+            if seg.flags & TCP_FLAG_RST == 0 {
+                out.SendRstFor(&self.Local(), &self.Remote(), seg);
+            }
+            return Transition::None;
+        }
+
+        if seg.flags & TCP_FLAG_RST != 0 {
+            if hasAck {
+                self.SetError(SysErr::ECONNREFUSED, out);
+                return self.Close(out);
+            }
+            return Transition::None;
+        }
+
+        if seg.flags & TCP_FLAG_SYN == 0 {
+            return Transition::None;
+        }
+
+        self.irs = seg.seq;
+        self.rcvNxt = seg.seq.wrapping_add(1);
+        self.Negotiate(ourMss, &seg.options);
+        self.sndWnd = seg.window as u32;
+        self.sndWl1 = seg.seq;
+        self.sndWl2 = seg.ack;
+        self.rtoDeadline = None;
+        self.retries = 0;
+
+        if !hasAck {
+            // simultaneous open
+            self.state = TcpState::SynRcvd;
+            self.SendSyn(ourMss, Some(&seg.options), out);
+            return Transition::None;
+        }
+
+        self.UpdateRtt(seg.ack, out.now);
+        self.sndUna = seg.ack;
+        self.state = TcpState::Established;
+        self.connected = true;
+        self.SendAck(out);
+        self.Notify(out, WRITEABLE_EVENT);
+        return Transition::Established;
+    }
+
+    // HandleSegment processes seg received for the endpoint, it isn't
+    // called for the listeners.
+    pub fn HandleSegment(&mut self, seg: &TcpSegment, ourMss: u32, out: &mut Output) -> Transition {
+        match self.state {
+            TcpState::Closed | TcpState::Listen => return Transition::None,
+            TcpState::SynSent => return self.HandleSynSent(seg, ourMss, out),
+            _ => (),
+        }
+
+        if !self.Acceptable(seg) {
+            if seg.flags & TCP_FLAG_RST == 0 {
+                self.SendAck(out);
+            }
+            return Transition::None;
+        }
+
+        if seg.flags & TCP_FLAG_RST != 0 {
+            // RFC 5961: only the exact sequence number resets the connection
+            if seg.seq != self.rcvNxt {
+                self.SendAck(out);
+                return Transition::None;
+            }
+
+            match self.state {
+                TcpState::SynRcvd => return self.Close(out),
+                TcpState::Closing | TcpState::LastAck | TcpState::TimeWait => {
+                    return self.Close(out)
+                }
+                _ => {
+                    self.SetError(SysErr::ECONNRESET, out);
+                    return self.Close(out);
+                }
+            }
+        }
+
+        if seg.flags & TCP_FLAG_SYN != 0 {
+            // challenge ack
+            self.SendAck(out);
+            return Transition::None;
+        }
+
+        if seg.flags & TCP_FLAG_ACK == 0 {
+            return Transition::None;
+        }
+
+        let mut transition = Transition::None;
+        if self.state == TcpState::SynRcvd {
+            if SeqLEQ(seg.ack, self.sndUna) || SeqGT(seg.ack, self.sndMax) {
+                out.SendRstFor(&self.Local(), &self.Remote(), seg);
+                return Transition::None;
+            }
+
+            self.state = TcpState::Established;
+            self.connected = true;
+            self.sndWl1 = seg.seq.wrapping_sub(1);
+            self.retries = 0;
+            transition = Transition::Established;
+        }
+
+        if !self.ProcessAck(seg, out) {
+            return transition;
+        }
+
+        match self.state {
+            TcpState::FinWait1 if self.FinAcked() => {
+                self.state = TcpState::FinWait2;
+                self.timeWaitDeadline = out.now + TCP_TIME_WAIT;
+            }
+            TcpState::Closing if self.FinAcked() => self.EnterTimeWait(out.now),
+            TcpState::LastAck if self.FinAcked() => return self.Close(out),
+            TcpState::TimeWait => {
+                // retransmitted FIN
+                self.SendAck(out);
+                self.timeWaitDeadline = out.now + TCP_TIME_WAIT;
+                return transition;
+            }
+            _ => (),
+        }
+
+        let needAck = match self.state {
+            TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2 => {
+                self.ProcessData(seg, out)
+            }
+            _ => seg.payload.len() > 0 || seg.flags & TCP_FLAG_FIN != 0,
+        };
+
+        let sndNxt = self.sndNxt;
+        self.Output(out);
+        if needAck && self.sndNxt == sndNxt {
+            self.SendAck(out);
+        }
+
+        return transition;
+    }
+
+    // Read takes up to len bytes from the receive buffer.
+    pub fn Read(&mut self, len: usize, peek: bool, out: &mut Output) -> Vec<u8> {
+        let len = core::cmp::min(len, self.rcvBuf.len());
+        let data: Vec<u8> = if peek {
+            self.rcvBuf.range(..len).copied().collect()
+        } else {
+            self.rcvBuf.drain(..len).collect()
+        };
+
+        // window update when the receive window was mostly closed
+        if !peek && len > 0 && self.state.Connected() && !self.rcvClosed {
+            let wnd = self.RcvWnd();
+            if wnd >= self.rcvAdvertised + core::cmp::max(self.mss, self.rcvBufSize as u32 / 4) {
+                self.SendAck(out);
+            }
+        }
+
+        return data;
+    }
+
+    pub fn Write(&mut self, data: &[u8], out: &mut Output) -> usize {
+        let len = core::cmp::min(data.len(), self.SndSpace());
+        self.sndBuf.extend(data[..len].iter());
+        self.Output(out);
+        return len;
+    }
+
+    // OnTick runs the timers of the endpoint.
+    pub fn OnTick(&mut self, ourMss: u32, out: &mut Output) -> Transition {
+        let now = out.now;
+        match self.state {
+            TcpState::TimeWait => {
+                if now >= self.timeWaitDeadline {
+                    return self.Close(out);
+                }
+                return Transition::None;
+            }
+            TcpState::FinWait2 => {
+                if self.orphan && now >= self.timeWaitDeadline {
+                    return self.Close(out);
+                }
+            }
+            _ => (),
+        }
+
+        let deadline = match self.rtoDeadline {
+            None => return Transition::None,
+            Some(d) => d,
+        };
+
+        if now < deadline {
+            return Transition::None;
+        }
+
+        self.rtoDeadline = None;
+        self.retries += 1;
+        self.rto = core::cmp::min(self.rto * 2, TCP_MAX_RTO);
+
+        match self.state {
+            TcpState::SynSent | TcpState::SynRcvd => {
+                if self.retries > TCP_MAX_SYN_RETRIES {
+                    self.SetError(SysErr::ETIMEDOUT, out);
+                    return self.Close(out);
+                }
+
+                if self.state == TcpState::SynSent {
+                    self.SendSyn(ourMss, None, out);
+                } else {
+                    // the options of the SYN-ACK echo the ones of the SYN
+                    let peer = TcpOptions {
+                        ws: if self.wsEnabled { Some(self.sndWndShift) } else { None },
+                        sackPermitted: self.sackPermitted,
+                        ..Default::default()
+                    };
+                    self.SendSyn(ourMss, Some(&peer), out);
+                }
+                return Transition::None;
+            }
+            _ => (),
+        }
+
+        if self.retries > TCP_MAX_RETRIES {
+            self.SetError(SysErr::ETIMEDOUT, out);
+            return self.Abort(out);
+        }
+
+        if self.sndUna == self.sndMax {
+            // zero window probe
+            if self.sndWnd == 0 && SeqLT(self.sndNxt, self.SndDataEnd()) {
+                let seq = self.sndNxt;
+                let consumed = self.SendData(seq, 1, out);
+                self.Advance(consumed, now);
+            }
+            return Transition::None;
+        }
+
+        let flight = self.InFlight();
+        self.ssthresh = core::cmp::max(flight / 2, 2 * self.mss);
+        self.cwnd = self.mss;
+        self.inRecovery = false;
+        self.dupAcks = 0;
+        self.rttSample = None;
+        self.scoreboard.clear();
+        self.recover = self.sndMax;
+        self.sndNxt = self.sndUna;
+        self.Output(out);
+        self.ArmRto(now);
+        return Transition::None;
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::collections::vec_deque::VecDeque;
+use alloc::vec::Vec;
+
+use super::super::super::super::linux_def::*;
+use super::super::super::kernel::waiter::*;
+use super::header::*;
+use super::*;
+
+pub const UDP_DEFAULT_BUF_SIZE: usize = 208 * 1024;
+pub const UDP_MAX_PAYLOAD: usize = 65507;
+
+pub struct UdpEndpoint {
+    pub id: u64,
+    pub queue: Queue,
+    pub v6: bool,
+    pub local: Option<FullAddr>,
+    pub remote: Option<FullAddr>,
+
+    pub rcvQueue: VecDeque<(FullAddr, Vec<u8>)>,
+    pub rcvBytes: usize,
+    pub rcvBufSize: usize,
+    pub sndBufSize: usize,
+    pub shutRead: bool,
+    pub shutWrite: bool,
+
+    pub reuseAddr: bool,
+    pub broadcast: bool,
+    pub v6only: bool,
+    pub error: Option<i32>,
+}
+
+impl UdpEndpoint {
+    pub fn New(id: u64, queue: Queue, v6: bool) -> Self {
+        return Self {
+            id: id,
+            queue: queue,
+            v6: v6,
+            local: None,
+            remote: None,
+            rcvQueue: VecDeque::new(),
+            rcvBytes: 0,
+            rcvBufSize: UDP_DEFAULT_BUF_SIZE,
+            sndBufSize: UDP_DEFAULT_BUF_SIZE,
+            shutRead: false,
+            shutWrite: false,
+            reuseAddr: false,
+            broadcast: false,
+            v6only: false,
+            error: None,
+        };
+    }
+
+    // Matches returns whether the datagram from src to dst is for the
+    // endpoint, the endpoint is bound to the port of dst.
+    pub fn Matches(&self, src: &FullAddr, dst: &FullAddr) -> bool {
+        let local = match &self.local {
+            None => return false,
+            Some(l) => l,
+        };
+
+        if !local.addr.IsUnspecified() && local.addr != dst.addr {
+            return false;
+        }
+
+        if local.addr.IsUnspecified() && local.addr.IsV6() != dst.addr.IsV6() {
+            // the ipv6 wildcard receives the ipv4 datagrams too
+            if !local.addr.IsV6() || self.v6only {
+                return false;
+            }
+        }
+
+        if let Some(remote) = &self.remote {
+            if remote.addr != src.addr || remote.port != src.port {
+                return false;
+            }
+        }
+
+        return true;
+    }
+
+    // Deliver queues the datagram, it is dropped when the receive buffer is
+    // full.
+    pub fn Deliver(&mut self, src: FullAddr, data: &[u8], out: &mut Output) {
+        if self.shutRead || self.rcvBytes + data.len() > self.rcvBufSize {
+            return;
+        }
+
+        self.rcvBytes += data.len();
+        self.rcvQueue.push_back((src, data.to_vec()));
+        out.notify.push((self.queue.clone(), READABLE_EVENT));
+    }
+
+    pub fn Recv(&mut self, peek: bool) -> Option<(FullAddr, Vec<u8>)> {
+        if peek {
+            return self.rcvQueue.front().cloned();
+        }
+
+        let (src, data) = self.rcvQueue.pop_front()?;
+        self.rcvBytes -= data.len();
+        return Some((src, data));
+    }
+
+    pub fn Readiness(&self, mask: EventMask) -> EventMask {
+        let mut ready = WRITEABLE_EVENT;
+        if self.rcvQueue.len() > 0 || self.shutRead {
+            ready |= READABLE_EVENT;
+        }
+
+        if self.error.is_some() {
+            ready |= EVENT_ERR;
+        }
+
+        return ready & (mask | EVENT_ERR);
+    }
+}
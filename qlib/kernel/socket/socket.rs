@@ -159,7 +159,8 @@ pub fn NewSocketDirent(task: &Task, _d: Arc<QMutex<Device>>, fd: i32) -> Result<
     }
     let inode = Inode::NewHostInode(task, &Arc::new(QMutex::new(msrc)), fd, &fstat, true, false)?;
 
-    let name = format!("socket:[{}]", fd);
+    // the name is the inode number reported in /proc/net
+    let name = format!("socket:[{}]", fstat.st_ino);
     return Ok(Dirent::New(&inode, &name.to_string()));
 }
 
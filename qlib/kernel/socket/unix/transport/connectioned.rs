@@ -280,6 +280,27 @@ impl ConnectionedEndPoint {
         return Self(Arc::new((internal, QMutex::new(()))));
     }
 
+    // ConnectExternal connects the endpoint to an externally backed peer, e.g.
+    // a host unix socket.
+    pub fn ConnectExternal(
+        &self,
+        receiver: Arc<Receiver>,
+        connected: Arc<ConnectedEndpoint>,
+    ) -> Result<()> {
+        let _l = self.Lock();
+        if self.Connected() {
+            return Err(Error::SysError(SysErr::EISCONN));
+        }
+
+        if self.Listening() {
+            return Err(Error::SysError(SysErr::EINVAL));
+        }
+
+        self.baseEndpoint.lock().receiver = Some(receiver);
+        self.baseEndpoint.lock().connected = Some(connected);
+        return Ok(());
+    }
+
     pub fn TryLock(&self) -> Option<QMutexGuard<()>> {
         return (self.0).1.try_lock();
     }
@@ -38,6 +38,7 @@ use super::super::super::super::linux::time::*;
 use super::super::super::super::device::*;
 use super::super::super::super::auth::*;
 use super::super::super::fs::host::hostinodeop::*;
+use super::super::super::fs::host::socket::*;
 use super::super::super::fs::inode::*;
 use super::super::super::fs::fsutil::inode::*;
 use super::super::super::fs::mount::*;
@@ -560,8 +561,49 @@ pub fn ExtractEndpoint(task: &Task, sockAddr: &[u8]) -> Result<BoundEndpoint> {
     }
 }
 
+// CheckSocketWrite checks that the task can connect to the socket file at
+// path: it looks the path up and needs the write permission on the file as
+// Linux does.
+pub fn CheckSocketWrite(task: &Task, path: &str) -> Result<()> {
+    let root = task.fsContext.RootDirectory();
+    let cwd = task.fsContext.WorkDirectory();
+    let mut remainingTraversals = 10; //DefaultTraversalLimit
+    let mns = task.mountNS.clone();
+    let d = mns.FindDirent(
+        task,
+        &root,
+        Some(cwd),
+        path,
+        &mut remainingTraversals,
+        true,
+    )?;
+
+    let inode = d.Inode();
+    return inode.check(
+        task,
+        &PermMask {
+            write: true,
+            ..Default::default()
+        },
+    );
+}
+
 impl SockOperations for UnixSocketOperations {
     fn Connect(&self, task: &Task, socketaddr: &[u8], _blocking: bool) -> Result<i64> {
+        if let BoundEndpoint::Connected(ref ce) = self.ep {
+            let path = ExtractPath(socketaddr)?;
+            if let Some(idx) = HostUnixSocketIdx(&path) {
+                // the allowlisted host sockets are connected on the host, the
+                // guest file of the socket grants the access as for the others
+                let path = String::from_utf8(path).unwrap();
+                CheckSocketWrite(task, &path)?;
+                let queue = ce.baseEndpoint.lock().queue.clone();
+                let hostEp = HostConnectedEndpoint::Connect(idx, &path, self.stype, &queue)?;
+                ce.ConnectExternal(Arc::new(hostEp.clone()), Arc::new(hostEp))?;
+                return Ok(0);
+            }
+        }
+
         let ep = ExtractEndpoint(task, socketaddr)?;
 
         // Connect the server endpoint.
@@ -251,6 +251,15 @@ pub fn GetAddr(sfamily: i16, addr: &[u8]) -> Result<SockAddr> {
 
             return Ok(SockAddr::Netlink(*a));
         }
+        AFType::AF_PACKET => {
+            if addr.len() < SockAddrLink::SOCK_ADDR_LINK_SIZE {
+                return Err(Error::SysError(SysErr::EFAULT));
+            }
+
+            let a = unsafe { &*((&addr[0]) as *const _ as *const SockAddrLink) };
+
+            return Ok(SockAddr::Link(*a));
+        }
         _ => (),
     }
 
@@ -285,6 +294,7 @@ pub enum SockAddr {
     Inet6(SocketAddrInet6),
     Unix(SockAddrUnix),
     Netlink(SockAddrNetlink),
+    Link(SockAddrLink),
     None,
 }
 
@@ -295,6 +305,7 @@ impl SockAddr {
             SockAddr::Inet6(addr) => addr.Len(),
             SockAddr::Unix(addr) => addr.Len(),
             SockAddr::Netlink(addr) => addr.Len(),
+            SockAddr::Link(addr) => addr.Len(),
             SockAddr::None => 0,
         }
     }
@@ -350,6 +361,15 @@ impl SockAddr {
                 }
                 return Ok(());
             }
+            SockAddr::Link(addr) => {
+                let ptr = addr as *const _ as u64 as *const u8;
+                let slice = unsafe { slice::from_raw_parts(ptr, len) };
+
+                for i in 0..len {
+                    buf[i] = slice[i];
+                }
+                return Ok(());
+            }
             SockAddr::None => return Err(Error::SysError(SysErr::EINVAL)),
         }
     }
@@ -475,3 +495,24 @@ impl SockAddrNetlink {
         return Self::SOCK_ADDR_NETLINK_SIZE;
     }
 }
+
+// SockAddrLink is struct sockaddr_ll, from uapi/linux/if_packet.h.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SockAddrLink {
+    pub Family: u16,
+    pub Protocol: u16,
+    pub InterfaceIndex: i32,
+    pub ARPHardwareType: u16,
+    pub PacketType: u8,
+    pub HardwareAddrLen: u8,
+    pub HardwareAddr: [u8; 8],
+}
+
+impl SockAddrLink {
+    pub const SOCK_ADDR_LINK_SIZE: usize = 20;
+
+    pub fn Len(&self) -> usize {
+        return Self::SOCK_ADDR_LINK_SIZE;
+    }
+}
@@ -0,0 +1,74 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// BPF_MAXINSNS is the maximum number of instructions of a classic BPF
+// program, from uapi/linux/bpf_common.h.
+pub const BPF_MAXINSNS: usize = 4096;
+
+// Instruction classes.
+pub const BPF_LD: u16 = 0x00;
+pub const BPF_JMP: u16 = 0x05;
+pub const BPF_RET: u16 = 0x06;
+
+// Load sizes and modes.
+pub const BPF_W: u16 = 0x00;
+pub const BPF_ABS: u16 = 0x20;
+
+// Jump operations and sources.
+pub const BPF_JEQ: u16 = 0x10;
+pub const BPF_K: u16 = 0x00;
+
+// The ancillary data loaded from the negative offsets, from
+// uapi/linux/filter.h.
+pub const SKF_AD_OFF: i32 = -0x1000;
+pub const SKF_AD_IFINDEX: i32 = 8;
+
+// SockFilter is struct sock_filter, a classic BPF instruction.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SockFilter {
+    pub Code: u16,
+    pub JumpTrue: u8,
+    pub JumpFalse: u8,
+    pub K: u32,
+}
+
+impl SockFilter {
+    pub fn Stmt(code: u16, k: u32) -> Self {
+        return Self {
+            Code: code,
+            JumpTrue: 0,
+            JumpFalse: 0,
+            K: k,
+        };
+    }
+
+    pub fn Jump(code: u16, k: u32, jt: u8, jf: u8) -> Self {
+        return Self {
+            Code: code,
+            JumpTrue: jt,
+            JumpFalse: jf,
+            K: k,
+        };
+    }
+}
+
+// SockFprog is struct sock_fprog, the SO_ATTACH_FILTER option.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SockFprog {
+    pub Len: u16,
+    pub pad: [u8; 6],
+    pub Filter: u64,
+}
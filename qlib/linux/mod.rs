@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod bpf;
 pub mod fcntl;
 pub mod futex;
 pub mod inotify;
@@ -19,6 +20,7 @@ pub mod ipc;
 pub mod limits;
 pub mod membarrier;
 pub mod netdevice;
+pub mod netlink;
 pub mod rusage;
 pub mod sem;
 pub mod shm;
@@ -0,0 +1,236 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Netlink protocols, from uapi/linux/netlink.h.
+pub const NETLINK_ROUTE: i32 = 0;
+pub const NETLINK_KOBJECT_UEVENT: i32 = 15;
+
+// SOL_NETLINK socket options, from uapi/linux/netlink.h.
+pub const NETLINK_ADD_MEMBERSHIP: i32 = 1;
+pub const NETLINK_DROP_MEMBERSHIP: i32 = 2;
+pub const NETLINK_PKTINFO: i32 = 3;
+pub const NETLINK_BROADCAST_ERROR: i32 = 4;
+pub const NETLINK_NO_ENOBUFS: i32 = 5;
+pub const NETLINK_LISTEN_ALL_NSID: i32 = 8;
+pub const NETLINK_LIST_MEMBERSHIPS: i32 = 9;
+pub const NETLINK_CAP_ACK: i32 = 10;
+pub const NETLINK_EXT_ACK: i32 = 11;
+pub const NETLINK_GET_STRICT_CHK: i32 = 12;
+
+// NetlinkMessageHeader is struct nlmsghdr, from uapi/linux/netlink.h.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct NetlinkMessageHeader {
+    pub Length: u32,
+    pub Type: u16,
+    pub Flags: u16,
+    pub Seq: u32,
+    pub PortID: u32,
+}
+
+pub const NETLINK_MESSAGE_HEADER_SIZE: usize = 16;
+
+// Netlink message header flags, from uapi/linux/netlink.h.
+pub const NLM_F_REQUEST: u16 = 0x1;
+pub const NLM_F_MULTI: u16 = 0x2;
+pub const NLM_F_ACK: u16 = 0x4;
+pub const NLM_F_ECHO: u16 = 0x8;
+pub const NLM_F_DUMP_INTR: u16 = 0x10;
+pub const NLM_F_ROOT: u16 = 0x100;
+pub const NLM_F_MATCH: u16 = 0x200;
+pub const NLM_F_ATOMIC: u16 = 0x400;
+pub const NLM_F_DUMP: u16 = NLM_F_ROOT | NLM_F_MATCH;
+
+// Standard netlink message types, from uapi/linux/netlink.h.
+pub const NLMSG_NOOP: u16 = 0x1;
+pub const NLMSG_ERROR: u16 = 0x2;
+pub const NLMSG_DONE: u16 = 0x3;
+pub const NLMSG_OVERRUN: u16 = 0x4;
+pub const NLMSG_MIN_TYPE: u16 = 0x10;
+
+pub const NLMSG_ALIGNTO: usize = 4;
+
+// NetlinkMessageAlign rounds len up to the netlink message alignment.
+pub fn NetlinkMessageAlign(len: usize) -> usize {
+    return (len + NLMSG_ALIGNTO - 1) & !(NLMSG_ALIGNTO - 1);
+}
+
+// NetlinkErrorMessage is struct nlmsgerr, from uapi/linux/netlink.h.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct NetlinkErrorMessage {
+    pub Error: i32,
+    pub Header: NetlinkMessageHeader,
+}
+
+// Route netlink message types, from uapi/linux/rtnetlink.h.
+pub const RTM_NEWLINK: u16 = 16;
+pub const RTM_DELLINK: u16 = 17;
+pub const RTM_GETLINK: u16 = 18;
+pub const RTM_SETLINK: u16 = 19;
+pub const RTM_NEWADDR: u16 = 20;
+pub const RTM_DELADDR: u16 = 21;
+pub const RTM_GETADDR: u16 = 22;
+pub const RTM_NEWROUTE: u16 = 24;
+pub const RTM_DELROUTE: u16 = 25;
+pub const RTM_GETROUTE: u16 = 26;
+pub const RTM_NEWNEIGH: u16 = 28;
+pub const RTM_DELNEIGH: u16 = 29;
+pub const RTM_GETNEIGH: u16 = 30;
+
+// RtAttr is struct rtattr, from uapi/linux/rtnetlink.h.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RtAttr {
+    pub Len: u16,
+    pub Type: u16,
+}
+
+pub const RTA_ALIGNTO: usize = 4;
+pub const RT_ATTR_SIZE: usize = 4;
+
+// RtAttrAlign rounds len up to the route attribute alignment.
+pub fn RtAttrAlign(len: usize) -> usize {
+    return (len + RTA_ALIGNTO - 1) & !(RTA_ALIGNTO - 1);
+}
+
+// InterfaceInfoMessage is struct ifinfomsg, from uapi/linux/rtnetlink.h.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct InterfaceInfoMessage {
+    pub Family: u8,
+    pub Padding: u8,
+    pub Type: u16,
+    pub Index: i32,
+    pub Flags: u32,
+    pub Change: u32,
+}
+
+// Interface attributes, from uapi/linux/if_link.h.
+pub const IFLA_UNSPEC: u16 = 0;
+pub const IFLA_ADDRESS: u16 = 1;
+pub const IFLA_BROADCAST: u16 = 2;
+pub const IFLA_IFNAME: u16 = 3;
+pub const IFLA_MTU: u16 = 4;
+pub const IFLA_LINK: u16 = 5;
+pub const IFLA_TXQLEN: u16 = 13;
+pub const IFLA_OPERSTATE: u16 = 16;
+
+// Interface operational states, from uapi/linux/if.h.
+pub const IF_OPER_UNKNOWN: u8 = 0;
+pub const IF_OPER_DOWN: u8 = 2;
+pub const IF_OPER_UP: u8 = 6;
+
+// Interface flags, from uapi/linux/if.h.
+pub const IFF_UP: u32 = 0x1;
+pub const IFF_BROADCAST: u32 = 0x2;
+pub const IFF_LOOPBACK: u32 = 0x8;
+pub const IFF_RUNNING: u32 = 0x40;
+pub const IFF_NOARP: u32 = 0x80;
+pub const IFF_MULTICAST: u32 = 0x1000;
+pub const IFF_LOWER_UP: u32 = 0x10000;
+
+// Device types, from uapi/linux/if_arp.h.
+pub const ARPHRD_ETHER: u16 = 1;
+pub const ARPHRD_LOOPBACK: u16 = 772;
+
+// InterfaceAddrMessage is struct ifaddrmsg, from uapi/linux/if_addr.h.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct InterfaceAddrMessage {
+    pub Family: u8,
+    pub PrefixLen: u8,
+    pub Flags: u8,
+    pub Scope: u8,
+    pub Index: u32,
+}
+
+// Address attributes, from uapi/linux/if_addr.h.
+pub const IFA_UNSPEC: u16 = 0;
+pub const IFA_ADDRESS: u16 = 1;
+pub const IFA_LOCAL: u16 = 2;
+pub const IFA_LABEL: u16 = 3;
+pub const IFA_BROADCAST: u16 = 4;
+
+// Address flags, from uapi/linux/if_addr.h.
+pub const IFA_F_PERMANENT: u8 = 0x80;
+
+// RouteMessage is struct rtmsg, from uapi/linux/rtnetlink.h.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RouteMessage {
+    pub Family: u8,
+    pub DstLen: u8,
+    pub SrcLen: u8,
+    pub TOS: u8,
+    pub Table: u8,
+    pub Protocol: u8,
+    pub Scope: u8,
+    pub Type: u8,
+    pub Flags: u32,
+}
+
+// Route attributes, from uapi/linux/rtnetlink.h.
+pub const RTA_UNSPEC: u16 = 0;
+pub const RTA_DST: u16 = 1;
+pub const RTA_SRC: u16 = 2;
+pub const RTA_IIF: u16 = 3;
+pub const RTA_OIF: u16 = 4;
+pub const RTA_GATEWAY: u16 = 5;
+pub const RTA_PRIORITY: u16 = 6;
+pub const RTA_PREFSRC: u16 = 7;
+pub const RTA_TABLE: u16 = 15;
+
+// Route tables, from uapi/linux/rtnetlink.h.
+pub const RT_TABLE_UNSPEC: u8 = 0;
+pub const RT_TABLE_MAIN: u8 = 254;
+pub const RT_TABLE_LOCAL: u8 = 255;
+
+// Route protocols, from uapi/linux/rtnetlink.h.
+pub const RTPROT_UNSPEC: u8 = 0;
+pub const RTPROT_KERNEL: u8 = 2;
+pub const RTPROT_BOOT: u8 = 3;
+pub const RTPROT_STATIC: u8 = 4;
+
+// Route scopes, from uapi/linux/rtnetlink.h.
+pub const RT_SCOPE_UNIVERSE: u8 = 0;
+pub const RT_SCOPE_LINK: u8 = 253;
+pub const RT_SCOPE_HOST: u8 = 254;
+
+// Route types, from uapi/linux/rtnetlink.h.
+pub const RTN_UNSPEC: u8 = 0;
+pub const RTN_UNICAST: u8 = 1;
+pub const RTN_LOCAL: u8 = 2;
+
+// NeighborMessage is struct ndmsg, from uapi/linux/neighbour.h.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct NeighborMessage {
+    pub Family: u8,
+    pub Padding1: u8,
+    pub Padding2: u16,
+    pub Index: i32,
+    pub State: u16,
+    pub Flags: u8,
+    pub Type: u8,
+}
+
+// Neighbor attributes, from uapi/linux/neighbour.h.
+pub const NDA_UNSPEC: u16 = 0;
+pub const NDA_DST: u16 = 1;
+pub const NDA_LLADDR: u16 = 2;
+
+// Neighbor states, from uapi/linux/neighbour.h.
+pub const NUD_REACHABLE: u16 = 0x02;
+pub const NUD_PERMANENT: u16 = 0x80;
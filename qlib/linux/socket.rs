@@ -81,6 +81,31 @@ pub const SO_COOKIE: i32 = 57;
 pub const SO_PEERGROUPS: i32 = 59;
 pub const SO_ZEROCOPY: i32 = 60;
 pub const SO_TXTIME: i32 = 61;
+pub const SO_TIMESTAMP_NEW: i32 = 63;
+pub const SO_TIMESTAMPNS_NEW: i32 = 64;
+pub const SO_TIMESTAMPING_NEW: i32 = 65;
+
+// SO_TIMESTAMPING flags, from uapi/linux/net_tstamp.h.
+pub const SOF_TIMESTAMPING_TX_HARDWARE: u32 = 1 << 0;
+pub const SOF_TIMESTAMPING_TX_SOFTWARE: u32 = 1 << 1;
+pub const SOF_TIMESTAMPING_RX_HARDWARE: u32 = 1 << 2;
+pub const SOF_TIMESTAMPING_RX_SOFTWARE: u32 = 1 << 3;
+pub const SOF_TIMESTAMPING_SOFTWARE: u32 = 1 << 4;
+pub const SOF_TIMESTAMPING_SYS_HARDWARE: u32 = 1 << 5;
+pub const SOF_TIMESTAMPING_RAW_HARDWARE: u32 = 1 << 6;
+pub const SOF_TIMESTAMPING_OPT_ID: u32 = 1 << 7;
+pub const SOF_TIMESTAMPING_TX_SCHED: u32 = 1 << 8;
+pub const SOF_TIMESTAMPING_TX_ACK: u32 = 1 << 9;
+pub const SOF_TIMESTAMPING_OPT_CMSG: u32 = 1 << 10;
+pub const SOF_TIMESTAMPING_OPT_TSONLY: u32 = 1 << 11;
+
+// UDP socket options, from uapi/linux/udp.h.
+pub const UDP_CORK: i32 = 1;
+pub const UDP_SEGMENT: i32 = 103;
+pub const UDP_GRO: i32 = 104;
+
+// UDP_MAX_SEGMENTS is the maximum number of segments of a UDP_SEGMENT send.
+pub const UDP_MAX_SEGMENTS: usize = 64;
 
 // shutdown(2) how commands, from <linux/net.h>.
 pub const SHUT_RD: i32 = 0;
@@ -133,6 +133,185 @@ impl InotifyEvent {
             Self::IN_ONESHOT;
 }
 
+pub struct Fanotify {}
+
+impl Fanotify {
+    // Events that user-space can register for, see include/uapi/linux/fanotify.h.
+    pub const FAN_ACCESS : u64 = 0x00000001;
+    pub const FAN_MODIFY : u64 = 0x00000002;
+    pub const FAN_ATTRIB : u64 = 0x00000004;
+    pub const FAN_CLOSE_WRITE : u64 = 0x00000008;
+    pub const FAN_CLOSE_NOWRITE : u64 = 0x00000010;
+    pub const FAN_OPEN : u64 = 0x00000020;
+    pub const FAN_MOVED_FROM : u64 = 0x00000040;
+    pub const FAN_MOVED_TO : u64 = 0x00000080;
+    pub const FAN_CREATE : u64 = 0x00000100;
+    pub const FAN_DELETE : u64 = 0x00000200;
+    pub const FAN_DELETE_SELF : u64 = 0x00000400;
+    pub const FAN_MOVE_SELF : u64 = 0x00000800;
+    pub const FAN_OPEN_EXEC : u64 = 0x00001000;
+
+    pub const FAN_Q_OVERFLOW : u64 = 0x00004000;
+
+    pub const FAN_OPEN_PERM : u64 = 0x00010000;
+    pub const FAN_ACCESS_PERM : u64 = 0x00020000;
+    pub const FAN_OPEN_EXEC_PERM : u64 = 0x00040000;
+
+    pub const FAN_EVENT_ON_CHILD : u64 = 0x08000000;
+    pub const FAN_ONDIR : u64 = 0x40000000;
+
+    pub const FAN_CLOSE : u64 = Self::FAN_CLOSE_WRITE | Self::FAN_CLOSE_NOWRITE;
+    pub const FAN_MOVE : u64 = Self::FAN_MOVED_FROM | Self::FAN_MOVED_TO;
+
+    // Events which are only reported to groups with FAN_REPORT_FID as there is no
+    // file to open for them.
+    pub const FAN_DIRENT_EVENTS : u64 = Self::FAN_MOVE | Self::FAN_CREATE | Self::FAN_DELETE;
+    pub const FAN_INODE_EVENTS : u64 = Self::FAN_DIRENT_EVENTS | Self::FAN_ATTRIB | Self::FAN_DELETE_SELF | Self::FAN_MOVE_SELF;
+
+    pub const FAN_ALL_PERM_EVENTS : u64 = Self::FAN_OPEN_PERM | Self::FAN_ACCESS_PERM | Self::FAN_OPEN_EXEC_PERM;
+    pub const FAN_ALL_EVENTS : u64 = Self::FAN_ACCESS
+        | Self::FAN_MODIFY
+        | Self::FAN_CLOSE
+        | Self::FAN_OPEN
+        | Self::FAN_OPEN_EXEC
+        | Self::FAN_INODE_EVENTS;
+
+    // flags for fanotify_init(2)
+    pub const FAN_CLOEXEC : u32 = 0x00000001;
+    pub const FAN_NONBLOCK : u32 = 0x00000002;
+
+    pub const FAN_CLASS_NOTIF : u32 = 0x00000000;
+    pub const FAN_CLASS_CONTENT : u32 = 0x00000004;
+    pub const FAN_CLASS_PRE_CONTENT : u32 = 0x00000008;
+    pub const FAN_ALL_CLASS_BITS : u32 = Self::FAN_CLASS_NOTIF | Self::FAN_CLASS_CONTENT | Self::FAN_CLASS_PRE_CONTENT;
+
+    pub const FAN_UNLIMITED_QUEUE : u32 = 0x00000010;
+    pub const FAN_UNLIMITED_MARKS : u32 = 0x00000020;
+    pub const FAN_ENABLE_AUDIT : u32 = 0x00000040;
+    pub const FAN_REPORT_TID : u32 = 0x00000100;
+    pub const FAN_REPORT_FID : u32 = 0x00000200;
+
+    pub const FAN_ALL_INIT_FLAGS : u32 = Self::FAN_CLOEXEC
+        | Self::FAN_NONBLOCK
+        | Self::FAN_ALL_CLASS_BITS
+        | Self::FAN_UNLIMITED_QUEUE
+        | Self::FAN_UNLIMITED_MARKS
+        | Self::FAN_ENABLE_AUDIT
+        | Self::FAN_REPORT_TID
+        | Self::FAN_REPORT_FID;
+
+    // flags for fanotify_mark(2)
+    pub const FAN_MARK_ADD : u32 = 0x00000001;
+    pub const FAN_MARK_REMOVE : u32 = 0x00000002;
+    pub const FAN_MARK_DONT_FOLLOW : u32 = 0x00000004;
+    pub const FAN_MARK_ONLYDIR : u32 = 0x00000008;
+    pub const FAN_MARK_IGNORED_MASK : u32 = 0x00000020;
+    pub const FAN_MARK_IGNORED_SURV_MODIFY : u32 = 0x00000040;
+    pub const FAN_MARK_FLUSH : u32 = 0x00000080;
+
+    pub const FAN_MARK_INODE : u32 = 0x00000000;
+    pub const FAN_MARK_MOUNT : u32 = 0x00000010;
+    pub const FAN_MARK_FILESYSTEM : u32 = 0x00000100;
+    pub const FAN_MARK_TYPE_MASK : u32 = Self::FAN_MARK_INODE | Self::FAN_MARK_MOUNT | Self::FAN_MARK_FILESYSTEM;
+
+    pub const FAN_ALL_MARK_FLAGS : u32 = Self::FAN_MARK_ADD
+        | Self::FAN_MARK_REMOVE
+        | Self::FAN_MARK_DONT_FOLLOW
+        | Self::FAN_MARK_ONLYDIR
+        | Self::FAN_MARK_IGNORED_MASK
+        | Self::FAN_MARK_IGNORED_SURV_MODIFY
+        | Self::FAN_MARK_FLUSH
+        | Self::FAN_MARK_TYPE_MASK;
+
+    pub const FANOTIFY_METADATA_VERSION : u8 = 3;
+
+    pub const FAN_EVENT_INFO_TYPE_FID : u8 = 1;
+
+    // responses written to the fanotify fd for permission events
+    pub const FAN_ALLOW : u32 = 0x01;
+    pub const FAN_DENY : u32 = 0x02;
+    pub const FAN_AUDIT : u32 = 0x10;
+
+    pub const FAN_NOFD : i32 = -1;
+
+    pub const FANOTIFY_DEFAULT_MAX_EVENTS : usize = 16384;
+    pub const FANOTIFY_DEFAULT_MAX_MARKS : usize = 8192;
+}
+
+// Commands and flags for quotactl(2), from include/uapi/linux/quota.h.
+pub struct Quota {}
+
+impl Quota {
+    pub const SUBCMDMASK : u32 = 0x00ff;
+    pub const SUBCMDSHIFT : u32 = 8;
+
+    pub const USRQUOTA : u32 = 0;
+    pub const GRPQUOTA : u32 = 1;
+    pub const PRJQUOTA : u32 = 2;
+    pub const MAXQUOTAS : u32 = 3;
+
+    pub const Q_SYNC : u32 = 0x800001;
+    pub const Q_QUOTAON : u32 = 0x800002;
+    pub const Q_QUOTAOFF : u32 = 0x800003;
+    pub const Q_GETFMT : u32 = 0x800004;
+    pub const Q_GETINFO : u32 = 0x800005;
+    pub const Q_SETINFO : u32 = 0x800006;
+    pub const Q_GETQUOTA : u32 = 0x800007;
+    pub const Q_SETQUOTA : u32 = 0x800008;
+    pub const Q_GETNEXTQUOTA : u32 = 0x800009;
+
+    pub const QFMT_VFS_V1 : u32 = 4;
+
+    // QIF_DQBLKSIZE is the size of the blocks of the limits of struct if_dqblk.
+    pub const QIF_DQBLKSIZE : u64 = 1024;
+
+    pub const QIF_BLIMITS : u32 = 1;
+    pub const QIF_SPACE : u32 = 2;
+    pub const QIF_ILIMITS : u32 = 4;
+    pub const QIF_INODES : u32 = 8;
+    pub const QIF_BTIME : u32 = 16;
+    pub const QIF_ITIME : u32 = 32;
+    pub const QIF_LIMITS : u32 = Self::QIF_BLIMITS | Self::QIF_ILIMITS;
+    pub const QIF_USAGE : u32 = Self::QIF_SPACE | Self::QIF_INODES;
+
+    pub const IIF_BGRACE : u32 = 1;
+    pub const IIF_IGRACE : u32 = 2;
+    pub const IIF_FLAGS : u32 = 4;
+
+    pub fn Cmd(cmd: u32) -> u32 {
+        return cmd >> Self::SUBCMDSHIFT;
+    }
+
+    pub fn Type(cmd: u32) -> u32 {
+        return cmd & Self::SUBCMDMASK;
+    }
+}
+
+// IfDqblk is struct if_dqblk from linux.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct IfDqblk {
+    pub dqb_bhardlimit: u64,
+    pub dqb_bsoftlimit: u64,
+    pub dqb_curspace: u64,
+    pub dqb_ihardlimit: u64,
+    pub dqb_isoftlimit: u64,
+    pub dqb_curinodes: u64,
+    pub dqb_btime: u64,
+    pub dqb_itime: u64,
+    pub dqb_valid: u32,
+}
+
+// IfDqinfo is struct if_dqinfo from linux.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct IfDqinfo {
+    pub dqi_bgrace: u64,
+    pub dqi_igrace: u64,
+    pub dqi_flags: u32,
+    pub dqi_valid: u32,
+}
+
 // Scheduling policies, exposed by sched_getscheduler(2)/sched_setscheduler(2).
 pub struct Sched {}
 
@@ -46,4 +46,105 @@ pub struct Process {
     pub Root: String,
     pub Stdiofds: [i32; 3],
     pub ExecId: Option<String>,
+
+    // disk quotas of the container mounts
+    pub DiskQuotas: Vec<MountQuota>,
+
+    // network interfaces of the sandbox, reported by the netlink route
+    // sockets
+    pub Interfaces: Vec<NetInterface>,
+
+    // the egress/ingress firewall of the sandbox sockets: the host policy and
+    // the policy of the sandbox spec, a call must be allowed by all of them
+    pub Firewall: Vec<FirewallPolicy>,
+
+    // the host unix sockets the sandbox may connect to
+    pub HostUnixSockets: Vec<HostUnixSocket>,
+
+    // the cap of the traffic the container sends, in bytes per second. 0
+    // means no cap.
+    pub EgressBandwidth: u64,
+}
+
+// MountQuota is the disk quota of a mount, set with the quota_bytes and
+// quota_inodes options of the mount in the container spec. 0 means no limit.
+#[derive(Serialize, Deserialize, Default, Debug, Eq, PartialEq, Clone)]
+pub struct MountQuota {
+    pub Path: String,
+    pub Bytes: u64,
+    pub Inodes: u64,
+}
+
+// NetInterface is a sandbox network interface, taken from the pod network
+// namespace or set with the io.quark.network.interfaces annotation of the
+// sandbox spec.
+#[derive(Serialize, Deserialize, Default, Debug, Eq, PartialEq, Clone)]
+pub struct NetInterface {
+    pub Name: String,
+    pub Mac: [u8; 6],
+    pub Mtu: u32,
+    pub Addrs: Vec<NetAddr>,
+    // the default routes of the interface
+    pub Gateways: Vec<Vec<u8>>,
+}
+
+// NetAddr is an ipv4 (4 bytes) or ipv6 (16 bytes) interface address.
+#[derive(Serialize, Deserialize, Default, Debug, Eq, PartialEq, Clone)]
+pub struct NetAddr {
+    pub Addr: Vec<u8>,
+    pub PrefixLen: u8,
+}
+
+// HostUnixSocket is a host unix socket reachable from the sandbox, a connect
+// to GuestPath in the sandbox is a connect to HostPath on the host.
+#[derive(Serialize, Deserialize, Default, Debug, Eq, PartialEq, Clone)]
+pub struct HostUnixSocket {
+    pub GuestPath: String,
+    pub HostPath: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy)]
+pub enum FirewallDirection {
+    // connect, sendto and sendmsg, matched with the remote address
+    Egress,
+    // accept, matched with the remote address
+    Ingress,
+    // bind and listen, matched with the local address
+    Bind,
+}
+
+// FirewallRule matches the addresses in Addr/PrefixLen and the ports in
+// [PortLo, PortHi]. An empty Addr matches any address.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct FirewallRule {
+    pub Direction: FirewallDirection,
+    pub Allow: bool,
+    pub Addr: Vec<u8>,
+    pub PrefixLen: u8,
+    pub PortLo: u16,
+    pub PortHi: u16,
+}
+
+// FirewallPolicy is the socket firewall of the sandbox, the first matching
+// rule of a direction applies, then the default of the direction.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct FirewallPolicy {
+    pub Rules: Vec<FirewallRule>,
+    pub DefaultEgress: bool,
+    pub DefaultIngress: bool,
+    pub DefaultBind: bool,
+    // deny the loopback destinations which are not served by the sandbox
+    pub NoLoopbackToHost: bool,
+}
+
+impl Default for FirewallPolicy {
+    fn default() -> Self {
+        return Self {
+            Rules: Vec::new(),
+            DefaultEgress: true,
+            DefaultIngress: true,
+            DefaultBind: true,
+            NoLoopbackToHost: false,
+        };
+    }
 }
@@ -186,6 +186,12 @@ impl<T: Clone> LruCache<T> {
     }
 
     pub fn Add(&mut self, key: u64, d: T) {
+        self.Push(key, d);
+    }
+
+    // Push adds the item as the most recently used one and returns the least
+    // recently used item if it has been evicted to make room for it.
+    pub fn Push(&mut self, key: u64, d: T) -> Option<(u64, T)> {
         assert!(
             self.currentSize == self.list.count,
             "LruCache add mismatch, self.currentSize is {}, self.list.count is {}, map",
@@ -193,19 +199,16 @@ impl<T: Clone> LruCache<T> {
             self.list.count
         );
 
+        let mut evicted = None;
         let exist = if !self.map.contains_key(&key) {
             if self.currentSize >= self.maxSize {
                 //remove the last one
                 //error!("LruCache pop self.currentSize is {} self.maxSize is {}",
                 //    self.currentSize, self.maxSize);
-                let remove = self.list.PopBack();
-                let remove = match remove {
-                    None => panic!("get zero size"),
-                    Some(r) => r,
-                };
-                let removeKey = (*remove).lock().GetKey();
-                self.map.remove(&removeKey);
-                self.currentSize -= 1;
+                evicted = self.PopBack();
+                if evicted.is_none() {
+                    panic!("get zero size");
+                }
             }
 
             let entry = Arc::new(QMutex::new(LinkEntry::New(key, d)));
@@ -228,11 +231,22 @@ impl<T: Clone> LruCache<T> {
                 }
             }
             self.list.PushFront(entry);
-            return;
+            return None;
         }
 
         self.list.PushFront(entry);
         self.currentSize += 1;
+        return evicted;
+    }
+
+    // PopBack removes the least recently used item.
+    pub fn PopBack(&mut self) -> Option<(u64, T)> {
+        let remove = self.list.PopBack()?;
+        let removeKey = (*remove).lock().GetKey();
+        self.map.remove(&removeKey);
+        self.currentSize -= 1;
+        let val = (*remove).lock().val.take().unwrap();
+        return Some((removeKey, val));
     }
 
     //ret: true- exit the item, false-not exist
@@ -287,8 +301,11 @@ impl<T: Clone> LruCache<T> {
     }
 
     pub fn SetMaxSize(&mut self, max: u64) {
-        //todo: shrink the cache
+        assert!(max > 0, "LruCache maxsize must be larger than 0");
         self.maxSize = max;
+        while self.currentSize > self.maxSize {
+            self.PopBack();
+        }
     }
 }
 
@@ -322,4 +339,22 @@ mod tests {
         assert!(cache.Get(3) == None);
         assert!(cache.Get(4) == Some(4));
     }
+
+    #[test]
+    fn test_cache_evict() {
+        let mut cache = LruCache::New(3);
+        assert!(cache.Push(1, 1) == None);
+        assert!(cache.Push(2, 2) == None);
+        assert!(cache.Push(3, 3) == None);
+        assert!(cache.Push(1, 1) == None);
+        assert!(cache.Push(4, 4) == Some((2, 2)));
+
+        assert!(cache.PopBack() == Some((3, 3)));
+        assert!(cache.Size() == 2);
+
+        cache.SetMaxSize(1);
+        assert!(cache.Size() == 1);
+        assert!(cache.Get(1) == None);
+        assert!(cache.Get(4) == Some(4));
+    }
 }
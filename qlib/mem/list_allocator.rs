@@ -418,6 +418,12 @@ impl ListAllocator {
         return false;
     }
 
+    // HeapUsage returns the bytes allocated from the heap and the size of the heap.
+    pub fn HeapUsage(&self) -> (usize, usize) {
+        let heap = self.heap.lock();
+        return (heap.stats_alloc_actual(), heap.stats_total_bytes());
+    }
+
     // ret: true: free some memory, false: no memory freed
     pub fn Free(&self) -> bool {
         let mut count = 0;
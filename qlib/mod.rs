@@ -47,6 +47,7 @@ pub mod path;
 pub mod perf_tunning;
 pub mod platform;
 pub mod qmsg;
+pub mod quantity;
 pub mod singleton;
 pub mod socket_buf;
 pub mod sort_arr;
@@ -95,6 +95,9 @@ pub enum Msg {
     IORecvfrom(IORecvfrom),
     IOSendMsg(IOSendMsg),
     IOSendto(IOSendto),
+    IORecvMMsg(IORecvMMsg),
+    IOSendMMsg(IOSendMMsg),
+    HostUnixConnect(HostUnixConnect),
     MMapFile(MMapFile),
     MUnmap(MUnmap),
     NonBlockingPoll(NonBlockingPoll),
@@ -121,7 +124,14 @@ pub enum Msg {
     SwapInPage(SwapInPage),
     SwapOut(SwapOut),
     SwapIn(SwapIn),
+    Checkpoint(Checkpoint),
     Proxy(Proxy),
+    HostInotifyInit(HostInotifyInit),
+    HostInotifyAddWatch(HostInotifyAddWatch),
+    HostInotifyRmWatch(HostInotifyRmWatch),
+    HostNameToHandleAt(HostNameToHandleAt),
+    HostOpenByHandleAt(HostOpenByHandleAt),
+    NetstackLinkInit(NetstackLinkInit),
 }
 
 #[derive(Clone, Default, Debug)]
@@ -131,12 +141,93 @@ pub struct Proxy {
     pub addrOut: u64,
 }
 
+#[derive(Clone, Default, Debug)]
+pub struct HostInotifyInit {}
+
+#[derive(Clone, Default, Debug)]
+pub struct HostInotifyAddWatch {
+    pub inotifyfd: i32,
+    pub fd: i32,
+    pub mask: u32,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct HostInotifyRmWatch {
+    pub inotifyfd: i32,
+    pub wd: i32,
+}
+
+pub const MAX_HANDLE_SZ: usize = 128;
+
+// HostFileHandle has the layout of linux's struct file_handle with the
+// largest handle the host could return.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct HostFileHandle {
+    pub handleBytes: u32,
+    pub handleType: i32,
+    pub handle: [u8; MAX_HANDLE_SZ],
+}
+
+impl Default for HostFileHandle {
+    fn default() -> Self {
+        return Self {
+            handleBytes: MAX_HANDLE_SZ as u32,
+            handleType: 0,
+            handle: [0; MAX_HANDLE_SZ],
+        };
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct HostNameToHandleAt {
+    pub fd: i32,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct HostOpenByHandleAt {
+    pub mountfd: i32,
+    pub id: u64,
+    pub readonly: bool,
+    pub addr: u64,
+}
+
+// NetstackNicInfo describes the sandbox network interface taken over by the
+// guest netstack.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct NetstackNicInfo {
+    pub mac: [u8; 6],
+    pub mtu: u32,
+    pub addr: [u8; 4],
+    pub prefixLen: u32,
+    pub gateway: [u8; 4],
+    pub addr6: [u8; 16],
+    pub prefixLen6: u32,
+    pub gateway6: [u8; 16],
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct NetstackLinkInit {
+    pub addr: u64,
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct SwapOut {}
 
 #[derive(Clone, Default, Debug)]
 pub struct SwapIn {}
 
+#[derive(Clone, Default, Debug)]
+pub struct Checkpoint {
+    // the image directory path
+    pub dir: u64,
+}
+
+// the Checkpoint return of a sandbox restored from the image, the sandbox
+// which takes the checkpoint gets 0
+pub const CHECKPOINT_RESTORED: u64 = 1;
+
 #[derive(Clone, Default, Debug)]
 pub struct SwapInPage {
     pub addr: u64,
@@ -788,6 +879,31 @@ pub struct IOSendto {
     pub len: u32,
 }
 
+#[derive(Clone, Default, Debug)]
+pub struct IORecvMMsg {
+    pub fd: i32,
+    //address of MMsgHdr array
+    pub msgvec: u64,
+    pub vlen: u32,
+    pub flags: i32,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct IOSendMMsg {
+    pub fd: i32,
+    //address of MMsgHdr array
+    pub msgvec: u64,
+    pub vlen: u32,
+    pub flags: i32,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct HostUnixConnect {
+    //index of the socket in the host unix socket allowlist
+    pub idx: usize,
+    pub stype: i32,
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct NewSocket {
     pub fd: i32,
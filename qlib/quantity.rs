@@ -0,0 +1,96 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// ParseBandwidth parses a kubernetes bandwidth quantity in bits per second,
+// e.g. "500k", "10M", "1.5G" or "1Mi". 0 and the quantities with an unknown
+// suffix are invalid.
+pub fn ParseBandwidth(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (num, suffix) = s.split_at(split);
+    let mult: u64 = match suffix {
+        "" => 1,
+        "k" => 1_000,
+        "M" => 1_000_000,
+        "G" => 1_000_000_000,
+        "T" => 1_000_000_000_000,
+        "P" => 1_000_000_000_000_000,
+        "Ki" => 1 << 10,
+        "Mi" => 1 << 20,
+        "Gi" => 1 << 30,
+        "Ti" => 1 << 40,
+        "Pi" => 1 << 50,
+        _ => return None,
+    };
+
+    let (int, frac) = match num.find('.') {
+        Some(i) => (&num[..i], &num[i + 1..]),
+        None => (num, ""),
+    };
+    if int.len() == 0 && frac.len() == 0 {
+        return None;
+    }
+
+    let mut bps: u64 = if int.len() == 0 {
+        0
+    } else {
+        int.parse::<u64>().ok()?.checked_mul(mult)?
+    };
+    if frac.len() > 0 {
+        // the digits below the unit are dropped
+        let frac = &frac[..frac.len().min(18)];
+        let fracBps = frac.parse::<u128>().ok()? * mult as u128 / 10u128.pow(frac.len() as u32);
+        bps = bps.checked_add(fracBps as u64)?;
+    }
+
+    if bps == 0 {
+        return None;
+    }
+    return Some(bps);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ParseBandwidth() {
+        assert_eq!(ParseBandwidth("100"), Some(100));
+        assert_eq!(ParseBandwidth(" 10M "), Some(10_000_000));
+        assert_eq!(ParseBandwidth("1.5k"), Some(1_500));
+        assert_eq!(ParseBandwidth(".5M"), Some(500_000));
+        assert_eq!(ParseBandwidth("2G"), Some(2_000_000_000));
+        assert_eq!(ParseBandwidth("1Ki"), Some(1024));
+        assert_eq!(ParseBandwidth("3Mi"), Some(3 << 20));
+        assert_eq!(ParseBandwidth("1.5Ki"), Some(1536));
+        assert_eq!(ParseBandwidth("1Ti"), Some(1 << 40));
+    }
+
+    #[test]
+    fn test_ParseBandwidthInvalid() {
+        assert_eq!(ParseBandwidth(""), None);
+        assert_eq!(ParseBandwidth("0"), None);
+        assert_eq!(ParseBandwidth("0M"), None);
+        assert_eq!(ParseBandwidth("-1M"), None);
+        assert_eq!(ParseBandwidth("10m"), None);
+        assert_eq!(ParseBandwidth("10MB"), None);
+        assert_eq!(ParseBandwidth("1e6"), None);
+        assert_eq!(ParseBandwidth("M"), None);
+        assert_eq!(ParseBandwidth("."), None);
+        assert_eq!(ParseBandwidth("1.2.3M"), None);
+        assert_eq!(ParseBandwidth("20000P"), None);
+    }
+}
@@ -157,6 +157,7 @@ pub enum RDMAReqMsg {
     RDMAListenUsingPodId(RDMAListenReqUsingPodId),
     RDMAConnect(RDMAConnectReq),
     RDMAConnectUsingPodId(RDMAConnectReqUsingPodId),
+    RDMAConnectUsingPodIdV6(RDMAConnectReqUsingPodIdV6),
     RDMAWrite(RDMAWriteReq),
     RDMARead(RDMAReadReq),
     RDMAShutdown(RDMAShutdownReq),
@@ -176,6 +177,7 @@ impl Default for RDMAReqMsg {
 #[derive(Clone, Copy, Debug)]
 pub enum RDMARespMsg {
     RDMAConnect(RDMAConnectResp),
+    RDMAConnectFail(RDMAConnectFailResp),
     RDMAAccept(RDMAAcceptResp),
     RDMANotify(RDMANotifyResp),
     RDMAFinNotify(RDMAFinNotifyResp),
@@ -189,6 +191,13 @@ impl Default for RDMARespMsg {
     }
 }
 
+// the connect is rejected, e.g. denied by a network policy
+#[derive(Default, Clone, Copy, Debug)]
+pub struct RDMAConnectFailResp {
+    pub sockfd: u32,
+    pub error: i32,
+}
+
 #[derive(Default, Clone, Copy, Debug)]
 pub struct RDMAConnectResp {
     pub sockfd: u32,
@@ -198,6 +207,9 @@ pub struct RDMAConnectResp {
     pub dstPort: u16,
     pub srcIpAddr: u32,
     pub srcPort: u16,
+    // the addresses of an ipv6 connection, all zero for ipv4
+    pub dstIpv6Addr: [u8; 16],
+    pub srcIpv6Addr: [u8; 16],
 }
 
 #[derive(Default, Clone, Copy, Debug)]
@@ -209,6 +221,9 @@ pub struct RDMAAcceptResp {
     pub dstPort: u16,
     pub srcIpAddr: u32,
     pub srcPort: u16,
+    // the addresses of an ipv6 connection, all zero for ipv4
+    pub dstIpv6Addr: [u8; 16],
+    pub srcIpv6Addr: [u8; 16],
 }
 
 #[derive(Default, Clone, Copy, Debug)]
@@ -230,7 +245,8 @@ pub struct RDMARecvUDPPacket {
 
 pub const FIN_RECEIVED_FROM_PEER: EventMask = 0x01;
 pub const FIN_SENT_TO_PEER: EventMask = 0x02;
-// pub const EVENT_OUT: EventMask = 0x04; // POLLOUT
+// the RDMA connection to the peer node is broken, the channel is reset
+pub const CONNECTION_RESET: EventMask = 0x04;
 // pub const EVENT_ERR: EventMask = 0x08; // POLLERR
 // pub const EVENT_HUP: EventMask = 0x10; // POLLHUP
 
@@ -301,6 +317,9 @@ pub struct RDMAConnectReq {
     pub dstPort: u16,
     pub srcIpAddr: u32,
     pub srcPort: u16,
+    // the addresses of an ipv6 connection, all zero for ipv4
+    pub dstIpv6Addr: [u8; 16],
+    pub srcIpv6Addr: [u8; 16],
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -313,6 +332,22 @@ pub struct RDMAConnectReqUsingPodId {
     pub srcPort: u16,
 }
 
+// IsIpv6 returns whether the ipv6 address of a connection is set, the ipv6
+// addresses of an ipv4 connection are all zero
+pub fn IsIpv6(addr: &[u8; 16]) -> bool {
+    return addr.iter().any(|b| *b != 0);
+}
+
+// the connect to an ipv6 address, dstIpAddr is in network order
+#[derive(Clone, Copy, Debug)]
+pub struct RDMAConnectReqUsingPodIdV6 {
+    pub sockfd: u32,
+    pub dstIpAddr: [u8; 16],
+    pub dstPort: u16,
+    pub podId: [u8; 64],
+    pub srcPort: u16,
+}
+
 #[derive(Default, Clone, Copy, Debug)]
 pub struct RDMAAcceptReq {
     //pub vpcId: u32,
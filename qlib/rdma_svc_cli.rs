@@ -133,6 +133,8 @@ impl RDMASvcClient {
             dstPort,
             srcIpAddr, //101099712, //u32::from(Ipv4Addr::from_str("192.168.6.6").unwrap()).to_be(),
             srcPort,   //16866u16.to_be(),
+            dstIpv6Addr: [0; 16],
+            srcIpv6Addr: [0; 16],
         }));
         res
     }
@@ -156,6 +158,27 @@ impl RDMASvcClient {
         res
     }
 
+    // connectUsingPodIdV6 connects to an ipv6 address, dstIpAddr is in network
+    // order
+    pub fn connectUsingPodIdV6(
+        &self,
+        sockfd: u32,
+        dstIpAddr: [u8; 16],
+        dstPort: u16,
+        srcPort: u16,
+    ) -> Result<()> {
+        let res = self.SentMsgToSvc(RDMAReqMsg::RDMAConnectUsingPodIdV6(
+            RDMAConnectReqUsingPodIdV6 {
+                sockfd,
+                dstIpAddr,
+                dstPort,
+                podId: self.podId,
+                srcPort,
+            },
+        ));
+        res
+    }
+
     pub fn read(&self, channelId: u32) -> Result<()> {
         // println!("rdmaSvcCli::read 1");
         if self.cliShareRegion.lock().sq.Push(RDMAReq {
@@ -339,6 +362,8 @@ impl RDMASvcClient {
                                         response.srcPort,
                                         response.dstIpAddr,
                                         response.dstPort,
+                                        response.srcIpv6Addr,
+                                        response.dstIpv6Addr,
                                     );
                                     self.channelToSocketMappings
                                         .lock()
@@ -362,6 +387,32 @@ impl RDMASvcClient {
                                 }
                             }
                         }
+                        RDMARespMsg::RDMAConnectFail(response) => {
+                            let sockfd = match self
+                                .rdmaIdToSocketMappings
+                                .lock()
+                                .remove(&response.sockfd)
+                            {
+                                Some(sockFdVal) => sockFdVal,
+                                None => {
+                                    debug!("RDMARespMsg::RDMAConnectFail, Can't find sockfd based on rdmaId: {}", response.sockfd);
+                                    continue;
+                                }
+                            };
+
+                            let fdInfo = GlobalIOMgr().GetByHost(sockfd).unwrap();
+                            let sockInfo = fdInfo.lock().sockInfo.lock().clone();
+                            match sockInfo {
+                                SockInfo::Socket(mut info) => {
+                                    info.error = response.error;
+                                    *fdInfo.lock().sockInfo.lock() = SockInfo::Socket(info);
+                                    fdInfo.lock().waitInfo.Notify(EVENT_OUT | EVENT_ERR);
+                                }
+                                _ => {
+                                    panic!("RDMARespMsg::RDMAConnectFail, SockInfo is not correct type: {:?}", sockInfo);
+                                }
+                            }
+                        }
                         RDMARespMsg::RDMAAccept(response) => {
                             let sockfd = match self
                                 .rdmaIdToSocketMappings
@@ -419,6 +470,8 @@ impl RDMASvcClient {
                                         response.srcPort,
                                         response.dstIpAddr,
                                         response.dstPort,
+                                        response.srcIpv6Addr,
+                                        response.dstIpv6Addr,
                                     );
 
                                     *GlobalIOMgr()
@@ -428,12 +481,22 @@ impl RDMASvcClient {
                                         .sockInfo
                                         .lock() = SockInfo::RDMADataSocket(dataSock);
 
-                                    let sockAddr = SockAddr::Inet(SockAddrInet {
-                                        Family: AFType::AF_INET as u16,
-                                        Port: response.dstPort,
-                                        Addr: response.dstIpAddr.to_be_bytes(),
-                                        Zero: [0; 8],
-                                    });
+                                    let sockAddr = if IsIpv6(&response.dstIpv6Addr) {
+                                        SockAddr::Inet6(SocketAddrInet6 {
+                                            Family: AFType::AF_INET6 as u16,
+                                            Port: response.dstPort,
+                                            Flowinfo: 0,
+                                            Addr: response.dstIpv6Addr,
+                                            Scope_id: 0,
+                                        })
+                                    } else {
+                                        SockAddr::Inet(SockAddrInet {
+                                            Family: AFType::AF_INET as u16,
+                                            Port: response.dstPort,
+                                            Addr: response.dstIpAddr.to_be_bytes(),
+                                            Zero: [0; 8],
+                                        })
+                                    };
                                     let mut tcpSockAddr = TcpSockAddr::default();
                                     let len = sockAddr.Len();
                                     let _res = sockAddr.Marsh(&mut tcpSockAddr.data, len);
@@ -558,14 +621,21 @@ impl RDMASvcClient {
                                 let sockInfo = fdInfo.unwrap().lock().sockInfo.lock().clone();
                                 match sockInfo {
                                     SockInfo::RDMADataSocket(dataSock) => {
-                                        dataSock.socketBuf.SetRClosed();
                                         let waitInfo = GlobalIOMgr()
                                             .GetByHost(sockFd)
                                             .unwrap()
                                             .lock()
                                             .waitInfo
                                             .clone();
-                                        waitInfo.Notify(EVENT_IN);
+                                        if response.event & CONNECTION_RESET != 0 {
+                                            dataSock.socketBuf.SetErr(SysErr::ECONNRESET);
+                                            dataSock.socketBuf.SetRClosed();
+                                            dataSock.socketBuf.SetWClosed();
+                                            waitInfo.Notify(EVENT_IN | EVENT_OUT | EVENT_ERR | EVENT_HUP);
+                                        } else {
+                                            dataSock.socketBuf.SetRClosed();
+                                            waitInfo.Notify(EVENT_IN);
+                                        }
                                     }
                                     _ => {
                                         panic!("RDMARespMsg::RDMAFinNotify, Unexpected sockInfo type: {:?}", sockInfo);
@@ -24,6 +24,7 @@ pub struct RDMAServerSockIntern {
     pub rdmaId: u32,
     pub acceptQueue: AcceptQueue,
     pub ipAddr: u32,
+    pub ipv6Addr: [u8; 16],
     pub port: u16,
 }
 
@@ -39,11 +40,18 @@ impl Deref for RDMAServerSock {
 }
 
 impl RDMAServerSock {
-    pub fn New(rdmaId: u32, acceptQueue: AcceptQueue, ipAddr: u32, port: u16) -> Self {
+    pub fn New(
+        rdmaId: u32,
+        acceptQueue: AcceptQueue,
+        ipAddr: u32,
+        ipv6Addr: [u8; 16],
+        port: u16,
+    ) -> Self {
         return Self(Arc::new(RDMAServerSockIntern {
             rdmaId,
             acceptQueue: acceptQueue,
             ipAddr,
+            ipv6Addr,
             port,
         }));
     }
@@ -75,6 +83,9 @@ pub struct RDMADataSockIntern {
     pub localPort: u16,
     pub peerIpAddr: u32,
     pub peerPort: u16,
+    // the addresses of an ipv6 connection, all zero for ipv4
+    pub localIpv6Addr: [u8; 16],
+    pub peerIpv6Addr: [u8; 16],
 }
 
 pub enum RDMAType {
@@ -104,6 +115,8 @@ impl RDMADataSock {
         localPort: u16,
         peerIpAddr: u32,
         peerPort: u16,
+        localIpv6Addr: [u8; 16],
+        peerIpv6Addr: [u8; 16],
     ) -> Self {
         if RDMA_ENABLE {
             return Self(Arc::new(RDMADataSockIntern {
@@ -115,6 +128,8 @@ impl RDMADataSock {
                 localPort,
                 peerIpAddr,
                 peerPort,
+                localIpv6Addr,
+                peerIpv6Addr,
             }));
         } else {
             return Self(Arc::new(RDMADataSockIntern {
@@ -126,6 +141,8 @@ impl RDMADataSock {
                 localPort,
                 peerIpAddr,
                 peerPort,
+                localIpv6Addr,
+                peerIpv6Addr,
             }));
         }
     }
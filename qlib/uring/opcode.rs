@@ -553,13 +553,14 @@ opcode!(
         msg: { *const u64 },
         ;;
         ioprio: u16 = 0,
-        flags: u32 = 0
+        flags: u32 = 0,
+        buf_group: u16 = 0
     }
 
     pub const CODE = sys::IORING_OP_RECVMSG;
 
     pub fn build(self) -> Entry {
-        let RecvMsg { fd, msg, ioprio, flags } = self;
+        let RecvMsg { fd, msg, ioprio, flags, buf_group } = self;
 
         let mut sqe = sqe_zeroed();
         sqe.opcode = Self::CODE;
@@ -568,6 +569,7 @@ opcode!(
         sqe.__bindgen_anon_2.addr = msg as _;
         sqe.len = 1;
         sqe.__bindgen_anon_3.msg_flags = flags;
+        sqe.__bindgen_anon_4.__bindgen_anon_1.__bindgen_anon_1.buf_group = buf_group;
         Entry(sqe)
     }
 );
@@ -1110,7 +1112,6 @@ opcode!(
     }
 );
 
-#[cfg(feature = "unstable")]
 opcode!(
     pub struct ProvideBuffers {
         addr: { *mut u8 },
@@ -1137,7 +1138,6 @@ opcode!(
     }
 );
 
-#[cfg(feature = "unstable")]
 opcode!(
     pub struct RemoveBuffers {
         nbufs: { u16 },
@@ -44,6 +44,9 @@ pub const IORING_FSYNC_DATASYNC: u32 = 1;
 pub const IORING_TIMEOUT_ABS: u32 = 1;
 pub const SPLICE_F_FD_IN_FIXED: u32 = 2147483648;
 pub const IORING_CQE_F_BUFFER: u32 = 1;
+pub const IORING_CQE_F_MORE: u32 = 2;
+pub const IORING_CQE_BUFFER_SHIFT: u32 = 16;
+pub const IORING_RECV_MULTISHOT: u16 = 2;
 pub const IORING_OFF_SQ_RING: u32 = 0;
 pub const IORING_OFF_CQ_RING: u32 = 134217728;
 pub const IORING_OFF_SQES: u32 = 268435456;
@@ -7,6 +7,7 @@ use libc;
 use super::qlib::linux_def::MemoryDef;
 use super::qlib::mem::list_allocator::*;
 use super::qlib::mem::bitmap_allocator::*;
+use super::vmspace::checkpoint::LoadHeap;
 
 pub const ENABLE_HUGEPAGE: bool = false;
 
@@ -88,11 +89,16 @@ impl HostAllocator {
 
         let heapStart = self.listHeapAddr.load(Ordering::Relaxed);
         let heapEnd = heapStart + heapSize as u64;
-        *self.Allocator() = ListAllocator::New(heapStart as _, heapEnd);
 
-        // reserve first 4KB gor the listAllocator
-        let size = core::mem::size_of::<ListAllocator>();
-        self.Allocator().Add(addr as usize + size, heapSize - size);
+        // a restored sandbox gets the heap with the ListAllocator from the
+        // checkpoint image
+        if !LoadHeap(heapStart, heapEnd) {
+            *self.Allocator() = ListAllocator::New(heapStart as _, heapEnd);
+
+            // reserve first 4KB gor the listAllocator
+            let size = core::mem::size_of::<ListAllocator>();
+            self.Allocator().Add(addr as usize + size, heapSize - size);
+        }
         self.initialized.store(true, Ordering::Relaxed);
     }
 
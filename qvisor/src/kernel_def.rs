@@ -140,6 +140,25 @@ impl ShareSpace {
         super::vmspace::VMSpace::BlockFd(controlSock);
     }
 
+    // Restore sets the host fds of a ShareSpace from a checkpoint image, the
+    // fds of the checkpointed sandbox process are gone.
+    pub fn Restore(&mut self, controlSock: i32) {
+        self.scheduler.Init();
+        self.SetLogfd(super::print::LOG.Logfd());
+        self.hostEpollfd
+            .store(FD_NOTIFIER.Epollfd(), Ordering::SeqCst);
+        self.controlSock = controlSock;
+        super::vmspace::VMSpace::BlockFd(controlSock);
+
+        let mut hiberMgr = self.hiberMgr.lock();
+        hiberMgr.reapSwapFile.fd = 0;
+        let pageMap = &mut hiberMgr.pageMap;
+        super::SWAP_FILE
+            .lock()
+            .Restore(pageMap)
+            .expect("ShareSpace::Restore fail to swap out the pages");
+    }
+
     pub fn TlbShootdown(&self, vcpuMask: u64) -> u64 {
         //let start_time = std::time::Instant::now();
         let vcpu_len = self.scheduler.VcpuArr.len();
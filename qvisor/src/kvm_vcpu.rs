@@ -47,6 +47,7 @@ use super::qlib::task_mgr::*;
 use super::qlib::vcpu_mgr::*;
 use super::runc::runtime::vm::*;
 use super::syncmgr::*;
+use super::vmspace::checkpoint::*;
 use super::URING_MGR;
 
 #[repr(C)]
@@ -147,6 +148,9 @@ pub struct KVMVcpu {
 
     pub autoStart: bool,
     pub interrupting: Mutex<(bool, Vec<Sender<()>>)>,
+
+    // the registers of a vcpu restored from a checkpoint
+    pub restoreState: Option<VcpuState>,
 }
 
 //for pub shareSpace: * mut Mutex<ShareSpace>
@@ -221,6 +225,7 @@ impl KVMVcpu {
             shareSpaceAddr: shareSpaceAddr,
             autoStart: autoStart,
             interrupting: Mutex::new((false, vec![])),
+            restoreState: None,
         });
     }
 
@@ -408,39 +413,44 @@ impl KVMVcpu {
 
     pub fn run(&self, tgid: i32) -> Result<()> {
         SetExitSignal();
-        self.setup_long_mode()?;
         let tid = unsafe { gettid() };
         self.threadid.store(tid as u64, Ordering::SeqCst);
         self.tgid.store(tgid as u64, Ordering::SeqCst);
 
-        let regs: kvm_regs = kvm_regs {
-            rflags: KERNEL_FLAGS_SET,
-            rip: self.entry,
-            rsp: self.topStackAddr,
-            rax: 0x11,
-            rbx: 0xdd,
-            //arg0
-            rdi: self.heapStartAddr, // self.pageAllocatorBaseAddr + self.,
-            //arg1
-            rsi: self.shareSpaceAddr,
-            //arg2
-            rdx: self.id as u64,
-            //arg3
-            rcx: VMS.lock().vdsoAddr,
-            //arg4
-            r8: self.vcpuCnt as u64,
-            //arg5
-            r9: self.autoStart as u64,
-            //rdx:
-            //rcx:
-            ..Default::default()
-        };
+        if let Some(state) = &self.restoreState {
+            self.RestoreState(state)?;
+        } else {
+            self.setup_long_mode()?;
+
+            let regs: kvm_regs = kvm_regs {
+                rflags: KERNEL_FLAGS_SET,
+                rip: self.entry,
+                rsp: self.topStackAddr,
+                rax: 0x11,
+                rbx: 0xdd,
+                //arg0
+                rdi: self.heapStartAddr, // self.pageAllocatorBaseAddr + self.,
+                //arg1
+                rsi: self.shareSpaceAddr,
+                //arg2
+                rdx: self.id as u64,
+                //arg3
+                rcx: VMS.lock().vdsoAddr,
+                //arg4
+                r8: self.vcpuCnt as u64,
+                //arg5
+                r9: self.autoStart as u64,
+                //rdx:
+                //rcx:
+                ..Default::default()
+            };
 
-        self.vcpu
-            .set_regs(&regs)
-            .map_err(|e| Error::IOError(format!("io::error is {:?}", e)))?;
+            self.vcpu
+                .set_regs(&regs)
+                .map_err(|e| Error::IOError(format!("io::error is {:?}", e)))?;
 
-        self.SetXCR0()?;
+            self.SetXCR0()?;
+        }
 
         let mut lastVal: u32 = 0;
         let mut first = true;
@@ -455,11 +465,19 @@ impl KVMVcpu {
             "start enter guest[{}]: entry is {:x}, stack is {:x}",
             self.id, self.entry, self.topStackAddr
         );
+
+        // the vcpu is out of the guest kernel and can be parked for a checkpoint
+        let mut parkable = false;
         loop {
             if !super::runc::runtime::vm::IsRunning() {
                 return Ok(());
             }
 
+            if VcpusFrozen() && (parkable || self.InUserMode()?) {
+                ParkVcpu(self.id);
+            }
+            parkable = false;
+
             self.state
                 .store(KVMVcpuState::GUEST as u64, Ordering::Release);
             fence(Ordering::Acquire);
@@ -587,6 +605,7 @@ impl KVMVcpu {
 
                             super::print::LOG.Clear();
                             PerfPrint();
+                            super::vmspace::netstack_link::RestoreLink();
 
                             SetExitStatus(exitCode);
 
@@ -602,6 +621,7 @@ impl KVMVcpu {
                             let msg = unsafe { &*(addr as *const Print) };
 
                             eprintln!("Application error: {}", msg.str);
+                            super::vmspace::netstack_link::RestoreLink();
                             ::std::process::exit(1);
                         }
 
@@ -642,6 +662,7 @@ impl KVMVcpu {
                                 "OOM!!! cpu [{}], size is {:x}, alignment is {:x}",
                                 self.id, data1, data2
                             );
+                            super::vmspace::netstack_link::RestoreLink();
                             ::std::process::exit(1);
                         }
 
@@ -771,6 +792,8 @@ impl KVMVcpu {
                                     panic!("HYPERCALL_HLT wait fail with error {:?}", e);
                                 }
                             }
+
+                            parkable = VcpusFrozen();
                         }
 
                         _ => info!("Unknow hyper call!!!!! address is {}", addr),
@@ -975,6 +998,14 @@ impl KVMVcpu {
         Ok(())
     }
 
+    pub fn InUserMode(&self) -> Result<bool> {
+        let sregs = self
+            .vcpu
+            .get_sregs()
+            .map_err(|e| Error::IOError(format!("io::error is {:?}", e)))?;
+        return Ok(sregs.ss.selector & 0x3 != 0);
+    }
+
     pub fn interrupt(&self, waitCh: Option<Sender<()>>) {
         let mut interrupting = self.interrupting.lock();
         if let Some(w) = waitCh {
@@ -1198,6 +1229,12 @@ impl CPULocal {
                 //Self::ProcessOnce(sharespace);
             }
 
+            // the vcpu is parked out of the wait for a checkpoint, the guest
+            // waits again when it gets no task
+            if VcpusFrozen() {
+                return Ok(0);
+            }
+
             super::GLOBAL_ALLOCATOR.Clear();
 
             let _nfds = unsafe { epoll_wait(self.epollfd, &mut events[0], 2, time) };
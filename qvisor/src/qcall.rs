@@ -15,6 +15,7 @@
 use super::kvm_vcpu::KVMVcpu;
 use super::qlib::common::*;
 use super::qlib::kernel::*;
+use super::qlib::linux_def::*;
 use super::qlib::qmsg::*;
 use super::qlib::range::*;
 use super::qlib::ShareSpace;
@@ -253,9 +254,43 @@ impl KVMVcpu {
                 SHARE_SPACE.hiberMgr.ReapSwapIn().unwrap();
                 ret = 0;
             }
+            Msg::Checkpoint(msg) => {
+                let dir = super::VMSpace::GetStr(msg.dir);
+                ret = match SHARE_SPACE.hiberMgr.Checkpoint(dir) {
+                    Ok(()) => 0,
+                    Err(Error::SysError(e)) => -e as i64 as u64,
+                    Err(err) => {
+                        error!("Checkpoint to {} fail: {:?}", dir, err);
+                        -SysErr::EIO as i64 as u64
+                    }
+                }
+            }
             Msg::Proxy(msg) => {
                 ret = super::VMSpace::Proxy(msg.cmd, msg.addrIn, msg.addrOut) as u64;
             }
+            Msg::HostInotifyInit(_msg) => {
+                ret = super::VMSpace::HostInotifyInit() as u64;
+            }
+            Msg::HostInotifyAddWatch(msg) => {
+                ret = super::VMSpace::HostInotifyAddWatch(msg.inotifyfd, msg.fd, msg.mask) as u64;
+            }
+            Msg::HostInotifyRmWatch(msg) => {
+                ret = super::VMSpace::HostInotifyRmWatch(msg.inotifyfd, msg.wd) as u64;
+            }
+            Msg::HostNameToHandleAt(msg) => {
+                ret = super::VMSpace::HostNameToHandleAt(msg.fd) as u64;
+            }
+            Msg::HostOpenByHandleAt(msg) => {
+                ret = super::VMSpace::HostOpenByHandleAt(
+                    msg.mountfd,
+                    msg.id,
+                    msg.readonly,
+                    msg.addr,
+                ) as u64;
+            }
+            Msg::NetstackLinkInit(msg) => {
+                ret = super::VMSpace::NetstackLinkInit(msg.addr) as u64;
+            }
             Msg::SymLinkAt(msg) => {
                 ret = super::VMSpace::SymLinkAt(msg.oldpath, msg.newdirfd, msg.newpath) as u64;
             }
@@ -303,6 +338,15 @@ impl KVMVcpu {
             Msg::IOSendto(msg) => {
                 ret = super::VMSpace::IOSendto(msg.fd, msg.buf, msg.size, msg.flags, msg.addr, msg.len) as u64;
             }
+            Msg::IORecvMMsg(msg) => {
+                ret = super::VMSpace::IORecvMMsg(msg.fd, msg.msgvec, msg.vlen, msg.flags) as u64;
+            }
+            Msg::IOSendMMsg(msg) => {
+                ret = super::VMSpace::IOSendMMsg(msg.fd, msg.msgvec, msg.vlen, msg.flags) as u64;
+            }
+            Msg::HostUnixConnect(msg) => {
+                ret = super::VMSpace::HostUnixConnect(msg.idx, msg.stype) as u64;
+            }
             Msg::MMapFile(msg) => {
                 ret = match super::PMA_KEEPER.MapFile(msg.len, msg.prot, msg.fd, msg.offset) {
                     Err(Error::SysError(e)) => -e as u64,
@@ -422,6 +422,31 @@ impl Cgroup {
         )));
     }
 
+    // CpuUsage returns the cpu time (ns) the processes of the cgroup used.
+    pub fn CpuUsage(&self) -> Result<u64> {
+        let path = self.MakePath("cpuacct");
+        let usage = GetValue(&path, "cpuacct.usage")?;
+        return ParseCgroupValue("cpuacct.usage", &usage);
+    }
+
+    // MemoryUsage returns the memory usage and the inactive file cache of the
+    // cgroup in bytes.
+    pub fn MemoryUsage(&self) -> Result<(u64, u64)> {
+        let path = self.MakePath("memory");
+        let usage = GetValue(&path, "memory.usage_in_bytes")?;
+        let usage = ParseCgroupValue("memory.usage_in_bytes", &usage)?;
+        let stat = GetValue(&path, "memory.stat")?;
+        let mut inactiveFile = 0;
+        for line in stat.lines() {
+            let mut fields = line.split_whitespace();
+            if fields.next() == Some("total_inactive_file") {
+                inactiveFile = ParseCgroupValue("memory.stat", fields.next().unwrap_or(""))?;
+            }
+        }
+
+        return Ok((usage, inactiveFile));
+    }
+
     pub fn MakePath(&self, controllerName: &str) -> String {
         let mut path = self.Name.to_string();
         match self.Parents.get(controllerName) {
@@ -435,6 +460,13 @@ impl Cgroup {
     }
 }
 
+fn ParseCgroupValue(name: &str, val: &str) -> Result<u64> {
+    return val
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| Error::Common(format!("invalid cgroup value {} {:?}", name, val)));
+}
+
 fn Noop(_spec: &LinuxResources, _path: &str) -> Result<()> {
     return Ok(());
 }
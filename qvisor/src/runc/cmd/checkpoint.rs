@@ -0,0 +1,67 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::string::String;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use super::super::super::qlib::common::*;
+use super::super::cmd::config::*;
+use super::super::container::container::*;
+use super::command::*;
+
+#[derive(Debug)]
+pub struct CheckpointCmd {
+    pub id: String,
+    pub imagePath: String,
+    pub leaveRunning: bool,
+}
+
+impl CheckpointCmd {
+    pub fn Init(cmd_matches: &ArgMatches) -> Result<Self> {
+        return Ok(Self {
+            id: cmd_matches.value_of("id").unwrap().to_string(),
+            imagePath: cmd_matches.value_of("image-path").unwrap().to_string(),
+            leaveRunning: cmd_matches.is_present("leave-running"),
+        });
+    }
+
+    pub fn SubCommand<'a, 'b>(common: &CommonArgs<'a, 'b>) -> App<'a, 'b> {
+        return SubCommand::with_name("checkpoint")
+            .setting(AppSettings::ColoredHelp)
+            .arg(&common.id_arg)
+            .arg(
+                Arg::with_name("image-path")
+                    .required(true)
+                    .long("image-path")
+                    .takes_value(true)
+                    .help("directory the checkpoint image is written to"),
+            )
+            .arg(
+                Arg::with_name("leave-running")
+                    .long("leave-running")
+                    .help("resume the container after the checkpoint"),
+            )
+            .about("checkpoint writes the sandbox of a container to an image directory, the container is left paused");
+    }
+
+    pub fn Run(&self, gCfg: &GlobalConfig) -> Result<()> {
+        info!("Container:: checkpoint ....");
+        let id = &self.id;
+
+        let mut container = Container::Load(&gCfg.RootDir, id)?;
+        container.Checkpoint(&self.imagePath, self.leaveRunning)?;
+
+        return Ok(());
+    }
+}
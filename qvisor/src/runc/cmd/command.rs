@@ -17,6 +17,7 @@ use std::env;
 
 use super::super::super::qlib::common::*;
 use super::boot::*;
+use super::checkpoint::*;
 use super::cmd::*;
 use super::config;
 use super::config::*;
@@ -27,6 +28,7 @@ use super::kill::*;
 use super::list::*;
 use super::pause::*;
 use super::ps::*;
+use super::restore::*;
 use super::resume::*;
 use super::run::*;
 use super::start::*;
@@ -181,6 +183,8 @@ pub fn Parse() -> Result<Arguments> {
         .subcommand(KillCmd::SubCommand(&common))
         .subcommand(DeleteCmd::SubCommand(&common))
         .subcommand(StateCmd::SubCommand(&common))
+        .subcommand(CheckpointCmd::SubCommand(&common))
+        .subcommand(RestoreCmd::SubCommand(&common))
         .get_matches_from(get_args());
 
     let level = match matches.occurrences_of("v") {
@@ -268,6 +272,14 @@ pub fn Parse() -> Result<Arguments> {
             config: gConfig,
             cmd: Command::StateCmd(StateCmd::Init(&cmd_matches)?),
         },
+        ("checkpoint", Some(cmd_matches)) => Arguments {
+            config: gConfig,
+            cmd: Command::CheckpointCmd(CheckpointCmd::Init(&cmd_matches)?),
+        },
+        ("restore", Some(cmd_matches)) => Arguments {
+            config: gConfig,
+            cmd: Command::RestoreCmd(RestoreCmd::Init(&cmd_matches)?),
+        },
         // We should never reach here because clap already enforces this
         _ => panic!("command not recognized"),
     };
@@ -297,6 +309,8 @@ pub enum Command {
     KillCmd(KillCmd),
     DeleteCmd(DeleteCmd),
     StateCmd(StateCmd),
+    CheckpointCmd(CheckpointCmd),
+    RestoreCmd(RestoreCmd),
 }
 
 pub fn Run(args: &mut Arguments) -> Result<()> {
@@ -315,5 +329,7 @@ pub fn Run(args: &mut Arguments) -> Result<()> {
         Command::KillCmd(cmd) => return cmd.Run(&mut args.config),
         Command::DeleteCmd(cmd) => return cmd.Run(&mut args.config),
         Command::StateCmd(cmd) => return cmd.Run(&mut args.config),
+        Command::CheckpointCmd(cmd) => return cmd.Run(&mut args.config),
+        Command::RestoreCmd(cmd) => return cmd.Run(&mut args.config),
     }
 }
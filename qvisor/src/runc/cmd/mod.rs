@@ -13,6 +13,7 @@
 // limitations under the License.
 
 pub mod boot;
+pub mod checkpoint;
 pub mod cmd;
 pub mod command;
 pub mod config;
@@ -23,6 +24,7 @@ pub mod kill;
 pub mod list;
 pub mod pause;
 pub mod ps;
+pub mod restore;
 pub mod resume;
 pub mod run;
 pub mod start;
@@ -0,0 +1,105 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::string::String;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::fs;
+
+use super::super::super::qlib::common::*;
+use super::super::super::qlib::path::*;
+use super::super::cmd::config::*;
+use super::super::container::container::*;
+use super::super::oci::*;
+use super::command::*;
+
+#[derive(Debug)]
+pub struct RestoreCmd {
+    pub id: String,
+    pub bundleDir: String,
+    pub imagePath: String,
+    pub consoleSocket: String,
+    pub pivot: bool,
+    pub pid: String,
+}
+
+impl RestoreCmd {
+    pub fn Init(cmd_matches: &ArgMatches) -> Result<Self> {
+        return Ok(Self {
+            id: cmd_matches.value_of("id").unwrap().to_string(),
+            bundleDir: cmd_matches.value_of("bundle").unwrap().to_string(),
+            imagePath: cmd_matches.value_of("image-path").unwrap().to_string(),
+            consoleSocket: cmd_matches.value_of("console-socket").unwrap().to_string(),
+            pivot: !cmd_matches.is_present("no-pivot"),
+            pid: cmd_matches.value_of("p").unwrap().to_string(),
+        });
+    }
+
+    pub fn SubCommand<'a, 'b>(common: &CommonArgs<'a, 'b>) -> App<'a, 'b> {
+        return SubCommand::with_name("restore")
+            .setting(AppSettings::ColoredHelp)
+            .arg(&common.id_arg)
+            .arg(&common.bundle_arg)
+            .arg(&common.consoleSocket_arg)
+            .arg(&common.no_pivot_arg)
+            .arg(&common.pid_arg)
+            .arg(
+                Arg::with_name("image-path")
+                    .required(true)
+                    .long("image-path")
+                    .takes_value(true)
+                    .help("directory of the checkpoint image"),
+            )
+            .about("restore a container from a checkpoint image");
+    }
+
+    pub fn Run(&self, gCfg: &GlobalConfig) -> Result<()> {
+        info!("Container:: restore ....");
+
+        // the image is read by the sandbox process which has another cwd
+        let imagePath = fs::canonicalize(&self.imagePath)
+            .map_err(|e| Error::Common(format!("RestoreCmd error is {:?}", e)))?
+            .to_str()
+            .unwrap()
+            .to_string();
+        let image = CheckpointImage::Load(&imagePath)?;
+        // the sandbox root and the host paths of the image are named by the id
+        if image.ID != self.id {
+            return Err(Error::Common(format!(
+                "checkpoint image {} is of container {}, it can't be restored as {}",
+                imagePath, image.ID, self.id
+            )));
+        }
+        info!(
+            "restore container {} from the checkpoint taken at {}",
+            self.id, image.CheckpointAt
+        );
+
+        let specfile = Join(&self.bundleDir, "config.json");
+        let spec = Spec::load(&specfile)
+            .map_err(|e| Error::Common(format!("RestoreCmd load spec error is {:?}", e)))?;
+
+        Container::Restore(
+            &self.id,
+            spec,
+            gCfg,
+            &self.bundleDir,
+            &imagePath,
+            &self.consoleSocket,
+            &self.pid,
+            self.pivot,
+        )?;
+
+        return Ok(());
+    }
+}
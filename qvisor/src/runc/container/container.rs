@@ -31,6 +31,10 @@ use super::super::super::qlib::control_msg::*;
 use super::super::super::qlib::linux_def::*;
 use super::super::super::qlib::path::*;
 use super::super::super::ucall::ucall::*;
+use super::super::super::vmspace::checkpoint::{
+    CHECKPOINT_INDEX_FILE, CHECKPOINT_PAGES_FILE, CHECKPOINT_STATE_FILE, CHECKPOINT_VCPU_FILE,
+    RESTORE_IMAGE_ENV,
+};
 //use super::super::super::qlib::util::*;
 use super::super::cgroup::cgroup::*;
 use super::super::cmd::config::*;
@@ -53,6 +57,10 @@ const METADATA_FILENAME: &str = "meta.json";
 // the container state and metadata.
 const METADATA_LOCK_FILENAME: &str = "meta.lock";
 
+// checkpointFilename is the name of the file in a checkpoint image directory
+// that holds the metadata of the checkpointed container.
+const CHECKPOINT_FILENAME: &str = "checkpoint.json";
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Container {
     // ID is the container ID.
@@ -103,6 +111,49 @@ pub struct Container {
     pub RootContainerDir: String,
 }
 
+// CheckpointImage is the container metadata of a checkpoint image, the guest
+// pages are in the pages files of the image directory.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CheckpointImage {
+    // Version is the qvisor version which wrote the image.
+    pub Version: String,
+    pub ID: String,
+    pub BundleDir: String,
+    pub CheckpointAt: u64,
+}
+
+impl CheckpointImage {
+    // Load reads and validates the checkpoint image in imagePath.
+    pub fn Load(imagePath: &str) -> Result<Self> {
+        for file in &[
+            CHECKPOINT_STATE_FILE,
+            CHECKPOINT_VCPU_FILE,
+            CHECKPOINT_PAGES_FILE,
+            CHECKPOINT_INDEX_FILE,
+        ] {
+            if !Path::new(&Join(imagePath, file)).exists() {
+                return Err(Error::Common(format!(
+                    "checkpoint image {} has no {}",
+                    imagePath, file
+                )));
+            }
+        }
+
+        let image: CheckpointImage = deserialize(&Join(imagePath, CHECKPOINT_FILENAME))
+            .map_err(|e| Error::Common(format!("CheckpointImage::Load error is {:?}", e)))?;
+        if image.Version != Version() {
+            return Err(Error::Common(format!(
+                "checkpoint image {} is written by qvisor {}, current version is {}",
+                imagePath,
+                image.Version,
+                Version()
+            )));
+        }
+
+        return Ok(image);
+    }
+}
+
 // List returns all container ids in the given root directory.
 pub fn ContainerList(rootDir: &str) -> Result<Vec<String>> {
     info!("List containers {}", rootDir);
@@ -872,11 +923,165 @@ impl Container {
         return self.Save();
     }
 
+    // Checkpoint writes the sandbox of the container to the image directory.
+    // The container is left paused unless leaveRunning is set.
+    pub fn Checkpoint(&mut self, imagePath: &str, leaveRunning: bool) -> Result<()> {
+        info!("Checkpoint container {} to {}", self.ID, imagePath);
+
+        let _unlock = self.Lock()?;
+
+        self.RequireStatus("checkpoint", &[Status::Running, Status::Paused])?;
+
+        fs::create_dir_all(imagePath).map_err(|e| {
+            Error::Common(format!(
+                "create checkpoint image {} fail: {:?}",
+                imagePath, e
+            ))
+        })?;
+        // the image is written by the sandbox process which has another cwd
+        let imagePath = fs::canonicalize(imagePath)
+            .map_err(|e| Error::Common(format!("Container::Checkpoint error is {:?}", e)))?
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        // the sandbox pauses itself for the checkpoint, it is resumed on the
+        // failure unless the container was paused before
+        let wasPaused = self.Status == Status::Paused;
+        let sandbox = self.Sandbox.as_ref().unwrap();
+        if let Err(e) = self.WriteCheckpoint(&imagePath) {
+            if !wasPaused {
+                if let Err(ue) = sandbox.Unpause(&self.ID) {
+                    error!("Checkpoint: resume container {} fail: {:?}", self.ID, ue);
+                }
+            }
+            return Err(e);
+        }
+
+        if wasPaused {
+            return Ok(());
+        }
+
+        if leaveRunning {
+            match sandbox.Unpause(&self.ID) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    // the sandbox stays paused
+                    self.changeStatus(Status::Paused);
+                    self.Save()?;
+                    return Err(e);
+                }
+            }
+        }
+
+        self.changeStatus(Status::Paused);
+        return self.Save();
+    }
+
+    // WriteCheckpoint has the sandbox write its pages to the image directory
+    // and writes the container metadata of the image.
+    fn WriteCheckpoint(&self, imagePath: &str) -> Result<()> {
+        self.Sandbox
+            .as_ref()
+            .unwrap()
+            .Checkpoint(&self.ID, imagePath)?;
+
+        let image = CheckpointImage {
+            Version: Version(),
+            ID: self.ID.to_string(),
+            BundleDir: self.BundleDir.to_string(),
+            CheckpointAt: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        };
+        serialize(&image, &Join(imagePath, CHECKPOINT_FILENAME))
+            .map_err(|e| Error::Common(format!("Container::Checkpoint error is {:?}", e)))?;
+        return Ok(());
+    }
+
+    // Restore creates the container in a new sandbox process which loads the
+    // sandbox from the checkpoint image instead of booting it, the restored
+    // sandbox is resumed and the container is left running.
+    pub fn Restore(
+        id: &str,
+        spec: Spec,
+        conf: &GlobalConfig,
+        bundleDir: &str,
+        imagePath: &str,
+        consoleSocket: &str,
+        pidFile: &str,
+        pivot: bool,
+    ) -> Result<Self> {
+        info!("Restore container {} from {}", id, imagePath);
+
+        if !IsRoot(&spec) {
+            return Err(Error::Common(format!(
+                "restore container {}: only the root container of a sandbox can be restored",
+                id
+            )));
+        }
+
+        // the sandbox process inherits the environment of the runtime
+        env::set_var(RESTORE_IMAGE_ENV, imagePath);
+        let ret = Container::Create(
+            id,
+            RunAction::Create,
+            spec,
+            conf,
+            bundleDir,
+            consoleSocket,
+            pidFile,
+            "",
+            true,
+            pivot,
+        );
+        env::remove_var(RESTORE_IMAGE_ENV);
+        let mut c = ret?;
+
+        // the checkpoint is taken with the sandbox paused
+        if let Err(e) = c.Sandbox.as_ref().unwrap().Unpause(&c.ID) {
+            error!("Restore: resume container {} fail: {:?}", c.ID, e);
+            c.Destroy()?;
+            return Err(e);
+        }
+
+        {
+            let _unlock = c.Lock()?;
+            c.changeStatus(Status::Running);
+            c.Save()?;
+        }
+
+        return Ok(c);
+    }
+
     pub fn Processes(&self) -> Result<Vec<ProcessInfo>> {
         self.RequireStatus("get processes of", &[Status::Running, Status::Paused])?;
         return self.Sandbox.as_ref().unwrap().Processes(&self.ID);
     }
 
+    // NetStats returns the traffic counters of the sockets of the container.
+    pub fn NetStats(&self) -> Result<Vec<NetStats>> {
+        self.RequireStatus("get network stats of", &[Status::Running, Status::Paused])?;
+        return self.Sandbox.as_ref().unwrap().NetStats(&self.ID);
+    }
+
+    // CgroupStats returns the cpu time (ns), the memory usage and the inactive
+    // file cache of the container. The containers of a sandbox share its
+    // virtual machine, so these are the usage of the sandbox cgroup. It
+    // returns None when the sandbox runs without a cgroup.
+    pub fn CgroupStats(&self) -> Result<Option<(u64, u64, u64)>> {
+        self.RequireStatus("get cgroup stats of", &[Status::Running, Status::Paused])?;
+        let cg = match &self.Sandbox.as_ref().unwrap().Cgroup {
+            None => return Ok(None),
+            Some(cg) => cg,
+        };
+
+        let cpu = cg.CpuUsage()?;
+        let (mem, inactiveFile) = cg.MemoryUsage()?;
+        return Ok(Some((cpu, mem, inactiveFile)));
+    }
+
     // Start starts running the containerized process inside the sandbox.
     pub fn Start(&mut self) -> Result<()> {
         info!("Start container {}", &self.ID);
@@ -29,6 +29,7 @@ use std::path::{Path, PathBuf};
 
 use super::super::super::qlib::common::*;
 use super::super::oci::{LinuxDevice, LinuxDeviceType, Mount, Spec};
+use super::super::specutils::specutils::IsQuotaOption;
 use super::cgroups;
 use super::nix_ext::*;
 
@@ -291,7 +292,9 @@ pub fn parse_mount(m: &Mount) -> (MsFlags, String) {
                 }
             }
             None => {
-                data.push(s.as_str());
+                if !IsQuotaOption(s) {
+                    data.push(s.as_str());
+                }
             }
         };
     }
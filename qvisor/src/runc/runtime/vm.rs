@@ -15,8 +15,11 @@
 //use kvm_bindings::{kvm_userspace_memory_region, KVM_CAP_X86_DISABLE_EXITS, kvm_enable_cap, KVM_X86_DISABLE_EXITS_HLT, KVM_X86_DISABLE_EXITS_MWAIT};
 use alloc::sync::Arc;
 use core::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::env;
+use std::fs;
 use std::os::unix::io::AsRawFd;
 use std::os::unix::io::FromRawFd;
+use std::path::Path;
 use std::thread;
 
 use kvm_bindings::*;
@@ -45,16 +48,18 @@ use super::super::super::qlib::linux_def::*;
 use super::super::super::qlib::pagetable::AlignedAllocator;
 use super::super::super::qlib::pagetable::PageTables;
 use super::super::super::qlib::perf_tunning::*;
+use super::super::super::qlib::qmsg::qcall::{QMsg, CHECKPOINT_RESTORED};
 use super::super::super::qlib::task_mgr::*;
 use super::super::super::qlib::ShareSpace;
 use super::super::super::runc::runtime::loader::*;
 use super::super::super::syncmgr;
+use super::super::super::vmspace::checkpoint::*;
 use super::super::super::vmspace::*;
 use super::super::super::SHARE_SPACE;
 use super::super::super::SHARE_SPACE_STRUCT;
 use super::super::super::{
-    ThreadId, KERNEL_IO_THREAD, PMA_KEEPER, QUARK_CONFIG, ROOT_CONTAINER_ID, THREAD_ID, URING_MGR,
-    VCPU, VMS,
+    ThreadId, FD_NOTIFIER, KERNEL_IO_THREAD, PMA_KEEPER, QUARK_CONFIG, ROOT_CONTAINER_ID,
+    THREAD_ID, URING_MGR, VCPU, VMS,
 };
 
 lazy_static! {
@@ -180,6 +185,13 @@ impl VirtualMachine {
         SHARE_SPACE.SetValue(spAddr);
         SHARESPACE.SetValue(spAddr);
 
+        Self::InitHostShareSpace(vmfd, cpuCount, controlSock);
+        error!("VM::InitShareSpace, after call init 2");
+    }
+
+    // InitHostShareSpace sets up the host side of the ShareSpace: the io
+    // threads, the uring and the singletons of the host.
+    fn InitHostShareSpace(vmfd: &VmFd, cpuCount: usize, controlSock: i32) {
         unsafe {
             vcpu::CPU_LOCAL.Init(&SHARESPACE.scheduler.VcpuArr);
         }
@@ -227,7 +239,6 @@ impl VirtualMachine {
 
         let syncPrint = sharespace.config.read().SyncPrint();
         super::super::super::print::SetSyncPrint(syncPrint);
-        error!("VM::InitShareSpace, after call init 2");
     }
 
     pub fn Init(args: Args /*args: &Args, kvmfd: i32*/) -> Result<Self> {
@@ -239,6 +250,12 @@ impl VirtualMachine {
             LOG.Reset(&args.ID[0..12]);
         }
 
+        if let Ok(dir) = env::var(RESTORE_IMAGE_ENV) {
+            // the processes started by the sandbox don't load the image
+            env::remove_var(RESTORE_IMAGE_ENV);
+            return Self::Restore(args, &dir);
+        }
+
         let kvmfd = args.KvmFd;
 
         /*if QUARK_CONFIG.lock().EnableRDMA {
@@ -402,6 +419,168 @@ impl VirtualMachine {
         Ok(vm)
     }
 
+    // RestoreShareSpace sets the host side of the ShareSpace loaded with the
+    // heap from a checkpoint image.
+    fn RestoreShareSpace(vmfd: &VmFd, state: &SandboxState, controlSock: i32) {
+        SHARE_SPACE.SetValue(state.shareSpaceAddr);
+        SHARESPACE.SetValue(state.shareSpaceAddr);
+
+        let sharespace = unsafe { &mut *(state.shareSpaceAddr as *mut ShareSpace) };
+        sharespace.Restore(controlSock);
+        Self::InitHostShareSpace(vmfd, state.vcpuCount, controlSock);
+
+        // the host fds of the guest are watched again, the uring and the
+        // host epoll are new
+        for fd in &state.fds {
+            URING_MGR.lock().Addfd(fd.fd).unwrap();
+            if fd.mask != 0 {
+                FD_NOTIFIER
+                    .EpollCtlAdd(fd.fd, fd.mask)
+                    .expect("VM::RestoreShareSpace fail to watch the host fd");
+            }
+        }
+    }
+
+    // Restore creates the vm of a sandbox from the checkpoint image in dir.
+    // The heap with the ShareSpace and the guest kernel heap is loaded at the
+    // first heap allocation of the process, the other guest memory is mapped
+    // and loaded here. The vcpus continue from the checkpoint and the
+    // Checkpoint hcall returns CHECKPOINT_RESTORED.
+    fn Restore(args: Args, dir: &str) -> Result<Self> {
+        let state = SandboxState::Load(dir)?;
+        let vcpuStates = DecodeVcpus(
+            &fs::read(Path::new(dir).join(CHECKPOINT_VCPU_FILE))
+                .map_err(|e| Error::IOError(format!("io::error is {:?}", e)))?,
+        )?;
+        if vcpuStates.len() != state.vcpuCount {
+            return Err(Error::Common(format!(
+                "the checkpoint image has {} vcpu states of {} vcpus",
+                vcpuStates.len(),
+                state.vcpuCount
+            )));
+        }
+
+        // the guest keeps the host fd numbers, they are taken before the vm
+        // creates its own fds
+        for fd in &state.fds {
+            fd.Reopen()?;
+        }
+
+        VMS.lock().vcpuCount = state.vcpuCount;
+        VMS.lock().RandomVcpuMapping();
+        let controlSock = args.ControlSock;
+        Self::Umask();
+
+        let kvm = unsafe { Kvm::from_raw_fd(args.KvmFd) };
+
+        let kvm_cpuid = kvm
+            .get_supported_cpuid(kvm_bindings::KVM_MAX_CPUID_ENTRIES)
+            .unwrap();
+
+        let vm_fd = kvm
+            .create_vm()
+            .map_err(|e| Error::IOError(format!("io::error is {:?}", e)))?;
+
+        let mut cap: kvm_enable_cap = Default::default();
+        cap.cap = KVM_CAP_X86_DISABLE_EXITS;
+        cap.args[0] = (KVM_X86_DISABLE_EXITS_HLT | KVM_X86_DISABLE_EXITS_MWAIT) as u64;
+        vm_fd.enable_cap(&cap).unwrap();
+        if !kvm.check_extension(Cap::ImmediateExit) {
+            panic!("KVM_CAP_IMMEDIATE_EXIT not supported");
+        }
+
+        Self::SetMemRegion(
+            1,
+            &vm_fd,
+            MemoryDef::PHY_LOWER_ADDR,
+            MemoryDef::PHY_LOWER_ADDR,
+            MemoryDef::KERNEL_MEM_INIT_REGION_SIZE * MemoryDef::ONE_GB,
+        )?;
+
+        PMA_KEEPER.Restore(
+            state.pmaStart,
+            state.pmaLen,
+            &state.pmaRanges,
+            &state.hugePages,
+            &state.allocPages,
+        );
+
+        let mut mappings = Vec::new();
+        for m in &state.mappings {
+            if m.start >= MemoryDef::HEAP_OFFSET {
+                continue;
+            }
+
+            let mut m = m.clone();
+            if m.end > MemoryDef::HEAP_OFFSET {
+                m.end = MemoryDef::HEAP_OFFSET;
+            }
+            m.Map()?;
+            mappings.push(m);
+        }
+
+        let pages = LoadPages(
+            dir.as_bytes(),
+            MemoryDef::PHY_LOWER_ADDR,
+            MemoryDef::HEAP_OFFSET,
+        )?;
+        for m in &mappings {
+            m.Protect()?;
+        }
+
+        {
+            let vms = &mut VMS.lock();
+            vms.controlSock = controlSock;
+            vms.hostAddrTop =
+                MemoryDef::PHY_LOWER_ADDR + 64 * MemoryDef::ONE_MB + 2 * MemoryDef::ONE_GB;
+            vms.pageTables = PageTables::Init(state.pageTableRoot);
+            vms.vdsoAddr = state.vdsoAddr;
+            vms.pivot = args.Pivot;
+            vms.args = Some(args);
+        }
+
+        Self::RestoreShareSpace(&vm_fd, &state, controlSock);
+
+        unsafe {
+            (*(state.qmsgAddr as *mut QMsg)).ret = CHECKPOINT_RESTORED;
+        }
+
+        let mut vcpus = Vec::with_capacity(state.vcpuCount);
+        for (i, vcpuState) in vcpuStates.into_iter().enumerate() {
+            let vcpu = Arc::new(KVMVcpu::Restore(
+                i,
+                state.vcpuCount,
+                &vm_fd,
+                MemoryDef::HEAP_OFFSET,
+                state.shareSpaceAddr,
+                vcpuState,
+            )?);
+            // enable cpuid in host
+            vcpu.vcpu.set_cpuid2(&kvm_cpuid).unwrap();
+            VMS.lock().vcpus.push(vcpu.clone());
+            vcpus.push(vcpu);
+        }
+
+        // the guest kernel doesn't boot again to release the vcpus
+        syncmgr::SyncMgr::WakeShareSpaceReady();
+        info!(
+            "restore {} vcpus, {} host fds, {} pages below the heap from {}",
+            vcpus.len(),
+            state.fds.len(),
+            pages,
+            dir
+        );
+
+        let vm = Self {
+            kvm: kvm,
+            vmfd: vm_fd,
+            vcpus: vcpus,
+            elf: KernelELF::New()?,
+        };
+
+        return Ok(vm);
+    }
+
     pub fn run(&mut self) -> Result<i32> {
         // start the io thread
         let cpu = self.vcpus[0].clone();
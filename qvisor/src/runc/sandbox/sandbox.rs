@@ -307,6 +307,41 @@ impl Sandbox {
         }
     }
 
+    pub fn NetStats(&self, cid: &str) -> Result<Vec<NetStats>> {
+        let client = self.SandboxConnect()?;
+
+        let req = UCallReq::NetStats(cid.to_string());
+
+        let resp = client.Call(&req)?;
+        match resp {
+            UCallResp::NetStatsResp(stats) => Ok(stats),
+            resp => {
+                panic!("NetStats get unknow resp {:?}", resp);
+            }
+        }
+    }
+
+    pub fn Checkpoint(&self, cid: &str, imagePath: &str) -> Result<()> {
+        info!(
+            "Checkpoint container {} in sandbox {} to {}",
+            cid, self.ID, imagePath
+        );
+        let client = self.SandboxConnect()?;
+
+        let req = UCallReq::Checkpoint(CheckpointArgs {
+            cid: cid.to_string(),
+            imagePath: imagePath.to_string(),
+        });
+
+        let resp = client.Call(&req)?;
+        match resp {
+            UCallResp::CheckpointResp => Ok(()),
+            resp => {
+                panic!("Checkpoint get unknow resp {:?}", resp);
+            }
+        }
+    }
+
     pub fn StartRootContainer(&self) -> Result<()> {
         let client = self.SandboxConnect()?;
 
@@ -380,6 +415,8 @@ impl Sandbox {
             ID: id.to_string(),
             Caps: specutils::Capabilities(false, &spec.process.capabilities),
             Root: format!("{}{}", "/", id),
+            DiskQuotas: specutils::DiskQuotas(&spec),
+            EgressBandwidth: specutils::EgressBandwidth(&spec),
             ..Default::default()
         };
 
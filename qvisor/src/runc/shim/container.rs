@@ -27,7 +27,9 @@ use time::OffsetDateTime;
 use super::super::super::runc::oci::LinuxResources;
 use containerd_shim::api::*;
 use containerd_shim::mount::*;
-use containerd_shim::protos::cgroups::metrics::Metrics;
+use containerd_shim::protos::cgroups::metrics::{
+    CPUStat, CPUUsage, MemoryEntry, MemoryStat, Metrics, NetworkStat,
+};
 use containerd_shim::protos::protobuf::well_known_types::Timestamp;
 use containerd_shim::protos::protobuf::{CodedInputStream, Message};
 use containerd_shim::util::read_spec_from_file;
@@ -338,35 +340,37 @@ impl CommonContainer {
     }
 
     pub fn stats(&self) -> Result<Metrics> {
-        return Err(Error::Unimplemented("CommonContainer::pids".to_string()));
-        /*let mut metrics = Metrics::new();
-        // get container main process cgroup
-        let path = get_cgroups_relative_paths_by_pid(self.common.init.pid() as u32)?;
-        let cgroup = Cgroup::load_with_relative_paths(hierarchies::auto(), Path::new("."), path);
+        let mut metrics = Metrics::new();
+        if let Some((cpu, mem, inactiveFile)) = self.container.CgroupStats()? {
+            let mut cpuUsage = CPUUsage::new();
+            cpuUsage.set_total(cpu);
+            let mut cpuStat = CPUStat::new();
+            cpuStat.set_usage(cpuUsage);
+            metrics.set_cpu(cpuStat);
+
+            let mut memEntry = MemoryEntry::new();
+            memEntry.set_usage(mem);
+            let mut memStat = MemoryStat::new();
+            memStat.set_usage(memEntry);
+            memStat.set_total_inactive_file(inactiveFile);
+            metrics.set_memory(memStat);
+        }
 
-        // to make it easy, fill the necessary metrics only.
-        for sub_system in Cgroup::subsystems(&cgroup) {
-            match sub_system {
-                Subsystem::CpuAcct(cpuacct_ctr) => {
-                    let mut cpu_usage = CPUUsage::new();
-                    cpu_usage.set_total(cpuacct_ctr.cpuacct().usage);
-                    let mut cpu_stat = CPUStat::new();
-                    cpu_stat.set_usage(cpu_usage);
-                    metrics.set_cpu(cpu_stat);
-                }
-                Subsystem::Mem(mem_ctr) => {
-                    let mem = mem_ctr.memory_stat();
-                    let mut mem_entry = MemoryEntry::new();
-                    mem_entry.set_usage(mem.usage_in_bytes);
-                    let mut mem_stat = MemoryStat::new();
-                    mem_stat.set_usage(mem_entry);
-                    mem_stat.set_total_inactive_file(mem.stat.total_inactive_file);
-                    metrics.set_memory(mem_stat);
-                }
-                _ => {}
-            }
+        for s in self.container.NetStats()? {
+            let mut stat = NetworkStat::new();
+            stat.set_name(s.Interface);
+            stat.set_rx_bytes(s.RxBytes);
+            stat.set_rx_packets(s.RxPackets);
+            stat.set_rx_errors(s.RxErrors);
+            stat.set_rx_dropped(s.RxDropped);
+            stat.set_tx_bytes(s.TxBytes);
+            stat.set_tx_packets(s.TxPackets);
+            stat.set_tx_errors(s.TxErrors);
+            stat.set_tx_dropped(s.TxDropped);
+            metrics.mut_network().push(stat);
         }
-        Ok(metrics)*/
+
+        Ok(metrics)
     }
 
     pub fn update(&mut self, _resources: &LinuxResources) -> Result<()> {
@@ -0,0 +1,296 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use std::fs;
+use std::net::IpAddr;
+use std::net::ToSocketAddrs;
+
+use super::super::super::qlib::common::*;
+use super::super::super::qlib::loader::FirewallDirection;
+use super::super::super::qlib::loader::FirewallPolicy;
+use super::super::super::qlib::loader::FirewallRule;
+use super::super::oci::*;
+
+// FirewallAnnotation is the OCI annotation holding the firewall policy of the
+// sandbox. It narrows the host wide policy in FIREWALL_POLICY_FILE: a socket
+// call must be allowed by both, so a pod can't open what the node denies.
+pub const FIREWALL_ANNOTATION: &str = "io.quark.network.firewall";
+pub const FIREWALL_POLICY_FILE: &str = "/etc/quark/firewall.policy";
+
+// The policy has one statement per line (or separated with ';'):
+//
+//   default <egress|ingress|bind> <allow|deny>
+//   deny loopback-to-host
+//   <allow|deny> <egress|ingress|bind> [any|<cidr>|<ip>|<dns name>] [port <p>|<lo>-<hi>]
+//
+// e.g. "default egress deny; allow egress pypi.org port 443; allow egress 10.0.0.0/8"
+// The dns names are resolved when the policy is loaded.
+
+fn ParseDirection(s: &str) -> Result<FirewallDirection> {
+    match s {
+        "egress" => return Ok(FirewallDirection::Egress),
+        "ingress" => return Ok(FirewallDirection::Ingress),
+        "bind" => return Ok(FirewallDirection::Bind),
+        _ => return Err(Error::Common(format!("unknown firewall direction {}", s))),
+    }
+}
+
+fn ParseAction(s: &str) -> Result<bool> {
+    match s {
+        "allow" => return Ok(true),
+        "deny" => return Ok(false),
+        _ => return Err(Error::Common(format!("unknown firewall action {}", s))),
+    }
+}
+
+fn ParsePorts(s: &str) -> Result<(u16, u16)> {
+    let parts: Vec<&str> = s.splitn(2, '-').collect();
+    let lo = parts[0]
+        .parse::<u16>()
+        .map_err(|_| Error::Common(format!("invalid firewall port {}", s)))?;
+    let hi = match parts.get(1) {
+        None => lo,
+        Some(p) => p
+            .parse::<u16>()
+            .map_err(|_| Error::Common(format!("invalid firewall port {}", s)))?,
+    };
+
+    if lo > hi {
+        return Err(Error::Common(format!("invalid firewall port range {}", s)));
+    }
+
+    return Ok((lo, hi));
+}
+
+fn IpBytes(addr: &IpAddr) -> Vec<u8> {
+    match addr {
+        IpAddr::V4(a) => return a.octets().to_vec(),
+        IpAddr::V6(a) => return a.octets().to_vec(),
+    }
+}
+
+// ParseTarget returns the (address, prefix length) the target matches, an
+// empty address matches any address.
+fn ParseTarget(s: &str, allow: bool) -> Result<Vec<(Vec<u8>, u8)>> {
+    if s == "any" {
+        return Ok(vec![(Vec::new(), 0)]);
+    }
+
+    let cidr: Vec<&str> = s.splitn(2, '/').collect();
+    if let Ok(addr) = cidr[0].parse::<IpAddr>() {
+        let addr = IpBytes(&addr);
+        let max = addr.len() as u8 * 8;
+        let prefixLen = match cidr.get(1) {
+            None => max,
+            Some(l) => l
+                .parse::<u8>()
+                .map_err(|_| Error::Common(format!("invalid firewall cidr {}", s)))?,
+        };
+
+        if prefixLen > max {
+            return Err(Error::Common(format!("invalid firewall cidr {}", s)));
+        }
+
+        return Ok(vec![(addr, prefixLen)]);
+    }
+
+    let addrs: Vec<(Vec<u8>, u8)> = match (s, 0).to_socket_addrs() {
+        Ok(addrs) => addrs
+            .map(|a| {
+                let ip = IpBytes(&a.ip());
+                let len = ip.len() as u8 * 8;
+                (ip, len)
+            })
+            .collect(),
+        Err(e) => {
+            // an unresolved allow rule only narrows the policy, a deny rule
+            // can't be dropped
+            if allow {
+                info!(
+                    "firewall: fail to resolve {}, the rule is ignored: {:?}",
+                    s, e
+                );
+                return Ok(Vec::new());
+            }
+            return Err(Error::Common(format!("fail to resolve {}: {:?}", s, e)));
+        }
+    };
+
+    return Ok(addrs);
+}
+
+pub fn ParseFirewallPolicy(text: &str) -> Result<FirewallPolicy> {
+    let mut policy = FirewallPolicy::default();
+    for line in text.split(|c| c == '\n' || c == ';') {
+        let line = match line.find('#') {
+            None => line,
+            Some(i) => &line[..i],
+        };
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.len() == 0 {
+            continue;
+        }
+
+        if tokens[0] == "default" {
+            if tokens.len() != 3 {
+                return Err(Error::Common(format!(
+                    "invalid firewall statement {}",
+                    line
+                )));
+            }
+
+            let allow = ParseAction(tokens[2])?;
+            match ParseDirection(tokens[1])? {
+                FirewallDirection::Egress => policy.DefaultEgress = allow,
+                FirewallDirection::Ingress => policy.DefaultIngress = allow,
+                FirewallDirection::Bind => policy.DefaultBind = allow,
+            }
+            continue;
+        }
+
+        let allow = ParseAction(tokens[0])?;
+        if tokens.len() == 2 && tokens[1] == "loopback-to-host" {
+            policy.NoLoopbackToHost = !allow;
+            continue;
+        }
+
+        if tokens.len() < 2 {
+            return Err(Error::Common(format!(
+                "invalid firewall statement {}",
+                line
+            )));
+        }
+
+        let direction = ParseDirection(tokens[1])?;
+        let mut rest = &tokens[2..];
+        let mut targets = vec![(Vec::new(), 0)];
+        if rest.len() > 0 && rest[0] != "port" {
+            targets = ParseTarget(rest[0], allow)?;
+            rest = &rest[1..];
+        }
+
+        let (portLo, portHi) = match rest {
+            [] => (0, u16::MAX),
+            ["port", ports] => ParsePorts(ports)?,
+            _ => {
+                return Err(Error::Common(format!(
+                    "invalid firewall statement {}",
+                    line
+                )))
+            }
+        };
+
+        for (addr, prefixLen) in targets {
+            policy.Rules.push(FirewallRule {
+                Direction: direction,
+                Allow: allow,
+                Addr: addr,
+                PrefixLen: prefixLen,
+                PortLo: portLo,
+                PortHi: portHi,
+            });
+        }
+    }
+
+    return Ok(policy);
+}
+
+// LoadFirewallPolicy returns the firewall policies of the sandbox: the host
+// policy file, then the spec annotation. An empty list means no firewall.
+pub fn LoadFirewallPolicy(spec: &Spec) -> Result<Vec<FirewallPolicy>> {
+    let mut policies = Vec::new();
+    match fs::read_to_string(FIREWALL_POLICY_FILE) {
+        Ok(text) => policies.push(ParseFirewallPolicy(&text)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+        Err(e) => {
+            return Err(Error::Common(format!(
+                "fail to read {}: {:?}",
+                FIREWALL_POLICY_FILE, e
+            )))
+        }
+    }
+
+    if let Some(text) = spec.annotations.get(FIREWALL_ANNOTATION) {
+        policies.push(ParseFirewallPolicy(text)?);
+    }
+
+    return Ok(policies);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ParseFirewallPolicy() {
+        let policy = ParseFirewallPolicy(
+            "default egress deny # comment\n\
+             allow egress 10.0.0.0/8 port 80-90; deny egress any port 22\n\
+             deny loopback-to-host\n\
+             allow bind ::1",
+        )
+        .unwrap();
+
+        assert!(!policy.DefaultEgress);
+        assert!(policy.DefaultIngress);
+        assert!(policy.DefaultBind);
+        assert!(policy.NoLoopbackToHost);
+        assert_eq!(policy.Rules.len(), 3);
+
+        assert_eq!(
+            policy.Rules[0],
+            FirewallRule {
+                Direction: FirewallDirection::Egress,
+                Allow: true,
+                Addr: vec![10, 0, 0, 0],
+                PrefixLen: 8,
+                PortLo: 80,
+                PortHi: 90,
+            }
+        );
+        assert_eq!(policy.Rules[1].Addr, Vec::<u8>::new());
+        assert!(!policy.Rules[1].Allow);
+        assert_eq!((policy.Rules[1].PortLo, policy.Rules[1].PortHi), (22, 22));
+        assert_eq!(policy.Rules[2].Direction, FirewallDirection::Bind);
+        assert_eq!(policy.Rules[2].PrefixLen, 128);
+        assert_eq!(
+            (policy.Rules[2].PortLo, policy.Rules[2].PortHi),
+            (0, u16::MAX)
+        );
+    }
+
+    #[test]
+    fn test_ParseFirewallPolicyInvalid() {
+        for text in [
+            "default egress",
+            "default sideways deny",
+            "permit egress any",
+            "allow",
+            "allow egress 10.0.0.0/33",
+            "allow egress 10.0.0.1 port 90-80",
+            "allow egress 10.0.0.1 port 70000",
+            "allow egress any port",
+            "allow egress any port 1 2",
+        ]
+        .iter()
+        {
+            assert!(ParseFirewallPolicy(text).is_err(), "{}", text);
+        }
+
+        assert_eq!(ParseFirewallPolicy("").unwrap(), FirewallPolicy::default());
+    }
+}
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod firewall;
 pub mod fs;
 pub mod namespace;
 pub mod specutils;
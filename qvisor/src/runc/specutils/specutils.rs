@@ -8,7 +8,12 @@ use std::fs::File;
 use super::super::super::qlib::auth::cap_set::*;
 use super::super::super::qlib::common::*;
 use super::super::super::qlib::linux_def::*;
+use super::super::super::qlib::loader::HostUnixSocket;
+use super::super::super::qlib::loader::MountQuota;
+use super::super::super::qlib::loader::NetAddr;
+use super::super::super::qlib::loader::NetInterface;
 use super::super::super::qlib::path::*;
+use super::super::super::qlib::quantity::*;
 use super::super::oci::*;
 use super::fs::*;
 
@@ -139,6 +144,286 @@ pub fn AbsPath(base: &str, rel: &str) -> String {
     return Join(base, rel);
 }
 
+// Mount options setting the disk quota of a mount in the sandbox.
+pub const QUOTA_BYTES_OPTION: &str = "quota_bytes";
+pub const QUOTA_INODES_OPTION: &str = "quota_inodes";
+
+// IsQuotaOption returns whether the mount option is a disk quota option,
+// which is handled by the sandbox and not passed to mount(2).
+pub fn IsQuotaOption(opt: &str) -> bool {
+    return opt.starts_with(&format!("{}=", QUOTA_BYTES_OPTION))
+        || opt.starts_with(&format!("{}=", QUOTA_INODES_OPTION));
+}
+
+// DiskQuotas returns the disk quotas set with the quota_bytes and
+// quota_inodes options of the spec mounts.
+pub fn DiskQuotas(spec: &Spec) -> Vec<MountQuota> {
+    let mut quotas = Vec::new();
+    for m in &spec.mounts {
+        let mut quota = MountQuota {
+            Path: m.destination.to_string(),
+            ..Default::default()
+        };
+
+        for opt in &m.options {
+            let kv: Vec<&str> = opt.splitn(2, '=').collect();
+            if kv.len() != 2 {
+                continue;
+            }
+
+            let val = match kv[1].parse::<u64>() {
+                Ok(v) => v,
+                Err(_) => {
+                    info!("ignoring invalid mount option {}", opt);
+                    continue;
+                }
+            };
+
+            match kv[0] {
+                QUOTA_BYTES_OPTION => quota.Bytes = val,
+                QUOTA_INODES_OPTION => quota.Inodes = val,
+                _ => (),
+            }
+        }
+
+        if quota.Bytes != 0 || quota.Inodes != 0 {
+            quotas.push(quota);
+        }
+    }
+
+    return quotas;
+}
+
+// NetworkInterfacesAnnotation is the OCI annotation describing the network
+// interfaces reported inside the sandbox, e.g.
+//   "eth0,mac=02:42:ac:11:00:02,mtu=1500,addr=172.17.0.2/16,gateway=172.17.0.1"
+// Interfaces are separated with ';', addr and gateway can be repeated.
+pub const NETWORK_INTERFACES_ANNOTATION: &str = "io.quark.network.interfaces";
+
+fn ParseIp(s: &str) -> Option<Vec<u8>> {
+    return match s.parse::<std::net::IpAddr>() {
+        Ok(std::net::IpAddr::V4(a)) => Some(a.octets().to_vec()),
+        Ok(std::net::IpAddr::V6(a)) => Some(a.octets().to_vec()),
+        Err(_) => None,
+    };
+}
+
+fn ParseMac(s: &str) -> Option<[u8; 6]> {
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() != 6 {
+        return None;
+    }
+
+    let mut mac = [0; 6];
+    for i in 0..6 {
+        mac[i] = u8::from_str_radix(parts[i], 16).ok()?;
+    }
+
+    return Some(mac);
+}
+
+fn ParseNetInterface(desc: &str) -> Option<NetInterface> {
+    let mut fields = desc.split(',');
+    let name = fields.next()?.trim();
+    if name.len() == 0 || name.len() >= 16 {
+        return None;
+    }
+
+    let mut intf = NetInterface {
+        Name: name.to_string(),
+        Mtu: 1500,
+        ..Default::default()
+    };
+
+    for field in fields {
+        let kv: Vec<&str> = field.trim().splitn(2, '=').collect();
+        if kv.len() != 2 {
+            return None;
+        }
+
+        match kv[0] {
+            "mac" => intf.Mac = ParseMac(kv[1])?,
+            "mtu" => intf.Mtu = kv[1].parse::<u32>().ok()?,
+            "addr" => {
+                let cidr: Vec<&str> = kv[1].splitn(2, '/').collect();
+                let addr = ParseIp(cidr[0])?;
+                let max = addr.len() as u8 * 8;
+                let prefixLen = match cidr.get(1) {
+                    None => max,
+                    Some(l) => l.parse::<u8>().ok()?,
+                };
+                if prefixLen > max {
+                    return None;
+                }
+
+                intf.Addrs.push(NetAddr {
+                    Addr: addr,
+                    PrefixLen: prefixLen,
+                });
+            }
+            "gateway" => intf.Gateways.push(ParseIp(kv[1])?),
+            _ => return None,
+        }
+    }
+
+    return Some(intf);
+}
+
+// NetInterfaces returns the sandbox network interfaces set with the
+// io.quark.network.interfaces annotation, None without the annotation.
+pub fn NetInterfaces(spec: &Spec) -> Option<Vec<NetInterface>> {
+    let mut intfs = Vec::new();
+    let desc = match spec.annotations.get(NETWORK_INTERFACES_ANNOTATION) {
+        None => return None,
+        Some(d) => d,
+    };
+
+    for d in desc.split(';') {
+        if d.trim().len() == 0 {
+            continue;
+        }
+
+        match ParseNetInterface(d) {
+            None => info!("ignoring invalid network interface {}", d),
+            Some(intf) => intfs.push(intf),
+        }
+    }
+
+    return Some(intfs);
+}
+
+// HOST_UNIX_SOCKETS_FILE lists the host unix sockets the sandboxes of the
+// node may connect to, one per line (or separated with ';'), e.g.
+//   /run/agent/metrics.sock
+//   /var/run/docker.sock=/run/host-docker.sock
+// It is set by the node operator, not by the pod: a socket is only given to a
+// sandbox whose spec bind mounts its host path. A guest path without '=' is
+// looked up in the bind mounts of the spec, the host path is the mount source
+// joined with the rest of the guest path.
+pub const HOST_UNIX_SOCKETS_FILE: &str = "/etc/quark/host-unix-sockets";
+
+fn IsBindMount(m: &Mount) -> bool {
+    return m.options.iter().any(|o| o == "bind" || o == "rbind");
+}
+
+fn IsUnder(path: &str, dir: &str) -> bool {
+    let dir = dir.trim_end_matches('/');
+    return path == dir || (path.starts_with(dir) && path.as_bytes()[dir.len()] == b'/');
+}
+
+fn HostSocketPath(spec: &Spec, guestPath: &str) -> Option<String> {
+    let mut best: Option<&Mount> = None;
+    for m in &spec.mounts {
+        if !IsUnder(guestPath, &m.destination) || !IsBindMount(m) {
+            continue;
+        }
+
+        if best.is_none() || best.unwrap().destination.len() < m.destination.len() {
+            best = Some(m);
+        }
+    }
+
+    let m = best?;
+    let rest = &guestPath[m.destination.trim_end_matches('/').len()..];
+    return Some(format!("{}{}", m.source.trim_end_matches('/'), rest));
+}
+
+// BindMounted returns whether the spec bind mounts the host path, the sandbox
+// can then reach it anyway.
+fn BindMounted(spec: &Spec, hostPath: &str) -> bool {
+    return spec
+        .mounts
+        .iter()
+        .any(|m| IsBindMount(m) && m.source.starts_with('/') && IsUnder(hostPath, &m.source));
+}
+
+// ParseHostUnixSockets parses the HOST_UNIX_SOCKETS_FILE entries, keeping the
+// sockets the spec gives access to.
+fn ParseHostUnixSockets(spec: &Spec, desc: &str) -> Vec<HostUnixSocket> {
+    let mut socks = Vec::new();
+    for d in desc.split(|c: char| c == ';' || c == '\n') {
+        let d = d.trim();
+        if d.len() == 0 || d.starts_with('#') {
+            continue;
+        }
+
+        let kv: Vec<&str> = d.splitn(2, '=').collect();
+        let guestPath = kv[0].trim();
+        if !guestPath.starts_with('/') || guestPath.split('/').any(|c| c == "..") {
+            info!("ignoring invalid host unix socket {}", d);
+            continue;
+        }
+
+        let hostPath = match kv.get(1) {
+            Some(p) => p.trim().to_string(),
+            None => match HostSocketPath(spec, guestPath) {
+                Some(p) => p,
+                None => {
+                    info!("ignoring host unix socket {} which is not bind mounted", d);
+                    continue;
+                }
+            },
+        };
+
+        if !hostPath.starts_with('/')
+            || hostPath.len() >= UNIX_PATH_MAX
+            || hostPath.split('/').any(|c| c == "..")
+        {
+            info!("ignoring invalid host unix socket {}", d);
+            continue;
+        }
+
+        if !BindMounted(spec, &hostPath) {
+            info!("ignoring host unix socket {} which is not bind mounted", d);
+            continue;
+        }
+
+        socks.push(HostUnixSocket {
+            GuestPath: guestPath.to_string(),
+            HostPath: hostPath,
+        });
+    }
+
+    return socks;
+}
+
+// HostUnixSockets returns the host unix sockets of HOST_UNIX_SOCKETS_FILE
+// which the sandbox may connect to.
+pub fn HostUnixSockets(spec: &Spec) -> Vec<HostUnixSocket> {
+    match fs::read_to_string(HOST_UNIX_SOCKETS_FILE) {
+        Ok(text) => return ParseHostUnixSockets(spec, &text),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        Err(e) => {
+            error!("fail to read {}: {:?}", HOST_UNIX_SOCKETS_FILE, e);
+            return Vec::new();
+        }
+    }
+}
+
+// EgressBandwidthAnnotation is the pod annotation capping the bandwidth the
+// pod sends, in bits per second with the kubernetes quantity suffixes, e.g.
+// "10M". The containerd CRI plugin passes it to the sandbox spec when it is in
+// the pod_annotations of the runtime.
+pub const EGRESS_BANDWIDTH_ANNOTATION: &str = "kubernetes.io/egress-bandwidth";
+
+// EgressBandwidth returns the egress bandwidth cap of the container set with
+// the kubernetes.io/egress-bandwidth annotation, in bytes per second. 0 means
+// no cap.
+pub fn EgressBandwidth(spec: &Spec) -> u64 {
+    let val = match spec.annotations.get(EGRESS_BANDWIDTH_ANNOTATION) {
+        None => return 0,
+        Some(v) => v,
+    };
+
+    match ParseBandwidth(val) {
+        Some(bps) => return core::cmp::max(bps / 8, 1),
+        None => {
+            info!("ignoring invalid egress bandwidth {}", val);
+            return 0;
+        }
+    }
+}
+
 // OpenSpec opens an OCI runtime spec from the given bundle directory.
 pub fn OpenSpec(bundleDir: &str) -> Result<Spec> {
     let path = Join(bundleDir, "config.json");
@@ -296,3 +581,72 @@ pub fn ContainsStr(strs: &[&str], str: &str) -> bool {
 
     return false;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn BindSpec() -> Spec {
+        let mut spec = Spec::default();
+        spec.mounts.push(Mount {
+            destination: "/run/agent".to_string(),
+            typ: "bind".to_string(),
+            source: "/var/lib/agent/run".to_string(),
+            options: vec!["rbind".to_string(), "rw".to_string()],
+        });
+        spec.mounts.push(Mount {
+            destination: "/tmp".to_string(),
+            typ: "tmpfs".to_string(),
+            source: "tmpfs".to_string(),
+            options: vec!["nosuid".to_string()],
+        });
+        return spec;
+    }
+
+    #[test]
+    fn test_ParseHostUnixSockets() {
+        let spec = BindSpec();
+        let socks = ParseHostUnixSockets(
+            &spec,
+            "# node sockets\n\
+             /run/agent/metrics.sock\n\
+             /run/docker.sock=/var/lib/agent/run/docker.sock; /tmp/x.sock\n\
+             /run/host.sock=/var/run/docker.sock\n\
+             /run/escape.sock=/var/lib/agent/run/../../../etc/x.sock\n\
+             relative.sock",
+        );
+
+        assert_eq!(socks.len(), 2);
+        assert_eq!(socks[0].GuestPath, "/run/agent/metrics.sock");
+        assert_eq!(socks[0].HostPath, "/var/lib/agent/run/metrics.sock");
+        assert_eq!(socks[1].GuestPath, "/run/docker.sock");
+        assert_eq!(socks[1].HostPath, "/var/lib/agent/run/docker.sock");
+    }
+
+    #[test]
+    fn test_BindMounted() {
+        let spec = BindSpec();
+        assert!(BindMounted(&spec, "/var/lib/agent/run"));
+        assert!(BindMounted(&spec, "/var/lib/agent/run/a/b.sock"));
+        assert!(!BindMounted(&spec, "/var/lib/agent/running.sock"));
+        assert!(!BindMounted(&spec, "tmpfs/x.sock"));
+    }
+
+    #[test]
+    fn test_EgressBandwidth() {
+        let mut spec = Spec::default();
+        assert_eq!(EgressBandwidth(&spec), 0);
+
+        spec.annotations
+            .insert(EGRESS_BANDWIDTH_ANNOTATION.to_string(), "8M".to_string());
+        assert_eq!(EgressBandwidth(&spec), 1_000_000);
+
+        spec.annotations
+            .insert(EGRESS_BANDWIDTH_ANNOTATION.to_string(), "4".to_string());
+        assert_eq!(EgressBandwidth(&spec), 1);
+
+        spec.annotations
+            .insert(EGRESS_BANDWIDTH_ANNOTATION.to_string(), "fast".to_string());
+        assert_eq!(EgressBandwidth(&spec), 0);
+    }
+}
@@ -32,6 +32,8 @@ pub enum UCallReq {
     Pause,
     Unpause,
     Ps(Cid),
+    NetStats(Cid),
+    Checkpoint(CheckpointArgs),
     WaitContainer(Cid),
     WaitPid(WaitPid),
     Signal(SignalArgs),
@@ -95,6 +95,16 @@ pub fn PsHandler(cid: &str) -> Result<ControlMsg> {
     return Ok(msg);
 }
 
+pub fn NetStatsHandler(cid: &str) -> Result<ControlMsg> {
+    let msg = ControlMsg::New(Payload::NetStats(cid.to_string()));
+    return Ok(msg);
+}
+
+pub fn CheckpointHandler(args: &CheckpointArgs) -> Result<ControlMsg> {
+    let msg = ControlMsg::New(Payload::Checkpoint(args.clone()));
+    return Ok(msg);
+}
+
 pub fn WaitHandler(cid: &str) -> Result<ControlMsg> {
     let msg = ControlMsg::New(Payload::WaitContainer(cid.to_string()));
     return Ok(msg);
@@ -164,6 +174,8 @@ pub fn ProcessReqHandler(req: &mut UCallReq, fds: &[i32]) -> Result<ControlMsg>
         UCallReq::Pause => PauseHandler()?,
         UCallReq::Unpause => UnpauseHandler()?,
         UCallReq::Ps(cid) => PsHandler(cid)?,
+        UCallReq::NetStats(cid) => NetStatsHandler(cid)?,
+        UCallReq::Checkpoint(args) => CheckpointHandler(args)?,
         UCallReq::WaitContainer(cid) => WaitHandler(cid)?,
         UCallReq::WaitPid(waitpid) => WaitPidHandler(waitpid)?,
         UCallReq::Signal(signalArgs) => SignalHandler(signalArgs)?,
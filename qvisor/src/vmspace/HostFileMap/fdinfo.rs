@@ -233,9 +233,38 @@ impl FdInfo {
     pub fn RecvMsg(sockfd: i32, msghdr: u64, flags: i32) -> i64 {
         let ret = unsafe { recvmsg(sockfd, msghdr as *mut msghdr, flags as c_int) };
 
+        if ret >= 0 {
+            Self::AddRights(msghdr);
+        }
+
         return SysRet(ret as i64);
     }
 
+    // AddRights adds the fds received in the SCM_RIGHTS messages of a host unix
+    // socket so that the guest can use them.
+    fn AddRights(msghdr: u64) {
+        let hdr = unsafe { &*(msghdr as *const msghdr) };
+        if hdr.msg_control.is_null() || hdr.msg_controllen == 0 {
+            return;
+        }
+
+        unsafe {
+            let mut cmsg = CMSG_FIRSTHDR(hdr);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == SOL_SOCKET && (*cmsg).cmsg_type == SCM_RIGHTS {
+                    let data = CMSG_DATA(cmsg) as *const i32;
+                    let cnt = ((*cmsg).cmsg_len as usize - CMSG_LEN(0) as usize) / 4;
+                    for i in 0..cnt {
+                        let fd = *data.add(i);
+                        GlobalIOMgr().AddFile(fd);
+                    }
+                }
+
+                cmsg = CMSG_NXTHDR(hdr, cmsg);
+            }
+        }
+    }
+
     pub fn Recvfrom(sockfd: i32, buf: u64, size: usize, flags: i32, addr: u64, len: u64) -> i64 {
         let ret = unsafe { recvfrom(sockfd, buf as  _, size, flags, addr as _, len as _) };
         return SysRet(ret as i64);
@@ -251,6 +280,24 @@ impl FdInfo {
         return SysRet(ret as i64);
     }
 
+    pub fn RecvMMsg(sockfd: i32, msgvec: u64, vlen: u32, flags: i32) -> i64 {
+        let ret = unsafe {
+            recvmmsg(
+                sockfd,
+                msgvec as *mut mmsghdr,
+                vlen as c_uint,
+                flags as c_int,
+                core::ptr::null_mut(),
+            )
+        };
+        return SysRet(ret as i64);
+    }
+
+    pub fn SendMMsg(sockfd: i32, msgvec: u64, vlen: u32, flags: i32) -> i64 {
+        let ret = unsafe { sendmmsg(sockfd, msgvec as *mut mmsghdr, vlen as c_uint, flags as c_int) };
+        return SysRet(ret as i64);
+    }
+
     pub fn GetSockName(sockfd: i32, addr: u64, addrlen: u64) -> i64 {
         let ret = unsafe { getsockname(sockfd, addr as *mut sockaddr, addrlen as *mut socklen_t) };
 
@@ -452,6 +499,16 @@ impl FdInfo {
         return Self::Sendto(fd, buf, size, flags, addr, len);
     }
 
+    pub fn IORecvMMsg(&self, msgvec: u64, vlen: u32, flags: i32) -> i64 {
+        let fd = self.lock().fd;
+        return Self::RecvMMsg(fd, msgvec, vlen, flags);
+    }
+
+    pub fn IOSendMMsg(&self, msgvec: u64, vlen: u32, flags: i32) -> i64 {
+        let fd = self.lock().fd;
+        return Self::SendMMsg(fd, msgvec, vlen, flags);
+    }
+
     pub fn IOGetSockName(&self, addr: u64, addrlen: u64) -> i64 {
         let sockfd = self.lock().fd;
         return Self::GetSockName(sockfd, addr, addrlen);
@@ -492,7 +549,7 @@ impl FdInfo {
         match self.SockInfo() {
             SockInfo::Socket(_socketInfo) => {
                 //TODO: should double check this is needed or not
-                let rdmaSocket = RDMAServerSock::New(sockfd as u32, acceptQueue, 0, 0);
+                let rdmaSocket = RDMAServerSock::New(sockfd as u32, acceptQueue, 0, [0; 16], 0);
                 *self.lock().sockInfo.lock() = SockInfo::RDMAServerSocket(rdmaSocket);
                 self.lock()
                     .AddWait(EVENT_READ | EVENT_WRITE)
@@ -589,7 +646,8 @@ impl FdInfo {
                     RDMAType::None
                 };
 
-                let rdmaSocket = RDMADataSock::New(sockfd as u32, sockBuf, 1, 0, 0, 0, 0);
+                let rdmaSocket =
+                    RDMADataSock::New(sockfd as u32, sockBuf, 1, 0, 0, 0, 0, [0; 16], [0; 16]);
                 *self.lock().sockInfo.lock() = SockInfo::RDMADataSocket(rdmaSocket);
                 self.lock()
                     .AddWait(EVENT_READ | EVENT_WRITE)
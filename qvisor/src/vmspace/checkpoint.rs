@@ -0,0 +1,1163 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::sync::Arc;
+use core::mem::size_of;
+use core::sync::atomic::{fence, AtomicBool, AtomicU64, Ordering};
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::{slice, thread, time};
+
+use kvm_bindings::*;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use super::super::kvm_vcpu::*;
+use super::super::qlib::common::*;
+use super::super::qlib::linux_def::*;
+use super::super::runc::oci::serialize::*;
+use super::super::runc::runtime::vm::IsRunning;
+use super::super::{SHARE_SPACE, VMS};
+
+// the files of a checkpoint image written by the sandbox
+pub const CHECKPOINT_STATE_FILE: &str = "state.json";
+pub const CHECKPOINT_VCPU_FILE: &str = "vcpus.img";
+pub const CHECKPOINT_PAGES_FILE: &str = "pages.img";
+pub const CHECKPOINT_INDEX_FILE: &str = "pages.idx";
+
+// RESTORE_IMAGE_ENV is the image directory of a sandbox process to restore,
+// it is read at the first heap allocation of the process.
+pub const RESTORE_IMAGE_ENV: &str = "QUARK_RESTORE_IMAGE";
+const RESTORE_IMAGE_ENV_C: &[u8] = b"QUARK_RESTORE_IMAGE\0";
+
+// the msrs of the guest kernel which are not in the kvm sregs
+pub const CHECKPOINT_MSRS: [u32; 10] = [
+    0x174,      // MSR_IA32_SYSENTER_CS
+    0x175,      // MSR_IA32_SYSENTER_ESP
+    0x176,      // MSR_IA32_SYSENTER_EIP
+    0x277,      // MSR_IA32_CR_PAT
+    0xc0000081, // MSR_STAR
+    0xc0000082, // MSR_LSTAR
+    0xc0000083, // MSR_CSTAR
+    0xc0000084, // MSR_SYSCALL_MASK
+    0xc0000102, // MSR_KERNEL_GS_BASE
+    0xc0000103, // MSR_TSC_AUX
+];
+
+pub const CHECKPOINT_MSR_COUNT: usize = CHECKPOINT_MSRS.len();
+
+fn CheckpointErr(e: std::io::Error) -> Error {
+    return Error::SysError(e.raw_os_error().unwrap_or(SysErr::EIO));
+}
+
+fn KvmErr<E: core::fmt::Debug>(e: E) -> Error {
+    return Error::IOError(format!("io::error is {:?}", e));
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct VcpuState {
+    pub id: u64,
+    pub regs: kvm_regs,
+    pub sregs: kvm_sregs,
+    pub xsave: kvm_xsave,
+    pub xcrs: kvm_xcrs,
+    pub events: kvm_vcpu_events,
+    pub nmsrs: u64,
+    pub msrs: [kvm_msr_entry; CHECKPOINT_MSR_COUNT],
+}
+
+#[repr(C)]
+struct MsrList {
+    nmsrs: u32,
+    pad: u32,
+    entries: [kvm_msr_entry; CHECKPOINT_MSR_COUNT],
+}
+
+// EncodeVcpus writes the vcpu states as the raw kvm structures, the first 8
+// bytes are the size of one state so that an image of another qvisor build is
+// rejected.
+pub fn EncodeVcpus(states: &[VcpuState]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(8 + states.len() * size_of::<VcpuState>());
+    data.extend_from_slice(&(size_of::<VcpuState>() as u64).to_le_bytes());
+    for state in states {
+        let bytes = unsafe {
+            slice::from_raw_parts(state as *const _ as *const u8, size_of::<VcpuState>())
+        };
+        data.extend_from_slice(bytes);
+    }
+
+    return data;
+}
+
+pub fn DecodeVcpus(data: &[u8]) -> Result<Vec<VcpuState>> {
+    let size = size_of::<VcpuState>();
+    if data.len() < 8
+        || u64::from_le_bytes([
+            data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
+        ]) != size as u64
+        || (data.len() - 8) % size != 0
+    {
+        return Err(Error::Common(format!(
+            "the vcpu image of {} bytes doesn't match the vcpu state of {} bytes",
+            data.len(),
+            size
+        )));
+    }
+
+    let mut states = Vec::with_capacity((data.len() - 8) / size);
+    for chunk in data[8..].chunks(size) {
+        let state = unsafe { core::ptr::read_unaligned(chunk.as_ptr() as *const VcpuState) };
+        states.push(state);
+    }
+
+    return Ok(states);
+}
+
+impl KVMVcpu {
+    pub const KVM_GET_MSRS: u64 = 0xc008ae88;
+    pub const KVM_SET_MSRS: u64 = 0x4008ae89;
+    pub const KVM_GET_VCPU_EVENTS: u64 = 0x8040ae9f;
+    pub const KVM_SET_VCPU_EVENTS: u64 = 0x4040aea0;
+
+    // Restore creates the vcpu of a restored sandbox, its registers are set
+    // from the state when it starts to run.
+    pub fn Restore(
+        id: usize,
+        vcpuCnt: usize,
+        vm_fd: &kvm_ioctls::VmFd,
+        heapStartAddr: u64,
+        shareSpaceAddr: u64,
+        state: VcpuState,
+    ) -> Result<Self> {
+        let vcpu = vm_fd.create_vcpu(id as u64).map_err(KvmErr)?;
+        let vcpuCoreId = VMS.lock().ComputeVcpuCoreId(id);
+
+        return Ok(Self {
+            id: id,
+            cordId: vcpuCoreId,
+            threadid: AtomicU64::new(0),
+            tgid: AtomicU64::new(0),
+            state: AtomicU64::new(KVMVcpuState::HOST as u64),
+            vcpuCnt,
+            vcpu,
+            topStackAddr: 0,
+            entry: state.regs.rip,
+            gdtAddr: state.sregs.gdt.base,
+            idtAddr: state.sregs.idt.base,
+            tssIntStackStart: 0,
+            tssAddr: state.sregs.tr.base,
+            heapStartAddr: heapStartAddr,
+            shareSpaceAddr: shareSpaceAddr,
+            autoStart: false,
+            interrupting: Mutex::new((false, vec![])),
+            restoreState: Some(state),
+        });
+    }
+
+    // SaveState completes the pending io of the vcpu and reads its state, the
+    // vcpu must be out of the guest.
+    pub fn SaveState(&self) -> Result<VcpuState> {
+        // kvm finishes the in/out of the last exit in the next KVM_RUN, the
+        // immediate exit runs it without entering the guest again
+        self.vcpu.set_kvm_immediate_exit(1);
+        let ret = match self.vcpu.run() {
+            Err(e) if e.errno() == SysErr::EINTR => Ok(()),
+            Err(e) => Err(Error::SysError(e.errno())),
+            Ok(_) => Err(Error::Common(format!(
+                "vcpu {} enters the guest in the checkpoint",
+                self.id
+            ))),
+        };
+        self.vcpu.set_kvm_immediate_exit(0);
+        ret?;
+
+        let mut state = VcpuState {
+            id: self.id as u64,
+            regs: self.vcpu.get_regs().map_err(KvmErr)?,
+            sregs: self.vcpu.get_sregs().map_err(KvmErr)?,
+            xsave: self.vcpu.get_xsave().map_err(KvmErr)?,
+            xcrs: self.vcpu.get_xcrs().map_err(KvmErr)?,
+            ..Default::default()
+        };
+
+        let ret = unsafe {
+            libc::ioctl(
+                self.vcpu.as_raw_fd(),
+                Self::KVM_GET_VCPU_EVENTS,
+                &mut state.events as *mut _ as u64,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::SysError(errno::errno().0));
+        }
+
+        let mut msrs = MsrList {
+            nmsrs: CHECKPOINT_MSR_COUNT as u32,
+            pad: 0,
+            entries: [kvm_msr_entry::default(); CHECKPOINT_MSR_COUNT],
+        };
+        for i in 0..CHECKPOINT_MSR_COUNT {
+            msrs.entries[i].index = CHECKPOINT_MSRS[i];
+        }
+
+        // KVM_GET_MSRS returns the count of the msrs read before the first
+        // one the host doesn't support
+        let ret = unsafe {
+            libc::ioctl(
+                self.vcpu.as_raw_fd(),
+                Self::KVM_GET_MSRS,
+                &mut msrs as *mut _ as u64,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::SysError(errno::errno().0));
+        }
+
+        state.nmsrs = ret as u64;
+        state.msrs = msrs.entries;
+        return Ok(state);
+    }
+
+    // RestoreState sets the vcpu registers of a restored sandbox, the xcr0 is
+    // set before the xsave area whose layout it decides.
+    pub fn RestoreState(&self, state: &VcpuState) -> Result<()> {
+        self.vcpu.set_sregs(&state.sregs).map_err(KvmErr)?;
+        self.vcpu.set_xcrs(&state.xcrs).map_err(KvmErr)?;
+        self.vcpu.set_xsave(&state.xsave).map_err(KvmErr)?;
+        self.vcpu.set_regs(&state.regs).map_err(KvmErr)?;
+
+        let msrs = MsrList {
+            nmsrs: state.nmsrs as u32,
+            pad: 0,
+            entries: state.msrs,
+        };
+        let ret = unsafe {
+            libc::ioctl(
+                self.vcpu.as_raw_fd(),
+                Self::KVM_SET_MSRS,
+                &msrs as *const _ as u64,
+            )
+        };
+        if ret < 0 || ret as u64 != state.nmsrs {
+            return Err(Error::Common(format!(
+                "vcpu {} sets {}/{} msrs, errno is {}",
+                self.id,
+                ret,
+                state.nmsrs,
+                errno::errno().0
+            )));
+        }
+
+        let ret = unsafe {
+            libc::ioctl(
+                self.vcpu.as_raw_fd(),
+                Self::KVM_SET_VCPU_EVENTS,
+                &state.events as *const _ as u64,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::SysError(errno::errno().0));
+        }
+
+        return Ok(());
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum MappingKind {
+    // private memory, anonymous or copied on write from a file
+    Private,
+    // shared anonymous memory, e.g. the guest memory from PMA_KEEPER.MapAnon
+    SharedAnon,
+    // a host file mapped by the guest, it is mapped from the file again
+    SharedFile,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub start: u64,
+    pub end: u64,
+    pub prot: i32,
+    pub kind: MappingKind,
+    // the backing file, empty for the anonymous memory
+    pub path: String,
+    pub offset: u64,
+}
+
+impl Mapping {
+    pub fn Len(&self) -> u64 {
+        return self.end - self.start;
+    }
+
+    // HasPages returns whether the pages of the mapping are in the image, the
+    // shared files keep their own pages
+    pub fn HasPages(&self) -> bool {
+        return self.kind != MappingKind::SharedFile && self.prot & libc::PROT_READ != 0;
+    }
+
+    // Parse parses a line of /proc/self/maps.
+    pub fn Parse(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(6, ' ');
+        let range = fields.next()?;
+        let perms = fields.next()?.as_bytes();
+        let offset = fields.next()?;
+        let _dev = fields.next()?;
+        let _inode = fields.next()?;
+        let path = fields.next().unwrap_or("").trim();
+
+        let mut bounds = range.split('-');
+        let start = u64::from_str_radix(bounds.next()?, 16).ok()?;
+        let end = u64::from_str_radix(bounds.next()?, 16).ok()?;
+        let offset = u64::from_str_radix(offset, 16).ok()?;
+        if perms.len() != 4 {
+            return None;
+        }
+
+        let mut prot = libc::PROT_NONE;
+        if perms[0] == b'r' {
+            prot |= libc::PROT_READ;
+        }
+        if perms[1] == b'w' {
+            prot |= libc::PROT_WRITE;
+        }
+        if perms[2] == b'x' {
+            prot |= libc::PROT_EXEC;
+        }
+
+        // a deleted file or a memfd can't be opened again
+        let isFile = path.starts_with('/') && !path.ends_with(" (deleted)");
+        let kind = if perms[3] == b'p' {
+            MappingKind::Private
+        } else if isFile {
+            MappingKind::SharedFile
+        } else {
+            MappingKind::SharedAnon
+        };
+
+        return Some(Self {
+            start: start,
+            end: end,
+            prot: prot,
+            kind: kind,
+            path: if isFile {
+                path.to_string()
+            } else {
+                String::new()
+            },
+            offset: if isFile { offset } else { 0 },
+        });
+    }
+
+    // Map maps the range again in the restored sandbox, the private and the
+    // shared anonymous memory is writable until its pages are loaded.
+    pub fn Map(&self) -> Result<()> {
+        let mut fd = -1;
+        let mut flags = libc::MAP_FIXED;
+        let mut prot = self.prot | libc::PROT_WRITE;
+        match self.kind {
+            MappingKind::Private => flags |= libc::MAP_PRIVATE,
+            MappingKind::SharedAnon => flags |= libc::MAP_SHARED,
+            MappingKind::SharedFile => {
+                flags |= libc::MAP_SHARED;
+                prot = self.prot;
+            }
+        }
+
+        if self.path.len() > 0 {
+            let openFlags =
+                if self.kind == MappingKind::SharedFile && self.prot & libc::PROT_WRITE != 0 {
+                    libc::O_RDWR
+                } else {
+                    libc::O_RDONLY
+                };
+            let path = CString::new(self.path.as_str())
+                .map_err(|e| Error::Common(format!("mapping path error is {:?}", e)))?;
+            fd = unsafe { libc::open(path.as_ptr(), openFlags | libc::O_CLOEXEC) };
+            if fd < 0 {
+                return Err(Error::SysError(errno::errno().0));
+            }
+        } else {
+            flags |= libc::MAP_ANONYMOUS;
+        }
+
+        let addr = unsafe {
+            libc::mmap(
+                self.start as _,
+                self.Len() as _,
+                prot,
+                flags,
+                fd,
+                self.offset as _,
+            )
+        };
+        let errno = errno::errno().0;
+        if fd >= 0 {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+
+        if addr == libc::MAP_FAILED {
+            return Err(Error::SysError(errno));
+        }
+
+        return Ok(());
+    }
+
+    // Protect sets the protection of the mapping after its pages are loaded.
+    pub fn Protect(&self) -> Result<()> {
+        let ret = unsafe { libc::mprotect(self.start as _, self.Len() as _, self.prot) };
+        if ret < 0 {
+            return Err(Error::SysError(errno::errno().0));
+        }
+
+        return Ok(());
+    }
+}
+
+// ParseMaps returns the mappings of the maps file in [start, end), the
+// mappings crossing the bounds are cut at them.
+pub fn ParseMaps(maps: &str, start: u64, end: u64) -> Vec<Mapping> {
+    let mut mappings = Vec::new();
+    for line in maps.lines() {
+        let mut m = match Mapping::Parse(line) {
+            None => continue,
+            Some(m) => m,
+        };
+
+        if m.end <= start || m.start >= end {
+            continue;
+        }
+
+        if m.start < start {
+            if m.path.len() > 0 {
+                m.offset += start - m.start;
+            }
+            m.start = start;
+        }
+
+        if m.end > end {
+            m.end = end;
+        }
+
+        mappings.push(m);
+    }
+
+    return mappings;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum HostFdKind {
+    File,
+    EventFd,
+    // a socket, a pipe or a deleted file, the restored fd is a socket whose
+    // peer is closed so that the guest gets EOF and EPIPE
+    Broken,
+}
+
+pub fn ClassifyFd(link: &str) -> HostFdKind {
+    if link == "anon_inode:[eventfd]" {
+        return HostFdKind::EventFd;
+    }
+
+    if link.starts_with('/') && !link.ends_with(" (deleted)") {
+        return HostFdKind::File;
+    }
+
+    return HostFdKind::Broken;
+}
+
+// HostFd is a host fd of the guest, the guest keeps the fd number so it is
+// opened again at the same number.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HostFd {
+    pub fd: i32,
+    pub kind: HostFdKind,
+    pub path: String,
+    pub flags: i32,
+    pub offset: i64,
+    // the events the guest waits on the fd in the host epoll
+    pub mask: u64,
+}
+
+impl HostFd {
+    pub fn Read(fd: i32, mask: u64) -> Result<Self> {
+        let link = fs::read_link(format!("/proc/self/fd/{}", fd)).map_err(CheckpointErr)?;
+        let link = link.to_string_lossy().to_string();
+
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 {
+            return Err(Error::SysError(errno::errno().0));
+        }
+
+        // sockets and pipes have no offset
+        let offset = unsafe { libc::lseek(fd, 0, libc::SEEK_CUR) };
+
+        return Ok(Self {
+            fd: fd,
+            kind: ClassifyFd(&link),
+            path: link,
+            flags: flags,
+            offset: if offset < 0 { 0 } else { offset },
+            mask: mask,
+        });
+    }
+
+    // Reopen opens the fd again at its number, the number must be free.
+    pub fn Reopen(&self) -> Result<()> {
+        if unsafe { libc::fcntl(self.fd, libc::F_GETFD) } >= 0 {
+            return Err(Error::Common(format!(
+                "host fd {} of the restored sandbox is in use",
+                self.fd
+            )));
+        }
+
+        let nonblock = self.flags & libc::O_NONBLOCK;
+        let fd = match self.kind {
+            HostFdKind::File => {
+                let path = CString::new(self.path.as_str())
+                    .map_err(|e| Error::Common(format!("host fd path error is {:?}", e)))?;
+                let flags = self.flags & !(libc::O_CREAT | libc::O_EXCL | libc::O_TRUNC);
+                let fd = unsafe { libc::open(path.as_ptr(), flags) };
+                if fd >= 0 && self.offset > 0 {
+                    unsafe {
+                        libc::lseek(fd, self.offset, libc::SEEK_SET);
+                    }
+                }
+                fd
+            }
+            HostFdKind::EventFd => {
+                let flags = if nonblock != 0 { libc::EFD_NONBLOCK } else { 0 };
+                unsafe { libc::eventfd(0, flags) }
+            }
+            HostFdKind::Broken => {
+                let mut fds = [0i32; 2];
+                let ret = unsafe {
+                    libc::socketpair(
+                        libc::AF_UNIX,
+                        libc::SOCK_STREAM | nonblock,
+                        0,
+                        fds.as_mut_ptr(),
+                    )
+                };
+                if ret < 0 {
+                    -1
+                } else {
+                    unsafe {
+                        libc::close(fds[1]);
+                    }
+                    fds[0]
+                }
+            }
+        };
+
+        if fd < 0 {
+            return Err(Error::SysError(errno::errno().0));
+        }
+
+        if fd != self.fd {
+            let ret = unsafe { libc::dup2(fd, self.fd) };
+            let errno = errno::errno().0;
+            unsafe {
+                libc::close(fd);
+            }
+            if ret < 0 {
+                return Err(Error::SysError(errno));
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+// SandboxState is the sandbox state of the checkpoint image which is not in
+// the guest memory.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SandboxState {
+    pub vcpuCount: usize,
+    pub shareSpaceAddr: u64,
+    pub pageTableRoot: u64,
+    pub vdsoAddr: u64,
+
+    // the vcpu which takes the checkpoint and the QMsg of its HCall, the
+    // restored sandbox returns CHECKPOINT_RESTORED from it
+    pub checkpointVcpu: usize,
+    pub qmsgAddr: u64,
+
+    pub pmaStart: u64,
+    pub pmaLen: u64,
+    pub pmaRanges: Vec<(u64, u64)>,
+    pub hugePages: Vec<u64>,
+    pub allocPages: Vec<u64>,
+
+    pub mappings: Vec<Mapping>,
+    pub fds: Vec<HostFd>,
+}
+
+impl SandboxState {
+    pub fn Save(&self, dir: &str) -> Result<()> {
+        let path = Path::new(dir).join(CHECKPOINT_STATE_FILE);
+        return serialize(self, path.to_str().unwrap())
+            .map_err(|e| Error::Common(format!("SandboxState::Save error is {:?}", e)));
+    }
+
+    pub fn Load(dir: &str) -> Result<Self> {
+        let path = Path::new(dir).join(CHECKPOINT_STATE_FILE);
+        return deserialize(path.to_str().unwrap())
+            .map_err(|e| Error::Common(format!("SandboxState::Load error is {:?}", e)));
+    }
+}
+
+pub const PAGEMAP_PRESENT: u64 = 1 << 63;
+pub const PAGEMAP_SWAPPED: u64 = 1 << 62;
+
+const PAGE_SIZE: usize = MemoryDef::PAGE_SIZE_4K as usize;
+const PAGEMAP_BATCH: usize = 512;
+
+// PageImage writes the pages file and the index file of a checkpoint image,
+// page i of the pages file is at the address of u64 i of the index file.
+// It doesn't allocate from the heap after Create so that it can write the
+// heap itself.
+pub struct PageImage {
+    pages: File,
+    index: File,
+    pagemap: File,
+    idx: [u8; PAGE_SIZE],
+    idxLen: usize,
+    pub count: u64,
+}
+
+impl PageImage {
+    pub fn Create(dir: &str) -> Result<Self> {
+        return Ok(Self {
+            pages: File::create(Path::new(dir).join(CHECKPOINT_PAGES_FILE))
+                .map_err(CheckpointErr)?,
+            index: File::create(Path::new(dir).join(CHECKPOINT_INDEX_FILE))
+                .map_err(CheckpointErr)?,
+            pagemap: File::open("/proc/self/pagemap").map_err(CheckpointErr)?,
+            idx: [0; PAGE_SIZE],
+            idxLen: 0,
+            count: 0,
+        });
+    }
+
+    fn IsZero(data: &[u8]) -> bool {
+        let words = unsafe { slice::from_raw_parts(data.as_ptr() as *const u64, data.len() / 8) };
+        return words.iter().all(|w| *w == 0);
+    }
+
+    // WritePage writes a page of the address, the zero pages are skipped.
+    pub fn WritePage(&mut self, addr: u64, data: &[u8]) -> Result<()> {
+        assert!(data.len() == PAGE_SIZE);
+        if Self::IsZero(data) {
+            return Ok(());
+        }
+
+        self.pages.write_all(data).map_err(CheckpointErr)?;
+        self.idx[self.idxLen..self.idxLen + 8].copy_from_slice(&addr.to_le_bytes());
+        self.idxLen += 8;
+        if self.idxLen == self.idx.len() {
+            self.index.write_all(&self.idx).map_err(CheckpointErr)?;
+            self.idxLen = 0;
+        }
+
+        self.count += 1;
+        return Ok(());
+    }
+
+    // WriteRange writes the pages of [start, end) which are in memory or in
+    // the host swap, the others are zero.
+    pub fn WriteRange(&mut self, start: u64, end: u64) -> Result<()> {
+        let mut entries = [0u64; PAGEMAP_BATCH];
+        let mut addr = start;
+        while addr < end {
+            let count = core::cmp::min(PAGEMAP_BATCH as u64, (end - addr) / PAGE_SIZE as u64);
+            let len = count as usize * 8;
+            let ret = unsafe {
+                libc::pread(
+                    self.pagemap.as_raw_fd(),
+                    entries.as_mut_ptr() as _,
+                    len,
+                    (addr / PAGE_SIZE as u64 * 8) as _,
+                )
+            };
+            if ret != len as isize {
+                return Err(Error::SysError(SysErr::EIO));
+            }
+
+            for i in 0..count as usize {
+                if entries[i] & (PAGEMAP_PRESENT | PAGEMAP_SWAPPED) == 0 {
+                    continue;
+                }
+
+                let page = addr + (i * PAGE_SIZE) as u64;
+                let data = unsafe { slice::from_raw_parts(page as *const u8, PAGE_SIZE) };
+                self.WritePage(page, data)?;
+            }
+
+            addr += count * PAGE_SIZE as u64;
+        }
+
+        return Ok(());
+    }
+
+    // Finish flushes the index and syncs the files, it returns the page count.
+    pub fn Finish(mut self) -> Result<u64> {
+        if self.idxLen > 0 {
+            self.index
+                .write_all(&self.idx[..self.idxLen])
+                .map_err(CheckpointErr)?;
+        }
+
+        self.pages.sync_all().map_err(CheckpointErr)?;
+        self.index.sync_all().map_err(CheckpointErr)?;
+        return Ok(self.count);
+    }
+}
+
+fn ImageFile(dir: &[u8], file: &str, buf: &mut [u8; 4096]) -> Result<*const libc::c_char> {
+    let file = file.as_bytes();
+    if dir.len() + file.len() + 2 > buf.len() {
+        return Err(Error::SysError(SysErr::ENAMETOOLONG));
+    }
+
+    buf[..dir.len()].copy_from_slice(dir);
+    buf[dir.len()] = b'/';
+    buf[dir.len() + 1..dir.len() + 1 + file.len()].copy_from_slice(file);
+    buf[dir.len() + 1 + file.len()] = 0;
+    return Ok(buf.as_ptr() as *const libc::c_char);
+}
+
+fn OpenImageFile(dir: &[u8], file: &str) -> Result<i32> {
+    let mut path = [0u8; 4096];
+    let fd = unsafe {
+        libc::open(
+            ImageFile(dir, file, &mut path)?,
+            libc::O_RDONLY | libc::O_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(Error::SysError(errno::errno().0));
+    }
+
+    return Ok(fd);
+}
+
+// LoadPages reads the pages of the image in [start, end) to their addresses,
+// the memory must be mapped writable. It doesn't allocate from the heap so
+// that it can load the heap of the restored sandbox.
+pub fn LoadPages(dir: &[u8], start: u64, end: u64) -> Result<u64> {
+    let indexfd = OpenImageFile(dir, CHECKPOINT_INDEX_FILE)?;
+    defer!(unsafe {
+        libc::close(indexfd);
+    });
+    let pagesfd = OpenImageFile(dir, CHECKPOINT_PAGES_FILE)?;
+    defer!(unsafe {
+        libc::close(pagesfd);
+    });
+
+    let mut buf = [0u8; PAGE_SIZE];
+    let mut page = 0u64;
+    let mut loaded = 0;
+    loop {
+        let n = unsafe { libc::read(indexfd, buf.as_mut_ptr() as _, buf.len()) };
+        if n < 0 {
+            return Err(Error::SysError(errno::errno().0));
+        }
+
+        if n == 0 {
+            return Ok(loaded);
+        }
+
+        if n % 8 != 0 {
+            return Err(Error::SysError(SysErr::EIO));
+        }
+
+        for chunk in buf[..n as usize].chunks(8) {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(chunk);
+            let addr = u64::from_le_bytes(bytes);
+            if start <= addr && addr < end {
+                let ret = unsafe {
+                    libc::pread(
+                        pagesfd,
+                        addr as _,
+                        PAGE_SIZE,
+                        (page * PAGE_SIZE as u64) as _,
+                    )
+                };
+                if ret != PAGE_SIZE as isize {
+                    return Err(Error::SysError(SysErr::EIO));
+                }
+                loaded += 1;
+            }
+
+            page += 1;
+        }
+    }
+}
+
+// LoadHeap loads the heap of a restored sandbox process, it runs before the
+// first heap allocation and returns false when the process isn't restored.
+pub fn LoadHeap(start: u64, end: u64) -> bool {
+    let dir = unsafe { libc::getenv(RESTORE_IMAGE_ENV_C.as_ptr() as *const libc::c_char) };
+    if dir.is_null() {
+        return false;
+    }
+
+    let dir = unsafe { CStr::from_ptr(dir) }.to_bytes();
+    if LoadPages(dir, start, end).is_err() {
+        // a panic message needs the heap
+        let msg = b"qvisor: fail to load the heap from the checkpoint image\n";
+        unsafe {
+            libc::write(2, msg.as_ptr() as _, msg.len());
+            libc::abort();
+        }
+    }
+
+    return true;
+}
+
+lazy_static! {
+    static ref VCPU_FREEZE: AtomicBool = AtomicBool::new(false);
+    static ref PARKED_VCPUS: AtomicU64 = AtomicU64::new(0);
+}
+
+pub const FREEZE_TIMEOUT_MS: u64 = 1000;
+
+// VcpusFrozen returns whether the vcpus are stopped for a checkpoint.
+#[inline]
+pub fn VcpusFrozen() -> bool {
+    fence(Ordering::SeqCst);
+    return VCPU_FREEZE.load(Ordering::SeqCst);
+}
+
+// ParkVcpu keeps the vcpu in the host until the checkpoint is done, the
+// caller parks it only where the guest holds no kernel lock: in the guest
+// user mode, the idle wait or the io wait.
+pub fn ParkVcpu(id: usize) {
+    PARKED_VCPUS.fetch_or(1 << id, Ordering::SeqCst);
+    while VcpusFrozen() && IsRunning() {
+        thread::sleep(time::Duration::from_millis(1));
+    }
+    PARKED_VCPUS.fetch_and(!(1 << id), Ordering::SeqCst);
+}
+
+// FreezeVcpus parks the vcpus other than the current one, it fails with
+// EBUSY when a vcpu stays in the guest kernel.
+pub fn FreezeVcpus(current: usize, vcpus: &[Arc<KVMVcpu>]) -> Result<()> {
+    VCPU_FREEZE.store(true, Ordering::SeqCst);
+
+    let mut want = 0u64;
+    for vcpu in vcpus {
+        if vcpu.id != current {
+            want |= 1 << vcpu.id;
+        }
+    }
+
+    for _ in 0..FREEZE_TIMEOUT_MS {
+        let parked = PARKED_VCPUS.load(Ordering::SeqCst);
+        if parked & want == want {
+            return Ok(());
+        }
+
+        for vcpu in vcpus {
+            if want & !parked & (1 << vcpu.id) == 0 {
+                continue;
+            }
+
+            if vcpu.state.load(Ordering::Acquire) == KVMVcpuState::GUEST as u64 {
+                vcpu.interrupt(None);
+            }
+            SHARE_SPACE.scheduler.VcpuArr[vcpu.id].Wakeup();
+        }
+
+        thread::sleep(time::Duration::from_millis(1));
+    }
+
+    ThawVcpus();
+    return Err(Error::SysError(SysErr::EBUSY));
+}
+
+pub fn ThawVcpus() {
+    VCPU_FREEZE.store(false, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    fn TempDir() -> String {
+        let dir = temp_dir().join(format!("checkpoint-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        return dir.to_str().unwrap().to_string();
+    }
+
+    fn MapAnon(len: usize) -> u64 {
+        let addr = unsafe {
+            libc::mmap(
+                core::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert!(addr != libc::MAP_FAILED);
+        return addr as u64;
+    }
+
+    #[test]
+    fn test_VcpuStateRoundTrip() {
+        let mut states = [VcpuState::default(), VcpuState::default()];
+        for (i, state) in states.iter_mut().enumerate() {
+            state.id = i as u64;
+            state.regs.rip = 0x1000 + i as u64;
+            state.regs.rsi = 0x2000;
+            state.sregs.cr3 = 0x3000;
+            state.xsave.region[10] = 0xdead;
+            state.xcrs.nr_xcrs = 1;
+            state.xcrs.xcrs[0].value = 0x7;
+            state.events.exception.injected = 1;
+            state.nmsrs = 2;
+            state.msrs[0] = kvm_msr_entry {
+                index: CHECKPOINT_MSRS[0],
+                reserved: 0,
+                data: 0x10,
+            };
+        }
+
+        let data = EncodeVcpus(&states);
+        assert_eq!(data.len(), 8 + 2 * size_of::<VcpuState>());
+        assert_eq!(DecodeVcpus(&data).unwrap(), states.to_vec());
+
+        assert!(DecodeVcpus(&data[..data.len() - 1]).is_err());
+        let mut other = data.clone();
+        other[0] ^= 1;
+        assert!(DecodeVcpus(&other).is_err());
+    }
+
+    #[test]
+    fn test_SandboxStateRoundTrip() {
+        let dir = TempDir();
+        let state = SandboxState {
+            vcpuCount: 4,
+            shareSpaceAddr: 0x7000_0000,
+            pageTableRoot: 0x1000,
+            vdsoAddr: 0x2000,
+            checkpointVcpu: 2,
+            qmsgAddr: 0x3000,
+            pmaStart: 0x100000,
+            pmaLen: 0x800000,
+            pmaRanges: vec![(0x100000, 0x400000)],
+            hugePages: vec![0x200000, 0x400000],
+            allocPages: vec![0x600000],
+            mappings: vec![Mapping {
+                start: 0x100000,
+                end: 0x200000,
+                prot: libc::PROT_READ,
+                kind: MappingKind::SharedFile,
+                path: "/etc/hosts".to_string(),
+                offset: 0x1000,
+            }],
+            fds: vec![HostFd {
+                fd: 100,
+                kind: HostFdKind::EventFd,
+                path: "anon_inode:[eventfd]".to_string(),
+                flags: libc::O_RDWR | libc::O_NONBLOCK,
+                offset: 0,
+                mask: 1,
+            }],
+        };
+
+        state.Save(&dir).unwrap();
+        assert_eq!(SandboxState::Load(&dir).unwrap(), state);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_PageImageRoundTrip() {
+        let dir = TempDir();
+        let len = 8 * PAGE_SIZE;
+        let start = MapAnon(len);
+
+        // page 1 and 5 have data, page 3 is touched but zero, the others are
+        // never touched
+        unsafe {
+            *((start + PAGE_SIZE as u64) as *mut u64) = 0x1234;
+            *((start + 3 * PAGE_SIZE as u64) as *mut u64) = 0;
+            *((start + 6 * PAGE_SIZE as u64 - 8) as *mut u64) = 0x5678;
+        }
+
+        let mut image = PageImage::Create(&dir).unwrap();
+        image.WriteRange(start, start + len as u64).unwrap();
+        let extra = [0xffu8; PAGE_SIZE];
+        image.WritePage(0x1000, &extra).unwrap();
+        assert_eq!(image.Finish().unwrap(), 3);
+
+        // the restored memory starts zeroed
+        let ret = unsafe {
+            libc::mmap(
+                start as _,
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
+                -1,
+                0,
+            )
+        };
+        assert_eq!(ret as u64, start);
+
+        // the page out of the range is skipped
+        let loaded = LoadPages(dir.as_bytes(), start, start + len as u64).unwrap();
+        assert_eq!(loaded, 2);
+        unsafe {
+            assert_eq!(*((start + PAGE_SIZE as u64) as *const u64), 0x1234);
+            assert_eq!(*((start + 6 * PAGE_SIZE as u64 - 8) as *const u64), 0x5678);
+            libc::munmap(start as _, len);
+        }
+
+        assert!(LoadPages(b"/nonexist", 0, u64::MAX).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ParseMaps() {
+        let maps = "\
+100000-200000 rw-p 00000000 00:00 0
+200000-300000 r-xp 00001000 08:01 1234                       /usr/local/bin/qkernel.bin
+300000-400000 rw-s 00002000 08:01 5678                       /data/file
+400000-500000 rw-s 00000000 00:01 9                          /dev/zero (deleted)
+500000-600000 ---p 00000000 00:00 0
+600000-700000 rw-p 00000000 00:00 0                          [heap]
+";
+        let mappings = ParseMaps(maps, 0x180000, 0x580000);
+        assert_eq!(mappings.len(), 5);
+
+        assert_eq!(mappings[0].start, 0x180000);
+        assert_eq!(mappings[0].kind, MappingKind::Private);
+        assert_eq!(mappings[0].prot, libc::PROT_READ | libc::PROT_WRITE);
+        assert_eq!(mappings[0].path, "");
+
+        assert_eq!(mappings[1].kind, MappingKind::Private);
+        assert_eq!(mappings[1].prot, libc::PROT_READ | libc::PROT_EXEC);
+        assert_eq!(mappings[1].path, "/usr/local/bin/qkernel.bin");
+        assert_eq!(mappings[1].offset, 0x1000);
+
+        assert_eq!(mappings[2].kind, MappingKind::SharedFile);
+        assert_eq!(mappings[2].path, "/data/file");
+        assert!(!mappings[2].HasPages());
+
+        assert_eq!(mappings[3].kind, MappingKind::SharedAnon);
+        assert_eq!(mappings[3].path, "");
+        assert!(mappings[3].HasPages());
+
+        assert_eq!(mappings[4].end, 0x580000);
+        assert_eq!(mappings[4].prot, libc::PROT_NONE);
+        assert!(!mappings[4].HasPages());
+
+        // the offset of a file mapping cut at the start moves with it
+        let mappings = ParseMaps(maps, 0x280000, 0x300000);
+        assert_eq!(mappings[0].offset, 0x81000);
+    }
+
+    #[test]
+    fn test_ClassifyFd() {
+        assert_eq!(ClassifyFd("/etc/hosts"), HostFdKind::File);
+        assert_eq!(ClassifyFd("anon_inode:[eventfd]"), HostFdKind::EventFd);
+        assert_eq!(ClassifyFd("socket:[1234]"), HostFdKind::Broken);
+        assert_eq!(ClassifyFd("pipe:[1234]"), HostFdKind::Broken);
+        assert_eq!(ClassifyFd("/tmp/a (deleted)"), HostFdKind::Broken);
+        assert_eq!(ClassifyFd("anon_inode:[eventpoll]"), HostFdKind::Broken);
+    }
+
+    #[test]
+    fn test_HostFdReopen() {
+        let dir = TempDir();
+        let path = format!("{}/file", dir);
+        fs::write(&path, b"0123456789").unwrap();
+
+        // the fds are moved to high numbers which the other tests don't take
+        // while they are closed
+        let file = CString::new(path.as_str()).unwrap();
+        let mut fds = [0i32; 2];
+        let opened = unsafe {
+            let filefd = libc::open(file.as_ptr(), libc::O_RDWR);
+            libc::lseek(filefd, 4, libc::SEEK_SET);
+            let eventfd = libc::eventfd(0, libc::EFD_NONBLOCK);
+            libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr());
+            [filefd, eventfd, fds[0]]
+        };
+        let (filefd, eventfd, sockfd) = (901, 902, 903);
+        for (fd, to) in opened.iter().zip(&[filefd, eventfd, sockfd]) {
+            unsafe {
+                assert_eq!(libc::dup2(*fd, *to), *to);
+                libc::close(*fd);
+            }
+        }
+
+        let saved = [
+            HostFd::Read(filefd, 0).unwrap(),
+            HostFd::Read(eventfd, 1).unwrap(),
+            HostFd::Read(sockfd, 0).unwrap(),
+        ];
+        assert_eq!(saved[0].kind, HostFdKind::File);
+        assert_eq!(saved[0].path, path);
+        assert_eq!(saved[0].offset, 4);
+        assert_eq!(saved[1].kind, HostFdKind::EventFd);
+        assert_eq!(saved[1].mask, 1);
+        assert_eq!(saved[2].kind, HostFdKind::Broken);
+
+        // the number in use is rejected
+        assert!(saved[0].Reopen().is_err());
+
+        for fd in &[filefd, eventfd, sockfd, fds[1]] {
+            unsafe {
+                libc::close(*fd);
+            }
+        }
+
+        for fd in &saved {
+            fd.Reopen().unwrap();
+            assert_eq!(HostFd::Read(fd.fd, fd.mask).unwrap().kind, fd.kind);
+        }
+
+        let mut buf = [0u8; 6];
+        let ret = unsafe { libc::read(filefd, buf.as_mut_ptr() as _, buf.len()) };
+        assert_eq!(ret, 6);
+        assert_eq!(&buf, b"456789");
+
+        let flags = unsafe { libc::fcntl(eventfd, libc::F_GETFL) };
+        assert!(flags & libc::O_NONBLOCK != 0);
+
+        // the peer of the broken fd is closed
+        let ret = unsafe { libc::read(sockfd, buf.as_mut_ptr() as _, buf.len()) };
+        assert_eq!(ret, 0);
+
+        for fd in &saved {
+            unsafe {
+                libc::close(fd.fd);
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -14,10 +14,13 @@
 
 use spin::Mutex;
 use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::sync::atomic::Ordering;
 use alloc::collections::BTreeSet;
 use std::collections::hash_map::Entry;
+use std::fs;
 use std::fs::OpenOptions;
+use std::path::Path;
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::IntoRawFd;
 use userfaultfd::UffdBuilder;
@@ -32,6 +35,8 @@ use crate::qlib::hiber_mgr::*;
 use crate::qlib::mem::block_allocator::*;
 use crate::SWAP_FILE;
 use crate::SHARE_SPACE;
+use crate::{LocalVcpu, PMA_KEEPER, VMS};
+use crate::vmspace::checkpoint::*;
 //use crate::GLOBAL_ALLOCATOR;
 use crate::vmspace::kernel::SHARESPACE;
 use crate::qlib::linux_def::IoVec;
@@ -129,6 +134,119 @@ impl HiberMgr {
 	}
 }
 
+fn CheckpointErr(e: std::io::Error) -> Error {
+    return Error::SysError(e.raw_os_error().unwrap_or(SysErr::EIO));
+}
+
+impl HiberMgr {
+    // Checkpoint writes the sandbox to the image directory: the vcpu states,
+    // the host fds of the guest with the data to open them again, the host
+    // mappings of the guest memory and their pages, the guest kernel objects
+    // are in the pages. The other vcpus are parked while the image is written
+    // and the sandbox keeps running after it.
+    pub fn Checkpoint(&self, dir: &str) -> Result<()> {
+        if SHARE_SPACE.config.read().EnableRDMA {
+            // the rdma queues are shared with rdma_srv
+            return Err(Error::SysError(SysErr::ENOTSUP));
+        }
+
+        let current = match LocalVcpu() {
+            None => return Err(Error::SysError(SysErr::EINVAL)),
+            Some(vcpu) => vcpu,
+        };
+
+        let vcpus = VMS.lock().vcpus.clone();
+        FreezeVcpus(current.id, &vcpus)?;
+        defer!(ThawVcpus());
+
+        if SHARE_SPACE.reapFileAvaiable.load(Ordering::SeqCst) {
+            // the reaped pages are zeroed in memory, bring them back first
+            self.ReapSwapIn()?;
+        }
+
+        let mut vcpuStates = Vec::with_capacity(vcpus.len());
+        for vcpu in &vcpus {
+            vcpuStates.push(vcpu.SaveState()?);
+        }
+
+        let maps = fs::read_to_string("/proc/self/maps").map_err(CheckpointErr)?;
+        let mappings = ParseMaps(
+            &maps,
+            MemoryDef::PHY_LOWER_ADDR,
+            MemoryDef::HEAP_OFFSET + MemoryDef::HEAP_SIZE,
+        );
+
+        let mut fds = Vec::new();
+        for (fd, mask) in SHARE_SPACE.ioMgr.fdTbl.lock().Fds() {
+            fds.push(HostFd::Read(fd, mask)?);
+        }
+
+        let (pmaStart, pmaLen, pmaRanges) = PMA_KEEPER.Ranges();
+        let (pageTableRoot, vdsoAddr) = {
+            let vms = VMS.lock();
+            (vms.pageTables.GetRoot(), vms.vdsoAddr)
+        };
+        let state = SandboxState {
+            vcpuCount: vcpus.len(),
+            shareSpaceAddr: SHARE_SPACE.Value(),
+            pageTableRoot: pageTableRoot,
+            vdsoAddr: vdsoAddr,
+            checkpointVcpu: current.id,
+            // the QMsg of the HCall is in rsi
+            qmsgAddr: vcpuStates[current.id].regs.rsi,
+            pmaStart: pmaStart,
+            pmaLen: pmaLen,
+            pmaRanges: pmaRanges,
+            hugePages: PMA_KEEPER.hugePages.lock().iter().cloned().collect(),
+            allocPages: PMA_KEEPER.allocPages.lock().iter().cloned().collect(),
+            mappings: mappings,
+            fds: fds,
+        };
+        state.Save(dir)?;
+        fs::write(
+            Path::new(dir).join(CHECKPOINT_VCPU_FILE),
+            EncodeVcpus(&vcpuStates),
+        )
+        .map_err(CheckpointErr)?;
+
+        let swapfd = SWAP_FILE.lock().fd;
+        let mut image = PageImage::Create(dir)?;
+
+        // nothing is allocated from the heap until the pages are written, an
+        // allocation changes the heap pages written before it
+        for m in &state.mappings {
+            if m.HasPages() {
+                image.WriteRange(m.start, m.end)?;
+            }
+        }
+
+        // the swapped out pages are only in the swap file
+        let intern = self.lock();
+        let mut buf = [0u8; MemoryDef::PAGE_SIZE_4K as usize];
+        for (page, offset) in intern.pageMap.iter() {
+            let ret =
+                unsafe { libc::pread(swapfd, buf.as_mut_ptr() as _, buf.len() as _, *offset as _) };
+            if ret != buf.len() as isize {
+                return Err(Error::SysError(SysErr::EIO));
+            }
+            image.WritePage(*page, &buf)?;
+        }
+        let swapped = intern.pageMap.len();
+        drop(intern);
+
+        let count = image.Finish()?;
+        info!(
+            "checkpoint {} vcpus, {} host fds, {} pages, swapped out pages {} to {}",
+            vcpuStates.len(),
+            state.fds.len(),
+            count,
+            swapped,
+            dir
+        );
+        return Ok(());
+    }
+}
+
 pub const REAP_SWAP_FILE_NAME : &str = "./reap_swapfile.data";
 
 impl ReapSwapFile {
@@ -368,6 +486,17 @@ impl SwapFile {
         assert!(offset < self.size);
         return self.mmapAddr + offset;
     }
+
+    // Restore swaps out the pages which were swapped out at the checkpoint,
+    // the restore loads them in memory from the image.
+    // input: page address --> file offset of the checkpointed sandbox
+    pub fn Restore(&mut self, pageMap: &mut BTreeMap<u64, u64>) -> Result<()> {
+        for (page, offset) in pageMap.iter_mut() {
+            *offset = self.SwapOutPage(*page)?;
+        }
+
+        return Ok(())
+    }
 }
 
 impl PageBlock {
@@ -0,0 +1,71 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+use std::collections::BTreeMap;
+
+use super::super::qlib::qmsg::*;
+
+lazy_static! {
+    pub static ref HOST_FILE_HANDLES: Mutex<HostFileHandles> =
+        Mutex::new(HostFileHandles::default());
+}
+
+// HostFileHandleEntry is a host file handle issued by name_to_handle_at for a
+// file the guest could reach, with the identity of the file it was issued for.
+#[derive(Clone, Copy)]
+pub struct HostFileHandleEntry {
+    pub handle: HostFileHandle,
+    pub dev: u64,
+    pub ino: u64,
+}
+
+// HostFileHandles keeps the host file handles issued to the guest. The guest
+// only gets an opaque id: the host handle bytes never leave qvisor, so that
+// the guest can't make qvisor open a host file it was never given a handle of.
+#[derive(Default)]
+pub struct HostFileHandles {
+    pub handles: BTreeMap<u64, HostFileHandleEntry>,
+
+    // (dev, ino) -> id, so that the table grows with the files and not with the
+    // calls
+    pub ids: BTreeMap<(u64, u64), u64>,
+    pub nextId: u64,
+}
+
+impl HostFileHandles {
+    pub fn Add(&mut self, handle: &HostFileHandle, dev: u64, ino: u64) -> u64 {
+        let entry = HostFileHandleEntry {
+            handle: *handle,
+            dev: dev,
+            ino: ino,
+        };
+
+        if let Some(id) = self.ids.get(&(dev, ino)) {
+            self.handles.insert(*id, entry);
+            return *id;
+        }
+
+        let id = self.nextId;
+        self.nextId += 1;
+        self.handles.insert(id, entry);
+        self.ids.insert((dev, ino), id);
+        return id;
+    }
+
+    pub fn Get(&self, id: u64) -> Option<HostFileHandleEntry> {
+        return self.handles.get(&id).cloned();
+    }
+}
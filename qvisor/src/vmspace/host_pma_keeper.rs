@@ -102,6 +102,44 @@ impl HostPMAKeeper {
         }
     }
 
+    // Ranges returns the managed range and the allocated ranges in it
+    pub fn Ranges(&self) -> (u64, u64, Vec<(u64, u64)>) {
+        let ranges = self.ranges.lock();
+        let mut allocated = Vec::new();
+        let mut seg = ranges.FirstSeg();
+        while seg.Ok() {
+            let r = seg.Range();
+            allocated.push((r.Start(), r.Len()));
+            seg = seg.NextSeg();
+        }
+
+        return (ranges.range.Start(), ranges.range.Len(), allocated);
+    }
+
+    // Restore sets the keeper to the state returned by Ranges and the huge
+    // pages of a checkpoint, the memory is mapped by the restore.
+    pub fn Restore(
+        &self,
+        start: u64,
+        len: u64,
+        allocated: &[(u64, u64)],
+        hugePages: &[u64],
+        allocPages: &[u64],
+    ) {
+        {
+            let mut ranges = self.ranges.lock();
+            ranges.Reset(start, len);
+            for &(start, len) in allocated {
+                let gap = ranges.FindGap(start);
+                let seg = ranges.Insert(&gap, &Range::New(start, len), HostSegment {});
+                assert!(seg.Ok(), "AreaSet <HostSegment>:: insert fail");
+            }
+        }
+
+        *self.hugePages.lock() = hugePages.iter().cloned().collect();
+        *self.allocPages.lock() = allocPages.iter().cloned().collect();
+    }
+
     fn Map(&self, mo: &mut MapOption, r: &Range) -> Result<u64> {
         match mo.MMap() {
             Err(e) => {
@@ -25,6 +25,7 @@ use super::super::qlib::kernel::TSC;
 use super::super::qlib::linux_def::*;
 use super::super::qlib::ShareSpace;
 use super::super::*;
+use super::checkpoint::*;
 
 pub struct KIOThread {
     pub eventfd: i32,
@@ -164,6 +165,14 @@ impl KIOThread {
 
         let mut data: u64 = 0;
         loop {
+            // the io thread is parked for a checkpoint before it processes
+            // the guest messages
+            if VcpusFrozen() {
+                if let Some(vcpu) = LocalVcpu() {
+                    ParkVcpu(vcpu.id);
+                }
+            }
+
             sharespace.IncrHostProcessor();
             if sharespace.Shutdown() {
                 return Err(Error::Exit);
@@ -14,11 +14,15 @@
 
 pub mod HostFileMap;
 //pub mod TimerMgr;
+pub mod checkpoint;
+pub mod host_file_handle;
 pub mod host_pma_keeper;
 pub mod host_uring;
 pub mod hostfdnotifier;
 pub mod kernel_io_thread;
 pub mod limits;
+pub mod net_interfaces;
+pub mod netstack_link;
 pub mod random;
 pub mod syscall;
 pub mod time;
@@ -44,6 +48,7 @@ use crate::qlib::fileinfo::*;
 use crate::vmspace::kernel::GlobalIOMgr;
 use crate::vmspace::kernel::GlobalRDMASvcCli;
 
+use self::host_file_handle::*;
 use self::limits::*;
 use self::random::*;
 use self::syscall::*;
@@ -66,6 +71,7 @@ use super::qlib::*;
 use super::runc::container::mounts::*;
 use super::runc::runtime::loader::*;
 use super::runc::runtime::signal_handle::*;
+use super::runc::specutils::firewall::*;
 use super::runc::specutils::specutils::*;
 use super::ucall::usocket::*;
 use super::*;
@@ -119,6 +125,8 @@ pub struct VMSpace {
     pub vcpus: Vec<Arc<KVMVcpu>>,
     pub haveMembarrierGlobal: bool,
     pub haveMembarrierPrivateExpedited: bool,
+    // the host unix sockets the sandbox may connect to
+    pub hostUnixSockets: Vec<loader::HostUnixSocket>,
 }
 
 unsafe impl Sync for VMSpace {}
@@ -223,6 +231,23 @@ impl VMSpace {
             .expect("load limitSet fail")
             .GetInternalCopy();
         process.Caps = Capabilities(false, &spec.process.capabilities);
+        process.DiskQuotas = DiskQuotas(&spec);
+        // the annotation overrides the interfaces of the pod network namespace
+        process.Interfaces = match NetInterfaces(&spec) {
+            Some(intfs) => intfs,
+            None => net_interfaces::SnapshotInterfaces(),
+        };
+        // a sandbox with an invalid policy must not start unprotected
+        process.Firewall = match LoadFirewallPolicy(&spec) {
+            Ok(policies) => policies,
+            Err(e) => {
+                error!("LoadProcessKernel: load firewall policy fail {:?}", e);
+                return -SysErr::EINVAL as i64;
+            }
+        };
+        process.HostUnixSockets = HostUnixSockets(&spec);
+        self.hostUnixSockets = process.HostUnixSockets.clone();
+        process.EgressBandwidth = EgressBandwidth(&spec);
 
         process.HostName = spec.hostname.to_string();
 
@@ -752,6 +777,71 @@ impl VMSpace {
         return fdInfo.IOSendto(buf, size, flags, addr, len);
     }
 
+    pub fn IORecvMMsg(fd: i32, msgvec: u64, vlen: u32, flags: i32) -> i64 {
+        let fdInfo = match Self::GetFdInfo(fd) {
+            Some(info) => info,
+            None => return -SysErr::EBADF as i64,
+        };
+
+        return fdInfo.IORecvMMsg(msgvec, vlen, flags);
+    }
+
+    pub fn IOSendMMsg(fd: i32, msgvec: u64, vlen: u32, flags: i32) -> i64 {
+        let fdInfo = match Self::GetFdInfo(fd) {
+            Some(info) => info,
+            None => return -SysErr::EBADF as i64,
+        };
+
+        return fdInfo.IOSendMMsg(msgvec, vlen, flags);
+    }
+
+    // HostUnixConnect connects to the idx-th host unix socket of the sandbox
+    // allowlist, the guest can't name any other host path.
+    pub fn HostUnixConnect(idx: usize, stype: i32) -> i64 {
+        let path = match VMS.lock().hostUnixSockets.get(idx) {
+            None => return -SysErr::EINVAL as i64,
+            Some(s) => s.HostPath.clone(),
+        };
+
+        let fd = unsafe {
+            socket(
+                AF_UNIX,
+                stype | SocketFlags::SOCK_NONBLOCK | SocketFlags::SOCK_CLOEXEC,
+                0,
+            )
+        };
+
+        if fd < 0 {
+            return Self::GetRet(fd as i64);
+        }
+
+        let mut addr: sockaddr_un = unsafe { core::mem::zeroed() };
+        addr.sun_family = AF_UNIX as sa_family_t;
+        for (i, b) in path.as_bytes().iter().enumerate() {
+            addr.sun_path[i] = *b as c_char;
+        }
+
+        let ret = unsafe {
+            connect(
+                fd,
+                &addr as *const _ as *const sockaddr,
+                core::mem::size_of::<sockaddr_un>() as socklen_t,
+            )
+        };
+
+        if ret < 0 {
+            let ret = Self::GetRet(ret as i64);
+            unsafe {
+                close(fd);
+            }
+            return ret;
+        }
+
+        let hostfd = GlobalIOMgr().AddSocket(fd);
+        URING_MGR.lock().Addfd(fd).unwrap();
+        return hostfd as i64;
+    }
+
     pub fn Fcntl(fd: i32, cmd: i32, arg: u64) -> i64 {
         let fdInfo = match Self::GetFdInfo(fd) {
             Some(info) => info,
@@ -1461,6 +1551,239 @@ impl VMSpace {
         return 0;
     }
 
+    // HostInotifyInit creates the host inotify instance used to observe changes
+    // made to host-backed files from outside of the sandbox, e.g. kubelet
+    // updating a ConfigMap volume. The fd is nonblocking: the guest waits for it
+    // through the host fd notifier and drains it once it becomes readable.
+    pub fn HostInotifyInit() -> i64 {
+        let fd = unsafe { inotify_init1(IN_CLOEXEC | IN_NONBLOCK) };
+        if fd < 0 {
+            return Self::GetRet(fd as i64);
+        }
+
+        let hostfd = GlobalIOMgr().AddFile(fd);
+        match URING_MGR.lock().Addfd(hostfd) {
+            Err(Error::SysError(e)) => {
+                GlobalIOMgr().RemoveFd(hostfd);
+                return -e as i64;
+            }
+            Err(e) => {
+                error!("HostInotifyInit add fd {} fail with error {:?}", hostfd, e);
+                GlobalIOMgr().RemoveFd(hostfd);
+                return -SysErr::EINVAL as i64;
+            }
+            Ok(()) => (),
+        }
+
+        return hostfd as i64;
+    }
+
+    pub fn HostInotifyAddWatch(inotifyfd: i32, fd: i32, mask: u32) -> i64 {
+        let inotifyfd = match Self::GetOsfd(inotifyfd) {
+            Some(fd) => fd,
+            None => return -SysErr::EBADF as i64,
+        };
+
+        let fd = match Self::GetOsfd(fd) {
+            Some(fd) => fd,
+            None => return -SysErr::EBADF as i64,
+        };
+
+        // the backing fd might be opened with O_PATH, go through the proc magic link
+        // so that inotify_add_watch resolves to the same host inode.
+        let path = format!("/proc/self/fd/{}", fd);
+        let cstr = CString::New(&path);
+        let ret = unsafe { inotify_add_watch(inotifyfd, cstr.Ptr() as *const c_char, mask) };
+
+        return Self::GetRet(ret as i64);
+    }
+
+    pub fn HostInotifyRmWatch(inotifyfd: i32, wd: i32) -> i64 {
+        let inotifyfd = match Self::GetOsfd(inotifyfd) {
+            Some(fd) => fd,
+            None => return -SysErr::EBADF as i64,
+        };
+
+        let ret = unsafe { inotify_rm_watch(inotifyfd, wd) };
+
+        return Self::GetRet(ret as i64);
+    }
+
+    // HostNameToHandleAt gets the host handle of the file of fd and keeps it in
+    // HOST_FILE_HANDLES. The guest gets the id of the entry, not the handle.
+    pub fn HostNameToHandleAt(fd: i32) -> i64 {
+        let fd = match Self::GetOsfd(fd) {
+            Some(fd) => fd,
+            None => return -SysErr::EBADF as i64,
+        };
+
+        let mut handle = HostFileHandle::default();
+        let mut mountId: i32 = 0;
+        let ret = unsafe {
+            libc::syscall(
+                SYS_name_to_handle_at,
+                fd,
+                b"\0".as_ptr() as *const c_char,
+                &mut handle as *mut HostFileHandle,
+                &mut mountId as *mut i32,
+                AT_EMPTY_PATH,
+            )
+        };
+
+        let ret = Self::GetRet(ret as i64);
+        if ret < 0 {
+            return ret;
+        }
+
+        let mut st: stat = unsafe { core::mem::zeroed() };
+        let ret = unsafe { libc::fstat(fd, &mut st) };
+        if ret < 0 {
+            return Self::GetRet(ret as i64);
+        }
+
+        let id = HOST_FILE_HANDLES
+            .lock()
+            .Add(&handle, st.st_dev as u64, st.st_ino as u64);
+        return id as i64;
+    }
+
+    // HostOpenByHandleAt opens the host file of the handle id issued by
+    // HostNameToHandleAt in the same way as TryOpenAt: read-write if possible,
+    // then read-only, then O_PATH. The opened file must be the one the handle
+    // was issued for and must be on the filesystem of mountfd.
+    pub fn HostOpenByHandleAt(mountfd: i32, id: u64, readonly: bool, addr: u64) -> i64 {
+        let mountfd = match Self::GetOsfd(mountfd) {
+            Some(fd) => fd,
+            None => return -SysErr::EBADF as i64,
+        };
+
+        let mut entry = match HOST_FILE_HANDLES.lock().Get(id) {
+            None => return -SysErr::ESTALE as i64,
+            Some(entry) => entry,
+        };
+
+        let mut mountStat: stat = unsafe { core::mem::zeroed() };
+        let ret = unsafe { libc::fstat(mountfd, &mut mountStat) };
+        if ret < 0 {
+            return Self::GetRet(ret as i64);
+        }
+
+        if mountStat.st_dev as u64 != entry.dev {
+            return -SysErr::ESTALE as i64;
+        }
+
+        let mut statfs: statvfs = unsafe { core::mem::zeroed() };
+        let ret = unsafe { libc::fstatvfs(mountfd, &mut statfs) };
+        if ret < 0 {
+            return Self::GetRet(ret as i64);
+        }
+
+        let readonly = readonly || statfs.f_flag & ST_RDONLY != 0;
+        let modes: &[(i32, bool)] = if readonly {
+            &[(Flags::O_RDONLY, false), (Flags::O_PATH, false)]
+        } else {
+            &[
+                (Flags::O_RDWR, true),
+                (Flags::O_RDONLY, false),
+                (Flags::O_PATH, false),
+            ]
+        };
+
+        let tryOpenAt = unsafe { &mut *(addr as *mut TryOpenStruct) };
+
+        let mut fd = -SysErr::ESTALE;
+        let mut writeable = false;
+        for (flags, w) in modes.iter() {
+            let ret = unsafe {
+                libc::syscall(
+                    SYS_open_by_handle_at,
+                    mountfd,
+                    &mut entry.handle as *mut HostFileHandle,
+                    *flags | Flags::O_NOFOLLOW,
+                )
+            };
+
+            fd = Self::GetRet(ret as i64) as i32;
+            if fd >= 0 {
+                writeable = *w;
+                break;
+            }
+
+            // the handle itself is bad, no need to try other modes
+            if fd == -SysErr::ESTALE || fd == -SysErr::EINVAL || fd == -SysErr::EPERM {
+                return fd as i64;
+            }
+        }
+
+        if fd < 0 {
+            return fd as i64;
+        }
+
+        let ret =
+            unsafe { libc::fstat(fd, tryOpenAt.fstat as *const _ as u64 as *mut stat) as i64 };
+
+        if ret < 0 {
+            let ret = Self::GetRet(ret as i64);
+            unsafe {
+                libc::close(fd);
+            }
+            return ret;
+        }
+
+        if tryOpenAt.fstat.st_dev != entry.dev || tryOpenAt.fstat.st_ino != entry.ino {
+            unsafe {
+                libc::close(fd);
+            }
+            return -SysErr::ESTALE as i64;
+        }
+
+        tryOpenAt.writeable = writeable;
+        let hostfd = GlobalIOMgr().AddFile(fd);
+
+        if tryOpenAt.fstat.IsRegularFile() {
+            match URING_MGR.lock().Addfd(hostfd) {
+                Err(Error::SysError(e)) => {
+                    GlobalIOMgr().RemoveFd(hostfd);
+                    return -e as i64;
+                }
+                Err(e) => {
+                    error!(
+                        "HostOpenByHandleAt add fd {} fail with error {:?}",
+                        hostfd, e
+                    );
+                    GlobalIOMgr().RemoveFd(hostfd);
+                    return -SysErr::EINVAL as i64;
+                }
+                Ok(()) => (),
+            }
+        }
+
+        return hostfd as i64;
+    }
+
+    // NetstackLinkInit opens the link of the guest netstack, the guest reads
+    // and writes the ethernet frames through uring.
+    pub fn NetstackLinkInit(addr: u64) -> i64 {
+        let info = unsafe { &mut *(addr as *mut NetstackNicInfo) };
+        let fd = netstack_link::OpenLink(info);
+        if fd < 0 {
+            return fd;
+        }
+
+        let hostfd = GlobalIOMgr().AddFile(fd as i32);
+        if let Err(e) = URING_MGR.lock().Addfd(hostfd) {
+            error!("NetstackLinkInit add fd {} fail with error {:?}", hostfd, e);
+            GlobalIOMgr().RemoveFd(hostfd);
+            netstack_link::RestoreLink();
+            match e {
+                Error::SysError(e) => return -e as i64,
+                _ => return -SysErr::EINVAL as i64,
+            }
+        }
+
+        return hostfd as i64;
+    }
+
     pub fn HostEpollWaitProcess() -> i64 {
         let ret = FD_NOTIFIER.HostEpollWait();
         return ret;
@@ -1751,6 +2074,7 @@ impl VMSpace {
             vcpus: Vec::new(),
             haveMembarrierGlobal: haveMembarrierGlobal,
             haveMembarrierPrivateExpedited: haveMembarrierPrivateExpedited,
+            hostUnixSockets: Vec::new(),
         };
     }
 }
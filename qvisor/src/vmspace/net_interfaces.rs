@@ -0,0 +1,159 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use libc::*;
+use std::ffi::CStr;
+
+use super::super::qlib::loader::NetAddr;
+use super::super::qlib::loader::NetInterface;
+use super::netstack_link::*;
+
+fn PrefixLen(mask: &[u8]) -> u8 {
+    return mask.iter().map(|b| b.count_ones()).sum::<u32>() as u8;
+}
+
+fn Find<'a>(intfs: &'a mut Vec<(u32, NetInterface)>, name: &str) -> &'a mut NetInterface {
+    if let Some(i) = intfs.iter().position(|(_, intf)| intf.Name == name) {
+        return &mut intfs[i].1;
+    }
+
+    let index = match std::ffi::CString::new(name) {
+        Ok(n) => unsafe { if_nametoindex(n.as_ptr()) },
+        Err(_) => 0,
+    };
+    intfs.push((
+        index,
+        NetInterface {
+            Name: name.to_string(),
+            Mac: ReadMac(name).unwrap_or_default(),
+            Mtu: ReadMtu(name),
+            ..Default::default()
+        },
+    ));
+    return &mut intfs.last_mut().unwrap().1;
+}
+
+// SnapshotInterfaces returns the network interfaces of the pod network
+// namespace qvisor runs in, loopback excluded. It is taken when the sandbox
+// is loaded, before the netstack takes over an interface, and is the default
+// interface table of the sandbox.
+pub fn SnapshotInterfaces() -> Vec<NetInterface> {
+    let mut intfs: Vec<(u32, NetInterface)> = Vec::new();
+    unsafe {
+        let mut ifap: *mut ifaddrs = core::ptr::null_mut();
+        if getifaddrs(&mut ifap) < 0 {
+            error!("SnapshotInterfaces: getifaddrs fail {}", errno::errno().0);
+            return Vec::new();
+        }
+
+        let mut ifa = ifap;
+        while !ifa.is_null() {
+            let cur = &*ifa;
+            ifa = cur.ifa_next;
+
+            let name = CStr::from_ptr(cur.ifa_name).to_string_lossy().into_owned();
+            if name == "lo" || cur.ifa_flags & IFF_LOOPBACK as u32 != 0 {
+                continue;
+            }
+
+            let intf = Find(&mut intfs, &name);
+            if cur.ifa_addr.is_null() {
+                continue;
+            }
+
+            match (*cur.ifa_addr).sa_family as i32 {
+                AF_INET => {
+                    let addr = &*(cur.ifa_addr as *const sockaddr_in);
+                    let prefixLen = if cur.ifa_netmask.is_null() {
+                        32
+                    } else {
+                        let mask = &*(cur.ifa_netmask as *const sockaddr_in);
+                        PrefixLen(&mask.sin_addr.s_addr.to_ne_bytes())
+                    };
+                    intf.Addrs.push(NetAddr {
+                        Addr: addr.sin_addr.s_addr.to_ne_bytes().to_vec(),
+                        PrefixLen: prefixLen,
+                    });
+                }
+                AF_INET6 => {
+                    let addr = &*(cur.ifa_addr as *const sockaddr_in6);
+                    let prefixLen = if cur.ifa_netmask.is_null() {
+                        128
+                    } else {
+                        let mask = &*(cur.ifa_netmask as *const sockaddr_in6);
+                        PrefixLen(&mask.sin6_addr.s6_addr)
+                    };
+                    intf.Addrs.push(NetAddr {
+                        Addr: addr.sin6_addr.s6_addr.to_vec(),
+                        PrefixLen: prefixLen,
+                    });
+                }
+                _ => (),
+            }
+        }
+
+        freeifaddrs(ifap);
+    }
+
+    for (_, intf) in intfs.iter_mut() {
+        let gateway = Gateway(&intf.Name);
+        if gateway != [0; 4] {
+            intf.Gateways.push(gateway.to_vec());
+        }
+
+        let gateway6 = Gateway6(&intf.Name);
+        if gateway6 != [0; 16] {
+            intf.Gateways.push(gateway6.to_vec());
+        }
+    }
+
+    // the sandbox indexes follow the order of the host ones
+    intfs.sort_by_key(|(index, _)| *index);
+    return intfs.into_iter().map(|(_, intf)| intf).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_PrefixLen() {
+        assert_eq!(PrefixLen(&[255, 255, 255, 0]), 24);
+        assert_eq!(PrefixLen(&[255, 255, 128, 0]), 17);
+        assert_eq!(PrefixLen(&[255; 4]), 32);
+        assert_eq!(PrefixLen(&[0; 4]), 0);
+
+        let mut mask = [0u8; 16];
+        mask[..8].copy_from_slice(&[0xff; 8]);
+        assert_eq!(PrefixLen(&mask), 64);
+        assert_eq!(PrefixLen(&[0xff; 16]), 128);
+    }
+
+    #[test]
+    fn test_SnapshotInterfaces() {
+        // whatever the network namespace of the test has, the loopback is left
+        // out and the addresses are well formed
+        for intf in SnapshotInterfaces() {
+            assert_ne!(intf.Name, "lo");
+            for addr in &intf.Addrs {
+                assert!(addr.Addr.len() == 4 || addr.Addr.len() == 16);
+                assert!(addr.PrefixLen as usize <= addr.Addr.len() * 8);
+            }
+
+            for gw in &intf.Gateways {
+                assert!(gw.len() == 4 || gw.len() == 16);
+            }
+        }
+    }
+}
@@ -0,0 +1,518 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use lazy_static::lazy_static;
+use libc::*;
+use spin::Mutex;
+use std::fs;
+use std::path::Path;
+
+use super::super::qlib::linux_def::*;
+use super::super::qlib::qmsg::*;
+use super::VMSpace;
+
+const TUNSETIFF: u64 = 0x400454ca;
+const IFF_TAP: i16 = 0x0002;
+const IFF_NO_PI: i16 = 0x1000;
+const PACKET_IGNORE_OUTGOING: i32 = 23;
+
+lazy_static! {
+    // the addresses removed from the sandbox interface, given back to the host
+    // when the sandbox exits
+    static ref STRIPPED_LINK: Mutex<Option<StrippedLink>> = Mutex::new(None);
+}
+
+#[derive(Clone, Copy)]
+struct StrippedLink {
+    ifname: [u8; IFNAMSIZ],
+    ifindex: i32,
+    info: NetstackNicInfo,
+    v4: bool,
+    v6: bool,
+}
+
+impl StrippedLink {
+    fn Name(&self) -> String {
+        let len = self.ifname.iter().position(|c| *c == 0).unwrap_or(IFNAMSIZ);
+        return String::from_utf8_lossy(&self.ifname[..len]).into_owned();
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct IfReq {
+    name: [u8; IFNAMSIZ],
+    data: [u8; 24],
+}
+
+impl IfReq {
+    fn New(ifname: &str) -> Self {
+        let mut req = Self::default();
+        let len = core::cmp::min(ifname.len(), IFNAMSIZ - 1);
+        req.name[..len].copy_from_slice(&ifname.as_bytes()[..len]);
+        return req;
+    }
+
+    // SockAddrIn returns the IPv4 address of the sockaddr_in in the request.
+    fn SockAddrIn(&self) -> [u8; 4] {
+        let mut addr = [0; 4];
+        addr.copy_from_slice(&self.data[4..8]);
+        return addr;
+    }
+
+    fn SetSockAddrIn(&mut self, addr: &[u8; 4]) {
+        self.data[0..2].copy_from_slice(&(AF_INET as u16).to_ne_bytes());
+        self.data[4..8].copy_from_slice(addr);
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct In6IfReq {
+    addr: [u8; 16],
+    prefixLen: u32,
+    ifindex: i32,
+}
+
+fn ParseHex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+
+    let mut v = Vec::with_capacity(s.len() / 2);
+    for i in (0..s.len()).step_by(2) {
+        v.push(u8::from_str_radix(&s[i..i + 2], 16).ok()?);
+    }
+
+    return Some(v);
+}
+
+// DefaultRouteInterface returns the interface of the IPv4 default route from
+// /proc/net/route, otherwise the one of the IPv6 default route from
+// /proc/net/ipv6_route.
+fn DefaultRouteInterface(route: &str, route6: &str) -> Option<String> {
+    for line in route.lines().skip(1) {
+        let f: Vec<&str> = line.split_whitespace().collect();
+        // iface destination gateway flags refcnt use metric mask
+        if f.len() >= 8 && f[1] == "00000000" && f[7] == "00000000" {
+            return Some(f[0].to_string());
+        }
+    }
+
+    for line in route6.lines() {
+        let f: Vec<&str> = line.split_whitespace().collect();
+        if f.len() < 10 || f[1] != "00" || f[9] == "lo" {
+            continue;
+        }
+
+        if f[0].chars().all(|c| c == '0') {
+            return Some(f[9].to_string());
+        }
+    }
+
+    return None;
+}
+
+// SandboxInterface returns the interface of the default route of the sandbox
+// network namespace, or its only non loopback interface when there is no
+// default route. The netstack doesn't guess between several interfaces.
+fn SandboxInterface() -> Option<String> {
+    let route = fs::read_to_string("/proc/net/route").unwrap_or_default();
+    let route6 = fs::read_to_string("/proc/net/ipv6_route").unwrap_or_default();
+    if let Some(ifname) = DefaultRouteInterface(&route, &route6) {
+        return Some(ifname);
+    }
+
+    let names: Vec<String> = fs::read_dir("/sys/class/net")
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|n| n != "lo")
+        .collect();
+    if names.len() != 1 {
+        error!(
+            "netstack: no default route, can't choose the interface among {:?}",
+            names
+        );
+        return None;
+    }
+
+    return names.into_iter().next();
+}
+
+pub fn ReadMac(ifname: &str) -> Option<[u8; 6]> {
+    let s = fs::read_to_string(format!("/sys/class/net/{}/address", ifname)).ok()?;
+    let v = ParseHex(&s.trim().replace(":", ""))?;
+    if v.len() != 6 {
+        return None;
+    }
+
+    let mut mac = [0; 6];
+    mac.copy_from_slice(&v);
+    return Some(mac);
+}
+
+pub fn ReadMtu(ifname: &str) -> u32 {
+    return fs::read_to_string(format!("/sys/class/net/{}/mtu", ifname))
+        .ok()
+        .and_then(|s| s.trim().parse::<u32>().ok())
+        .unwrap_or(1500);
+}
+
+// Gateway returns the IPv4 default gateway of the interface from
+// /proc/net/route, whose addresses are hex in host byte order.
+pub fn Gateway(ifname: &str) -> [u8; 4] {
+    let content = fs::read_to_string("/proc/net/route").unwrap_or_default();
+    for line in content.lines().skip(1) {
+        let f: Vec<&str> = line.split_whitespace().collect();
+        if f.len() < 3 || f[0] != ifname || f[1] != "00000000" {
+            continue;
+        }
+
+        if let Ok(gw) = u32::from_str_radix(f[2], 16) {
+            return gw.to_le_bytes();
+        }
+    }
+
+    return [0; 4];
+}
+
+// Addr6 returns the global IPv6 address of the interface and its prefix
+// length from /proc/net/if_inet6.
+fn Addr6(ifname: &str) -> Option<([u8; 16], u32)> {
+    let content = fs::read_to_string("/proc/net/if_inet6").ok()?;
+    for line in content.lines() {
+        let f: Vec<&str> = line.split_whitespace().collect();
+        // address ifindex prefixlen scope flags name
+        if f.len() < 6 || f[5] != ifname || f[3] != "00" {
+            continue;
+        }
+
+        let v = ParseHex(f[0])?;
+        let plen = u32::from_str_radix(f[2], 16).ok()?;
+        let mut addr = [0; 16];
+        addr.copy_from_slice(&v);
+        return Some((addr, plen));
+    }
+
+    return None;
+}
+
+// Gateway6 returns the IPv6 default gateway of the interface from
+// /proc/net/ipv6_route.
+pub fn Gateway6(ifname: &str) -> [u8; 16] {
+    let content = fs::read_to_string("/proc/net/ipv6_route").unwrap_or_default();
+    for line in content.lines() {
+        let f: Vec<&str> = line.split_whitespace().collect();
+        // dest plen src srcplen nexthop metric refcnt use flags name
+        if f.len() < 10 || f[9] != ifname || f[1] != "00" {
+            continue;
+        }
+
+        if f[0].chars().any(|c| c != '0') {
+            continue;
+        }
+
+        if let Some(v) = ParseHex(f[4]) {
+            let mut gw = [0; 16];
+            gw.copy_from_slice(&v);
+            return gw;
+        }
+    }
+
+    return [0; 16];
+}
+
+// InetIoctl runs an ioctl on a new AF_INET/AF_INET6 socket and returns -errno.
+fn InetIoctl<T>(family: i32, req: u64, arg: &mut T) -> i64 {
+    unsafe {
+        let sock = socket(family, SOCK_DGRAM | SOCK_CLOEXEC, 0);
+        if sock < 0 {
+            return VMSpace::GetRet(sock as i64);
+        }
+
+        let ret = ioctl(sock, req, arg as *mut T);
+        let ret = if ret < 0 {
+            VMSpace::GetRet(ret as i64)
+        } else {
+            0
+        };
+        close(sock);
+        return ret;
+    }
+}
+
+fn Addr6Req(link: &StrippedLink) -> In6IfReq {
+    return In6IfReq {
+        addr: link.info.addr6,
+        prefixLen: link.info.prefixLen6,
+        ifindex: link.ifindex,
+    };
+}
+
+// StripAddresses removes the IP addresses of the interface so that the host
+// kernel doesn't answer the frames destined to the guest netstack, e.g. reset
+// the tcp connections it doesn't know. The addresses are given back by
+// RestoreLink, also when only a part of them could be removed.
+fn StripAddresses(ifname: &str, ifindex: i32, info: &NetstackNicInfo) -> i64 {
+    let mut link = StrippedLink {
+        ifname: IfReq::New(ifname).name,
+        ifindex: ifindex,
+        info: *info,
+        v4: false,
+        v6: false,
+    };
+
+    if info.prefixLen > 0 {
+        let mut req = IfReq::New(ifname);
+        req.SetSockAddrIn(&[0; 4]);
+        let ret = InetIoctl(AF_INET, SIOCSIFADDR, &mut req);
+        if ret < 0 {
+            error!(
+                "netstack: fail to remove the ipv4 address of {}: {}",
+                ifname, ret
+            );
+            return ret;
+        }
+        link.v4 = true;
+    }
+
+    if info.prefixLen6 > 0 {
+        let mut req = Addr6Req(&link);
+        let ret = InetIoctl(AF_INET6, SIOCDIFADDR, &mut req);
+        if ret < 0 {
+            error!(
+                "netstack: fail to remove the ipv6 address of {}: {}",
+                ifname, ret
+            );
+            RestoreAddresses(&link);
+            return ret;
+        }
+        link.v6 = true;
+    }
+
+    *STRIPPED_LINK.lock() = Some(link);
+    return 0;
+}
+
+fn RestoreAddresses(link: &StrippedLink) {
+    let ifname = link.Name();
+    let info = &link.info;
+    if link.v4 {
+        let mut req = IfReq::New(&ifname);
+        req.SetSockAddrIn(&info.addr);
+        let ret = InetIoctl(AF_INET, SIOCSIFADDR, &mut req);
+        if ret < 0 {
+            error!(
+                "netstack: fail to restore the ipv4 address of {}: {}",
+                ifname, ret
+            );
+        }
+
+        let mask = if info.prefixLen == 0 {
+            0
+        } else {
+            u32::MAX << (32 - core::cmp::min(info.prefixLen, 32))
+        };
+        let mut req = IfReq::New(&ifname);
+        req.SetSockAddrIn(&mask.to_be_bytes());
+        let ret = InetIoctl(AF_INET, SIOCSIFNETMASK, &mut req);
+        if ret < 0 {
+            error!(
+                "netstack: fail to restore the ipv4 netmask of {}: {}",
+                ifname, ret
+            );
+        }
+
+        // the default route went away with the address
+        if info.gateway != [0; 4] {
+            unsafe {
+                let mut rt: rtentry = core::mem::zeroed();
+                let mut req = IfReq::default();
+                req.SetSockAddrIn(&[0; 4]);
+                rt.rt_dst = *(req.data.as_ptr() as *const sockaddr);
+                rt.rt_genmask = *(req.data.as_ptr() as *const sockaddr);
+                req.SetSockAddrIn(&info.gateway);
+                rt.rt_gateway = *(req.data.as_ptr() as *const sockaddr);
+                rt.rt_flags = RTF_UP | RTF_GATEWAY;
+                let mut name = link.ifname;
+                rt.rt_dev = name.as_mut_ptr() as *mut c_char;
+                let ret = InetIoctl(AF_INET, SIOCADDRT, &mut rt);
+                if ret < 0 && ret != -SysErr::EEXIST as i64 {
+                    error!(
+                        "netstack: fail to restore the default route of {}: {}",
+                        ifname, ret
+                    );
+                }
+            }
+        }
+    }
+
+    if link.v6 {
+        let mut req = Addr6Req(link);
+        let ret = InetIoctl(AF_INET6, SIOCSIFADDR, &mut req);
+        if ret < 0 {
+            error!(
+                "netstack: fail to restore the ipv6 address of {}: {}",
+                ifname, ret
+            );
+        }
+    }
+}
+
+// RestoreLink gives the addresses taken over by the netstack back to the
+// sandbox interface, it is called when the sandbox exits.
+pub fn RestoreLink() {
+    let link = STRIPPED_LINK.lock().take();
+    if let Some(link) = link {
+        info!("netstack: restore the addresses of {}", link.Name());
+        RestoreAddresses(&link);
+    }
+}
+
+fn OpenTap(ifname: &str) -> i32 {
+    unsafe {
+        let path = b"/dev/net/tun\0";
+        let fd = open(path.as_ptr() as *const c_char, O_RDWR | O_CLOEXEC);
+        if fd < 0 {
+            return VMSpace::GetRet(fd as i64) as i32;
+        }
+
+        let mut req = IfReq::New(ifname);
+        req.data[0..2].copy_from_slice(&(IFF_TAP | IFF_NO_PI).to_ne_bytes());
+        let ret = ioctl(fd, TUNSETIFF, &mut req as *mut IfReq);
+        if ret < 0 {
+            let ret = VMSpace::GetRet(ret as i64);
+            close(fd);
+            return ret as i32;
+        }
+
+        return fd;
+    }
+}
+
+fn OpenPacket(ifindex: i32) -> i32 {
+    unsafe {
+        let protocol = (ETH_P_ALL as u16).to_be();
+        let fd = socket(AF_PACKET, SOCK_RAW | SOCK_CLOEXEC, protocol as i32);
+        if fd < 0 {
+            return VMSpace::GetRet(fd as i64) as i32;
+        }
+
+        let mut addr: sockaddr_ll = core::mem::zeroed();
+        addr.sll_family = AF_PACKET as u16;
+        addr.sll_protocol = protocol;
+        addr.sll_ifindex = ifindex;
+        let ret = bind(
+            fd,
+            &addr as *const sockaddr_ll as *const sockaddr,
+            core::mem::size_of::<sockaddr_ll>() as u32,
+        );
+        if ret < 0 {
+            let ret = VMSpace::GetRet(ret as i64);
+            close(fd);
+            return ret as i32;
+        }
+
+        // the frames sent by the netstack itself are not received back
+        let val: i32 = 1;
+        setsockopt(
+            fd,
+            SOL_PACKET,
+            PACKET_IGNORE_OUTGOING,
+            &val as *const i32 as *const c_void,
+            4,
+        );
+
+        return fd;
+    }
+}
+
+// OpenLink takes over the sandbox network interface: it fills info with the
+// interface configuration and returns a host fd exchanging ethernet frames
+// with it, a tap queue when the interface is a tap device, otherwise a packet
+// socket bound to the interface. The fd is blocking so that the uring reads
+// wait for the frames instead of failing with EAGAIN.
+pub fn OpenLink(info: &mut NetstackNicInfo) -> i64 {
+    let ifname = match SandboxInterface() {
+        None => return -SysErr::ENODEV as i64,
+        Some(n) => n,
+    };
+
+    let cname = match std::ffi::CString::new(ifname.clone()) {
+        Ok(n) => n,
+        Err(_) => return -SysErr::EINVAL as i64,
+    };
+    let ifindex = unsafe { if_nametoindex(cname.as_ptr()) } as i32;
+    if ifindex == 0 {
+        return -SysErr::ENODEV as i64;
+    }
+
+    info.mac = match ReadMac(&ifname) {
+        None => return -SysErr::ENODEV as i64,
+        Some(m) => m,
+    };
+    info.mtu = ReadMtu(&ifname);
+
+    unsafe {
+        let sock = socket(AF_INET, SOCK_DGRAM | SOCK_CLOEXEC, 0);
+        if sock < 0 {
+            return VMSpace::GetRet(sock as i64);
+        }
+
+        let mut req = IfReq::New(&ifname);
+        if ioctl(sock, SIOCGIFADDR, &mut req as *mut IfReq) == 0 {
+            info.addr = req.SockAddrIn();
+            let mut req = IfReq::New(&ifname);
+            if ioctl(sock, SIOCGIFNETMASK, &mut req as *mut IfReq) == 0 {
+                info.prefixLen = u32::from_be_bytes(req.SockAddrIn()).count_ones();
+            }
+        }
+        close(sock);
+    }
+
+    info.gateway = Gateway(&ifname);
+    if let Some((addr, plen)) = Addr6(&ifname) {
+        info.addr6 = addr;
+        info.prefixLen6 = plen;
+    }
+    info.gateway6 = Gateway6(&ifname);
+
+    let tap = Path::new(&format!("/sys/class/net/{}/tun_flags", ifname)).exists();
+    let fd = if tap {
+        OpenTap(&ifname)
+    } else {
+        OpenPacket(ifindex)
+    };
+
+    if fd < 0 {
+        error!("netstack: fail to open the link of {}: {}", ifname, fd);
+        return fd as i64;
+    }
+
+    let ret = StripAddresses(&ifname, ifindex, info);
+    if ret < 0 {
+        unsafe {
+            close(fd);
+        }
+        return ret;
+    }
+
+    info!(
+        "netstack: take over {} tap {} addr {:?}/{} gateway {:?} mtu {}",
+        ifname, tap, info.addr, info.prefixLen, info.gateway, info.mtu
+    );
+
+    return fd as i64;
+}
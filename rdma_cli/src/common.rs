@@ -631,7 +631,9 @@ pub const READ_WRITE_FLAGS: i32 = libc::EPOLLET | libc::EPOLLOUT | libc::EPOLLIN
 pub enum FdType {
     TCPSocketServer(u16),  //port
     TCPSocketConnect(u32), //sockfd maintained by RDMASvcCli
+    UDPSocketServer(u16),  //port
     ClientEvent,
+    ReloadTimer,
 }
 
 #[derive(Clone)]
@@ -643,6 +645,8 @@ pub enum Srv_FdType {
     RDMACompletionChannel,
     SrvEventFd(i32),
     NodeEventFd(NodeEvent),
+    HeartbeatTimerFd(i32),
+    QoSTimerFd(i32),
 }
 
 #[derive(Clone, Debug)]
@@ -729,6 +733,37 @@ pub fn epoll_delete(epoll_fd: RawFd, fd: RawFd) -> std::io::Result<()> {
     Ok(())
 }
 
+// MonotonicUs returns the monotonic clock in microseconds
+pub fn MonotonicUs() -> u64 {
+    let mut ts: libc::timespec = unsafe { mem::zeroed() };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    return ts.tv_sec as u64 * 1_000_000 + ts.tv_nsec as u64 / 1000;
+}
+
+// timerfd_create creates a non-blocking timer firing every intervalMs
+pub fn timerfd_create(intervalMs: u64) -> std::io::Result<RawFd> {
+    let fd = syscall!(timerfd_create(libc::CLOCK_MONOTONIC, libc::TFD_NONBLOCK))?;
+    let interval = libc::timespec {
+        tv_sec: (intervalMs / 1000) as i64,
+        tv_nsec: ((intervalMs % 1000) * 1_000_000) as i64,
+    };
+    let spec = libc::itimerspec {
+        it_interval: interval,
+        it_value: interval,
+    };
+    syscall!(timerfd_settime(fd, 0, &spec, std::ptr::null_mut()))?;
+    Ok(fd)
+}
+
+// WriteFileAtomic writes the file through a temporary file renamed over it, so
+// that the readers never see a partial file
+pub fn WriteFileAtomic(path: &std::path::Path, content: &str) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, content)?;
+    return std::fs::rename(&tmp, path);
+}
+
 pub fn unblock_fd(fd: i32) {
     unsafe {
         let flags = libc::fcntl(fd, Cmd::F_GETFL, 0);
@@ -174,7 +174,9 @@ fn wait(epoll_fd: i32, gatewayCli: &GatewayClient, fds: &mut HashMap<i32, FdType
         for ev in &events {
             let event_data = fds.get(&(ev.U64 as i32));
             match event_data {
-                Some(FdType::TCPSocketServer(_port)) => {
+                Some(FdType::TCPSocketServer(_))
+                | Some(FdType::UDPSocketServer(_))
+                | Some(FdType::ReloadTimer) => {
                     println!("Egress gateway doesn't have this type!");
                 }
                 Some(FdType::TCPSocketConnect(sockfd)) => {
@@ -234,6 +236,13 @@ fn wait(epoll_fd: i32, gatewayCli: &GatewayClient, fds: &mut HashMap<i32, FdType
 
                                     gatewayCli.ReadFromSocket(sockInfo, &sockFdMappings);
                                 }
+                                RDMARespMsg::RDMAConnectFail(response) => {
+                                    // the egress gateway doesn't connect over RDMA
+                                    println!(
+                                        "Egress gateway gets connect failure, sockfd: {}",
+                                        response.sockfd
+                                    );
+                                }
                                 RDMARespMsg::RDMAAccept(response) => {
                                     let mut sockFdInfos = gatewayCli.serverSockFdInfos.lock();
                                     let sockInfo = sockFdInfos.get_mut(&response.sockfd).unwrap();
@@ -68,6 +68,7 @@ pub mod kernel_def;
 pub mod qlib;
 
 pub mod common;
+pub mod port_mapping;
 pub mod rdma_def;
 pub mod unix_socket_def;
 
@@ -87,6 +88,7 @@ use self::qlib::mem::list_allocator::*;
 use crate::qlib::rdma_share::*;
 use common::EpollEvent;
 use common::*;
+use port_mapping::*;
 use qlib::linux_def::*;
 use qlib::rdma_svc_cli::*;
 use qlib::socket_buf::{SocketBuff, SocketBuffIntern};
@@ -119,40 +121,25 @@ fn main() -> io::Result<()> {
     epoll_add(epoll_fd, cliEventFd, read_event(cliEventFd as u64))?;
     fds.insert(cliEventFd, FdType::ClientEvent);
 
-    // set up TCP Server to wait for incoming connection
-    let server_fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0) };
-    fds.insert(server_fd, FdType::TCPSocketServer(6666));
-    unblock_fd(server_fd);
-    epoll_add(epoll_fd, server_fd, read_write_event(server_fd as u64))?;
-    unsafe {
-        // TODO: need mapping 6666 -> (172.16.1.6:8888) for testing purpose, late should come from control plane
-        let serv_addr: libc::sockaddr_in = libc::sockaddr_in {
-            sin_family: libc::AF_INET as u16,
-            sin_port: 6666u16.to_be(),
-            sin_addr: libc::in_addr {
-                s_addr: u32::from_be_bytes([0, 0, 0, 0]).to_be(),
-            },
-            sin_zero: mem::zeroed(),
-        };
+    // the listeners of the port mappings are set up by the first poll, the
+    // timer reloads the mappings when the config file changes
+    let mut mappings = PortMappings::New();
+    mappings.Poll(epoll_fd, &mut fds);
+    let timer_fd = timerfd_create(INGRESS_RELOAD_INTERVAL_MS)?;
+    epoll_add(epoll_fd, timer_fd, read_event(timer_fd as u64))?;
+    fds.insert(timer_fd, FdType::ReloadTimer);
 
-        let result = libc::bind(
-            server_fd,
-            &serv_addr as *const libc::sockaddr_in as *const libc::sockaddr,
-            mem::size_of_val(&serv_addr) as u32,
-        );
-        if result < 0 {
-            libc::close(server_fd);
-            panic!("last OS error: {:?}", Error::last_os_error());
-        }
-        libc::listen(server_fd, 128);
-    }
-
-    wait(epoll_fd, &gatewayCli, &mut fds);
+    wait(epoll_fd, &gatewayCli, &mut fds, &mut mappings);
 
     return Ok(());
 }
 
-fn wait(epoll_fd: i32, gatewayCli: &GatewayClient, fds: &mut HashMap<i32, FdType>) {
+fn wait(
+    epoll_fd: i32,
+    gatewayCli: &GatewayClient,
+    fds: &mut HashMap<i32, FdType>,
+    mappings: &mut PortMappings,
+) {
     let mut events: Vec<EpollEvent> = Vec::with_capacity(1024);
     let mut sockFdMappings: HashMap<u32, i32> = HashMap::new(); // mapping between sockfd maintained by rdmaSvcCli and fd for incoming requests.
     loop {
@@ -180,7 +167,8 @@ fn wait(epoll_fd: i32, gatewayCli: &GatewayClient, fds: &mut HashMap<i32, FdType
         for ev in &events {
             let event_data = fds.get(&(ev.U64 as i32));
             match event_data {
-                Some(FdType::TCPSocketServer(_port)) => {
+                Some(FdType::TCPSocketServer(port)) => {
+                    let port = *port;
                     let mut stream_fd;
                     let mut cliaddr: libc::sockaddr_in = unsafe { mem::zeroed() };
                     let mut len = mem::size_of_val(&cliaddr) as u32;
@@ -193,25 +181,59 @@ fn wait(epoll_fd: i32, gatewayCli: &GatewayClient, fds: &mut HashMap<i32, FdType
                             );
                         }
                         if stream_fd > 0 {
+                            let mapping = match mappings.Get(MappingProtocol::TCP, port) {
+                                Some(mapping) => mapping,
+                                None => {
+                                    // the mapping is removed by a reload
+                                    close(stream_fd);
+                                    continue;
+                                }
+                            };
+                            let (ipAddr, targetPort) = mapping.TCPTarget();
+                            let sockfd = match gatewayCli.sockIdMgr.lock().AllocId() {
+                                Ok(sockfd) => sockfd,
+                                Err(e) => {
+                                    error!("fail to alloc sockfd for port {}: {:?}", port, e);
+                                    mapping.stats.connectFailures += 1;
+                                    close(stream_fd);
+                                    continue;
+                                }
+                            };
+                            if gatewayCli.connect(sockfd, ipAddr, targetPort).is_err() {
+                                mapping.stats.connectFailures += 1;
+                                gatewayCli.sockIdMgr.lock().Remove(sockfd);
+                                close(stream_fd);
+                                continue;
+                            }
+
                             unblock_fd(stream_fd);
                             let _ret =
                                 epoll_add(epoll_fd, stream_fd, read_write_event(stream_fd as u64));
-
-                            //TODO: use port to map to different (ip, port), hardcode for testing purpose, should come from control plane in the future
-                            let sockfd = gatewayCli.sockIdMgr.lock().AllocId().unwrap(); //TODO: rename sockfd
-                            let egressEndpoint = Endpoint::Egress();
-                            let _ret = gatewayCli.connect(
-                                sockfd,
-                                egressEndpoint.ipAddr,
-                                egressEndpoint.port,
-                            );
                             fds.insert(stream_fd, FdType::TCPSocketConnect(sockfd));
                             sockFdMappings.insert(sockfd, stream_fd);
+                            mappings.ConnAccepted(sockfd, port);
                         } else {
                             break;
                         }
                     }
                 }
+                Some(FdType::UDPSocketServer(port)) => {
+                    let port = *port;
+                    mappings.ReadUDP(gatewayCli, ev.U64 as i32, port);
+                }
+                Some(FdType::ReloadTimer) => {
+                    let mut expirations = 0u64;
+                    unsafe {
+                        libc::read(
+                            ev.U64 as i32,
+                            &mut expirations as *mut u64 as *mut libc::c_void,
+                            8,
+                        )
+                    };
+                    mappings.Poll(epoll_fd, fds);
+                    mappings.Prune(gatewayCli);
+                    mappings.WriteStats();
+                }
                 Some(FdType::TCPSocketConnect(sockfd)) => {
                     let mut sockInfo = gatewayCli.GetDataSocket(sockfd);
                     if !matches!(*sockInfo.status.lock(), SockStatus::ESTABLISHED) {
@@ -277,6 +299,16 @@ fn wait(epoll_fd: i32, gatewayCli: &GatewayClient, fds: &mut HashMap<i32, FdType
 
                                     gatewayCli.ReadFromSocket(sockInfo, &sockFdMappings);
                                 }
+                                RDMARespMsg::RDMAConnectFail(response) => {
+                                    // the connect is refused, e.g. by a network policy
+                                    gatewayCli.dataSockFdInfos.lock().remove(&response.sockfd);
+                                    gatewayCli.sockIdMgr.lock().Remove(response.sockfd);
+                                    if let Some(stream_fd) = sockFdMappings.remove(&response.sockfd) {
+                                        fds.remove(&stream_fd);
+                                        close(stream_fd);
+                                    }
+                                    mappings.ConnRefused(response.sockfd);
+                                }
                                 RDMARespMsg::RDMAAccept(response) => {
                                     let mut sockFdInfos = gatewayCli.serverSockFdInfos.lock();
                                     let sockInfo = sockFdInfos.get_mut(&response.sockfd).unwrap();
@@ -343,10 +375,21 @@ fn wait(epoll_fd: i32, gatewayCli: &GatewayClient, fds: &mut HashMap<i32, FdType
                                         gatewayCli.WriteToSocket(&mut sockInfo, &sockFdMappings);
                                     }
                                 }
-                                RDMARespMsg::RDMAReturnUDPBuff(_response) => {
-                                    // TODO Handle UDP
+                                RDMARespMsg::RDMAReturnUDPBuff(response) => {
+                                    gatewayCli
+                                        .rdmaSvcCli
+                                        .udpSentBufferAllocator
+                                        .lock()
+                                        .ReturnBuffer(response.udpBuffIdx);
+                                }
+                                RDMARespMsg::RDMARecvUDPPacket(response) => {
+                                    let udpPacket =
+                                        gatewayCli.rdmaSvcCli.cliShareRegion.lock().udpBufRecv
+                                            [response.udpBuffIdx as usize];
+                                    let _ret =
+                                        gatewayCli.rdmaSvcCli.returnUDPBuff(response.udpBuffIdx);
+                                    mappings.WriteUDP(&udpPacket);
                                 }
-                                RDMARespMsg::RDMARecvUDPPacket(_udpBuffIdx) => todo!()
                             },
                             None => {
                                 break;
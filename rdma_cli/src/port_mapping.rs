@@ -0,0 +1,784 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::mem;
+use std::net::Ipv4Addr;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use super::common::*;
+use super::qlib::rdma_share::*;
+
+// the json file of the port mappings, it can be a mounted ConfigMap, or it is
+// written by rdma_srv from the tsor-ingress-mappings ConfigMap of the informer
+pub const INGRESS_CONFIG_ENV: &str = "RDMA_INGRESS_CONFIG";
+// the json file the per-mapping counters are written to
+pub const INGRESS_STATS_ENV: &str = "RDMA_INGRESS_STATS";
+pub const INGRESS_RELOAD_INTERVAL_MS: u64 = 1000;
+
+// the source ports of the UDP sessions over RDMA
+pub const UDP_SESSION_PORT_START: u32 = 40000;
+pub const UDP_SESSION_PORT_COUNT: u32 = 16384;
+pub const UDP_SESSION_IDLE_US: u64 = 60 * 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MappingProtocol {
+    TCP,
+    UDP,
+}
+
+impl MappingProtocol {
+    pub fn Parse(protocol: &str) -> Result<Self, String> {
+        match protocol {
+            "TCP" | "tcp" => return Ok(MappingProtocol::TCP),
+            "UDP" | "udp" => return Ok(MappingProtocol::UDP),
+            _ => return Err(format!("unknown protocol {}", protocol)),
+        }
+    }
+
+    pub fn Name(&self) -> &'static str {
+        match self {
+            MappingProtocol::TCP => return "TCP",
+            MappingProtocol::UDP => return "UDP",
+        }
+    }
+}
+
+// PortMappingEntry is one mapping of the config file:
+// {"mappings": [{"name": "web", "protocol": "TCP", "port": 8080,
+//                "target_ip": "10.96.0.20", "target_port": 80}]}
+// the target is a service cluster ip or a pod ip.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct PortMappingEntry {
+    pub name: String,
+    pub protocol: String,
+    pub port: u16,
+    pub target_ip: String,
+    pub target_port: u16,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct IngressConfig {
+    pub mappings: Vec<PortMappingEntry>,
+}
+
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct PortMappingStats {
+    pub accepted: u64,
+    pub active: u64,
+    pub connectFailures: u64,
+    pub udpPacketsIn: u64,
+    pub udpPacketsOut: u64,
+    pub udpDrops: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PortMappingReport {
+    pub name: String,
+    pub protocol: String,
+    pub port: u16,
+    pub target: String,
+    pub stats: PortMappingStats,
+}
+
+pub struct PortMapping {
+    pub name: String,
+    pub protocol: MappingProtocol,
+    pub port: u16,
+    pub targetIp: Ipv4Addr,
+    pub targetPort: u16,
+    pub listenFd: RawFd,
+    pub stats: PortMappingStats,
+}
+
+impl PortMapping {
+    // TCP target in the encoding of RDMASvcClient::connect
+    pub fn TCPTarget(&self) -> (u32, u16) {
+        return (u32::from(self.targetIp).to_be(), self.targetPort.to_be());
+    }
+
+    // UDP target in the encoding of UDPPacket
+    pub fn UDPTarget(&self) -> (u32, u16) {
+        return (u32::from(self.targetIp), self.targetPort);
+    }
+}
+
+// UDPSession is a client of a UDP mapping, the replies to its source port over
+// RDMA are sent back to the client address.
+pub struct UDPSession {
+    pub port: u16,
+    pub clientAddr: libc::sockaddr_in,
+    pub lastActive: u64,
+}
+
+pub struct PortMappings {
+    pub configPath: Option<PathBuf>,
+    pub statsPath: Option<PathBuf>,
+    pub modified: Option<SystemTime>,
+    pub loaded: bool,
+    // (protocol, listen port) --> mapping
+    pub mappings: HashMap<(MappingProtocol, u16), PortMapping>,
+    // sockfd maintained by rdmaSvcCli --> listen port of the TCP connection
+    pub conns: HashMap<u32, u16>,
+    // (listen port, client ip, client port) --> session source port
+    pub udpSessions: HashMap<(u16, u32, u16), u16>,
+    // session source port --> session
+    pub udpSessionPorts: HashMap<u16, UDPSession>,
+    pub udpPortMgr: IdMgr,
+}
+
+impl PortMappings {
+    pub fn New() -> Self {
+        let path = |name: &str| match env::var(name) {
+            Ok(path) if path.len() > 0 => Some(PathBuf::from(path)),
+            _ => None,
+        };
+
+        return PortMappings {
+            configPath: path(INGRESS_CONFIG_ENV),
+            statsPath: path(INGRESS_STATS_ENV),
+            modified: None,
+            loaded: false,
+            mappings: HashMap::new(),
+            conns: HashMap::new(),
+            udpSessions: HashMap::new(),
+            udpSessionPorts: HashMap::new(),
+            udpPortMgr: IdMgr::Init(UDP_SESSION_PORT_START, UDP_SESSION_PORT_COUNT),
+        };
+    }
+
+    // DefaultEntries is the mapping without config file: 6666 -> egress gateway
+    fn DefaultEntries() -> Vec<PortMappingEntry> {
+        return vec![PortMappingEntry {
+            name: "default".to_string(),
+            protocol: "TCP".to_string(),
+            port: 6666,
+            target_ip: "0.0.1.1".to_string(),
+            target_port: 16868,
+        }];
+    }
+
+    fn Load(&self) -> Result<Vec<PortMappingEntry>, String> {
+        let path = match &self.configPath {
+            None => return Ok(Self::DefaultEntries()),
+            Some(path) => path,
+        };
+
+        let content = fs::read_to_string(path).map_err(|e| format!("{:?}", e))?;
+        let config: IngressConfig =
+            serde_json::from_str(&content).map_err(|e| format!("{:?}", e))?;
+        return Ok(config.mappings);
+    }
+
+    // Poll reloads the config file when it is changed. The listeners of the
+    // removed mappings are closed and the ones of the new mappings are opened,
+    // the accepted connections are not touched.
+    pub fn Poll(&mut self, epoll_fd: RawFd, fds: &mut HashMap<i32, FdType>) {
+        let modified = match &self.configPath {
+            None => None,
+            Some(path) => fs::metadata(path).and_then(|m| m.modified()).ok(),
+        };
+        if self.configPath.is_some() && modified.is_none() {
+            // wait for the config file
+            return;
+        }
+        if self.loaded && modified == self.modified {
+            return;
+        }
+        self.loaded = true;
+        self.modified = modified;
+
+        let entries = match self.Load() {
+            Ok(entries) => entries,
+            Err(e) => {
+                // keep the current mappings, the file may be half written
+                error!("fail to load ingress config {:?}: {}", self.configPath, e);
+                return;
+            }
+        };
+
+        let mut desired = HashMap::new();
+        for entry in entries {
+            let protocol = match MappingProtocol::Parse(&entry.protocol) {
+                Ok(p) => p,
+                Err(e) => {
+                    error!("invalid port mapping {}: {}", entry.name, e);
+                    continue;
+                }
+            };
+            match Ipv4Addr::from_str(&entry.target_ip) {
+                Ok(ip) => {
+                    desired.insert((protocol, entry.port), (entry, ip));
+                }
+                Err(e) => error!("invalid port mapping {}: {:?}", entry.name, e),
+            }
+        }
+
+        let removed: Vec<_> = self
+            .mappings
+            .keys()
+            .filter(|key| !desired.contains_key(*key))
+            .cloned()
+            .collect();
+        for key in removed {
+            let mapping = self.mappings.remove(&key).unwrap();
+            let _ret = epoll_delete(epoll_fd, mapping.listenFd);
+            fds.remove(&mapping.listenFd);
+            close(mapping.listenFd);
+            info!(
+                "remove port mapping {} {}:{}",
+                mapping.name,
+                key.0.Name(),
+                key.1
+            );
+        }
+
+        for ((protocol, port), (entry, targetIp)) in desired {
+            if let Some(mapping) = self.mappings.get_mut(&(protocol, port)) {
+                // the new connections go to the new target
+                mapping.name = entry.name;
+                mapping.targetIp = targetIp;
+                mapping.targetPort = entry.target_port;
+                continue;
+            }
+
+            let listenFd = match Listen(protocol, port) {
+                Ok(fd) => fd,
+                Err(e) => {
+                    error!(
+                        "fail to listen on {}:{} for {}: {:?}",
+                        protocol.Name(),
+                        port,
+                        entry.name,
+                        e
+                    );
+                    continue;
+                }
+            };
+            let fdType = match protocol {
+                MappingProtocol::TCP => FdType::TCPSocketServer(port),
+                MappingProtocol::UDP => FdType::UDPSocketServer(port),
+            };
+            fds.insert(listenFd, fdType);
+            let _ret = epoll_add(epoll_fd, listenFd, read_event(listenFd as u64));
+            info!(
+                "add port mapping {} {}:{} -> {}:{}",
+                entry.name,
+                protocol.Name(),
+                port,
+                targetIp,
+                entry.target_port
+            );
+            self.mappings.insert(
+                (protocol, port),
+                PortMapping {
+                    name: entry.name,
+                    protocol: protocol,
+                    port: port,
+                    targetIp: targetIp,
+                    targetPort: entry.target_port,
+                    listenFd: listenFd,
+                    stats: PortMappingStats::default(),
+                },
+            );
+        }
+
+        let udpPorts: Vec<_> = self
+            .udpSessionPorts
+            .iter()
+            .filter(|(_, s)| !self.mappings.contains_key(&(MappingProtocol::UDP, s.port)))
+            .map(|(port, _)| *port)
+            .collect();
+        for port in udpPorts {
+            self.RemoveUDPSession(port);
+        }
+    }
+
+    pub fn Get(&mut self, protocol: MappingProtocol, port: u16) -> Option<&mut PortMapping> {
+        return self.mappings.get_mut(&(protocol, port));
+    }
+
+    pub fn ConnAccepted(&mut self, sockfd: u32, port: u16) {
+        if let Some(oldPort) = self.conns.insert(sockfd, port) {
+            self.ConnClosed(oldPort);
+        }
+        if let Some(mapping) = self.Get(MappingProtocol::TCP, port) {
+            mapping.stats.accepted += 1;
+            mapping.stats.active += 1;
+        }
+    }
+
+    fn ConnClosed(&mut self, port: u16) {
+        if let Some(mapping) = self.Get(MappingProtocol::TCP, port) {
+            if mapping.stats.active > 0 {
+                mapping.stats.active -= 1;
+            }
+        }
+    }
+
+    // ConnRefused drops the connection refused by rdma_srv
+    pub fn ConnRefused(&mut self, sockfd: u32) {
+        if let Some(port) = self.conns.remove(&sockfd) {
+            self.ConnClosed(port);
+            if let Some(mapping) = self.Get(MappingProtocol::TCP, port) {
+                mapping.stats.connectFailures += 1;
+            }
+        }
+    }
+
+    // Prune drops the connections closed by the gateway client and the idle
+    // UDP sessions.
+    pub fn Prune(&mut self, gatewayCli: &GatewayClient) {
+        let closed: Vec<_> = {
+            let dataSockFdInfos = gatewayCli.dataSockFdInfos.lock();
+            self.conns
+                .keys()
+                .filter(|sockfd| !dataSockFdInfos.contains_key(*sockfd))
+                .cloned()
+                .collect()
+        };
+        for sockfd in closed {
+            let port = self.conns.remove(&sockfd).unwrap();
+            self.ConnClosed(port);
+        }
+
+        self.ExpireUDPSessions(MonotonicUs());
+    }
+
+    // ExpireUDPSessions drops the UDP sessions idle for UDP_SESSION_IDLE_US
+    pub fn ExpireUDPSessions(&mut self, now: u64) {
+        let idle: Vec<_> = self
+            .udpSessionPorts
+            .iter()
+            .filter(|(_, s)| s.lastActive + UDP_SESSION_IDLE_US < now)
+            .map(|(port, _)| *port)
+            .collect();
+        for port in idle {
+            self.RemoveUDPSession(port);
+        }
+    }
+
+    // UDPSession returns the source port of the client of the mapping
+    fn UDPSession(&mut self, port: u16, clientAddr: &libc::sockaddr_in) -> Option<u16> {
+        let key = (port, clientAddr.sin_addr.s_addr, clientAddr.sin_port);
+        if let Some(sessionPort) = self.udpSessions.get(&key) {
+            let session = self.udpSessionPorts.get_mut(sessionPort).unwrap();
+            session.lastActive = MonotonicUs();
+            return Some(*sessionPort);
+        }
+
+        let sessionPort = self.udpPortMgr.AllocId().ok()? as u16;
+        self.udpSessions.insert(key, sessionPort);
+        self.udpSessionPorts.insert(
+            sessionPort,
+            UDPSession {
+                port: port,
+                clientAddr: *clientAddr,
+                lastActive: MonotonicUs(),
+            },
+        );
+        return Some(sessionPort);
+    }
+
+    fn RemoveUDPSession(&mut self, sessionPort: u16) {
+        if let Some(session) = self.udpSessionPorts.remove(&sessionPort) {
+            self.udpSessions.remove(&(
+                session.port,
+                session.clientAddr.sin_addr.s_addr,
+                session.clientAddr.sin_port,
+            ));
+            self.udpPortMgr.Remove(sessionPort as u32);
+        }
+    }
+
+    // ReadUDP forwards the datagrams received on the listener of the mapping
+    // to its target with RDMASendUDPPacket.
+    pub fn ReadUDP(&mut self, gatewayCli: &GatewayClient, fd: RawFd, port: u16) {
+        let mut buf = [0u8; UDP_BUFF_LEN];
+        loop {
+            let mut clientAddr: libc::sockaddr_in = unsafe { mem::zeroed() };
+            let mut len = mem::size_of_val(&clientAddr) as u32;
+            let cnt = unsafe {
+                libc::recvfrom(
+                    fd,
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    libc::MSG_TRUNC,
+                    &mut clientAddr as *mut libc::sockaddr_in as *mut libc::sockaddr,
+                    &mut len,
+                )
+            };
+            if cnt < 0 {
+                break;
+            }
+
+            let sessionPort = self.UDPSession(port, &clientAddr);
+            let mapping = match self.Get(MappingProtocol::UDP, port) {
+                Some(mapping) => mapping,
+                None => return,
+            };
+            mapping.stats.udpPacketsIn += 1;
+            let sessionPort = match sessionPort {
+                Some(p) if cnt as usize <= UDP_BUFF_LEN => p,
+                _ => {
+                    mapping.stats.udpDrops += 1;
+                    continue;
+                }
+            };
+
+            let (udpBuffAddr, udpBuffIdx) = gatewayCli
+                .rdmaSvcCli
+                .udpSentBufferAllocator
+                .lock()
+                .GetFreeBuffer();
+            if udpBuffAddr == 0 {
+                mapping.stats.udpDrops += 1;
+                continue;
+            }
+
+            let (dstIpAddr, dstPort) = mapping.UDPTarget();
+            let udpPacket = unsafe { &mut (*(udpBuffAddr as *mut UDPPacket)) };
+            udpPacket.srcPort = sessionPort;
+            udpPacket.dstIpAddr = dstIpAddr;
+            udpPacket.dstPort = dstPort;
+            udpPacket.length = cnt as u16;
+            udpPacket.buf[..cnt as usize].copy_from_slice(&buf[..cnt as usize]);
+            if gatewayCli.rdmaSvcCli.sendUDPPacket(udpBuffIdx).is_err() {
+                gatewayCli
+                    .rdmaSvcCli
+                    .udpSentBufferAllocator
+                    .lock()
+                    .ReturnBuffer(udpBuffIdx);
+                mapping.stats.udpDrops += 1;
+            }
+        }
+    }
+
+    // WriteUDP sends the reply received over RDMA back to the session client
+    pub fn WriteUDP(&mut self, udpPacket: &UDPPacket) {
+        let (port, clientAddr) = match self.udpSessionPorts.get_mut(&udpPacket.dstPort) {
+            Some(session) => {
+                session.lastActive = MonotonicUs();
+                (session.port, session.clientAddr)
+            }
+            None => {
+                error!("no UDP session for port {}", udpPacket.dstPort);
+                return;
+            }
+        };
+
+        let mapping = match self.Get(MappingProtocol::UDP, port) {
+            Some(mapping) => mapping,
+            None => return,
+        };
+        let len = (udpPacket.length as usize).min(UDP_BUFF_LEN);
+        let cnt = unsafe {
+            libc::sendto(
+                mapping.listenFd,
+                udpPacket.buf.as_ptr() as *const libc::c_void,
+                len,
+                0,
+                &clientAddr as *const libc::sockaddr_in as *const libc::sockaddr,
+                mem::size_of_val(&clientAddr) as u32,
+            )
+        };
+        if cnt < 0 {
+            mapping.stats.udpDrops += 1;
+        } else {
+            mapping.stats.udpPacketsOut += 1;
+        }
+    }
+
+    // WriteStats writes the counters of the mappings to the stats file
+    pub fn WriteStats(&self) {
+        let path = match &self.statsPath {
+            None => return,
+            Some(path) => path,
+        };
+
+        let mut reports: Vec<_> = self
+            .mappings
+            .values()
+            .map(|m| PortMappingReport {
+                name: m.name.clone(),
+                protocol: m.protocol.Name().to_string(),
+                port: m.port,
+                target: format!("{}:{}", m.targetIp, m.targetPort),
+                stats: m.stats.clone(),
+            })
+            .collect();
+        reports.sort_by(|a, b| (&a.protocol, a.port).cmp(&(&b.protocol, b.port)));
+
+        let content = match serde_json::to_string_pretty(&reports) {
+            Ok(content) => content,
+            Err(e) => {
+                error!("fail to serialize ingress stats: {:?}", e);
+                return;
+            }
+        };
+        if let Err(e) = WriteFileAtomic(path, &content) {
+            error!("fail to write ingress stats {:?}: {:?}", path, e);
+        }
+    }
+}
+
+// Listen opens the non-blocking listener of a mapping on all the addresses
+fn Listen(protocol: MappingProtocol, port: u16) -> std::io::Result<RawFd> {
+    let sockType = match protocol {
+        MappingProtocol::TCP => libc::SOCK_STREAM,
+        MappingProtocol::UDP => libc::SOCK_DGRAM,
+    };
+    let fd = syscall!(socket(libc::AF_INET, sockType, 0))?;
+
+    let addr: libc::sockaddr_in = libc::sockaddr_in {
+        sin_family: libc::AF_INET as u16,
+        sin_port: port.to_be(),
+        sin_addr: libc::in_addr { s_addr: 0 },
+        sin_zero: unsafe { mem::zeroed() },
+    };
+    let reuse: i32 = 1;
+    let res = syscall!(setsockopt(
+        fd,
+        libc::SOL_SOCKET,
+        libc::SO_REUSEADDR,
+        &reuse as *const i32 as *const libc::c_void,
+        mem::size_of_val(&reuse) as u32,
+    ))
+    .and_then(|_| {
+        syscall!(bind(
+            fd,
+            &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+            mem::size_of_val(&addr) as u32,
+        ))
+    })
+    .and_then(|_| match protocol {
+        MappingProtocol::TCP => syscall!(listen(fd, 128)),
+        MappingProtocol::UDP => Ok(0),
+    });
+    if let Err(e) = res {
+        close(fd);
+        return Err(e);
+    }
+
+    unblock_fd(fd);
+    return Ok(fd);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, UdpSocket};
+
+    fn FreeTCPPort() -> u16 {
+        let listener = TcpListener::bind("0.0.0.0:0").unwrap();
+        return listener.local_addr().unwrap().port();
+    }
+
+    fn FreeUDPPort() -> u16 {
+        let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+        return socket.local_addr().unwrap().port();
+    }
+
+    fn Mapping(name: &str, protocol: &str, port: u16, target: &str, targetPort: u16) -> String {
+        return format!(
+            r#"{{"name": "{}", "protocol": "{}", "port": {}, "target_ip": "{}", "target_port": {}}}"#,
+            name, protocol, port, target, targetPort
+        );
+    }
+
+    fn Reload(
+        mappings: &mut PortMappings,
+        epoll_fd: RawFd,
+        fds: &mut HashMap<i32, FdType>,
+        entries: &[String],
+    ) {
+        let content = format!(r#"{{"mappings": [{}]}}"#, entries.join(","));
+        fs::write(mappings.configPath.as_ref().unwrap(), content).unwrap();
+        // the mtime may not change within the timer granularity
+        mappings.modified = None;
+        mappings.Poll(epoll_fd, fds);
+    }
+
+    fn Close(mappings: &PortMappings, epoll_fd: RawFd) {
+        for mapping in mappings.mappings.values() {
+            close(mapping.listenFd);
+        }
+        close(epoll_fd);
+    }
+
+    #[test]
+    fn test_PollDiff() {
+        let dir = tempfile::tempdir().unwrap();
+        let epoll_fd = epoll_create().unwrap();
+        let mut fds = HashMap::new();
+        let mut mappings = PortMappings::New();
+        mappings.configPath = Some(dir.path().join("ingress.json"));
+        mappings.statsPath = None;
+
+        // no mapping before the config file is written
+        mappings.Poll(epoll_fd, &mut fds);
+        assert_eq!(mappings.mappings.len(), 0);
+
+        let webPort = FreeTCPPort();
+        let dnsPort = FreeUDPPort();
+        Reload(
+            &mut mappings,
+            epoll_fd,
+            &mut fds,
+            &[
+                Mapping("web", "TCP", webPort, "10.96.0.20", 80),
+                Mapping("dns", "udp", dnsPort, "10.96.0.10", 53),
+            ],
+        );
+        assert_eq!(mappings.mappings.len(), 2);
+        let webFd = mappings
+            .Get(MappingProtocol::TCP, webPort)
+            .unwrap()
+            .listenFd;
+        let dnsFd = mappings
+            .Get(MappingProtocol::UDP, dnsPort)
+            .unwrap()
+            .listenFd;
+        assert!(matches!(fds.get(&webFd), Some(FdType::TCPSocketServer(p)) if *p == webPort));
+        assert!(matches!(fds.get(&dnsFd), Some(FdType::UDPSocketServer(p)) if *p == dnsPort));
+        assert_eq!(
+            mappings
+                .Get(MappingProtocol::UDP, dnsPort)
+                .unwrap()
+                .UDPTarget(),
+            (u32::from(Ipv4Addr::new(10, 96, 0, 10)), 53)
+        );
+
+        // web is retargeted, dns is removed and api is added
+        let apiPort = FreeTCPPort();
+        Reload(
+            &mut mappings,
+            epoll_fd,
+            &mut fds,
+            &[
+                Mapping("web", "TCP", webPort, "10.96.0.21", 8080),
+                Mapping("api", "TCP", apiPort, "10.244.1.5", 9000),
+            ],
+        );
+        assert_eq!(mappings.mappings.len(), 2);
+        let web = mappings.Get(MappingProtocol::TCP, webPort).unwrap();
+        // the listener is kept so that the accepted connections are not dropped
+        assert_eq!(web.listenFd, webFd);
+        assert_eq!(
+            web.TCPTarget(),
+            (
+                u32::from(Ipv4Addr::new(10, 96, 0, 21)).to_be(),
+                8080u16.to_be()
+            )
+        );
+        assert!(mappings.Get(MappingProtocol::UDP, dnsPort).is_none());
+        assert!(!fds.contains_key(&dnsFd));
+        let apiFd = mappings
+            .Get(MappingProtocol::TCP, apiPort)
+            .unwrap()
+            .listenFd;
+        assert!(fds.contains_key(&apiFd));
+        assert_eq!(fds.len(), 2);
+
+        Close(&mappings, epoll_fd);
+    }
+
+    #[test]
+    fn test_PollInvalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let epoll_fd = epoll_create().unwrap();
+        let mut fds = HashMap::new();
+        let mut mappings = PortMappings::New();
+        mappings.configPath = Some(dir.path().join("ingress.json"));
+        mappings.statsPath = None;
+
+        let webPort = FreeTCPPort();
+        Reload(
+            &mut mappings,
+            epoll_fd,
+            &mut fds,
+            &[Mapping("web", "TCP", webPort, "10.96.0.20", 80)],
+        );
+        assert_eq!(mappings.mappings.len(), 1);
+
+        // a half written file keeps the current mappings
+        fs::write(mappings.configPath.as_ref().unwrap(), r#"{"mappings": [{"#).unwrap();
+        mappings.modified = None;
+        mappings.Poll(epoll_fd, &mut fds);
+        assert!(mappings.Get(MappingProtocol::TCP, webPort).is_some());
+
+        // the invalid entries are skipped
+        Reload(
+            &mut mappings,
+            epoll_fd,
+            &mut fds,
+            &[
+                Mapping("web", "TCP", webPort, "10.96.0.20", 80),
+                Mapping("sctp", "SCTP", FreeTCPPort(), "10.96.0.30", 80),
+                Mapping("bad", "TCP", FreeTCPPort(), "10.96.0", 80),
+            ],
+        );
+        assert_eq!(mappings.mappings.len(), 1);
+        assert_eq!(fds.len(), 1);
+
+        Close(&mappings, epoll_fd);
+    }
+
+    #[test]
+    fn test_UDPSessionExpiry() {
+        let mut mappings = PortMappings::New();
+        let client = |ip: [u8; 4], port: u16| libc::sockaddr_in {
+            sin_family: libc::AF_INET as u16,
+            sin_port: port.to_be(),
+            sin_addr: libc::in_addr {
+                s_addr: u32::from(Ipv4Addr::from(ip)).to_be(),
+            },
+            sin_zero: unsafe { mem::zeroed() },
+        };
+
+        let first = mappings
+            .UDPSession(53, &client([10, 0, 0, 1], 5000))
+            .unwrap();
+        let second = mappings
+            .UDPSession(53, &client([10, 0, 0, 2], 5000))
+            .unwrap();
+        assert_ne!(first, second);
+        assert!(first as u32 >= UDP_SESSION_PORT_START);
+        // the same client keeps its session port
+        assert_eq!(
+            mappings.UDPSession(53, &client([10, 0, 0, 1], 5000)),
+            Some(first)
+        );
+
+        // the first session is idle, the second one got a reply
+        let now = MonotonicUs();
+        mappings.udpSessionPorts.get_mut(&first).unwrap().lastActive =
+            now - UDP_SESSION_IDLE_US - 1;
+        mappings.ExpireUDPSessions(now);
+        assert!(!mappings.udpSessionPorts.contains_key(&first));
+        assert!(mappings.udpSessionPorts.contains_key(&second));
+        assert_eq!(mappings.udpSessions.len(), 1);
+        assert!(!mappings.udpPortMgr.set.contains(&(first as u32)));
+
+        mappings.ExpireUDPSessions(now + UDP_SESSION_IDLE_US + 1);
+        assert_eq!(mappings.udpSessionPorts.len(), 0);
+        assert_eq!(mappings.udpSessions.len(), 0);
+        assert_eq!(mappings.udpPortMgr.set.len(), 0);
+    }
+}
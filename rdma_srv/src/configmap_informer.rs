@@ -20,6 +20,8 @@ use svc_client::quark_cm_service_client::QuarkCmServiceClient;
 use svc_client::MaxResourceVersionMessage;
 use svc_client::ConfigMapMessage;
 use std::collections::HashSet;
+use std::env;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use tokio::time::*;
 use tonic::Request;
@@ -82,7 +84,7 @@ impl ConfigMapInformer {
         Ok(())
     }
 
-    fn handle(&mut self, configMap_message: &ConfigMapMessage) {
+    pub fn handle(&mut self, configMap_message: &ConfigMapMessage) {
         let name = &configMap_message.name;
         let mut configMaps_map = RDMA_CTLINFO.configMaps.lock();
         if configMap_message.event_type == EVENT_TYPE_SET {
@@ -93,6 +95,7 @@ impl ConfigMapInformer {
                 index: AtomicUsize::new(0),
             };
             configMaps_map.insert(name.clone(), configMap);
+            WriteIngressConfig(name, &configMap_message.value);
             if configMap_message.resource_version > self.max_resource_version {
                 self.max_resource_version = configMap_message.resource_version;
             }
@@ -100,13 +103,31 @@ impl ConfigMapInformer {
             if configMaps_map.contains_key(name) {
                 if configMaps_map[&name.clone()].resource_version < configMap_message.resource_version {
                     configMaps_map.remove(name);
+                    WriteIngressConfig(name, INGRESS_CONFIG_EMPTY);
                 }
             }
         }
         if configMap_message.resource_version > self.max_resource_version {
             self.max_resource_version = configMap_message.resource_version;
         }
+        RDMA_CTLINFO.resource_version_set("configmap", self.max_resource_version);
         println!("Handled ConfigMap: {:?}", configMap_message);
         println!("Debug: configMaps_map len:{} {:?}", configMaps_map.len(), configMaps_map);
     }
 }
+
+// WriteIngressConfig hands the port mappings of the ingress ConfigMap to the
+// rdma_cli ingress gateway on this node, which polls the config file.
+fn WriteIngressConfig(name: &str, value: &str) {
+    if name != INGRESS_CONFIGMAP_NAME {
+        return;
+    }
+    let path = match env::var(INGRESS_CONFIG_ENV) {
+        Ok(path) if path.len() > 0 => PathBuf::from(path),
+        _ => return,
+    };
+
+    if let Err(e) = WriteFileAtomic(&path, value) {
+        error!("fail to write ingress config {:?}: {:?}", path, e);
+    }
+}
@@ -16,3 +16,69 @@ pub const EVENT_TYPE_SET: &str = "set";
 pub const EVENT_TYPE_DELETE: &str = "delete";
 
 pub const GRPC_SERVER_ADDRESS: &str = "http://[::1]:51051";
+
+// RDMA_SRV_TRANSPORT=soft runs the RDMA service on the software RDMA device
+pub const RDMA_TRANSPORT_ENV: &str = "RDMA_SRV_TRANSPORT";
+pub const RDMA_TRANSPORT_SOFT: &str = "soft";
+pub const SOFT_RDMA_ADDR_ENV: &str = "RDMA_SRV_SOFT_ADDR";
+pub const SOFT_RDMA_PORT_ENV: &str = "RDMA_SRV_SOFT_PORT";
+// the file with the secret shared by the software RDMA devices of the cluster
+pub const SOFT_RDMA_SECRET_FILE_ENV: &str = "RDMA_SRV_SOFT_SECRET_FILE";
+
+// service annotations selecting how TSoR balances the service connections
+pub const LB_POLICY_ANNOTATION: &str = "quarksoft.io/tsor-lb-policy";
+pub const LB_TOPOLOGY_ANNOTATION: &str = "quarksoft.io/tsor-topology-aware";
+pub const LB_POLICY_ROUND_ROBIN: &str = "round-robin";
+pub const LB_POLICY_LEAST_CONNECTIONS: &str = "least-connections";
+pub const LB_POLICY_CLIENT_IP_HASH: &str = "client-ip-hash";
+pub const SESSION_AFFINITY_CLIENT_IP: &str = "ClientIP";
+
+// keepalive of the RDMA connections between nodes, in milliseconds
+pub const HEARTBEAT_INTERVAL_MS: u64 = 1000;
+// the peer node is dead when no control message arrived for that many intervals
+pub const HEARTBEAT_MISS_LIMIT: u64 = 5;
+pub const RECONNECT_BACKOFF_MIN_MS: u64 = 500;
+pub const RECONNECT_BACKOFF_MAX_MS: u64 = 30000;
+
+// RDMA_SRV_CONTROL_DIR=<dir> reads the cluster state from the json files in dir
+// instead of the quarkcm service
+pub const CONTROL_DIR_ENV: &str = "RDMA_SRV_CONTROL_DIR";
+
+// the value of the ConfigMap is the json port mapping config of the rdma_cli
+// ingress gateway, it is written to the file RDMA_INGRESS_CONFIG names
+pub const INGRESS_CONFIGMAP_NAME: &str = "tsor-ingress-mappings";
+pub const INGRESS_CONFIG_ENV: &str = "RDMA_INGRESS_CONFIG";
+pub const INGRESS_CONFIG_EMPTY: &str = "{\"mappings\": []}";
+
+// the policyTypes of a kubernetes NetworkPolicy
+pub const NETWORK_POLICY_TYPE_INGRESS: &str = "Ingress";
+pub const NETWORK_POLICY_TYPE_EGRESS: &str = "Egress";
+// the label kubernetes sets on every namespace with the namespace name
+pub const NAMESPACE_NAME_LABEL: &str = "kubernetes.io/metadata.name";
+pub const PROTOCOL_TCP: &str = "TCP";
+
+// pod annotations of the TSoR QoS: the priority is "high", "normal", "low" or a
+// weight, the bandwidth caps the pod writes in bits per second, e.g. "100M"
+pub const QOS_PRIORITY_ANNOTATION: &str = "quarksoft.io/tsor-priority";
+pub const QOS_BANDWIDTH_ANNOTATION: &str = "quarksoft.io/tsor-bandwidth";
+pub const QOS_WEIGHT_HIGH: u32 = 4;
+pub const QOS_WEIGHT_NORMAL: u32 = 2;
+pub const QOS_WEIGHT_LOW: u32 = 1;
+pub const QOS_WEIGHT_MAX: u32 = 64;
+// refill interval of the bandwidth token buckets, in milliseconds
+pub const QOS_TIMER_INTERVAL_MS: u64 = 10;
+// the bandwidth burst a capped pod may send at once, in milliseconds
+pub const QOS_BURST_MS: u64 = 100;
+// RDMA_SRV_QOS_STATS=<file> writes the per-pod QoS statistics to file
+pub const QOS_STATS_ENV: &str = "RDMA_SRV_QOS_STATS";
+
+// RDMA_SRV_STATS_ADDR=<ip:port> is the address of the http stats server which
+// serves /metrics in the prometheus text format and /state in json, empty
+// disables the server
+pub const STATS_ADDR_ENV: &str = "RDMA_SRV_STATS_ADDR";
+pub const STATS_ADDR_DEFAULT: &str = "127.0.0.1:8890";
+pub const STATS_METRICS_PATH: &str = "/metrics";
+pub const STATS_STATE_PATH: &str = "/state";
+pub const STATS_TIMEOUT_SECS: u64 = 5;
+// the requests served at once, each in its own thread
+pub const STATS_MAX_CONNS: usize = 8;
@@ -20,6 +20,8 @@ use svc_client::quark_cm_service_client::QuarkCmServiceClient;
 use svc_client::MaxResourceVersionMessage;
 use svc_client::EndpointsMessage;
 use std::collections::HashSet;
+use std::net::Ipv6Addr;
+use std::str::FromStr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use tokio::time::*;
 use tonic::Request;
@@ -82,18 +84,42 @@ impl EndpointsInformer {
         Ok(())
     }
 
-    fn handle(&mut self, endpoints_message: &EndpointsMessage) {
+    pub fn handle(&mut self, endpoints_message: &EndpointsMessage) {
         let name = &endpoints_message.name;
         let mut endpointses_map = RDMA_CTLINFO.endpointses.lock();
         if endpoints_message.event_type == EVENT_TYPE_SET {
             let mut ip_with_ports = HashSet::new();
             for ipWithPortStr in &endpoints_message.ip_with_ports {
-                let splitted = ipWithPortStr.split(":").collect::<Vec<_>>();
+                // ip:protocol:port, an ipv6 ip has colons itself so split from the end
+                let splitted = ipWithPortStr.rsplitn(3, ":").collect::<Vec<_>>();
+                if splitted.len() != 3 {
+                    error!("endpoints {}: invalid endpoint {}", name, ipWithPortStr);
+                    continue;
+                }
+                let port = match splitted[0].parse::<u16>() {
+                    Ok(port) => port,
+                    Err(_) => {
+                        error!("endpoints {}: invalid port in {}", name, ipWithPortStr);
+                        continue;
+                    }
+                };
+                let ipStr = splitted[2].trim_start_matches('[').trim_end_matches(']');
+                let (ip, ipv6) = match ipStr.parse::<u32>() {
+                    Ok(ip) => (ip.to_be(), None),
+                    Err(_) => match Ipv6Addr::from_str(ipStr) {
+                        Ok(ipv6) => (0, Some(ipv6)),
+                        Err(_) => {
+                            error!("endpoints {}: invalid ip in {}", name, ipWithPortStr);
+                            continue;
+                        }
+                    },
+                };
                 ip_with_ports.insert(IpWithPort {
-                    ip: splitted[0].to_string().parse::<u32>().unwrap().to_be(),
+                    ip: ip,
+                    ipv6: ipv6,
                     port: Port {
                         protocal: splitted[1].to_string(),
-                        port: splitted[2].to_string().parse::<u16>().unwrap().to_be(),
+                        port: port.to_be(),
                     }
                 });
             }
@@ -118,6 +144,7 @@ impl EndpointsInformer {
         if endpoints_message.resource_version > self.max_resource_version {
             self.max_resource_version = endpoints_message.resource_version;
         }
+        RDMA_CTLINFO.resource_version_set("endpoints", self.max_resource_version);
         println!("Handled Endpoints: {:?}", endpoints_message);
         println!("Debug: endpointses_map len:{} {:?}", endpointses_map.len(), endpointses_map);
     }
@@ -0,0 +1,669 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::constants::*;
+use crate::configmap_informer::svc_client::ConfigMapMessage;
+use crate::configmap_informer::ConfigMapInformer;
+use crate::endpoints_informer::svc_client::EndpointsMessage;
+use crate::endpoints_informer::EndpointsInformer;
+use crate::network_policy_informer::svc_client::NetworkPolicyMessage;
+use crate::network_policy_informer::NetworkPolicyInformer;
+use crate::node_informer::svc_client::NodeMessage;
+use crate::node_informer::NodeInformer;
+use crate::pod_informer::svc_client::PodMessage;
+use crate::pod_informer::PodInformer;
+use crate::service_informer::svc_client::ServiceMessage;
+use crate::service_informer::ServiceInformer;
+use crate::RDMA_CTLINFO;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// FileControlDir returns the directory of the file based control plane, set by
+// RDMA_SRV_CONTROL_DIR. rdma_srv watches the quarkcm service when it is unset.
+pub fn FileControlDir() -> Option<PathBuf> {
+    match env::var(CONTROL_DIR_ENV) {
+        Ok(dir) if dir.len() > 0 => Some(PathBuf::from(dir)),
+        _ => None,
+    }
+}
+
+// The ip addresses in the files are dotted strings, they are converted to the
+// encoding used by the quarkcm messages.
+fn ParseIp(ip: &str) -> Result<Ipv4Addr, String> {
+    Ipv4Addr::from_str(ip).map_err(|e| format!("invalid ip address {}: {:?}", ip, e))
+}
+
+// NetworkOrder is the encoding of the node and pod addresses
+fn NetworkOrder(ip: &str) -> Result<u32, String> {
+    Ok(u32::from(ParseIp(ip)?).to_be())
+}
+
+// HostOrder is the encoding of the service and endpoints addresses
+fn HostOrder(ip: &str) -> Result<u32, String> {
+    Ok(u32::from(ParseIp(ip)?))
+}
+
+// Ipv6 validates an optional ipv6 address, the quarkcm messages carry it as a
+// string
+fn Ipv6(ip: &str) -> Result<String, String> {
+    if ip.is_empty() {
+        return Ok(String::new());
+    }
+    Ipv6Addr::from_str(ip)
+        .map(|ip| ip.to_string())
+        .map_err(|e| format!("invalid ipv6 address {}: {:?}", ip, e))
+}
+
+// DualStack encodes the ipv4 and ipv6 addresses of a pod or a service, an
+// ipv6 only one has no ipv4 address
+fn DualStack(
+    ip: &str,
+    ipv6: &str,
+    encode: fn(&str) -> Result<u32, String>,
+) -> Result<(u32, String), String> {
+    let ipv6 = Ipv6(ipv6)?;
+    if ip.is_empty() && !ipv6.is_empty() {
+        return Ok((0, ipv6));
+    }
+    Ok((encode(ip)?, ipv6))
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct NodeEntry {
+    pub name: String,
+    pub hostname: String,
+    pub ip: String,
+    #[serde(default)]
+    pub creation_timestamp: u64,
+    pub subnet: String,
+    pub netmask: String,
+    // cidr of the node ipv6 pod subnet, e.g. "fd00:10:1::/64"
+    #[serde(default)]
+    pub subnet_v6: String,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct PodEntry {
+    pub key: String,
+    #[serde(default)]
+    pub ip: String,
+    #[serde(default)]
+    pub ipv6: String,
+    pub node_name: String,
+    pub container_id: String,
+    #[serde(default)]
+    pub namespace: String,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    #[serde(default)]
+    pub namespace_labels: HashMap<String, String>,
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ServiceEntry {
+    pub name: String,
+    #[serde(default)]
+    pub cluster_ip: String,
+    #[serde(default)]
+    pub cluster_ipv6: String,
+    // "protocol:port", e.g. "TCP:80"
+    pub ports: Vec<String>,
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+    #[serde(default)]
+    pub session_affinity: String,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct EndpointsEntry {
+    pub name: String,
+    // "ip:protocol:port", e.g. "10.1.1.5:TCP:8080" or "[fd00::5]:TCP:8080"
+    pub ip_with_ports: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ConfigMapEntry {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct NetworkPolicyEntry {
+    pub name: String,
+    pub namespace: String,
+    // the kubernetes NetworkPolicySpec
+    pub spec: serde_json::Value,
+}
+
+pub trait FileEntry: Clone + PartialEq {
+    fn Key(&self) -> String;
+}
+
+impl FileEntry for NodeEntry {
+    fn Key(&self) -> String {
+        self.name.clone()
+    }
+}
+
+impl FileEntry for PodEntry {
+    fn Key(&self) -> String {
+        self.key.clone()
+    }
+}
+
+impl FileEntry for ServiceEntry {
+    fn Key(&self) -> String {
+        self.name.clone()
+    }
+}
+
+impl FileEntry for EndpointsEntry {
+    fn Key(&self) -> String {
+        self.name.clone()
+    }
+}
+
+impl FileEntry for ConfigMapEntry {
+    fn Key(&self) -> String {
+        self.name.clone()
+    }
+}
+
+impl FileEntry for NetworkPolicyEntry {
+    fn Key(&self) -> String {
+        format!("{}/{}", self.namespace, self.name)
+    }
+}
+
+pub enum FileEvent<T> {
+    Set(T),
+    Delete(T),
+}
+
+// the extensions of the control files, in the order they are looked up
+const CONTROL_FILE_EXTENSIONS: [&str; 3] = ["json", "yaml", "yml"];
+
+// FileSource is one json or yaml file holding an array of objects of a kind,
+// e.g. nodes.json or nodes.yaml. The json file is used when both exist.
+pub struct FileSource<T: FileEntry> {
+    pub dir: PathBuf,
+    pub name: String,
+    // whether the file changed since the last load
+    pub dirty: bool,
+    // the objects last loaded: key --> object
+    pub objects: HashMap<String, T>,
+}
+
+impl<T: FileEntry + Deserialize> FileSource<T> {
+    pub fn New(dir: &Path, name: &str) -> Self {
+        FileSource {
+            dir: dir.to_path_buf(),
+            name: name.to_string(),
+            dirty: true,
+            objects: HashMap::new(),
+        }
+    }
+
+    // Watches returns whether a file of the directory is a file of the source
+    pub fn Watches(&self, file: &OsStr) -> bool {
+        let path = Path::new(file);
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        return path.file_stem() == Some(OsStr::new(&self.name))
+            && CONTROL_FILE_EXTENSIONS.contains(&ext);
+    }
+
+    // Mark flags the source to be reloaded when the file changed
+    pub fn Mark(&mut self, file: &OsStr) {
+        if self.Watches(file) {
+            self.dirty = true;
+        }
+    }
+
+    // Path returns the file of the source, None when there is none
+    pub fn Path(&self) -> Option<PathBuf> {
+        for ext in &CONTROL_FILE_EXTENSIONS {
+            let path = self.dir.join(format!("{}.{}", self.name, ext));
+            if path.exists() {
+                return Some(path);
+            }
+        }
+
+        return None;
+    }
+
+    // Poll reloads the file if it changed, and returns the objects set and
+    // deleted since the last load. A missing file has no objects.
+    pub fn Poll(&mut self) -> Vec<FileEvent<T>> {
+        if !self.dirty {
+            return Vec::new();
+        }
+        self.dirty = false;
+
+        let objects: Vec<T> = match self.Path() {
+            None => Vec::new(),
+            Some(path) => match Self::Load(&path) {
+                Ok(objects) => objects,
+                Err(e) => {
+                    // keep the last objects until the file is fixed
+                    error!("fail to load {:?}: {}", path, e);
+                    return Vec::new();
+                }
+            },
+        };
+
+        let mut events = Vec::new();
+        let mut current = HashMap::new();
+        for object in objects {
+            let key = object.Key();
+            if self.objects.get(&key) != Some(&object) {
+                events.push(FileEvent::Set(object.clone()));
+            }
+            current.insert(key, object);
+        }
+
+        for (key, object) in self.objects.drain() {
+            if !current.contains_key(&key) {
+                events.push(FileEvent::Delete(object));
+            }
+        }
+
+        self.objects = current;
+        return events;
+    }
+
+    fn Load(path: &Path) -> Result<Vec<T>, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("{:?}", e))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&content).map_err(|e| format!("{:?}", e)),
+            _ => serde_yaml::from_str(&content).map_err(|e| format!("{:?}", e)),
+        }
+    }
+}
+
+// FileInformer is the control plane source which watches a local directory
+// instead of the quarkcm service, for single node, multi-process and static
+// bare-metal deployments. The directory holds the json or yaml files:
+//   nodes, pods, services, endpoints, configmaps, networkpolicies
+// e.g. nodes.json or nodes.yaml, each is an array of objects. The directory
+// is watched with inotify, the objects added or changed in a file are set,
+// the removed ones are deleted, with the same resource version semantics as
+// the quarkcm watch. A file is written in place or renamed into the
+// directory, it is reloaded when it is closed or moved in.
+pub struct FileInformer {
+    pub dir: PathBuf,
+    pub resource_version: i32,
+    pub nodes: FileSource<NodeEntry>,
+    pub pods: FileSource<PodEntry>,
+    pub services: FileSource<ServiceEntry>,
+    pub endpointses: FileSource<EndpointsEntry>,
+    pub configMaps: FileSource<ConfigMapEntry>,
+    pub networkPolicies: FileSource<NetworkPolicyEntry>,
+    pub node_informer: NodeInformer,
+    pub pod_informer: PodInformer,
+    pub service_informer: ServiceInformer,
+    pub endpoints_informer: EndpointsInformer,
+    pub configmap_informer: ConfigMapInformer,
+    pub network_policy_informer: NetworkPolicyInformer,
+}
+
+impl FileInformer {
+    pub fn new(dir: &Path) -> FileInformer {
+        FileInformer {
+            dir: dir.to_path_buf(),
+            resource_version: 0,
+            nodes: FileSource::New(dir, "nodes"),
+            pods: FileSource::New(dir, "pods"),
+            services: FileSource::New(dir, "services"),
+            endpointses: FileSource::New(dir, "endpoints"),
+            configMaps: FileSource::New(dir, "configmaps"),
+            networkPolicies: FileSource::New(dir, "networkpolicies"),
+            node_informer: NodeInformer::new(),
+            pod_informer: PodInformer::new(),
+            service_informer: ServiceInformer::new(),
+            endpoints_informer: EndpointsInformer::new(),
+            configmap_informer: ConfigMapInformer::new(),
+            network_policy_informer: NetworkPolicyInformer::new(),
+        }
+    }
+}
+
+impl FileInformer {
+    pub async fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        // the watch is set before the first load so that no change is missed
+        let inotify = Inotify::init(InitFlags::IN_CLOEXEC)?;
+        inotify.add_watch(
+            &self.dir,
+            AddWatchFlags::IN_CLOSE_WRITE
+                | AddWatchFlags::IN_MOVED_TO
+                | AddWatchFlags::IN_MOVED_FROM
+                | AddWatchFlags::IN_DELETE,
+        )?;
+
+        RDMA_CTLINFO.isCMConnected_set(true);
+        loop {
+            self.poll();
+            let events = tokio::task::spawn_blocking(move || inotify.read_events()).await??;
+            for event in events {
+                match event.name {
+                    Some(name) => self.mark(&name),
+                    // the queue overflowed, reload all the files
+                    None => self.mark_all(),
+                }
+            }
+        }
+    }
+
+    pub fn mark(&mut self, file: &OsStr) {
+        self.nodes.Mark(file);
+        self.pods.Mark(file);
+        self.services.Mark(file);
+        self.endpointses.Mark(file);
+        self.configMaps.Mark(file);
+        self.networkPolicies.Mark(file);
+    }
+
+    pub fn mark_all(&mut self) {
+        self.nodes.dirty = true;
+        self.pods.dirty = true;
+        self.services.dirty = true;
+        self.endpointses.dirty = true;
+        self.configMaps.dirty = true;
+        self.networkPolicies.dirty = true;
+    }
+
+    // poll applies the changes of all the files, the config maps and nodes
+    // first as the pods and services depend on them.
+    pub fn poll(&mut self) {
+        for event in self.configMaps.Poll() {
+            let (entry, event_type) = Self::split(event);
+            let message = ConfigMapMessage {
+                name: entry.name,
+                value: entry.value,
+                resource_version: self.next_resource_version(),
+                event_type: event_type.to_string(),
+            };
+            self.configmap_informer.handle(&message);
+        }
+
+        for event in self.nodes.Poll() {
+            let (entry, event_type) = Self::split(event);
+            match Self::node_message(&entry) {
+                Ok(mut message) => {
+                    message.resource_version = self.next_resource_version();
+                    message.event_type = event_type.to_string();
+                    self.node_informer.handle(&message);
+                }
+                Err(e) => error!("invalid node {}: {}", entry.name, e),
+            }
+        }
+
+        for event in self.pods.Poll() {
+            let (entry, event_type) = Self::split(event);
+            match DualStack(&entry.ip, &entry.ipv6, NetworkOrder) {
+                Ok((ip, ipv6)) => {
+                    let message = PodMessage {
+                        key: entry.key,
+                        ip: ip,
+                        ipv6: ipv6,
+                        node_name: entry.node_name,
+                        container_id: entry.container_id,
+                        namespace: entry.namespace,
+                        labels: entry.labels,
+                        namespace_labels: entry.namespace_labels,
+                        annotations: entry.annotations,
+                        resource_version: self.next_resource_version(),
+                        event_type: event_type.to_string(),
+                    };
+                    self.pod_informer.handle(&message);
+                }
+                Err(e) => error!("invalid pod {}: {}", entry.key, e),
+            }
+        }
+
+        for event in self.services.Poll() {
+            let (entry, event_type) = Self::split(event);
+            match DualStack(&entry.cluster_ip, &entry.cluster_ipv6, HostOrder) {
+                Ok((ip, ipv6)) => {
+                    let message = ServiceMessage {
+                        name: entry.name,
+                        cluster_ip: ip,
+                        cluster_ipv6: ipv6,
+                        ports: entry.ports,
+                        resource_version: self.next_resource_version(),
+                        event_type: event_type.to_string(),
+                        annotations: entry.annotations,
+                        session_affinity: entry.session_affinity,
+                    };
+                    self.service_informer.handle(&message);
+                }
+                Err(e) => error!("invalid service {}: {}", entry.name, e),
+            }
+        }
+
+        for event in self.endpointses.Poll() {
+            let (entry, event_type) = Self::split(event);
+            match Self::ip_with_ports(&entry.ip_with_ports) {
+                Ok(ip_with_ports) => {
+                    let message = EndpointsMessage {
+                        name: entry.name,
+                        ip_with_ports: ip_with_ports,
+                        resource_version: self.next_resource_version(),
+                        event_type: event_type.to_string(),
+                    };
+                    self.endpoints_informer.handle(&message);
+                }
+                Err(e) => error!("invalid endpoints {}: {}", entry.name, e),
+            }
+        }
+
+        for event in self.networkPolicies.Poll() {
+            let (entry, event_type) = Self::split(event);
+            match serde_json::to_string(&entry.spec) {
+                Ok(spec) => {
+                    let message = NetworkPolicyMessage {
+                        name: entry.name,
+                        namespace: entry.namespace,
+                        spec: spec,
+                        resource_version: self.next_resource_version(),
+                        event_type: event_type.to_string(),
+                    };
+                    self.network_policy_informer.handle(&message);
+                }
+                Err(e) => error!("invalid network policy {}: {:?}", entry.name, e),
+            }
+        }
+    }
+
+    fn next_resource_version(&mut self) -> i32 {
+        self.resource_version += 1;
+        self.resource_version
+    }
+
+    fn split<T>(event: FileEvent<T>) -> (T, &'static str) {
+        match event {
+            FileEvent::Set(entry) => (entry, EVENT_TYPE_SET),
+            FileEvent::Delete(entry) => (entry, EVENT_TYPE_DELETE),
+        }
+    }
+
+    fn node_message(entry: &NodeEntry) -> Result<NodeMessage, String> {
+        Ok(NodeMessage {
+            name: entry.name.clone(),
+            hostname: entry.hostname.clone(),
+            ip: NetworkOrder(&entry.ip)?,
+            creation_timestamp: entry.creation_timestamp,
+            resource_version: 0,
+            subnet: NetworkOrder(&entry.subnet)?,
+            net_mask: NetworkOrder(&entry.netmask)?,
+            subnet_v6: entry.subnet_v6.clone(),
+            event_type: String::new(),
+        })
+    }
+
+    // ip_with_ports converts "ip:protocol:port" to the quarkcm encoding, an
+    // ipv6 ip is kept as a string
+    fn ip_with_ports(ipWithPorts: &Vec<String>) -> Result<Vec<String>, String> {
+        let mut converted = Vec::new();
+        for ipWithPort in ipWithPorts {
+            let splitted = ipWithPort.rsplitn(3, ":").collect::<Vec<_>>();
+            if splitted.len() != 3 || splitted[0].parse::<u16>().is_err() {
+                return Err(format!("invalid endpoint {}", ipWithPort));
+            }
+            let ip = splitted[2].trim_start_matches('[').trim_end_matches(']');
+            let ip = if ip.contains(":") {
+                Ipv6(ip)?
+            } else {
+                HostOrder(ip)?.to_string()
+            };
+            converted.push(format!("{}:{}:{}", ip, splitted[1], splitted[0]));
+        }
+        Ok(converted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Keys returns the sorted keys of the objects set and deleted
+    fn Keys<T: FileEntry>(events: Vec<FileEvent<T>>) -> (Vec<String>, Vec<String>) {
+        let mut sets = Vec::new();
+        let mut deletes = Vec::new();
+        for event in events {
+            match event {
+                FileEvent::Set(entry) => sets.push(entry.Key()),
+                FileEvent::Delete(entry) => deletes.push(entry.Key()),
+            }
+        }
+        sets.sort();
+        deletes.sort();
+        return (sets, deletes);
+    }
+
+    fn Strings(keys: &[&str]) -> Vec<String> {
+        return keys.iter().map(|k| k.to_string()).collect();
+    }
+
+    fn Write(dir: &Path, file: &str, content: &str) {
+        fs::write(dir.join(file), content).unwrap();
+    }
+
+    #[test]
+    fn test_PollDiff() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut source: FileSource<ConfigMapEntry> = FileSource::New(dir.path(), "configmaps");
+
+        // a missing file has no objects
+        assert_eq!(Keys(source.Poll()), (vec![], vec![]));
+
+        Write(
+            dir.path(),
+            "configmaps.json",
+            r#"[{"name": "a", "value": "1"}, {"name": "b", "value": "2"}]"#,
+        );
+        source.Mark(OsStr::new("configmaps.json"));
+        assert_eq!(Keys(source.Poll()), (Strings(&["a", "b"]), vec![]));
+
+        // not reloaded until the file changes again
+        Write(dir.path(), "configmaps.json", "[]");
+        assert_eq!(Keys(source.Poll()), (vec![], vec![]));
+
+        // b changed, a removed, c added
+        Write(
+            dir.path(),
+            "configmaps.json",
+            r#"[{"name": "b", "value": "3"}, {"name": "c", "value": "4"}]"#,
+        );
+        source.Mark(OsStr::new("configmaps.json"));
+        assert_eq!(Keys(source.Poll()), (Strings(&["b", "c"]), Strings(&["a"])));
+        assert_eq!(source.objects["b"].value, "3");
+
+        // an unchanged reload has no events
+        source.Mark(OsStr::new("configmaps.json"));
+        assert_eq!(Keys(source.Poll()), (vec![], vec![]));
+
+        fs::remove_file(dir.path().join("configmaps.json")).unwrap();
+        source.Mark(OsStr::new("configmaps.json"));
+        assert_eq!(Keys(source.Poll()), (vec![], Strings(&["b", "c"])));
+        assert!(source.objects.is_empty());
+    }
+
+    #[test]
+    fn test_PollInvalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut source: FileSource<ConfigMapEntry> = FileSource::New(dir.path(), "configmaps");
+        Write(
+            dir.path(),
+            "configmaps.json",
+            r#"[{"name": "a", "value": "1"}]"#,
+        );
+        assert_eq!(Keys(source.Poll()), (Strings(&["a"]), vec![]));
+
+        // the objects are kept while the file is invalid
+        Write(dir.path(), "configmaps.json", r#"[{"name": "a", "#);
+        source.Mark(OsStr::new("configmaps.json"));
+        assert_eq!(Keys(source.Poll()), (vec![], vec![]));
+        assert_eq!(source.objects.len(), 1);
+
+        Write(dir.path(), "configmaps.json", "[]");
+        source.Mark(OsStr::new("configmaps.json"));
+        assert_eq!(Keys(source.Poll()), (vec![], Strings(&["a"])));
+    }
+
+    #[test]
+    fn test_PollYaml() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut source: FileSource<NodeEntry> = FileSource::New(dir.path(), "nodes");
+        Write(
+            dir.path(),
+            "nodes.yaml",
+            "- name: node1\n  hostname: host1\n  ip: 10.0.0.1\n  subnet: 10.1.1.0\n  netmask: 255.255.255.0\n",
+        );
+        assert_eq!(Keys(source.Poll()), (Strings(&["node1"]), vec![]));
+        assert_eq!(source.objects["node1"].ip, "10.0.0.1");
+        assert_eq!(source.objects["node1"].subnet_v6, "");
+
+        // the json file is preferred over the yaml one
+        Write(
+            dir.path(),
+            "nodes.json",
+            r#"[{"name": "node2", "hostname": "host2", "ip": "10.0.0.2", "subnet": "10.1.2.0", "netmask": "255.255.255.0"}]"#,
+        );
+        source.Mark(OsStr::new("nodes.json"));
+        assert_eq!(
+            Keys(source.Poll()),
+            (Strings(&["node2"]), Strings(&["node1"]))
+        );
+    }
+
+    #[test]
+    fn test_Watches() {
+        let source: FileSource<NodeEntry> = FileSource::New(Path::new("/tmp"), "nodes");
+        assert!(source.Watches(OsStr::new("nodes.json")));
+        assert!(source.Watches(OsStr::new("nodes.yaml")));
+        assert!(source.Watches(OsStr::new("nodes.yml")));
+        assert!(!source.Watches(OsStr::new("nodes.json.tmp")));
+        assert!(!source.Watches(OsStr::new("pods.json")));
+        assert!(!source.Watches(OsStr::new("nodes")));
+    }
+}
@@ -0,0 +1,379 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use super::constants::*;
+use super::rdma_ctrlconn::*;
+use super::rdma_srv::RDMA_SRV;
+
+// BalanceContext is what a policy knows about the connection being balanced
+pub struct BalanceContext {
+    // pod ip of the connecting client
+    pub clientIp: IpAddr,
+    // round-robin counter of the service endpoints
+    pub index: usize,
+}
+
+// LoadBalancer picks the endpoint of a service connection. The candidates are
+// sorted, so that a policy sees the same order on every node.
+pub trait LoadBalancer {
+    fn Pick(&self, candidates: &[IpWithPort], ctx: &BalanceContext) -> Option<IpWithPort>;
+}
+
+pub struct RoundRobin {}
+
+impl LoadBalancer for RoundRobin {
+    fn Pick(&self, candidates: &[IpWithPort], ctx: &BalanceContext) -> Option<IpWithPort> {
+        if candidates.len() == 0 {
+            return None;
+        }
+
+        return Some(candidates[ctx.index % candidates.len()].clone());
+    }
+}
+
+// LeastConnections picks the endpoint with the fewest RDMA channels from this
+// node, the ties rotate as round-robin. Only the channels of this node count,
+// the channels the other nodes opened to an endpoint are not known here, so
+// the load is balanced per node and not across the cluster.
+pub struct LeastConnections {}
+
+impl LeastConnections {
+    pub fn PickWithCounts(
+        candidates: &[IpWithPort],
+        ctx: &BalanceContext,
+        counts: &HashMap<(IpAddr, u16), usize>,
+    ) -> Option<IpWithPort> {
+        if candidates.len() == 0 {
+            return None;
+        }
+
+        let mut best: Option<(usize, &IpWithPort)> = None;
+        for i in 0..candidates.len() {
+            let candidate = &candidates[(ctx.index + i) % candidates.len()];
+            let count = *counts
+                .get(&(candidate.Addr(), candidate.port.port))
+                .unwrap_or(&0);
+            match best {
+                Some((min, _)) if min <= count => (),
+                _ => best = Some((count, candidate)),
+            }
+        }
+
+        return best.map(|(_, candidate)| candidate.clone());
+    }
+}
+
+impl LoadBalancer for LeastConnections {
+    fn Pick(&self, candidates: &[IpWithPort], ctx: &BalanceContext) -> Option<IpWithPort> {
+        return Self::PickWithCounts(candidates, ctx, &RDMA_SRV.ChannelCounts());
+    }
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+// Fnv1a hashes the bytes with the 64 bit FNV-1a, which is the same on every
+// node and every build, and mixes the result with the murmur3 finalizer so
+// that the inputs differing in the last bytes also differ in the high bits.
+pub fn Fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    for part in parts {
+        for b in *part {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^= hash >> 33;
+    return hash;
+}
+
+fn IpBytes(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(ip) => return ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ip) => return ip.octets(),
+    }
+}
+
+// ClientIPHash keeps the connections of a client on one endpoint. It is the
+// rendezvous hashing of the client ip, so only the clients of a removed
+// endpoint move when the endpoints change. The hash doesn't depend on the
+// node, so the clients stick to an endpoint across the nodes.
+pub struct ClientIPHash {}
+
+impl ClientIPHash {
+    fn Weight(clientIp: IpAddr, candidate: &IpWithPort) -> u64 {
+        return Fnv1a(&[
+            &IpBytes(clientIp),
+            &IpBytes(candidate.Addr()),
+            &candidate.port.port.to_be_bytes(),
+            candidate.port.protocal.as_bytes(),
+        ]);
+    }
+}
+
+impl LoadBalancer for ClientIPHash {
+    fn Pick(&self, candidates: &[IpWithPort], ctx: &BalanceContext) -> Option<IpWithPort> {
+        return candidates
+            .iter()
+            .max_by_key(|candidate| Self::Weight(ctx.clientIp, candidate))
+            .cloned();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LBPolicy {
+    RoundRobin,
+    LeastConnections,
+    ClientIPHash,
+}
+
+impl Default for LBPolicy {
+    fn default() -> Self {
+        return LBPolicy::RoundRobin;
+    }
+}
+
+impl LBPolicy {
+    // FromService returns the policy set by the service annotation, a service
+    // with "sessionAffinity: ClientIP" hashes the client ip by default.
+    pub fn FromService(annotations: &HashMap<String, String>, sessionAffinity: &str) -> Self {
+        match annotations.get(LB_POLICY_ANNOTATION).map(|p| p.as_str()) {
+            Some(LB_POLICY_ROUND_ROBIN) => return LBPolicy::RoundRobin,
+            Some(LB_POLICY_LEAST_CONNECTIONS) => return LBPolicy::LeastConnections,
+            Some(LB_POLICY_CLIENT_IP_HASH) => return LBPolicy::ClientIPHash,
+            Some(policy) => {
+                error!("unknown load balancing policy {}, use round-robin", policy);
+            }
+            None => (),
+        }
+
+        if sessionAffinity == SESSION_AFFINITY_CLIENT_IP {
+            return LBPolicy::ClientIPHash;
+        }
+
+        return LBPolicy::RoundRobin;
+    }
+
+    pub fn Balancer(&self) -> &'static dyn LoadBalancer {
+        match self {
+            LBPolicy::RoundRobin => return &RoundRobin {},
+            LBPolicy::LeastConnections => return &LeastConnections {},
+            LBPolicy::ClientIPHash => return &ClientIPHash {},
+        }
+    }
+}
+
+// TopologyAware returns whether the service prefers the endpoints on this node
+pub fn TopologyAware(annotations: &HashMap<String, String>) -> bool {
+    match annotations.get(LB_TOPOLOGY_ANNOTATION) {
+        Some(v) => return v == "true",
+        None => return false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn Endpoint(ip: &str, port: u16) -> IpWithPort {
+        let port = Port {
+            protocal: "TCP".to_string(),
+            port: port,
+        };
+        match ip.parse::<IpAddr>().unwrap() {
+            IpAddr::V4(ip) => {
+                return IpWithPort {
+                    ip: u32::from(ip).to_be(),
+                    ipv6: None,
+                    port: port,
+                }
+            }
+            IpAddr::V6(ip) => {
+                return IpWithPort {
+                    ip: 0,
+                    ipv6: Some(ip),
+                    port: port,
+                }
+            }
+        }
+    }
+
+    fn Context(clientIp: &str, index: usize) -> BalanceContext {
+        return BalanceContext {
+            clientIp: clientIp.parse().unwrap(),
+            index: index,
+        };
+    }
+
+    fn Endpoints() -> Vec<IpWithPort> {
+        return vec![
+            Endpoint("10.1.0.1", 80),
+            Endpoint("10.1.0.2", 80),
+            Endpoint("10.1.0.3", 80),
+        ];
+    }
+
+    #[test]
+    fn test_RoundRobin() {
+        let eps = Endpoints();
+        let rr = RoundRobin {};
+        assert_eq!(rr.Pick(&[], &Context("10.0.0.1", 0)), None);
+        for i in 0..6 {
+            assert_eq!(
+                rr.Pick(&eps, &Context("10.0.0.1", i)),
+                Some(eps[i % 3].clone())
+            );
+        }
+    }
+
+    #[test]
+    fn test_LeastConnections() {
+        let eps = Endpoints();
+        let ctx = Context("10.0.0.1", 0);
+        let mut counts = HashMap::new();
+        assert_eq!(LeastConnections::PickWithCounts(&[], &ctx, &counts), None);
+
+        counts.insert((eps[0].Addr(), 80), 2);
+        counts.insert((eps[1].Addr(), 80), 1);
+        counts.insert((eps[2].Addr(), 80), 3);
+        assert_eq!(
+            LeastConnections::PickWithCounts(&eps, &ctx, &counts),
+            Some(eps[1].clone())
+        );
+
+        // the channels to another port of the endpoint don't count
+        counts.insert((eps[1].Addr(), 80), 5);
+        counts.insert((eps[0].Addr(), 8080), 0);
+        assert_eq!(
+            LeastConnections::PickWithCounts(&eps, &ctx, &counts),
+            Some(eps[0].clone())
+        );
+    }
+
+    #[test]
+    fn test_LeastConnectionsTies() {
+        let eps = Endpoints();
+        let mut counts = HashMap::new();
+        counts.insert((eps[1].Addr(), 80), 1);
+        // the endpoints without a channel tie, they rotate with the index
+        let picks: Vec<IpWithPort> = (0..4)
+            .map(|i| LeastConnections::PickWithCounts(&eps, &Context("10.0.0.1", i), &counts))
+            .map(|p| p.unwrap())
+            .collect();
+        assert_eq!(
+            picks,
+            vec![
+                eps[0].clone(),
+                eps[2].clone(),
+                eps[2].clone(),
+                eps[0].clone()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ClientIPHash() {
+        let eps = Endpoints();
+        let hash = ClientIPHash {};
+        assert_eq!(hash.Pick(&[], &Context("10.0.0.1", 0)), None);
+
+        // a client sticks to its endpoint whatever the index and the order
+        let pick = hash.Pick(&eps, &Context("10.0.0.1", 0)).unwrap();
+        let mut reversed = eps.clone();
+        reversed.reverse();
+        assert_eq!(hash.Pick(&reversed, &Context("10.0.0.1", 7)), Some(pick));
+    }
+
+    #[test]
+    fn test_ClientIPHashRemoval() {
+        let eps = Endpoints();
+        let hash = ClientIPHash {};
+        let mut used = [0; 3];
+        for i in 0..64u8 {
+            let client = Ipv4Addr::new(10, 0, 1, i).to_string();
+            let pick = hash.Pick(&eps, &Context(&client, 0)).unwrap();
+            let idx = eps.iter().position(|ep| *ep == pick).unwrap();
+            used[idx] += 1;
+
+            // only the clients of the removed endpoint move
+            let rest = vec![eps[0].clone(), eps[2].clone()];
+            let repick = hash.Pick(&rest, &Context(&client, 0)).unwrap();
+            if idx != 1 {
+                assert_eq!(repick, pick);
+            }
+        }
+
+        // the clients spread over all the endpoints
+        assert!(used.iter().all(|n| *n > 0));
+    }
+
+    #[test]
+    fn test_Fnv1a() {
+        // the FNV-1a test vectors before the finalizer
+        let mix = |h: u64| {
+            let mut h = h;
+            h ^= h >> 33;
+            h = h.wrapping_mul(0xff51afd7ed558ccd);
+            h ^= h >> 33;
+            h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+            h ^= h >> 33;
+            h
+        };
+        assert_eq!(Fnv1a(&[]), mix(0xcbf29ce484222325));
+        assert_eq!(Fnv1a(&[b"a"]), mix(0xaf63dc4c8601ec8c));
+        assert_eq!(Fnv1a(&[b"foo", b"bar"]), Fnv1a(&[b"foobar"]));
+        assert_eq!(Fnv1a(&[b"foobar"]), mix(0x85944171f73967e8));
+        assert_eq!(
+            IpBytes("10.0.0.1".parse().unwrap()),
+            "::ffff:10.0.0.1".parse::<Ipv6Addr>().unwrap().octets()
+        );
+    }
+
+    #[test]
+    fn test_FromService() {
+        let mut annotations = HashMap::new();
+        assert_eq!(
+            LBPolicy::FromService(&annotations, ""),
+            LBPolicy::RoundRobin
+        );
+        assert_eq!(
+            LBPolicy::FromService(&annotations, SESSION_AFFINITY_CLIENT_IP),
+            LBPolicy::ClientIPHash
+        );
+
+        annotations.insert(
+            LB_POLICY_ANNOTATION.to_string(),
+            LB_POLICY_LEAST_CONNECTIONS.to_string(),
+        );
+        assert_eq!(
+            LBPolicy::FromService(&annotations, SESSION_AFFINITY_CLIENT_IP),
+            LBPolicy::LeastConnections
+        );
+
+        annotations.insert(LB_POLICY_ANNOTATION.to_string(), "random".to_string());
+        assert_eq!(
+            LBPolicy::FromService(&annotations, ""),
+            LBPolicy::RoundRobin
+        );
+    }
+}
@@ -70,6 +70,7 @@ pub mod qlib;
 // pub mod qlib;
 
 pub mod id_mgr;
+pub mod load_balancer;
 pub mod rdma;
 pub mod rdma_agent;
 pub mod rdma_channel;
@@ -77,15 +78,21 @@ pub mod rdma_conn;
 pub mod rdma_ctrlconn;
 pub mod rdma_def;
 pub mod rdma_srv;
+pub mod soft_rdma;
 pub mod unix_socket_def;
 
 pub mod common;
 pub mod configmap_informer;
 pub mod constants;
 pub mod endpoints_informer;
+pub mod file_informer;
+pub mod network_policy;
+pub mod network_policy_informer;
 pub mod node_informer;
 pub mod pod_informer;
+pub mod qos;
 pub mod service_informer;
+pub mod stats;
 
 use crate::qlib::bytestream::ByteStream;
 use crate::rdma_srv::RDMA_CTLINFO;
@@ -103,13 +110,17 @@ use std::path::Path;
 pub static SHARE_SPACE: ShareSpaceRef = ShareSpaceRef::New();
 use self::qlib::mem::list_allocator::*;
 use crate::qlib::rdma_share::*;
-use crate::rdma::RDMA;
+use crate::rdma::{QueuePairOps, RDMA};
+use crate::soft_rdma::{SoftRDMAConfig, SoftRDMAEnabled};
 use common::*;
 use configmap_informer::ConfigMapInformer;
+use constants::{HEARTBEAT_INTERVAL_MS, QOS_TIMER_INTERVAL_MS};
 use endpoints_informer::EndpointsInformer;
+use file_informer::{FileControlDir, FileInformer};
 use id_mgr::IdMgr;
 use local_ip_address::list_afinet_netifas;
 use local_ip_address::local_ip;
+use network_policy_informer::NetworkPolicyInformer;
 use node_informer::NodeInformer;
 use pod_informer::PodInformer;
 use qlib::kernel::TSC;
@@ -123,6 +134,7 @@ use rdma_ctrlconn::Node;
 use rdma_ctrlconn::Pod;
 use service_informer::ServiceInformer;
 use spin::Mutex;
+use stats::{StartStatsServer, StatsCommand};
 use std::io::Error;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicU32, Ordering};
@@ -169,8 +181,22 @@ pub const IO_WAIT_CYCLES: i64 = 100_000_000; // 1ms
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // "rdma_srv stats" queries the stats server of the running rdma_srv
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 1 && args[1] == "stats" {
+        std::process::exit(StatsCommand(&args[1..]));
+    }
+
     println!("RDMA Service is starting!");
-    RDMA.Init("", 1);
+    if SoftRDMAEnabled() {
+        let res = SoftRDMAConfig::FromEnv().and_then(|config| RDMA.InitSoft(&config));
+        if let Err(e) = res {
+            println!("Failed to start the software RDMA device: {:?}", e);
+            std::process::exit(1);
+        }
+    } else {
+        RDMA.Init("", 1);
+    }
     let hostname_os = hostname::get()?;
     match hostname_os.into_string() {
         Ok(v) => RDMA_CTLINFO.hostname_set(v),
@@ -182,14 +208,27 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("epoll_fd is {}", epoll_fd);
     RDMA_CTLINFO.epoll_fd_set(epoll_fd);
 
-    let args: Vec<_> = env::args().collect();
     let server_fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0) };
     println!("server_fd is {}", server_fd);
     unblock_fd(server_fd);
     RDMA_CTLINFO.fds_insert(server_fd, Srv_FdType::TCPSocketServer);
     epoll_add(epoll_fd, server_fd, read_write_event(server_fd as u64))?;
 
-    if RDMA_CTLINFO.isK8s {
+    let controlDir = FileControlDir();
+    if let Some(dir) = controlDir.clone() {
+        println!("Watch cluster state in {:?}", dir);
+        tokio::spawn(async move {
+            let mut file_informer = FileInformer::new(&dir);
+            match file_informer.run().await {
+                Err(e) => {
+                    println!("Error to handle control files: {:?}", e);
+                }
+                Ok(_) => (),
+            };
+        });
+    }
+
+    if RDMA_CTLINFO.isK8s && controlDir.is_none() {
         tokio::spawn(async {
             while !RDMA_CTLINFO.isCMConnected_get() {
                 let mut node_informer = NodeInformer::new();
@@ -254,6 +293,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 Ok(_) => (),
             };
         });
+
+        tokio::spawn(async {
+            while !RDMA_CTLINFO.isCMConnected_get() {
+                thread::sleep_ms(1000);
+            }
+            let mut network_policy_informer = NetworkPolicyInformer::new();
+            match network_policy_informer.run().await {
+                Err(e) => {
+                    println!("Error to handle network policies: {:?}", e);
+                }
+                Ok(_) => (),
+            };
+        });
     }
 
     //watch RDMA event
@@ -385,6 +437,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     epoll_add(epoll_fd, srvEventFd, read_event(srvEventFd as u64))?;
     unblock_fd(srvEventFd);
     RDMA_CTLINFO.fds_insert(srvEventFd, Srv_FdType::SrvEventFd(srvEventFd));
+
+    let heartbeatTimerFd = timerfd_create(HEARTBEAT_INTERVAL_MS)?;
+    epoll_add(
+        epoll_fd,
+        heartbeatTimerFd,
+        read_event(heartbeatTimerFd as u64),
+    )?;
+    RDMA_CTLINFO.fds_insert(
+        heartbeatTimerFd,
+        Srv_FdType::HeartbeatTimerFd(heartbeatTimerFd),
+    );
+
+    let qosTimerFd = timerfd_create(QOS_TIMER_INTERVAL_MS)?;
+    epoll_add(epoll_fd, qosTimerFd, read_event(qosTimerFd as u64))?;
+    RDMA_CTLINFO.fds_insert(qosTimerFd, Srv_FdType::QoSTimerFd(qosTimerFd));
+    StartStatsServer();
     let hostname = RDMA_CTLINFO.hostname_get();
     let mut events: Vec<EpollEvent> = Vec::with_capacity(1024);
 
@@ -436,7 +504,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             // print!("u64: {}, events: {:x}", ev.U64, ev.Events);
             // let event_data = RDMA_CTLINFO.fds_get(ev.U64 as i32);
             let mut fds = RDMA_CTLINFO.fds.lock();
-            let event_data = fds.get(&(ev.U64 as i32)).unwrap();
+            let event_data = match fds.get(&(ev.U64 as i32)) {
+                Some(event_data) => event_data,
+                // the fd is closed by a connection reset
+                None => continue,
+            };
             match event_data {
                 Srv_FdType::TCPSocketServer => {
                     let stream_fd;
@@ -453,6 +525,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     println!("stream_fd is: {}", stream_fd);
 
                     let peerIpAddrU32 = cliaddr.sin_addr.s_addr;
+                    let nodeIp = if peerIpAddrU32 == RDMA_CTLINFO.localIp_get() {
+                        0
+                    } else {
+                        peerIpAddrU32
+                    };
 
                     let controlRegionId =
                         RDMA_SRV.controlBufIdMgr.lock().AllocId().unwrap() as usize; // TODO: should handle no space issue.
@@ -471,6 +548,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
                     let rdmaConn = RDMAConn::New(
                         stream_fd,
+                        nodeIp,
+                        controlRegionId as u32,
                         sockBuf.clone(),
                         RDMA_SRV.keys[controlRegionId / 1024][1],
                         RDMA_SRV.udpQP.qpNum(),
@@ -514,24 +593,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                             .insert(qp.qpNum(), rdmaChannel.clone());
                     }
 
-                    if peerIpAddrU32 == RDMA_CTLINFO.localIp_get() {
-                        RDMA_SRV.conns.lock().insert(0, rdmaConn);
-                        fds.insert(stream_fd, Srv_FdType::TCPSocketConnect(0));
-                    } else {
-                        RDMA_SRV.conns.lock().insert(peerIpAddrU32, rdmaConn);
-                        fds.insert(stream_fd, Srv_FdType::TCPSocketConnect(peerIpAddrU32));
+                    // the peer rdma_srv restarted, the old connection is stale
+                    if let Some(staleConn) = RDMA_SRV.conns.lock().insert(nodeIp, rdmaConn) {
+                        staleConn.MarkBroken();
                     }
+                    fds.insert(stream_fd, Srv_FdType::TCPSocketConnect(nodeIp));
 
                     epoll_add(epoll_fd, stream_fd, read_write_event(stream_fd as u64))?;
                 }
-                Srv_FdType::TCPSocketConnect(ipAddr) => match RDMA_SRV.conns.lock().get(&ipAddr) {
-                    Some(rdmaConn) => {
-                        rdmaConn.Notify(ev.Events as u64);
-                    }
-                    _ => {
-                        panic!("no RDMA connection for {} found!", ipAddr)
+                Srv_FdType::TCPSocketConnect(ipAddr) => {
+                    let rdmaConn = RDMA_SRV.conns.lock().get(&ipAddr).cloned();
+                    match rdmaConn {
+                        Some(rdmaConn) => {
+                            rdmaConn.Notify(ev.Events as u64);
+                        }
+                        _ => {
+                            error!("no RDMA connection for {} found!", ipAddr)
+                        }
                     }
-                },
+                }
                 Srv_FdType::UnixDomainSocketServer(_srv_sock) => {
                     let conn_sock = UnixSocket::Accept(ev.U64 as i32).unwrap();
                     let conn_sock_fd = conn_sock.as_raw_fd();
@@ -562,6 +642,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                                         Some(agentId) => {
                                             debug!("Remove agent from RDMA_SRV.agents");
                                             RDMA_SRV.agents.lock().remove(&agentId);
+                                            RDMA_SRV.qos.lock().RemoveAgent(agentId);
                                             fds.remove(&(ev.U64 as i32));
                                         }
                                         None => {
@@ -646,6 +727,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     std::mem::drop(fds);
                     SetupConnections();
                 }
+                Srv_FdType::HeartbeatTimerFd(timerFd) => {
+                    let mut expirations: u64 = 0;
+                    let _ret = unsafe {
+                        libc::read(*timerFd, &mut expirations as *mut _ as *mut libc::c_void, 8)
+                    };
+                    std::mem::drop(fds);
+                    RDMA_SRV.Heartbeat();
+                    ReconnectPeers();
+                    RDMA_SRV.qos.lock().WriteStats(MonotonicUs());
+                }
+                Srv_FdType::QoSTimerFd(timerFd) => {
+                    let mut expirations: u64 = 0;
+                    let _ret = unsafe {
+                        libc::read(*timerFd, &mut expirations as *mut _ as *mut libc::c_void, 8)
+                    };
+                    std::mem::drop(fds);
+                    RDMA_SRV.QoSTimer();
+                }
             }
             //println!("Finish processing fd: {}, event: {}", ev.U64, ev.Events);
         }
@@ -685,6 +784,7 @@ fn RDMAProcessOnce() -> usize {
     }
     // SendConsumedData(&mut channels);
     count += RDMA_SRV.HandleClientRequest();
+    RDMA_SRV.ResetBrokenConns();
     count
 }
 
@@ -719,18 +819,24 @@ fn InitContainer(conn_sock: &UnixSocket, podId: [u8; 64]) {
         .podIdToAgents
         .lock()
         .insert(rdmaAgent.podId, rdmaAgent.clone());
-    match RDMA_CTLINFO
-        .containerids
-        .lock()
-        .get(&String::from_utf8(rdmaAgent.podId.to_vec()).unwrap())
-    {
+    let podId = String::from_utf8(rdmaAgent.podId.to_vec()).unwrap();
+    let podIp = RDMA_CTLINFO.containerids.lock().get(&podId).cloned();
+    match podIp {
         Some(ip) => {
-            RDMA_SRV
-                .ipAddrToAgents
-                .lock()
-                .insert(*ip, rdmaAgent.clone());
+            RDMA_SRV.ipAddrToAgents.lock().insert(ip, rdmaAgent.clone());
+            if let Some(pod) = RDMA_CTLINFO.pods.lock().get(&ip) {
+                RDMA_SRV.SetAgentQoS(rdmaAgentId, pod);
+            }
+        }
+        None => {
+            // ipv6 only pod
+            let podIpv6 = RDMA_CTLINFO.containeridsV6.lock().get(&podId).cloned();
+            if let Some(ip) = podIpv6 {
+                if let Some(pod) = RDMA_CTLINFO.podsV6.lock().get(&ip) {
+                    RDMA_SRV.SetAgentQoS(rdmaAgentId, pod);
+                }
+            }
         }
-        None => {}
     }
 
     RDMA_SRV
@@ -767,6 +873,18 @@ fn SetupConnections() {
     }
 }
 
+// ReconnectPeers reconnects to the peer nodes whose reconnect backoff expired
+fn ReconnectPeers() {
+    for ip in RDMA_CTLINFO.peer_reconnect_due() {
+        if RDMA_SRV.ExistsConnection(&ip) || !RDMA_CTLINFO.nodes.lock().contains_key(&ip) {
+            continue;
+        }
+
+        println!("reconnect to node {}", Ipv4Addr::from(u32::from_be(ip)));
+        SetupConnection(&ip);
+    }
+}
+
 fn SetupConnection(ip: &u32) {
     let node = RDMA_CTLINFO.node_get(*ip);
     let sock_fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0) };
@@ -796,6 +914,8 @@ fn SetupConnection(ip: &u32) {
 
     let rdmaConn = RDMAConn::New(
         sock_fd,
+        node.ipAddr,
+        controlRegionId as u32,
         sockBuf.clone(),
         RDMA_SRV.keys[controlRegionId / 16][1],
         RDMA_SRV.udpQP.qpNum(),
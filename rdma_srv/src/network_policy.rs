@@ -0,0 +1,577 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use cidr::IpCidr;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use super::constants::*;
+use super::rdma_ctrlconn::*;
+
+// The types below are the subset of the kubernetes NetworkPolicySpec which
+// TSoR enforces, deserialized from the json sent by quarkcm.
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct LabelSelector {
+    #[serde(default)]
+    pub matchLabels: HashMap<String, String>,
+    #[serde(default)]
+    pub matchExpressions: Vec<LabelSelectorRequirement>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LabelSelectorRequirement {
+    pub key: String,
+    pub operator: String,
+    #[serde(default)]
+    pub values: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct IPBlock {
+    pub cidr: String,
+    #[serde(default)]
+    pub except: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NetworkPolicyPeer {
+    #[serde(default)]
+    pub podSelector: Option<LabelSelector>,
+    #[serde(default)]
+    pub namespaceSelector: Option<LabelSelector>,
+    #[serde(default)]
+    pub ipBlock: Option<IPBlock>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NetworkPolicyPort {
+    #[serde(default)]
+    pub protocol: Option<String>,
+    // port number or name
+    #[serde(default)]
+    pub port: Option<Value>,
+    #[serde(default)]
+    pub endPort: Option<u16>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NetworkPolicyIngressRule {
+    #[serde(default)]
+    pub from: Vec<NetworkPolicyPeer>,
+    #[serde(default)]
+    pub ports: Vec<NetworkPolicyPort>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NetworkPolicyEgressRule {
+    #[serde(default)]
+    pub to: Vec<NetworkPolicyPeer>,
+    #[serde(default)]
+    pub ports: Vec<NetworkPolicyPort>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NetworkPolicySpec {
+    #[serde(default)]
+    pub podSelector: LabelSelector,
+    #[serde(default)]
+    pub ingress: Vec<NetworkPolicyIngressRule>,
+    #[serde(default)]
+    pub egress: Option<Vec<NetworkPolicyEgressRule>>,
+    #[serde(default)]
+    pub policyTypes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyDirection {
+    Ingress,
+    Egress,
+}
+
+#[derive(Debug, Clone)]
+pub struct NetworkPolicy {
+    pub name: String,
+    pub namespace: String,
+    pub spec: NetworkPolicySpec,
+    pub resource_version: i32,
+}
+
+impl LabelSelector {
+    // an empty selector selects everything
+    pub fn Matches(&self, labels: &HashMap<String, String>) -> bool {
+        for (key, value) in &self.matchLabels {
+            if labels.get(key) != Some(value) {
+                return false;
+            }
+        }
+
+        for req in &self.matchExpressions {
+            let value = labels.get(&req.key);
+            let matched = match req.operator.as_str() {
+                "In" => value.map_or(false, |v| req.values.contains(v)),
+                "NotIn" => value.map_or(true, |v| !req.values.contains(v)),
+                "Exists" => value.is_some(),
+                "DoesNotExist" => value.is_none(),
+                _ => {
+                    error!("unknown label selector operator {}", req.operator);
+                    false
+                }
+            };
+            if !matched {
+                return false;
+            }
+        }
+
+        return true;
+    }
+}
+
+// a cidr of the other address family doesn't contain the ip
+fn InCidr(cidr: &str, ip: &IpAddr) -> bool {
+    match IpCidr::from_str(cidr) {
+        Ok(ipCidr) => return ipCidr.contains(ip),
+        Err(_) => {
+            error!("invalid network policy cidr {}", cidr);
+            return false;
+        }
+    }
+}
+
+impl IPBlock {
+    pub fn Contains(&self, ip: &IpAddr) -> bool {
+        return InCidr(&self.cidr, ip) && !self.except.iter().any(|e| InCidr(e, ip));
+    }
+}
+
+impl NetworkPolicyPeer {
+    // Matches returns whether the peer of a pod in namespace selects the
+    // remote ip, which is a pod ip or an ip outside of the cluster.
+    pub fn Matches(&self, namespace: &str, ip: &IpAddr, pod: Option<&Pod>) -> bool {
+        if let Some(ipBlock) = &self.ipBlock {
+            return ipBlock.Contains(ip);
+        }
+
+        let pod = match pod {
+            Some(pod) => pod,
+            None => return false,
+        };
+
+        let namespaceMatched = match &self.namespaceSelector {
+            Some(selector) => selector.Matches(&pod.namespace_labels),
+            None => pod.namespace == namespace,
+        };
+        let podMatched = match &self.podSelector {
+            Some(selector) => selector.Matches(&pod.labels),
+            None => true,
+        };
+        return namespaceMatched && podMatched;
+    }
+}
+
+impl NetworkPolicyPort {
+    // port is in host order
+    pub fn Matches(&self, protocol: &str, port: u16) -> bool {
+        let policyProtocol = self.protocol.as_ref().map_or(PROTOCOL_TCP, |p| p.as_str());
+        if policyProtocol != protocol {
+            return false;
+        }
+
+        match &self.port {
+            None => return true,
+            Some(Value::Number(start)) if start.as_u64().is_some() => {
+                let start = start.as_u64().unwrap();
+                let end = self.endPort.map_or(start, |end| end as u64);
+                return start <= port as u64 && port as u64 <= end;
+            }
+            Some(other) => {
+                // the container port names are unknown to TSoR
+                error!("unsupported network policy port {:?}", other);
+                return false;
+            }
+        }
+    }
+}
+
+fn PortsMatch(ports: &[NetworkPolicyPort], protocol: &str, port: u16) -> bool {
+    return ports.len() == 0 || ports.iter().any(|p| p.Matches(protocol, port));
+}
+
+fn PeersMatch(
+    peers: &[NetworkPolicyPeer],
+    namespace: &str,
+    ip: &IpAddr,
+    pod: Option<&Pod>,
+) -> bool {
+    return peers.len() == 0 || peers.iter().any(|p| p.Matches(namespace, ip, pod));
+}
+
+impl NetworkPolicy {
+    pub fn Key(namespace: &str, name: &str) -> String {
+        return format!("{}/{}", namespace, name);
+    }
+
+    // HasType returns whether the policy isolates the pods in the direction.
+    // Without policyTypes, a policy is for ingress, and for egress when it
+    // has egress rules.
+    pub fn HasType(&self, direction: PolicyDirection) -> bool {
+        if self.spec.policyTypes.len() > 0 {
+            let name = match direction {
+                PolicyDirection::Ingress => NETWORK_POLICY_TYPE_INGRESS,
+                PolicyDirection::Egress => NETWORK_POLICY_TYPE_EGRESS,
+            };
+            return self.spec.policyTypes.iter().any(|t| t == name);
+        }
+
+        match direction {
+            PolicyDirection::Ingress => return true,
+            PolicyDirection::Egress => return self.spec.egress.is_some(),
+        }
+    }
+
+    pub fn Selects(&self, pod: &Pod) -> bool {
+        return self.namespace == pod.namespace && self.spec.podSelector.Matches(&pod.labels);
+    }
+
+    // Allows returns whether a rule of the policy allows the traffic between
+    // the pod it selects and the peer
+    pub fn Allows(
+        &self,
+        direction: PolicyDirection,
+        peerIp: &IpAddr,
+        peerPod: Option<&Pod>,
+        protocol: &str,
+        port: u16,
+    ) -> bool {
+        match direction {
+            PolicyDirection::Ingress => {
+                return self.spec.ingress.iter().any(|rule| {
+                    PeersMatch(&rule.from, &self.namespace, peerIp, peerPod)
+                        && PortsMatch(&rule.ports, protocol, port)
+                });
+            }
+            PolicyDirection::Egress => {
+                let rules = match &self.spec.egress {
+                    Some(rules) => rules,
+                    None => return false,
+                };
+                return rules.iter().any(|rule| {
+                    PeersMatch(&rule.to, &self.namespace, peerIp, peerPod)
+                        && PortsMatch(&rule.ports, protocol, port)
+                });
+            }
+        }
+    }
+}
+
+// PodAllows returns whether the policies allow the traffic of the pod in the
+// direction. A pod not selected by any policy of the direction is not isolated.
+fn PodAllows(
+    policies: &HashMap<String, NetworkPolicy>,
+    pod: &Pod,
+    direction: PolicyDirection,
+    peerIp: &IpAddr,
+    peerPod: Option<&Pod>,
+    protocol: &str,
+    port: u16,
+) -> bool {
+    let mut isolated = false;
+    for policy in policies.values() {
+        if !policy.HasType(direction) || !policy.Selects(pod) {
+            continue;
+        }
+
+        isolated = true;
+        if policy.Allows(direction, peerIp, peerPod, protocol, port) {
+            return true;
+        }
+    }
+
+    return !isolated;
+}
+
+// Allowed returns whether the policies allow the connection from srcIp to
+// dstIp:port, the egress of the source pod and the ingress of the destination
+// pod. The pods are None for the ips out of the cluster, the port is in host
+// order.
+pub fn Allowed(
+    policies: &HashMap<String, NetworkPolicy>,
+    srcPod: Option<&Pod>,
+    dstPod: Option<&Pod>,
+    srcIp: IpAddr,
+    dstIp: IpAddr,
+    protocol: &str,
+    port: u16,
+) -> bool {
+    if policies.len() == 0 {
+        return true;
+    }
+
+    if let Some(pod) = srcPod {
+        if !PodAllows(
+            policies,
+            pod,
+            PolicyDirection::Egress,
+            &dstIp,
+            dstPod,
+            protocol,
+            port,
+        ) {
+            return false;
+        }
+    }
+
+    if let Some(pod) = dstPod {
+        if !PodAllows(
+            policies,
+            pod,
+            PolicyDirection::Ingress,
+            &srcIp,
+            srcPod,
+            protocol,
+            port,
+        ) {
+            return false;
+        }
+    }
+
+    return true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn Labels(labels: &[(&str, &str)]) -> HashMap<String, String> {
+        return labels
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+    }
+
+    fn NewPod(namespace: &str, app: &str, team: &str) -> Pod {
+        return Pod {
+            key: format!("{}/{}", namespace, app),
+            namespace: namespace.to_string(),
+            labels: Labels(&[("app", app)]),
+            namespace_labels: Labels(&[(NAMESPACE_NAME_LABEL, namespace), ("team", team)]),
+            ..Default::default()
+        };
+    }
+
+    // the policies of the test cases, by name, all in the prod namespace
+    const POLICIES: &[(&str, &str)] = &[
+        (
+            "db-web",
+            r#"{"podSelector": {"matchLabels": {"app": "db"}},
+                "ingress": [{"from": [{"podSelector": {"matchLabels": {"app": "web"}}}],
+                             "ports": [{"protocol": "TCP", "port": 5432}]}]}"#,
+        ),
+        (
+            "db-ops",
+            r#"{"podSelector": {"matchLabels": {"app": "db"}},
+                "ingress": [{"from": [{"namespaceSelector": {"matchExpressions": [
+                    {"key": "team", "operator": "In", "values": ["ops", "sre"]}]}}]}]}"#,
+        ),
+        (
+            "db-lan",
+            r#"{"podSelector": {"matchLabels": {"app": "db"}},
+                "ingress": [{"from": [{"ipBlock": {"cidr": "10.0.0.0/8", "except": ["10.1.0.0/16"]}}]}]}"#,
+        ),
+        (
+            "deny-in",
+            r#"{"podSelector": {}, "policyTypes": ["Ingress"]}"#,
+        ),
+        (
+            "web-db",
+            r#"{"podSelector": {"matchLabels": {"app": "web"}}, "policyTypes": ["Egress"],
+                "egress": [{"to": [{"podSelector": {"matchLabels": {"app": "db"}}}],
+                            "ports": [{"port": 5432}]},
+                           {"to": [{"ipBlock": {"cidr": "192.168.0.0/16", "except": ["192.168.1.0/24"]}}],
+                            "ports": [{"protocol": "TCP", "port": 53, "endPort": 54}]}]}"#,
+        ),
+        (
+            "deny-out",
+            r#"{"podSelector": {}, "policyTypes": ["Egress"]}"#,
+        ),
+    ];
+
+    fn Policies(names: &[&str]) -> HashMap<String, NetworkPolicy> {
+        let mut policies = HashMap::new();
+        for name in names {
+            let spec = POLICIES.iter().find(|(n, _)| n == name).unwrap().1;
+            let policy = NetworkPolicy {
+                name: name.to_string(),
+                namespace: "prod".to_string(),
+                spec: serde_json::from_str(spec).unwrap(),
+                resource_version: 0,
+            };
+            policies.insert(NetworkPolicy::Key("prod", name), policy);
+        }
+        return policies;
+    }
+
+    fn Pods() -> HashMap<&'static str, Pod> {
+        let mut pods = HashMap::new();
+        pods.insert("prod/web", NewPod("prod", "web", "dev"));
+        pods.insert("prod/db", NewPod("prod", "db", "dev"));
+        pods.insert("prod/cache", NewPod("prod", "cache", "dev"));
+        pods.insert("dev/web", NewPod("dev", "web", "dev"));
+        pods.insert("ops/monitor", NewPod("ops", "monitor", "ops"));
+        return pods;
+    }
+
+    // Case is (policies, source pod, destination pod, peer ip, protocol, port,
+    // allowed), the pods are "namespace/app", None for an ip out of the cluster.
+    type Case = (
+        &'static [&'static str],
+        Option<&'static str>,
+        Option<&'static str>,
+        &'static str,
+        &'static str,
+        u16,
+        bool,
+    );
+
+    const POD_IP: &str = "10.244.0.2";
+
+    // Run checks the cases, the peer ip is the source ip for ingress and the
+    // destination ip for egress.
+    fn Run(direction: PolicyDirection, cases: &[Case]) {
+        let pods = Pods();
+        for (i, (names, src, dst, peerIp, protocol, port, allowed)) in cases.iter().enumerate() {
+            let policies = Policies(names);
+            let (srcIp, dstIp) = match direction {
+                PolicyDirection::Ingress => (peerIp.parse().unwrap(), POD_IP.parse().unwrap()),
+                PolicyDirection::Egress => (POD_IP.parse().unwrap(), peerIp.parse().unwrap()),
+            };
+            let res = Allowed(
+                &policies,
+                src.map(|p| &pods[p]),
+                dst.map(|p| &pods[p]),
+                srcIp,
+                dstIp,
+                protocol,
+                *port,
+            );
+            assert_eq!(res, *allowed, "{:?} case {}", direction, i);
+        }
+    }
+
+    #[test]
+    fn test_AllowedIngress() {
+        let (web, db, cache) = (Some("prod/web"), Some("prod/db"), Some("prod/cache"));
+        let (devWeb, monitor) = (Some("dev/web"), Some("ops/monitor"));
+        let tcp = PROTOCOL_TCP;
+        Run(
+            PolicyDirection::Ingress,
+            &[
+                // no policy
+                (&[], cache, db, POD_IP, tcp, 80, true),
+                // pod selector: same namespace, port and protocol
+                (&["db-web"], web, db, POD_IP, tcp, 5432, true),
+                (&["db-web"], web, db, POD_IP, tcp, 80, false),
+                (&["db-web"], web, db, POD_IP, "UDP", 5432, false),
+                (&["db-web"], cache, db, POD_IP, tcp, 5432, false),
+                (&["db-web"], devWeb, db, POD_IP, tcp, 5432, false),
+                (&["db-web"], None, db, "10.2.0.1", tcp, 5432, false),
+                // a pod no policy selects isn't isolated
+                (&["db-web"], cache, web, POD_IP, tcp, 80, true),
+                // namespace selector
+                (&["db-ops"], monitor, db, POD_IP, tcp, 9100, true),
+                (&["db-ops"], devWeb, db, POD_IP, tcp, 9100, false),
+                (&["db-ops"], None, db, "10.2.0.1", tcp, 9100, false),
+                // ip block with except
+                (&["db-lan"], None, db, "10.2.0.1", tcp, 5432, true),
+                (&["db-lan"], None, db, "10.1.2.3", tcp, 5432, false),
+                (&["db-lan"], None, db, "11.0.0.1", tcp, 5432, false),
+                (&["db-lan"], None, db, "fd00::1", tcp, 5432, false),
+                // default deny, only in its namespace
+                (&["deny-in"], web, db, POD_IP, tcp, 5432, false),
+                (&["deny-in"], web, devWeb, POD_IP, tcp, 80, true),
+                // the rules of the policies selecting a pod are unioned
+                (&["deny-in", "db-web"], web, db, POD_IP, tcp, 5432, true),
+                (&["db-web", "db-ops"], monitor, db, POD_IP, tcp, 9100, true),
+                // egress isn't isolated by the ingress policies
+                (&["deny-in"], web, None, "8.8.8.8", tcp, 443, true),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_AllowedEgress() {
+        let (web, db, cache) = (Some("prod/web"), Some("prod/db"), Some("prod/cache"));
+        let devWeb = Some("dev/web");
+        let tcp = PROTOCOL_TCP;
+        Run(
+            PolicyDirection::Egress,
+            &[
+                // pod selector, the protocol defaults to TCP
+                (&["web-db"], web, db, POD_IP, tcp, 5432, true),
+                (&["web-db"], web, db, POD_IP, tcp, 80, false),
+                (&["web-db"], web, db, POD_IP, "UDP", 5432, false),
+                (&["web-db"], web, cache, POD_IP, tcp, 5432, false),
+                // ip block with except and a port range
+                (&["web-db"], web, None, "192.168.2.1", tcp, 53, true),
+                (&["web-db"], web, None, "192.168.2.1", tcp, 54, true),
+                (&["web-db"], web, None, "192.168.2.1", tcp, 55, false),
+                (&["web-db"], web, None, "192.168.1.1", tcp, 53, false),
+                // a pod no policy selects isn't isolated
+                (&["web-db"], cache, None, "8.8.8.8", tcp, 443, true),
+                // default deny, only in its namespace
+                (&["deny-out"], devWeb, None, "8.8.8.8", tcp, 443, true),
+                (&["deny-out"], db, None, "8.8.8.8", tcp, 443, false),
+                (&["deny-out"], db, devWeb, POD_IP, tcp, 80, false),
+                (&["deny-out", "web-db"], web, db, POD_IP, tcp, 5432, true),
+                // ingress isn't isolated by the egress policies
+                (&["deny-out"], None, db, POD_IP, tcp, 5432, true),
+                // the egress of the source and the ingress of the destination
+                (&["web-db", "deny-in"], web, db, POD_IP, tcp, 5432, false),
+                (&["web-db", "db-web"], web, db, POD_IP, tcp, 5432, true),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_HasType() {
+        let policy = |spec: &str| NetworkPolicy {
+            name: "p".to_string(),
+            namespace: "prod".to_string(),
+            spec: serde_json::from_str(spec).unwrap(),
+            resource_version: 0,
+        };
+
+        let cases = [
+            (r#"{}"#, true, false),
+            (r#"{"egress": []}"#, true, true),
+            (r#"{"policyTypes": ["Egress"]}"#, false, true),
+            (r#"{"policyTypes": ["Ingress", "Egress"]}"#, true, true),
+        ];
+        for (spec, ingress, egress) in cases.iter() {
+            let p = policy(spec);
+            assert_eq!(p.HasType(PolicyDirection::Ingress), *ingress, "{}", spec);
+            assert_eq!(p.HasType(PolicyDirection::Egress), *egress, "{}", spec);
+        }
+
+        // a policy without egress rules denies all the egress of its pods
+        let pods = Pods();
+        let p = policy(r#"{"policyTypes": ["Egress"]}"#);
+        let ip: IpAddr = "8.8.8.8".parse().unwrap();
+        assert!(p.Selects(&pods["prod/web"]));
+        assert!(!p.Selects(&pods["dev/web"]));
+        assert!(!p.Allows(PolicyDirection::Egress, &ip, None, PROTOCOL_TCP, 443));
+    }
+}
@@ -0,0 +1,122 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::constants::*;
+use crate::network_policy::*;
+use crate::RDMA_CTLINFO;
+use svc_client::quark_cm_service_client::QuarkCmServiceClient;
+use svc_client::MaxResourceVersionMessage;
+use svc_client::NetworkPolicyMessage;
+use tokio::time::*;
+use tonic::Request;
+
+pub mod svc_client {
+    tonic::include_proto!("quarkcmsvc");
+}
+
+#[derive(Debug)]
+pub struct NetworkPolicyInformer {
+    pub max_resource_version: i32,
+}
+
+impl NetworkPolicyInformer {
+    pub fn new() -> NetworkPolicyInformer {
+        NetworkPolicyInformer {
+            max_resource_version: 0,
+        }
+    }
+}
+
+impl NetworkPolicyInformer {
+    pub async fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = QuarkCmServiceClient::connect(GRPC_SERVER_ADDRESS).await?;
+
+        let ref policies_message = client
+            .list_network_policy(())
+            .await?
+            .into_inner()
+            .network_policies;
+        for policy_message in policies_message {
+            self.handle(policy_message);
+        }
+
+        loop {
+            match self.run_watch().await {
+                Ok(_) => {}
+                Err(e) => {
+                    println!("NetworkPolicy watch error: {:?}", e);
+                }
+            }
+
+            sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    async fn run_watch(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        println!(
+            "Start networkPolicy run_watch. max_resource_version: {}",
+            self.max_resource_version
+        );
+        let mut client = QuarkCmServiceClient::connect(GRPC_SERVER_ADDRESS).await?;
+        let mut policy_stream = client
+            .watch_network_policy(Request::new(MaxResourceVersionMessage {
+                max_resource_version: self.max_resource_version,
+            }))
+            .await?
+            .into_inner();
+
+        while let Some(policy_message) = policy_stream.message().await? {
+            self.handle(&policy_message);
+        }
+        Ok(())
+    }
+
+    pub fn handle(&mut self, policy_message: &NetworkPolicyMessage) {
+        let key = NetworkPolicy::Key(&policy_message.namespace, &policy_message.name);
+        let mut policies_map = RDMA_CTLINFO.networkPolicies.lock();
+        if policy_message.event_type == EVENT_TYPE_SET {
+            match serde_json::from_str::<NetworkPolicySpec>(&policy_message.spec) {
+                Ok(spec) => {
+                    let policy = NetworkPolicy {
+                        name: policy_message.name.clone(),
+                        namespace: policy_message.namespace.clone(),
+                        spec: spec,
+                        resource_version: policy_message.resource_version,
+                    };
+                    policies_map.insert(key, policy);
+                }
+                Err(e) => {
+                    // keep the last version of the policy
+                    error!("invalid network policy {}: {:?}", key, e);
+                }
+            }
+        } else if policy_message.event_type == EVENT_TYPE_DELETE {
+            if policies_map.contains_key(&key) {
+                if policies_map[&key].resource_version < policy_message.resource_version {
+                    policies_map.remove(&key);
+                }
+            }
+        }
+        if policy_message.resource_version > self.max_resource_version {
+            self.max_resource_version = policy_message.resource_version;
+        }
+        RDMA_CTLINFO.resource_version_set("networkpolicy", self.max_resource_version);
+        println!("Handled NetworkPolicy: {:?}", policy_message);
+        println!(
+            "Debug: policies_map len:{} {:?}",
+            policies_map.len(),
+            policies_map
+        );
+    }
+}
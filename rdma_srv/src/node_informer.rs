@@ -16,6 +16,8 @@ use crate::common::*;
 use super::constants::*;
 use crate::rdma_ctrlconn::*;
 use crate::RDMA_CTLINFO;
+use cidr::Ipv6Cidr;
+use std::str::FromStr;
 use svc_client::quark_cm_service_client::QuarkCmServiceClient;
 use svc_client::MaxResourceVersionMessage;
 use svc_client::NodeMessage;
@@ -82,7 +84,7 @@ impl NodeInformer {
         Ok(())
     }
 
-    fn handle(&mut self, node_message: &NodeMessage) {
+    pub fn handle(&mut self, node_message: &NodeMessage) {
         println!("Start to handle Node: {:?}", node_message);
         let ip = node_message.ip;
         let mut nodes_map = RDMA_CTLINFO.nodes.lock();
@@ -94,6 +96,7 @@ impl NodeInformer {
                 ipAddr: ip,
                 subnet: node_message.subnet,
                 netmask: node_message.net_mask,
+                subnetV6: Ipv6Cidr::from_str(&node_message.subnet_v6).ok(),
                 timestamp: node_message.creation_timestamp,
                 resource_version: node_message.resource_version,
             };
@@ -133,6 +136,7 @@ impl NodeInformer {
         if node_message.resource_version > self.max_resource_version {
             self.max_resource_version = node_message.resource_version;
         }
+        RDMA_CTLINFO.resource_version_set("node", self.max_resource_version);
         println!("Handled Node: {:?}", node_message);
         println!("Debug: nodes_map len:{} {:?}", nodes_map.len(), nodes_map);
     }
@@ -14,9 +14,13 @@
 
 use super::constants::*;
 use crate::common::*;
+use crate::qos::PodQoS;
 use crate::rdma_ctrlconn::*;
 use crate::RDMA_CTLINFO;
 use crate::RDMA_SRV;
+use std::collections::HashMap;
+use std::net::Ipv6Addr;
+use std::str::FromStr;
 use svc_client::quark_cm_service_client::QuarkCmServiceClient;
 use svc_client::MaxResourceVersionMessage;
 use svc_client::PodMessage;
@@ -84,50 +88,84 @@ impl PodInformer {
         Ok(())
     }
 
-    fn handle(&mut self, pod_message: &PodMessage) {
+    pub fn handle(&mut self, pod_message: &PodMessage) {
         let ip = pod_message.ip;
         let mut pods_map = RDMA_CTLINFO.pods.lock();
         let mut containerids_map = RDMA_CTLINFO.containerids.lock();
         let mut ip_to_podId_map = RDMA_CTLINFO.ipToPodIdMappings.lock();
+        // empty for a pod without an ipv6 address
+        let ipv6 = Ipv6Addr::from_str(&pod_message.ipv6).ok();
+        let mut podsV6_map = RDMA_CTLINFO.podsV6.lock();
+        let mut containeridsV6_map = RDMA_CTLINFO.containeridsV6.lock();
         if pod_message.event_type == EVENT_TYPE_SET {
             let pod = Pod {
                 key: pod_message.key.clone(),
                 ip: ip,
+                ipv6: ipv6,
                 node_name: pod_message.node_name.clone(),
                 container_id: pod_message.container_id.clone(),
                 resource_version: pod_message.resource_version,
+                namespace: pod_message.namespace.clone(),
+                labels: pod_message.labels.clone(),
+                namespace_labels: NamespaceLabels(pod_message),
+                qos: PodQoS::FromAnnotations(&pod_message.annotations),
             };
-            containerids_map.insert(pod.container_id.clone(), pod.ip.clone());
-            ip_to_podId_map.insert(pod.ip, pod.container_id.clone());
-            match RDMA_SRV.ipAddrToAgents.lock().get(&pod.ip) {
-                Some(_agent) => {}
-                None => {
-                    match RDMA_SRV
-                        .podIdToAgents
-                        .lock()
-                        .get(pod.container_id.as_bytes())
-                    {
-                        Some(rdmaAgent) => {
-                            RDMA_SRV
-                                .ipAddrToAgents
-                                .lock()
-                                .insert(pod.ip, rdmaAgent.clone());
-                        }
-                        None => {
-                            panic!(
-                                "Could not find agent from podId: {:?}",
-                                pod.container_id.as_bytes()
-                            );
+            if let Some(ipv6) = ipv6 {
+                containeridsV6_map.insert(pod.container_id.clone(), ipv6);
+                podsV6_map.insert(ipv6, pod.clone());
+            }
+            if ip == 0 {
+                // ipv6 only pod, it is not in the ipv4 maps
+                if let Some(rdmaAgent) = RDMA_SRV
+                    .podIdToAgents
+                    .lock()
+                    .get(pod.container_id.as_bytes())
+                {
+                    RDMA_SRV.SetAgentQoS(rdmaAgent.id, &pod);
+                }
+            } else {
+                containerids_map.insert(pod.container_id.clone(), pod.ip.clone());
+                ip_to_podId_map.insert(pod.ip, pod.container_id.clone());
+                match RDMA_SRV.ipAddrToAgents.lock().get(&pod.ip) {
+                    Some(_agent) => {}
+                    None => {
+                        match RDMA_SRV
+                            .podIdToAgents
+                            .lock()
+                            .get(pod.container_id.as_bytes())
+                        {
+                            Some(rdmaAgent) => {
+                                RDMA_SRV
+                                    .ipAddrToAgents
+                                    .lock()
+                                    .insert(pod.ip, rdmaAgent.clone());
+                            }
+                            None => {
+                                // the agent is mapped in InitContainer when the
+                                // container starts later
+                                println!("Could not find agent from podId: {:?}", pod.container_id);
+                            }
                         }
                     }
                 }
-            }
+                if let Some(rdmaAgent) = RDMA_SRV.ipAddrToAgents.lock().get(&pod.ip) {
+                    RDMA_SRV.SetAgentQoS(rdmaAgent.id, &pod);
+                }
 
-            pods_map.insert(ip, pod);
+                pods_map.insert(ip, pod);
+            }
             if pod_message.resource_version > self.max_resource_version {
                 self.max_resource_version = pod_message.resource_version;
             }
         } else if pod_message.event_type == EVENT_TYPE_DELETE {
+            if let Some(ipv6) = ipv6 {
+                if let Some(pod) = podsV6_map.get(&ipv6) {
+                    if pod.resource_version < pod_message.resource_version {
+                        containeridsV6_map.remove(&pod.container_id);
+                        podsV6_map.remove(&ipv6);
+                    }
+                }
+            }
             ip_to_podId_map.remove(&ip);
             RDMA_SRV.ipAddrToAgents.lock().remove(&ip);
             if pods_map.contains_key(&ip) {
@@ -141,6 +179,7 @@ impl PodInformer {
         if pod_message.resource_version > self.max_resource_version {
             self.max_resource_version = pod_message.resource_version;
         }
+        RDMA_CTLINFO.resource_version_set("pod", self.max_resource_version);
         println!("Handled Pod: {:?}", pod_message);
         println!("Debug: pods_map len:{} {:?}", pods_map.len(), pods_map);
         println!(
@@ -155,3 +194,13 @@ impl PodInformer {
         );
     }
 }
+
+// NamespaceLabels returns the labels of the pod namespace, with the name label
+// kubernetes sets on every namespace
+pub fn NamespaceLabels(pod_message: &PodMessage) -> HashMap<String, String> {
+    let mut labels = pod_message.namespace_labels.clone();
+    labels
+        .entry(NAMESPACE_NAME_LABEL.to_string())
+        .or_insert(pod_message.namespace.clone());
+    return labels;
+}
@@ -0,0 +1,481 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::path::PathBuf;
+
+use super::common::*;
+use super::constants::*;
+use super::qlib::quantity::*;
+
+// PodQoS is the QoS of the writes of a pod over the RDMA connections shared
+// with the other pods of the node
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PodQoS {
+    // share of the receive requests of the peer when the pods contend
+    pub weight: u32,
+    // bandwidth cap in bytes per second, 0 for unlimited
+    pub rate: u64,
+}
+
+impl Default for PodQoS {
+    fn default() -> Self {
+        PodQoS {
+            weight: QOS_WEIGHT_NORMAL,
+            rate: 0,
+        }
+    }
+}
+
+impl PodQoS {
+    pub fn FromAnnotations(annotations: &HashMap<String, String>) -> Self {
+        let mut qos = PodQoS::default();
+        if let Some(priority) = annotations.get(QOS_PRIORITY_ANNOTATION) {
+            match ParsePriority(priority) {
+                Some(weight) => qos.weight = weight,
+                None => error!("invalid {}: {}", QOS_PRIORITY_ANNOTATION, priority),
+            }
+        }
+
+        if let Some(bandwidth) = annotations.get(QOS_BANDWIDTH_ANNOTATION) {
+            match ParseBandwidth(bandwidth) {
+                Some(bitsPerSec) => qos.rate = (bitsPerSec / 8).max(1),
+                None => error!("invalid {}: {}", QOS_BANDWIDTH_ANNOTATION, bandwidth),
+            }
+        }
+
+        return qos;
+    }
+}
+
+fn ParsePriority(priority: &str) -> Option<u32> {
+    match priority {
+        "high" => return Some(QOS_WEIGHT_HIGH),
+        "normal" => return Some(QOS_WEIGHT_NORMAL),
+        "low" => return Some(QOS_WEIGHT_LOW),
+        _ => {}
+    }
+
+    match priority.parse::<u32>() {
+        Ok(weight) if weight > 0 => return Some(weight.min(QOS_WEIGHT_MAX)),
+        _ => return None,
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct PodQoSStats {
+    pub bytesSent: u64,
+    pub writes: u64,
+    // write requests queued for the receive requests of the peer
+    pub queued: u64,
+    pub queueDelayTotalUs: u64,
+    pub queueDelayMaxUs: u64,
+    // writes deferred by the bandwidth cap
+    pub throttled: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PodQoSReport {
+    pub pod: String,
+    pub weight: u32,
+    pub bandwidthBytes: u64,
+    pub bytesSent: u64,
+    pub throughputBytes: u64,
+    pub writes: u64,
+    pub queued: u64,
+    pub avgQueueDelayUs: u64,
+    pub maxQueueDelayUs: u64,
+    pub throttled: u64,
+}
+
+pub struct PodQoSState {
+    pub podKey: String,
+    pub qos: PodQoS,
+    // token bucket of the bandwidth cap, in bytes
+    pub tokens: u64,
+    pub lastRefill: u64,
+    // channels waiting for the tokens
+    pub throttled: HashSet<u32>,
+    pub stats: PodQoSStats,
+    // bytesSent and time of the last report, for the throughput
+    pub reportedBytes: u64,
+    pub reportTime: u64,
+}
+
+impl PodQoSState {
+    fn Burst(&self) -> u64 {
+        return (self.qos.rate * QOS_BURST_MS / 1000).max(1);
+    }
+}
+
+// QoSMgr keeps the QoS and the statistics of the pods: agent id --> pod
+pub struct QoSMgr {
+    pub pods: HashMap<u32, PodQoSState>,
+    pub statsPath: Option<PathBuf>,
+}
+
+impl QoSMgr {
+    pub fn New() -> Self {
+        let statsPath = match env::var(QOS_STATS_ENV) {
+            Ok(path) if path.len() > 0 => Some(PathBuf::from(path)),
+            _ => None,
+        };
+
+        return QoSMgr {
+            pods: HashMap::new(),
+            statsPath: statsPath,
+        };
+    }
+
+    // SetPodQoS sets the QoS of the pod served by the agent
+    pub fn SetPodQoS(&mut self, agentId: u32, podKey: &str, qos: PodQoS, now: u64) {
+        let isNew = !self.pods.contains_key(&agentId);
+        let pod = self.pods.entry(agentId).or_insert(PodQoSState {
+            podKey: podKey.to_string(),
+            qos: qos,
+            tokens: 0,
+            lastRefill: now,
+            throttled: HashSet::new(),
+            stats: PodQoSStats::default(),
+            reportedBytes: 0,
+            reportTime: now,
+        });
+        if pod.podKey != podKey || pod.qos != qos {
+            println!("QoS of pod {}: {:?}", podKey, qos);
+        }
+        pod.podKey = podKey.to_string();
+        pod.qos = qos;
+        // a new pod starts with a full bucket
+        pod.tokens = if isNew {
+            pod.Burst()
+        } else {
+            pod.tokens.min(pod.Burst())
+        };
+    }
+
+    pub fn RemoveAgent(&mut self, agentId: u32) {
+        self.pods.remove(&agentId);
+    }
+
+    pub fn Weight(&self, agentId: u32) -> u32 {
+        match self.pods.get(&agentId) {
+            Some(pod) => return pod.qos.weight,
+            None => return QOS_WEIGHT_NORMAL,
+        }
+    }
+
+    // Allowance returns the bytes the channel may write now, up to len. The
+    // channel waits for the refill when the pod is out of tokens.
+    pub fn Allowance(&mut self, agentId: u32, channelId: u32, len: usize) -> usize {
+        let pod = match self.pods.get_mut(&agentId) {
+            Some(pod) => pod,
+            None => return len,
+        };
+        if pod.qos.rate == 0 {
+            return len;
+        }
+
+        if pod.tokens == 0 {
+            if pod.throttled.insert(channelId) {
+                pod.stats.throttled += 1;
+            }
+            return 0;
+        }
+
+        return len.min(pod.tokens as usize);
+    }
+
+    // Charge accounts the bytes written by the pod
+    pub fn Charge(&mut self, agentId: u32, len: usize) {
+        if let Some(pod) = self.pods.get_mut(&agentId) {
+            pod.stats.bytesSent += len as u64;
+            pod.stats.writes += 1;
+            if pod.qos.rate != 0 {
+                pod.tokens = pod.tokens.saturating_sub(len as u64);
+            }
+        }
+    }
+
+    pub fn Queued(&mut self, agentId: u32) {
+        if let Some(pod) = self.pods.get_mut(&agentId) {
+            pod.stats.queued += 1;
+        }
+    }
+
+    pub fn Dequeued(&mut self, agentId: u32, delayUs: u64) {
+        if let Some(pod) = self.pods.get_mut(&agentId) {
+            pod.stats.queueDelayTotalUs += delayUs;
+            pod.stats.queueDelayMaxUs = pod.stats.queueDelayMaxUs.max(delayUs);
+        }
+    }
+
+    // Refill fills the token buckets and returns the throttled channels which
+    // can write again
+    pub fn Refill(&mut self, now: u64) -> Vec<u32> {
+        let mut resumed = Vec::new();
+        for pod in self.pods.values_mut() {
+            if pod.qos.rate == 0 {
+                pod.lastRefill = now;
+                resumed.extend(pod.throttled.drain());
+                continue;
+            }
+
+            let elapsed = now.saturating_sub(pod.lastRefill);
+            let tokens = pod.qos.rate * elapsed / 1_000_000;
+            if tokens == 0 {
+                continue;
+            }
+            // the remainder of the elapsed time is kept for the next refill
+            pod.lastRefill += tokens * 1_000_000 / pod.qos.rate;
+            pod.tokens = (pod.tokens + tokens).min(pod.Burst());
+            resumed.extend(pod.throttled.drain());
+        }
+
+        return resumed;
+    }
+
+    pub fn Reports(&mut self, now: u64) -> Vec<PodQoSReport> {
+        let mut reports = Vec::new();
+        for pod in self.pods.values_mut() {
+            let stats = &pod.stats;
+            let elapsed = now.saturating_sub(pod.reportTime);
+            let throughput = if elapsed == 0 {
+                0
+            } else {
+                (stats.bytesSent - pod.reportedBytes) * 1_000_000 / elapsed
+            };
+            let avgQueueDelayUs = if stats.queued == 0 {
+                0
+            } else {
+                stats.queueDelayTotalUs / stats.queued
+            };
+            reports.push(PodQoSReport {
+                pod: pod.podKey.clone(),
+                weight: pod.qos.weight,
+                bandwidthBytes: pod.qos.rate,
+                bytesSent: stats.bytesSent,
+                throughputBytes: throughput,
+                writes: stats.writes,
+                queued: stats.queued,
+                avgQueueDelayUs: avgQueueDelayUs,
+                maxQueueDelayUs: stats.queueDelayMaxUs,
+                throttled: stats.throttled,
+            });
+
+            pod.reportedBytes = stats.bytesSent;
+            pod.reportTime = now;
+            // the max queueing delay is of the report interval
+            pod.stats.queueDelayMaxUs = 0;
+        }
+
+        reports.sort_by(|a, b| a.pod.cmp(&b.pod));
+        return reports;
+    }
+
+    pub fn WriteStats(&mut self, now: u64) {
+        let path = match &self.statsPath {
+            None => return,
+            Some(path) => path.clone(),
+        };
+
+        let reports = self.Reports(now);
+        let content = match serde_json::to_string_pretty(&reports) {
+            Ok(content) => content,
+            Err(e) => {
+                error!("fail to serialize QoS stats: {:?}", e);
+                return;
+            }
+        };
+        if let Err(e) = WriteFileAtomic(&path, &content) {
+            error!("fail to write QoS stats {:?}: {:?}", path, e);
+        }
+    }
+}
+
+// FairQueue holds the write requests of the channels waiting for the receive
+// requests of the peer node. The pods are served in weighted round robin so
+// that a bulk transfer pod can't starve the others.
+#[derive(Default)]
+pub struct FairQueue {
+    // agent id --> (channel id, enqueue time in us)
+    pub queues: HashMap<u32, VecDeque<(u32, u64)>>,
+    // the pods with waiting requests, in round robin order
+    pub active: VecDeque<u32>,
+    // requests the front pod got in its current turn
+    pub served: u32,
+    pub len: usize,
+}
+
+impl FairQueue {
+    pub fn Push(&mut self, agentId: u32, channelId: u32, now: u64) {
+        let queue = self.queues.entry(agentId).or_insert(VecDeque::new());
+        if queue.len() == 0 {
+            self.active.push_back(agentId);
+        }
+        queue.push_back((channelId, now));
+        self.len += 1;
+    }
+
+    // Pop returns the next (agent id, channel id, enqueue time), weight gives
+    // the requests a pod gets per turn
+    pub fn Pop<F: Fn(u32) -> u32>(&mut self, weight: F) -> Option<(u32, u32, u64)> {
+        let agentId = *self.active.front()?;
+        let queue = self.queues.get_mut(&agentId).unwrap();
+        let (channelId, enqueueTime) = queue.pop_front().unwrap();
+        self.len -= 1;
+        self.served += 1;
+        if queue.len() == 0 {
+            self.queues.remove(&agentId);
+            self.active.pop_front();
+            self.served = 0;
+        } else if self.served >= weight(agentId) {
+            self.active.rotate_left(1);
+            self.served = 0;
+        }
+
+        return Some((agentId, channelId, enqueueTime));
+    }
+
+    pub fn len(&self) -> usize {
+        return self.len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn Drain(queue: &mut FairQueue, weights: &HashMap<u32, u32>) -> Vec<(u32, u32)> {
+        let mut order = Vec::new();
+        while let Some((agentId, channelId, _)) = queue.Pop(|agentId| weights[&agentId]) {
+            order.push((agentId, channelId));
+        }
+        return order;
+    }
+
+    #[test]
+    fn test_FairQueuePop() {
+        let mut queue = FairQueue::default();
+        for channelId in 0..6 {
+            queue.Push(1, channelId, 10);
+        }
+        for channelId in 10..13 {
+            queue.Push(2, channelId, 20);
+        }
+        assert_eq!(queue.len(), 9);
+
+        // agent 1 gets 2 requests per turn, agent 2 gets 1
+        let weights: HashMap<u32, u32> = [(1, 2), (2, 1)].iter().cloned().collect();
+        let order = Drain(&mut queue, &weights);
+        assert_eq!(
+            order,
+            vec![
+                (1, 0),
+                (1, 1),
+                (2, 10),
+                (1, 2),
+                (1, 3),
+                (2, 11),
+                (1, 4),
+                (1, 5),
+                (2, 12),
+            ]
+        );
+        assert_eq!(queue.len(), 0);
+        assert_eq!(queue.queues.len(), 0);
+        assert_eq!(queue.active.len(), 0);
+    }
+
+    #[test]
+    fn test_FairQueuePopEmptied() {
+        let mut queue = FairQueue::default();
+        queue.Push(1, 0, 10);
+        queue.Push(2, 10, 20);
+        queue.Push(2, 11, 30);
+        let weights: HashMap<u32, u32> = [(1, 4), (2, 4)].iter().cloned().collect();
+
+        // the emptied pod gives the turn to the next one at once
+        assert_eq!(queue.Pop(|a| weights[&a]), Some((1, 0, 10)));
+        assert_eq!(queue.served, 0);
+        // a pod pushed again goes to the back of the round
+        queue.Push(1, 1, 40);
+        assert_eq!(Drain(&mut queue, &weights), vec![(2, 10), (2, 11), (1, 1)]);
+    }
+
+    #[test]
+    fn test_Refill() {
+        let mut mgr = QoSMgr::New();
+        // 1000 bytes per second, the burst is 100 bytes
+        let qos = PodQoS {
+            weight: QOS_WEIGHT_NORMAL,
+            rate: 1000,
+        };
+        mgr.SetPodQoS(1, "default/a", qos, 0);
+        mgr.SetPodQoS(2, "default/b", PodQoS::default(), 0);
+        assert_eq!(mgr.pods[&1].tokens, 100);
+
+        assert_eq!(mgr.Allowance(1, 7, 150), 100);
+        mgr.Charge(1, 100);
+        assert_eq!(mgr.Allowance(1, 7, 150), 0);
+        assert_eq!(mgr.pods[&1].stats.throttled, 1);
+        // the unlimited pod is not throttled
+        assert_eq!(mgr.Allowance(2, 8, 1 << 20), 1 << 20);
+
+        // less than a byte of tokens, the elapsed time is kept
+        assert_eq!(mgr.Refill(500), Vec::<u32>::new());
+        assert_eq!(mgr.pods[&1].tokens, 0);
+        assert_eq!(mgr.Refill(10_500), vec![7]);
+        assert_eq!(mgr.pods[&1].tokens, 10);
+        assert_eq!(mgr.pods[&1].lastRefill, 10_000);
+
+        // the bucket is capped at the burst
+        assert_eq!(mgr.Refill(10_000_000), Vec::<u32>::new());
+        assert_eq!(mgr.pods[&1].tokens, 100);
+    }
+
+    #[test]
+    fn test_FromAnnotations() {
+        let annotations = |priority: &str, bandwidth: &str| {
+            let mut annotations = HashMap::new();
+            annotations.insert(QOS_PRIORITY_ANNOTATION.to_string(), priority.to_string());
+            annotations.insert(QOS_BANDWIDTH_ANNOTATION.to_string(), bandwidth.to_string());
+            return annotations;
+        };
+
+        let qos = PodQoS::FromAnnotations(&annotations("high", "100M"));
+        assert_eq!(
+            qos,
+            PodQoS {
+                weight: QOS_WEIGHT_HIGH,
+                rate: 12_500_000,
+            }
+        );
+        let qos = PodQoS::FromAnnotations(&annotations("100", "1.5Mi"));
+        assert_eq!(
+            qos,
+            PodQoS {
+                weight: QOS_WEIGHT_MAX,
+                rate: 196_608,
+            }
+        );
+        // a cap below a byte per second is still a cap
+        assert_eq!(PodQoS::FromAnnotations(&annotations("low", "1")).rate, 1);
+        // the invalid annotations keep the defaults
+        assert_eq!(
+            PodQoS::FromAnnotations(&annotations("urgent", "10MB")),
+            PodQoS::default()
+        );
+    }
+}
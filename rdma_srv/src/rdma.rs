@@ -6,13 +6,17 @@ use spin::Mutex;
 use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::mem::MaybeUninit;
+use std::net::Ipv4Addr;
 use std::ptr;
 
 use super::qlib::common::*;
 use super::qlib::linux_def::*;
 use super::rdma_srv::RDMA_SRV;
+use super::soft_rdma::*;
+use super::stats::SrvCounters;
 //use super::super::super::IO_MGR;
 
+use enum_dispatch::enum_dispatch;
 use lazy_static::lazy_static;
 
 lazy_static! {
@@ -39,6 +43,18 @@ impl Gid {
         u64::from_be_bytes(self.raw[..8].try_into().unwrap())
     }
 
+    /// The gid of a software RDMA device is the IPv4-mapped IPv6 address of
+    /// its endpoint.
+    pub fn FromIpv4(addr: Ipv4Addr) -> Self {
+        return Self {
+            raw: addr.to_ipv6_mapped().octets(),
+        };
+    }
+
+    pub fn Ipv4(&self) -> Ipv4Addr {
+        return Ipv4Addr::new(self.raw[12], self.raw[13], self.raw[14], self.raw[15]);
+    }
+
     /// Expose the interface_id component of the `Gid` as a u64. This is
     /// equivalent to accessing the `global.interface_id` component of the
     /// `rdmaffi::ibv_gid` union.
@@ -303,8 +319,9 @@ impl Default for CompleteQueue {
     }
 }
 
+// IBDevice is the verbs device backed by an RDMA NIC through libibverbs.
 #[derive(Default)]
-pub struct RDMAContextIntern {
+pub struct IBDevice {
     //device_attr: rdmaffi::ibv_device_attr,
     /* Device attributes */
     portAttr: PortAttr,               /* IB port attributes */
@@ -317,7 +334,10 @@ pub struct RDMAContextIntern {
     gid: Gid,
 }
 
-impl RDMAContextIntern {
+unsafe impl Send for IBDevice {}
+unsafe impl Sync for IBDevice {}
+
+impl IBDevice {
     pub fn New(deviceName: &str, ibPort: u8) -> Self {
         let ibContext = IBContext::New(deviceName);
         let portAttr = ibContext.QueryPort(ibPort);
@@ -357,41 +377,56 @@ impl RDMAContextIntern {
     }
 }
 
-#[derive(Default)]
-pub struct RDMAContext(Mutex<RDMAContextIntern>);
-
-unsafe impl Send for RDMAContext {}
-unsafe impl Sync for RDMAContext {}
-
-impl Deref for RDMAContext {
-    type Target = Mutex<RDMAContextIntern>;
-
-    fn deref(&self) -> &Mutex<RDMAContextIntern> {
-        &self.0
-    }
-}
-
 pub const MAX_SEND_WR: u32 = 100;
 pub const MAX_RECV_WR: u32 = 8192;
 pub const MAX_SEND_SGE: u32 = 1;
 pub const MAX_RECV_SGE: u32 = 1;
 
-impl RDMAContext {
-    pub fn Init(&self, deviceName: &str, ibPort: u8) {
-        *self.0.lock() = RDMAContextIntern::New(deviceName, ibPort);
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum QueuePairType {
+    RC,
+    UD,
+}
+
+// RDMAVerbs is the device part of the verbs used by RDMAConn, RDMAChannel and
+// RDMAControlChannel. All the queue pairs of a device share one completion
+// queue, whose events are signaled on CompleteChannelFd.
+#[enum_dispatch]
+pub trait RDMAVerbs {
+    fn Lid(&self) -> u16;
+    fn Gid(&self) -> Gid;
+    fn CreateAddressHandler(&self, port_num: u8, lid: u16, gid: Gid) -> Result<AddressHandler>;
+    fn CreateQueuePair(&self, qpType: QueuePairType) -> Result<QueuePair>;
+    fn CreateMemoryRegion(&self, addr: u64, size: usize) -> Result<MemoryRegion>;
+    fn CompleteChannelFd(&self) -> i32;
+    // PollCQ takes the next work completion, returns false if there is none
+    fn PollCQ(&self, wc: &mut rdmaffi::ibv_wc) -> bool;
+    // HandleCQEvent consumes the completion event and rearms the notification
+    fn HandleCQEvent(&self) -> Result<()>;
+}
+
+#[enum_dispatch(RDMAVerbs)]
+pub enum RDMADevice {
+    IBDevice,
+    SoftDevice,
+}
+
+impl Default for RDMADevice {
+    fn default() -> Self {
+        return RDMADevice::IBDevice(IBDevice::default());
     }
+}
 
-    pub fn Lid(&self) -> u16 {
-        let context = self.lock();
-        return context.portAttr.0.lid;
+impl RDMAVerbs for IBDevice {
+    fn Lid(&self) -> u16 {
+        return self.portAttr.0.lid;
     }
 
-    pub fn Gid(&self) -> Gid {
-        let context = self.lock();
-        return context.gid;
+    fn Gid(&self) -> Gid {
+        return self.gid;
     }
 
-    pub fn CreateAddressHandler(&self, port_num: u8, lid: u16, gid: Gid) -> Result<AddressHandler> {
+    fn CreateAddressHandler(&self, port_num: u8, lid: u16, gid: Gid) -> Result<AddressHandler> {
         let mut ah_attr = rdmaffi::ibv_ah_attr {
             grh: rdmaffi::ibv_global_route {
                 dgid: rdmaffi::ibv_gid::from(gid),
@@ -413,26 +448,31 @@ impl RDMAContext {
         // ah_attr.grh.hop_limit = 5;
         // ah_attr.is_global = 1;
         // ah_attr.port_num = port_num;
-        let context = self.lock();
-        let ah = unsafe { rdmaffi::ibv_create_ah(context.protectDomain.0, &mut ah_attr as *mut _) };
+        let ah = unsafe { rdmaffi::ibv_create_ah(self.protectDomain.0, &mut ah_attr as *mut _) };
         if ah.is_null() {
             error!("CreateAddressHandler, errorno: {}", errno::errno().0);
             return Err(Error::SysError(errno::errno().0));
         }
 
-        return Ok(AddressHandler(Mutex::new(ah)));
+        return Ok(AddressHandler::IBAddressHandler(IBAddressHandler(
+            Mutex::new(ah),
+        )));
     }
 
     // Create Queue Pair
-    pub fn CreateQueuePair(&self, qp_type: u32) -> Result<QueuePair> {
+    fn CreateQueuePair(&self, qpType: QueuePairType) -> Result<QueuePair> {
         // println!("CreateQueuePair 1");
-        let context = self.lock();
+        let qp_type = match qpType {
+            QueuePairType::RC => rdmaffi::ibv_qp_type::IBV_QPT_RC,
+            QueuePairType::UD => rdmaffi::ibv_qp_type::IBV_QPT_UD,
+        };
+
         //create queue pair
         let mut qp_init_attr = rdmaffi::ibv_qp_init_attr {
             // TODO: offset(0), may need find some different value
             qp_context: 0 as *mut _,
-            send_cq: context.completeQueue.0 as *const _ as *mut _,
-            recv_cq: context.completeQueue.0 as *const _ as *mut _,
+            send_cq: self.completeQueue.0 as *const _ as *mut _,
+            recv_cq: self.completeQueue.0 as *const _ as *mut _,
             srq: ptr::null::<rdmaffi::ibv_srq>() as *mut _,
             cap: rdmaffi::ibv_qp_cap {
                 max_send_wr: 8192, //MAX_SEND_WR,
@@ -446,130 +486,50 @@ impl RDMAContext {
         };
 
         let qp =
-            unsafe { rdmaffi::ibv_create_qp(context.protectDomain.0, &mut qp_init_attr as *mut _) };
+            unsafe { rdmaffi::ibv_create_qp(self.protectDomain.0, &mut qp_init_attr as *mut _) };
         if qp.is_null() {
             // println!("errorno: {}", errno::errno().0);
             return Err(Error::SysError(errno::errno().0));
         }
 
-        return Ok(QueuePair(Mutex::new(qp)));
-    }
-
-    pub fn CreateRCQueuePair(&self) -> Result<QueuePair> {
-        self.CreateQueuePair(rdmaffi::ibv_qp_type::IBV_QPT_RC)
+        return Ok(QueuePair::IBQueuePair(IBQueuePair {
+            qp: Mutex::new(qp),
+            ibPort: self.ibPort,
+        }));
     }
 
-    pub fn CreateUDQueuePair(&self) -> Result<QueuePair> {
-        self.CreateQueuePair(rdmaffi::ibv_qp_type::IBV_QPT_UD)
-    }
-
-    pub fn CreateMemoryRegion(&self, addr: u64, size: usize) -> Result<MemoryRegion> {
-        let context = self.lock();
+    fn CreateMemoryRegion(&self, addr: u64, size: usize) -> Result<MemoryRegion> {
         let access = rdmaffi::ibv_access_flags::IBV_ACCESS_LOCAL_WRITE
             | rdmaffi::ibv_access_flags::IBV_ACCESS_REMOTE_WRITE
             | rdmaffi::ibv_access_flags::IBV_ACCESS_REMOTE_READ
             | rdmaffi::ibv_access_flags::IBV_ACCESS_REMOTE_ATOMIC;
 
         let mr = unsafe {
-            rdmaffi::ibv_reg_mr(
-                context.protectDomain.0,
-                addr as *mut _,
-                size,
-                access.0 as i32,
-            )
+            rdmaffi::ibv_reg_mr(self.protectDomain.0, addr as *mut _, size, access.0 as i32)
         };
 
         if mr.is_null() {
             return Err(Error::SysError(errno::errno().0));
         }
 
-        return Ok(MemoryRegion(mr));
+        return Ok(MemoryRegion::IBMemoryRegion(IBMemoryRegion(mr)));
     }
 
-    pub fn CompleteQueue(&self) -> *mut rdmaffi::ibv_cq {
-        return self.lock().completeQueue.0;
+    fn CompleteChannelFd(&self) -> i32 {
+        return self.ccfd;
     }
 
-    pub fn CompleteChannel(&self) -> *mut rdmaffi::ibv_comp_channel {
-        return self.lock().completeChannel.0;
-    }
-
-    pub fn CompleteChannelFd(&self) -> i32 {
-        let fd = self.lock().ccfd;
-        // println!("XXXX, fd: {} ", fd);
-        return fd;
-    }
-
-    pub fn PollCompletionQueueAndProcess(
-        &self,
-        channels: &mut HashMap<u32, HashSet<u32>>,
-    ) -> usize {
-        // println!("PollCompletionQueueAndProcess");
-        let mut wc = rdmaffi::ibv_wc {
-            //TODO: find a better way to initialize
-            wr_id: 0,
-            status: rdmaffi::ibv_wc_status::IBV_WC_SUCCESS,
-            opcode: rdmaffi::ibv_wc_opcode::IBV_WC_BIND_MW,
-            vendor_err: 0,
-            byte_len: 0,
-            imm_data_invalidated_rkey_union: rdmaffi::imm_data_invalidated_rkey_union_t {
-                imm_data: 0,
-            }, //TODO: need double check
-            qp_num: 0,
-            src_qp: 0,
-            wc_flags: 0,
-            pkey_index: 0,
-            slid: 0,
-            sl: 0,
-            dlid_path_bits: 0,
-        };
-
-        let mut count = 0;
-
-        loop {
-            // let poll_result = unsafe { rdmaffi::ibv_poll_cq(self.CompleteQueue(), 2, &mut wc) };
-            // let wc_ptr: *const rdmaffi::ibv_wc = &wc;
-            // if poll_result == 2 {
-            //     count += 2;
-            //     //self.ProcessWC(&wc);
-            //     self.ProcessWC(unsafe {&(*wc_ptr.offset(0))});
-            //     self.ProcessWC(unsafe {&(*wc_ptr.offset(1))});
-            // } else if poll_result == 1 {
-            //     // if count > 0 {
-            //     //     error!("PollCompletionQueueAndProcess: processed wcs: {}", count);
-            //     // }
-            //     count += 1;
-            //     self.ProcessWC(unsafe {&(*wc_ptr.offset(0))});
-            //     return count;
-            // } else if poll_result == 0 {
-            //     return count;
-            // } else {
-            //     // debug!("Error to query CQ!")
-            //     // break;
-            // }
-
-            let poll_result = unsafe { rdmaffi::ibv_poll_cq(self.CompleteQueue(), 1, &mut wc) };
-            if poll_result == 1 {
-                count += 1;
-                self.ProcessWC(&wc, channels);
-            } else if poll_result == 0 {
-                // if count > 0 {
-                //     error!("PollCompletionQueueAndProcess: processed wcs: {}", count);
-                // }
-                return count;
-            } else {
-                // debug!("Error to query CQ!")
-                // break;
-            }
-        }
+    fn PollCQ(&self, wc: &mut rdmaffi::ibv_wc) -> bool {
+        let poll_result = unsafe { rdmaffi::ibv_poll_cq(self.completeQueue.0, 1, wc) };
+        return poll_result == 1;
     }
 
-    pub fn HandleCQEvent(&self) -> Result<()> {
+    fn HandleCQEvent(&self) -> Result<()> {
         let mut cq_ptr: *mut rdmaffi::ibv_cq = ptr::null_mut();
         let mut cq_context: *mut std::os::raw::c_void = ptr::null_mut();
         let ret = unsafe {
             rdmaffi::ibv_get_cq_event(
-                self.CompleteChannel(),
+                self.completeChannel.0,
                 &mut cq_ptr, //&mut self.CompleteQueue(),
                 &mut cq_context,
             )
@@ -580,7 +540,7 @@ impl RDMAContext {
             return Ok(());
         }
 
-        let ret1 = unsafe { rdmaffi::ibv_req_notify_cq(self.CompleteQueue(), 0) };
+        let ret1 = unsafe { rdmaffi::ibv_req_notify_cq(self.completeQueue.0, 0) };
         if ret1 != 0 {
             // TODO: should keep call here?
         }
@@ -588,107 +548,96 @@ impl RDMAContext {
         unsafe { rdmaffi::ibv_ack_cq_events(cq_ptr, 1) };
         Ok(())
     }
+}
 
-    pub fn PollCompletion(&self) -> Result<()> {
-        let mut wc = rdmaffi::ibv_wc {
-            //TODO: find a better way to initialize
-            wr_id: 0,
-            status: rdmaffi::ibv_wc_status::IBV_WC_SUCCESS,
-            opcode: rdmaffi::ibv_wc_opcode::IBV_WC_BIND_MW,
-            vendor_err: 0,
-            byte_len: 0,
-            imm_data_invalidated_rkey_union: rdmaffi::imm_data_invalidated_rkey_union_t {
-                imm_data: 0,
-            }, //TODO: need double check
-            qp_num: 0,
-            src_qp: 0,
-            wc_flags: 0,
-            pkey_index: 0,
-            slid: 0,
-            sl: 0,
-            dlid_path_bits: 0,
-        };
+// EmptyWorkCompletion returns a work completion to be filled by PollCQ
+pub fn EmptyWorkCompletion() -> rdmaffi::ibv_wc {
+    return rdmaffi::ibv_wc {
+        //TODO: find a better way to initialize
+        wr_id: 0,
+        status: rdmaffi::ibv_wc_status::IBV_WC_SUCCESS,
+        opcode: rdmaffi::ibv_wc_opcode::IBV_WC_BIND_MW,
+        vendor_err: 0,
+        byte_len: 0,
+        imm_data_invalidated_rkey_union: rdmaffi::imm_data_invalidated_rkey_union_t { imm_data: 0 },
+        qp_num: 0,
+        src_qp: 0,
+        wc_flags: 0,
+        pkey_index: 0,
+        slid: 0,
+        sl: 0,
+        dlid_path_bits: 0,
+    };
+}
 
-        let mut cq_ptr: *mut rdmaffi::ibv_cq = ptr::null_mut();
-        let mut cq_context: *mut std::os::raw::c_void = ptr::null_mut();
-        let ret = unsafe {
-            rdmaffi::ibv_get_cq_event(
-                self.CompleteChannel(),
-                &mut cq_ptr, //&mut self.CompleteQueue(),
-                &mut cq_context,
-            )
-        };
+// RDMAContext is the verbs device used by the RDMA service, the NIC one by
+// default, or the software one for the machines without an RDMA NIC.
+#[derive(Default)]
+pub struct RDMAContext(Mutex<RDMADevice>);
 
-        if ret != 0 {
-            // debug!("Failed to get next CQ event");
-        }
+impl RDMAContext {
+    pub fn Init(&self, deviceName: &str, ibPort: u8) {
+        *self.0.lock() = RDMADevice::IBDevice(IBDevice::New(deviceName, ibPort));
+    }
 
-        let ret1 = unsafe { rdmaffi::ibv_req_notify_cq(self.CompleteQueue(), 0) };
-        if ret1 != 0 {
-            // TODO: should keep call here?
-        }
+    pub fn InitSoft(&self, config: &SoftRDMAConfig) -> Result<()> {
+        *self.0.lock() = RDMADevice::SoftDevice(SoftDevice::New(config)?);
+        return Ok(());
+    }
 
-        loop {
-            let poll_result = unsafe { rdmaffi::ibv_poll_cq(self.CompleteQueue(), 1, &mut wc) };
-            if poll_result > 0 {
-                self.ProcessWC(&wc, &mut HashMap::new());
-            } else if poll_result == 0 {
-                break;
-            } else {
-                // debug!("Error to query CQ!")
-                // break;
-            }
-        }
+    pub fn Lid(&self) -> u16 {
+        return self.0.lock().Lid();
+    }
 
-        unsafe { rdmaffi::ibv_ack_cq_events(cq_ptr, 1) };
-        Ok(())
+    pub fn Gid(&self) -> Gid {
+        return self.0.lock().Gid();
+    }
 
-        // let ret1 = unsafe { rdmaffi::ibv_req_notify_cq(self.CompleteQueue(), 0) };
-        // if ret1 != 0 {
-        //     // TODO: should keep call here?
-        //     // debug!("Couldn't request CQ notification\n");
-        // }
+    pub fn CreateAddressHandler(&self, port_num: u8, lid: u16, gid: Gid) -> Result<AddressHandler> {
+        return self.0.lock().CreateAddressHandler(port_num, lid, gid);
+    }
 
-        // loop {
-        //     loop {
-        //         let poll_result = unsafe { rdmaffi::ibv_poll_cq(self.CompleteQueue(), 1, &mut wc) };
-        //         if poll_result > 0 {
-        //             self.ProcessWC(&wc);
-        //         } else if poll_result == 0 {
-        //             break;
-        //         } else {
-        //             // debug!("Error to query CQ!")
-        //             // break;
-        //         }
-        //     }
+    pub fn CreateRCQueuePair(&self) -> Result<QueuePair> {
+        return self.0.lock().CreateQueuePair(QueuePairType::RC);
+    }
 
-        //     let mut cq_ptr: *mut rdmaffi::ibv_cq = ptr::null_mut();
-        //     let mut cq_context: *mut std::os::raw::c_void = ptr::null_mut();
-        //     let ret = unsafe {
-        //         rdmaffi::ibv_get_cq_event(
-        //             self.CompleteChannel(),
-        //             &mut cq_ptr, //&mut self.CompleteQueue(),
-        //             &mut cq_context,
-        //         )
-        //     };
-
-        //     let mut ret1 = unsafe { rdmaffi::ibv_req_notify_cq(self.CompleteQueue(), 0) };
-        //     if ret1 != 0 {
-        //         // TODO: should keep call here?
-        //     }
+    pub fn CreateUDQueuePair(&self) -> Result<QueuePair> {
+        return self.0.lock().CreateQueuePair(QueuePairType::UD);
+    }
 
-        //     if ret == -1 {
-        //         return Ok(());
-        //     }
-        //     //TODO: potnetial improvemnt to ack in batch
-        //     unsafe { rdmaffi::ibv_ack_cq_events(cq_ptr, 1) };
-        //     ret1 = unsafe { rdmaffi::ibv_req_notify_cq(self.CompleteQueue(), 0) };
-        //     if ret1 != 0 {
-        //         // TODO: should keep call here?
-        //     }
+    pub fn CreateMemoryRegion(&self, addr: u64, size: usize) -> Result<MemoryRegion> {
+        return self.0.lock().CreateMemoryRegion(addr, size);
+    }
+
+    pub fn CompleteChannelFd(&self) -> i32 {
+        let fd = self.0.lock().CompleteChannelFd();
+        // println!("XXXX, fd: {} ", fd);
+        return fd;
+    }
+
+    pub fn PollCompletionQueueAndProcess(
+        &self,
+        channels: &mut HashMap<u32, HashSet<u32>>,
+    ) -> usize {
+        // println!("PollCompletionQueueAndProcess");
+        let mut wc = EmptyWorkCompletion();
+        let mut count = 0;
+
+        // the device lock is not held in ProcessWC, which posts new work requests
+        while self.0.lock().PollCQ(&mut wc) {
+            count += 1;
+            self.ProcessWC(&wc, channels);
+        }
+
+        // if count > 0 {
+        //     error!("PollCompletionQueueAndProcess: processed wcs: {}", count);
         // }
+        return count;
     }
 
+    pub fn HandleCQEvent(&self) -> Result<()> {
+        return self.0.lock().HandleCQEvent();
+    }
     // call back for
     pub fn ProcessWC(&self, wc: &rdmaffi::ibv_wc, channels: &mut HashMap<u32, HashSet<u32>>) {
         // println!("ProcessWC 1");
@@ -720,6 +669,10 @@ impl RDMAContext {
                 "ProcessWC::1, work reqeust failed with status: {}, id: {}",
                 wc.status, wc.wr_id
             );
+            SrvCounters::Inc(&RDMA_SRV.counters.completionErrors);
+            // the opcode is not valid in a failed work completion
+            RDMA_SRV.HandleWCError(wc.qp_num, wc.wr_id, wc.status as u32);
+            return;
         }
         if wc.opcode == rdmaffi::ibv_wc_opcode::IBV_WC_RDMA_WRITE {
             // debug!(
@@ -738,6 +691,7 @@ impl RDMAContext {
             //     }
             // }
 
+            SrvCounters::Inc(&RDMA_SRV.counters.writeCompletions);
             RDMA_SRV.ProcessRDMAWriteImmFinish(wc.wr_id as u32, wc.qp_num);
         } else if wc.opcode == rdmaffi::ibv_wc_opcode::IBV_WC_RECV_RDMA_WITH_IMM {
             let imm = unsafe { wc.imm_data_invalidated_rkey_union.imm_data };
@@ -761,12 +715,15 @@ impl RDMAContext {
                     channels.insert(wc.qp_num, vec![channelId].into_iter().collect());
                 }
             }
+            SrvCounters::Inc(&RDMA_SRV.counters.recvCompletions);
             RDMA_SRV.ProcessRDMARecvWriteImm(immData.ReadCount() as _, wc.qp_num, wc.byte_len as _);
         } else if wc.opcode == rdmaffi::ibv_wc_opcode::IBV_WC_RECV {
             // error!("ProcessWC::3");
+            SrvCounters::Inc(&RDMA_SRV.counters.udpRecvCompletions);
             RDMA_SRV.ProcessRDMARecv(wc.qp_num, wc.wr_id, wc.byte_len);
         }else if wc.opcode == rdmaffi::ibv_wc_opcode::IBV_WC_SEND {
             // error!("ProcessWC::4");
+            SrvCounters::Inc(&RDMA_SRV.counters.sendCompletions);
             RDMA_SRV.ProcessRDMASend(wc.wr_id);
         } else {
             // debug!("ProcessWC::5, opcode: {}, wr_id: {}", wc.opcode, wc.wr_id);
@@ -819,52 +776,101 @@ impl WorkRequestId {
     // }
 }
 
-pub struct AddressHandler(pub Mutex<*mut rdmaffi::ibv_ah>);
+pub enum AddressHandler {
+    IBAddressHandler(IBAddressHandler),
+    SoftAddressHandler(SoftAddressHandler),
+}
 
 impl Default for AddressHandler {
+    fn default() -> Self {
+        return AddressHandler::IBAddressHandler(IBAddressHandler::default());
+    }
+}
+
+pub struct IBAddressHandler(pub Mutex<*mut rdmaffi::ibv_ah>);
+
+impl Default for IBAddressHandler {
     fn default() -> Self {
         return Self(Mutex::new(0 as _));
     }
 }
 
-unsafe impl Send for AddressHandler {}
-unsafe impl Sync for AddressHandler {}
+unsafe impl Send for IBAddressHandler {}
+unsafe impl Sync for IBAddressHandler {}
 
-impl Drop for AddressHandler {
+impl Drop for IBAddressHandler {
     fn drop(&mut self) {}
 }
 
-impl AddressHandler {
+impl IBAddressHandler {
     pub fn Data(&self) -> *mut rdmaffi::ibv_ah {
         return *self.0.lock();
     }
 }
 
-pub struct QueuePair(pub Mutex<*mut rdmaffi::ibv_qp>);
+// QueuePairOps is the queue pair part of the verbs: the RC queue pairs carry
+// the RDMA write with immediate of the channels, the UD queue pair carries the
+// UDP packets.
+#[enum_dispatch]
+pub trait QueuePairOps {
+    fn qpNum(&self) -> u32;
+    fn WriteImm(
+        &self,
+        wrId: u64,
+        laddr: u64,
+        len: u32,
+        lkey: u32,
+        raddr: u64,
+        rkey: u32,
+        imm: u32,
+    ) -> Result<()>;
+    fn PostRecv(&self, wrId: u64, addr: u64, lkey: u32, length: u32) -> Result<()>;
+    fn PostSendUDQP(
+        &self,
+        ah: &AddressHandler,
+        remote_qpn: u32,
+        wrId: u64,
+        laddr: u64,
+        len: u32,
+        lkey: u32,
+    ) -> Result<()>;
+    fn SetupRCQP(&self, remote_qpn: u32, dlid: u16, dgid: Gid) -> Result<()>;
+    fn SetupUDQP(&self) -> Result<()>;
+}
+
+#[enum_dispatch(QueuePairOps)]
+pub enum QueuePair {
+    IBQueuePair,
+    SoftQueuePair,
+}
 
-impl Default for QueuePair {
+pub struct IBQueuePair {
+    pub qp: Mutex<*mut rdmaffi::ibv_qp>,
+    pub ibPort: u8,
+}
+
+impl Default for IBQueuePair {
     fn default() -> Self {
-        return Self(Mutex::new(0 as _));
+        return Self {
+            qp: Mutex::new(0 as _),
+            ibPort: 0,
+        };
     }
 }
 
-unsafe impl Send for QueuePair {}
-unsafe impl Sync for QueuePair {}
+unsafe impl Send for IBQueuePair {}
+unsafe impl Sync for IBQueuePair {}
 
-impl Drop for QueuePair {
+impl Drop for IBQueuePair {
     fn drop(&mut self) {}
 }
 
-impl QueuePair {
-    pub fn Data(&self) -> *mut rdmaffi::ibv_qp {
-        return *self.0.lock();
-    }
-
-    pub fn qpNum(&self) -> u32 {
+impl QueuePairOps for IBQueuePair {
+    fn qpNum(&self) -> u32 {
         return unsafe { (*self.Data()).qp_num };
     }
 
-    pub fn WriteImm(
+    fn WriteImm(
         &self,
         wrId: u64,
         laddr: u64,
@@ -920,7 +926,7 @@ impl QueuePair {
         return Ok(());
     }
 
-    pub fn PostRecv(&self, wrId: u64, addr: u64, lkey: u32, length: u32) -> Result<()> {
+    fn PostRecv(&self, wrId: u64, addr: u64, lkey: u32, length: u32) -> Result<()> {
         let mut sge = rdmaffi::ibv_sge { addr, length, lkey };
         let mut rw = rdmaffi::ibv_recv_wr {
             wr_id: wrId,
@@ -939,7 +945,7 @@ impl QueuePair {
         return Ok(());
     }
 
-    pub fn PostSendUDQP(
+    fn PostSendUDQP(
         &self,
         ah: &AddressHandler,
         remote_qpn: u32,
@@ -952,6 +958,10 @@ impl QueuePair {
         //     "PostSendUDQP, remote_qpn: {}, wrId: {}, laddr: 0x{:x}, len: {}, lkey: {}",
         //     remote_qpn, wrId, laddr, len, lkey
         // );
+        let ah = match ah {
+            AddressHandler::IBAddressHandler(ah) => ah.Data(),
+            _ => return Err(Error::SysError(SysErr::EINVAL)),
+        };
         let opcode = rdmaffi::ibv_wr_opcode::IBV_WR_SEND;
         let mut sge = rdmaffi::ibv_sge {
             addr: laddr,
@@ -975,7 +985,7 @@ impl QueuePair {
             },
             wr: rdmaffi::wr_t {
                 ud: rdmaffi::ud_t {
-                    ah: ah.Data(),
+                    ah: ah,
                     remote_qpn,
                     remote_qkey: 0x11111111,
                 },
@@ -999,27 +1009,27 @@ impl QueuePair {
         return Ok(());
     }
 
-    pub fn SetupRCQP(
-        &self,
-        context: &RDMAContext,
-        remote_qpn: u32,
-        dlid: u16,
-        dgid: Gid,
-    ) -> Result<()> {
-        self.ToInitRCQP(context)?;
-        self.ToRtrRCQP(context, remote_qpn, dlid, dgid)?;
+    fn SetupRCQP(&self, remote_qpn: u32, dlid: u16, dgid: Gid) -> Result<()> {
+        self.ToInitRCQP()?;
+        self.ToRtrRCQP(remote_qpn, dlid, dgid)?;
         self.ToRtsRCQP()?;
         return Ok(());
     }
 
-    pub fn SetupUDQP(&self, context: &RDMAContext) -> Result<()> {
-        self.ToInitUDQP(context)?;
-        self.ToRtrUDQP(context)?;
+    fn SetupUDQP(&self) -> Result<()> {
+        self.ToInitUDQP()?;
+        self.ToRtrUDQP()?;
         self.ToRtsUDQP()?;
         return Ok(());
     }
+}
 
-    pub fn ToInitRCQP(&self, context: &RDMAContext) -> Result<()> {
+impl IBQueuePair {
+    pub fn Data(&self) -> *mut rdmaffi::ibv_qp {
+        return *self.qp.lock();
+    }
+
+    pub fn ToInitRCQP(&self) -> Result<()> {
         let mut attr = rdmaffi::ibv_qp_attr {
             qp_state: rdmaffi::ibv_qp_state::IBV_QPS_INIT,
             cur_qp_state: rdmaffi::ibv_qp_state::IBV_QPS_INIT,
@@ -1084,7 +1094,7 @@ impl QueuePair {
         };
 
         attr.qp_state = rdmaffi::ibv_qp_state::IBV_QPS_INIT;
-        attr.port_num = context.lock().ibPort;
+        attr.port_num = self.ibPort;
         attr.pkey_index = 0;
         let qp_access_flags = rdmaffi::ibv_access_flags::IBV_ACCESS_LOCAL_WRITE
             | rdmaffi::ibv_access_flags::IBV_ACCESS_REMOTE_READ
@@ -1102,7 +1112,7 @@ impl QueuePair {
         return Ok(());
     }
 
-    pub fn ToInitUDQP(&self, context: &RDMAContext) -> Result<()> {
+    pub fn ToInitUDQP(&self) -> Result<()> {
         let mut attr = rdmaffi::ibv_qp_attr {
             qp_state: rdmaffi::ibv_qp_state::IBV_QPS_INIT,
             cur_qp_state: rdmaffi::ibv_qp_state::IBV_QPS_INIT,
@@ -1167,7 +1177,7 @@ impl QueuePair {
         };
 
         attr.qp_state = rdmaffi::ibv_qp_state::IBV_QPS_INIT;
-        attr.port_num = context.lock().ibPort;
+        attr.port_num = self.ibPort;
         attr.pkey_index = 0;
         // let qp_access_flags = rdmaffi::ibv_access_flags::IBV_ACCESS_LOCAL_WRITE
         //     | rdmaffi::ibv_access_flags::IBV_ACCESS_REMOTE_READ
@@ -1187,13 +1197,7 @@ impl QueuePair {
         return Ok(());
     }
 
-    pub fn ToRtrRCQP(
-        &self,
-        context: &RDMAContext,
-        remote_qpn: u32,
-        dlid: u16,
-        dgid: Gid,
-    ) -> Result<()> {
+    pub fn ToRtrRCQP(&self, remote_qpn: u32, dlid: u16, dgid: Gid) -> Result<()> {
         let mut attr = rdmaffi::ibv_qp_attr {
             qp_state: rdmaffi::ibv_qp_state::IBV_QPS_INIT,
             cur_qp_state: rdmaffi::ibv_qp_state::IBV_QPS_INIT,
@@ -1267,7 +1271,7 @@ impl QueuePair {
         attr.ah_attr.dlid = dlid;
         attr.ah_attr.sl = 0;
         attr.ah_attr.src_path_bits = 0;
-        attr.ah_attr.port_num = context.lock().ibPort;
+        attr.ah_attr.port_num = self.ibPort;
         let gid_idx = 0;
 
         // todo: configure with Qingqu
@@ -1298,7 +1302,7 @@ impl QueuePair {
         return Ok(());
     }
 
-    pub fn ToRtrUDQP(&self, context: &RDMAContext) -> Result<()> {
+    pub fn ToRtrUDQP(&self) -> Result<()> {
         let mut attr = rdmaffi::ibv_qp_attr {
             qp_state: rdmaffi::ibv_qp_state::IBV_QPS_INIT,
             cur_qp_state: rdmaffi::ibv_qp_state::IBV_QPS_INIT,
@@ -1372,7 +1376,7 @@ impl QueuePair {
         attr.ah_attr.dlid = 0;
         attr.ah_attr.sl = 0;
         attr.ah_attr.src_path_bits = 0;
-        attr.ah_attr.port_num = context.lock().ibPort;
+        attr.ah_attr.port_num = self.ibPort;
         // let gid_idx = 0;
 
         // todo: configure with Qingqu
@@ -1571,8 +1575,26 @@ impl QueuePair {
     }
 }
 
-pub struct MemoryRegion(pub *mut rdmaffi::ibv_mr);
-impl Drop for MemoryRegion {
+#[enum_dispatch]
+pub trait MemoryRegionOps {
+    fn LKey(&self) -> u32;
+    fn RKey(&self) -> u32;
+}
+
+#[enum_dispatch(MemoryRegionOps)]
+pub enum MemoryRegion {
+    IBMemoryRegion,
+    SoftMemoryRegion,
+}
+
+impl Default for MemoryRegion {
+    fn default() -> Self {
+        return MemoryRegion::IBMemoryRegion(IBMemoryRegion::default());
+    }
+}
+
+pub struct IBMemoryRegion(pub *mut rdmaffi::ibv_mr);
+impl Drop for IBMemoryRegion {
     fn drop(&mut self) {
         unsafe {
             let _ret = rdmaffi::ibv_dereg_mr(self.0);
@@ -1580,21 +1602,21 @@ impl Drop for MemoryRegion {
     }
 }
 
-impl Default for MemoryRegion {
+impl Default for IBMemoryRegion {
     fn default() -> Self {
         return Self(0 as _);
     }
 }
 
-impl MemoryRegion {
-    pub fn LKey(&self) -> u32 {
+impl MemoryRegionOps for IBMemoryRegion {
+    fn LKey(&self) -> u32 {
         return unsafe { (*self.0).lkey };
     }
 
-    pub fn RKey(&self) -> u32 {
+    fn RKey(&self) -> u32 {
         return unsafe { (*self.0).rkey };
     }
 }
 
-unsafe impl Send for MemoryRegion {}
-unsafe impl Sync for MemoryRegion {}
+unsafe impl Send for IBMemoryRegion {}
+unsafe impl Sync for IBMemoryRegion {}
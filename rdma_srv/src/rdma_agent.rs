@@ -18,10 +18,12 @@ use spin::Mutex;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::ffi::CString;
+use std::net::Ipv6Addr;
 use std::ops::{Deref, DerefMut};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::{env, mem, ptr, thread, time};
 
+use super::constants::*;
 use super::id_mgr::IdMgr;
 use super::qlib::common::*;
 use super::qlib::linux_def::*;
@@ -398,6 +400,19 @@ impl RDMAAgent {
                 // error!("RDMAReqMsg::RDMAListenUsingPodId, podId: {:?}, port: {}", podId, msg.port);
             }
             RDMAReqMsg::RDMAConnect(msg) => {
+                // the source of the gateway connections is out of the cluster,
+                // only the ingress policies of the destination pod apply.
+                // The ips are converted as HandleConnectRequest does.
+                if !RDMA_CTLINFO.NetworkPolicyAllows(
+                    msg.srcIpAddr.to_be(),
+                    msg.dstIpAddr.to_be(),
+                    PROTOCOL_TCP,
+                    u16::from_be(msg.dstPort),
+                ) {
+                    self.SendConnectFail(msg.sockfd, SysErr::ECONNREFUSED);
+                    return;
+                }
+
                 //TODOCtrlPlane: need get nodeIp from dstIpAddr
                 match RDMA_CTLINFO.get_node_ip_by_pod_ip(&msg.dstIpAddr) {
                     Some(nodeIpAddr) => {
@@ -440,16 +455,19 @@ impl RDMAAgent {
                 let mut dstPort = msg.dstPort;
                 if RDMA_CTLINFO.IsEgress(dstIpAddr) {
                     self.SendControlMsgInternal(
-                        msg.sockfd,
                         RDMA_CTLINFO.localIp_get(),
-                        ipAddr.to_be(),
-                        msg.srcPort,
-                        dstIpAddr,
-                        dstPort,
+                        RDMAConnectReq {
+                            sockfd: msg.sockfd,
+                            dstIpAddr: dstIpAddr,
+                            dstPort: dstPort,
+                            srcIpAddr: ipAddr.to_be(),
+                            srcPort: msg.srcPort,
+                            ..Default::default()
+                        },
                     );
                 } else {
                     // error!("RDMAConnectUsingPodId: Connect to ip {} port {}", dstIpAddr, dstPort);
-                    match RDMA_CTLINFO.IsService(dstIpAddr, &dstPort) {
+                    match RDMA_CTLINFO.IsService(dstIpAddr, &dstPort, ipAddr) {
                         None => {}
                         Some(ipWithPort) => {
                             println!("The traffic is connecting to a service. Change the connection to {:?}", ipWithPort);
@@ -457,15 +475,35 @@ impl RDMAAgent {
                             dstPort = ipWithPort.port.port;
                         }
                     }
+                    // the peer rdma_srv checks the same in HandleConnectRequest,
+                    // denying here saves the round trip
+                    if !RDMA_CTLINFO.NetworkPolicyAllows(
+                        ipAddr,
+                        dstIpAddr.to_be(),
+                        PROTOCOL_TCP,
+                        u16::from_be(dstPort),
+                    ) {
+                        println!(
+                            "Connect from {} to {}:{} is denied by network policy",
+                            ipAddr,
+                            dstIpAddr.to_be(),
+                            u16::from_be(dstPort)
+                        );
+                        self.SendConnectFail(msg.sockfd, SysErr::ECONNREFUSED);
+                        return;
+                    }
                     match RDMA_CTLINFO.get_node_ip_by_pod_ip(&dstIpAddr) {
                         Some(nodeIpAddr) => {
                             self.SendControlMsgInternal(
-                                msg.sockfd,
                                 nodeIpAddr,
-                                ipAddr.to_be(),
-                                msg.srcPort,
-                                dstIpAddr,
-                                dstPort,
+                                RDMAConnectReq {
+                                    sockfd: msg.sockfd,
+                                    dstIpAddr: dstIpAddr,
+                                    dstPort: dstPort,
+                                    srcIpAddr: ipAddr.to_be(),
+                                    srcPort: msg.srcPort,
+                                    ..Default::default()
+                                },
                             );
                         }
                         None => {
@@ -474,6 +512,72 @@ impl RDMAAgent {
                     }
                 }
             }
+            RDMAReqMsg::RDMAConnectUsingPodIdV6(msg) => {
+                let podId = String::from_utf8(msg.podId.to_vec()).unwrap();
+                let srcIp = match RDMA_CTLINFO.containeridsV6.lock().get(&podId) {
+                    Some(ip) => *ip,
+                    None => {
+                        error!("RDMAConnectUsingPodIdV6, pod {} has no ipv6 address", podId);
+                        self.SendConnectFail(msg.sockfd, SysErr::EADDRNOTAVAIL);
+                        return;
+                    }
+                };
+
+                let mut dstIp = Ipv6Addr::from(msg.dstIpAddr);
+                let mut dstPort = msg.dstPort;
+                if RDMA_CTLINFO.IsEgressV6(&dstIp) {
+                    // the egress gateway only takes ipv4 connections
+                    error!("RDMAConnectUsingPodIdV6, no ipv6 egress to {}", dstIp);
+                    self.SendConnectFail(msg.sockfd, SysErr::ENETUNREACH);
+                    return;
+                }
+
+                if let Some(ipWithPort) = RDMA_CTLINFO.IsServiceV6(&dstIp, &dstPort, srcIp) {
+                    println!(
+                        "The traffic is connecting to a service. Change the connection to {:?}",
+                        ipWithPort
+                    );
+                    // the endpoints of the ipv6 cluster ip are ipv6
+                    dstIp = ipWithPort.ipv6.unwrap();
+                    dstPort = ipWithPort.port.port;
+                }
+
+                if !RDMA_CTLINFO.NetworkPolicyAllowsV6(
+                    srcIp,
+                    dstIp,
+                    PROTOCOL_TCP,
+                    u16::from_be(dstPort),
+                ) {
+                    println!(
+                        "Connect from {} to [{}]:{} is denied by network policy",
+                        srcIp,
+                        dstIp,
+                        u16::from_be(dstPort)
+                    );
+                    self.SendConnectFail(msg.sockfd, SysErr::ECONNREFUSED);
+                    return;
+                }
+
+                match RDMA_CTLINFO.get_node_ip_by_pod_ipv6(&dstIp) {
+                    Some(nodeIpAddr) => {
+                        self.SendControlMsgInternal(
+                            nodeIpAddr,
+                            RDMAConnectReq {
+                                sockfd: msg.sockfd,
+                                dstPort: dstPort,
+                                srcPort: msg.srcPort,
+                                dstIpv6Addr: dstIp.octets(),
+                                srcIpv6Addr: srcIp.octets(),
+                                ..Default::default()
+                            },
+                        );
+                    }
+                    None => {
+                        error!("RDMAConnectUsingPodIdV6, no node has the pod ip {}", dstIp);
+                        self.SendConnectFail(msg.sockfd, SysErr::EHOSTUNREACH);
+                    }
+                }
+            }
             RDMAReqMsg::RDMAWrite(msg) => match RDMA_SRV.channels.lock().get(&msg.channelId) {
                 Some(rdmaChannel) => {
                     rdmaChannel.RDMASend();
@@ -578,35 +682,26 @@ impl RDMAAgent {
         }
     }
 
-    fn SendControlMsgInternal(
-        &self,
-        sockfd: u32,
-        nodeIpAddr: u32,
-        srcIpAddr: u32,
-        srcPort: u16,
-        dstIpAddr: u32,
-        dstPort: u16,
-    ) {
+    // SendConnectFail fails the connect of the guest socket
+    pub fn SendConnectFail(&self, sockfd: u32, error: i32) {
+        self.SendResponse(RDMAResp {
+            user_data: 0,
+            msg: RDMARespMsg::RDMAConnectFail(RDMAConnectFailResp { sockfd, error }),
+        });
+    }
+
+    fn SendControlMsgInternal(&self, nodeIpAddr: u32, connectReq: RDMAConnectReq) {
         let conns = RDMA_SRV.conns.lock();
         let rdmaConn = conns.get(&nodeIpAddr).unwrap();
 
-        let rdmaChannel = self.CreateClientRDMAChannel(
-            &RDMAConnectReq {
-                sockfd: sockfd,
-                dstIpAddr: dstIpAddr,
-                dstPort: dstPort,
-                srcIpAddr: srcIpAddr,
-                srcPort: srcPort,
-            },
-            rdmaConn.clone(),
-        );
+        let rdmaChannel = self.CreateClientRDMAChannel(&connectReq, rdmaConn.clone());
 
         RDMA_SRV
             .channels
             .lock()
             .insert(rdmaChannel.localId, rdmaChannel.clone());
 
-        let connectReqeust = rdmaChannel.CreateConnectRequest(sockfd);
+        let connectReqeust = rdmaChannel.CreateConnectRequest(connectReq.sockfd);
         rdmaConn
             .ctrlChan
             .lock()
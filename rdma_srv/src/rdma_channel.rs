@@ -17,6 +17,7 @@ use alloc::sync::Weak;
 use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use spin::{Mutex, MutexGuard};
 use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::ops::{Deref, DerefMut};
 
 use super::rdma_agent::*;
@@ -89,6 +90,9 @@ pub struct RDMAChannelIntern {
     pub dstIpAddr: u32,
     pub srcPort: u16,
     pub dstPort: u16,
+    // the addresses of an ipv6 channel, all zero for ipv4
+    pub srcIpv6Addr: [u8; 16],
+    pub dstIpv6Addr: [u8; 16],
     pub status: Mutex<ChannelStatus>,
     pub duplexMode: Mutex<DuplexMode>,
     pub ioBufIndex: u32,
@@ -382,6 +386,17 @@ impl RDMAChannelIntern {
         }
     }
 
+    // Reset closes the channel when its RDMA connection is broken, the channel
+    // is released once the client closes it.
+    pub fn Reset(&self) {
+        *self.status.lock() = ChannelStatus::CLOSED;
+        *self.finReceived.lock() = true;
+        self.agent.SendResponse(ResetResponse(self.localId));
+        if *self.closeRequestedByClient.lock() {
+            self.ReleaseChannelResource();
+        }
+    }
+
     fn ReleaseChannelResource(&self) {
         RDMA_SRV.channels.lock().remove(&self.localId);
     }
@@ -440,8 +455,16 @@ impl RDMAChannelIntern {
             let mut immData = remoteInfo.remoteId;
             let mut wrId = self.localId;
             let mut len = totalLen;
-            if len > remoteInfo.freespace as usize {
-                len = remoteInfo.freespace as usize;
+            // the bandwidth cap of the pod, the control channel is not capped
+            let mut limit = remoteInfo.freespace as usize;
+            if self.localId != 0 {
+                limit = RDMA_SRV
+                    .qos
+                    .lock()
+                    .Allowance(self.agent.id, self.localId, limit);
+            }
+            if len > limit {
+                len = limit;
             } else {
                 if self.ShouldSendFIN() {
                     immData = immData | 0x80000000;
@@ -452,15 +475,22 @@ impl RDMAChannelIntern {
             // println!("***********len = {}", totalLen);
 
             if len != 0 {
-                self.RDMAWriteImm(
+                let res = self.RDMAWriteImm(
                     addr,
                     remoteInfo.raddr + remoteInfo.offset as u64,
                     len,
                     remoteInfo.rkey,
                     immData,
                     wrId as u64,
-                )
-                .expect("RDMAWriteImm fail...");
+                );
+                if let Err(e) = res {
+                    error!(
+                        "RDMAWriteImm fail, channelId: {}, error: {:?}",
+                        self.localId, e
+                    );
+                    self.conn.MarkBroken();
+                    return;
+                }
                 // println!(
                 //     "after calling self.RDMAWriteImm. raddr: {}, rkey: {}, len: {}",
                 //     remoteInfo.raddr + remoteInfo.offset as u64,
@@ -470,6 +500,9 @@ impl RDMAChannelIntern {
                 remoteInfo.freespace -= len as u32;
                 remoteInfo.offset = (remoteInfo.offset + len as u32) % remoteInfo.rlen;
                 remoteInfo.sending = true;
+                if self.localId != 0 {
+                    RDMA_SRV.qos.lock().Charge(self.agent.id, len);
+                }
                 // println!("RDMASendLocked::5, remoteInfo: {:?}", remoteInfo);
                 //error!("RDMASendLocked::2, writeCount: {}, readCount: {}", len, readCount);
             } else {
@@ -479,15 +512,21 @@ impl RDMAChannelIntern {
             if self.ShouldSendFIN() {
                 let immData = remoteInfo.remoteId | 0x80000000;
                 let wrId = self.localId | 0x80000000;
-                self.RDMAWriteImm(
+                let res = self.RDMAWriteImm(
                     addr,
                     remoteInfo.raddr + remoteInfo.offset as u64,
                     0,
                     remoteInfo.rkey,
                     immData,
                     wrId as u64,
-                )
-                .expect("RDMAWriteImm fail...");
+                );
+                if let Err(e) = res {
+                    error!(
+                        "RDMAWriteImm fail, channelId: {}, error: {:?}",
+                        self.localId, e
+                    );
+                    self.conn.MarkBroken();
+                }
             } else {
                 **remoteRecvRequestCount += 1;
             }
@@ -533,6 +572,8 @@ impl RDMAChannel {
             dstIpAddr: 0,
             srcPort: 0,
             dstPort: 0,
+            srcIpv6Addr: [0; 16],
+            dstIpv6Addr: [0; 16],
             status: Mutex::new(ChannelStatus::ESTABLISHED),
             duplexMode: Mutex::new(DuplexMode::SHUTDOWN_NONE),
             lkey,
@@ -570,6 +611,8 @@ impl RDMAChannel {
             dstIpAddr: connectRequest.srcIpAddr,
             srcPort: connectRequest.dstPort,
             dstPort: connectRequest.srcPort,
+            srcIpv6Addr: connectRequest.dstIpv6Addr,
+            dstIpv6Addr: connectRequest.srcIpv6Addr,
             status: Mutex::new(ChannelStatus::ESTABLISHED),
             duplexMode: Mutex::new(DuplexMode::SHUTDOWN_NONE),
             lkey,
@@ -616,6 +659,8 @@ impl RDMAChannel {
             dstIpAddr: connectRequest.dstIpAddr,
             srcPort: connectRequest.srcPort,
             dstPort: connectRequest.dstPort,
+            srcIpv6Addr: connectRequest.srcIpv6Addr,
+            dstIpv6Addr: connectRequest.dstIpv6Addr,
             status: Mutex::new(ChannelStatus::SYN_SENT),
             duplexMode: Mutex::new(DuplexMode::SHUTDOWN_NONE),
             lkey,
@@ -641,6 +686,8 @@ impl RDMAChannel {
             dstPort: self.dstPort,
             srcIpAddr: self.srcIpAddr,
             srcPort: self.srcPort,
+            dstIpv6Addr: self.dstIpv6Addr,
+            srcIpv6Addr: self.srcIpv6Addr,
             recvRequestCount: self
                 .conn
                 .localInsertedRecvRequestCount
@@ -652,6 +699,33 @@ impl RDMAChannel {
     pub fn RemoteKey(&self) -> u32 {
         self.rkey
     }
+
+    // the ipv4 addresses of the channel are in network order
+    pub fn SrcAddr(&self) -> IpAddr {
+        if IsIpv6(&self.srcIpv6Addr) {
+            return IpAddr::V6(Ipv6Addr::from(self.srcIpv6Addr));
+        }
+        return IpAddr::V4(Ipv4Addr::from(u32::from_be(self.srcIpAddr)));
+    }
+
+    pub fn DstAddr(&self) -> IpAddr {
+        if IsIpv6(&self.dstIpv6Addr) {
+            return IpAddr::V6(Ipv6Addr::from(self.dstIpv6Addr));
+        }
+        return IpAddr::V4(Ipv4Addr::from(u32::from_be(self.dstIpAddr)));
+    }
 }
 
 pub struct RDMAChannelWeak(Weak<RDMAChannelIntern>);
+
+// ResetResponse notifies the client that the channel is reset with its broken
+// RDMA connection
+pub fn ResetResponse(channelId: u32) -> RDMAResp {
+    return RDMAResp {
+        user_data: 0,
+        msg: RDMARespMsg::RDMAFinNotify(RDMAFinNotifyResp {
+            channelId: channelId,
+            event: CONNECTION_RESET,
+        }),
+    };
+}
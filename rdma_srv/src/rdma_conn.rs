@@ -21,9 +21,12 @@ use libc::*;
 use spin::{Mutex, MutexGuard};
 use std::collections::HashSet;
 use std::collections::VecDeque;
+use std::net::Ipv6Addr;
 use std::ops::{Deref, DerefMut};
 use std::{env, ptr, thread};
 
+use super::common::{close, epoll_delete, MonotonicUs};
+use super::constants::{HEARTBEAT_INTERVAL_MS, HEARTBEAT_MISS_LIMIT, PROTOCOL_TCP};
 use super::qlib::common::*;
 use super::rdma::*;
 use super::rdma_agent::*;
@@ -33,6 +36,7 @@ use super::rdma_ctrlconn::*;
 use super::qlib::linux_def::*;
 use super::qlib::rdma_share::*;
 use super::qlib::socket_buf::*;
+use super::qos::FairQueue;
 use super::rdma_srv::*;
 
 // RDMA Queue Pair
@@ -73,6 +77,13 @@ impl RDMAInfo {
 // RDMA connections between 2 nodes
 pub struct RDMAConnInternal {
     pub fd: i32,
+    // key of the connection in RDMA_SRV.conns: the peer node ipaddr, 0 for the
+    // connection accepted from the local node
+    pub nodeIp: u32,
+    // index of the control channel buffer in RDMA_SRV.controlRegion
+    pub controlBufIndex: u32,
+    // monotonic time in us when the last control message was received
+    pub lastHeard: AtomicU64,
     pub qps: Vec<QueuePair>,
     //pub ctrlChan: Mutex<RDMAControlChannel>,
     pub ctrlChan: Mutex<RDMAControlChannel>,
@@ -81,7 +92,7 @@ pub struct RDMAConnInternal {
     pub remoteRDMAInfo: Mutex<RDMAInfo>,
     pub remoteRecvRequestCount: Mutex<u32>,
     pub localInsertedRecvRequestCount: AtomicU32,
-    pub requestsQueue: Mutex<VecDeque<u32>>, //currently using channel id
+    pub requestsQueue: Mutex<FairQueue>, //data channels, scheduled by pod
     pub controlRequestsQueue: Mutex<VecDeque<u32>>, //currently using channel id
     pub addressHandler: Mutex<AddressHandler>, //used for UD QP
 }
@@ -110,7 +121,14 @@ impl Drop for RDMAConn {
 }
 
 impl RDMAConn {
-    pub fn New(fd: i32, sockBuf: SocketBuff, controlRKey: u32, udpQPNum: u32) -> Self {
+    pub fn New(
+        fd: i32,
+        nodeIp: u32,
+        controlBufIndex: u32,
+        sockBuf: SocketBuff,
+        controlRKey: u32,
+        udpQPNum: u32,
+    ) -> Self {
         let rc_qp = RDMA.CreateRCQueuePair().expect("RDMA create RC QP fail");
         println!("after create RC qp");
         let (addr, len) = sockBuf.ReadBuf();
@@ -129,6 +147,9 @@ impl RDMAConn {
         //RDMA_SRV.controlChannels.lock().insert(qp.qpNum(), rdmaChannel.clone());
         Self(Arc::new(RDMAConnInternal {
             fd: fd,
+            nodeIp: nodeIp,
+            controlBufIndex: controlBufIndex,
+            lastHeard: AtomicU64::new(0),
             qps: vec![rc_qp],
             //ctrlChan: Mutex::new(RDMAControlChannel(Weak::new())),
             ctrlChan: Mutex::new(RDMAControlChannel::default()),
@@ -137,7 +158,7 @@ impl RDMAConn {
             remoteRDMAInfo: Mutex::new(RDMAInfo::default()),
             remoteRecvRequestCount: Mutex::new(0),
             localInsertedRecvRequestCount: AtomicU32::new(0),
-            requestsQueue: Mutex::new(VecDeque::default()),
+            requestsQueue: Mutex::new(FairQueue::default()),
             controlRequestsQueue: Mutex::new(VecDeque::default()),
             addressHandler: Mutex::new(AddressHandler::default()),
         }))
@@ -176,7 +197,7 @@ impl RDMAConn {
     pub fn SetupRDMA(&self) {
         let remoteInfo = self.remoteRDMAInfo.lock();
         self.qps[0]
-            .SetupRCQP(&RDMA, remoteInfo.rc_qp_num, remoteInfo.lid, remoteInfo.gid)
+            .SetupRCQP(remoteInfo.rc_qp_num, remoteInfo.lid, remoteInfo.gid)
             .expect("SetupRCQP fail...");
         for _i in 0..RECV_REQUEST_COUNT {
             self.qps[0]
@@ -199,7 +220,12 @@ impl RDMAConn {
                     Ok(()) => {
                         // println!("Received remote RDMA Info");
                     }
-                    _ => return,
+                    Err(Error::SysError(SysErr::EAGAIN)) => return,
+                    Err(e) => {
+                        error!("RecvRemoteRDMAInfo fail, fd: {}, error: {:?}", self.fd, e);
+                        self.MarkBroken();
+                        return;
+                    }
                 }
                 println!("SetupRDMA, fd:{} ", self.fd);
                 self.SetupRDMA();
@@ -212,19 +238,35 @@ impl RDMAConn {
                     Ok(()) => {
                         self.SetReady();
                     }
-                    _ => (),
+                    Err(Error::SysError(SysErr::EAGAIN)) => (),
+                    Err(e) => {
+                        error!("RecvAck fail, fd: {}, error: {:?}", self.fd, e);
+                        self.MarkBroken();
+                    }
                 }
             }
             SocketState::WaitingForRemoteReady => {
                 match self.RecvAck() {
                     Ok(()) => {}
-                    _ => return,
+                    Err(Error::SysError(SysErr::EAGAIN)) => return,
+                    Err(e) => {
+                        error!("RecvAck fail, fd: {}, error: {:?}", self.fd, e);
+                        self.MarkBroken();
+                        return;
+                    }
                 }
                 self.SetReady();
             }
             SocketState::Ready => {
                 // println!("Read::Ready, fd:{} ", self.fd);
+                // nothing is sent on the handshake socket after ready, it is
+                // readable only when the peer rdma_srv is gone
+                if self.PeerClosed() {
+                    error!("RDMA connection to node {} is closed by peer", self.nodeIp);
+                    self.MarkBroken();
+                }
             }
+            SocketState::Error => (),
             _ => {
                 panic!(
                     "RDMA socket read state error with state {:?}",
@@ -236,10 +278,13 @@ impl RDMAConn {
 
     pub fn Write(&self) {
         match self.SocketState() {
-            SocketState::Init => {
-                self.SendLocalRDMAInfo().unwrap();
-                self.SetSocketState(SocketState::WaitingForRemoteMeta);
-            }
+            SocketState::Init => match self.SendLocalRDMAInfo() {
+                Ok(()) => self.SetSocketState(SocketState::WaitingForRemoteMeta),
+                Err(e) => {
+                    error!("SendLocalRDMAInfo fail, fd: {}, error: {:?}", self.fd, e);
+                    self.MarkBroken();
+                }
+            },
             SocketState::WaitingForRemoteMeta => {
                 // println!("Write::1, fd:{} ", self.fd);
                 //TODO: server side received 4(W) first and 5 (R|W) afterwards. Need more investigation to see why it's different.
@@ -251,6 +296,7 @@ impl RDMAConn {
             SocketState::Ready => {
                 // println!("Write::Ready, fd:{} ", self.fd);
             }
+            SocketState::Error => (),
             _ => {
                 panic!(
                     "RDMA socket Write state error with state {:?}",
@@ -262,7 +308,7 @@ impl RDMAConn {
 
     pub fn SocketState(&self) -> SocketState {
         let state = self.socketState.load(Ordering::Relaxed);
-        assert!(state <= SocketState::Ready as u64);
+        assert!(state <= SocketState::Error as u64);
         let state: SocketState = unsafe { mem::transmute(state) };
         return state;
     }
@@ -273,6 +319,8 @@ impl RDMAConn {
 
     pub fn SetReady(&self) {
         self.SetSocketState(SocketState::Ready);
+        self.Touch();
+        RDMA_CTLINFO.peer_connected(self.nodeIp);
         // println!("Ready!!!");
         //self.ctrlChan.lock().SendData();
         // let laddr = RDMA_SRV.udpMemRegion.addr + 10 * (mem::size_of::<UDPPacket>() + 40) as u64 + 40;
@@ -348,6 +396,10 @@ impl RDMAConn {
             return Err(Error::SysError(errno));
         }
 
+        if ret == 0 {
+            return Err(Error::SysError(SysErr::ECONNRESET));
+        }
+
         //self.socketBuf.SetErr(0); //TODO: find a better place
 
         assert!(
@@ -408,6 +460,10 @@ impl RDMAConn {
             return Err(Error::SysError(errno));
         }
 
+        if ret == 0 {
+            return Err(Error::SysError(SysErr::ECONNRESET));
+        }
+
         assert!(
             ret == 8 as isize,
             "RecvAck fail ret is {}, expect {}",
@@ -426,6 +482,15 @@ impl RDMAConn {
 
     pub fn Notify(&self, eventmask: EventMask) {
         // println!("RDMAConn::Notify 1, fd:{} ", self.fd);
+        if eventmask & (EVENT_ERR | EVENT_HUP) != 0 {
+            error!(
+                "RDMA connection to node {} fail, fd: {}, events: {:x}",
+                self.nodeIp, self.fd, eventmask
+            );
+            self.MarkBroken();
+            return;
+        }
+
         if eventmask & EVENT_WRITE != 0 {
             // println!("RDMAConn::Notify 2, fd:{} ", self.fd);
             self.Write();
@@ -470,6 +535,10 @@ impl RDMAConn {
         rdmaChannel: &RDMAChannelIntern,
         remoteInfo: MutexGuard<ChannelRDMAInfo>,
     ) {
+        if self.IsBroken() {
+            return;
+        }
+
         let mut remoteRecvRequestCount = self.remoteRecvRequestCount.lock();
         // println!(
         //     "RDMAConn::RDMAWrite, channelId: {}, *remoteRecvRequestCount: {}",
@@ -492,7 +561,11 @@ impl RDMAConn {
                     self.controlRequestsQueue.lock().len()
                 );
             } else {
-                self.requestsQueue.lock().push_back(rdmaChannel.localId);
+                let agentId = rdmaChannel.agent.id;
+                self.requestsQueue
+                    .lock()
+                    .Push(agentId, rdmaChannel.localId, MonotonicUs());
+                RDMA_SRV.qos.lock().Queued(agentId);
                 println!(
                     "self.requestsQueue.lock(), len: {}",
                     self.requestsQueue.lock().len()
@@ -551,9 +624,13 @@ impl RDMAConn {
         }
         let mut requests = self.requestsQueue.lock();
         while *remoteRecvRequestCount > 0 {
-            let channelId = requests.pop_front();
-            match channelId {
-                Some(id) => {
+            let request = requests.Pop(|agentId| RDMA_SRV.qos.lock().Weight(agentId));
+            match request {
+                Some((agentId, id, enqueueTime)) => {
+                    RDMA_SRV
+                        .qos
+                        .lock()
+                        .Dequeued(agentId, MonotonicUs() - enqueueTime);
                     if id != 0 {
                         RDMA_SRV
                             .channels
@@ -578,6 +655,103 @@ impl RDMAConn {
         }
     }
 
+    pub fn Equals(&self, other: &RDMAConn) -> bool {
+        return Arc::ptr_eq(&self.0, &other.0);
+    }
+
+    pub fn Touch(&self) {
+        self.lastHeard.store(MonotonicUs(), Ordering::Relaxed);
+    }
+
+    pub fn IsBroken(&self) -> bool {
+        return self.socketState.load(Ordering::Acquire) == SocketState::Error as u64;
+    }
+
+    // MarkBroken moves the connection to error state and queues it to be reset
+    // by the RDMA service loop, the caller may hold the service locks.
+    pub fn MarkBroken(&self) {
+        let prev = self
+            .socketState
+            .swap(SocketState::Error as u64, Ordering::SeqCst);
+        if prev != SocketState::Error as u64 {
+            RDMA_SRV.brokenConns.lock().push(self.clone());
+        }
+    }
+
+    fn PeerClosed(&self) -> bool {
+        let mut data: u64 = 0;
+        let ret = unsafe { read(self.fd, &mut data as *mut _ as u64 as _, 8) };
+        if ret < 0 {
+            return errno::errno().0 != SysErr::EAGAIN;
+        }
+
+        return ret == 0;
+    }
+
+    // Heartbeat sends a heartbeat to the peer node, or marks the connection
+    // broken when nothing is heard from the peer for HEARTBEAT_MISS_LIMIT
+    // intervals.
+    pub fn Heartbeat(&self, now: u64) {
+        if !matches!(self.SocketState(), SocketState::Ready) {
+            return;
+        }
+
+        let lastHeard = self.lastHeard.load(Ordering::Relaxed);
+        if now > lastHeard + HEARTBEAT_INTERVAL_MS * HEARTBEAT_MISS_LIMIT * 1000 {
+            error!(
+                "RDMA connection to node {} timeout, last heard {} us ago",
+                self.nodeIp,
+                now - lastHeard
+            );
+            RDMA_CTLINFO.peer_update(self.nodeIp, |peer| peer.stats.heartbeatTimeouts += 1);
+            self.MarkBroken();
+            return;
+        }
+
+        self.ctrlChan
+            .lock()
+            .SendControlMsg(ControlMsgBody::Heartbeat(Heartbeat {
+                sendTime: now,
+                recvRequestCount: self.localInsertedRecvRequestCount.swap(0, Ordering::SeqCst),
+                reply: false,
+            }));
+        RDMA_CTLINFO.peer_update(self.nodeIp, |peer| peer.stats.heartbeatsSent += 1);
+    }
+
+    // Reset tears down the connection: the channels over it are reset and
+    // the guests are notified with CONNECTION_RESET. It returns the count of
+    // reset channels.
+    pub fn Reset(&self) -> usize {
+        self.SetSocketState(SocketState::Error);
+        {
+            let mut conns = RDMA_SRV.conns.lock();
+            let current = conns.get(&self.nodeIp).map(|c| c.Equals(self));
+            if current == Some(true) {
+                conns.remove(&self.nodeIp);
+            }
+        }
+
+        for qp in self.GetQueuePairs() {
+            RDMA_SRV.controlChannels.lock().remove(&qp.qpNum());
+            RDMA_SRV.controlChannels2.lock().remove(&qp.qpNum());
+        }
+        // drop the control channel, it holds the connection
+        *self.ctrlChan.lock() = RDMAControlChannel::default();
+
+        let channels = ConnChannels(&RDMA_SRV.channels.lock(), |channel| {
+            channel.conn.Equals(self)
+        });
+        for channel in &channels {
+            channel.Reset();
+        }
+
+        let _ = epoll_delete(RDMA_CTLINFO.epoll_fd_get(), self.fd);
+        RDMA_CTLINFO.fds.lock().remove(&self.fd);
+        close(self.fd);
+        RDMA_SRV.controlBufIdMgr.lock().Remove(self.controlBufIndex);
+        return channels.len();
+    }
+
     pub fn PostRecv(&self, _qpNum: u32, wrId: u64, addr: u64, lkey: u32) -> Result<()> {
         //TODO: get right qp when multiple QP are used between two physical machines.
         self.qps[0].PostRecv(wrId, addr, lkey, 0)?;
@@ -671,7 +845,18 @@ impl RDMAControlChannel {
 
     pub fn ProcessRDMARecvWriteImm(&self, qpNum: u32, recvCount: u64) {
         // println!("RDMAControlChannel::ProcessRDMARecvWriteImm 1");
-        let rdmaChannel = self.chan.upgrade().unwrap();
+        // the channel is gone when the connection was reset meanwhile
+        let rdmaChannel = match self.chan.upgrade() {
+            Some(chan) => chan,
+            None => {
+                error!(
+                    "RDMAControlChannel::ProcessRDMARecvWriteImm, control channel of qp {} is gone",
+                    qpNum
+                );
+                return;
+            }
+        };
+        rdmaChannel.conn.Touch();
         let _res = rdmaChannel.conn.PostRecv(
             qpNum,
             rdmaChannel.localId as u64,
@@ -706,44 +891,44 @@ impl RDMAControlChannel {
             let msg = unsafe { &*(rAddr as *mut ControlMsgBody) };
             match msg {
                 ControlMsgBody::ConnectRequest(msg) => {
-                    self.chan
-                        .upgrade()
-                        .unwrap()
+                    rdmaChannel
                         .conn
                         .IncreaseRemoteRequestCount(msg.recvRequestCount);
                     self.HandleConnectRequest(msg);
                 }
                 ControlMsgBody::ConnectResponse(msg) => {
-                    self.chan
-                        .upgrade()
-                        .unwrap()
+                    rdmaChannel
                         .conn
                         .IncreaseRemoteRequestCount(msg.recvRequestCount);
                     self.HandleConnectResponse(msg);
                 }
+                ControlMsgBody::ConnectReject(msg) => {
+                    rdmaChannel
+                        .conn
+                        .IncreaseRemoteRequestCount(msg.recvRequestCount);
+                    self.HandleConnectReject(msg);
+                }
                 ControlMsgBody::ConsumedData(msg) => {
                     // println!("ControlChannel::ConsumedData: {}", msg.consumedData);
-                    self.chan
-                        .upgrade()
-                        .unwrap()
+                    rdmaChannel
                         .conn
                         .IncreaseRemoteRequestCount(msg.recvRequestCount);
                     self.HandleConsumedData(qpNum, msg);
                 }
                 ControlMsgBody::RecvRequestCount(msg) => {
-                    self.chan
-                        .upgrade()
-                        .unwrap()
+                    rdmaChannel.conn.IncreaseRemoteRequestCount(msg.count);
+                }
+                ControlMsgBody::Heartbeat(msg) => {
+                    rdmaChannel
                         .conn
-                        .IncreaseRemoteRequestCount(msg.count);
+                        .IncreaseRemoteRequestCount(msg.recvRequestCount);
+                    self.HandleHeartbeat(&rdmaChannel.conn, msg);
                 }
                 ControlMsgBody::DummyMsg => {
                     panic!("Control channel received dummy message!");
                 }
                 ControlMsgBody::ConsumedDataGroup(msg) => {
-                    self.chan
-                        .upgrade()
-                        .unwrap()
+                    rdmaChannel
                         .conn
                         .IncreaseRemoteRequestCount(msg.recvRequestCount);
                     self.HandleConsumedDataGroup(msg);
@@ -802,6 +987,23 @@ impl RDMAControlChannel {
         }
     }
 
+    pub fn HandleHeartbeat(&self, conn: &RDMAConn, heartbeat: &Heartbeat) {
+        if heartbeat.reply {
+            let rtt = MonotonicUs().saturating_sub(heartbeat.sendTime);
+            RDMA_CTLINFO.peer_update(conn.nodeIp, |peer| {
+                peer.stats.heartbeatsReceived += 1;
+                peer.stats.lastRttUs = rtt;
+            });
+            return;
+        }
+
+        self.SendControlMsg(ControlMsgBody::Heartbeat(Heartbeat {
+            sendTime: heartbeat.sendTime,
+            recvRequestCount: conn.localInsertedRecvRequestCount.swap(0, Ordering::SeqCst),
+            reply: true,
+        }));
+    }
+
     pub fn HandleConnectResponse(&self, connectResponse: &ConnectResponse) {
         // println!("handle Connect Response: {:?}", connectResponse);
         match RDMA_SRV
@@ -829,6 +1031,8 @@ impl RDMAControlChannel {
                         dstPort: rdmaChannel.dstPort,
                         srcIpAddr: rdmaChannel.srcIpAddr,
                         srcPort: rdmaChannel.srcPort,
+                        dstIpv6Addr: rdmaChannel.dstIpv6Addr,
+                        srcIpv6Addr: rdmaChannel.srcIpv6Addr,
                     }),
                 })
             }
@@ -841,12 +1045,86 @@ impl RDMAControlChannel {
         }
     }
 
+    // HandleConnectReject drops the client channel and fails the connect
+    pub fn HandleConnectReject(&self, connectReject: &ConnectReject) {
+        let rdmaChannel = RDMA_SRV
+            .channels
+            .lock()
+            .remove(&connectReject.remoteChannelId);
+        match rdmaChannel {
+            Some(rdmaChannel) => {
+                rdmaChannel.agent.SendResponse(RDMAResp {
+                    user_data: 0,
+                    msg: RDMARespMsg::RDMAConnectFail(RDMAConnectFailResp {
+                        sockfd: connectReject.remoteSockFd,
+                        error: connectReject.error,
+                    }),
+                });
+            }
+            None => {
+                println!("Channel id {} is not found!", connectReject.remoteChannelId);
+            }
+        }
+    }
+
+    fn SendConnectReject(&self, connectRequest: &ConnectRequest, error: i32) {
+        let recvRequestCount = match self.chan.upgrade() {
+            Some(chan) => chan
+                .conn
+                .localInsertedRecvRequestCount
+                .swap(0, Ordering::SeqCst),
+            None => return,
+        };
+        self.SendControlMsg(ControlMsgBody::ConnectReject(ConnectReject {
+            remoteChannelId: connectRequest.remoteChannelId,
+            remoteSockFd: connectRequest.sockFd,
+            error: error,
+            recvRequestCount: recvRequestCount,
+        }));
+    }
+
     pub fn HandleConnectRequest(&self, connectRequest: &ConnectRequest) {
         let mut found = false;
         let mut agentId = 0;
         let mut sockfd = 0;
 
-        if RDMA_CTLINFO.IsEgress(connectRequest.dstIpAddr) {
+        let ipv6 = IsIpv6(&connectRequest.dstIpv6Addr);
+        let (srcIp, dstIp, allowed) = if ipv6 {
+            let srcIp = Ipv6Addr::from(connectRequest.srcIpv6Addr);
+            let dstIp = Ipv6Addr::from(connectRequest.dstIpv6Addr);
+            let allowed = RDMA_CTLINFO.NetworkPolicyAllowsV6(
+                srcIp,
+                dstIp,
+                PROTOCOL_TCP,
+                u16::from_be(connectRequest.dstPort),
+            );
+            (srcIp.to_string(), dstIp.to_string(), allowed)
+        } else {
+            let allowed = RDMA_CTLINFO.NetworkPolicyAllows(
+                connectRequest.srcIpAddr.to_be(),
+                connectRequest.dstIpAddr.to_be(),
+                PROTOCOL_TCP,
+                u16::from_be(connectRequest.dstPort),
+            );
+            (
+                connectRequest.srcIpAddr.to_be().to_string(),
+                connectRequest.dstIpAddr.to_be().to_string(),
+                allowed,
+            )
+        };
+        if !allowed {
+            error!(
+                "HandleConnectRequest, connect from {} to {}:{} is denied by network policy",
+                srcIp,
+                dstIp,
+                u16::from_be(connectRequest.dstPort)
+            );
+            self.SendConnectReject(connectRequest, SysErr::ECONNREFUSED);
+            return;
+        }
+
+        // the egress gateway only takes ipv4 connections
+        if !ipv6 && RDMA_CTLINFO.IsEgress(connectRequest.dstIpAddr) {
             match RDMA_SRV.srvEndPoints.lock().get(&Endpoint::Egress()) {
                 Some(srvEndpoint) => match srvEndpoint.status {
                     SrvEndPointStatus::Listening => {
@@ -861,11 +1139,20 @@ impl RDMAControlChannel {
                 }
             }
         } else {            
-            match RDMA_CTLINFO
-                .ipToPodIdMappings
-                .lock()
-                .get(&(connectRequest.dstIpAddr.to_be()))
-            {
+            let podIdStr = if ipv6 {
+                RDMA_CTLINFO
+                    .podsV6
+                    .lock()
+                    .get(&Ipv6Addr::from(connectRequest.dstIpv6Addr))
+                    .map(|pod| pod.container_id.clone())
+            } else {
+                RDMA_CTLINFO
+                    .ipToPodIdMappings
+                    .lock()
+                    .get(&(connectRequest.dstIpAddr.to_be()))
+                    .cloned()
+            };
+            match podIdStr {
                 Some(podIdStr) => {
                     let mut podId: [u8; 64] = [0; 64];
                     // if podIdStr.len() != podId.len() {
@@ -904,7 +1191,7 @@ impl RDMAControlChannel {
                 None => {
                     error!(
                         "HandleConnectRequest, podId for ip: {} is not found!!",
-                        connectRequest.dstIpAddr.to_be()
+                        dstIp
                     );
                 }
             }
@@ -949,10 +1236,12 @@ impl RDMAControlChannel {
                     dstPort: rdmaChannel.dstPort,
                     srcIpAddr: rdmaChannel.srcIpAddr,
                     srcPort: rdmaChannel.srcPort,
+                    dstIpv6Addr: rdmaChannel.dstIpv6Addr,
+                    srcIpv6Addr: rdmaChannel.srcIpv6Addr,
                 }),
             });
         } else {
-            println!("TODO: no server listening, need ack error back!");
+            self.SendConnectReject(connectRequest, SysErr::ECONNREFUSED);
         }
     }
 
@@ -966,7 +1255,11 @@ impl RDMAControlChannel {
     pub fn SendControlMsg(&self, msg: ControlMsgBody) {
         //-> Result<()> {
         // println!("RDMAControlChannel::SendControlMsg, msg: {:?}", msg);
-        let rdmaChannel = self.chan.upgrade().unwrap();
+        let rdmaChannel = match self.chan.upgrade() {
+            Some(chan) => chan,
+            // the connection is reset
+            None => return,
+        };
         // println!("RDMAControlChannel::SendControlMsg 1");
         let mut writeBuf = rdmaChannel.sockBuf.writeBuf.lock();
         // println!(
@@ -1075,6 +1368,8 @@ impl RDMAControlChannel {
             srcPort: 80,
             dstIpAddr: 0,
             dstPort: 8080,
+            srcIpv6Addr: [0; 16],
+            dstIpv6Addr: [0; 16],
             recvRequestCount: self
                 .chan
                 .upgrade()
@@ -1104,10 +1399,12 @@ impl RDMAControlChannel {
 pub enum ControlMsgBody {
     ConnectRequest(ConnectRequest),
     ConnectResponse(ConnectResponse),
+    ConnectReject(ConnectReject),
     // ConnectConfirm(ConnectConfirm),
     ConsumedDataGroup(ConsumedDataGroup),
     ConsumedData(ConsumedData),
     RecvRequestCount(RecvRequestCount),
+    Heartbeat(Heartbeat),
     DummyMsg,
 }
 
@@ -1135,6 +1432,14 @@ pub struct RecvRequestCount {
     pub count: u32,
 }
 
+#[derive(Clone, Debug)]
+pub struct Heartbeat {
+    // monotonic time of the heartbeat sender, echoed back in the reply
+    pub sendTime: u64,
+    pub recvRequestCount: u32,
+    pub reply: bool,
+}
+
 #[derive(Clone, Debug)]
 pub struct ConnectRequest {
     pub remoteChannelId: u32,
@@ -1145,6 +1450,9 @@ pub struct ConnectRequest {
     pub dstPort: u16,
     pub srcIpAddr: u32,
     pub srcPort: u16,
+    // the addresses of an ipv6 connection, all zero for ipv4
+    pub dstIpv6Addr: [u8; 16],
+    pub srcIpv6Addr: [u8; 16],
     pub recvRequestCount: u32,
     pub sockFd: u32,
 }
@@ -1160,6 +1468,16 @@ pub struct ConnectResponse {
     pub remoteSockFd: u32,
 }
 
+// ConnectReject fails the connect request, e.g. no server is listening or a
+// network policy denies it
+#[derive(Clone, Debug)]
+pub struct ConnectReject {
+    pub remoteChannelId: u32,
+    pub remoteSockFd: u32,
+    pub error: i32,
+    pub recvRequestCount: u32,
+}
+
 #[repr(u32)]
 pub enum ControlMsgType {
     ConnectionRequest,
@@ -16,11 +16,15 @@ use crate::rdma_srv::{RDMA_CTLINFO, RDMA_SRV};
 use spin::Mutex;
 use std::cell::RefCell;
 use std::iter::FromIterator;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::{collections::HashMap, collections::HashSet, str::FromStr};
 
 use super::common::*;
-use cidr::Ipv4Cidr;
+use super::constants::*;
+use super::load_balancer::*;
+use super::network_policy::*;
+use super::qos::PodQoS;
+use cidr::{Ipv4Cidr, Ipv6Cidr};
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::sync::atomic::{AtomicUsize, Ordering};
 
@@ -38,18 +42,30 @@ pub struct CtrlInfo {
     // pods: pod ipaddr --> Pod
     pub pods: Mutex<HashMap<u32, Pod>>,
 
+    // podsV6: pod ipv6 addr --> Pod
+    pub podsV6: Mutex<HashMap<Ipv6Addr, Pod>>,
+
     // services: service ip --> Service
     pub services: Mutex<HashMap<u32, Service>>,
 
+    // servicesV6: service ipv6 cluster ip --> Service
+    pub servicesV6: Mutex<HashMap<Ipv6Addr, Service>>,
+
     // endpointses: endpoints name --> Endpoints
     pub endpointses: Mutex<HashMap<String, Endpoints>>,
 
     // configMaps: configMap name --> ConfigMap
     pub configMaps: Mutex<HashMap<String, ConfigMap>>,
 
+    // networkPolicies: namespace/name --> NetworkPolicy
+    pub networkPolicies: Mutex<HashMap<String, NetworkPolicy>>,
+
     // containerids: containerid --> ip
     pub containerids: Mutex<HashMap<String, u32>>,
 
+    // containeridsV6: containerid --> ipv6
+    pub containeridsV6: Mutex<HashMap<String, Ipv6Addr>>,
+
     //ip --> podId
     pub ipToPodIdMappings: Mutex<HashMap<u32, String>>,
 
@@ -81,6 +97,12 @@ pub struct CtrlInfo {
     pub isCMConnected: Mutex<bool>,
 
     pub localIp: Mutex<u32>,
+
+    // peers: peer node ipaddr --> PeerConn
+    pub peers: Mutex<HashMap<u32, PeerConn>>,
+
+    // resourceVersions: informer kind --> max resource version handled
+    pub resourceVersions: Mutex<HashMap<String, i32>>,
 }
 
 impl Default for CtrlInfo {
@@ -102,6 +124,7 @@ impl Default for CtrlInfo {
                 timestamp: 0,
                 subnet: u32::from(Ipv4Addr::from_str("192.168.2.0").unwrap()),
                 netmask: u32::from(Ipv4Addr::from_str("255.255.255.0").unwrap()),
+                subnetV6: None,
                 resource_version: 0,
             };
             let lab2ip = u32::from(Ipv4Addr::from_str("172.16.1.99").unwrap()).to_be();
@@ -111,6 +134,7 @@ impl Default for CtrlInfo {
                 timestamp: 0,
                 subnet: u32::from(Ipv4Addr::from_str("192.168.1.0").unwrap()),
                 netmask: u32::from(Ipv4Addr::from_str("255.255.255.0").unwrap()),
+                subnetV6: None,
                 resource_version: 0,
             };
             nodes.insert(lab1ip, node1);
@@ -137,10 +161,14 @@ impl Default for CtrlInfo {
         CtrlInfo {
             nodes: Mutex::new(nodes),
             pods: Mutex::new(pods),
+            podsV6: Mutex::new(HashMap::new()),
             services: Mutex::new(services),
+            servicesV6: Mutex::new(HashMap::new()),
             endpointses: Mutex::new(endpointses),
             configMaps: Mutex::new(configMaps),
+            networkPolicies: Mutex::new(HashMap::new()),
             containerids: Mutex::new(containerids),
+            containeridsV6: Mutex::new(HashMap::new()),
             ipToPodIdMappings: Mutex::new(ipToPodIdMappings),
             subnetmap: Mutex::new(HashMap::new()),
             veps: Mutex::new(HashMap::new()),
@@ -162,6 +190,8 @@ impl Default for CtrlInfo {
             isK8s: isK8s,
             isCMConnected: Mutex::new(false),
             localIp: Mutex::new(0),
+            peers: Mutex::new(HashMap::new()),
+            resourceVersions: Mutex::new(HashMap::new()),
         }
     }
 }
@@ -224,6 +254,60 @@ impl CtrlInfo {
         set
     }
 
+    pub fn resource_version_set(&self, kind: &str, version: i32) {
+        self.resourceVersions
+            .lock()
+            .insert(kind.to_string(), version);
+    }
+
+    pub fn resource_versions_get(&self) -> HashMap<String, i32> {
+        self.resourceVersions.lock().clone()
+    }
+
+    pub fn peer_update<F: FnOnce(&mut PeerConn)>(&self, ip: u32, f: F) {
+        let mut peers = self.peers.lock();
+        f(peers.entry(ip).or_insert(PeerConn::default()));
+    }
+
+    pub fn peer_stats_get(&self) -> HashMap<u32, PeerConnStats> {
+        self.peers
+            .lock()
+            .iter()
+            .map(|(ip, peer)| (*ip, peer.stats.clone()))
+            .collect()
+    }
+
+    // peer_connected resets the reconnect backoff once the connection is ready
+    pub fn peer_connected(&self, ip: u32) {
+        self.peer_update(ip, |peer| peer.Connected());
+    }
+
+    // peer_reconnect_schedule retries the connection to the peer node after an
+    // exponential backoff
+    pub fn peer_reconnect_schedule(&self, ip: u32) {
+        self.peer_update(ip, |peer| {
+            let backoff = peer.ScheduleRetry(MonotonicUs() / 1000);
+            error!(
+                "reconnect to node {} in {} ms, attempt: {}",
+                Ipv4Addr::from(u32::from_be(ip)),
+                backoff,
+                peer.failures
+            );
+        });
+    }
+
+    // peer_reconnect_due returns the peer nodes whose reconnect backoff expired
+    pub fn peer_reconnect_due(&self) -> Vec<u32> {
+        let now = MonotonicUs() / 1000;
+        let mut due = Vec::new();
+        for (ip, peer) in self.peers.lock().iter_mut() {
+            if peer.RetryDue(now) {
+                due.push(*ip);
+            }
+        }
+        due
+    }
+
     pub fn get_node_ip_by_pod_ip(&self, ip: &u32) -> Option<u32> {
         for (_, node) in self.nodes.lock().iter() {
             // if !self.isK8s {
@@ -237,33 +321,118 @@ impl CtrlInfo {
         None
     }
 
-    pub fn IsService(&self, ip: u32, port: &u16) -> Option<IpWithPort> {
-        let services = self.services.lock();
-        if services.contains_key(&ip) {
-            for p in services[&ip].ports.iter() {
-                if p.port == *port {
-                    let endpointses = self.endpointses.lock();
-                    let ipWithPorts = &endpointses[&services[&ip].name].ip_with_ports;
-                    let atomicIndex = &endpointses[&services[&ip].name].index;
-
-                    let mut expectedIndex = atomicIndex.fetch_add(1, Ordering::SeqCst);
-                    if expectedIndex >= ipWithPorts.len() {
-                        expectedIndex = 0;
-                        atomicIndex.store(0, Ordering::SeqCst)
-                    }
-                    let mut currentIndex = 0;
-                    for ipWithPort in ipWithPorts.iter() {
-                        if currentIndex == expectedIndex {
-                            return Some(ipWithPort.clone());
-                        }
-                        currentIndex += 1;
-                    }
+    // get_node_ip_by_pod_ipv6 returns the node of an ipv6 pod, the RDMA
+    // connections between the nodes are over the node ipv4 addresses
+    pub fn get_node_ip_by_pod_ipv6(&self, ip: &Ipv6Addr) -> Option<u32> {
+        for (_, node) in self.nodes.lock().iter() {
+            if let Some(subnet) = &node.subnetV6 {
+                if subnet.contains(ip) {
+                    return Some(node.ipAddr);
                 }
             }
         }
         None
     }
 
+    pub fn get_node_ip_by_endpoint(&self, endpoint: &IpWithPort) -> Option<u32> {
+        match &endpoint.ipv6 {
+            Some(ip) => self.get_node_ip_by_pod_ipv6(ip),
+            None => self.get_node_ip_by_pod_ip(&endpoint.ip),
+        }
+    }
+
+    // NetworkPolicyAllows returns whether the network policies allow the
+    // connection from srcIp to dstIp:port. The ips are pod ips as keyed in
+    // pods, the port is in host order.
+    pub fn NetworkPolicyAllows(&self, srcIp: u32, dstIp: u32, protocol: &str, port: u16) -> bool {
+        let policies = self.networkPolicies.lock();
+        let pods = self.pods.lock();
+        return Allowed(
+            &policies,
+            pods.get(&srcIp),
+            pods.get(&dstIp),
+            IpAddr::V4(Ipv4Addr::from(u32::from_be(srcIp))),
+            IpAddr::V4(Ipv4Addr::from(u32::from_be(dstIp))),
+            protocol,
+            port,
+        );
+    }
+
+    // NetworkPolicyAllowsV6 is NetworkPolicyAllows for an ipv6 connection
+    pub fn NetworkPolicyAllowsV6(
+        &self,
+        srcIp: Ipv6Addr,
+        dstIp: Ipv6Addr,
+        protocol: &str,
+        port: u16,
+    ) -> bool {
+        let policies = self.networkPolicies.lock();
+        let pods = self.podsV6.lock();
+        return Allowed(
+            &policies,
+            pods.get(&srcIp),
+            pods.get(&dstIp),
+            IpAddr::V6(srcIp),
+            IpAddr::V6(dstIp),
+            protocol,
+            port,
+        );
+    }
+
+    // IsService returns the endpoint the connection of clientIp to the service
+    // ip:port goes to, picked by the load balancing policy of the service.
+    pub fn IsService(&self, ip: u32, port: &u16, clientIp: u32) -> Option<IpWithPort> {
+        let service = self.services.lock().get(&ip)?.clone();
+        return self.PickEndpoint(&service, port, IpAddr::V4(Ipv4Addr::from(clientIp)));
+    }
+
+    // IsServiceV6 is IsService for the ipv6 cluster ip of a dual-stack or
+    // ipv6 service
+    pub fn IsServiceV6(&self, ip: &Ipv6Addr, port: &u16, clientIp: Ipv6Addr) -> Option<IpWithPort> {
+        let service = self.servicesV6.lock().get(ip)?.clone();
+        return self.PickEndpoint(&service, port, IpAddr::V6(clientIp));
+    }
+
+    // PickEndpoint picks the endpoint of the service in the address family of
+    // the client, so that a dual-stack service resolves per family.
+    fn PickEndpoint(&self, service: &Service, port: &u16, clientIp: IpAddr) -> Option<IpWithPort> {
+        if !service.ports.iter().any(|p| p.port == *port) {
+            return None;
+        }
+
+        let (mut candidates, index) = {
+            let endpointses = self.endpointses.lock();
+            let endpoints = endpointses.get(&service.name)?;
+            let candidates: Vec<IpWithPort> = endpoints
+                .ip_with_ports
+                .iter()
+                .filter(|c| c.ipv6.is_some() == clientIp.is_ipv6())
+                .cloned()
+                .collect();
+            let index = endpoints.index.fetch_add(1, Ordering::SeqCst);
+            (candidates, index)
+        };
+
+        candidates.sort();
+        if service.topology_aware {
+            let localIp = self.localIp_get();
+            let local: Vec<IpWithPort> = candidates
+                .iter()
+                .filter(|c| self.get_node_ip_by_endpoint(c) == Some(localIp))
+                .cloned()
+                .collect();
+            if local.len() > 0 {
+                candidates = local;
+            }
+        }
+
+        let ctx = BalanceContext {
+            clientIp: clientIp,
+            index: index,
+        };
+        return service.lb_policy.Balancer().Pick(&candidates, &ctx);
+    }
+
     pub fn IsEgress(&self, ip: u32) -> bool {
         if !self.isK8s {
             return false;
@@ -281,17 +450,51 @@ impl CtrlInfo {
         true
     }
 
+    pub fn IsEgressV6(&self, ip: &Ipv6Addr) -> bool {
+        if !self.isK8s {
+            return false;
+        }
+        if self.IsInSubnetV6(ip, "podSubnet") || self.IsInSubnetV6(ip, "serviceSubnet") {
+            return false;
+        }
+
+        debug!("IP {} is egress traffic", ip);
+        true
+    }
+
+    // The subnets of a dual-stack cluster are comma separated, e.g.
+    // "10.244.0.0/16,fd00:10:244::/56"
     pub fn IsInSubnet(&self, ip: u32, cidrName: String) -> bool {
         let configMaps = self.configMaps.lock();
         if configMaps.contains_key(&cidrName) {
-            let cidr = &configMaps[&cidrName].value;
-            let ipv4Cidr = Ipv4Cidr::from_str(cidr).unwrap();
-            let mut splitted = cidr.split("/");
-            let ipv4 = Ipv4Addr::from_str(splitted.next().unwrap()).unwrap();
-            let ipv4Mask = Ipv4Addr::from_str(&ipv4Cidr.mask().to_string()).unwrap();
-            let subnet = u32::from(ipv4);
-            let mask = u32::from(ipv4Mask);
-            return mask & ip == subnet;
+            for cidr in configMaps[&cidrName].value.split(",") {
+                let cidr = cidr.trim();
+                let ipv4Cidr = match Ipv4Cidr::from_str(cidr) {
+                    Ok(ipv4Cidr) => ipv4Cidr,
+                    Err(_) => continue,
+                };
+                let mut splitted = cidr.split("/");
+                let ipv4 = Ipv4Addr::from_str(splitted.next().unwrap()).unwrap();
+                let ipv4Mask = Ipv4Addr::from_str(&ipv4Cidr.mask().to_string()).unwrap();
+                let subnet = u32::from(ipv4);
+                let mask = u32::from(ipv4Mask);
+                if mask & ip == subnet {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    pub fn IsInSubnetV6(&self, ip: &Ipv6Addr, cidrName: &str) -> bool {
+        let configMaps = self.configMaps.lock();
+        if let Some(configMap) = configMaps.get(cidrName) {
+            return configMap
+                .value
+                .split(",")
+                .filter_map(|cidr| Ipv6Cidr::from_str(cidr.trim()).ok())
+                .any(|cidr| cidr.contains(ip));
         }
 
         false
@@ -338,38 +541,123 @@ pub struct Node {
     // node subnet/mask
     pub subnet: u32,
     pub netmask: u32,
+    // node subnet in the ipv6 family
+    pub subnetV6: Option<Ipv6Cidr>,
     //pub nodename: String ....
 }
 
+// PeerConnStats are the metrics of the RDMA connection to a peer node
+#[derive(Default, Debug, Clone)]
+pub struct PeerConnStats {
+    pub connects: u64,
+    pub resets: u64,
+    pub reconnects: u64,
+    // channels reset with the connection
+    pub channelsReset: u64,
+    pub heartbeatsSent: u64,
+    pub heartbeatsReceived: u64,
+    pub heartbeatTimeouts: u64,
+    // round trip time of the last heartbeat, in microseconds
+    pub lastRttUs: u64,
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct PeerConn {
+    pub stats: PeerConnStats,
+    // failed connection attempts since the last ready connection
+    pub failures: u32,
+    // monotonic time in ms to reconnect, 0 if no reconnect is pending
+    pub nextRetry: u64,
+}
+
+impl PeerConn {
+    // Backoff returns the delay of the next reconnect, doubled on each failed
+    // attempt up to RECONNECT_BACKOFF_MAX_MS
+    pub fn Backoff(&self) -> u64 {
+        return RECONNECT_BACKOFF_MIN_MS
+            .saturating_mul(1 << self.failures.min(16))
+            .min(RECONNECT_BACKOFF_MAX_MS);
+    }
+
+    // ScheduleRetry counts a failed attempt at now, in ms, and returns the
+    // delay of the reconnect
+    pub fn ScheduleRetry(&mut self, now: u64) -> u64 {
+        let backoff = self.Backoff();
+        self.failures += 1;
+        self.nextRetry = now + backoff;
+        return backoff;
+    }
+
+    // RetryDue returns whether the reconnect is due at now, in ms, a due
+    // reconnect is no longer pending
+    pub fn RetryDue(&mut self, now: u64) -> bool {
+        if self.nextRetry == 0 || self.nextRetry > now {
+            return false;
+        }
+
+        self.nextRetry = 0;
+        self.stats.reconnects += 1;
+        return true;
+    }
+
+    pub fn Connected(&mut self) {
+        self.stats.connects += 1;
+        self.failures = 0;
+        self.nextRetry = 0;
+    }
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct Pod {
     pub key: String,
     pub ip: u32,
+    pub ipv6: Option<Ipv6Addr>,
     pub node_name: String,
     pub container_id: String,
     pub resource_version: i32,
+    pub namespace: String,
+    pub labels: HashMap<String, String>,
+    // labels of the pod namespace, for the network policy namespace selectors
+    pub namespace_labels: HashMap<String, String>,
+    pub qos: PodQoS,
 }
 
 #[derive(Default, Debug, Clone)]
 pub struct Service {
     pub name: String,
     pub cluster_ip: u32,
+    pub cluster_ipv6: Option<Ipv6Addr>,
     pub ports: HashSet<Port>,
     pub resource_version: i32,
+    pub lb_policy: LBPolicy,
+    // prefer the endpoints on this node
+    pub topology_aware: bool,
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Port {
     pub protocal: String,
     pub port: u16,
 }
 
-#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub struct IpWithPort {
     pub ip: u32,
+    // the address of an ipv6 endpoint, ip is 0 then
+    pub ipv6: Option<Ipv6Addr>,
     pub port: Port,
 }
 
+impl IpWithPort {
+    // ip is in network order as the channel addresses
+    pub fn Addr(&self) -> IpAddr {
+        match self.ipv6 {
+            Some(ip) => IpAddr::V6(ip),
+            None => IpAddr::V4(Ipv4Addr::from(u32::from_be(self.ip))),
+        }
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct Endpoints {
     pub name: String,
@@ -397,3 +685,163 @@ pub struct VirtualEpInfo {
     pub vep: VirtualEp,
     pub dstEps: Vec<Endpoint>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn Endpoint(ip: &str, port: u16) -> IpWithPort {
+        let port = Port {
+            protocal: "TCP".to_string(),
+            port: port.to_be(),
+        };
+        match ip.parse::<IpAddr>().unwrap() {
+            IpAddr::V4(ip) => {
+                return IpWithPort {
+                    ip: u32::from(ip).to_be(),
+                    ipv6: None,
+                    port: port,
+                }
+            }
+            IpAddr::V6(ip) => {
+                return IpWithPort {
+                    ip: 0,
+                    ipv6: Some(ip),
+                    port: port,
+                }
+            }
+        }
+    }
+
+    // DualStack sets up the service default/web on 10.96.0.20 and fd00:96::20
+    // with the endpoints in eps
+    fn DualStack(eps: &[IpWithPort]) -> (CtrlInfo, u32, Ipv6Addr) {
+        let ctrl = CtrlInfo::default();
+        let clusterIp = u32::from(Ipv4Addr::new(10, 96, 0, 20)).to_be();
+        let clusterIpv6 = Ipv6Addr::from_str("fd00:96::20").unwrap();
+        let service = Service {
+            name: "default/web".to_string(),
+            cluster_ip: clusterIp,
+            cluster_ipv6: Some(clusterIpv6),
+            ports: [Port {
+                protocal: "TCP".to_string(),
+                port: 80u16.to_be(),
+            }]
+            .iter()
+            .cloned()
+            .collect(),
+            ..Default::default()
+        };
+        ctrl.services.lock().insert(clusterIp, service.clone());
+        ctrl.servicesV6.lock().insert(clusterIpv6, service);
+        ctrl.endpointses.lock().insert(
+            "default/web".to_string(),
+            Endpoints {
+                name: "default/web".to_string(),
+                ip_with_ports: eps.iter().cloned().collect(),
+                ..Default::default()
+            },
+        );
+        return (ctrl, clusterIp, clusterIpv6);
+    }
+
+    #[test]
+    fn test_PickEndpointDualStack() {
+        let eps = [
+            Endpoint("10.244.1.5", 8080),
+            Endpoint("10.244.2.6", 8080),
+            Endpoint("fd00:244:1::5", 8080),
+        ];
+        let (ctrl, clusterIp, clusterIpv6) = DualStack(&eps);
+        let clientIp = u32::from(Ipv4Addr::new(10, 244, 3, 7));
+        let clientIpv6 = Ipv6Addr::from_str("fd00:244:3::7").unwrap();
+
+        // the ipv4 clients go to the ipv4 endpoints in round robin
+        let picks: HashSet<IpWithPort> = (0..4)
+            .map(|_| ctrl.IsService(clusterIp, &80u16.to_be(), clientIp).unwrap())
+            .collect();
+        assert_eq!(picks, eps[..2].iter().cloned().collect());
+
+        // the ipv6 clients go to the ipv6 endpoint only
+        for _ in 0..4 {
+            assert_eq!(
+                ctrl.IsServiceV6(&clusterIpv6, &80u16.to_be(), clientIpv6),
+                Some(eps[2].clone())
+            );
+        }
+
+        // the port is not a service port
+        assert_eq!(ctrl.IsService(clusterIp, &8080u16.to_be(), clientIp), None);
+        assert_eq!(
+            ctrl.IsServiceV6(&clusterIpv6, &8080u16.to_be(), clientIpv6),
+            None
+        );
+        // not a service ip
+        let otherIp = u32::from(Ipv4Addr::new(10, 96, 0, 21)).to_be();
+        assert_eq!(ctrl.IsService(otherIp, &80u16.to_be(), clientIp), None);
+    }
+
+    #[test]
+    fn test_PeerBackoff() {
+        let mut peer = PeerConn::default();
+        let mut backoffs = Vec::new();
+        for _ in 0..10 {
+            backoffs.push(peer.ScheduleRetry(1000));
+        }
+        assert_eq!(
+            backoffs,
+            vec![500, 1000, 2000, 4000, 8000, 16000, 30000, 30000, 30000, 30000]
+        );
+        assert_eq!(peer.failures, 10);
+        assert_eq!(peer.nextRetry, 1000 + RECONNECT_BACKOFF_MAX_MS);
+
+        // the backoff stays capped however long the peer is down
+        peer.failures = u32::MAX - 1;
+        assert_eq!(peer.ScheduleRetry(0), RECONNECT_BACKOFF_MAX_MS);
+
+        // a ready connection restarts the schedule
+        peer.Connected();
+        assert_eq!(peer.failures, 0);
+        assert_eq!(peer.nextRetry, 0);
+        assert_eq!(peer.ScheduleRetry(0), RECONNECT_BACKOFF_MIN_MS);
+    }
+
+    #[test]
+    fn test_PeerRetryDue() {
+        let mut peer = PeerConn::default();
+        assert!(!peer.RetryDue(u64::MAX));
+
+        peer.ScheduleRetry(1000);
+        peer.ScheduleRetry(1000);
+        assert_eq!(peer.nextRetry, 2000);
+        assert!(!peer.RetryDue(1999));
+        assert!(peer.RetryDue(2000));
+        assert_eq!(peer.stats.reconnects, 1);
+
+        // the reconnect is due once
+        assert!(!peer.RetryDue(3000));
+        assert_eq!(peer.stats.reconnects, 1);
+        assert_eq!(peer.failures, 2);
+    }
+
+    #[test]
+    fn test_PickEndpointSingleStack() {
+        // the ipv6 clients find no endpoint of an ipv4 only service
+        let eps = [Endpoint("10.244.1.5", 8080)];
+        let (ctrl, clusterIp, clusterIpv6) = DualStack(&eps);
+        let clientIpv6 = Ipv6Addr::from_str("fd00:244:3::7").unwrap();
+        assert_eq!(
+            ctrl.IsServiceV6(&clusterIpv6, &80u16.to_be(), clientIpv6),
+            None
+        );
+        assert_eq!(
+            ctrl.IsService(clusterIp, &80u16.to_be(), 0),
+            Some(eps[0].clone())
+        );
+
+        // and the ipv4 clients none of an ipv6 only one
+        let eps = [Endpoint("fd00:244:1::5", 8080)];
+        let (ctrl, clusterIp, _) = DualStack(&eps);
+        assert_eq!(ctrl.IsService(clusterIp, &80u16.to_be(), 0), None);
+    }
+}
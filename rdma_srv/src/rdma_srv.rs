@@ -12,19 +12,23 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::common::MonotonicUs;
 use super::id_mgr::{ChannelIdMgr, IdMgr};
 use super::qlib::rdma_share::*;
+use super::qos::*;
 use super::rdma::*;
 use super::rdma_agent::*;
 use super::rdma_channel::*;
 use super::rdma_conn::*;
 use super::rdma_ctrlconn::*;
+use super::stats::SrvCounters;
 use core::sync::atomic::Ordering;
 use lazy_static::lazy_static;
 use spin::Mutex;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::CString;
 use std::mem::MaybeUninit;
+use std::net::IpAddr;
 use std::sync::Arc;
 use std::{env, mem, ptr, thread, time};
 
@@ -101,6 +105,9 @@ pub struct RDMASrv {
     // rdma connects: remote node ipaddr --> RDMAConn
     pub conns: Mutex<HashMap<u32, RDMAConn>>,
 
+    // rdma connects failed, to be reset by the service loop
+    pub brokenConns: Mutex<Vec<RDMAConn>>,
+
     // todo: tbd: need it?
     // rdma connects: virtual subnet ipaddr --> RDMAConn
     // pub vipMapping: HashMap<u32, RDMAConn>,
@@ -143,6 +150,12 @@ pub struct RDMASrv {
     pub udpBufferAllocator: Mutex<UDPBufferAllocator>,
     pub podIdToAgents: Mutex<HashMap<[u8; 64], RDMAAgent>>,
     pub ipAddrToAgents: Mutex<HashMap<u32, RDMAAgent>>,
+
+    // QoS of the pods sharing the RDMA connections
+    pub qos: Mutex<QoSMgr>,
+
+    // counters of the work completions, for the stats server
+    pub counters: SrvCounters,
 }
 
 impl Drop for RDMASrv {
@@ -246,7 +259,7 @@ impl RDMASrv {
             .CreateMemoryRegion(udpBufferAddr as u64, udpBufferSize)
             .unwrap();
         let udpQP = RDMA.CreateUDQueuePair().expect("Create UD QP failed...");
-        udpQP.SetupUDQP(&RDMA).expect("SetupUDQP fail...");
+        udpQP.SetupUDQP().expect("SetupUDQP fail...");
 
         for i in 0..RECV_UDP_COUNT {
             let addr = udpBufferAddr as u64 + (i * udpPacketExtendedSize as u32) as u64;
@@ -275,6 +288,7 @@ impl RDMASrv {
                 len: udpBufferSize as u64,
             },
             conns: Mutex::new(HashMap::new()),
+            brokenConns: Mutex::new(Vec::new()),
             channels: Mutex::new(HashMap::new()),
             agents: Mutex::new(HashMap::new()),
             shareRegion: unsafe {
@@ -305,6 +319,8 @@ impl RDMASrv {
             udpBufferAllocator,
             ipAddrToAgents: Mutex::new(HashMap::new()),
             podIdToAgents: Mutex::new(HashMap::new()),
+            qos: Mutex::new(QoSMgr::New()),
+            counters: SrvCounters::default(),
         };
     }
 
@@ -343,7 +359,8 @@ impl RDMASrv {
         } else {
             match self.controlChannels.lock().get(&qpNum) {
                 None => {
-                    panic!("ProcessRDMAWriteImmFinish get unexpected qpNum: {}", qpNum);
+                    // the connection of the queue pair is reset
+                    error!("ProcessRDMAWriteImmFinish get unexpected qpNum: {}", qpNum);
                 }
                 Some(channel) => {
                     channel.ProcessRDMAWriteImmFinish();
@@ -385,7 +402,7 @@ impl RDMASrv {
             match RDMA_SRV.controlChannels.lock().get(&qpNum) {
                 //where lock end??
                 None => {
-                    panic!("ProcessRDMAWriteImmFinish get unexpected qpNum: {}", qpNum);
+                    error!("ProcessRDMARecvWriteImm get unexpected qpNum: {}", qpNum);
                 }
                 Some(channel) => {
                     channel.ProcessRDMARecvWriteImm(qpNum, recvCount as u64);
@@ -526,9 +543,115 @@ impl RDMASrv {
 
     // }
 
+    // HandleWCError handles a failed work request: the RC queue pair is in
+    // error state, so its connection is reset.
+    pub fn HandleWCError(&self, qpNum: u32, wrId: u64, status: u32) {
+        if qpNum == self.udpQP.qpNum() {
+            error!("UD work request fail, wrId: {}, status: {}", wrId, status);
+            return;
+        }
+
+        let conn = match self.controlChannels2.lock().get(&qpNum) {
+            Some(channel) => channel.conn.clone(),
+            None => return, // the connection is already reset
+        };
+        error!(
+            "work request to node {} fail, qpNum: {}, wrId: {}, status: {}",
+            conn.nodeIp, qpNum, wrId, status
+        );
+        conn.MarkBroken();
+    }
+
+    // ResetBrokenConns resets the connections marked broken, and schedules the
+    // reconnect to the peer nodes this node connects to.
+    pub fn ResetBrokenConns(&self) {
+        let conns: Vec<RDMAConn> = {
+            let mut brokenConns = self.brokenConns.lock();
+            if brokenConns.len() == 0 {
+                return;
+            }
+            brokenConns.drain(..).collect()
+        };
+
+        for conn in conns {
+            let channelCount = conn.Reset();
+            error!(
+                "reset RDMA connection to node {}, {} channels are reset",
+                conn.nodeIp, channelCount
+            );
+            RDMA_CTLINFO.peer_update(conn.nodeIp, |peer| {
+                peer.stats.resets += 1;
+                peer.stats.channelsReset += channelCount as u64;
+            });
+
+            if ShouldReconnect(conn.nodeIp, &RDMA_CTLINFO.get_node_ips_for_connecting()) {
+                RDMA_CTLINFO.peer_reconnect_schedule(conn.nodeIp);
+            }
+        }
+    }
+
+    // Heartbeat sends heartbeats over the connections to the peer nodes
+    pub fn Heartbeat(&self) {
+        let now = MonotonicUs();
+        let conns: Vec<RDMAConn> = self.conns.lock().values().cloned().collect();
+        for conn in conns {
+            conn.Heartbeat(now);
+        }
+    }
+
+    // QoSTimer refills the bandwidth of the pods and resumes the channels
+    // throttled by the bandwidth caps
+    pub fn QoSTimer(&self) {
+        let resumed = self.qos.lock().Refill(MonotonicUs());
+        for channelId in resumed {
+            let rdmaChannel = match self.channels.lock().get(&channelId) {
+                Some(rdmaChannel) => rdmaChannel.clone(),
+                None => continue,
+            };
+            rdmaChannel.RDMASend();
+        }
+    }
+
+    // SetAgentQoS applies the QoS of the pod to the agent serving it
+    pub fn SetAgentQoS(&self, agentId: u32, pod: &Pod) {
+        self.qos
+            .lock()
+            .SetPodQoS(agentId, &pod.key, pod.qos, MonotonicUs());
+    }
+
     pub fn ExistsConnection(&self, ip: &u32) -> bool {
         self.conns.lock().contains_key(ip)
     }
+
+    // ChannelCounts returns the number of channels to each (ip, port)
+    pub fn ChannelCounts(&self) -> HashMap<(IpAddr, u16), usize> {
+        let mut counts = HashMap::new();
+        for (_, channel) in self.channels.lock().iter() {
+            *counts.entry((channel.DstAddr(), channel.dstPort)).or_insert(0) += 1;
+        }
+
+        return counts;
+    }
+}
+
+// ConnChannels returns the channels carried by a broken connection, ordered by
+// channel id, the channels on the other connections are kept. They are reset
+// after the channels lock is dropped as the reset may release the channel.
+pub fn ConnChannels<T: Clone, F: Fn(&T) -> bool>(channels: &HashMap<u32, T>, onConn: F) -> Vec<T> {
+    let mut ids: Vec<u32> = channels
+        .iter()
+        .filter(|(_, channel)| onConn(channel))
+        .map(|(id, _)| *id)
+        .collect();
+    ids.sort();
+    return ids.iter().map(|id| channels[id].clone()).collect();
+}
+
+// ShouldReconnect returns whether the peer node of a reset connection is
+// reconnected: only the nodes this node connects to are, the others connect
+// back to this node.
+pub fn ShouldReconnect(nodeIp: u32, connecting: &HashSet<u32>) -> bool {
+    return nodeIp != 0 && connecting.contains(&nodeIp);
 }
 
 // scenarios:
@@ -544,3 +667,81 @@ impl RDMASrv {
 //      2. tcp connection close
 //      3. rdma connection disconnect (keepalive?)
 // request/response type
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Channel stands for an RDMA channel on the connection conn
+    #[derive(Clone, Debug, PartialEq)]
+    struct Channel {
+        id: u32,
+        conn: u32,
+    }
+
+    fn Channels(conns: &[u32]) -> HashMap<u32, Channel> {
+        let mut channels = HashMap::new();
+        for (i, conn) in conns.iter().enumerate() {
+            let id = i as u32 + 1;
+            let channel = Channel {
+                id: id,
+                conn: *conn,
+            };
+            channels.insert(id, channel);
+        }
+        return channels;
+    }
+
+    #[test]
+    fn test_ConnChannels() {
+        let channels = Channels(&[1, 2, 1, 3, 1, 2]);
+        let ids = |conn: u32| -> Vec<u32> {
+            ConnChannels(&channels, |channel| channel.conn == conn)
+                .iter()
+                .map(|channel| channel.id)
+                .collect()
+        };
+        assert_eq!(ids(1), vec![1, 3, 5]);
+        assert_eq!(ids(2), vec![2, 6]);
+        assert_eq!(ids(3), vec![4]);
+        assert!(ids(4).is_empty());
+        assert!(ConnChannels(&HashMap::<u32, Channel>::new(), |_| true).is_empty());
+    }
+
+    #[test]
+    fn test_ResetBrokenConn() {
+        // the clients of the channels on the broken connection get a fin
+        // notification, the other channels are kept
+        let mut channels = Channels(&[7, 8, 7, 8]);
+        let mut responses = Vec::new();
+        for channel in ConnChannels(&channels, |channel| channel.conn == 7) {
+            responses.push(ResetResponse(channel.id));
+            channels.remove(&channel.id);
+        }
+
+        let notified: Vec<(u32, EventMask)> = responses
+            .iter()
+            .map(|resp| match resp.msg {
+                RDMARespMsg::RDMAFinNotify(fin) => (fin.channelId, fin.event),
+                _ => panic!("unexpected response {:?}", resp),
+            })
+            .collect();
+        assert_eq!(notified, vec![(1, CONNECTION_RESET), (3, CONNECTION_RESET)]);
+        assert!(responses.iter().all(|resp| resp.user_data == 0));
+        let kept: Vec<u32> = channels.keys().cloned().collect();
+        assert_eq!(kept.len(), 2);
+        assert!(kept.iter().all(|id| channels[id].conn == 8));
+    }
+
+    #[test]
+    fn test_ShouldReconnect() {
+        let connecting: HashSet<u32> = [10, 11].iter().cloned().collect();
+        assert!(ShouldReconnect(10, &connecting));
+        assert!(ShouldReconnect(11, &connecting));
+        // the peer connects back to this node
+        assert!(!ShouldReconnect(12, &connecting));
+        // the connection accepted from the local node
+        assert!(!ShouldReconnect(0, &connecting));
+        assert!(!ShouldReconnect(10, &HashSet::new()));
+    }
+}
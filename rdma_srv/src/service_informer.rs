@@ -14,12 +14,15 @@
 
 use crate::common::*;
 use super::constants::*;
+use crate::load_balancer::*;
 use crate::rdma_ctrlconn::*;
 use crate::RDMA_CTLINFO;
 use svc_client::quark_cm_service_client::QuarkCmServiceClient;
 use svc_client::MaxResourceVersionMessage;
 use svc_client::ServiceMessage;
 use std::collections::HashSet;
+use std::net::Ipv6Addr;
+use std::str::FromStr;
 use tokio::time::*;
 use tonic::Request;
 
@@ -81,10 +84,13 @@ impl ServiceInformer {
         Ok(())
     }
 
-    fn handle(&mut self, service_message: &ServiceMessage) {
+    pub fn handle(&mut self, service_message: &ServiceMessage) {
         let name = &service_message.name;
         let ip = &service_message.cluster_ip.to_be();
         let mut services_map = RDMA_CTLINFO.services.lock();        
+        // empty for a service without an ipv6 cluster ip
+        let ipv6 = Ipv6Addr::from_str(&service_message.cluster_ipv6).ok();
+        let mut servicesV6_map = RDMA_CTLINFO.servicesV6.lock();
         if service_message.event_type == EVENT_TYPE_SET {
             let mut ports = HashSet::new();
             for portStr in &service_message.ports {
@@ -98,14 +104,29 @@ impl ServiceInformer {
             let service = Service {
                 name: name.clone(),
                 cluster_ip: ip.clone(),
+                cluster_ipv6: ipv6,
                 ports : ports,
                 resource_version: service_message.resource_version,
+                lb_policy: LBPolicy::FromService(&service_message.annotations, &service_message.session_affinity),
+                topology_aware: TopologyAware(&service_message.annotations),
             };
-            services_map.insert(ip.clone(), service);
+            if let Some(ipv6) = ipv6 {
+                servicesV6_map.insert(ipv6, service.clone());
+            }
+            if *ip != 0 {
+                services_map.insert(ip.clone(), service);
+            }
             if service_message.resource_version > self.max_resource_version {
                 self.max_resource_version = service_message.resource_version;
             }
         } else if service_message.event_type == EVENT_TYPE_DELETE {
+            if let Some(ipv6) = ipv6 {
+                if let Some(service) = servicesV6_map.get(&ipv6) {
+                    if service.resource_version < service_message.resource_version {
+                        servicesV6_map.remove(&ipv6);
+                    }
+                }
+            }
             if services_map.contains_key(ip) {
                 if services_map[ip].resource_version < service_message.resource_version {
                     services_map.remove(ip);
@@ -115,6 +136,7 @@ impl ServiceInformer {
         if service_message.resource_version > self.max_resource_version {
             self.max_resource_version = service_message.resource_version;
         }
+        RDMA_CTLINFO.resource_version_set("service", self.max_resource_version);
         println!("Handled Service: {:?}", service_message);
         println!("Debug: services_map len:{} {:?}", services_map.len(), services_map);
     }
@@ -0,0 +1,926 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The software RDMA device emulates the verbs used by the RDMA service over
+// TCP, so that TSoR runs on the machines without an RDMA NIC. The endpoint of
+// a device is advertised in the lid (TCP port) and gid (IPv4-mapped address)
+// of the RDMAInfo exchanged by RDMAConn. A work request is carried to the peer
+// device as a SoftFrame followed by its payload, the receiving thread of the
+// peer copies the payload into the registered memory and queues the work
+// completion, which is signaled on an eventfd in place of the completion
+// channel. The devices authenticate each other with a challenge-response on
+// the secret shared by the cluster before exchanging the work requests.
+
+use alloc::sync::Arc;
+use core::ops::Deref;
+use hmac::{Hmac, Mac};
+use local_ip_address::local_ip;
+use rdmaffi;
+use sha2::Sha256;
+use spin::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddrV4, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use std::{ptr, slice, thread};
+
+use super::constants::*;
+use super::qlib::common::*;
+use super::qlib::linux_def::*;
+use super::rdma::*;
+
+// a UD receive starts with the 40 bytes GRH, as on an RDMA NIC
+pub const SOFT_RDMA_GRH_SIZE: u32 = 40;
+
+const SOFT_OP_WRITE_IMM: u32 = 1;
+const SOFT_OP_SEND: u32 = 2;
+
+const SOFT_FRAME_SIZE: usize = 32;
+
+// the largest datagram of a UD queue pair, as the largest MTU of a NIC
+const SOFT_RDMA_UD_MTU: u32 = 4096;
+
+const SOFT_NONCE_SIZE: usize = 16;
+const SOFT_MAC_SIZE: usize = 32;
+const SOFT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+type HmacSha256 = Hmac<Sha256>;
+
+// SoftRDMAEnabled returns whether the RDMA service runs on the software device
+pub fn SoftRDMAEnabled() -> bool {
+    match env::var(RDMA_TRANSPORT_ENV) {
+        Ok(transport) => return transport == RDMA_TRANSPORT_SOFT,
+        Err(_) => return false,
+    }
+}
+
+// SoftRDMAConfig is the endpoint of the software device and the secret shared
+// by the devices of the cluster.
+#[derive(Debug, Clone)]
+pub struct SoftRDMAConfig {
+    // the address advertised to the peers, the device listens on it
+    pub addr: Ipv4Addr,
+    // 0 is any free port
+    pub port: u16,
+    pub secret: Vec<u8>,
+}
+
+impl SoftRDMAConfig {
+    pub fn FromEnv() -> Result<Self> {
+        let addr = match env::var(SOFT_RDMA_ADDR_ENV) {
+            Ok(addr) => Ipv4Addr::from_str(&addr)
+                .map_err(|_| Error::Common(format!("invalid software RDMA address {}", addr)))?,
+            Err(_) => match local_ip() {
+                Ok(IpAddr::V4(addr)) => addr,
+                _ => Ipv4Addr::LOCALHOST,
+            },
+        };
+
+        let port = match env::var(SOFT_RDMA_PORT_ENV) {
+            Ok(port) => port
+                .parse::<u16>()
+                .map_err(|_| Error::Common(format!("invalid software RDMA port {}", port)))?,
+            Err(_) => 0,
+        };
+
+        let secretFile = env::var(SOFT_RDMA_SECRET_FILE_ENV).map_err(|_| {
+            Error::Common(format!(
+                "the software RDMA device needs a secret, {} is not set",
+                SOFT_RDMA_SECRET_FILE_ENV
+            ))
+        })?;
+        let secret = fs::read(&secretFile).map_err(|e| {
+            Error::Common(format!(
+                "fail to read the software RDMA secret {}: {:?}",
+                secretFile, e
+            ))
+        })?;
+        let secret = String::from_utf8_lossy(&secret).trim().as_bytes().to_vec();
+        if secret.len() == 0 {
+            return Err(Error::Common(format!(
+                "the software RDMA secret {} is empty",
+                secretFile
+            )));
+        }
+
+        return Ok(Self {
+            addr: addr,
+            port: port,
+            secret: secret,
+        });
+    }
+}
+
+fn IOError(e: std::io::Error) -> Error {
+    return Error::SysError(e.raw_os_error().unwrap_or(SysErr::EIO));
+}
+
+// HandshakeMac is the proof of the secret of a side of the handshake, the
+// role keeps a proof of one side from being replayed as the other side.
+fn HandshakeMac(secret: &[u8], role: &[u8], nonces: &[&[u8]]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("hmac takes a key of any size");
+    mac.update(b"quark-soft-rdma");
+    mac.update(role);
+    for nonce in nonces {
+        mac.update(nonce);
+    }
+
+    return mac;
+}
+
+// Handshake authenticates the peer device on a new connection:
+//   connector -> acceptor: nonceC
+//   acceptor -> connector: nonceA, mac("acceptor", nonceC, nonceA)
+//   connector -> acceptor: mac("connector", nonceA, nonceC)
+fn Handshake(stream: &mut TcpStream, secret: &[u8], connector: bool) -> Result<()> {
+    stream
+        .set_read_timeout(Some(SOFT_HANDSHAKE_TIMEOUT))
+        .map_err(IOError)?;
+
+    let nonce: [u8; SOFT_NONCE_SIZE] = rand::random();
+    let mut peerNonce = [0u8; SOFT_NONCE_SIZE];
+    let mut peerMac = [0u8; SOFT_MAC_SIZE];
+    if connector {
+        stream.write_all(&nonce).map_err(IOError)?;
+        stream.read_exact(&mut peerNonce).map_err(IOError)?;
+        stream.read_exact(&mut peerMac).map_err(IOError)?;
+        HandshakeMac(secret, b"acceptor", &[&nonce, &peerNonce])
+            .verify_slice(&peerMac)
+            .map_err(|_| Error::SysError(SysErr::EACCES))?;
+        let mac = HandshakeMac(secret, b"connector", &[&peerNonce, &nonce]);
+        stream
+            .write_all(&mac.finalize().into_bytes())
+            .map_err(IOError)?;
+    } else {
+        stream.read_exact(&mut peerNonce).map_err(IOError)?;
+        let mac = HandshakeMac(secret, b"acceptor", &[&peerNonce, &nonce]);
+        stream.write_all(&nonce).map_err(IOError)?;
+        stream
+            .write_all(&mac.finalize().into_bytes())
+            .map_err(IOError)?;
+        stream.read_exact(&mut peerMac).map_err(IOError)?;
+        HandshakeMac(secret, b"connector", &[&nonce, &peerNonce])
+            .verify_slice(&peerMac)
+            .map_err(|_| Error::SysError(SysErr::EACCES))?;
+    }
+
+    stream.set_read_timeout(None).map_err(IOError)?;
+    return Ok(());
+}
+
+// SoftFrame is the header of a work request carried to the peer device
+#[derive(Default, Debug, Clone, Copy)]
+pub struct SoftFrame {
+    pub opcode: u32,
+    pub dstQpn: u32,
+    pub srcQpn: u32,
+    pub imm: u32,
+    pub raddr: u64,
+    pub rkey: u32,
+    pub len: u32,
+}
+
+impl SoftFrame {
+    pub fn Encode(&self) -> [u8; SOFT_FRAME_SIZE] {
+        let mut buf = [0u8; SOFT_FRAME_SIZE];
+        buf[0..4].copy_from_slice(&self.opcode.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.dstQpn.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.srcQpn.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.imm.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.raddr.to_le_bytes());
+        buf[24..28].copy_from_slice(&self.rkey.to_le_bytes());
+        buf[28..32].copy_from_slice(&self.len.to_le_bytes());
+        return buf;
+    }
+
+    pub fn Decode(buf: &[u8; SOFT_FRAME_SIZE]) -> Self {
+        let u32At = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+        let mut raddr = [0u8; 8];
+        raddr.copy_from_slice(&buf[16..24]);
+        return Self {
+            opcode: u32At(0),
+            dstQpn: u32At(4),
+            srcQpn: u32At(8),
+            imm: u32At(12),
+            raddr: u64::from_le_bytes(raddr),
+            rkey: u32At(24),
+            len: u32At(28),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SoftRecv {
+    pub wrId: u64,
+    pub addr: u64,
+    pub lkey: u32,
+    pub length: u32,
+}
+
+// SoftQPState holds the posted receives and the write with immediate which
+// arrived before a receive was posted. A NIC would retry those (RNR), here
+// they wait for the next PostRecv.
+#[derive(Default)]
+pub struct SoftQPState {
+    pub recvs: VecDeque<SoftRecv>,
+    pub writeImms: VecDeque<(u32, u32)>,
+}
+
+pub struct SoftQueuePairIntern {
+    pub qpNum: u32,
+    pub qpType: QueuePairType,
+    // peer device endpoint and queue pair number, set by SetupRCQP
+    pub remote: Mutex<Option<(SocketAddrV4, u32)>>,
+    pub state: Mutex<SoftQPState>,
+}
+
+pub struct SoftDeviceIntern {
+    pub addr: SocketAddrV4,
+    pub secret: Vec<u8>,
+    pub eventfd: i32,
+    pub nextQpNum: AtomicU32,
+    pub qps: Mutex<HashMap<u32, Arc<SoftQueuePairIntern>>>,
+    // key -> (addr, len) of the registered memory, a region has the same lkey and rkey
+    pub mrs: Mutex<HashMap<u32, (u64, usize)>>,
+    pub peers: Mutex<HashMap<SocketAddrV4, Arc<Mutex<TcpStream>>>>,
+    pub completions: Mutex<VecDeque<rdmaffi::ibv_wc>>,
+}
+
+impl SoftDeviceIntern {
+    pub fn Complete(&self, wc: rdmaffi::ibv_wc) {
+        self.completions.lock().push_back(wc);
+        let data: u64 = 1;
+        let ret = unsafe { libc::write(self.eventfd, &data as *const _ as *const libc::c_void, 8) };
+        if ret < 0 {
+            error!(
+                "SoftDevice::Complete, fail to signal eventfd: {}",
+                errno::errno().0
+            );
+        }
+    }
+
+    pub fn CompleteWR(&self, opcode: u32, wrId: u64, qpNum: u32, len: u32, imm: u32, srcQp: u32) {
+        let mut wc = EmptyWorkCompletion();
+        wc.opcode = opcode;
+        wc.wr_id = wrId;
+        wc.qp_num = qpNum;
+        wc.byte_len = len;
+        wc.imm_data_invalidated_rkey_union.imm_data = imm;
+        wc.src_qp = srcQp;
+        self.Complete(wc);
+    }
+
+    // InRegion returns whether [addr, addr + len) is in the memory region of key
+    pub fn InRegion(&self, key: u32, addr: u64, len: u32) -> bool {
+        match self.mrs.lock().get(&key) {
+            None => return false,
+            Some((start, size)) => {
+                let end = match addr.checked_add(len as u64) {
+                    None => return false,
+                    Some(end) => end,
+                };
+                let regionEnd = match start.checked_add(*size as u64) {
+                    None => return false,
+                    Some(end) => end,
+                };
+                return addr >= *start && end <= regionEnd;
+            }
+        }
+    }
+
+    pub fn Peer(&self, peer: SocketAddrV4) -> Result<Arc<Mutex<TcpStream>>> {
+        if let Some(stream) = self.peers.lock().get(&peer) {
+            return Ok(stream.clone());
+        }
+
+        let mut stream = TcpStream::connect(peer).map_err(IOError)?;
+        stream.set_nodelay(true).map_err(IOError)?;
+        if let Err(e) = Handshake(&mut stream, &self.secret, true) {
+            error!("SoftDevice::Peer, fail to authenticate {}: {:?}", peer, e);
+            return Err(e);
+        }
+        let stream = Arc::new(Mutex::new(stream));
+        self.peers.lock().insert(peer, stream.clone());
+        return Ok(stream);
+    }
+
+    pub fn Send(&self, peer: SocketAddrV4, frame: &SoftFrame, laddr: u64) -> Result<()> {
+        let stream = self.Peer(peer)?;
+        let payload = unsafe { slice::from_raw_parts(laddr as *const u8, frame.len as usize) };
+        let mut stream = stream.lock();
+        let res = stream
+            .write_all(&frame.Encode())
+            .and_then(|_| stream.write_all(payload));
+        if let Err(e) = res {
+            // reconnect on the next work request
+            self.peers.lock().remove(&peer);
+            return Err(IOError(e));
+        }
+
+        return Ok(());
+    }
+
+    pub fn Accept(self: Arc<Self>, listener: TcpListener) {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let _ = stream.set_nodelay(true);
+                    let device = self.clone();
+                    thread::spawn(move || device.Receive(stream));
+                }
+                Err(e) => {
+                    error!("SoftDevice::Accept, error: {:?}", e);
+                }
+            }
+        }
+    }
+
+    pub fn Receive(&self, mut stream: TcpStream) {
+        if let Err(e) = Handshake(&mut stream, &self.secret, false) {
+            error!(
+                "SoftDevice::Receive, fail to authenticate {:?}: {:?}",
+                stream.peer_addr(),
+                e
+            );
+            return;
+        }
+
+        let mut header = [0u8; SOFT_FRAME_SIZE];
+        let mut payload = Vec::new();
+        loop {
+            if stream.read_exact(&mut header).is_err() {
+                return;
+            }
+
+            // the payload goes to the registered memory, a frame which can't
+            // fit it breaks the connection before the payload is buffered
+            let frame = SoftFrame::Decode(&header);
+            let fits = match frame.opcode {
+                SOFT_OP_WRITE_IMM => self.InRegion(frame.rkey, frame.raddr, frame.len),
+                SOFT_OP_SEND => frame.len <= SOFT_RDMA_UD_MTU,
+                _ => false,
+            };
+            if !fits {
+                error!(
+                    "SoftDevice::Receive, invalid frame {:?} from {:?}",
+                    frame,
+                    stream.peer_addr()
+                );
+                return;
+            }
+
+            payload.resize(frame.len as usize, 0);
+            if stream.read_exact(&mut payload).is_err() {
+                return;
+            }
+
+            match frame.opcode {
+                SOFT_OP_WRITE_IMM => self.DeliverWriteImm(&frame, &payload),
+                _ => self.DeliverSend(&frame, &payload),
+            }
+        }
+    }
+
+    pub fn DeliverWriteImm(&self, frame: &SoftFrame, payload: &[u8]) {
+        if !self.InRegion(frame.rkey, frame.raddr, frame.len) {
+            error!(
+                "SoftDevice::DeliverWriteImm, write out of the memory region, rkey: {}, raddr: 0x{:x}, len: {}",
+                frame.rkey, frame.raddr, frame.len
+            );
+            return;
+        }
+
+        let qp = match self.qps.lock().get(&frame.dstQpn) {
+            None => {
+                error!("SoftDevice::DeliverWriteImm, no qp {}", frame.dstQpn);
+                return;
+            }
+            Some(qp) => qp.clone(),
+        };
+
+        unsafe {
+            ptr::copy_nonoverlapping(payload.as_ptr(), frame.raddr as *mut u8, payload.len());
+        }
+
+        let mut state = qp.state.lock();
+        match state.recvs.pop_front() {
+            Some(recv) => self.CompleteWR(
+                rdmaffi::ibv_wc_opcode::IBV_WC_RECV_RDMA_WITH_IMM,
+                recv.wrId,
+                qp.qpNum,
+                frame.len,
+                frame.imm,
+                frame.srcQpn,
+            ),
+            None => state.writeImms.push_back((frame.len, frame.imm)),
+        }
+    }
+
+    pub fn DeliverSend(&self, frame: &SoftFrame, payload: &[u8]) {
+        let qp = match self.qps.lock().get(&frame.dstQpn) {
+            None => {
+                error!("SoftDevice::DeliverSend, no qp {}", frame.dstQpn);
+                return;
+            }
+            Some(qp) => qp.clone(),
+        };
+
+        // UD is unreliable, the datagram is dropped when there is no receive
+        let recv = match qp.state.lock().recvs.pop_front() {
+            None => {
+                debug!(
+                    "SoftDevice::DeliverSend, no receive posted on qp {}",
+                    qp.qpNum
+                );
+                return;
+            }
+            Some(recv) => recv,
+        };
+
+        let len = frame.len + SOFT_RDMA_GRH_SIZE;
+        if len > recv.length || !self.InRegion(recv.lkey, recv.addr, len) {
+            error!(
+                "SoftDevice::DeliverSend, datagram of {} bytes doesn't fit the receive of {} bytes",
+                frame.len, recv.length
+            );
+            return;
+        }
+
+        unsafe {
+            ptr::copy_nonoverlapping(
+                payload.as_ptr(),
+                (recv.addr + SOFT_RDMA_GRH_SIZE as u64) as *mut u8,
+                payload.len(),
+            );
+        }
+
+        self.CompleteWR(
+            rdmaffi::ibv_wc_opcode::IBV_WC_RECV,
+            recv.wrId,
+            qp.qpNum,
+            len,
+            0,
+            frame.srcQpn,
+        );
+    }
+}
+
+#[derive(Clone)]
+pub struct SoftDevice(Arc<SoftDeviceIntern>);
+
+impl Deref for SoftDevice {
+    type Target = Arc<SoftDeviceIntern>;
+
+    fn deref(&self) -> &Arc<SoftDeviceIntern> {
+        &self.0
+    }
+}
+
+impl SoftDevice {
+    pub fn New(config: &SoftRDMAConfig) -> Result<Self> {
+        let listener =
+            TcpListener::bind(SocketAddrV4::new(config.addr, config.port)).map_err(|e| {
+                Error::Common(format!(
+                    "SoftDevice fail to listen on {}:{}: {:?}",
+                    config.addr, config.port, e
+                ))
+            })?;
+        let port = listener.local_addr().map_err(IOError)?.port();
+        let eventfd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK) };
+        if eventfd < 0 {
+            return Err(Error::SysError(errno::errno().0));
+        }
+
+        let intern = Arc::new(SoftDeviceIntern {
+            addr: SocketAddrV4::new(config.addr, port),
+            secret: config.secret.clone(),
+            eventfd: eventfd,
+            nextQpNum: AtomicU32::new(1),
+            qps: Mutex::new(HashMap::new()),
+            mrs: Mutex::new(HashMap::new()),
+            peers: Mutex::new(HashMap::new()),
+            completions: Mutex::new(VecDeque::new()),
+        });
+
+        println!("Software RDMA device listens on {}", intern.addr);
+        let device = intern.clone();
+        thread::spawn(move || device.Accept(listener));
+        return Ok(Self(intern));
+    }
+}
+
+impl RDMAVerbs for SoftDevice {
+    fn Lid(&self) -> u16 {
+        return self.addr.port();
+    }
+
+    fn Gid(&self) -> Gid {
+        return Gid::FromIpv4(*self.addr.ip());
+    }
+
+    fn CreateAddressHandler(&self, _port_num: u8, lid: u16, gid: Gid) -> Result<AddressHandler> {
+        return Ok(AddressHandler::SoftAddressHandler(SoftAddressHandler(
+            SocketAddrV4::new(gid.Ipv4(), lid),
+        )));
+    }
+
+    fn CreateQueuePair(&self, qpType: QueuePairType) -> Result<QueuePair> {
+        let qp = Arc::new(SoftQueuePairIntern {
+            qpNum: self.nextQpNum.fetch_add(1, Ordering::SeqCst),
+            qpType: qpType,
+            remote: Mutex::new(None),
+            state: Mutex::new(SoftQPState::default()),
+        });
+        self.qps.lock().insert(qp.qpNum, qp.clone());
+        return Ok(QueuePair::SoftQueuePair(SoftQueuePair {
+            qp: qp,
+            device: self.0.clone(),
+        }));
+    }
+
+    fn CreateMemoryRegion(&self, addr: u64, size: usize) -> Result<MemoryRegion> {
+        // the keys are random so that a peer can't guess the other regions
+        let mut mrs = self.mrs.lock();
+        let mut key: u32 = rand::random();
+        while key == 0 || mrs.contains_key(&key) {
+            key = rand::random();
+        }
+        mrs.insert(key, (addr, size));
+        drop(mrs);
+        return Ok(MemoryRegion::SoftMemoryRegion(SoftMemoryRegion {
+            key: key,
+            device: self.0.clone(),
+        }));
+    }
+
+    fn CompleteChannelFd(&self) -> i32 {
+        return self.eventfd;
+    }
+
+    fn PollCQ(&self, wc: &mut rdmaffi::ibv_wc) -> bool {
+        match self.completions.lock().pop_front() {
+            None => return false,
+            Some(c) => {
+                *wc = c;
+                return true;
+            }
+        }
+    }
+
+    fn HandleCQEvent(&self) -> Result<()> {
+        let mut data: u64 = 0;
+        let ret = unsafe { libc::read(self.eventfd, &mut data as *mut _ as *mut libc::c_void, 8) };
+        if ret < 0 && errno::errno().0 != SysErr::EAGAIN {
+            return Err(Error::SysError(errno::errno().0));
+        }
+
+        return Ok(());
+    }
+}
+
+pub struct SoftAddressHandler(pub SocketAddrV4);
+
+pub struct SoftQueuePair {
+    pub qp: Arc<SoftQueuePairIntern>,
+    pub device: Arc<SoftDeviceIntern>,
+}
+
+impl Drop for SoftQueuePair {
+    fn drop(&mut self) {
+        self.device.qps.lock().remove(&self.qp.qpNum);
+    }
+}
+
+impl QueuePairOps for SoftQueuePair {
+    fn qpNum(&self) -> u32 {
+        return self.qp.qpNum;
+    }
+
+    fn WriteImm(
+        &self,
+        wrId: u64,
+        laddr: u64,
+        len: u32,
+        lkey: u32,
+        raddr: u64,
+        rkey: u32,
+        imm: u32,
+    ) -> Result<()> {
+        let (peer, remoteQpn) = match *self.qp.remote.lock() {
+            None => return Err(Error::SysError(SysErr::EINVAL)),
+            Some(remote) => remote,
+        };
+
+        if !self.device.InRegion(lkey, laddr, len) {
+            return Err(Error::SysError(SysErr::EINVAL));
+        }
+
+        let frame = SoftFrame {
+            opcode: SOFT_OP_WRITE_IMM,
+            dstQpn: remoteQpn,
+            srcQpn: self.qp.qpNum,
+            imm: imm,
+            raddr: raddr,
+            rkey: rkey,
+            len: len,
+        };
+        self.device.Send(peer, &frame, laddr)?;
+        self.device.CompleteWR(
+            rdmaffi::ibv_wc_opcode::IBV_WC_RDMA_WRITE,
+            wrId,
+            self.qp.qpNum,
+            len,
+            0,
+            0,
+        );
+        return Ok(());
+    }
+
+    fn PostRecv(&self, wrId: u64, addr: u64, lkey: u32, length: u32) -> Result<()> {
+        let mut state = self.qp.state.lock();
+        if let Some((len, imm)) = state.writeImms.pop_front() {
+            self.device.CompleteWR(
+                rdmaffi::ibv_wc_opcode::IBV_WC_RECV_RDMA_WITH_IMM,
+                wrId,
+                self.qp.qpNum,
+                len,
+                imm,
+                0,
+            );
+            return Ok(());
+        }
+
+        state.recvs.push_back(SoftRecv {
+            wrId: wrId,
+            addr: addr,
+            lkey: lkey,
+            length: length,
+        });
+        return Ok(());
+    }
+
+    fn PostSendUDQP(
+        &self,
+        ah: &AddressHandler,
+        remote_qpn: u32,
+        wrId: u64,
+        laddr: u64,
+        len: u32,
+        lkey: u32,
+    ) -> Result<()> {
+        let peer = match ah {
+            AddressHandler::SoftAddressHandler(ah) => ah.0,
+            _ => return Err(Error::SysError(SysErr::EINVAL)),
+        };
+
+        if !self.device.InRegion(lkey, laddr, len) {
+            return Err(Error::SysError(SysErr::EINVAL));
+        }
+
+        let frame = SoftFrame {
+            opcode: SOFT_OP_SEND,
+            dstQpn: remote_qpn,
+            srcQpn: self.qp.qpNum,
+            len: len,
+            ..Default::default()
+        };
+        self.device.Send(peer, &frame, laddr)?;
+        self.device.CompleteWR(
+            rdmaffi::ibv_wc_opcode::IBV_WC_SEND,
+            wrId,
+            self.qp.qpNum,
+            len,
+            0,
+            0,
+        );
+        return Ok(());
+    }
+
+    fn SetupRCQP(&self, remote_qpn: u32, dlid: u16, dgid: Gid) -> Result<()> {
+        *self.qp.remote.lock() = Some((SocketAddrV4::new(dgid.Ipv4(), dlid), remote_qpn));
+        return Ok(());
+    }
+
+    fn SetupUDQP(&self) -> Result<()> {
+        return Ok(());
+    }
+}
+
+pub struct SoftMemoryRegion {
+    pub key: u32,
+    pub device: Arc<SoftDeviceIntern>,
+}
+
+impl Drop for SoftMemoryRegion {
+    fn drop(&mut self) {
+        self.device.mrs.lock().remove(&self.key);
+    }
+}
+
+impl MemoryRegionOps for SoftMemoryRegion {
+    fn LKey(&self) -> u32 {
+        return self.key;
+    }
+
+    fn RKey(&self) -> u32 {
+        return self.key;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    const SECRET: &str = "cluster secret";
+
+    // Device starts a software device on the loopback as an rdma_srv would
+    fn Device(secret: &str) -> SoftDevice {
+        let config = SoftRDMAConfig {
+            addr: Ipv4Addr::LOCALHOST,
+            port: 0,
+            secret: secret.as_bytes().to_vec(),
+        };
+        return SoftDevice::New(&config).unwrap();
+    }
+
+    fn WaitCompletion(device: &SoftDevice, timeout: Duration) -> Option<rdmaffi::ibv_wc> {
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            let mut wc = EmptyWorkCompletion();
+            if device.PollCQ(&mut wc) {
+                return Some(wc);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        return None;
+    }
+
+    fn Connect(a: &SoftDevice, b: &SoftDevice) -> (QueuePair, QueuePair) {
+        let qpA = a.CreateQueuePair(QueuePairType::RC).unwrap();
+        let qpB = b.CreateQueuePair(QueuePairType::RC).unwrap();
+        qpA.SetupRCQP(qpB.qpNum(), b.Lid(), b.Gid()).unwrap();
+        qpB.SetupRCQP(qpA.qpNum(), a.Lid(), a.Gid()).unwrap();
+        return (qpA, qpB);
+    }
+
+    #[test]
+    fn test_WriteImm() {
+        let a = Device(SECRET);
+        let b = Device(SECRET);
+        let (qpA, qpB) = Connect(&a, &b);
+
+        let src = vec![7u8; 64];
+        let dst = vec![0u8; 128];
+        let mrA = a
+            .CreateMemoryRegion(src.as_ptr() as u64, src.len())
+            .unwrap();
+        let mrB = b
+            .CreateMemoryRegion(dst.as_ptr() as u64, dst.len())
+            .unwrap();
+
+        qpB.PostRecv(1, 0, mrB.LKey(), 0).unwrap();
+        qpA.WriteImm(
+            2,
+            src.as_ptr() as u64,
+            64,
+            mrA.LKey(),
+            dst.as_ptr() as u64 + 32,
+            mrB.RKey(),
+            99,
+        )
+        .unwrap();
+
+        let wc = WaitCompletion(&a, Duration::from_secs(5)).unwrap();
+        assert_eq!(wc.opcode, rdmaffi::ibv_wc_opcode::IBV_WC_RDMA_WRITE);
+        assert_eq!(wc.wr_id, 2);
+
+        let wc = WaitCompletion(&b, Duration::from_secs(5)).unwrap();
+        assert_eq!(wc.opcode, rdmaffi::ibv_wc_opcode::IBV_WC_RECV_RDMA_WITH_IMM);
+        assert_eq!(wc.wr_id, 1);
+        assert_eq!(wc.byte_len, 64);
+        assert_eq!(wc.qp_num, qpB.qpNum());
+        assert_eq!(unsafe { wc.imm_data_invalidated_rkey_union.imm_data }, 99);
+        assert!(dst[..32].iter().all(|b| *b == 0));
+        assert!(dst[32..96].iter().all(|b| *b == 7));
+        assert!(dst[96..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_WriteImmOutOfRegion() {
+        let a = Device(SECRET);
+        let b = Device(SECRET);
+        let (qpA, qpB) = Connect(&a, &b);
+
+        let src = vec![7u8; 64];
+        let dst = vec![0u8; 64];
+        let mrA = a
+            .CreateMemoryRegion(src.as_ptr() as u64, src.len())
+            .unwrap();
+        let mrB = b
+            .CreateMemoryRegion(dst.as_ptr() as u64, dst.len())
+            .unwrap();
+        qpB.PostRecv(1, 0, mrB.LKey(), 0).unwrap();
+
+        // past the end of the region, then with a key which isn't registered
+        let laddr = src.as_ptr() as u64;
+        let raddr = dst.as_ptr() as u64;
+        qpA.WriteImm(2, laddr, 64, mrA.LKey(), raddr + 32, mrB.RKey(), 0)
+            .unwrap();
+        qpA.WriteImm(3, laddr, 64, mrA.LKey(), raddr, mrB.RKey() ^ 1, 0)
+            .unwrap();
+
+        assert!(WaitCompletion(&b, Duration::from_millis(200)).is_none());
+        assert!(dst.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_UnauthenticatedPeer() {
+        let a = Device("another secret");
+        let b = Device(SECRET);
+        let (qpA, qpB) = Connect(&a, &b);
+
+        let src = vec![7u8; 64];
+        let dst = vec![0u8; 64];
+        let mrA = a
+            .CreateMemoryRegion(src.as_ptr() as u64, src.len())
+            .unwrap();
+        let mrB = b
+            .CreateMemoryRegion(dst.as_ptr() as u64, dst.len())
+            .unwrap();
+        qpB.PostRecv(1, 0, mrB.LKey(), 0).unwrap();
+
+        let res = qpA.WriteImm(
+            2,
+            src.as_ptr() as u64,
+            64,
+            mrA.LKey(),
+            dst.as_ptr() as u64,
+            mrB.RKey(),
+            0,
+        );
+        assert!(res.is_err());
+        assert!(WaitCompletion(&b, Duration::from_millis(200)).is_none());
+        assert!(dst.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_SendUD() {
+        let a = Device(SECRET);
+        let b = Device(SECRET);
+        let qpA = a.CreateQueuePair(QueuePairType::UD).unwrap();
+        let qpB = b.CreateQueuePair(QueuePairType::UD).unwrap();
+        let ah = a.CreateAddressHandler(1, b.Lid(), b.Gid()).unwrap();
+
+        let src: Vec<u8> = (0..100).collect();
+        let dst = vec![0u8; 256];
+        let mrA = a
+            .CreateMemoryRegion(src.as_ptr() as u64, src.len())
+            .unwrap();
+        let mrB = b
+            .CreateMemoryRegion(dst.as_ptr() as u64, dst.len())
+            .unwrap();
+
+        qpB.PostRecv(1, dst.as_ptr() as u64, mrB.LKey(), dst.len() as u32)
+            .unwrap();
+        qpA.PostSendUDQP(&ah, qpB.qpNum(), 2, src.as_ptr() as u64, 100, mrA.LKey())
+            .unwrap();
+
+        let wc = WaitCompletion(&b, Duration::from_secs(5)).unwrap();
+        assert_eq!(wc.opcode, rdmaffi::ibv_wc_opcode::IBV_WC_RECV);
+        assert_eq!(wc.wr_id, 1);
+        assert_eq!(wc.byte_len, 100 + SOFT_RDMA_GRH_SIZE);
+        assert_eq!(wc.src_qp, qpA.qpNum());
+        let grh = SOFT_RDMA_GRH_SIZE as usize;
+        assert_eq!(&dst[grh..grh + 100], &src[..]);
+    }
+
+    #[test]
+    fn test_InRegion() {
+        let a = Device(SECRET);
+        let mr = a.CreateMemoryRegion(u64::MAX - 16, 16).unwrap();
+        let key = mr.LKey();
+        assert_eq!(mr.RKey(), key);
+        assert!(a.InRegion(key, u64::MAX - 16, 16));
+        assert!(a.InRegion(key, u64::MAX - 8, 8));
+        assert!(!a.InRegion(key, u64::MAX - 8, 16));
+        assert!(!a.InRegion(key, u64::MAX - 17, 4));
+        assert!(!a.InRegion(key ^ 1, u64::MAX - 16, 16));
+    }
+}
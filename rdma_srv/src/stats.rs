@@ -0,0 +1,714 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::{App, Arg};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt::Write as FmtWrite;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, Shutdown, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::constants::*;
+use super::rdma_channel::*;
+use super::rdma_conn::*;
+use super::rdma_srv::*;
+
+// SrvCounters are the counters of the RDMA work completions
+#[derive(Default)]
+pub struct SrvCounters {
+    pub writeCompletions: AtomicU64,
+    pub recvCompletions: AtomicU64,
+    pub udpRecvCompletions: AtomicU64,
+    pub sendCompletions: AtomicU64,
+    pub completionErrors: AtomicU64,
+}
+
+impl SrvCounters {
+    pub fn Inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn Snapshot(&self) -> CountersState {
+        return CountersState {
+            writeCompletions: self.writeCompletions.load(Ordering::Relaxed),
+            recvCompletions: self.recvCompletions.load(Ordering::Relaxed),
+            udpRecvCompletions: self.udpRecvCompletions.load(Ordering::Relaxed),
+            sendCompletions: self.sendCompletions.load(Ordering::Relaxed),
+            completionErrors: self.completionErrors.load(Ordering::Relaxed),
+        };
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CountersState {
+    pub writeCompletions: u64,
+    pub recvCompletions: u64,
+    pub udpRecvCompletions: u64,
+    pub sendCompletions: u64,
+    pub completionErrors: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ChannelState {
+    pub id: u32,
+    pub remoteId: u32,
+    pub agentId: u32,
+    pub pod: String,
+    pub node: String,
+    pub srcIpAddr: String,
+    pub srcPort: u16,
+    pub dstIpAddr: String,
+    pub dstPort: u16,
+    pub status: String,
+    // free space of the peer read buffer
+    pub remoteFreespace: u32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct EndpointState {
+    pub agentId: u32,
+    pub sockfd: u32,
+    pub ipAddr: String,
+    pub pod: String,
+    pub port: u16,
+    pub status: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ConnState {
+    pub node: String,
+    pub socketState: String,
+    pub channels: usize,
+    pub queueDepth: usize,
+    pub controlQueueDepth: usize,
+    pub remoteRecvRequestCount: u32,
+    pub connects: u64,
+    pub resets: u64,
+    pub heartbeatTimeouts: u64,
+    pub lastRttUs: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PodState {
+    pub agentId: u32,
+    pub pod: String,
+    pub channels: usize,
+    pub bytesSent: u64,
+    pub queued: u64,
+    pub throttled: u64,
+}
+
+// SrvState is the json dump of the RDMA service
+#[derive(Serialize, Debug, Clone)]
+pub struct SrvState {
+    pub hostname: String,
+    pub localIp: String,
+    pub channels: Vec<ChannelState>,
+    pub endpoints: Vec<EndpointState>,
+    pub connections: Vec<ConnState>,
+    pub pods: Vec<PodState>,
+    pub resourceVersions: BTreeMap<String, i32>,
+    pub counters: CountersState,
+}
+
+// the node and pod ips are in network order
+fn IpString(ip: u32) -> String {
+    return Ipv4Addr::from(u32::from_be(ip)).to_string();
+}
+
+fn PodIdString(podId: &[u8]) -> String {
+    let len = podId.iter().position(|b| *b == 0).unwrap_or(podId.len());
+    return String::from_utf8_lossy(&podId[..len]).to_string();
+}
+
+fn SocketStateName(state: u64) -> &'static str {
+    match state {
+        s if s == SocketState::Init as u64 => return "Init",
+        s if s == SocketState::Connect as u64 => return "Connect",
+        s if s == SocketState::WaitingForRemoteMeta as u64 => return "WaitingForRemoteMeta",
+        s if s == SocketState::WaitingForRemoteReady as u64 => return "WaitingForRemoteReady",
+        s if s == SocketState::Ready as u64 => return "Ready",
+        _ => return "Error",
+    }
+}
+
+fn SrvEndPointStatusName(status: &SrvEndPointStatus) -> &'static str {
+    match status {
+        SrvEndPointStatus::Binded => return "Binded",
+        SrvEndPointStatus::Listening => return "Listening",
+    }
+}
+
+impl RDMASrv {
+    // State collects the state of the service. The maps are copied before the
+    // channels and connections are inspected, so that the service locks are
+    // not held with the channel locks.
+    pub fn State(&self) -> SrvState {
+        let channels: Vec<RDMAChannel> = self.channels.lock().values().cloned().collect();
+        let conns: Vec<RDMAConn> = self.conns.lock().values().cloned().collect();
+
+        let mut channelStates = Vec::new();
+        let mut connChannels: HashMap<u32, usize> = HashMap::new();
+        let mut agentChannels: HashMap<u32, usize> = HashMap::new();
+        for channel in &channels {
+            let remoteInfo = channel.remoteChannelRDMAInfo.lock().clone();
+            channelStates.push(ChannelState {
+                id: channel.localId,
+                remoteId: remoteInfo.remoteId,
+                agentId: channel.agent.id,
+                pod: PodIdString(&channel.agent.podId),
+                node: IpString(channel.conn.nodeIp),
+                srcIpAddr: channel.SrcAddr().to_string(),
+                srcPort: u16::from_be(channel.srcPort),
+                dstIpAddr: channel.DstAddr().to_string(),
+                dstPort: u16::from_be(channel.dstPort),
+                status: format!("{:?}", *channel.status.lock()),
+                remoteFreespace: remoteInfo.freespace,
+            });
+            *connChannels.entry(channel.conn.nodeIp).or_insert(0) += 1;
+            *agentChannels.entry(channel.agent.id).or_insert(0) += 1;
+        }
+        channelStates.sort_by_key(|c| c.id);
+
+        let mut endpoints = Vec::new();
+        for srvEndpoint in self.srvEndPoints.lock().values() {
+            endpoints.push(EndpointState {
+                agentId: srvEndpoint.agentId,
+                sockfd: srvEndpoint.sockfd,
+                ipAddr: IpString(srvEndpoint.endpoint.ipAddr),
+                pod: String::new(),
+                port: u16::from_be(srvEndpoint.endpoint.port),
+                status: SrvEndPointStatusName(&srvEndpoint.status).to_string(),
+            });
+        }
+        for srvEndpoint in self.srvPodIdEndpoints.lock().values() {
+            endpoints.push(EndpointState {
+                agentId: srvEndpoint.agentId,
+                sockfd: srvEndpoint.sockfd,
+                ipAddr: String::new(),
+                pod: PodIdString(&srvEndpoint.podId),
+                port: u16::from_be(srvEndpoint.port),
+                status: SrvEndPointStatusName(&srvEndpoint.status).to_string(),
+            });
+        }
+        endpoints.sort_by(|a, b| (a.agentId, a.port).cmp(&(b.agentId, b.port)));
+
+        let peerStats = RDMA_CTLINFO.peer_stats_get();
+        let mut connStates = Vec::new();
+        for conn in &conns {
+            let peer = peerStats.get(&conn.nodeIp).cloned().unwrap_or_default();
+            connStates.push(ConnState {
+                node: IpString(conn.nodeIp),
+                socketState: SocketStateName(conn.socketState.load(Ordering::Acquire)).to_string(),
+                channels: connChannels.get(&conn.nodeIp).cloned().unwrap_or(0),
+                queueDepth: conn.requestsQueue.lock().len(),
+                controlQueueDepth: conn.controlRequestsQueue.lock().len(),
+                remoteRecvRequestCount: *conn.remoteRecvRequestCount.lock(),
+                connects: peer.connects,
+                resets: peer.resets,
+                heartbeatTimeouts: peer.heartbeatTimeouts,
+                lastRttUs: peer.lastRttUs,
+            });
+        }
+        connStates.sort_by(|a, b| a.node.cmp(&b.node));
+
+        let mut pods = Vec::new();
+        {
+            let qos = self.qos.lock();
+            for agent in self.agents.lock().values() {
+                let stats = qos.pods.get(&agent.id).map(|p| p.stats.clone());
+                let stats = stats.unwrap_or_default();
+                pods.push(PodState {
+                    agentId: agent.id,
+                    pod: PodIdString(&agent.podId),
+                    channels: agentChannels.get(&agent.id).cloned().unwrap_or(0),
+                    bytesSent: stats.bytesSent,
+                    queued: stats.queued,
+                    throttled: stats.throttled,
+                });
+            }
+        }
+        pods.sort_by_key(|p| p.agentId);
+
+        return SrvState {
+            hostname: RDMA_CTLINFO.hostname_get(),
+            localIp: IpString(RDMA_CTLINFO.localIp_get()),
+            channels: channelStates,
+            endpoints: endpoints,
+            connections: connStates,
+            pods: pods,
+            resourceVersions: RDMA_CTLINFO.resource_versions_get().into_iter().collect(),
+            counters: self.counters.Snapshot(),
+        };
+    }
+}
+
+fn Metric(out: &mut String, name: &str, typ: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, typ);
+}
+
+// the label values are escaped as the prometheus text format requires
+fn Label(value: &str) -> String {
+    return value
+        .replace("\\", "\\\\")
+        .replace("\"", "\\\"")
+        .replace("\n", "\\n");
+}
+
+// Metrics renders the state in the prometheus text format
+pub fn Metrics(state: &SrvState) -> String {
+    let mut out = String::new();
+
+    Metric(&mut out, "rdma_srv_channels", "gauge", "RDMA channels.");
+    let _ = writeln!(out, "rdma_srv_channels {}", state.channels.len());
+
+    Metric(
+        &mut out,
+        "rdma_srv_endpoints",
+        "gauge",
+        "Listening endpoints.",
+    );
+    let _ = writeln!(out, "rdma_srv_endpoints {}", state.endpoints.len());
+
+    Metric(
+        &mut out,
+        "rdma_srv_pod_channels",
+        "gauge",
+        "RDMA channels of the pod.",
+    );
+    for pod in &state.pods {
+        let _ = writeln!(
+            out,
+            "rdma_srv_pod_channels{{pod=\"{}\"}} {}",
+            Label(&pod.pod),
+            pod.channels
+        );
+    }
+
+    Metric(
+        &mut out,
+        "rdma_srv_pod_sent_bytes_total",
+        "counter",
+        "Bytes written by the pod over RDMA.",
+    );
+    for pod in &state.pods {
+        let _ = writeln!(
+            out,
+            "rdma_srv_pod_sent_bytes_total{{pod=\"{}\"}} {}",
+            Label(&pod.pod),
+            pod.bytesSent
+        );
+    }
+
+    Metric(
+        &mut out,
+        "rdma_srv_conn_up",
+        "gauge",
+        "Whether the connection to the node is ready.",
+    );
+    for conn in &state.connections {
+        let up = if conn.socketState == "Ready" { 1 } else { 0 };
+        let _ = writeln!(out, "rdma_srv_conn_up{{node=\"{}\"}} {}", conn.node, up);
+    }
+
+    Metric(
+        &mut out,
+        "rdma_srv_conn_channels",
+        "gauge",
+        "RDMA channels over the connection.",
+    );
+    for conn in &state.connections {
+        let _ = writeln!(
+            out,
+            "rdma_srv_conn_channels{{node=\"{}\"}} {}",
+            conn.node, conn.channels
+        );
+    }
+
+    Metric(
+        &mut out,
+        "rdma_srv_conn_queue_depth",
+        "gauge",
+        "Write requests waiting for the receive requests of the node.",
+    );
+    for conn in &state.connections {
+        let _ = writeln!(
+            out,
+            "rdma_srv_conn_queue_depth{{node=\"{}\",queue=\"data\"}} {}",
+            conn.node, conn.queueDepth
+        );
+        let _ = writeln!(
+            out,
+            "rdma_srv_conn_queue_depth{{node=\"{}\",queue=\"control\"}} {}",
+            conn.node, conn.controlQueueDepth
+        );
+    }
+
+    Metric(
+        &mut out,
+        "rdma_srv_conn_resets_total",
+        "counter",
+        "Resets of the connection to the node.",
+    );
+    for conn in &state.connections {
+        let _ = writeln!(
+            out,
+            "rdma_srv_conn_resets_total{{node=\"{}\"}} {}",
+            conn.node, conn.resets
+        );
+    }
+
+    Metric(
+        &mut out,
+        "rdma_srv_conn_rtt_microseconds",
+        "gauge",
+        "Round trip time of the last heartbeat.",
+    );
+    for conn in &state.connections {
+        let _ = writeln!(
+            out,
+            "rdma_srv_conn_rtt_microseconds{{node=\"{}\"}} {}",
+            conn.node, conn.lastRttUs
+        );
+    }
+
+    Metric(
+        &mut out,
+        "rdma_srv_completions_total",
+        "counter",
+        "RDMA work completions.",
+    );
+    let counters = &state.counters;
+    for (typ, value) in &[
+        ("write", counters.writeCompletions),
+        ("recv", counters.recvCompletions),
+        ("udp_recv", counters.udpRecvCompletions),
+        ("send", counters.sendCompletions),
+    ] {
+        let _ = writeln!(
+            out,
+            "rdma_srv_completions_total{{type=\"{}\"}} {}",
+            typ, value
+        );
+    }
+
+    Metric(
+        &mut out,
+        "rdma_srv_completion_errors_total",
+        "counter",
+        "RDMA work completions failed.",
+    );
+    let _ = writeln!(
+        out,
+        "rdma_srv_completion_errors_total {}",
+        counters.completionErrors
+    );
+
+    Metric(
+        &mut out,
+        "rdma_srv_informer_resource_version",
+        "gauge",
+        "Max resource version handled by the informer.",
+    );
+    for (kind, version) in &state.resourceVersions {
+        let _ = writeln!(
+            out,
+            "rdma_srv_informer_resource_version{{kind=\"{}\"}} {}",
+            kind, version
+        );
+    }
+
+    return out;
+}
+
+fn Respond(stream: &mut TcpStream, status: &str, contentType: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        contentType,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes());
+}
+
+fn HandleStatsRequest(mut stream: TcpStream) {
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(STATS_TIMEOUT_SECS)));
+    let mut buf = [0u8; 4096];
+    let mut len = 0;
+    // only the request line is needed
+    while len < buf.len() {
+        match stream.read(&mut buf[len..]) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => len += n,
+        }
+        if buf[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+            break;
+        }
+    }
+
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let mut parts = request.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("");
+    if method != "GET" {
+        Respond(&mut stream, "405 Method Not Allowed", "text/plain", "");
+    } else if path == STATS_METRICS_PATH {
+        let body = Metrics(&RDMA_SRV.State());
+        Respond(&mut stream, "200 OK", "text/plain; version=0.0.4", &body);
+    } else if path == STATS_STATE_PATH {
+        match serde_json::to_string_pretty(&RDMA_SRV.State()) {
+            Ok(body) => Respond(&mut stream, "200 OK", "application/json", &body),
+            Err(e) => Respond(
+                &mut stream,
+                "500 Internal Server Error",
+                "text/plain",
+                &format!("{:?}", e),
+            ),
+        }
+    } else {
+        Respond(&mut stream, "404 Not Found", "text/plain", "");
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+fn StatsAddr() -> String {
+    match env::var(STATS_ADDR_ENV) {
+        Ok(addr) => return addr,
+        Err(_) => return STATS_ADDR_DEFAULT.to_string(),
+    }
+}
+
+// StartStatsServer serves the prometheus metrics and the json state over http
+// in its own thread, RDMA_SRV_STATS_ADDR="" disables it.
+pub fn StartStatsServer() {
+    let addr = StatsAddr();
+    if addr.len() == 0 {
+        return;
+    }
+
+    let listener = match TcpListener::bind(&addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("fail to start the stats server at {}: {:?}", addr, e);
+            return;
+        }
+    };
+    println!("Stats server listens at {}", addr);
+    thread::spawn(move || {
+        // each request is served in its own thread so that a slow client
+        // doesn't hold the others
+        let active = Arc::new(AtomicUsize::new(0));
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!("stats server accept fail: {:?}", e);
+                    continue;
+                }
+            };
+            if active.fetch_add(1, Ordering::SeqCst) >= STATS_MAX_CONNS {
+                active.fetch_sub(1, Ordering::SeqCst);
+                let _ = stream.shutdown(Shutdown::Both);
+                continue;
+            }
+
+            let active = active.clone();
+            thread::spawn(move || {
+                defer!(active.fetch_sub(1, Ordering::SeqCst););
+                HandleStatsRequest(stream);
+            });
+        }
+    });
+}
+
+// StatsCommand is the "rdma_srv stats" subcommand which queries the stats
+// server of the running rdma_srv
+pub fn StatsCommand(args: &[String]) -> i32 {
+    let matches = App::new("rdma_srv stats")
+        .about("Show the stats of the running rdma_srv")
+        .arg(
+            Arg::with_name("addr")
+                .long("addr")
+                .takes_value(true)
+                .help("address of the stats server, RDMA_SRV_STATS_ADDR by default"),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("dump the service state in json instead of the metrics"),
+        )
+        .get_matches_from(args);
+
+    let addr = match matches.value_of("addr") {
+        Some(addr) => addr.to_string(),
+        None => StatsAddr(),
+    };
+    let path = if matches.is_present("json") {
+        STATS_STATE_PATH
+    } else {
+        STATS_METRICS_PATH
+    };
+
+    match QueryStats(&addr, path) {
+        Ok(body) => {
+            print!("{}", body);
+            return 0;
+        }
+        Err(e) => {
+            eprintln!("fail to query rdma_srv stats at {}: {}", addr, e);
+            return 1;
+        }
+    }
+}
+
+fn QueryStats(addr: &str, path: &str) -> Result<String, String> {
+    let mut stream = TcpStream::connect(addr).map_err(|e| format!("{:?}", e))?;
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(STATS_TIMEOUT_SECS)));
+    let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, addr);
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| format!("{:?}", e))?;
+
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|e| format!("{:?}", e))?;
+    let (head, body) = match response.find("\r\n\r\n") {
+        Some(i) => (&response[..i], &response[i + 4..]),
+        None => return Err(format!("invalid response: {}", response)),
+    };
+    let status = head.lines().next().unwrap_or("");
+    if !status.contains(" 200 ") {
+        return Err(status.to_string());
+    }
+
+    return Ok(body.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn State() -> SrvState {
+        let mut resourceVersions = BTreeMap::new();
+        resourceVersions.insert("pod".to_string(), 12);
+        resourceVersions.insert("service".to_string(), 7);
+        return SrvState {
+            hostname: "node1".to_string(),
+            localIp: "10.0.0.1".to_string(),
+            channels: Vec::new(),
+            endpoints: Vec::new(),
+            connections: vec![
+                ConnState {
+                    node: "10.0.0.2".to_string(),
+                    socketState: "Ready".to_string(),
+                    channels: 3,
+                    queueDepth: 4,
+                    controlQueueDepth: 1,
+                    remoteRecvRequestCount: 0,
+                    connects: 1,
+                    resets: 2,
+                    heartbeatTimeouts: 0,
+                    lastRttUs: 35,
+                },
+                ConnState {
+                    node: "10.0.0.3".to_string(),
+                    socketState: "Connect".to_string(),
+                    channels: 0,
+                    queueDepth: 0,
+                    controlQueueDepth: 0,
+                    remoteRecvRequestCount: 0,
+                    connects: 0,
+                    resets: 0,
+                    heartbeatTimeouts: 0,
+                    lastRttUs: 0,
+                },
+            ],
+            pods: vec![PodState {
+                agentId: 1,
+                pod: "default/web\"1\"".to_string(),
+                channels: 3,
+                bytesSent: 4096,
+                queued: 0,
+                throttled: 0,
+            }],
+            resourceVersions: resourceVersions,
+            counters: CountersState {
+                writeCompletions: 10,
+                recvCompletions: 11,
+                udpRecvCompletions: 0,
+                sendCompletions: 2,
+                completionErrors: 1,
+            },
+        };
+    }
+
+    #[test]
+    fn test_Metrics() {
+        let metrics = Metrics(&State());
+        let lines: Vec<&str> = metrics.lines().collect();
+        for expected in &[
+            "# HELP rdma_srv_channels RDMA channels.",
+            "# TYPE rdma_srv_channels gauge",
+            "rdma_srv_channels 0",
+            "rdma_srv_endpoints 0",
+            "rdma_srv_pod_channels{pod=\"default/web\\\"1\\\"\"} 3",
+            "# TYPE rdma_srv_pod_sent_bytes_total counter",
+            "rdma_srv_pod_sent_bytes_total{pod=\"default/web\\\"1\\\"\"} 4096",
+            "rdma_srv_conn_up{node=\"10.0.0.2\"} 1",
+            "rdma_srv_conn_up{node=\"10.0.0.3\"} 0",
+            "rdma_srv_conn_channels{node=\"10.0.0.2\"} 3",
+            "rdma_srv_conn_queue_depth{node=\"10.0.0.2\",queue=\"data\"} 4",
+            "rdma_srv_conn_queue_depth{node=\"10.0.0.2\",queue=\"control\"} 1",
+            "rdma_srv_conn_resets_total{node=\"10.0.0.2\"} 2",
+            "rdma_srv_conn_rtt_microseconds{node=\"10.0.0.2\"} 35",
+            "rdma_srv_completions_total{type=\"write\"} 10",
+            "rdma_srv_completions_total{type=\"recv\"} 11",
+            "rdma_srv_completions_total{type=\"udp_recv\"} 0",
+            "rdma_srv_completions_total{type=\"send\"} 2",
+            "rdma_srv_completion_errors_total 1",
+            "rdma_srv_informer_resource_version{kind=\"pod\"} 12",
+            "rdma_srv_informer_resource_version{kind=\"service\"} 7",
+        ] {
+            assert!(lines.contains(expected), "missing {}", expected);
+        }
+
+        // every sample follows the HELP and TYPE of its metric
+        let mut described = Vec::new();
+        for line in &lines {
+            if line.starts_with("# TYPE ") {
+                described.push(line.split_whitespace().nth(2).unwrap());
+            } else if !line.starts_with("# HELP ") {
+                let name = line.split(|c| c == '{' || c == ' ').next().unwrap();
+                assert_eq!(described.last(), Some(&name), "{}", line);
+            }
+        }
+    }
+
+    #[test]
+    fn test_Label() {
+        assert_eq!(Label("default/web"), "default/web");
+        assert_eq!(Label("a\"b"), "a\\\"b");
+        assert_eq!(Label("a\\b"), "a\\\\b");
+        assert_eq!(Label("a\nb"), "a\\nb");
+        // the backslash is escaped before the quote
+        assert_eq!(Label("\\\""), "\\\\\\\"");
+    }
+}